@@ -0,0 +1,108 @@
+//! Key/value configuration stored as JSON in `kael_config`.
+use anyhow::Result;
+use rusqlite::{params, OptionalExtension};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::StorageManager;
+
+#[derive(Clone)]
+pub struct ConfigRepository {
+    storage: StorageManager,
+}
+
+impl ConfigRepository {
+    pub(crate) fn new(storage: StorageManager) -> Self {
+        Self { storage }
+    }
+
+    /// Raw JSON value for `key`, if set.
+    pub async fn get_raw(&self, key: &str) -> Result<Option<String>> {
+        let key = key.to_string();
+        self.storage
+            .call(move |conn| {
+                let value = conn
+                    .query_row("SELECT value FROM kael_config WHERE key = ?1", [key], |row| row.get(0))
+                    .optional()?;
+                Ok(value)
+            })
+            .await
+    }
+
+    pub async fn set_raw(&self, key: &str, value: &str) -> Result<()> {
+        let (key, value) = (key.to_string(), value.to_string());
+        self.storage
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO kael_config (key, value) VALUES (?1, ?2)
+                     ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+                    params![key, value],
+                )?;
+                Ok(())
+            })
+            .await
+    }
+
+    /// Deserialize the value stored under `key`.
+    pub async fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        match self.get_raw(key).await? {
+            Some(raw) => Ok(Some(serde_json::from_str(&raw)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Serialize `value` as JSON under `key`.
+    pub async fn set<T: Serialize>(&self, key: &str, value: &T) -> Result<()> {
+        let raw = serde_json::to_string(value)?;
+        self.set_raw(key, &raw).await
+    }
+
+    pub async fn delete(&self, key: &str) -> Result<()> {
+        let key = key.to_string();
+        self.storage
+            .call(move |conn| {
+                conn.execute("DELETE FROM kael_config WHERE key = ?1", [key])?;
+                Ok(())
+            })
+            .await
+    }
+
+    /// All stored keys, sorted.
+    pub async fn keys(&self) -> Result<Vec<String>> {
+        self.storage
+            .call(|conn| {
+                let mut stmt = conn.prepare("SELECT key FROM kael_config ORDER BY key")?;
+                let keys = stmt
+                    .query_map([], |row| row.get(0))?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(keys)
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_typed_config_values() {
+        let storage = StorageManager::open_in_memory().unwrap();
+        let config = storage.config();
+
+        assert_eq!(config.get::<bool>("hybrid_assist").await.unwrap(), None);
+
+        config.set("hybrid_assist", &true).await.unwrap();
+        config.set("provider_order", &vec!["ollama", "mistral"]).await.unwrap();
+
+        assert_eq!(config.get::<bool>("hybrid_assist").await.unwrap(), Some(true));
+        assert_eq!(
+            config.get::<Vec<String>>("provider_order").await.unwrap().unwrap(),
+            vec!["ollama".to_string(), "mistral".to_string()]
+        );
+        assert_eq!(config.keys().await.unwrap(), vec!["hybrid_assist", "provider_order"]);
+
+        config.delete("hybrid_assist").await.unwrap();
+        assert_eq!(config.get_raw("hybrid_assist").await.unwrap(), None);
+    }
+}
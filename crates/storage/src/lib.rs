@@ -1,90 +1,180 @@
-/// Local SQLite-backed storage for messages, sessions, and cached API responses.
-use anyhow::Result;
-use chrono::{DateTime, Utc};
+//! Local SQLite-backed storage shared by the desktop app and its tools.
+//!
+//! A single `StorageManager` owns one connection to the database. Blocking
+//! rusqlite work is moved onto tokio's blocking pool, so the async APIs can be
+//! awaited from UI tasks without stalling the runtime. Typed repositories hang
+//! off the manager for each table group.
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Result};
 use rusqlite::Connection;
-use serde::{Deserialize, Serialize};
-use uuid::Uuid;
-
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct ChatMessage {
-    pub id: String,
-    pub session_id: String,
-    pub author: String,
-    pub text: String,
-    pub created_at: DateTime<Utc>,
-}
 
+pub mod config;
+pub mod messages;
+pub mod migrations;
+pub mod projects;
+pub mod scripts;
+pub mod sessions;
+pub mod sync;
+
+pub use config::ConfigRepository;
+pub use messages::{ChatMessage, MessageRepository};
+pub use projects::{AppProject, AppStatus, ProjectRepository};
+pub use scripts::{Script, ScriptRepository, ScriptRun};
+pub use sessions::{Session, SessionRepository};
+pub use sync::{Remote, SyncEngine, SyncReport, SyncRepository, Syncable};
+
+/// Shared handle to the Kael database. Cloning is cheap; all clones use the
+/// same underlying connection.
+#[derive(Clone)]
 pub struct StorageManager {
-    db_path: String,
+    conn: Arc<Mutex<Connection>>,
 }
 
 impl StorageManager {
-    pub fn new(db_path: &str) -> Result<Self> {
-        let manager = StorageManager {
-            db_path: db_path.to_string(),
-        };
-        manager.init_db()?;
-        Ok(manager)
+    /// Open (or create) the database at `db_path` and run pending migrations.
+    pub fn open(db_path: impl AsRef<Path>) -> Result<Self> {
+        let db_path = db_path.as_ref();
+        if let Some(parent) = db_path.parent() {
+            if !parent.as_os_str().is_empty() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+
+        create_private(db_path)?;
+        let conn = Connection::open(db_path)?;
+        conn.execute_batch("PRAGMA journal_mode = WAL;")?;
+        Self::from_connection(conn)
+    }
+
+    /// Open a private in-memory database. Used by tests and by throwaway profiles.
+    pub fn open_in_memory() -> Result<Self> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(mut conn: Connection) -> Result<Self> {
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        migrations::run_migrations(&mut conn)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Import conversations from a chat history database written before
+    /// kael-storage existed. See [`migrations::import_legacy_chat_history`].
+    pub fn import_legacy_chat_history(&self, legacy_path: &Path) -> Result<usize> {
+        let mut conn = self.conn.lock().map_err(|_| anyhow!("storage connection poisoned"))?;
+        migrations::import_legacy_chat_history(&mut conn, legacy_path)
+    }
+
+    /// Run `f` against the connection on the blocking thread pool.
+    pub async fn call<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let conn = Arc::clone(&self.conn);
+        tokio::task::spawn_blocking(move || {
+            let mut guard = conn.lock().map_err(|_| anyhow!("storage connection poisoned"))?;
+            f(&mut guard)
+        })
+        .await?
+    }
+
+    pub fn sessions(&self) -> SessionRepository {
+        SessionRepository::new(self.clone())
+    }
+
+    pub fn messages(&self) -> MessageRepository {
+        MessageRepository::new(self.clone())
     }
 
-    fn init_db(&self) -> Result<()> {
-        let conn = Connection::open(&self.db_path)?;
-        conn.execute_batch(
-            "CREATE TABLE IF NOT EXISTS chat_messages (
-                id TEXT PRIMARY KEY,
-                session_id TEXT NOT NULL,
-                author TEXT NOT NULL,
-                text TEXT NOT NULL,
-                created_at TEXT NOT NULL
-            );
-            CREATE TABLE IF NOT EXISTS sessions (
-                id TEXT PRIMARY KEY,
-                created_at TEXT NOT NULL,
-                updated_at TEXT NOT NULL
-            );",
-        )?;
-        Ok(())
+    pub fn projects(&self) -> ProjectRepository {
+        ProjectRepository::new(self.clone())
     }
 
-    pub fn save_message(&self, session_id: &str, author: &str, text: &str) -> Result<ChatMessage> {
-        let conn = Connection::open(&self.db_path)?;
-        let id = Uuid::new_v4().to_string();
-        let now = Utc::now();
-
-        conn.execute(
-            "INSERT INTO chat_messages (id, session_id, author, text, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
-            (&id, session_id, author, text, now.to_rfc3339()),
-        )?;
-
-        Ok(ChatMessage {
-            id,
-            session_id: session_id.to_string(),
-            author: author.to_string(),
-            text: text.to_string(),
-            created_at: now,
+    pub fn scripts(&self) -> ScriptRepository {
+        ScriptRepository::new(self.clone())
+    }
+
+    pub fn config(&self) -> ConfigRepository {
+        ConfigRepository::new(self.clone())
+    }
+
+    pub fn sync(&self) -> SyncRepository {
+        SyncRepository::new(self.clone())
+    }
+
+    /// Whether both handles use the same database.
+    pub fn same_as(&self, other: &StorageManager) -> bool {
+        Arc::ptr_eq(&self.conn, &other.conn)
+    }
+}
+
+/// The database holds settings and cached credentials: owner read/write only.
+/// The file is created with that mode before SQLite opens it, and files from
+/// older builds are tightened. SQLite gives the -wal and -shm files the same
+/// mode as the main file.
+#[cfg(unix)]
+fn create_private(db_path: &Path) -> Result<()> {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .mode(0o600)
+        .open(db_path)?;
+    std::fs::set_permissions(db_path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn create_private(_db_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Parse an RFC 3339 column. An unreadable value becomes the Unix epoch, so
+/// a corrupt row loses every sync merge instead of winning it.
+pub(crate) fn parse_timestamp(value: &str) -> chrono::DateTime<chrono::Utc> {
+    chrono::DateTime::parse_from_rfc3339(value)
+        .map(|dt| dt.with_timezone(&chrono::Utc))
+        .unwrap_or_else(|e| {
+            tracing::warn!("Invalid timestamp {:?} in database: {}", value, e);
+            chrono::DateTime::UNIX_EPOCH
         })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_open_file_database_runs_migrations() {
+        let dir = std::env::temp_dir().join(format!("kael-storage-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("kael.db");
+
+        let storage = StorageManager::open(&path).unwrap();
+        let version: i64 = storage
+            .call(|conn| Ok(conn.query_row("PRAGMA user_version", [], |row| row.get(0))?))
+            .await
+            .unwrap();
+        assert_eq!(version, migrations::SCHEMA_VERSION);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        std::fs::remove_dir_all(&dir).ok();
     }
 
-    pub fn load_session(&self, session_id: &str) -> Result<Vec<ChatMessage>> {
-        let conn = Connection::open(&self.db_path)?;
-        let mut stmt = conn.prepare(
-            "SELECT id, session_id, author, text, created_at FROM chat_messages WHERE session_id = ?1 ORDER BY created_at",
-        )?;
-
-        let messages = stmt
-            .query_map([session_id], |row| {
-                Ok(ChatMessage {
-                    id: row.get(0)?,
-                    session_id: row.get(1)?,
-                    author: row.get(2)?,
-                    text: row.get(3)?,
-                    created_at: DateTime::parse_from_rfc3339(&row.get::<_, String>(4)?)
-                        .map(|dt| dt.with_timezone(&Utc))
-                        .unwrap_or_else(|_| Utc::now()),
-                })
-            })?
-            .collect::<Result<Vec<_>, _>>()?;
-
-        Ok(messages)
+    #[test]
+    fn test_parse_timestamp() {
+        let parsed = parse_timestamp("2026-10-01T12:00:00+02:00");
+        assert_eq!(parsed.to_rfc3339(), "2026-10-01T10:00:00+00:00");
+        assert_eq!(parse_timestamp("yesterday"), chrono::DateTime::UNIX_EPOCH);
     }
 }
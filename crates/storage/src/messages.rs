@@ -0,0 +1,275 @@
+//! Chat messages, optionally grouped into sessions.
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{parse_timestamp, StorageManager, Syncable};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub id: String,
+    #[serde(default)]
+    pub session_id: Option<String>,
+    pub role: String, // "user" or "model"
+    pub text: String,
+    pub timestamp: DateTime<Utc>,
+    #[serde(default)]
+    pub synced: bool,
+}
+
+impl ChatMessage {
+    pub fn new(role: String, text: String) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            session_id: None,
+            role,
+            text,
+            timestamp: Utc::now(),
+            synced: false,
+        }
+    }
+
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        Ok(ChatMessage {
+            id: row.get(0)?,
+            session_id: row.get(1)?,
+            role: row.get(2)?,
+            text: row.get(3)?,
+            timestamp: parse_timestamp(&row.get::<_, String>(4)?),
+            synced: row.get::<_, i32>(5)? != 0,
+        })
+    }
+}
+
+const SELECT_MESSAGE: &str =
+    "SELECT id, session_id, role, text, timestamp, COALESCE(synced, 0) FROM chat_messages";
+
+#[derive(Clone)]
+pub struct MessageRepository {
+    storage: StorageManager,
+}
+
+impl MessageRepository {
+    pub(crate) fn new(storage: StorageManager) -> Self {
+        Self { storage }
+    }
+
+    /// Append a message. When it belongs to a session, the session's `updated_at` is bumped.
+    pub async fn add(&self, session_id: Option<&str>, role: &str, text: &str) -> Result<ChatMessage> {
+        let mut message = ChatMessage::new(role.to_string(), text.to_string());
+        message.session_id = session_id.map(str::to_string);
+        self.insert(&message).await?;
+        Ok(message)
+    }
+
+    /// Insert or replace a fully-formed message (e.g. one pulled from the cloud).
+    pub async fn insert(&self, message: &ChatMessage) -> Result<()> {
+        let message = message.clone();
+        self.storage
+            .call(move |conn| {
+                let tx = conn.transaction()?;
+                tx.execute(
+                    "INSERT OR REPLACE INTO chat_messages (id, session_id, role, text, timestamp, synced)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    params![
+                        message.id,
+                        message.session_id,
+                        message.role,
+                        message.text,
+                        message.timestamp.to_rfc3339(),
+                        message.synced as i32,
+                    ],
+                )?;
+                if let Some(session_id) = &message.session_id {
+                    tx.execute(
+                        "UPDATE sessions SET updated_at = ?1 WHERE id = ?2",
+                        params![Utc::now().to_rfc3339(), session_id],
+                    )?;
+                }
+                tx.commit()?;
+                Ok(())
+            })
+            .await
+    }
+
+    pub async fn get(&self, id: &str) -> Result<Option<ChatMessage>> {
+        let id = id.to_string();
+        self.storage
+            .call(move |conn| {
+                let message = conn
+                    .query_row(&format!("{SELECT_MESSAGE} WHERE id = ?1"), [id], ChatMessage::from_row)
+                    .optional()?;
+                Ok(message)
+            })
+            .await
+    }
+
+    /// Messages of one session in chronological order.
+    pub async fn list_for_session(&self, session_id: &str) -> Result<Vec<ChatMessage>> {
+        let session_id = session_id.to_string();
+        self.storage
+            .call(move |conn| {
+                let mut stmt = conn.prepare(&format!(
+                    "{SELECT_MESSAGE} WHERE session_id = ?1 ORDER BY timestamp ASC, rowid ASC"
+                ))?;
+                let messages = stmt
+                    .query_map([session_id], ChatMessage::from_row)?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(messages)
+            })
+            .await
+    }
+
+    /// Every stored message in chronological order.
+    pub async fn list_all(&self) -> Result<Vec<ChatMessage>> {
+        self.storage
+            .call(|conn| {
+                let mut stmt = conn.prepare(&format!("{SELECT_MESSAGE} ORDER BY timestamp ASC, rowid ASC"))?;
+                let messages = stmt
+                    .query_map([], ChatMessage::from_row)?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(messages)
+            })
+            .await
+    }
+
+    /// Messages whose text contains `query`, newest first, capped at `limit`.
+    pub async fn search(&self, query: &str, limit: usize) -> Result<Vec<ChatMessage>> {
+        let pattern = format!("%{}%", query);
+        self.storage
+            .call(move |conn| {
+                let mut stmt = conn.prepare(&format!(
+                    "{SELECT_MESSAGE} WHERE text LIKE ?1 ORDER BY timestamp DESC LIMIT ?2"
+                ))?;
+                let messages = stmt
+                    .query_map(params![pattern, limit as i64], ChatMessage::from_row)?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(messages)
+            })
+            .await
+    }
+
+    /// Messages not yet pushed to the cloud.
+    pub async fn unsynced(&self) -> Result<Vec<ChatMessage>> {
+        self.storage
+            .call(|conn| {
+                let mut stmt = conn.prepare(&format!(
+                    "{SELECT_MESSAGE} WHERE COALESCE(synced, 0) = 0 ORDER BY timestamp ASC, rowid ASC"
+                ))?;
+                let messages = stmt
+                    .query_map([], ChatMessage::from_row)?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(messages)
+            })
+            .await
+    }
+
+    pub async fn mark_synced(&self, ids: &[String]) -> Result<()> {
+        let ids = ids.to_vec();
+        self.storage
+            .call(move |conn| {
+                let tx = conn.transaction()?;
+                for id in &ids {
+                    tx.execute("UPDATE chat_messages SET synced = 1 WHERE id = ?1", [id])?;
+                }
+                tx.commit()?;
+                Ok(())
+            })
+            .await
+    }
+
+    pub async fn delete(&self, id: &str) -> Result<()> {
+        let id = id.to_string();
+        self.storage
+            .call(move |conn| {
+                conn.execute("DELETE FROM chat_messages WHERE id = ?1", [id])?;
+                Ok(())
+            })
+            .await
+    }
+
+    /// (sessions, messages) row counts.
+    pub async fn stats(&self) -> Result<(usize, usize)> {
+        self.storage
+            .call(|conn| {
+                let sessions: i64 = conn.query_row("SELECT COUNT(*) FROM sessions", [], |row| row.get(0))?;
+                let messages: i64 =
+                    conn.query_row("SELECT COUNT(*) FROM chat_messages", [], |row| row.get(0))?;
+                Ok((sessions as usize, messages as usize))
+            })
+            .await
+    }
+}
+
+#[async_trait]
+impl Syncable for ChatMessage {
+    const COLLECTION: &'static str = "chat_messages";
+    const LOCAL_FIELDS: &'static [&'static str] = &["synced"];
+
+    fn sync_id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn modified_at(&self) -> Option<DateTime<Utc>> {
+        Some(self.timestamp)
+    }
+
+    async fn load_all(storage: &StorageManager) -> Result<Vec<Self>> {
+        storage.messages().list_all().await
+    }
+
+    async fn load(storage: &StorageManager, id: &str) -> Result<Option<Self>> {
+        storage.messages().get(id).await
+    }
+
+    async fn store(storage: &StorageManager, record: &Self) -> Result<()> {
+        storage.messages().insert(record).await
+    }
+
+    async fn remove(storage: &StorageManager, id: &str) -> Result<()> {
+        storage.messages().delete(id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_messages_by_session_and_search() {
+        let storage = StorageManager::open_in_memory().unwrap();
+        let session = storage.sessions().create("Test Chat", None, None).await.unwrap();
+        let messages = storage.messages();
+
+        messages.add(Some(&session.id), "user", "How do I update?").await.unwrap();
+        messages.add(Some(&session.id), "model", "Run paru -Syu").await.unwrap();
+        messages.add(None, "user", "unrelated").await.unwrap();
+
+        let in_session = messages.list_for_session(&session.id).await.unwrap();
+        assert_eq!(in_session.len(), 2);
+        assert_eq!(in_session[0].role, "user");
+
+        let hits = messages.search("paru", 10).await.unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].text, "Run paru -Syu");
+
+        assert_eq!(messages.stats().await.unwrap(), (1, 3));
+    }
+
+    #[tokio::test]
+    async fn test_mark_synced() {
+        let storage = StorageManager::open_in_memory().unwrap();
+        let messages = storage.messages();
+        let first = messages.add(None, "user", "one").await.unwrap();
+        messages.add(None, "user", "two").await.unwrap();
+
+        messages.mark_synced(std::slice::from_ref(&first.id)).await.unwrap();
+
+        let pending = messages.unsynced().await.unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].text, "two");
+    }
+}
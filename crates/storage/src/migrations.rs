@@ -0,0 +1,328 @@
+//! Schema migrations, tracked with `PRAGMA user_version`.
+use std::path::Path;
+
+use anyhow::Result;
+use rusqlite::Connection;
+use tracing::info;
+
+/// Schema version written by the newest migration.
+pub const SCHEMA_VERSION: i64 = 3;
+
+/// Bring the database up to `SCHEMA_VERSION`. Each step runs in its own transaction.
+pub fn run_migrations(conn: &mut Connection) -> Result<()> {
+    let current: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+    if current < 1 {
+        let tx = conn.transaction()?;
+        migrate_v1(&tx)?;
+        tx.pragma_update(None, "user_version", 1)?;
+        tx.commit()?;
+        info!("Storage schema migrated to v1");
+    }
+
+    if current < 2 {
+        let tx = conn.transaction()?;
+        migrate_v2(&tx)?;
+        tx.pragma_update(None, "user_version", 2)?;
+        tx.commit()?;
+        info!("Storage schema migrated to v2");
+    }
+
+    if current < 3 {
+        let tx = conn.transaction()?;
+        migrate_v3(&tx)?;
+        tx.pragma_update(None, "user_version", 3)?;
+        tx.commit()?;
+        info!("Storage schema migrated to v3");
+    }
+
+    Ok(())
+}
+
+/// Initial schema. Tables use `IF NOT EXISTS` so databases created by the
+/// app's old ad-hoc migrations are adopted rather than replaced.
+fn migrate_v1(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS sessions (
+            id TEXT PRIMARY KEY,
+            title TEXT NOT NULL DEFAULT '',
+            provider TEXT,
+            model TEXT,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS chat_messages (
+            id TEXT PRIMARY KEY,
+            role TEXT NOT NULL,
+            text TEXT NOT NULL,
+            timestamp TEXT NOT NULL,
+            synced INTEGER DEFAULT 0
+        );
+
+        CREATE TABLE IF NOT EXISTS scripts (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            content TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS kael_config (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS app_projects (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            description TEXT NOT NULL,
+            status TEXT NOT NULL,
+            version TEXT NOT NULL,
+            archived INTEGER NOT NULL DEFAULT 0,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL,
+            synced INTEGER NOT NULL DEFAULT 0,
+            firebase_synced_at TEXT
+        );",
+    )?;
+
+    // Older chat_messages tables predate sessions.
+    if !has_column(conn, "chat_messages", "session_id")? {
+        conn.execute_batch(
+            "ALTER TABLE chat_messages ADD COLUMN session_id TEXT
+                REFERENCES sessions(id) ON DELETE CASCADE;",
+        )?;
+    }
+
+    conn.execute_batch(
+        "CREATE INDEX IF NOT EXISTS idx_chat_messages_session
+            ON chat_messages(session_id, timestamp);
+        CREATE INDEX IF NOT EXISTS idx_sessions_updated
+            ON sessions(updated_at DESC);",
+    )?;
+
+    Ok(())
+}
+
+/// Script library: descriptions, tags and run history.
+fn migrate_v2(conn: &Connection) -> Result<()> {
+    if !has_column(conn, "scripts", "description")? {
+        conn.execute_batch("ALTER TABLE scripts ADD COLUMN description TEXT NOT NULL DEFAULT '';")?;
+    }
+    if !has_column(conn, "scripts", "tags")? {
+        // JSON array of tag strings
+        conn.execute_batch("ALTER TABLE scripts ADD COLUMN tags TEXT NOT NULL DEFAULT '[]';")?;
+    }
+
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS script_runs (
+            id TEXT PRIMARY KEY,
+            script_id TEXT NOT NULL REFERENCES scripts(id) ON DELETE CASCADE,
+            command TEXT NOT NULL,
+            args TEXT NOT NULL DEFAULT '{}',
+            exit_code INTEGER,
+            started_at TEXT NOT NULL,
+            finished_at TEXT
+        );
+        CREATE INDEX IF NOT EXISTS idx_script_runs_script
+            ON script_runs(script_id, started_at DESC);",
+    )?;
+
+    Ok(())
+}
+
+/// Sync engine: merge metadata for every synced record and the outbox of
+/// records waiting to be pushed.
+fn migrate_v3(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS sync_records (
+            collection TEXT NOT NULL,
+            id TEXT NOT NULL,
+            -- Versioned record as JSON
+            doc TEXT NOT NULL,
+            PRIMARY KEY (collection, id)
+        );
+
+        CREATE TABLE IF NOT EXISTS sync_outbox (
+            seq INTEGER PRIMARY KEY AUTOINCREMENT,
+            collection TEXT NOT NULL,
+            record_id TEXT NOT NULL,
+            op TEXT NOT NULL,
+            queued_at TEXT NOT NULL,
+            attempts INTEGER NOT NULL DEFAULT 0,
+            last_error TEXT,
+            -- Unix milliseconds; NULL means now
+            retry_at INTEGER,
+            UNIQUE (collection, record_id)
+        );",
+    )?;
+    Ok(())
+}
+
+/// Copy conversations from the chat history database used before
+/// kael-storage (`conversations` and `messages` with integer ids) into
+/// `sessions` and `chat_messages`. Imported rows keep `legacy-` ids, so
+/// importing the same file again adds nothing. Returns the number of
+/// messages imported.
+pub fn import_legacy_chat_history(conn: &mut Connection, legacy_path: &Path) -> Result<usize> {
+    conn.execute(
+        "ATTACH DATABASE ?1 AS legacy",
+        [legacy_path.to_string_lossy().as_ref()],
+    )?;
+    let imported = copy_legacy_chat_history(conn);
+    conn.execute_batch("DETACH DATABASE legacy;")?;
+    let imported = imported?;
+    if imported > 0 {
+        info!("Imported {} messages from {}", imported, legacy_path.display());
+    }
+    Ok(imported)
+}
+
+fn copy_legacy_chat_history(conn: &mut Connection) -> Result<usize> {
+    let tables: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM legacy.sqlite_master
+         WHERE type = 'table' AND name IN ('conversations', 'messages')",
+        [],
+        |row| row.get(0),
+    )?;
+    if tables < 2 {
+        return Ok(0);
+    }
+
+    // The old schema stored SQLite's `datetime('now')`, e.g. `2025-01-01 09:30:00` in UTC
+    let tx = conn.transaction()?;
+    tx.execute(
+        "INSERT OR IGNORE INTO sessions (id, title, provider, model, created_at, updated_at)
+         SELECT 'legacy-' || id, title, provider, model,
+                COALESCE(strftime('%Y-%m-%dT%H:%M:%SZ', created_at), created_at),
+                COALESCE(strftime('%Y-%m-%dT%H:%M:%SZ', updated_at), updated_at)
+         FROM legacy.conversations",
+        [],
+    )?;
+    let imported = tx.execute(
+        "INSERT OR IGNORE INTO chat_messages (id, session_id, role, text, timestamp, synced)
+         SELECT 'legacy-' || m.conversation_id || '-' || m.id, 'legacy-' || m.conversation_id,
+                m.role, m.content,
+                COALESCE(strftime('%Y-%m-%dT%H:%M:%SZ', m.timestamp), m.timestamp), 0
+         FROM legacy.messages m
+         JOIN legacy.conversations c ON c.id = m.conversation_id",
+        [],
+    )?;
+    tx.commit()?;
+    Ok(imported)
+}
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> Result<bool> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({table})"))?;
+    let names = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(names.iter().any(|name| name == column))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrations_are_idempotent() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        run_migrations(&mut conn).unwrap();
+        run_migrations(&mut conn).unwrap();
+
+        let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0)).unwrap();
+        assert_eq!(version, SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn test_legacy_chat_messages_gain_session_column() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE chat_messages (
+                id TEXT PRIMARY KEY,
+                role TEXT NOT NULL,
+                text TEXT NOT NULL,
+                timestamp TEXT NOT NULL,
+                synced INTEGER DEFAULT 0
+            );
+            INSERT INTO chat_messages (id, role, text, timestamp) VALUES ('m1', 'user', 'hi', '2025-01-01T00:00:00Z');",
+        )
+        .unwrap();
+
+        run_migrations(&mut conn).unwrap();
+
+        assert!(has_column(&conn, "chat_messages", "session_id").unwrap());
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM chat_messages WHERE session_id IS NULL", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_import_legacy_chat_history() {
+        let dir = std::env::temp_dir().join(format!("kael-legacy-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let legacy_path = dir.join("chat_history.db");
+        // Schema and rows as written by the old services/chat_history.rs
+        Connection::open(&legacy_path)
+            .unwrap()
+            .execute_batch(
+                "PRAGMA foreign_keys = OFF;
+                CREATE TABLE conversations (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    title TEXT NOT NULL,
+                    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+                    updated_at TEXT NOT NULL DEFAULT (datetime('now')),
+                    provider TEXT NOT NULL,
+                    model TEXT NOT NULL
+                );
+                CREATE TABLE messages (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    conversation_id INTEGER NOT NULL,
+                    role TEXT NOT NULL CHECK(role IN ('user', 'assistant')),
+                    content TEXT NOT NULL,
+                    timestamp TEXT NOT NULL DEFAULT (datetime('now')),
+                    FOREIGN KEY (conversation_id) REFERENCES conversations(id) ON DELETE CASCADE
+                );
+                INSERT INTO conversations (title, created_at, updated_at, provider, model)
+                    VALUES ('Pacman lock', '2025-01-01 09:30:00', '2025-01-01 09:31:00', 'ollama', 'llama3');
+                INSERT INTO messages (conversation_id, role, content, timestamp)
+                    VALUES (1, 'user', 'why is pacman locked?', '2025-01-01 09:30:00'),
+                           (1, 'assistant', 'Remove /var/lib/pacman/db.lck', '2025-01-01 09:31:00'),
+                           (7, 'user', 'orphan', '2025-01-01 10:00:00');",
+            )
+            .unwrap();
+
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("PRAGMA foreign_keys = ON;").unwrap();
+        run_migrations(&mut conn).unwrap();
+        assert_eq!(import_legacy_chat_history(&mut conn, &legacy_path).unwrap(), 2);
+        assert_eq!(import_legacy_chat_history(&mut conn, &legacy_path).unwrap(), 0);
+
+        let session: (String, String, String) = conn
+            .query_row("SELECT id, title, created_at FROM sessions", [], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .unwrap();
+        assert_eq!(
+            session,
+            ("legacy-1".to_string(), "Pacman lock".to_string(), "2025-01-01T09:30:00Z".to_string())
+        );
+        let messages: Vec<(String, String)> = conn
+            .prepare("SELECT role, text FROM chat_messages WHERE session_id = 'legacy-1' ORDER BY timestamp")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(messages[0], ("user".to_string(), "why is pacman locked?".to_string()));
+        assert_eq!(messages[1].0, "assistant");
+
+        // A file without the old tables is left alone
+        let empty = dir.join("empty.db");
+        Connection::open(&empty).unwrap().execute_batch("CREATE TABLE t (x);").unwrap();
+        assert_eq!(import_legacy_chat_history(&mut conn, &empty).unwrap(), 0);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,286 @@
+//! App tracker projects.
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{parse_timestamp, StorageManager, Syncable};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum AppStatus {
+    #[serde(rename = "making")]
+    Making,
+    #[serde(rename = "want")]
+    Want,
+    #[serde(rename = "testing")]
+    Testing,
+    #[serde(rename = "done")]
+    Done,
+}
+
+impl AppStatus {
+    pub fn color(&self) -> &'static str {
+        match self {
+            AppStatus::Making => "#e040fb",  // Magenta - actively working
+            AppStatus::Want => "#ffcc00",    // Yellow - planned/wanted
+            AppStatus::Testing => "#7aebbe", // Cyan - beta testing
+            AppStatus::Done => "#4ecca3",    // Green - completed
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            AppStatus::Making => "Making",
+            AppStatus::Want => "Want to Make",
+            AppStatus::Testing => "Testing",
+            AppStatus::Done => "Done",
+        }
+    }
+
+    /// Value stored in the database and in Firestore.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AppStatus::Making => "making",
+            AppStatus::Want => "want",
+            AppStatus::Testing => "testing",
+            AppStatus::Done => "done",
+        }
+    }
+
+    /// Inverse of `as_str`; unknown values fall back to `Want`.
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "making" => AppStatus::Making,
+            "testing" => AppStatus::Testing,
+            "done" => AppStatus::Done,
+            _ => AppStatus::Want,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AppProject {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub status: AppStatus,
+    pub version: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    #[serde(default)]
+    pub archived: bool,
+}
+
+impl AppProject {
+    pub fn new(name: String, description: String, status: AppStatus) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            name,
+            description,
+            status,
+            version: "0.0.1-alpha.1".to_string(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            archived: false,
+        }
+    }
+
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        Ok(AppProject {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            description: row.get(2)?,
+            status: AppStatus::parse(&row.get::<_, String>(3)?),
+            version: row.get(4)?,
+            archived: row.get::<_, i32>(5)? != 0,
+            created_at: parse_timestamp(&row.get::<_, String>(6)?),
+            updated_at: parse_timestamp(&row.get::<_, String>(7)?),
+        })
+    }
+}
+
+const SELECT_PROJECT: &str =
+    "SELECT id, name, description, status, version, archived, created_at, updated_at FROM app_projects";
+
+#[derive(Clone)]
+pub struct ProjectRepository {
+    storage: StorageManager,
+}
+
+impl ProjectRepository {
+    pub(crate) fn new(storage: StorageManager) -> Self {
+        Self { storage }
+    }
+
+    /// Insert or replace a project. Saving marks it as not yet synced.
+    pub async fn save(&self, project: &AppProject) -> Result<()> {
+        let project = project.clone();
+        self.storage
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT OR REPLACE INTO app_projects
+                    (id, name, description, status, version, archived, created_at, updated_at, synced)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, 0)",
+                    params![
+                        project.id,
+                        project.name,
+                        project.description,
+                        project.status.as_str(),
+                        project.version,
+                        project.archived as i32,
+                        project.created_at.to_rfc3339(),
+                        project.updated_at.to_rfc3339(),
+                    ],
+                )?;
+                Ok(())
+            })
+            .await
+    }
+
+    pub async fn get(&self, id: &str) -> Result<Option<AppProject>> {
+        let id = id.to_string();
+        self.storage
+            .call(move |conn| {
+                let project = conn
+                    .query_row(&format!("{SELECT_PROJECT} WHERE id = ?1"), [id], AppProject::from_row)
+                    .optional()?;
+                Ok(project)
+            })
+            .await
+    }
+
+    /// All projects, most recently updated first.
+    pub async fn list(&self) -> Result<Vec<AppProject>> {
+        self.query(format!("{SELECT_PROJECT} ORDER BY updated_at DESC")).await
+    }
+
+    /// Projects that are not archived.
+    pub async fn list_active(&self) -> Result<Vec<AppProject>> {
+        self.query(format!("{SELECT_PROJECT} WHERE archived = 0 ORDER BY updated_at DESC"))
+            .await
+    }
+
+    pub async fn list_archived(&self) -> Result<Vec<AppProject>> {
+        self.query(format!("{SELECT_PROJECT} WHERE archived = 1 ORDER BY updated_at DESC"))
+            .await
+    }
+
+    async fn query(&self, sql: String) -> Result<Vec<AppProject>> {
+        self.storage
+            .call(move |conn| {
+                let mut stmt = conn.prepare(&sql)?;
+                let projects = stmt
+                    .query_map([], AppProject::from_row)?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(projects)
+            })
+            .await
+    }
+
+    pub async fn set_archived(&self, id: &str, archived: bool) -> Result<()> {
+        let id = id.to_string();
+        self.storage
+            .call(move |conn| {
+                conn.execute(
+                    "UPDATE app_projects SET archived = ?1, updated_at = ?2, synced = 0 WHERE id = ?3",
+                    params![archived as i32, Utc::now().to_rfc3339(), id],
+                )?;
+                Ok(())
+            })
+            .await
+    }
+
+    pub async fn delete(&self, id: &str) -> Result<()> {
+        let id = id.to_string();
+        self.storage
+            .call(move |conn| {
+                conn.execute("DELETE FROM app_projects WHERE id = ?1", [id])?;
+                Ok(())
+            })
+            .await
+    }
+
+    /// Record that the given projects now match Firestore.
+    pub async fn mark_synced(&self, ids: &[String]) -> Result<()> {
+        let ids = ids.to_vec();
+        self.storage
+            .call(move |conn| {
+                let tx = conn.transaction()?;
+                let now = Utc::now().to_rfc3339();
+                for id in &ids {
+                    tx.execute(
+                        "UPDATE app_projects SET synced = 1, firebase_synced_at = ?1 WHERE id = ?2",
+                        params![now, id],
+                    )?;
+                }
+                tx.commit()?;
+                Ok(())
+            })
+            .await
+    }
+}
+
+#[async_trait]
+impl Syncable for AppProject {
+    const COLLECTION: &'static str = "projects";
+
+    fn sync_id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn modified_at(&self) -> Option<DateTime<Utc>> {
+        Some(self.updated_at)
+    }
+
+    async fn load_all(storage: &StorageManager) -> Result<Vec<Self>> {
+        storage.projects().list().await
+    }
+
+    async fn load(storage: &StorageManager, id: &str) -> Result<Option<Self>> {
+        storage.projects().get(id).await
+    }
+
+    async fn store(storage: &StorageManager, record: &Self) -> Result<()> {
+        let projects = storage.projects();
+        projects.save(record).await?;
+        projects.mark_synced(std::slice::from_ref(&record.id)).await
+    }
+
+    async fn remove(storage: &StorageManager, id: &str) -> Result<()> {
+        storage.projects().delete(id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_project_crud_and_archive() {
+        let storage = StorageManager::open_in_memory().unwrap();
+        let projects = storage.projects();
+
+        let project = AppProject::new("Kael".into(), "AI terminal".into(), AppStatus::Making);
+        projects.save(&project).await.unwrap();
+        assert_eq!(projects.get(&project.id).await.unwrap().unwrap().name, "Kael");
+
+        projects.set_archived(&project.id, true).await.unwrap();
+        assert!(projects.list_active().await.unwrap().is_empty());
+        let archived = projects.list_archived().await.unwrap();
+        assert_eq!(archived.len(), 1);
+        assert_eq!(archived[0].status, AppStatus::Making);
+
+        projects.delete(&project.id).await.unwrap();
+        assert!(projects.list().await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_status_round_trip() {
+        for status in [AppStatus::Making, AppStatus::Want, AppStatus::Testing, AppStatus::Done] {
+            assert_eq!(AppStatus::parse(status.as_str()), status);
+        }
+        assert_eq!(AppStatus::parse("bogus"), AppStatus::Want);
+    }
+}
@@ -0,0 +1,338 @@
+//! Saved shell scripts and their run history.
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{parse_timestamp, StorageManager, Syncable};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Script {
+    pub id: String,
+    pub name: String,
+    pub content: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Script {
+    pub fn new(name: impl Into<String>, content: impl Into<String>) -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::new_v4().to_string(),
+            name: name.into(),
+            content: content.into(),
+            description: String::new(),
+            tags: Vec::new(),
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t.eq_ignore_ascii_case(tag))
+    }
+
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        let tags: String = row.get(4)?;
+        Ok(Script {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            content: row.get(2)?,
+            description: row.get(3)?,
+            tags: serde_json::from_str(&tags).unwrap_or_default(),
+            created_at: parse_timestamp(&row.get::<_, String>(5)?),
+            updated_at: parse_timestamp(&row.get::<_, String>(6)?),
+        })
+    }
+}
+
+/// One execution of a script. `exit_code` stays `None` until the run finishes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScriptRun {
+    pub id: String,
+    pub script_id: String,
+    /// Command line as sent to the terminal, after placeholder substitution.
+    pub command: String,
+    pub args: BTreeMap<String, String>,
+    pub exit_code: Option<i32>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+impl ScriptRun {
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        let args: String = row.get(3)?;
+        Ok(ScriptRun {
+            id: row.get(0)?,
+            script_id: row.get(1)?,
+            command: row.get(2)?,
+            args: serde_json::from_str(&args).unwrap_or_default(),
+            exit_code: row.get(4)?,
+            started_at: parse_timestamp(&row.get::<_, String>(5)?),
+            finished_at: row.get::<_, Option<String>>(6)?.map(|s| parse_timestamp(&s)),
+        })
+    }
+}
+
+const SELECT_SCRIPT: &str =
+    "SELECT id, name, content, description, tags, created_at, updated_at FROM scripts";
+
+const SELECT_RUN: &str =
+    "SELECT id, script_id, command, args, exit_code, started_at, finished_at FROM script_runs";
+
+#[derive(Clone)]
+pub struct ScriptRepository {
+    storage: StorageManager,
+}
+
+impl ScriptRepository {
+    pub(crate) fn new(storage: StorageManager) -> Self {
+        Self { storage }
+    }
+
+    /// Insert or update a script, keyed by id.
+    pub async fn save(&self, script: &Script) -> Result<()> {
+        let script = script.clone();
+        let tags = serde_json::to_string(&script.tags)?;
+        self.storage
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO scripts (id, name, content, description, tags, created_at, updated_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                     ON CONFLICT(id) DO UPDATE SET
+                        name = excluded.name,
+                        content = excluded.content,
+                        description = excluded.description,
+                        tags = excluded.tags,
+                        updated_at = excluded.updated_at",
+                    params![
+                        script.id,
+                        script.name,
+                        script.content,
+                        script.description,
+                        tags,
+                        script.created_at.to_rfc3339(),
+                        script.updated_at.to_rfc3339(),
+                    ],
+                )?;
+                Ok(())
+            })
+            .await
+    }
+
+    pub async fn get(&self, id: &str) -> Result<Option<Script>> {
+        let id = id.to_string();
+        self.storage
+            .call(move |conn| {
+                let script = conn
+                    .query_row(&format!("{SELECT_SCRIPT} WHERE id = ?1"), [id], Script::from_row)
+                    .optional()?;
+                Ok(script)
+            })
+            .await
+    }
+
+    /// All scripts sorted by name.
+    pub async fn list(&self) -> Result<Vec<Script>> {
+        self.storage
+            .call(|conn| {
+                let mut stmt = conn.prepare(&format!("{SELECT_SCRIPT} ORDER BY name COLLATE NOCASE"))?;
+                let scripts = stmt
+                    .query_map([], Script::from_row)?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(scripts)
+            })
+            .await
+    }
+
+    /// Scripts carrying `tag` (case-insensitive), sorted by name.
+    pub async fn list_by_tag(&self, tag: &str) -> Result<Vec<Script>> {
+        let mut scripts = self.list().await?;
+        scripts.retain(|s| s.has_tag(tag));
+        Ok(scripts)
+    }
+
+    /// Every distinct tag in use, sorted.
+    pub async fn tags(&self) -> Result<Vec<String>> {
+        let mut tags: Vec<String> = self.list().await?.into_iter().flat_map(|s| s.tags).collect();
+        tags.sort_by_key(|t| t.to_lowercase());
+        tags.dedup_by(|a, b| a.eq_ignore_ascii_case(b));
+        Ok(tags)
+    }
+
+    /// Delete a script together with its run history.
+    pub async fn delete(&self, id: &str) -> Result<()> {
+        let id = id.to_string();
+        self.storage
+            .call(move |conn| {
+                conn.execute("DELETE FROM scripts WHERE id = ?1", [id])?;
+                Ok(())
+            })
+            .await
+    }
+
+    /// Record that a run has started.
+    pub async fn start_run(
+        &self,
+        script_id: &str,
+        command: &str,
+        args: &BTreeMap<String, String>,
+    ) -> Result<ScriptRun> {
+        let run = ScriptRun {
+            id: Uuid::new_v4().to_string(),
+            script_id: script_id.to_string(),
+            command: command.to_string(),
+            args: args.clone(),
+            exit_code: None,
+            started_at: Utc::now(),
+            finished_at: None,
+        };
+        let record = run.clone();
+        let args = serde_json::to_string(&record.args)?;
+        self.storage
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO script_runs (id, script_id, command, args, started_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![record.id, record.script_id, record.command, args, record.started_at.to_rfc3339()],
+                )?;
+                Ok(())
+            })
+            .await?;
+        Ok(run)
+    }
+
+    /// Store the exit status of a run. Returns false if the run id is unknown.
+    pub async fn finish_run(&self, run_id: &str, exit_code: i32) -> Result<bool> {
+        let run_id = run_id.to_string();
+        self.storage
+            .call(move |conn| {
+                let updated = conn.execute(
+                    "UPDATE script_runs SET exit_code = ?1, finished_at = ?2 WHERE id = ?3",
+                    params![exit_code, Utc::now().to_rfc3339(), run_id],
+                )?;
+                Ok(updated > 0)
+            })
+            .await
+    }
+
+    /// Most recent runs of a script, newest first.
+    pub async fn runs(&self, script_id: &str, limit: usize) -> Result<Vec<ScriptRun>> {
+        let script_id = script_id.to_string();
+        self.storage
+            .call(move |conn| {
+                let mut stmt = conn.prepare(&format!(
+                    "{SELECT_RUN} WHERE script_id = ?1 ORDER BY started_at DESC, rowid DESC LIMIT ?2"
+                ))?;
+                let runs = stmt
+                    .query_map(params![script_id, limit as i64], ScriptRun::from_row)?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(runs)
+            })
+            .await
+    }
+}
+
+#[async_trait]
+impl Syncable for Script {
+    const COLLECTION: &'static str = "scripts";
+
+    fn sync_id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn modified_at(&self) -> Option<DateTime<Utc>> {
+        Some(self.updated_at)
+    }
+
+    async fn load_all(storage: &StorageManager) -> Result<Vec<Self>> {
+        storage.scripts().list().await
+    }
+
+    async fn load(storage: &StorageManager, id: &str) -> Result<Option<Self>> {
+        storage.scripts().get(id).await
+    }
+
+    async fn store(storage: &StorageManager, record: &Self) -> Result<()> {
+        storage.scripts().save(record).await
+    }
+
+    async fn remove(storage: &StorageManager, id: &str) -> Result<()> {
+        storage.scripts().delete(id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_script_crud() {
+        let storage = StorageManager::open_in_memory().unwrap();
+        let scripts = storage.scripts();
+
+        let mut script = Script::new("update", "paru -Syu");
+        scripts.save(&script).await.unwrap();
+
+        script.content = "paru -Syu --noconfirm".to_string();
+        script.updated_at = Utc::now();
+        scripts.save(&script).await.unwrap();
+
+        let all = scripts.list().await.unwrap();
+        assert_eq!(all.len(), 1);
+        assert_eq!(all[0].content, "paru -Syu --noconfirm");
+
+        scripts.delete(&script.id).await.unwrap();
+        assert!(scripts.get(&script.id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_tags() {
+        let storage = StorageManager::open_in_memory().unwrap();
+        let scripts = storage.scripts();
+
+        let mut update = Script::new("update", "paru -Syu");
+        update.tags = vec!["pacman".into(), "Maintenance".into()];
+        let mut logs = Script::new("logs", "journalctl -f");
+        logs.tags = vec!["maintenance".into()];
+        scripts.save(&update).await.unwrap();
+        scripts.save(&logs).await.unwrap();
+
+        assert_eq!(scripts.list_by_tag("MAINTENANCE").await.unwrap().len(), 2);
+        assert_eq!(scripts.list_by_tag("pacman").await.unwrap()[0].name, "update");
+        assert_eq!(scripts.tags().await.unwrap(), vec!["maintenance", "pacman"]);
+    }
+
+    #[tokio::test]
+    async fn test_run_history() {
+        let storage = StorageManager::open_in_memory().unwrap();
+        let scripts = storage.scripts();
+        let script = Script::new("greet", "echo {{name}}");
+        scripts.save(&script).await.unwrap();
+
+        let args = BTreeMap::from([("name".to_string(), "kael".to_string())]);
+        let first = scripts.start_run(&script.id, "echo kael", &args).await.unwrap();
+        assert!(scripts.finish_run(&first.id, 0).await.unwrap());
+        let second = scripts.start_run(&script.id, "echo kael", &args).await.unwrap();
+        assert!(scripts.finish_run(&second.id, 2).await.unwrap());
+        assert!(!scripts.finish_run("missing", 1).await.unwrap());
+
+        let runs = scripts.runs(&script.id, 10).await.unwrap();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].exit_code, Some(2));
+        assert_eq!(runs[1].args["name"], "kael");
+        assert!(runs[1].finished_at.is_some());
+
+        scripts.delete(&script.id).await.unwrap();
+        assert!(scripts.runs(&script.id, 10).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,246 @@
+//! Chat sessions (conversations). Messages reference a session by id.
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{parse_timestamp, StorageManager, Syncable};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    pub title: String,
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Session {
+    pub fn new(title: impl Into<String>) -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::new_v4().to_string(),
+            title: title.into(),
+            provider: None,
+            model: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        Ok(Session {
+            id: row.get(0)?,
+            title: row.get(1)?,
+            provider: row.get(2)?,
+            model: row.get(3)?,
+            created_at: parse_timestamp(&row.get::<_, String>(4)?),
+            updated_at: parse_timestamp(&row.get::<_, String>(5)?),
+        })
+    }
+}
+
+const SELECT_SESSION: &str =
+    "SELECT id, title, provider, model, created_at, updated_at FROM sessions";
+
+#[derive(Clone)]
+pub struct SessionRepository {
+    storage: StorageManager,
+}
+
+impl SessionRepository {
+    pub(crate) fn new(storage: StorageManager) -> Self {
+        Self { storage }
+    }
+
+    /// Create a session with the given title and optional provider/model labels.
+    pub async fn create(
+        &self,
+        title: &str,
+        provider: Option<&str>,
+        model: Option<&str>,
+    ) -> Result<Session> {
+        let mut session = Session::new(title);
+        session.provider = provider.map(str::to_string);
+        session.model = model.map(str::to_string);
+        self.upsert(&session).await?;
+        Ok(session)
+    }
+
+    /// Insert or replace a session record as-is.
+    pub async fn upsert(&self, session: &Session) -> Result<()> {
+        let session = session.clone();
+        self.storage
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO sessions (id, title, provider, model, created_at, updated_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                     ON CONFLICT(id) DO UPDATE SET
+                        title = excluded.title,
+                        provider = excluded.provider,
+                        model = excluded.model,
+                        updated_at = excluded.updated_at",
+                    params![
+                        session.id,
+                        session.title,
+                        session.provider,
+                        session.model,
+                        session.created_at.to_rfc3339(),
+                        session.updated_at.to_rfc3339(),
+                    ],
+                )?;
+                Ok(())
+            })
+            .await
+    }
+
+    pub async fn get(&self, id: &str) -> Result<Option<Session>> {
+        let id = id.to_string();
+        self.storage
+            .call(move |conn| {
+                let session = conn
+                    .query_row(
+                        &format!("{SELECT_SESSION} WHERE id = ?1"),
+                        [id],
+                        Session::from_row,
+                    )
+                    .optional()?;
+                Ok(session)
+            })
+            .await
+    }
+
+    /// All sessions, most recently updated first.
+    pub async fn list(&self) -> Result<Vec<Session>> {
+        self.storage
+            .call(|conn| {
+                let mut stmt = conn.prepare(&format!("{SELECT_SESSION} ORDER BY updated_at DESC"))?;
+                let sessions = stmt
+                    .query_map([], Session::from_row)?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(sessions)
+            })
+            .await
+    }
+
+    /// Sessions whose title contains `query` (case-insensitive).
+    pub async fn search(&self, query: &str) -> Result<Vec<Session>> {
+        let pattern = format!("%{}%", query);
+        self.storage
+            .call(move |conn| {
+                let mut stmt = conn.prepare(&format!(
+                    "{SELECT_SESSION} WHERE title LIKE ?1 ORDER BY updated_at DESC"
+                ))?;
+                let sessions = stmt
+                    .query_map([pattern], Session::from_row)?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(sessions)
+            })
+            .await
+    }
+
+    pub async fn rename(&self, id: &str, title: &str) -> Result<()> {
+        let (id, title) = (id.to_string(), title.to_string());
+        self.storage
+            .call(move |conn| {
+                conn.execute(
+                    "UPDATE sessions SET title = ?1, updated_at = ?2 WHERE id = ?3",
+                    params![title, Utc::now().to_rfc3339(), id],
+                )?;
+                Ok(())
+            })
+            .await
+    }
+
+    /// Bump `updated_at` so the session sorts to the top of `list`.
+    pub async fn touch(&self, id: &str) -> Result<()> {
+        let id = id.to_string();
+        self.storage
+            .call(move |conn| {
+                conn.execute(
+                    "UPDATE sessions SET updated_at = ?1 WHERE id = ?2",
+                    params![Utc::now().to_rfc3339(), id],
+                )?;
+                Ok(())
+            })
+            .await
+    }
+
+    /// Delete a session and, via the foreign key, all of its messages.
+    pub async fn delete(&self, id: &str) -> Result<()> {
+        let id = id.to_string();
+        self.storage
+            .call(move |conn| {
+                conn.execute("DELETE FROM sessions WHERE id = ?1", [id])?;
+                Ok(())
+            })
+            .await
+    }
+}
+
+#[async_trait]
+impl Syncable for Session {
+    const COLLECTION: &'static str = "chat_sessions";
+
+    fn sync_id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn modified_at(&self) -> Option<DateTime<Utc>> {
+        Some(self.updated_at)
+    }
+
+    async fn load_all(storage: &StorageManager) -> Result<Vec<Self>> {
+        storage.sessions().list().await
+    }
+
+    async fn load(storage: &StorageManager, id: &str) -> Result<Option<Self>> {
+        storage.sessions().get(id).await
+    }
+
+    async fn store(storage: &StorageManager, record: &Self) -> Result<()> {
+        storage.sessions().upsert(record).await
+    }
+
+    async fn remove(storage: &StorageManager, id: &str) -> Result<()> {
+        storage.sessions().delete(id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_session_crud() {
+        let storage = StorageManager::open_in_memory().unwrap();
+        let sessions = storage.sessions();
+
+        let created = sessions.create("Pacman help", Some("ollama"), Some("llama3")).await.unwrap();
+        let loaded = sessions.get(&created.id).await.unwrap().unwrap();
+        assert_eq!(loaded.title, "Pacman help");
+        assert_eq!(loaded.provider.as_deref(), Some("ollama"));
+
+        sessions.rename(&created.id, "Paru help").await.unwrap();
+        let found = sessions.search("paru").await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].title, "Paru help");
+
+        sessions.delete(&created.id).await.unwrap();
+        assert!(sessions.get(&created.id).await.unwrap().is_none());
+        assert!(sessions.list().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_delete_session_cascades_to_messages() {
+        let storage = StorageManager::open_in_memory().unwrap();
+        let session = storage.sessions().create("Temp", None, None).await.unwrap();
+        storage.messages().add(Some(&session.id), "user", "hello").await.unwrap();
+
+        storage.sessions().delete(&session.id).await.unwrap();
+        assert!(storage.messages().list_for_session(&session.id).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,484 @@
+//! Offline-first sync of local tables with a remote store.
+//!
+//! Every synced record keeps merge metadata in `sync_records` (see
+//! [`Versioned`]). A sync run for one collection:
+//!
+//! 1. **Scan**: compares each local row with its metadata. Changed fields get
+//!    a new stamp (the row's own `updated_at` when it has one), rows that
+//!    disappeared become tombstones, and both are queued in `sync_outbox`.
+//! 2. **Pull**: merges every remote copy into the local one and writes the
+//!    result back to the table. A record the remote is behind on is queued
+//!    again, so an overwritten push is repaired on the next run.
+//! 3. **Push**: sends queued records. A failed push is retried with backoff;
+//!    the outbox survives restarts, so edits made offline go out once the
+//!    remote is reachable.
+//!
+//! Tombstones are kept so devices that were away still learn of deletes.
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use tracing::warn;
+
+use crate::StorageManager;
+
+pub mod outbox;
+pub mod versioned;
+
+pub use outbox::{retry_delay_ms, OutboxEntry, SyncRepository};
+pub use versioned::{Fields, Stamp, VersionVector, Versioned};
+
+/// A record type that can be synced.
+#[async_trait]
+pub trait Syncable: Serialize + DeserializeOwned + Send + Sync + Sized + 'static {
+    /// Name of the remote collection and of the record's outbox entries
+    const COLLECTION: &'static str;
+    /// Fields that stay on this device
+    const LOCAL_FIELDS: &'static [&'static str] = &[];
+
+    fn sync_id(&self) -> String;
+
+    /// When the record last changed, if it keeps track
+    fn modified_at(&self) -> Option<DateTime<Utc>> {
+        None
+    }
+
+    async fn load_all(storage: &StorageManager) -> Result<Vec<Self>>;
+    async fn load(storage: &StorageManager, id: &str) -> Result<Option<Self>>;
+    /// Write a merged record to its table
+    async fn store(storage: &StorageManager, record: &Self) -> Result<()>;
+    async fn remove(storage: &StorageManager, id: &str) -> Result<()>;
+}
+
+/// Where records are synced to.
+#[async_trait]
+pub trait Remote: Send + Sync {
+    /// Every record of `collection`, tombstones included
+    async fn fetch_all(&self, collection: &str) -> Result<Vec<Versioned>>;
+    /// Create or replace one record
+    async fn store(&self, collection: &str, record: &Versioned) -> Result<()>;
+}
+
+/// What one sync run did.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SyncReport {
+    /// Remote changes written to local tables
+    pub downloaded: usize,
+    /// Records pushed to the remote
+    pub uploaded: usize,
+    /// Records both sides had changed since they last agreed
+    pub conflicts: usize,
+    /// Remote records that could not be written locally; retried next run
+    pub skipped: usize,
+    /// Records still waiting in the outbox
+    pub pending: usize,
+    /// Why the remote could not be reached, if it couldn't
+    pub offline: Option<String>,
+}
+
+impl SyncReport {
+    pub fn add(&mut self, other: SyncReport) {
+        self.downloaded += other.downloaded;
+        self.uploaded += other.uploaded;
+        self.conflicts += other.conflicts;
+        self.skipped += other.skipped;
+        self.pending += other.pending;
+        self.offline = self.offline.take().or(other.offline);
+    }
+}
+
+fn to_fields<T: Syncable>(record: &T) -> Result<Fields> {
+    let Value::Object(mut fields) = serde_json::to_value(record)? else {
+        anyhow::bail!("{} records must serialize to a map", T::COLLECTION);
+    };
+    for field in T::LOCAL_FIELDS {
+        fields.remove(*field);
+    }
+    Ok(fields)
+}
+
+/// Syncs one database with one remote.
+pub struct SyncEngine<R> {
+    storage: StorageManager,
+    remote: R,
+    device: String,
+    clock: Arc<dyn Fn() -> i64 + Send + Sync>,
+}
+
+impl<R: Remote> SyncEngine<R> {
+    pub async fn new(storage: StorageManager, remote: R) -> Result<Self> {
+        let device = storage.sync().device_id().await?;
+        Ok(Self {
+            storage,
+            remote,
+            device,
+            clock: Arc::new(|| Utc::now().timestamp_millis()),
+        })
+    }
+
+    /// Use `clock` (Unix milliseconds) instead of the system time.
+    pub fn with_clock(mut self, clock: impl Fn() -> i64 + Send + Sync + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    pub fn device(&self) -> &str {
+        &self.device
+    }
+
+    /// Scan, pull and push one collection.
+    pub async fn sync<T: Syncable>(&self) -> Result<SyncReport> {
+        let repo = self.storage.sync();
+        let mut report = SyncReport::default();
+
+        self.scan::<T>().await?;
+        match self.remote.fetch_all(T::COLLECTION).await {
+            Ok(remote) => {
+                self.pull::<T>(remote, &mut report).await?;
+                self.push::<T>(&mut report).await?;
+            }
+            Err(e) => report.offline = Some(e.to_string()),
+        }
+        report.pending = repo.pending(Some(T::COLLECTION)).await?;
+        Ok(report)
+    }
+
+    /// Queue local changes made since the last run.
+    async fn scan<T: Syncable>(&self) -> Result<()> {
+        let repo = self.storage.sync();
+        let now = (self.clock)();
+        let mut known: BTreeMap<String, Versioned> = repo
+            .records(T::COLLECTION)
+            .await?
+            .into_iter()
+            .map(|record| (record.id.clone(), record))
+            .collect();
+
+        let mut seen = HashSet::new();
+        for record in T::load_all(&self.storage).await? {
+            let id = record.sync_id();
+            let time = record.modified_at().map_or(now, |t| t.timestamp_millis());
+            let fields = to_fields(&record)?;
+            if let Some(next) = Versioned::edit(known.get(&id), &id, fields, time, &self.device) {
+                repo.save(T::COLLECTION, &next, true).await?;
+            }
+            seen.insert(id);
+        }
+
+        known.retain(|id, _| !seen.contains(id));
+        for record in known.values() {
+            if let Some(next) = record.delete(now, &self.device) {
+                repo.save(T::COLLECTION, &next, true).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn pull<T: Syncable>(&self, remote: Vec<Versioned>, report: &mut SyncReport) -> Result<()> {
+        let repo = self.storage.sync();
+        for theirs in remote {
+            let ours = repo.record(T::COLLECTION, &theirs.id).await?;
+            let merged = match &ours {
+                Some(ours) => {
+                    if ours.version.compare(&theirs.version).is_none() {
+                        report.conflicts += 1;
+                    }
+                    ours.merge(&theirs)
+                }
+                None => theirs.clone(),
+            };
+
+            let changed = ours.as_ref() != Some(&merged);
+            if changed {
+                if let Err(e) = self.apply::<T>(&merged).await {
+                    warn!("Skipping {} {}: {}", T::COLLECTION, merged.id, e);
+                    report.skipped += 1;
+                    continue;
+                }
+                report.downloaded += 1;
+            }
+            // The remote lacks edits this device has: send them again
+            let behind = merged != theirs;
+            if changed || behind {
+                repo.save(T::COLLECTION, &merged, behind).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write a merged record to the local table, keeping its local fields.
+    async fn apply<T: Syncable>(&self, merged: &Versioned) -> Result<()> {
+        if merged.is_deleted() {
+            return T::remove(&self.storage, &merged.id).await;
+        }
+        let mut fields = match T::load(&self.storage, &merged.id).await? {
+            Some(current) => match serde_json::to_value(current)? {
+                Value::Object(fields) => fields,
+                _ => Fields::new(),
+            },
+            None => Fields::new(),
+        };
+        for (key, value) in &merged.fields {
+            if !T::LOCAL_FIELDS.contains(&key.as_str()) {
+                fields.insert(key.clone(), value.clone());
+            }
+        }
+        let record: T = serde_json::from_value(Value::Object(fields))?;
+        T::store(&self.storage, &record).await
+    }
+
+    async fn push<T: Syncable>(&self, report: &mut SyncReport) -> Result<()> {
+        let repo = self.storage.sync();
+        let now = (self.clock)();
+        for entry in repo.due(T::COLLECTION, now).await? {
+            let Some(record) = repo.record(T::COLLECTION, &entry.record_id).await? else {
+                repo.complete(entry.seq).await?;
+                continue;
+            };
+            match self.remote.store(T::COLLECTION, &record).await {
+                Ok(()) => {
+                    repo.complete(entry.seq).await?;
+                    report.uploaded += 1;
+                }
+                Err(e) => {
+                    // Most likely offline: leave the rest for the next run
+                    repo.fail(entry.seq, &e.to_string(), now).await?;
+                    report.offline = Some(e.to_string());
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+    use std::sync::Mutex;
+
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::{AppProject, AppStatus, ChatMessage, Script};
+
+    /// The cloud both devices sync with
+    #[derive(Clone, Default)]
+    struct MemoryRemote {
+        docs: Arc<Mutex<BTreeMap<(String, String), Versioned>>>,
+        offline: Arc<AtomicBool>,
+        /// Reachable, but refusing writes
+        rejecting: Arc<AtomicBool>,
+    }
+
+    #[async_trait]
+    impl Remote for MemoryRemote {
+        async fn fetch_all(&self, collection: &str) -> Result<Vec<Versioned>> {
+            anyhow::ensure!(!self.offline.load(Ordering::SeqCst), "network unreachable");
+            let docs = self.docs.lock().unwrap();
+            Ok(docs
+                .iter()
+                .filter(|((c, _), _)| c == collection)
+                .map(|(_, doc)| doc.clone())
+                .collect())
+        }
+
+        async fn store(&self, collection: &str, record: &Versioned) -> Result<()> {
+            anyhow::ensure!(!self.offline.load(Ordering::SeqCst), "network unreachable");
+            anyhow::ensure!(!self.rejecting.load(Ordering::SeqCst), "permission denied");
+            let key = (collection.to_string(), record.id.clone());
+            self.docs.lock().unwrap().insert(key, record.clone());
+            Ok(())
+        }
+    }
+
+    struct Device {
+        storage: StorageManager,
+        engine: SyncEngine<MemoryRemote>,
+        clock: Arc<AtomicI64>,
+    }
+
+    impl Device {
+        async fn new(name: &str, remote: &MemoryRemote) -> Self {
+            let storage = StorageManager::open_in_memory().unwrap();
+            storage.config().set("sync.device_id", &name).await.unwrap();
+            let clock = Arc::new(AtomicI64::new(0));
+            let time = Arc::clone(&clock);
+            let engine = SyncEngine::new(storage.clone(), remote.clone())
+                .await
+                .unwrap()
+                .with_clock(move || time.load(Ordering::SeqCst));
+            Self { storage, engine, clock }
+        }
+
+        fn at(&self, ms: i64) -> DateTime<Utc> {
+            self.clock.store(ms, Ordering::SeqCst);
+            Utc.timestamp_millis_opt(ms).unwrap()
+        }
+
+        async fn sync(&self) -> SyncReport {
+            self.engine.sync::<AppProject>().await.unwrap()
+        }
+
+        async fn project(&self, id: &str) -> Option<AppProject> {
+            self.storage.projects().get(id).await.unwrap()
+        }
+
+        /// Change a project the way the app does: edit and bump `updated_at`
+        async fn edit(&self, id: &str, ms: i64, f: impl FnOnce(&mut AppProject)) {
+            let mut project = self.project(id).await.unwrap();
+            f(&mut project);
+            project.updated_at = self.at(ms);
+            self.storage.projects().save(&project).await.unwrap();
+        }
+    }
+
+    async fn new_project(device: &Device, ms: i64) -> AppProject {
+        let mut project = AppProject::new("Kael".into(), "AI terminal".into(), AppStatus::Want);
+        project.created_at = device.at(ms);
+        project.updated_at = device.at(ms);
+        device.storage.projects().save(&project).await.unwrap();
+        project
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_edits_merge_per_field() {
+        let cloud = MemoryRemote::default();
+        let laptop = Device::new("laptop", &cloud).await;
+        let desktop = Device::new("desktop", &cloud).await;
+
+        let project = new_project(&laptop, 1_000).await;
+        assert_eq!(laptop.sync().await.uploaded, 1);
+        let report = desktop.sync().await;
+        assert_eq!((report.downloaded, report.pending), (1, 0));
+        assert_eq!(desktop.project(&project.id).await.unwrap(), project);
+
+        // Both change the project before hearing from each other
+        laptop.edit(&project.id, 2_000, |p| p.name = "Kael-OS".into()).await;
+        laptop.edit(&project.id, 2_100, |p| p.description = "from the laptop".into()).await;
+        desktop
+            .edit(&project.id, 2_500, |p| {
+                p.status = AppStatus::Making;
+                p.description = "from the desktop".into();
+            })
+            .await;
+
+        laptop.sync().await;
+        let report = desktop.sync().await;
+        assert_eq!(report.conflicts, 1);
+        laptop.sync().await;
+
+        let merged = laptop.project(&project.id).await.unwrap();
+        assert_eq!(merged.name, "Kael-OS");
+        assert_eq!(merged.status, AppStatus::Making);
+        // Same field on both: the later edit wins
+        assert_eq!(merged.description, "from the desktop");
+        assert_eq!(desktop.project(&project.id).await.unwrap(), merged);
+
+        // Nothing left to do once both agree
+        assert_eq!(laptop.sync().await, SyncReport::default());
+        assert_eq!(desktop.sync().await, SyncReport::default());
+    }
+
+    #[tokio::test]
+    async fn test_offline_edits_and_deletes_are_retried() {
+        let cloud = MemoryRemote::default();
+        let laptop = Device::new("laptop", &cloud).await;
+        let desktop = Device::new("desktop", &cloud).await;
+        let kept = new_project(&laptop, 1_000).await;
+        let dropped = new_project(&laptop, 1_000).await;
+        laptop.sync().await;
+        desktop.sync().await;
+
+        // The desktop goes offline, deletes one project and edits the other
+        cloud.offline.store(true, Ordering::SeqCst);
+        desktop.at(5_000);
+        desktop.storage.projects().delete(&dropped.id).await.unwrap();
+        desktop.edit(&kept.id, 5_000, |p| p.version = "1.0.0".into()).await;
+        let report = desktop.sync().await;
+        assert!(report.offline.is_some());
+        assert_eq!(report.pending, 2);
+
+        // Back online: the queued changes go out
+        cloud.offline.store(false, Ordering::SeqCst);
+        let report = desktop.sync().await;
+        assert_eq!((report.uploaded, report.pending), (2, 0));
+
+        // A rejected push waits before it is tried again
+        desktop.edit(&kept.id, 6_000, |p| p.archived = true).await;
+        cloud.rejecting.store(true, Ordering::SeqCst);
+        let report = desktop.sync().await;
+        assert!(report.offline.is_some());
+        cloud.rejecting.store(false, Ordering::SeqCst);
+        assert_eq!(desktop.sync().await.uploaded, 0);
+        desktop.at(6_000 + retry_delay_ms(1));
+        assert_eq!(desktop.sync().await.uploaded, 1);
+
+        let report = laptop.sync().await;
+        assert_eq!(report.downloaded, 2);
+        assert_eq!(laptop.project(&dropped.id).await, None);
+        let edited = laptop.project(&kept.id).await.unwrap();
+        assert_eq!(edited.version, "1.0.0");
+        assert!(edited.archived);
+
+        // Re-creating a deleted record brings it back everywhere
+        let mut again = dropped.clone();
+        again.updated_at = laptop.at(7_000);
+        laptop.storage.projects().save(&again).await.unwrap();
+        laptop.sync().await;
+        desktop.sync().await;
+        assert_eq!(desktop.project(&dropped.id).await.unwrap().updated_at, again.updated_at);
+    }
+
+    #[tokio::test]
+    async fn test_failed_push_is_repaired_by_the_next_pull() {
+        let cloud = MemoryRemote::default();
+        let laptop = Device::new("laptop", &cloud).await;
+        let desktop = Device::new("desktop", &cloud).await;
+        let project = new_project(&laptop, 1_000).await;
+        laptop.sync().await;
+        desktop.sync().await;
+
+        // Another client overwrites the laptop's push with an older copy
+        laptop.edit(&project.id, 2_000, |p| p.name = "Renamed".into()).await;
+        laptop.sync().await;
+        let key = (AppProject::COLLECTION.to_string(), project.id.clone());
+        let stale = desktop.storage.sync().record(AppProject::COLLECTION, &project.id).await.unwrap();
+        cloud.docs.lock().unwrap().insert(key, stale.unwrap());
+
+        assert_eq!(laptop.sync().await.uploaded, 1);
+        desktop.sync().await;
+        assert_eq!(desktop.project(&project.id).await.unwrap().name, "Renamed");
+    }
+
+    #[tokio::test]
+    async fn test_scripts_and_messages_sync() {
+        let cloud = MemoryRemote::default();
+        let laptop = Device::new("laptop", &cloud).await;
+        let desktop = Device::new("desktop", &cloud).await;
+
+        let script = Script::new("update", "paru -Syu");
+        laptop.storage.scripts().save(&script).await.unwrap();
+        let session = laptop.storage.sessions().create("Pacman", None, None).await.unwrap();
+        let message = laptop.storage.messages().add(Some(&session.id), "user", "db locked?").await.unwrap();
+        laptop.storage.messages().mark_synced(std::slice::from_ref(&message.id)).await.unwrap();
+
+        for device in [&laptop, &desktop] {
+            device.engine.sync::<Script>().await.unwrap();
+            device.engine.sync::<crate::Session>().await.unwrap();
+            device.engine.sync::<ChatMessage>().await.unwrap();
+        }
+
+        assert_eq!(desktop.storage.scripts().get(&script.id).await.unwrap().unwrap(), script);
+        let messages = desktop.storage.messages().list_for_session(&session.id).await.unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].text, "db locked?");
+        // The synced flag is this device's own bookkeeping
+        assert!(!messages[0].synced);
+    }
+}
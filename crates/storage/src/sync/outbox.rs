@@ -0,0 +1,189 @@
+//! Merge metadata and the outbox, kept in `sync_records` and `sync_outbox`.
+use anyhow::Result;
+use chrono::Utc;
+use rusqlite::{params, OptionalExtension, Row};
+use uuid::Uuid;
+
+use super::Versioned;
+use crate::StorageManager;
+
+/// Config key holding this device's id.
+const DEVICE_KEY: &str = "sync.device_id";
+/// First retry of a failed push, doubled per attempt.
+const RETRY_MIN_MS: i64 = 5_000;
+const RETRY_MAX_MS: i64 = 15 * 60 * 1000;
+
+/// A record waiting to be pushed. The record itself is read from
+/// `sync_records` at push time, so several edits go out as one write.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutboxEntry {
+    pub seq: i64,
+    pub collection: String,
+    pub record_id: String,
+    /// `put` or `delete`
+    pub op: String,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+}
+
+impl OutboxEntry {
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        Ok(OutboxEntry {
+            seq: row.get(0)?,
+            collection: row.get(1)?,
+            record_id: row.get(2)?,
+            op: row.get(3)?,
+            attempts: row.get(4)?,
+            last_error: row.get(5)?,
+        })
+    }
+}
+
+/// Delay before retrying a push that failed `attempts` times.
+pub fn retry_delay_ms(attempts: u32) -> i64 {
+    let doublings = attempts.saturating_sub(1).min(20);
+    (RETRY_MIN_MS << doublings).min(RETRY_MAX_MS)
+}
+
+#[derive(Clone)]
+pub struct SyncRepository {
+    storage: StorageManager,
+}
+
+impl SyncRepository {
+    pub(crate) fn new(storage: StorageManager) -> Self {
+        Self { storage }
+    }
+
+    /// This database's device id, created on first use.
+    pub async fn device_id(&self) -> Result<String> {
+        let config = self.storage.config();
+        if let Some(id) = config.get::<String>(DEVICE_KEY).await? {
+            return Ok(id);
+        }
+        let id = Uuid::new_v4().to_string();
+        config.set(DEVICE_KEY, &id).await?;
+        Ok(id)
+    }
+
+    pub async fn record(&self, collection: &str, id: &str) -> Result<Option<Versioned>> {
+        let (collection, id) = (collection.to_string(), id.to_string());
+        self.storage
+            .call(move |conn| {
+                let doc: Option<String> = conn
+                    .query_row(
+                        "SELECT doc FROM sync_records WHERE collection = ?1 AND id = ?2",
+                        params![collection, id],
+                        |row| row.get(0),
+                    )
+                    .optional()?;
+                Ok(doc.map(|doc| serde_json::from_str(&doc)).transpose()?)
+            })
+            .await
+    }
+
+    /// Every tracked record of `collection`, tombstones included.
+    pub async fn records(&self, collection: &str) -> Result<Vec<Versioned>> {
+        let collection = collection.to_string();
+        self.storage
+            .call(move |conn| {
+                let mut stmt = conn.prepare("SELECT doc FROM sync_records WHERE collection = ?1 ORDER BY id")?;
+                let docs = stmt
+                    .query_map([collection], |row| row.get::<_, String>(0))?
+                    .collect::<Result<Vec<_>, _>>()?;
+                docs.iter()
+                    .map(|doc| Ok(serde_json::from_str(doc)?))
+                    .collect()
+            })
+            .await
+    }
+
+    /// Store `record`'s metadata and, with `push`, queue it for the remote.
+    /// A record that is already queued keeps its place and retry time.
+    pub async fn save(&self, collection: &str, record: &Versioned, push: bool) -> Result<()> {
+        let collection = collection.to_string();
+        let record = record.clone();
+        let doc = serde_json::to_string(&record)?;
+        self.storage
+            .call(move |conn| {
+                let tx = conn.transaction()?;
+                tx.execute(
+                    "INSERT INTO sync_records (collection, id, doc) VALUES (?1, ?2, ?3)
+                     ON CONFLICT(collection, id) DO UPDATE SET doc = excluded.doc",
+                    params![collection, record.id, doc],
+                )?;
+                if push {
+                    let op = if record.is_deleted() { "delete" } else { "put" };
+                    tx.execute(
+                        "INSERT INTO sync_outbox (collection, record_id, op, queued_at) VALUES (?1, ?2, ?3, ?4)
+                         ON CONFLICT(collection, record_id) DO UPDATE SET op = excluded.op",
+                        params![collection, record.id, op, Utc::now().to_rfc3339()],
+                    )?;
+                }
+                tx.commit()?;
+                Ok(())
+            })
+            .await
+    }
+
+    /// Queued entries of `collection` whose retry time has come, oldest first.
+    pub async fn due(&self, collection: &str, now_ms: i64) -> Result<Vec<OutboxEntry>> {
+        let collection = collection.to_string();
+        self.storage
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT seq, collection, record_id, op, attempts, last_error FROM sync_outbox
+                     WHERE collection = ?1 AND (retry_at IS NULL OR retry_at <= ?2)
+                     ORDER BY seq",
+                )?;
+                let entries = stmt
+                    .query_map(params![collection, now_ms], OutboxEntry::from_row)?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(entries)
+            })
+            .await
+    }
+
+    /// Number of queued entries, in one collection or in all.
+    pub async fn pending(&self, collection: Option<&str>) -> Result<usize> {
+        let collection = collection.map(str::to_string);
+        self.storage
+            .call(move |conn| {
+                let count: i64 = conn.query_row(
+                    "SELECT COUNT(*) FROM sync_outbox WHERE ?1 IS NULL OR collection = ?1",
+                    [collection],
+                    |row| row.get(0),
+                )?;
+                Ok(count as usize)
+            })
+            .await
+    }
+
+    /// The entry was pushed.
+    pub async fn complete(&self, seq: i64) -> Result<()> {
+        self.storage
+            .call(move |conn| {
+                conn.execute("DELETE FROM sync_outbox WHERE seq = ?1", [seq])?;
+                Ok(())
+            })
+            .await
+    }
+
+    /// The push failed; try again after a growing delay.
+    pub async fn fail(&self, seq: i64, error: &str, now_ms: i64) -> Result<()> {
+        let error = error.to_string();
+        self.storage
+            .call(move |conn| {
+                let attempts: u32 = conn.query_row("SELECT attempts FROM sync_outbox WHERE seq = ?1", [seq], |row| {
+                    row.get(0)
+                })?;
+                let attempts = attempts + 1;
+                conn.execute(
+                    "UPDATE sync_outbox SET attempts = ?1, last_error = ?2, retry_at = ?3 WHERE seq = ?4",
+                    params![attempts, error, now_ms + retry_delay_ms(attempts), seq],
+                )?;
+                Ok(())
+            })
+            .await
+    }
+}
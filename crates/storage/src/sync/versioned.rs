@@ -0,0 +1,237 @@
+//! Records with the metadata needed to merge copies edited on different
+//! devices: a stamp per field, a version vector and an optional tombstone.
+//!
+//! Merging keeps, for every field, the value with the latest stamp, so two
+//! devices that changed different fields of a project both keep their
+//! change, and the later edit wins when they changed the same one. The merge
+//! is commutative, associative and idempotent: every device that has seen
+//! the same edits ends up with the same record, whatever the order.
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// A record's synced fields, as serialized by serde.
+pub type Fields = Map<String, Value>;
+
+/// When and where a value was written. Later stamps win; the device id
+/// breaks ties so every device picks the same winner.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Stamp {
+    /// Unix milliseconds
+    pub time: i64,
+    pub device: String,
+}
+
+/// Number of edits each device has made to a record.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct VersionVector(pub BTreeMap<String, u64>);
+
+impl VersionVector {
+    pub fn get(&self, device: &str) -> u64 {
+        self.0.get(device).copied().unwrap_or(0)
+    }
+
+    pub fn bump(&mut self, device: &str) {
+        *self.0.entry(device.to_string()).or_default() += 1;
+    }
+
+    pub fn merge(&mut self, other: &VersionVector) {
+        for (device, &count) in &other.0 {
+            let entry = self.0.entry(device.clone()).or_default();
+            *entry = (*entry).max(count);
+        }
+    }
+
+    /// `Less` when `other` has seen every edit this has and more, `None` when
+    /// both saw edits the other didn't (a concurrent change).
+    pub fn compare(&self, other: &VersionVector) -> Option<Ordering> {
+        let devices: BTreeSet<&String> = self.0.keys().chain(other.0.keys()).collect();
+        let mut order = Ordering::Equal;
+        for device in devices {
+            match self.get(device).cmp(&other.get(device)) {
+                Ordering::Equal => {}
+                step if order == Ordering::Equal => order = step,
+                step if step != order => return None,
+                _ => {}
+            }
+        }
+        Some(order)
+    }
+}
+
+/// A synced record and its merge metadata.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Versioned {
+    pub id: String,
+    pub fields: Fields,
+    /// Last write to each field
+    pub stamps: BTreeMap<String, Stamp>,
+    pub version: VersionVector,
+    /// Set when the record was deleted. Fields written after it bring the
+    /// record back.
+    #[serde(default)]
+    pub deleted: Option<Stamp>,
+}
+
+impl Versioned {
+    /// A record written before sync metadata existed. Its fields carry the
+    /// oldest possible stamp, so any tracked edit wins over them.
+    pub fn untracked(id: &str, fields: Fields) -> Self {
+        let stamp = Stamp {
+            time: 0,
+            device: String::new(),
+        };
+        Self {
+            id: id.to_string(),
+            stamps: fields.keys().map(|k| (k.clone(), stamp.clone())).collect(),
+            fields,
+            version: VersionVector::default(),
+            deleted: None,
+        }
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        match &self.deleted {
+            Some(deleted) => self.stamps.values().all(|stamp| stamp < deleted),
+            None => false,
+        }
+    }
+
+    fn latest(&self) -> Option<&Stamp> {
+        self.stamps.values().chain(self.deleted.as_ref()).max()
+    }
+
+    /// A stamp for a new write, never before anything already on the record,
+    /// so a device whose clock runs behind still orders its edits after the
+    /// ones it has seen.
+    fn next_stamp(prev: Option<&Versioned>, time: i64, device: &str) -> Stamp {
+        let floor = prev.and_then(Versioned::latest).map_or(i64::MIN, |s| s.time + 1);
+        Stamp {
+            time: time.max(floor),
+            device: device.to_string(),
+        }
+    }
+
+    /// Record that `device` wrote `fields` at `time`. `None` when nothing
+    /// changed since `prev`.
+    pub fn edit(prev: Option<&Versioned>, id: &str, fields: Fields, time: i64, device: &str) -> Option<Versioned> {
+        // Re-creating a deleted record writes every field again
+        let revived = prev.is_some_and(Versioned::is_deleted);
+        let changes: Vec<(String, Value)> = fields
+            .into_iter()
+            .filter(|(key, value)| revived || prev.and_then(|p| p.fields.get(key)) != Some(value))
+            .collect();
+        if changes.is_empty() {
+            return None;
+        }
+
+        let stamp = Self::next_stamp(prev, time, device);
+        let mut next = prev.cloned().unwrap_or_else(|| Versioned {
+            id: id.to_string(),
+            fields: Fields::new(),
+            stamps: BTreeMap::new(),
+            version: VersionVector::default(),
+            deleted: None,
+        });
+        for (key, value) in changes {
+            next.stamps.insert(key.clone(), stamp.clone());
+            next.fields.insert(key, value);
+        }
+        next.version.bump(device);
+        Some(next)
+    }
+
+    /// Record that `device` deleted the record at `time`. `None` if it is
+    /// already deleted.
+    pub fn delete(&self, time: i64, device: &str) -> Option<Versioned> {
+        if self.is_deleted() {
+            return None;
+        }
+        let mut next = self.clone();
+        next.deleted = Some(Self::next_stamp(Some(self), time, device));
+        next.version.bump(device);
+        Some(next)
+    }
+
+    /// Combine two copies of the same record: per field the later stamp
+    /// wins, and the later tombstone is kept.
+    pub fn merge(&self, other: &Versioned) -> Versioned {
+        let mut merged = self.clone();
+        for (key, theirs) in &other.stamps {
+            let their_value = other.fields.get(key).cloned().unwrap_or(Value::Null);
+            let take = match merged.stamps.get(key) {
+                None => true,
+                Some(ours) => match theirs.cmp(ours) {
+                    Ordering::Greater => true,
+                    Ordering::Less => false,
+                    // Only untracked records share stamps; pick a value both sides agree on
+                    Ordering::Equal => {
+                        let rank = |value: Option<&Value>| value.map(Value::to_string).unwrap_or_default();
+                        rank(other.fields.get(key)) > rank(merged.fields.get(key))
+                    }
+                },
+            };
+            if take {
+                merged.stamps.insert(key.clone(), theirs.clone());
+                merged.fields.insert(key.clone(), their_value);
+            }
+        }
+        merged.deleted = merged.deleted.clone().max(other.deleted.clone());
+        merged.version.merge(&other.version);
+        merged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn fields(value: Value) -> Fields {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn test_merge_is_order_independent() {
+        let base = Versioned::edit(None, "p1", fields(json!({"name": "Kael", "status": "want"})), 10, "a").unwrap();
+        let a = Versioned::edit(Some(&base), "p1", fields(json!({"name": "Kael-OS", "status": "want"})), 20, "a")
+            .unwrap();
+        let b = Versioned::edit(Some(&base), "p1", fields(json!({"name": "Kael", "status": "making"})), 15, "b")
+            .unwrap();
+        let c = b.delete(12, "b").unwrap();
+
+        assert_eq!(a.version.compare(&b.version), None);
+        assert_eq!(base.version.compare(&a.version), Some(Ordering::Less));
+
+        let ab = a.merge(&b);
+        assert_eq!(ab, b.merge(&a));
+        assert_eq!(ab.merge(&ab), ab);
+        assert_eq!(ab.fields, fields(json!({"name": "Kael-OS", "status": "making"})));
+        assert_eq!(a.merge(&b).merge(&c), a.merge(&b.merge(&c)));
+
+        // The delete was stamped after every edit b had seen, but a's rename is later
+        assert_eq!(c.deleted.as_ref().unwrap().time, 16);
+        assert!(c.is_deleted());
+        assert!(!a.merge(&c).is_deleted());
+        assert!(b.merge(&c).is_deleted());
+    }
+
+    #[test]
+    fn test_edit_stamps_changed_fields_only() {
+        let base = Versioned::edit(None, "s1", fields(json!({"name": "x", "content": "ls"})), 10, "a").unwrap();
+        assert!(Versioned::edit(Some(&base), "s1", base.fields.clone(), 50, "a").is_none());
+
+        let next = Versioned::edit(Some(&base), "s1", fields(json!({"name": "x", "content": "ls -la"})), 5, "b")
+            .unwrap();
+        assert_eq!(next.stamps["name"].time, 10);
+        // A clock behind the last write still orders after it
+        assert_eq!(next.stamps["content"], Stamp { time: 11, device: "b".into() });
+
+        let untracked = Versioned::untracked("s1", fields(json!({"name": "y", "content": "pwd"})));
+        assert_eq!(next.merge(&untracked).fields, next.fields);
+    }
+}
@@ -0,0 +1,398 @@
+//! Splits integrated shell output into per-command blocks.
+//!
+//! `CommandTracker::feed` scans raw PTY output for the OSC 133 and OSC 7
+//! markers emitted by the shell integration. Input typed between `B` and `C`
+//! becomes the block's command, output between `C` and `D` its output.
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::Screen;
+
+/// Finished blocks kept by a tracker.
+pub const DEFAULT_BLOCK_HISTORY: usize = 200;
+
+/// Raw output kept per block; older bytes are dropped from the front.
+const MAX_BLOCK_OUTPUT: usize = 256 * 1024;
+
+/// Longest OSC payload we buffer before giving up on the sequence.
+const MAX_OSC_LEN: usize = 4096;
+
+/// One command run at an integrated prompt.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandBlock {
+    pub id: u64,
+    /// Command line as the user saw it, escape sequences rendered away.
+    pub command: String,
+    /// Output with escape sequences rendered away.
+    pub output: String,
+    /// `None` while running, or when the shell did not report a status.
+    pub exit_code: Option<i32>,
+    /// Working directory the command was started in.
+    pub cwd: Option<PathBuf>,
+    pub started_at: SystemTime,
+    /// `None` while running.
+    pub duration: Option<Duration>,
+}
+
+impl CommandBlock {
+    pub fn is_running(&self) -> bool {
+        self.duration.is_none()
+    }
+
+    pub fn succeeded(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+
+    pub fn failed(&self) -> bool {
+        self.exit_code.is_some_and(|code| code != 0)
+    }
+}
+
+/// What a chunk of output changed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlockEvent {
+    /// The shell printed a prompt.
+    Prompt { cwd: Option<PathBuf> },
+    /// A command started running; `output` is still empty.
+    Started(CommandBlock),
+    Finished(CommandBlock),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    /// Before the first marker, or after a command ended.
+    Idle,
+    Prompt,
+    Input,
+    Output,
+}
+
+#[derive(Debug)]
+enum Scan {
+    Ground,
+    Esc,
+    Osc(Vec<u8>),
+    OscEsc(Vec<u8>),
+}
+
+struct Running {
+    block: CommandBlock,
+    started: Instant,
+}
+
+/// Turns one session's output into command blocks.
+pub struct CommandTracker {
+    scan: Scan,
+    phase: Phase,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    running: Option<Running>,
+    cwd: Option<PathBuf>,
+    next_id: u64,
+    history: VecDeque<CommandBlock>,
+    history_limit: usize,
+}
+
+impl Default for CommandTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CommandTracker {
+    pub fn new() -> Self {
+        Self::with_history(DEFAULT_BLOCK_HISTORY)
+    }
+
+    pub fn with_history(history_limit: usize) -> Self {
+        Self {
+            scan: Scan::Ground,
+            phase: Phase::Idle,
+            input: Vec::new(),
+            output: Vec::new(),
+            running: None,
+            cwd: None,
+            next_id: 1,
+            history: VecDeque::new(),
+            history_limit,
+        }
+    }
+
+    /// Feed raw PTY output; returns the block events it completed.
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<BlockEvent> {
+        let mut events = Vec::new();
+        for &b in bytes {
+            self.scan_byte(b, &mut events);
+        }
+        events
+    }
+
+    /// Last reported working directory.
+    pub fn cwd(&self) -> Option<&PathBuf> {
+        self.cwd.as_ref()
+    }
+
+    /// The command currently running, with its output so far.
+    pub fn current(&self) -> Option<CommandBlock> {
+        self.running.as_ref().map(|r| CommandBlock {
+            output: render_text(&self.output),
+            ..r.block.clone()
+        })
+    }
+
+    /// Finished blocks, oldest first.
+    pub fn history(&self) -> &VecDeque<CommandBlock> {
+        &self.history
+    }
+
+    /// The most recent block that exited non-zero.
+    pub fn last_failed(&self) -> Option<&CommandBlock> {
+        self.history.iter().rev().find(|b| b.failed())
+    }
+
+    pub fn get(&self, id: u64) -> Option<&CommandBlock> {
+        self.history.iter().find(|b| b.id == id)
+    }
+
+    fn scan_byte(&mut self, b: u8, events: &mut Vec<BlockEvent>) {
+        const ESC: u8 = 0x1b;
+        const BEL: u8 = 0x07;
+        self.scan = match std::mem::replace(&mut self.scan, Scan::Ground) {
+            Scan::Ground if b == ESC => Scan::Esc,
+            Scan::Ground => {
+                self.text(&[b]);
+                Scan::Ground
+            }
+            Scan::Esc if b == b']' => Scan::Osc(Vec::new()),
+            Scan::Esc if b == ESC => {
+                self.text(&[ESC]);
+                Scan::Esc
+            }
+            Scan::Esc => {
+                self.text(&[ESC, b]);
+                Scan::Ground
+            }
+            Scan::Osc(payload) if b == BEL => {
+                self.osc(payload, events);
+                Scan::Ground
+            }
+            Scan::Osc(payload) if b == ESC => Scan::OscEsc(payload),
+            Scan::Osc(mut payload) => {
+                payload.push(b);
+                if payload.len() > MAX_OSC_LEN {
+                    self.text(b"\x1b]");
+                    self.text(&payload);
+                    Scan::Ground
+                } else {
+                    Scan::Osc(payload)
+                }
+            }
+            Scan::OscEsc(payload) => {
+                // ST is `ESC \`; anything else aborts the OSC
+                self.osc(payload, events);
+                if b == b'\\' {
+                    Scan::Ground
+                } else {
+                    self.scan_byte(ESC, events);
+                    self.scan_byte(b, events);
+                    return;
+                }
+            }
+        };
+    }
+
+    fn text(&mut self, bytes: &[u8]) {
+        match self.phase {
+            Phase::Input => self.input.extend_from_slice(bytes),
+            Phase::Output => {
+                self.output.extend_from_slice(bytes);
+                if self.output.len() > MAX_BLOCK_OUTPUT {
+                    let excess = self.output.len() - MAX_BLOCK_OUTPUT;
+                    self.output.drain(..excess);
+                }
+            }
+            Phase::Idle | Phase::Prompt => {}
+        }
+    }
+
+    fn osc(&mut self, payload: Vec<u8>, events: &mut Vec<BlockEvent>) {
+        let text = String::from_utf8_lossy(&payload);
+        let mut parts = text.splitn(3, ';');
+        match (parts.next(), parts.next()) {
+            (Some("133"), Some("A")) => {
+                // A prompt without `D` means the status was lost; close the block anyway
+                self.finish(None, events);
+                self.phase = Phase::Prompt;
+                events.push(BlockEvent::Prompt { cwd: self.cwd.clone() });
+            }
+            (Some("133"), Some("B")) => {
+                self.input.clear();
+                self.phase = Phase::Input;
+            }
+            (Some("133"), Some(c)) if c.starts_with('C') => self.start(events),
+            (Some("133"), Some(d)) if d.starts_with('D') => {
+                let code = parts.next().and_then(|c| c.trim().parse().ok());
+                self.finish(code, events);
+                self.phase = Phase::Idle;
+            }
+            (Some("7"), Some(_)) => {
+                self.cwd = parse_file_url(&text[2..]);
+            }
+            _ => {
+                // Not ours (e.g. window title); keep it in the stream
+                self.text(b"\x1b]");
+                self.text(&payload);
+                self.text(b"\x07");
+            }
+        }
+    }
+
+    fn start(&mut self, events: &mut Vec<BlockEvent>) {
+        self.finish(None, events);
+        let command = render_text(&self.input).trim().to_string();
+        self.input.clear();
+        self.output.clear();
+        let block = CommandBlock {
+            id: self.next_id,
+            command,
+            output: String::new(),
+            exit_code: None,
+            cwd: self.cwd.clone(),
+            started_at: SystemTime::now(),
+            duration: None,
+        };
+        self.next_id += 1;
+        events.push(BlockEvent::Started(block.clone()));
+        self.running = Some(Running {
+            block,
+            started: Instant::now(),
+        });
+        self.phase = Phase::Output;
+    }
+
+    fn finish(&mut self, exit_code: Option<i32>, events: &mut Vec<BlockEvent>) {
+        let Some(running) = self.running.take() else {
+            return;
+        };
+        let block = CommandBlock {
+            output: render_text(&self.output),
+            exit_code,
+            duration: Some(running.started.elapsed()),
+            ..running.block
+        };
+        self.output.clear();
+        self.history.push_back(block.clone());
+        while self.history.len() > self.history_limit {
+            self.history.pop_front();
+        }
+        events.push(BlockEvent::Finished(block));
+    }
+}
+
+/// Path from an OSC 7 `file://host/path` URL, with `%XX` escapes decoded.
+fn parse_file_url(url: &str) -> Option<PathBuf> {
+    let rest = url.strip_prefix("file://")?;
+    let path = &rest[rest.find('/')?..];
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    Some(PathBuf::from(String::from_utf8_lossy(&decoded).into_owned()))
+}
+
+/// Run bytes through a wide screen so carriage returns, backspaces and
+/// colours resolve to the text a user would have seen.
+fn render_text(bytes: &[u8]) -> String {
+    if bytes.is_empty() {
+        return String::new();
+    }
+    let mut screen = Screen::new(1, 500);
+    let mut lines = Vec::new();
+    // Drain the scrollback after every line so only one row is kept as cells
+    for piece in bytes.split_inclusive(|&b| b == b'\n') {
+        screen.process(piece);
+        lines.extend(screen.scrollback().iter().map(|row| crate::screen::row_to_string(row)));
+        screen.clear_scrollback();
+    }
+    lines.push(screen.contents());
+    while lines.last().is_some_and(|l| l.is_empty()) {
+        lines.pop();
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn finished(events: &[BlockEvent]) -> Vec<CommandBlock> {
+        events
+            .iter()
+            .filter_map(|e| match e {
+                BlockEvent::Finished(block) => Some(block.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_segments_commands() {
+        let mut tracker = CommandTracker::new();
+        let mut events = tracker.feed(b"\x1b]133;D;0\x07\x1b]7;file://box/home/me\x07\x1b]133;A\x07$ \x1b]133;B\x07");
+        assert!(finished(&events).is_empty());
+        events.extend(tracker.feed(b"ls mi\x08\x08\x1b[Kmissing\r\n\x1b]133;C\x07"));
+        assert!(matches!(events.last(), Some(BlockEvent::Started(b)) if b.command == "ls missing"));
+        assert!(tracker.current().is_some());
+
+        // Output and the end marker may arrive split across chunks
+        let mut events = tracker.feed(b"ls: cannot access 'missing'\r\n\x1b]13");
+        events.extend(tracker.feed(b"3;D;2\x1b\\\x1b]133;A\x07$ "));
+        let blocks = finished(&events);
+        assert_eq!(blocks.len(), 1);
+        let block = &blocks[0];
+        assert_eq!(block.command, "ls missing");
+        assert_eq!(block.output, "ls: cannot access 'missing'");
+        assert_eq!(block.exit_code, Some(2));
+        assert_eq!(block.cwd, Some(PathBuf::from("/home/me")));
+        assert!(block.failed() && !block.is_running());
+        assert_eq!(tracker.last_failed().map(|b| b.id), Some(block.id));
+        assert!(tracker.current().is_none());
+    }
+
+    #[test]
+    fn test_foreign_osc_and_missing_status() {
+        let mut tracker = CommandTracker::with_history(1);
+        tracker.feed(b"\x1b]133;B\x07true\r\n\x1b]133;C\x07\x1b]0;title\x07\x1b[32mok\x1b[0m\n");
+        // Prompt without a `D`: block closes with an unknown status
+        let blocks = finished(&tracker.feed(b"\x1b]133;A\x07"));
+        assert_eq!(blocks[0].exit_code, None);
+        assert_eq!(blocks[0].output, "ok");
+
+        tracker.feed(b"\x1b]133;B\x07false\n\x1b]133;C\x07\x1b]133;D;1\x07");
+        assert_eq!(tracker.history().len(), 1);
+        assert_eq!(tracker.history()[0].command, "false");
+    }
+
+    #[test]
+    fn test_parse_file_url() {
+        assert_eq!(parse_file_url("file://host/tmp/a%20b"), Some(PathBuf::from("/tmp/a b")));
+        assert_eq!(parse_file_url("file:///srv"), Some(PathBuf::from("/srv")));
+        assert_eq!(parse_file_url("http://x/y"), None);
+    }
+}
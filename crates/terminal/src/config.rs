@@ -0,0 +1,137 @@
+use std::io;
+use std::path::PathBuf;
+
+use portable_pty::{CommandBuilder, PtySize};
+
+use crate::elevate::SUDO_PROMPT;
+use crate::integration::{self, ShellKind};
+
+/// How to start a shell session: program, arguments, working directory,
+/// extra environment and initial window size.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShellConfig {
+    /// Display name for tabs; defaults to the program's file name.
+    pub name: Option<String>,
+    pub program: String,
+    pub args: Vec<String>,
+    /// Starting directory; inherits the app's working directory when unset.
+    pub cwd: Option<PathBuf>,
+    pub env: Vec<(String, String)>,
+    pub size: PtySize,
+    /// Inject the OSC 133 shell integration (bash, zsh and fish only).
+    pub integration: bool,
+}
+
+impl Default for ShellConfig {
+    /// The user's login shell from `$SHELL`, falling back to `/bin/sh`.
+    fn default() -> Self {
+        let program = std::env::var("SHELL")
+            .ok()
+            .filter(|s| !s.trim().is_empty())
+            .unwrap_or_else(|| "/bin/sh".to_string());
+        Self::new(program)
+    }
+}
+
+impl ShellConfig {
+    pub fn new(program: impl Into<String>) -> Self {
+        Self {
+            name: None,
+            program: program.into(),
+            args: Vec::new(),
+            cwd: None,
+            env: Vec::new(),
+            size: PtySize {
+                rows: 24,
+                cols: 120,
+                pixel_width: 0,
+                pixel_height: 0,
+            },
+            integration: false,
+        }
+    }
+
+    /// POSIX `/bin/sh`, for sessions that drive the shell programmatically.
+    pub fn sh() -> Self {
+        Self::new("/bin/sh")
+    }
+
+    /// fish, found on `PATH`.
+    pub fn fish() -> Self {
+        Self::new("fish")
+    }
+
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn with_args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args = args.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn with_cwd(mut self, cwd: impl Into<PathBuf>) -> Self {
+        self.cwd = Some(cwd.into());
+        self
+    }
+
+    pub fn with_env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn with_size(mut self, rows: u16, cols: u16) -> Self {
+        self.size.rows = rows;
+        self.size.cols = cols;
+        self
+    }
+
+    /// Report prompts, command boundaries and exit codes; see `CommandTracker`.
+    pub fn with_integration(mut self) -> Self {
+        self.integration = true;
+        self
+    }
+
+    /// Make sudo print `SUDO_PROMPT`, so `PasswordPrompt` can tell its
+    /// prompt apart from program output.
+    pub fn with_sudo_prompt(self) -> Self {
+        self.with_env("SUDO_PROMPT", SUDO_PROMPT)
+    }
+
+    pub fn shell_kind(&self) -> ShellKind {
+        ShellKind::detect(&self.program)
+    }
+
+    /// Tab label: the explicit name, or the program's file name.
+    pub fn display_name(&self) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+        self.program
+            .rsplit('/')
+            .next()
+            .filter(|s| !s.is_empty())
+            .unwrap_or(&self.program)
+            .to_string()
+    }
+
+    pub(crate) fn command(&self) -> io::Result<CommandBuilder> {
+        let mut cmd = CommandBuilder::new(&self.program);
+        cmd.args(&self.args);
+        if self.integration {
+            integration::apply(self.shell_kind(), &self.args, &mut cmd)?;
+        }
+        if let Some(cwd) = &self.cwd {
+            cmd.cwd(cwd);
+        }
+        for (key, value) in &self.env {
+            cmd.env(key, value);
+        }
+        Ok(cmd)
+    }
+}
@@ -0,0 +1,669 @@
+//! Detached session daemon.
+//!
+//! The `kael-terminal` binary runs a `SessionManager` behind a Unix socket,
+//! so shells keep running when the desktop app exits or crashes and can be
+//! reattached later, like tmux or dtach.
+//!
+//! Every connection starts with one JSON request line and gets one JSON
+//! response line. Control requests end there. `Attach` turns the
+//! connection into a raw byte stream: first the session's recent output, so
+//! the client can rebuild its screen, then live output. Bytes the client
+//! writes go to the shell.
+use std::collections::{BTreeMap, VecDeque};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use async_channel::Receiver;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::unix::OwnedWriteHalf;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{broadcast, Notify};
+use tracing::{info, warn};
+
+use crate::{ExitInfo, SessionId, SessionInfo, SessionManager, ShellConfig, TerminalError};
+
+/// Overrides the default socket path.
+pub const SOCKET_ENV: &str = "KAEL_TERMINAL_SOCKET";
+
+/// Output kept per session for clients that attach later.
+const BACKLOG_BYTES: usize = 512 * 1024;
+
+/// Live output chunks buffered per attached client before it is dropped.
+/// A dropped client reattaches and gets the backlog instead.
+const LIVE_CHUNKS: usize = 256;
+
+/// How long `connect_or_start` waits for a freshly started daemon.
+const START_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `$KAEL_TERMINAL_SOCKET`, or `daemon.sock` in the per-user runtime dir.
+pub fn default_socket_path() -> PathBuf {
+    match std::env::var_os(SOCKET_ENV).filter(|s| !s.is_empty()) {
+        Some(path) => PathBuf::from(path),
+        None => crate::integration::runtime_dir().join("daemon.sock"),
+    }
+}
+
+/// A `ShellConfig` on the wire.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpawnSpec {
+    pub name: Option<String>,
+    pub program: String,
+    pub args: Vec<String>,
+    pub cwd: Option<PathBuf>,
+    pub env: Vec<(String, String)>,
+    pub rows: u16,
+    pub cols: u16,
+    pub integration: bool,
+}
+
+impl From<&ShellConfig> for SpawnSpec {
+    fn from(config: &ShellConfig) -> Self {
+        Self {
+            name: config.name.clone(),
+            program: config.program.clone(),
+            args: config.args.clone(),
+            cwd: config.cwd.clone(),
+            env: config.env.clone(),
+            rows: config.size.rows,
+            cols: config.size.cols,
+            integration: config.integration,
+        }
+    }
+}
+
+impl From<SpawnSpec> for ShellConfig {
+    fn from(spec: SpawnSpec) -> Self {
+        let mut config = ShellConfig::new(spec.program)
+            .with_args(spec.args)
+            .with_size(spec.rows, spec.cols);
+        config.name = spec.name;
+        config.cwd = spec.cwd;
+        config.env = spec.env;
+        config.integration = spec.integration;
+        config
+    }
+}
+
+/// A session owned by the daemon.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RemoteSession {
+    pub id: SessionId,
+    pub name: String,
+    pub program: String,
+    pub cwd: Option<PathBuf>,
+    pub rows: u16,
+    pub cols: u16,
+    pub pid: Option<u32>,
+    /// `None` while the shell is still running.
+    pub exit: Option<ExitInfo>,
+}
+
+impl From<SessionInfo> for RemoteSession {
+    fn from(info: SessionInfo) -> Self {
+        Self {
+            id: info.id,
+            name: info.name,
+            program: info.program,
+            cwd: info.cwd,
+            rows: info.size.rows,
+            cols: info.size.cols,
+            pid: info.pid,
+            exit: info.exit,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Request {
+    Ping,
+    Spawn { spec: SpawnSpec },
+    List,
+    Attach { id: SessionId },
+    Resize { id: SessionId, rows: u16, cols: u16 },
+    WriteSecret { id: SessionId, data: Vec<u8> },
+    Close { id: SessionId },
+    StartRecording { id: SessionId, path: PathBuf },
+    StopRecording { id: SessionId },
+    Shutdown,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct Response {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<SessionId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sessions: Option<Vec<RemoteSession>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    path: Option<PathBuf>,
+}
+
+impl Response {
+    fn error(e: impl std::fmt::Display) -> Self {
+        Self {
+            error: Some(e.to_string()),
+            ..Self::default()
+        }
+    }
+}
+
+async fn write_json<T: Serialize>(writer: &mut (impl AsyncWriteExt + Unpin), value: &T) -> io::Result<()> {
+    let mut line = serde_json::to_vec(value)?;
+    line.push(b'\n');
+    writer.write_all(&line).await
+}
+
+async fn read_json<T: for<'de> Deserialize<'de>>(reader: &mut (impl AsyncBufReadExt + Unpin)) -> Result<T> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).await? == 0 {
+        bail!(TerminalError::Daemon("connection closed".to_string()));
+    }
+    Ok(serde_json::from_str(&line)?)
+}
+
+/// Recent output of one session plus the feed for attached clients.
+struct Backlog {
+    bytes: VecDeque<u8>,
+    /// `None` once the shell's output has ended.
+    live: Option<broadcast::Sender<Arc<[u8]>>>,
+}
+
+/// Make `dir` ready to hold the daemon socket, which accepts input for
+/// every session. A directory the daemon creates is made owner-only; one
+/// that already exists is left alone, but must belong to this user and not
+/// be writable by anyone else.
+fn prepare_socket_dir(dir: &Path) -> Result<()> {
+    if let Some(parent) = dir.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    match std::fs::create_dir(dir) {
+        Ok(()) => {
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))?;
+            }
+            Ok(())
+        }
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::MetadataExt;
+                let meta = std::fs::metadata(dir)?;
+                // SAFETY: geteuid has no preconditions and cannot fail
+                let uid = unsafe { libc::geteuid() };
+                if meta.uid() != uid || meta.mode() & 0o022 != 0 {
+                    bail!(TerminalError::Daemon(format!(
+                        "{} must be owned by you and not writable by others",
+                        dir.display()
+                    )));
+                }
+            }
+            Ok(())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The server side: sessions plus their output backlogs.
+pub struct Daemon {
+    manager: SessionManager,
+    backlogs: Arc<Mutex<BTreeMap<SessionId, Backlog>>>,
+    shutdown: Notify,
+}
+
+impl Default for Daemon {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Daemon {
+    pub fn new() -> Self {
+        Self {
+            manager: SessionManager::new(),
+            backlogs: Arc::new(Mutex::new(BTreeMap::new())),
+            shutdown: Notify::new(),
+        }
+    }
+
+    /// Listen on `socket` until a `Shutdown` request, then close every
+    /// session. Fails if another daemon already answers there.
+    pub async fn run(self: Arc<Self>, socket: PathBuf) -> Result<()> {
+        let socket = socket.as_path();
+        if let Some(parent) = socket.parent() {
+            prepare_socket_dir(parent)?;
+        }
+        if socket.exists() {
+            if UnixStream::connect(socket).await.is_ok() {
+                bail!(TerminalError::Daemon(format!("already running at {}", socket.display())));
+            }
+            // Left behind by a daemon that died
+            std::fs::remove_file(socket)?;
+        }
+        let listener = UnixListener::bind(socket)?;
+        info!("terminal daemon listening on {}", socket.display());
+
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let (stream, _) = accepted?;
+                    let daemon = Arc::clone(&self);
+                    tokio::spawn(async move {
+                        if let Err(e) = daemon.handle(stream).await {
+                            warn!("terminal daemon connection: {e}");
+                        }
+                    });
+                }
+                _ = self.shutdown.notified() => break,
+            }
+        }
+
+        for session in self.manager.list().await {
+            let _ = self.manager.close(session.id).await;
+        }
+        let _ = std::fs::remove_file(socket);
+        Ok(())
+    }
+
+    async fn handle(self: Arc<Self>, stream: UnixStream) -> Result<()> {
+        let (read, mut write) = stream.into_split();
+        let mut reader = BufReader::new(read);
+        let request = match read_json::<Request>(&mut reader).await {
+            Ok(request) => request,
+            Err(e) => {
+                write_json(&mut write, &Response::error(format!("bad request: {e}"))).await?;
+                return Ok(());
+            }
+        };
+        match request {
+            Request::Attach { id } => self.attach(id, reader, write).await,
+            request => {
+                let response = self.execute(request).await.unwrap_or_else(Response::error);
+                write_json(&mut write, &response).await?;
+                Ok(())
+            }
+        }
+    }
+
+    async fn execute(&self, request: Request) -> Result<Response> {
+        let mut response = Response::default();
+        match request {
+            Request::Ping => {}
+            Request::Spawn { spec } => {
+                let id = self.manager.spawn(spec.into()).await?;
+                self.pump(id).await?;
+                response.id = Some(id);
+            }
+            Request::List => {
+                let sessions = self.manager.list().await;
+                response.sessions = Some(sessions.into_iter().map(RemoteSession::from).collect());
+            }
+            Request::Resize { id, rows, cols } => self.manager.resize(id, rows, cols).await?,
+            Request::WriteSecret { id, data } => self.manager.write_secret(id, &data).await?,
+            Request::Close { id } => {
+                self.manager.close(id).await?;
+                self.backlogs.lock().unwrap().remove(&id);
+            }
+            Request::StartRecording { id, path } => self.manager.start_recording(id, &path).await?,
+            Request::StopRecording { id } => response.path = self.manager.stop_recording(id).await?,
+            Request::Shutdown => self.shutdown.notify_one(),
+            Request::Attach { .. } => unreachable!("attach is handled by the connection"),
+        }
+        Ok(response)
+    }
+
+    /// Drain a session's output into its backlog and attached clients, so
+    /// the shell never blocks on a full channel while nobody is attached.
+    async fn pump(&self, id: SessionId) -> Result<()> {
+        let rx = self.manager.output(id).await?;
+        let (live, _) = broadcast::channel(LIVE_CHUNKS);
+        self.backlogs.lock().unwrap().insert(
+            id,
+            Backlog {
+                bytes: VecDeque::new(),
+                live: Some(live),
+            },
+        );
+
+        let backlogs = Arc::clone(&self.backlogs);
+        tokio::spawn(async move {
+            while let Ok(chunk) = rx.recv().await {
+                let mut backlogs = backlogs.lock().unwrap();
+                let Some(backlog) = backlogs.get_mut(&id) else { return }; // closed
+                backlog.bytes.extend(&chunk);
+                let excess = backlog.bytes.len().saturating_sub(BACKLOG_BYTES);
+                backlog.bytes.drain(..excess);
+                if let Some(live) = &backlog.live {
+                    let _ = live.send(chunk.into());
+                }
+            }
+            if let Some(backlog) = backlogs.lock().unwrap().get_mut(&id) {
+                backlog.live = None;
+            }
+        });
+        Ok(())
+    }
+
+    async fn attach(
+        &self,
+        id: SessionId,
+        mut reader: impl AsyncRead + Unpin + Send + 'static,
+        mut write: OwnedWriteHalf,
+    ) -> Result<()> {
+        // Snapshot and subscribe under one lock so no chunk is lost or repeated
+        let attached = self.backlogs.lock().unwrap().get(&id).map(|backlog| {
+            let bytes: Vec<u8> = backlog.bytes.iter().copied().collect();
+            (bytes, backlog.live.as_ref().map(|live| live.subscribe()))
+        });
+        let Some((backlog, live)) = attached else {
+            write_json(&mut write, &Response::error(TerminalError::UnknownSession(id))).await?;
+            return Ok(());
+        };
+        write_json(&mut write, &Response::default()).await?;
+        write.write_all(&backlog).await?;
+
+        let Some(mut live) = live else {
+            return Ok(()); // the shell is gone; the backlog is all there is
+        };
+        let manager = self.manager.clone();
+        let mut input = tokio::spawn(async move {
+            let mut buf = [0u8; 4096];
+            loop {
+                match reader.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        if manager.write(id, &buf[..n]).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+        loop {
+            tokio::select! {
+                _ = &mut input => break, // client went away
+                chunk = live.recv() => match chunk {
+                    Ok(chunk) => {
+                        if write.write_all(&chunk).await.is_err() {
+                            break;
+                        }
+                    }
+                    // Lagged: the client is too slow; it can reattach
+                    Err(_) => break,
+                },
+            }
+        }
+        input.abort();
+        Ok(())
+    }
+}
+
+/// Start the daemon binary in its own process group, detached from the
+/// caller's stdio, so it outlives the caller.
+pub fn start_detached(binary: &Path, socket: &Path) -> io::Result<()> {
+    let mut cmd = std::process::Command::new(binary);
+    cmd.arg("--socket")
+        .arg(socket)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null());
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+    }
+    cmd.spawn().map(drop)
+}
+
+/// Client for a running daemon. Each call opens its own connection.
+#[derive(Debug, Clone)]
+pub struct DaemonClient {
+    socket: PathBuf,
+}
+
+impl DaemonClient {
+    pub fn new(socket: impl Into<PathBuf>) -> Self {
+        Self { socket: socket.into() }
+    }
+
+    /// Connect to the daemon at `socket`, starting `binary` if nobody
+    /// answers there yet.
+    pub async fn connect_or_start(socket: impl Into<PathBuf>, binary: &Path) -> Result<Self> {
+        let client = Self::new(socket);
+        if client.ping().await.is_ok() {
+            return Ok(client);
+        }
+        start_detached(binary, &client.socket)
+            .map_err(|e| TerminalError::Daemon(format!("failed to start {}: {e}", binary.display())))?;
+        let started = tokio::time::timeout(START_TIMEOUT, async {
+            while client.ping().await.is_err() {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await;
+        if started.is_err() {
+            bail!(TerminalError::Daemon(format!(
+                "{} did not come up at {}",
+                binary.display(),
+                client.socket.display()
+            )));
+        }
+        Ok(client)
+    }
+
+    pub fn socket(&self) -> &Path {
+        &self.socket
+    }
+
+    async fn request(&self, request: &Request) -> Result<(Response, BufReader<tokio::net::unix::OwnedReadHalf>, OwnedWriteHalf)> {
+        let stream = UnixStream::connect(&self.socket)
+            .await
+            .map_err(|e| TerminalError::Daemon(format!("{}: {e}", self.socket.display())))?;
+        let (read, mut write) = stream.into_split();
+        write_json(&mut write, request).await?;
+        let mut reader = BufReader::new(read);
+        let response: Response = read_json(&mut reader).await?;
+        if let Some(error) = response.error {
+            bail!(TerminalError::Daemon(error));
+        }
+        Ok((response, reader, write))
+    }
+
+    async fn call(&self, request: Request) -> Result<Response> {
+        self.request(&request).await.map(|(response, _, _)| response)
+    }
+
+    pub async fn ping(&self) -> Result<()> {
+        self.call(Request::Ping).await.map(drop)
+    }
+
+    pub async fn spawn(&self, config: &ShellConfig) -> Result<SessionId> {
+        let response = self.call(Request::Spawn { spec: config.into() }).await?;
+        response.id.ok_or_else(|| anyhow!(TerminalError::Daemon("spawn returned no id".to_string())))
+    }
+
+    /// All sessions in creation order, exited ones included.
+    pub async fn list(&self) -> Result<Vec<RemoteSession>> {
+        Ok(self.call(Request::List).await?.sessions.unwrap_or_default())
+    }
+
+    pub async fn resize(&self, id: SessionId, rows: u16, cols: u16) -> Result<()> {
+        self.call(Request::Resize { id, rows, cols }).await.map(drop)
+    }
+
+    /// Type a password into the session without it reaching a recording.
+    pub async fn write_secret(&self, id: SessionId, data: &[u8]) -> Result<()> {
+        let data = data.to_vec();
+        self.call(Request::WriteSecret { id, data }).await.map(drop)
+    }
+
+    /// Kill the shell and forget the session.
+    pub async fn close(&self, id: SessionId) -> Result<()> {
+        self.call(Request::Close { id }).await.map(drop)
+    }
+
+    pub async fn start_recording(&self, id: SessionId, path: &Path) -> Result<()> {
+        let path = path.to_path_buf();
+        self.call(Request::StartRecording { id, path }).await.map(drop)
+    }
+
+    pub async fn stop_recording(&self, id: SessionId) -> Result<Option<PathBuf>> {
+        Ok(self.call(Request::StopRecording { id }).await?.path)
+    }
+
+    /// Close every session and stop the daemon.
+    pub async fn shutdown(&self) -> Result<()> {
+        self.call(Request::Shutdown).await.map(drop)
+    }
+
+    /// Stream a session: recent output first, then live output.
+    pub async fn attach(&self, id: SessionId) -> Result<Attachment> {
+        let (_, mut reader, writer) = self.request(&Request::Attach { id }).await?;
+        let (tx, output) = async_channel::bounded::<Vec<u8>>(128);
+        tokio::spawn(async move {
+            let mut buf = [0u8; 4096];
+            loop {
+                match reader.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        if tx.send(buf[..n].to_vec()).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+        Ok(Attachment {
+            output,
+            writer: Arc::new(tokio::sync::Mutex::new(writer)),
+        })
+    }
+}
+
+/// A client's view of one attached session. The output channel closes when
+/// the shell exits or the daemon goes away; dropping every clone detaches
+/// without touching the shell.
+#[derive(Clone)]
+pub struct Attachment {
+    output: Receiver<Vec<u8>>,
+    writer: Arc<tokio::sync::Mutex<OwnedWriteHalf>>,
+}
+
+impl Attachment {
+    pub fn output(&self) -> Receiver<Vec<u8>> {
+        self.output.clone()
+    }
+
+    pub async fn write(&self, data: &[u8]) -> Result<()> {
+        self.writer.lock().await.write_all(data).await?;
+        Ok(())
+    }
+
+    /// The stream has ended (shell exited or daemon gone).
+    pub fn is_closed(&self) -> bool {
+        self.output.is_closed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn read_until(rx: &Receiver<Vec<u8>>, needle: &str) -> String {
+        let mut out = String::new();
+        let _ = tokio::time::timeout(Duration::from_secs(5), async {
+            while let Ok(chunk) = rx.recv().await {
+                out.push_str(&String::from_utf8_lossy(&chunk));
+                if out.contains(needle) {
+                    break;
+                }
+            }
+        })
+        .await;
+        out
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_socket_dir_must_be_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let base = std::env::temp_dir().join(format!("kael-daemon-dir-{}", std::process::id()));
+        let created = base.join("sockets");
+        prepare_socket_dir(&created).unwrap();
+        assert_eq!(std::fs::metadata(&created).unwrap().permissions().mode() & 0o777, 0o700);
+
+        // A shared directory is refused, not chmodded
+        let shared = base.join("shared");
+        std::fs::create_dir(&shared).unwrap();
+        std::fs::set_permissions(&shared, std::fs::Permissions::from_mode(0o777)).unwrap();
+        let err = prepare_socket_dir(&shared).unwrap_err();
+        assert!(err.to_string().contains("not writable by others"), "{err}");
+        assert_eq!(std::fs::metadata(&shared).unwrap().permissions().mode() & 0o777, 0o777);
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_session_survives_detach_and_reattach() {
+        let socket = std::env::temp_dir().join(format!("kael-daemon-test-{}/daemon.sock", std::process::id()));
+        let server = tokio::spawn(Arc::new(Daemon::new()).run(socket.clone()));
+        let client = DaemonClient::new(&socket);
+        let _ = tokio::time::timeout(Duration::from_secs(5), async {
+            while client.ping().await.is_err() {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await;
+
+        let id = client.spawn(&ShellConfig::sh().with_name("upgrade")).await.unwrap();
+        let first = client.attach(id).await.unwrap();
+        first.write(b"echo step=$((40+2))\n").await.unwrap();
+        assert!(read_until(&first.output(), "step=42").await.contains("step=42"));
+        // The UI goes away mid-run
+        drop(first);
+
+        let sessions = client.list().await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].name, "upgrade");
+        assert_eq!(sessions[0].exit, None);
+
+        // A new client sees what happened while it was gone, then live output
+        let second = client.attach(id).await.unwrap();
+        let rx = second.output();
+        assert!(read_until(&rx, "step=42").await.contains("step=42"));
+        second.write(b"echo again\n").await.unwrap();
+        assert!(read_until(&rx, "again").await.contains("again"));
+
+        client.resize(id, 30, 100).await.unwrap();
+        assert_eq!(client.list().await.unwrap()[0].rows, 30);
+        assert!(client.attach(SessionId(99)).await.is_err());
+
+        client.close(id).await.unwrap();
+        assert!(client.list().await.unwrap().is_empty());
+        client.shutdown().await.unwrap();
+        server.await.unwrap().unwrap();
+        assert!(!socket.exists());
+    }
+
+    #[test]
+    fn test_spawn_spec_round_trip() {
+        let config = ShellConfig::new("/bin/bash")
+            .with_name("Kael")
+            .with_cwd("/tmp")
+            .with_env("A", "1")
+            .with_size(40, 90)
+            .with_integration();
+        let json = serde_json::to_string(&Request::Spawn { spec: (&config).into() }).unwrap();
+        assert!(json.starts_with(r#"{"op":"spawn""#), "{json}");
+        let Request::Spawn { spec } = serde_json::from_str(&json).unwrap() else {
+            panic!("not a spawn request")
+        };
+        assert_eq!(ShellConfig::from(spec), config);
+    }
+}
@@ -0,0 +1,399 @@
+//! Privileged commands inside a PTY.
+//!
+//! The command line is typed into the shell unchanged, so quoting and
+//! pipelines behave exactly as the user wrote them. `PasswordPrompt` watches
+//! the output for sudo's (or polkit's) password prompt, which is answered
+//! with a `Secret`: a short-lived password that is zeroized when dropped.
+use std::fmt;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use zeroize::Zeroizing;
+
+/// Prompt sudo prints in sessions configured with `ShellConfig::with_sudo_prompt`.
+/// `%p` is the user whose password is wanted.
+pub const SUDO_PROMPT: &str = "[kael-sudo] password for %p: ";
+
+const SUDO_PROMPT_MARKER: &str = "[kael-sudo] password for ";
+
+/// Longest line we keep while looking for a prompt.
+const MAX_PROMPT_LINE: usize = 512;
+
+/// How a command gets root.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Elevation {
+    /// `sudo`, prompting in the terminal.
+    Sudo,
+    /// `pkexec`; polkit asks through the desktop agent, or in the terminal
+    /// when no agent is running.
+    Pkexec,
+}
+
+impl Elevation {
+    /// sudo when installed, otherwise pkexec, otherwise `None`.
+    pub fn preferred() -> Option<Self> {
+        if on_path("sudo") {
+            Some(Elevation::Sudo)
+        } else if on_path("pkexec") {
+            Some(Elevation::Pkexec)
+        } else {
+            None
+        }
+    }
+
+    /// The method a command line asks for, if it starts with `sudo` or `pkexec`.
+    pub fn requested_by(cmdline: &str) -> Option<Self> {
+        match cmdline.split_whitespace().next() {
+            Some("sudo") => Some(Elevation::Sudo),
+            Some("pkexec") => Some(Elevation::Pkexec),
+            _ => None,
+        }
+    }
+
+    /// The line to type into the shell to run `cmdline` with this method.
+    /// A leading `sudo`/`pkexec` in `cmdline` is replaced, not doubled.
+    pub fn command_line(self, cmdline: &str) -> String {
+        let trimmed = cmdline.trim();
+        let inner = match Self::requested_by(trimmed) {
+            Some(_) => trimmed.split_once(char::is_whitespace).map_or("", |(_, rest)| rest.trim_start()),
+            None => trimmed,
+        };
+        match self {
+            // sudo's own options (-u, -E, ...) stay in place
+            Elevation::Sudo => format!("sudo {}", inner),
+            // pkexec takes a single program, so hand the whole line to a shell
+            Elevation::Pkexec => format!("pkexec /bin/sh -c {}", shell_quote(inner)),
+        }
+    }
+}
+
+fn on_path(program: &str) -> bool {
+    std::env::var_os("PATH")
+        .map(|paths| std::env::split_paths(&paths).any(|dir| is_executable(&dir.join(program))))
+        .unwrap_or(false)
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// A password held only until it is used or expires. The bytes are
+/// zeroized when the secret is dropped.
+pub struct Secret {
+    bytes: Zeroizing<Vec<u8>>,
+    expires_at: Instant,
+}
+
+impl Secret {
+    /// How long a secret stays usable by default.
+    pub const DEFAULT_TTL: Duration = Duration::from_secs(60);
+
+    pub fn new(password: String) -> Self {
+        Self::with_ttl(password, Self::DEFAULT_TTL)
+    }
+
+    pub fn with_ttl(password: String, ttl: Duration) -> Self {
+        Self {
+            bytes: Zeroizing::new(password.into_bytes()),
+            expires_at: Instant::now() + ttl,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        Instant::now() >= self.expires_at
+    }
+
+    /// Consume the secret into the bytes that answer a prompt (password and
+    /// newline), or `None` once expired. The returned buffer zeroizes itself.
+    pub fn into_answer(self) -> Option<Zeroizing<Vec<u8>>> {
+        if self.is_expired() {
+            return None;
+        }
+        let mut answer = Zeroizing::new(Vec::with_capacity(self.bytes.len() + 1));
+        answer.extend_from_slice(&self.bytes);
+        answer.push(b'\n');
+        Some(answer)
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(<redacted>)")
+    }
+}
+
+/// Which prompt `PasswordPrompt` saw.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PromptKind {
+    /// Our `SUDO_PROMPT`; carries the user whose password is wanted.
+    Sudo { user: String },
+    /// Any other line ending in "password:" (sudo's default, polkit's text agent).
+    Other(String),
+}
+
+/// Escape sequence being skipped.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    #[default]
+    None,
+    Esc,
+    Csi,
+    Osc,
+}
+
+/// Watches PTY output for password prompts. A prompt is the current,
+/// unfinished line, so it is reported when output stops right after it.
+#[derive(Debug, Default)]
+pub struct PasswordPrompt {
+    line: String,
+    escape: Escape,
+    reported: bool,
+}
+
+impl PasswordPrompt {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed output; returns the prompt the chunk ended on, once per prompt.
+    pub fn feed(&mut self, chunk: &[u8]) -> Option<PromptKind> {
+        let text = String::from_utf8_lossy(chunk);
+        for c in text.chars() {
+            self.push(c);
+        }
+        if self.reported {
+            return None;
+        }
+        let kind = self.classify()?;
+        self.reported = true;
+        Some(kind)
+    }
+
+    fn push(&mut self, c: char) {
+        match self.escape {
+            Escape::Esc => {
+                self.escape = match c {
+                    '[' => Escape::Csi,
+                    ']' => Escape::Osc,
+                    _ => Escape::None,
+                };
+                return;
+            }
+            Escape::Csi => {
+                // Parameters and intermediates until the final byte
+                if ('@'..='~').contains(&c) {
+                    self.escape = Escape::None;
+                }
+                return;
+            }
+            Escape::Osc => {
+                // Ends at BEL or ST (`ESC \`, whose ESC is swallowed here)
+                if c == '\x07' || c == '\\' {
+                    self.escape = Escape::None;
+                }
+                return;
+            }
+            Escape::None => {}
+        }
+        match c {
+            '\x1b' => self.escape = Escape::Esc,
+            '\n' | '\r' => {
+                self.line.clear();
+                self.reported = false;
+            }
+            c if c.is_control() => {}
+            c => {
+                if self.line.len() < MAX_PROMPT_LINE {
+                    self.line.push(c);
+                }
+                self.reported = false;
+            }
+        }
+    }
+
+    fn classify(&self) -> Option<PromptKind> {
+        let line = self.line.trim();
+        // Other output (e.g. the shell prompt) may precede it on the same line
+        if let Some((_, rest)) = line.split_once(SUDO_PROMPT_MARKER) {
+            let user = rest.trim_end_matches(':').trim().to_string();
+            return Some(PromptKind::Sudo { user });
+        }
+        let lower = line.to_lowercase();
+        if lower.ends_with(':') && lower.contains("password") {
+            return Some(PromptKind::Other(line.to_string()));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SessionManager, ShellConfig};
+    use async_channel::Receiver;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const PASSWORD: &str = "correct horse";
+
+    /// A directory holding fake `sudo` and `pkexec` scripts that check the
+    /// password and then run their command.
+    fn fake_bin() -> PathBuf {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let n = NEXT.fetch_add(1, Ordering::Relaxed);
+        let dir = std::env::temp_dir().join(format!("kael-elevate-{}-{}", std::process::id(), n));
+        fs::create_dir_all(&dir).unwrap();
+        // Like the real tools, they talk to the terminal rather than
+        // stdin/stdout, and echo goes off before the prompt is printed
+        let check = format!(
+            r#"read -r pw < /dev/tty; stty echo < /dev/tty; echo > /dev/tty
+if [ "$pw" != "{PASSWORD}" ]; then echo "Sorry, try again." > /dev/tty; exit 1; fi
+"#
+        );
+        let sudo = format!(
+            r#"#!/bin/sh
+stty -echo < /dev/tty
+prompt=${{SUDO_PROMPT:-[sudo] password for %p: }}
+printf '%s' "$(echo "$prompt" | sed 's/%p/root/')" > /dev/tty
+{check}"$@"
+"#
+        );
+        let pkexec = format!("#!/bin/sh\nstty -echo < /dev/tty\nprintf 'Password: ' > /dev/tty\n{check}\"$@\"\n");
+        for (file, script) in [("sudo", sudo), ("pkexec", pkexec)] {
+            let path = dir.join(file);
+            fs::write(&path, script).unwrap();
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+            }
+        }
+        dir
+    }
+
+    async fn run(method: Elevation, cmdline: &str, secret: Secret) -> (Option<PromptKind>, String) {
+        let bin = fake_bin();
+        let path = format!("{}:{}", bin.display(), std::env::var("PATH").unwrap_or_default());
+        let manager = SessionManager::new();
+        let id = manager
+            .spawn(ShellConfig::sh().with_sudo_prompt().with_env("PATH", path))
+            .await
+            .unwrap();
+        let rx = manager.output(id).await.unwrap();
+        let line = format!("{}; echo END\n", method.command_line(cmdline));
+        manager.write(id, line.as_bytes()).await.unwrap();
+
+        let mut detector = PasswordPrompt::new();
+        let mut seen = None;
+        let mut secret = Some(secret);
+        let out = read_while(&rx, |chunk| {
+            if let Some(kind) = detector.feed(chunk) {
+                seen = Some(kind);
+                return secret.take().and_then(Secret::into_answer);
+            }
+            None
+        }, &manager, id)
+        .await;
+        manager.close(id).await.unwrap();
+        let _ = fs::remove_dir_all(bin);
+        (seen, out)
+    }
+
+    /// Collect output until the END marker, writing back whatever `on_chunk` returns.
+    async fn read_while(
+        rx: &Receiver<Vec<u8>>,
+        mut on_chunk: impl FnMut(&[u8]) -> Option<Zeroizing<Vec<u8>>>,
+        manager: &SessionManager,
+        id: crate::SessionId,
+    ) -> String {
+        let mut out = String::new();
+        let _ = tokio::time::timeout(Duration::from_secs(10), async {
+            while let Ok(chunk) = rx.recv().await {
+                out.push_str(&String::from_utf8_lossy(&chunk));
+                if let Some(answer) = on_chunk(&chunk) {
+                    manager.write(id, &answer).await.unwrap();
+                }
+                if out.lines().any(|l| l.trim() == "END") {
+                    break;
+                }
+            }
+        })
+        .await;
+        out
+    }
+
+    #[test]
+    fn test_command_lines() {
+        assert_eq!(Elevation::requested_by("sudo -u bob ls"), Some(Elevation::Sudo));
+        assert_eq!(Elevation::requested_by("sudoku"), None);
+        assert_eq!(
+            Elevation::Sudo.command_line("sudo  grep 'a b' /etc/x | wc -l"),
+            "sudo grep 'a b' /etc/x | wc -l"
+        );
+        assert_eq!(
+            Elevation::Pkexec.command_line("sudo echo 'it''s' | tr a b"),
+            r"pkexec /bin/sh -c 'echo '\''it'\'''\''s'\'' | tr a b'"
+        );
+    }
+
+    #[test]
+    fn test_prompt_detection() {
+        let mut p = PasswordPrompt::new();
+        assert_eq!(p.feed(b"building...\r\n"), None);
+        assert_eq!(
+            p.feed(b"\x1b[0m[kael-sudo] password for root: "),
+            Some(PromptKind::Sudo { user: "root".into() })
+        );
+        // Reported once, until new output arrives
+        assert_eq!(p.feed(b""), None);
+        assert_eq!(p.feed(b"\r\nSorry, try again.\r\n[sudo] password for me:"), Some(PromptKind::Other("[sudo] password for me:".into())));
+        assert_eq!(p.feed(b"\r\nPassword strength: good\r\n"), None);
+    }
+
+    #[test]
+    fn test_secret_expiry_and_debug() {
+        let secret = Secret::with_ttl("pw".into(), Duration::ZERO);
+        assert_eq!(format!("{secret:?}"), "Secret(<redacted>)");
+        assert!(secret.into_answer().is_none());
+        let answer = Secret::new("pw".into()).into_answer().unwrap();
+        assert_eq!(answer.as_slice(), b"pw\n");
+    }
+
+    #[tokio::test]
+    async fn test_sudo_answered_in_pty() {
+        let (prompt, out) = run(
+            Elevation::Sudo,
+            "sudo printf '%s\\n' 'quoted  arg' | tr a-z A-Z",
+            Secret::new(PASSWORD.into()),
+        )
+        .await;
+        assert_eq!(prompt, Some(PromptKind::Sudo { user: "root".into() }));
+        assert!(out.contains("QUOTED  ARG"), "{out}");
+        assert!(!out.contains(PASSWORD), "password echoed: {out}");
+    }
+
+    #[tokio::test]
+    async fn test_wrong_password_and_pkexec() {
+        let (_, out) = run(Elevation::Sudo, "sudo echo hi", Secret::new("nope".into())).await;
+        assert!(out.contains("Sorry, try again."), "{out}");
+
+        let (prompt, out) = run(Elevation::Pkexec, "sudo echo via polkit", Secret::new(PASSWORD.into())).await;
+        // The shell's own prompt may share the line
+        assert!(matches!(prompt, Some(PromptKind::Other(line)) if line.ends_with("Password:")));
+        assert!(out.contains("via polkit"), "{out}");
+    }
+}
@@ -0,0 +1,238 @@
+//! Shell integration for bash, zsh and fish.
+//!
+//! The injected scripts mark the prompt with OSC 133 (`A` prompt start,
+//! `B` input start, `C` command output start, `D;<status>` command end) and
+//! report the working directory with OSC 7 before every prompt. The user's
+//! own rc files are still loaded first. `CommandTracker` turns the markers
+//! back into command blocks.
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use portable_pty::CommandBuilder;
+
+/// Set in the environment of integrated shells.
+pub const INTEGRATION_ENV: &str = "KAEL_SHELL_INTEGRATION";
+
+/// When set, the bash integration skips `~/.bashrc` so the shell behaves
+/// the same on every machine.
+pub const SKIP_USER_RC_ENV: &str = "KAEL_SKIP_USER_RC";
+
+const BASH_RC: &str = r#"# Kael shell integration for bash, loaded with --rcfile
+if [ -z "$KAEL_SKIP_USER_RC" ]; then
+    [ -f /etc/bash.bashrc ] && . /etc/bash.bashrc
+    [ -f "$HOME/.bashrc" ] && . "$HOME/.bashrc"
+fi
+
+__kael_precmd() {
+    local status=$?
+    printf '\033]133;D;%s\007' "$status"
+    printf '\033]7;file://%s%s\007' "$HOSTNAME" "$PWD"
+    printf '\033]133;A\007'
+    return $status
+}
+
+if [[ "$PROMPT_COMMAND" != *__kael_precmd* ]]; then
+    PROMPT_COMMAND="__kael_precmd${PROMPT_COMMAND:+; $PROMPT_COMMAND}"
+fi
+PS1="${PS1}\[\033]133;B\007\]"
+PS0="\033]133;C\007${PS0}"
+"#;
+
+const ZSH_ENV: &str = r#"# Kael shell integration for zsh: ZDOTDIR points here until .zshrc
+if [[ -f "$KAEL_USER_ZDOTDIR/.zshenv" ]]; then
+    __kael_zdotdir=$ZDOTDIR
+    ZDOTDIR=$KAEL_USER_ZDOTDIR
+    . "$KAEL_USER_ZDOTDIR/.zshenv"
+    ZDOTDIR=$__kael_zdotdir
+    unset __kael_zdotdir
+fi
+"#;
+
+const ZSH_RC: &str = r#"# Kael shell integration for zsh
+ZDOTDIR=$KAEL_USER_ZDOTDIR
+[[ -f "$ZDOTDIR/.zshrc" ]] && . "$ZDOTDIR/.zshrc"
+
+__kael_precmd() {
+    local ret=$?
+    printf '\033]133;D;%s\007' "$ret"
+    printf '\033]7;file://%s%s\007' "$HOST" "$PWD"
+    printf '\033]133;A\007'
+}
+
+__kael_preexec() {
+    printf '\033]133;C\007'
+}
+
+precmd_functions=(__kael_precmd $precmd_functions)
+preexec_functions+=(__kael_preexec)
+PS1="${PS1}%{$(printf '\033]133;B\007')%}"
+"#;
+
+const FISH_INIT: &str = r#"# Kael shell integration for fish, sourced with --init-command
+function __kael_preexec --on-event fish_preexec
+    printf '\e]133;C\a'
+end
+
+function __kael_postexec --on-event fish_postexec
+    printf '\e]133;D;%s\a' $status
+end
+
+function __kael_prompt --on-event fish_prompt
+    printf '\e]7;file://%s%s\a' $hostname $PWD
+    printf '\e]133;A\a'
+end
+
+functions -c fish_prompt __kael_user_prompt
+function fish_prompt
+    __kael_user_prompt
+    printf '\e]133;B\a'
+end
+"#;
+
+/// Shells with an integration script.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShellKind {
+    Bash,
+    Zsh,
+    Fish,
+    /// Anything else (sh, dash, ...); runs without markers.
+    Other,
+}
+
+impl ShellKind {
+    /// Guess the shell from the program path, e.g. `/usr/bin/zsh` or `-bash`.
+    pub fn detect(program: &str) -> Self {
+        let name = program.rsplit('/').next().unwrap_or(program).trim_start_matches('-');
+        match name {
+            "bash" => ShellKind::Bash,
+            "zsh" => ShellKind::Zsh,
+            "fish" => ShellKind::Fish,
+            _ => ShellKind::Other,
+        }
+    }
+}
+
+/// Per-user runtime directory for the integration scripts and the daemon
+/// socket: `$XDG_RUNTIME_DIR/kael-terminal`, or a directory under the
+/// system temp dir.
+pub(crate) fn runtime_dir() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR").filter(|d| !d.is_empty()) {
+        Some(dir) => PathBuf::from(dir).join("kael-terminal"),
+        None => {
+            let user = std::env::var("USER").unwrap_or_else(|_| "user".to_string());
+            std::env::temp_dir().join(format!("kael-terminal-{user}"))
+        }
+    }
+}
+
+fn write_script(path: &Path, contents: &str) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(parent, fs::Permissions::from_mode(0o700))?;
+        }
+    }
+    // Skip the write when an identical copy exists, so running shells never
+    // see a half-written file
+    if fs::read_to_string(path).is_ok_and(|existing| existing == contents) {
+        return Ok(());
+    }
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(tmp, path)
+}
+
+/// Add the integration for `kind` to `cmd`. Only interactive shells are
+/// touched: with explicit `args` (e.g. `-c`) there is no prompt to mark.
+pub(crate) fn apply(kind: ShellKind, args: &[String], cmd: &mut CommandBuilder) -> io::Result<()> {
+    if !args.is_empty() || kind == ShellKind::Other {
+        return Ok(());
+    }
+    let dir = runtime_dir();
+    match kind {
+        ShellKind::Bash => {
+            let rc = dir.join("kael.bash");
+            write_script(&rc, BASH_RC)?;
+            cmd.arg("--rcfile");
+            cmd.arg(rc);
+        }
+        ShellKind::Zsh => {
+            let zdotdir = dir.join("zsh");
+            write_script(&zdotdir.join(".zshenv"), ZSH_ENV)?;
+            write_script(&zdotdir.join(".zshrc"), ZSH_RC)?;
+            let user_zdotdir = std::env::var_os("ZDOTDIR")
+                .or_else(|| std::env::var_os("HOME"))
+                .unwrap_or_default();
+            cmd.env("KAEL_USER_ZDOTDIR", user_zdotdir);
+            cmd.env("ZDOTDIR", zdotdir);
+        }
+        ShellKind::Fish => {
+            let init = dir.join("kael.fish");
+            write_script(&init, FISH_INIT)?;
+            cmd.arg("--init-command");
+            cmd.arg(format!("source '{}'", init.display()));
+        }
+        ShellKind::Other => {}
+    }
+    cmd.env(INTEGRATION_ENV, "1");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BlockEvent, CommandTracker, SessionManager, ShellConfig};
+    use std::time::Duration;
+
+    #[test]
+    fn test_detect_shell_kind() {
+        assert_eq!(ShellKind::detect("/usr/bin/bash"), ShellKind::Bash);
+        assert_eq!(ShellKind::detect("-zsh"), ShellKind::Zsh);
+        assert_eq!(ShellKind::detect("fish"), ShellKind::Fish);
+        assert_eq!(ShellKind::detect("/bin/sh"), ShellKind::Other);
+    }
+
+    #[tokio::test]
+    async fn test_bash_reports_command_blocks() {
+        if !Path::new("/bin/bash").exists() {
+            return;
+        }
+        let manager = SessionManager::new();
+        let config = ShellConfig::new("/bin/bash")
+            .with_integration()
+            .with_env(SKIP_USER_RC_ENV, "1")
+            .with_cwd("/");
+        let id = manager.spawn(config).await.unwrap();
+        let rx = manager.output(id).await.unwrap();
+        manager.write(id, b"cd /tmp; (exit 3)\necho done\n").await.unwrap();
+
+        let mut tracker = CommandTracker::new();
+        let mut blocks = Vec::new();
+        let _ = tokio::time::timeout(Duration::from_secs(10), async {
+            while let Ok(chunk) = rx.recv().await {
+                for event in tracker.feed(&chunk) {
+                    if let BlockEvent::Finished(block) = event {
+                        blocks.push(block);
+                    }
+                }
+                if blocks.len() == 2 {
+                    break;
+                }
+            }
+        })
+        .await;
+        manager.close(id).await.unwrap();
+
+        assert_eq!(blocks.len(), 2, "blocks: {blocks:?}");
+        assert_eq!(blocks[0].command, "cd /tmp; (exit 3)");
+        assert_eq!(blocks[0].exit_code, Some(3));
+        assert_eq!(blocks[0].cwd.as_deref(), Some(Path::new("/")));
+        assert_eq!(blocks[1].command, "echo done");
+        assert_eq!(blocks[1].output, "done");
+        assert!(blocks[1].succeeded());
+        assert_eq!(blocks[1].cwd.as_deref(), Some(Path::new("/tmp")));
+    }
+}
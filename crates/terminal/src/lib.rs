@@ -1,15 +1,46 @@
 use std::io::Read;
+use std::sync::Arc;
 
 use anyhow::Result;
 use async_channel::{Receiver, Sender};
-use portable_pty::{native_pty_system, CommandBuilder, PtySize};
+use portable_pty::{native_pty_system, ChildKiller, PtySize};
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use tracing::{error};
+use tokio::sync::watch;
+use tracing::error;
+
+mod blocks;
+mod config;
+pub mod daemon;
+mod elevate;
+mod integration;
+mod manager;
+pub mod recording;
+pub mod sandbox;
+pub mod screen;
+pub mod scrollback;
+
+pub use blocks::{BlockEvent, CommandBlock, CommandTracker};
+pub use config::ShellConfig;
+pub use daemon::{Attachment, DaemonClient, RemoteSession};
+pub use elevate::{Elevation, PasswordPrompt, PromptKind, Secret, SUDO_PROMPT};
+pub use integration::{ShellKind, INTEGRATION_ENV, SKIP_USER_RC_ENV};
+pub use manager::{SessionEvent, SessionId, SessionInfo, SessionManager};
+pub use recording::{Cast, CastEvent, RecorderSlot};
+pub use sandbox::{ChangeKind, FileChange, Sandbox, SandboxRun};
+pub use screen::Screen;
+pub use scrollback::{LineId, Point, Search, SearchMatch, Selection};
 
 #[derive(Error, Debug)]
 pub enum TerminalError {
     #[error("PTY spawn error: {0}")]
     Spawn(String),
+    #[error("No terminal session with id {0}")]
+    UnknownSession(SessionId),
+    #[error("Terminal daemon: {0}")]
+    Daemon(String),
+    #[error("Sandbox: {0}")]
+    Sandbox(String),
 }
 
 #[derive(Clone)]
@@ -19,38 +50,103 @@ pub struct PtySessionHandle {
     rx: Receiver<Vec<u8>>, // for potential stdin echo or control
 }
 
+/// How a shell process ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExitInfo {
+    pub code: u32,
+    pub success: bool,
+}
+
+/// Input side of a PTY. Shared so a write can run without holding the lock
+/// that guards the session itself.
+pub type PtyWriter = Arc<std::sync::Mutex<Box<dyn std::io::Write + Send>>>;
+
 pub struct PtySession {
-    pub child: Box<dyn portable_pty::Child + Send>,
+    pub config: ShellConfig,
+    pub pid: Option<u32>,
     pub master: Box<dyn portable_pty::MasterPty + Send>,
-    pub writer: Box<dyn std::io::Write + Send>,
+    pub writer: PtyWriter,
     pub reader_task: tokio::task::JoinHandle<()>,
     pub handle: PtySessionHandle,
+    /// Records this session while a recording is running.
+    pub recorder: RecorderSlot,
+    killer: Box<dyn ChildKiller + Send + Sync>,
+    exit: watch::Receiver<Option<ExitInfo>>,
 }
 
 impl PtySession {
     pub fn kill(&mut self) -> Result<()> {
-        self.child.kill().map_err(|e| anyhow::anyhow!(e))
+        if self.exit_info().is_some() {
+            return Ok(());
+        }
+        self.killer.kill().map_err(|e| anyhow::anyhow!(e))
+    }
+
+    /// Exit status, once the shell has exited.
+    pub fn exit_info(&self) -> Option<ExitInfo> {
+        *self.exit.borrow()
+    }
+
+    /// Wait for the shell to exit.
+    pub async fn wait(&self) -> ExitInfo {
+        let mut exit = self.exit.clone();
+        loop {
+            if let Some(info) = *exit.borrow_and_update() {
+                return info;
+            }
+            if exit.changed().await.is_err() {
+                // Waiter thread is gone without reporting; treat as abnormal exit
+                return ExitInfo { code: 1, success: false };
+            }
+        }
     }
 }
 
 pub struct TerminalManager;
 
 impl TerminalManager {
+    /// Spawn `/bin/sh` with the given size (24x120 by default).
     pub fn spawn_shell(size: Option<PtySize>) -> Result<PtySession> {
+        let mut config = ShellConfig::sh();
+        if let Some(size) = size {
+            config.size = size;
+        }
+        Self::spawn(config)
+    }
+
+    /// Spawn a shell described by `config`.
+    pub fn spawn(config: ShellConfig) -> Result<PtySession> {
         let pty_system = native_pty_system();
-        let pair = pty_system.openpty(size.unwrap_or(PtySize {
-            rows: 24,
-            cols: 120,
-            pixel_width: 0,
-            pixel_height: 0,
-        }))
-        .map_err(|e| anyhow::anyhow!(e))?;
-
-        let cmd = CommandBuilder::new("/bin/sh");
-        let child = pair
+        let pair = pty_system
+            .openpty(config.size)
+            .map_err(|e| TerminalError::Spawn(e.to_string()))?;
+
+        let cmd = config
+            .command()
+            .map_err(|e| TerminalError::Spawn(format!("shell integration: {e}")))?;
+        let mut child = pair
             .slave
             .spawn_command(cmd)
-            .map_err(|e| anyhow::anyhow!(e))?;
+            .map_err(|e| TerminalError::Spawn(format!("{}: {e}", config.program)))?;
+        // Drop our copy of the slave so the reader sees EOF when the shell exits
+        drop(pair.slave);
+
+        let pid = child.process_id();
+        let killer = child.clone_killer();
+        let (exit_tx, exit) = watch::channel(None);
+        std::thread::spawn(move || {
+            let info = match child.wait() {
+                Ok(status) => ExitInfo {
+                    code: status.exit_code(),
+                    success: status.success(),
+                },
+                Err(e) => {
+                    error!("PTY wait error: {e}");
+                    ExitInfo { code: 1, success: false }
+                }
+            };
+            let _ = exit_tx.send(Some(info));
+        });
 
         let reader = pair.master.try_clone_reader().map_err(|e| anyhow::anyhow!(e))?;
         let writer = pair.master.take_writer().map_err(|e| anyhow::anyhow!(e))?;
@@ -73,30 +169,52 @@ impl TerminalManager {
                     }
                     Ok(_) => break,
                     Err(e) => {
-                        eprintln!("PTY read error: {e}");
+                        // EIO is how Linux reports a hung-up PTY after the shell exits
+                        if e.raw_os_error() != Some(5) {
+                            eprintln!("PTY read error: {e}");
+                        }
                         break;
                     }
                 }
             }
+            tx_reader.close();
         });
 
+        // Consumers read through the recording tee
+        let recorder = RecorderSlot::default();
+        let rx = recording::tee(rx, recorder.clone());
+
         Ok(PtySession {
-            child,
+            config,
+            pid,
             master,
-            writer,
+            writer: Arc::new(std::sync::Mutex::new(writer)),
             reader_task,
             handle: PtySessionHandle { tx, rx },
+            recorder,
+            killer,
+            exit,
         })
     }
 
     pub async fn write_input(session: &mut PtySession, data: &[u8]) -> Result<()> {
+        session.recorder.input(data);
+        Self::write_pty(&session.writer, data)
+    }
+
+    /// Blocking write of `data` to the PTY, bypassing any recording.
+    pub fn write_pty(writer: &PtyWriter, data: &[u8]) -> Result<()> {
         use std::io::Write;
-        session.writer.write_all(data).map_err(|e| anyhow::anyhow!(e))?;
-        session.writer.flush().map_err(|e| anyhow::anyhow!(e))
+        let mut writer = writer.lock().map_err(|_| anyhow::anyhow!("PTY writer poisoned"))?;
+        writer.write_all(data).map_err(|e| anyhow::anyhow!(e))?;
+        writer.flush().map_err(|e| anyhow::anyhow!(e))
     }
 
     pub async fn resize(session: &mut PtySession, size: PtySize) -> Result<()> {
-        session.master.resize(size).map_err(|e| anyhow::anyhow!(format!("{e}")))
+        session.master.resize(size).map_err(|e| anyhow::anyhow!(format!("{e}")))?;
+        session.config.size = size;
+        session.recorder.resize(size.cols, size.rows);
+        Ok(())
     }
 
     pub fn output_stream(session: &PtySession) -> Receiver<Vec<u8>> {
@@ -0,0 +1,67 @@
+//! `kael-terminal`: the detached session daemon. Owns PTY sessions and
+//! serves them on a Unix socket so they survive desktop app restarts.
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use kael_terminal::daemon::{self, Daemon, DaemonClient};
+
+const USAGE: &str = "Usage: kael-terminal [--socket PATH] [serve | list | stop]
+
+  serve   run the daemon (default)
+  list    show the daemon's sessions
+  stop    close every session and stop the daemon
+
+The socket defaults to $KAEL_TERMINAL_SOCKET or
+$XDG_RUNTIME_DIR/kael-terminal/daemon.sock.";
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut socket = daemon::default_socket_path();
+    let mut command = "serve".to_string();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--socket" => match args.next() {
+                Some(path) => socket = PathBuf::from(path),
+                None => bail!("--socket needs a path"),
+            },
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return Ok(());
+            }
+            "serve" | "list" | "stop" => command = arg,
+            other => bail!("unknown argument: {other}\n\n{USAGE}"),
+        }
+    }
+
+    match command.as_str() {
+        "list" => {
+            for session in DaemonClient::new(&socket).list().await? {
+                let state = match session.exit {
+                    None => "running".to_string(),
+                    Some(exit) => format!("exited {}", exit.code),
+                };
+                println!(
+                    "{}\t{}\t{}\t{}x{}\t{}",
+                    session.id,
+                    session.name,
+                    session.program,
+                    session.cols,
+                    session.rows,
+                    state
+                );
+            }
+            Ok(())
+        }
+        "stop" => DaemonClient::new(&socket).shutdown().await,
+        _ => {
+            // Closing the terminal that started us must not take the shells down
+            #[cfg(unix)]
+            let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+            #[cfg(unix)]
+            tokio::spawn(async move { while hangup.recv().await.is_some() {} });
+            Arc::new(Daemon::new()).run(socket).await
+        }
+    }
+}
@@ -0,0 +1,317 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_channel::Receiver;
+use portable_pty::PtySize;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, Mutex};
+
+use crate::{ExitInfo, PtySession, ShellConfig, TerminalError, TerminalManager};
+
+/// Identifier of a session within one `SessionManager`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct SessionId(pub u64);
+
+impl fmt::Display for SessionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Lifecycle notifications, delivered to every subscriber.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionEvent {
+    Spawned { id: SessionId, name: String, pid: Option<u32> },
+    Exited { id: SessionId, exit: ExitInfo },
+    Closed { id: SessionId },
+}
+
+/// Snapshot of one session for tab bars and status displays.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionInfo {
+    pub id: SessionId,
+    pub name: String,
+    pub program: String,
+    pub cwd: Option<PathBuf>,
+    pub size: PtySize,
+    pub pid: Option<u32>,
+    /// `None` while the shell is still running.
+    pub exit: Option<ExitInfo>,
+}
+
+/// Owns any number of PTY sessions keyed by `SessionId`. Cheap to clone;
+/// clones share the same sessions.
+#[derive(Clone)]
+pub struct SessionManager {
+    sessions: Arc<Mutex<BTreeMap<SessionId, PtySession>>>,
+    next_id: Arc<AtomicU64>,
+    events: broadcast::Sender<SessionEvent>,
+}
+
+impl Default for SessionManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SessionManager {
+    pub fn new() -> Self {
+        let (events, _) = broadcast::channel(64);
+        Self {
+            sessions: Arc::new(Mutex::new(BTreeMap::new())),
+            next_id: Arc::new(AtomicU64::new(1)),
+            events,
+        }
+    }
+
+    /// Receive lifecycle events for sessions spawned or closed after this call.
+    pub fn subscribe(&self) -> broadcast::Receiver<SessionEvent> {
+        self.events.subscribe()
+    }
+
+    /// Start a new session. An `Exited` event follows when the shell ends;
+    /// the session stays listed (with its exit status) until `close`.
+    pub async fn spawn(&self, config: ShellConfig) -> Result<SessionId> {
+        let session = TerminalManager::spawn(config)?;
+        let id = SessionId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let name = session.config.display_name();
+        let pid = session.pid;
+
+        let mut exit = session.exit.clone();
+        self.sessions.lock().await.insert(id, session);
+        let _ = self.events.send(SessionEvent::Spawned { id, name, pid });
+
+        let events = self.events.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Some(exit) = *exit.borrow_and_update() {
+                    let _ = events.send(SessionEvent::Exited { id, exit });
+                    break;
+                }
+                if exit.changed().await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(id)
+    }
+
+    /// Send input to the session. The sessions lock is released before the
+    /// write, which can block while the shell is not reading.
+    pub async fn write(&self, id: SessionId, data: &[u8]) -> Result<()> {
+        self.write_to(id, data, false).await
+    }
+
+    /// Send a password or other secret. A recording gets `***` in its place.
+    pub async fn write_secret(&self, id: SessionId, data: &[u8]) -> Result<()> {
+        self.write_to(id, data, true).await
+    }
+
+    async fn write_to(&self, id: SessionId, data: &[u8], secret: bool) -> Result<()> {
+        let (writer, recorder) = {
+            let sessions = self.sessions.lock().await;
+            let session = sessions.get(&id).ok_or(TerminalError::UnknownSession(id))?;
+            (session.writer.clone(), session.recorder.clone())
+        };
+        if secret {
+            recorder.redacted_input();
+        } else {
+            recorder.input(data);
+        }
+        // The copy may hold a password: wipe it once written
+        let data = zeroize::Zeroizing::new(data.to_vec());
+        tokio::task::spawn_blocking(move || TerminalManager::write_pty(&writer, &data)).await?
+    }
+
+    pub async fn resize(&self, id: SessionId, rows: u16, cols: u16) -> Result<()> {
+        let mut sessions = self.sessions.lock().await;
+        let session = sessions.get_mut(&id).ok_or(TerminalError::UnknownSession(id))?;
+        let size = PtySize {
+            rows,
+            cols,
+            ..session.config.size
+        };
+        TerminalManager::resize(session, size).await
+    }
+
+    /// The session's own output channel.
+    pub async fn output(&self, id: SessionId) -> Result<Receiver<Vec<u8>>> {
+        let sessions = self.sessions.lock().await;
+        let session = sessions.get(&id).ok_or(TerminalError::UnknownSession(id))?;
+        Ok(TerminalManager::output_stream(session))
+    }
+
+    /// Record the session to an asciicast v2 file at `path` until
+    /// `stop_recording`, replacing any recording in progress.
+    pub async fn start_recording(&self, id: SessionId, path: &Path) -> Result<()> {
+        let sessions = self.sessions.lock().await;
+        let session = sessions.get(&id).ok_or(TerminalError::UnknownSession(id))?;
+        let size = session.config.size;
+        session
+            .recorder
+            .start(path, size.cols, size.rows, Some(session.config.display_name()))
+    }
+
+    /// Stop recording; returns the finished file, if one was recording.
+    pub async fn stop_recording(&self, id: SessionId) -> Result<Option<PathBuf>> {
+        let sessions = self.sessions.lock().await;
+        let session = sessions.get(&id).ok_or(TerminalError::UnknownSession(id))?;
+        Ok(session.recorder.stop())
+    }
+
+    /// The file being recorded to, if any.
+    pub async fn recording(&self, id: SessionId) -> Option<PathBuf> {
+        let sessions = self.sessions.lock().await;
+        sessions.get(&id).and_then(|s| s.recorder.path())
+    }
+
+    pub async fn rename(&self, id: SessionId, name: impl Into<String>) -> Result<()> {
+        let mut sessions = self.sessions.lock().await;
+        let session = sessions.get_mut(&id).ok_or(TerminalError::UnknownSession(id))?;
+        session.config.name = Some(name.into());
+        Ok(())
+    }
+
+    /// Kill the shell but keep the session listed.
+    pub async fn kill(&self, id: SessionId) -> Result<()> {
+        let mut sessions = self.sessions.lock().await;
+        let session = sessions.get_mut(&id).ok_or(TerminalError::UnknownSession(id))?;
+        session.kill()
+    }
+
+    /// Kill the shell if it is still running and forget the session.
+    pub async fn close(&self, id: SessionId) -> Result<()> {
+        let session = self.sessions.lock().await.remove(&id);
+        let mut session = session.ok_or(TerminalError::UnknownSession(id))?;
+        session.kill()?;
+        let _ = self.events.send(SessionEvent::Closed { id });
+        Ok(())
+    }
+
+    pub async fn info(&self, id: SessionId) -> Option<SessionInfo> {
+        let sessions = self.sessions.lock().await;
+        sessions.get(&id).map(|s| Self::describe(id, s))
+    }
+
+    /// All sessions in creation order.
+    pub async fn list(&self) -> Vec<SessionInfo> {
+        let sessions = self.sessions.lock().await;
+        sessions.iter().map(|(id, s)| Self::describe(*id, s)).collect()
+    }
+
+    /// Wait until the session's shell exits.
+    pub async fn wait(&self, id: SessionId) -> Result<ExitInfo> {
+        let mut exit = {
+            let sessions = self.sessions.lock().await;
+            let session = sessions.get(&id).ok_or(TerminalError::UnknownSession(id))?;
+            session.exit.clone()
+        };
+        loop {
+            if let Some(info) = *exit.borrow_and_update() {
+                return Ok(info);
+            }
+            if exit.changed().await.is_err() {
+                return Ok(ExitInfo { code: 1, success: false });
+            }
+        }
+    }
+
+    fn describe(id: SessionId, session: &PtySession) -> SessionInfo {
+        SessionInfo {
+            id,
+            name: session.config.display_name(),
+            program: session.config.program.clone(),
+            cwd: session.config.cwd.clone(),
+            size: session.config.size,
+            pid: session.pid,
+            exit: session.exit_info(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    async fn read_until(rx: &Receiver<Vec<u8>>, needle: &str) -> String {
+        let mut out = String::new();
+        let _ = tokio::time::timeout(Duration::from_secs(5), async {
+            while let Ok(chunk) = rx.recv().await {
+                out.push_str(&String::from_utf8_lossy(&chunk));
+                if out.contains(needle) {
+                    break;
+                }
+            }
+        })
+        .await;
+        out
+    }
+
+    #[tokio::test]
+    async fn test_sessions_have_separate_output() {
+        let manager = SessionManager::new();
+        let logs = manager
+            .spawn(ShellConfig::sh().with_name("logs").with_env("KAEL_TAB", "logs"))
+            .await
+            .unwrap();
+        let build = manager
+            .spawn(ShellConfig::sh().with_cwd("/").with_size(40, 100))
+            .await
+            .unwrap();
+        assert_ne!(logs, build);
+
+        let logs_rx = manager.output(logs).await.unwrap();
+        let build_rx = manager.output(build).await.unwrap();
+        manager.write(logs, b"echo tab=$KAEL_TAB\n").await.unwrap();
+        manager.write(build, b"echo dir=$(pwd)\n").await.unwrap();
+
+        assert!(read_until(&logs_rx, "tab=logs").await.contains("tab=logs"));
+        let build_out = read_until(&build_rx, "dir=/").await;
+        assert!(build_out.contains("dir=/"));
+        assert!(!build_out.contains("tab=logs"));
+
+        let infos = manager.list().await;
+        assert_eq!(infos.len(), 2);
+        assert_eq!(infos[0].name, "logs");
+        assert_eq!(infos[1].name, "sh");
+        assert_eq!(infos[1].size.rows, 40);
+
+        manager.close(logs).await.unwrap();
+        manager.close(build).await.unwrap();
+        assert!(manager.list().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_exit_event_carries_status() {
+        let manager = SessionManager::new();
+        let mut events = manager.subscribe();
+
+        let id = manager
+            .spawn(ShellConfig::sh().with_args(["-c", "exit 3"]))
+            .await
+            .unwrap();
+
+        let exit = manager.wait(id).await.unwrap();
+        assert_eq!(exit, ExitInfo { code: 3, success: false });
+
+        let mut seen = Vec::new();
+        while let Ok(Ok(event)) = tokio::time::timeout(Duration::from_secs(5), events.recv()).await {
+            let done = matches!(event, SessionEvent::Exited { .. });
+            seen.push(event);
+            if done {
+                break;
+            }
+        }
+        assert!(matches!(seen[0], SessionEvent::Spawned { id: spawned, .. } if spawned == id));
+        assert_eq!(seen.last(), Some(&SessionEvent::Exited { id, exit }));
+        assert_eq!(manager.info(id).await.unwrap().exit, Some(exit));
+
+        assert!(manager.write(SessionId(999), b"x").await.is_err());
+    }
+}
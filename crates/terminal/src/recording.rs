@@ -0,0 +1,401 @@
+//! Session recording and replay in asciicast v2 format.
+//!
+//! Every session's output passes through `tee`, which copies chunks into the
+//! session's `RecorderSlot` while one is recording. Input and resizes are
+//! recorded by `SessionManager`. A file is a JSON header line followed by
+//! one `[seconds, code, data]` line per event, `code` being `o` (output),
+//! `i` (input) or `r` (resize, `COLSxROWS`). Passwords typed through
+//! `SessionManager::write_secret` are recorded as `***`.
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context, Result};
+use async_channel::Receiver;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::Screen;
+
+/// asciicast v2 header line.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CastHeader {
+    pub version: u32,
+    pub width: u16,
+    pub height: u16,
+    /// Unix time the recording started.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env: Option<serde_json::Map<String, Value>>,
+}
+
+/// One recorded event.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CastEvent {
+    Output(String),
+    Input(String),
+    Resize { cols: u16, rows: u16 },
+}
+
+/// A loaded recording.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cast {
+    pub header: CastHeader,
+    /// Events with their offset from the start, in order.
+    pub events: Vec<(f64, CastEvent)>,
+}
+
+impl Cast {
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("open {}", path.display()))?;
+        let mut lines = BufReader::new(file).lines();
+        let header_line = lines.next().ok_or_else(|| anyhow!("empty recording"))??;
+        let header: CastHeader = serde_json::from_str(&header_line).context("asciicast header")?;
+        if header.version != 2 {
+            return Err(anyhow!("unsupported asciicast version {}", header.version));
+        }
+
+        let mut events = Vec::new();
+        for (n, line) in lines.enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            // A recording cut short by a crash may end in a partial line
+            let Ok((time, code, data)) = serde_json::from_str::<(f64, String, String)>(&line) else {
+                tracing::warn!("skipping malformed asciicast event on line {}", n + 2);
+                continue;
+            };
+            let event = match code.as_str() {
+                "o" => CastEvent::Output(data),
+                "i" => CastEvent::Input(data),
+                "r" => match data.split_once('x').map(|(c, r)| (c.parse(), r.parse())) {
+                    Some((Ok(cols), Ok(rows))) => CastEvent::Resize { cols, rows },
+                    _ => continue,
+                },
+                // Markers and future event types
+                _ => continue,
+            };
+            events.push((time, event));
+        }
+        Ok(Self { header, events })
+    }
+
+    /// Length of the recording in seconds.
+    pub fn duration(&self) -> f64 {
+        self.events.last().map_or(0.0, |(t, _)| *t)
+    }
+
+    /// The output as plain text, rendered through a terminal of the recorded
+    /// size: what a user would have seen, scrollback included.
+    pub fn transcript(&self) -> String {
+        let mut screen = Screen::with_scrollback(self.header.height, self.header.width, usize::MAX);
+        for (_, event) in &self.events {
+            match event {
+                CastEvent::Output(data) => screen.process(data.as_bytes()),
+                CastEvent::Resize { cols, rows } => screen.resize(*rows, *cols),
+                CastEvent::Input(_) => {}
+            }
+        }
+        let mut lines: Vec<String> = screen
+            .scrollback()
+            .iter()
+            .map(|row| crate::screen::row_to_string(row))
+            .collect();
+        lines.push(screen.contents());
+        lines.join("\n").trim_end().to_string()
+    }
+
+    /// Play the recording back in real time, scaled by `speed`. Pauses are
+    /// capped at `idle_limit` seconds. The channel closes at the end.
+    pub fn play(self, speed: f64, idle_limit: Option<f64>) -> Receiver<CastEvent> {
+        let (tx, rx) = async_channel::bounded(128);
+        let speed = if speed > 0.0 { speed } else { 1.0 };
+        tokio::spawn(async move {
+            let mut last = 0.0;
+            for (time, event) in self.events {
+                let mut gap = (time - last).max(0.0);
+                if let Some(limit) = idle_limit {
+                    gap = gap.min(limit);
+                }
+                last = time;
+                if gap > 0.0 {
+                    tokio::time::sleep(Duration::from_secs_f64(gap / speed)).await;
+                }
+                if tx.send(event).await.is_err() {
+                    break; // viewer went away
+                }
+            }
+        });
+        rx
+    }
+}
+
+struct Recorder {
+    path: PathBuf,
+    out: BufWriter<File>,
+    started: Instant,
+    /// Trailing bytes of an unfinished UTF-8 sequence, per stream
+    pending_output: Vec<u8>,
+    pending_input: Vec<u8>,
+}
+
+impl Recorder {
+    fn event(&mut self, code: &str, data: &str) -> Result<()> {
+        let time = self.started.elapsed().as_secs_f64();
+        let line = serde_json::to_string(&(round_time(time), code, data))?;
+        writeln!(self.out, "{}", line)?;
+        // Flush per event so a crash still leaves a usable recording
+        self.out.flush()?;
+        Ok(())
+    }
+
+    fn bytes(&mut self, code: &str, bytes: &[u8]) -> Result<()> {
+        let pending = if code == "i" { &mut self.pending_input } else { &mut self.pending_output };
+        pending.extend_from_slice(bytes);
+        let text = take_utf8(pending);
+        if text.is_empty() {
+            return Ok(());
+        }
+        self.event(code, &text)
+    }
+}
+
+/// asciinema writes six decimals
+fn round_time(t: f64) -> f64 {
+    (t * 1_000_000.0).round() / 1_000_000.0
+}
+
+/// Decode the complete UTF-8 prefix of `buf`, leaving an unfinished trailing
+/// sequence in place. Invalid bytes become U+FFFD.
+fn take_utf8(buf: &mut Vec<u8>) -> String {
+    let end = match std::str::from_utf8(buf) {
+        // error_len() is None only for a sequence cut off at the end
+        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+        _ => buf.len(),
+    };
+    let text = String::from_utf8_lossy(&buf[..end]).into_owned();
+    buf.drain(..end);
+    text
+}
+
+/// Where a session's recording goes while one is running. Shared between
+/// the session (input, resize) and its output tee.
+#[derive(Clone, Default)]
+pub struct RecorderSlot(Arc<Mutex<Option<Recorder>>>);
+
+impl RecorderSlot {
+    /// Start writing a new recording to `path`, replacing any current one.
+    pub fn start(&self, path: &Path, cols: u16, rows: u16, title: Option<String>) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        // Recordings contain everything typed and shown: owner read/write only
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let file = options.open(path).with_context(|| format!("create {}", path.display()))?;
+        let mut env = serde_json::Map::new();
+        for key in ["SHELL", "TERM"] {
+            if let Ok(value) = std::env::var(key) {
+                env.insert(key.to_string(), Value::String(value));
+            }
+        }
+        let header = CastHeader {
+            version: 2,
+            width: cols,
+            height: rows,
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs()),
+            title,
+            env: Some(env),
+        };
+        let mut out = BufWriter::new(file);
+        writeln!(out, "{}", serde_json::to_string(&header)?)?;
+        out.flush()?;
+        *self.lock() = Some(Recorder {
+            path: path.to_path_buf(),
+            out,
+            started: Instant::now(),
+            pending_output: Vec::new(),
+            pending_input: Vec::new(),
+        });
+        Ok(())
+    }
+
+    /// Stop recording; returns the file written, if one was running.
+    pub fn stop(&self) -> Option<PathBuf> {
+        let mut recorder = self.lock().take()?;
+        let _ = recorder.out.flush();
+        Some(recorder.path)
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.lock().is_some()
+    }
+
+    pub fn path(&self) -> Option<PathBuf> {
+        self.lock().as_ref().map(|r| r.path.clone())
+    }
+
+    pub(crate) fn output(&self, bytes: &[u8]) {
+        self.record(|r| r.bytes("o", bytes));
+    }
+
+    pub(crate) fn input(&self, bytes: &[u8]) {
+        self.record(|r| r.bytes("i", bytes));
+    }
+
+    /// Mark that a secret was typed without recording it.
+    pub(crate) fn redacted_input(&self) {
+        self.record(|r| r.event("i", "***"));
+    }
+
+    pub(crate) fn resize(&self, cols: u16, rows: u16) {
+        self.record(|r| r.event("r", &format!("{}x{}", cols, rows)));
+    }
+
+    fn record(&self, f: impl FnOnce(&mut Recorder) -> Result<()>) {
+        let mut slot = self.lock();
+        if let Some(recorder) = slot.as_mut() {
+            if let Err(e) = f(recorder) {
+                // A full disk should not take the terminal down; drop the recording
+                tracing::error!("recording to {} failed: {e}", recorder.path.display());
+                *slot = None;
+            }
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<Recorder>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Forward `rx` to a new channel, copying every chunk into `slot` while it
+/// is recording. The returned channel closes when `rx` does.
+pub fn tee(rx: Receiver<Vec<u8>>, slot: RecorderSlot) -> Receiver<Vec<u8>> {
+    let (tx, out) = async_channel::bounded(rx.capacity().unwrap_or(128));
+    tokio::spawn(async move {
+        while let Ok(chunk) = rx.recv().await {
+            slot.output(&chunk);
+            if tx.send(chunk).await.is_err() {
+                break;
+            }
+        }
+        tx.close();
+    });
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SessionManager, ShellConfig};
+
+    fn temp_cast(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("kael-cast-{}-{}.cast", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_utf8_split_across_chunks() {
+        let mut buf = "é".as_bytes()[..1].to_vec();
+        assert_eq!(take_utf8(&mut buf), "");
+        buf.extend_from_slice(&"é".as_bytes()[1..]);
+        buf.extend_from_slice(b"x\xff");
+        assert_eq!(take_utf8(&mut buf), "éx\u{fffd}");
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_load_and_transcript() {
+        let path = temp_cast("load");
+        std::fs::write(
+            &path,
+            concat!(
+                "{\"version\": 2, \"width\": 30, \"height\": 3}\n",
+                "[0.1, \"o\", \"$ paru -Syu\\r\\n\"]\n",
+                "[0.2, \"i\", \"y\"]\n",
+                "[1.5, \"o\", \"\\u001b[31merror:\\u001b[0m failed to commit\\r\\n\"]\n",
+                "[1.6, \"r\", \"40x5\"]\n",
+                "[1.7, \"m\", \"marker\"]\n",
+                "[1.8, \"o\", \"trunc",
+            ),
+        )
+        .unwrap();
+        let cast = Cast::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(cast.header.width, 30);
+        assert_eq!(cast.events.len(), 4);
+        assert_eq!(cast.events[1].1, CastEvent::Input("y".into()));
+        assert_eq!(cast.events[3].1, CastEvent::Resize { cols: 40, rows: 5 });
+        assert_eq!(cast.duration(), 1.6);
+        assert_eq!(cast.transcript(), "$ paru -Syu\nerror: failed to commit");
+    }
+
+    #[tokio::test]
+    async fn test_play_caps_idle_time() {
+        let cast = Cast {
+            header: CastHeader { version: 2, width: 10, height: 2, timestamp: None, title: None, env: None },
+            events: vec![
+                (0.0, CastEvent::Output("a".into())),
+                (30.0, CastEvent::Output("b".into())),
+            ],
+        };
+        let started = Instant::now();
+        let rx = cast.play(2.0, Some(0.1));
+        assert_eq!(rx.recv().await.unwrap(), CastEvent::Output("a".into()));
+        assert_eq!(rx.recv().await.unwrap(), CastEvent::Output("b".into()));
+        assert!(rx.recv().await.is_err());
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn test_records_session() {
+        let path = temp_cast("session");
+        let manager = SessionManager::new();
+        let id = manager.spawn(ShellConfig::sh().with_size(10, 60)).await.unwrap();
+        let rx = manager.output(id).await.unwrap();
+        manager.start_recording(id, &path).await.unwrap();
+
+        manager.write(id, b"echo rec-$((40 + 2))\n").await.unwrap();
+        manager.write_secret(id, b"hunter2\n").await.unwrap();
+        manager.resize(id, 12, 70).await.unwrap();
+        let mut out = String::new();
+        let _ = tokio::time::timeout(Duration::from_secs(5), async {
+            while let Ok(chunk) = rx.recv().await {
+                out.push_str(&String::from_utf8_lossy(&chunk));
+                if out.contains("rec-42") {
+                    break;
+                }
+            }
+        })
+        .await;
+        assert_eq!(manager.stop_recording(id).await.unwrap(), Some(path.clone()));
+        manager.close(id).await.unwrap();
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        let cast = Cast::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((cast.header.width, cast.header.height), (60, 10));
+        assert!(cast.events.iter().any(|(_, e)| *e == CastEvent::Input("echo rec-$((40 + 2))\n".into())));
+        // sh echoes it back as output; a password prompt would not
+        assert!(!cast.events.iter().any(|(_, e)| matches!(e, CastEvent::Input(i) if i.contains("hunter2"))));
+        assert!(cast.events.iter().any(|(_, e)| *e == CastEvent::Input("***".into())));
+        assert!(cast.events.iter().any(|(_, e)| *e == CastEvent::Resize { cols: 70, rows: 12 }));
+        assert!(cast.transcript().contains("rec-42"));
+    }
+}
@@ -0,0 +1,510 @@
+//! Trying commands without consequences.
+//!
+//! `Sandbox::run` executes a command line under bubblewrap: the root file
+//! system is mounted read-only, `$HOME`, `/tmp` and `/dev/shm` are empty
+//! tmpfs mounts, and the command gets its own user, PID, IPC and network
+//! namespaces (so no network unless asked for), no capabilities, and a new
+//! session so it cannot push keystrokes into the terminal it came from.
+//!
+//! The working directory is the one place the command may write to, and it
+//! writes to a copy. When it exits, the copy is compared with the original:
+//! `SandboxRun` lists the changed files, renders them as a unified diff, and
+//! only touches the real directory when `apply` is called.
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use similar::TextDiff;
+use tokio::io::AsyncReadExt;
+
+use crate::integration::runtime_dir;
+use crate::{ExitInfo, TerminalError};
+
+/// Largest working directory copied into the sandbox.
+pub const MAX_WORKDIR_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Output kept from a sandboxed command.
+const MAX_OUTPUT_BYTES: usize = 256 * 1024;
+
+/// How long a sandboxed command may run by default.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+static NEXT_RUN: AtomicU64 = AtomicU64::new(0);
+
+/// Where and how a command is tried.
+#[derive(Debug, Clone)]
+pub struct Sandbox {
+    workdir: PathBuf,
+    network: bool,
+    timeout: Duration,
+}
+
+impl Sandbox {
+    /// A sandbox whose writable copy is made from `workdir`.
+    pub fn new(workdir: impl Into<PathBuf>) -> Self {
+        Self {
+            workdir: workdir.into(),
+            network: false,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Share the host's network instead of an empty namespace.
+    pub fn with_network(mut self) -> Self {
+        self.network = true;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn workdir(&self) -> &Path {
+        &self.workdir
+    }
+
+    /// Whether bubblewrap is installed.
+    pub fn available() -> bool {
+        bwrap_path().is_some()
+    }
+
+    /// bubblewrap arguments running `cmdline` with `scratch` mounted over
+    /// the working directory.
+    fn args(&self, scratch: &Path, cmdline: &str) -> Vec<OsString> {
+        let home = std::env::var_os("HOME").unwrap_or_else(|| "/root".into());
+        let mut args: Vec<OsString> = ["--ro-bind", "/", "/", "--dev", "/dev", "--proc", "/proc"]
+            .into_iter()
+            .chain(["--tmpfs", "/tmp", "--tmpfs", "/dev/shm", "--tmpfs"])
+            .map(OsString::from)
+            .collect();
+        args.push(home);
+        // After the tmpfs mounts, so a working directory inside $HOME or
+        // /tmp is not hidden by them
+        args.extend(["--bind".into(), scratch.into(), self.workdir.clone().into()]);
+        args.push("--unshare-all".into());
+        if self.network {
+            args.push("--share-net".into());
+        }
+        args.extend(["--cap-drop", "ALL", "--die-with-parent", "--new-session", "--chdir"].map(OsString::from));
+        args.push(self.workdir.clone().into());
+        args.extend(["/bin/sh", "-c", cmdline].map(OsString::from));
+        args
+    }
+
+    /// Run `cmdline` in the sandbox. The real working directory is not
+    /// changed; see `SandboxRun::apply`.
+    pub async fn run(&self, cmdline: &str) -> Result<SandboxRun, TerminalError> {
+        let bwrap = bwrap_path()
+            .ok_or_else(|| TerminalError::Sandbox("bubblewrap (bwrap) is not installed".to_string()))?;
+        let workdir = self
+            .workdir
+            .canonicalize()
+            .map_err(|e| TerminalError::Sandbox(format!("{}: {e}", self.workdir.display())))?;
+        let sandbox = Self {
+            workdir,
+            ..self.clone()
+        };
+
+        let scratch = runtime_dir().join(format!(
+            "sandbox-{}-{}",
+            std::process::id(),
+            NEXT_RUN.fetch_add(1, Ordering::Relaxed)
+        ));
+        let size = tree_size(&sandbox.workdir).map_err(|e| TerminalError::Sandbox(e.to_string()))?;
+        if size > MAX_WORKDIR_BYTES {
+            return Err(TerminalError::Sandbox(format!(
+                "{} is too large to try commands in ({} MiB, limit {} MiB)",
+                sandbox.workdir.display(),
+                size / (1024 * 1024),
+                MAX_WORKDIR_BYTES / (1024 * 1024)
+            )));
+        }
+        // From here on the scratch copy is cleaned up by SandboxRun's Drop
+        let mut run = SandboxRun {
+            workdir: sandbox.workdir.clone(),
+            scratch,
+            output: String::new(),
+            exit: ExitInfo {
+                code: 1,
+                success: false,
+            },
+            timed_out: false,
+            changes: Vec::new(),
+        };
+        copy_tree(&run.workdir, &run.scratch).map_err(|e| TerminalError::Sandbox(e.to_string()))?;
+
+        let mut child = tokio::process::Command::new(bwrap)
+            .args(sandbox.args(&run.scratch, cmdline))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| TerminalError::Sandbox(format!("bwrap: {e}")))?;
+        let (stdout, stderr) = (child.stdout.take(), child.stderr.take());
+        let waited = tokio::time::timeout(sandbox.timeout, async {
+            let (mut out, err) = tokio::join!(read_capped(stdout), read_capped(stderr));
+            out.extend_from_slice(&err);
+            (out, child.wait().await)
+        })
+        .await;
+
+        match waited {
+            Ok((output, status)) => {
+                let status = status.map_err(|e| TerminalError::Sandbox(e.to_string()))?;
+                run.output = String::from_utf8_lossy(&output).into_owned();
+                run.exit = ExitInfo {
+                    code: status.code().unwrap_or(1) as u32,
+                    success: status.success(),
+                };
+            }
+            Err(_) => {
+                let _ = child.kill().await;
+                run.timed_out = true;
+                run.output = format!("Timed out after {}s", sandbox.timeout.as_secs());
+            }
+        }
+        run.changes = compare(&run.workdir, &run.scratch).map_err(|e| TerminalError::Sandbox(e.to_string()))?;
+        Ok(run)
+    }
+}
+
+/// Everything from `pipe`, keeping the first `MAX_OUTPUT_BYTES`. Reading on
+/// past the limit keeps a chatty command from blocking on a full pipe.
+async fn read_capped(pipe: Option<impl tokio::io::AsyncRead + Unpin>) -> Vec<u8> {
+    let mut kept = Vec::new();
+    let Some(mut pipe) = pipe else { return kept };
+    let mut buf = [0u8; 8192];
+    while let Ok(n) = pipe.read(&mut buf).await {
+        if n == 0 {
+            break;
+        }
+        let room = MAX_OUTPUT_BYTES.saturating_sub(kept.len());
+        kept.extend_from_slice(&buf[..n.min(room)]);
+    }
+    kept
+}
+
+fn bwrap_path() -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join("bwrap"))
+        .find(|candidate| candidate.is_file())
+}
+
+/// What happened to one file in the working directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Modified,
+    Removed,
+}
+
+/// A changed file, relative to the working directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileChange {
+    pub path: PathBuf,
+    pub kind: ChangeKind,
+}
+
+/// The result of a sandboxed command, holding its file changes until they
+/// are applied or dropped.
+#[derive(Debug)]
+pub struct SandboxRun {
+    workdir: PathBuf,
+    scratch: PathBuf,
+    /// stdout followed by stderr.
+    pub output: String,
+    pub exit: ExitInfo,
+    pub timed_out: bool,
+    pub changes: Vec<FileChange>,
+}
+
+impl SandboxRun {
+    pub fn workdir(&self) -> &Path {
+        &self.workdir
+    }
+
+    /// Unified diff of every change. Binary files and symlinks get a one-line
+    /// note instead.
+    pub fn diff(&self) -> String {
+        let mut out = String::new();
+        for change in &self.changes {
+            let old = self.workdir.join(&change.path);
+            let new = self.scratch.join(&change.path);
+            let (old, new) = match change.kind {
+                ChangeKind::Added => (None, Some(new)),
+                ChangeKind::Removed => (Some(old), None),
+                ChangeKind::Modified => (Some(old), Some(new)),
+            };
+            let read = |path: &Option<PathBuf>| match path {
+                Some(path) if !is_symlink(path) => fs::read(path).ok().and_then(|b| String::from_utf8(b).ok()),
+                Some(_) => None,
+                None => Some(String::new()),
+            };
+            let name = change.path.display();
+            match (read(&old), read(&new)) {
+                (Some(before), Some(after)) => {
+                    let a = if old.is_some() { format!("a/{name}") } else { "/dev/null".to_string() };
+                    let b = if new.is_some() { format!("b/{name}") } else { "/dev/null".to_string() };
+                    let diff = TextDiff::from_lines(&before, &after);
+                    out.push_str(&diff.unified_diff().header(&a, &b).to_string());
+                }
+                _ => out.push_str(&format!("Binary file {name} {}\n", change.kind.verb())),
+            }
+        }
+        out
+    }
+
+    /// Carry the changes over to the real working directory. Returns how
+    /// many files were written or removed.
+    pub fn apply(&self) -> io::Result<usize> {
+        // Removals first, so a file can replace a directory and vice versa
+        let (removed, written): (Vec<_>, Vec<_>) = self.changes.iter().partition(|c| c.kind == ChangeKind::Removed);
+        for change in removed {
+            remove_entry(&self.workdir.join(&change.path))?;
+        }
+        for change in written {
+            let target = self.workdir.join(&change.path);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            remove_entry(&target)?;
+            copy_entry(&self.scratch.join(&change.path), &target)?;
+        }
+        // Directories the command removed, deepest first; kept if not empty
+        let before = entries(&self.workdir)?;
+        for (path, entry) in before.iter().rev() {
+            if *entry == Entry::Dir && fs::symlink_metadata(self.scratch.join(path)).is_err() {
+                let _ = fs::remove_dir(self.workdir.join(path));
+            }
+        }
+        Ok(self.changes.len())
+    }
+}
+
+impl Drop for SandboxRun {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.scratch);
+    }
+}
+
+impl ChangeKind {
+    fn verb(self) -> &'static str {
+        match self {
+            ChangeKind::Added => "added",
+            ChangeKind::Modified => "changed",
+            ChangeKind::Removed => "removed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Entry {
+    Dir,
+    File,
+    Link(PathBuf),
+}
+
+fn is_symlink(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink())
+}
+
+/// Every directory, file and symlink under `root`, by relative path.
+/// Sockets, FIFOs and devices are left out.
+fn entries(root: &Path) -> io::Result<BTreeMap<PathBuf, Entry>> {
+    fn walk(root: &Path, rel: &Path, out: &mut BTreeMap<PathBuf, Entry>) -> io::Result<()> {
+        for item in fs::read_dir(root.join(rel))? {
+            let item = item?;
+            let path = rel.join(item.file_name());
+            let kind = item.file_type()?;
+            if kind.is_symlink() {
+                out.insert(path, Entry::Link(fs::read_link(item.path())?));
+            } else if kind.is_dir() {
+                out.insert(path.clone(), Entry::Dir);
+                walk(root, &path, out)?;
+            } else if kind.is_file() {
+                out.insert(path, Entry::File);
+            }
+        }
+        Ok(())
+    }
+    let mut out = BTreeMap::new();
+    walk(root, Path::new(""), &mut out)?;
+    Ok(out)
+}
+
+fn tree_size(root: &Path) -> io::Result<u64> {
+    let mut total = 0;
+    for (path, entry) in entries(root)? {
+        if entry == Entry::File {
+            total += fs::metadata(root.join(path))?.len();
+        }
+    }
+    Ok(total)
+}
+
+fn copy_entry(from: &Path, to: &Path) -> io::Result<()> {
+    if is_symlink(from) {
+        std::os::unix::fs::symlink(fs::read_link(from)?, to)
+    } else {
+        fs::copy(from, to).map(|_| ())
+    }
+}
+
+fn remove_entry(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Copy `from` to a new directory `to`, keeping symlinks as symlinks.
+fn copy_tree(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for (path, entry) in entries(from)? {
+        match entry {
+            Entry::Dir => fs::create_dir_all(to.join(&path))?,
+            _ => copy_entry(&from.join(&path), &to.join(&path))?,
+        }
+    }
+    Ok(())
+}
+
+/// Files and symlinks that differ between `original` and `copy`.
+fn compare(original: &Path, copy: &Path) -> io::Result<Vec<FileChange>> {
+    let before = entries(original)?;
+    let after = entries(copy)?;
+    let mut changes = Vec::new();
+    for (path, entry) in &after {
+        let kind = match (before.get(path), entry) {
+            (_, Entry::Dir) => continue,
+            (None | Some(Entry::Dir), _) => ChangeKind::Added,
+            (Some(old), new) if old != new => ChangeKind::Modified,
+            (Some(Entry::File), Entry::File) => {
+                let (old, new) = (original.join(path), copy.join(path));
+                let same = fs::metadata(&old)?.len() == fs::metadata(&new)?.len() && fs::read(&old)? == fs::read(&new)?;
+                if same {
+                    continue;
+                }
+                ChangeKind::Modified
+            }
+            _ => continue,
+        };
+        changes.push(FileChange { path: path.clone(), kind });
+    }
+    for (path, entry) in &before {
+        let gone = match after.get(path) {
+            None => true,
+            // A file replaced by a directory: the new files inside were added
+            Some(Entry::Dir) => *entry != Entry::Dir,
+            Some(_) => false,
+        };
+        if gone && *entry != Entry::Dir {
+            changes.push(FileChange {
+                path: path.clone(),
+                kind: ChangeKind::Removed,
+            });
+        }
+    }
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("kael-sandbox-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_bwrap_args() {
+        let sandbox = Sandbox::new("/work/project");
+        let args: Vec<String> = sandbox
+            .args(Path::new("/run/scratch"), "rm -rf build")
+            .into_iter()
+            .map(|a| a.into_string().unwrap())
+            .collect();
+        let joined = args.join(" ");
+        assert!(joined.starts_with("--ro-bind / / "));
+        assert!(joined.contains("--bind /run/scratch /work/project --unshare-all --cap-drop ALL"));
+        assert!(joined.ends_with("--chdir /work/project /bin/sh -c rm -rf build"));
+        assert!(!args.contains(&"--share-net".to_string()));
+        // The writable copy is mounted after the tmpfs mounts that could hide it
+        let bind = args.iter().position(|a| a == "--bind").unwrap();
+        assert!(args.iter().rposition(|a| a == "--tmpfs").unwrap() < bind);
+
+        let args = Sandbox::new("/w").with_network().args(Path::new("/s"), "true");
+        assert!(args.iter().any(|a| a == "--share-net"));
+    }
+
+    #[test]
+    fn test_changes_diff_and_apply() {
+        let work = temp_dir("work");
+        fs::write(work.join("keep.txt"), "same\n").unwrap();
+        fs::write(work.join("edit.txt"), "one\ntwo\n").unwrap();
+        fs::create_dir_all(work.join("old")).unwrap();
+        fs::write(work.join("old/gone.txt"), "bye\n").unwrap();
+
+        let scratch = temp_dir("scratch");
+        fs::remove_dir_all(&scratch).unwrap();
+        copy_tree(&work, &scratch).unwrap();
+        // What the sandboxed command did to its copy
+        fs::write(scratch.join("edit.txt"), "one\n2\n").unwrap();
+        fs::remove_dir_all(scratch.join("old")).unwrap();
+        fs::create_dir_all(scratch.join("new")).unwrap();
+        fs::write(scratch.join("new/blob.bin"), [0xff, 0x00, 0xfe]).unwrap();
+
+        let run = SandboxRun {
+            workdir: work.clone(),
+            scratch: scratch.clone(),
+            output: String::new(),
+            exit: ExitInfo { code: 0, success: true },
+            timed_out: false,
+            changes: compare(&work, &scratch).unwrap(),
+        };
+        let summary: Vec<(String, ChangeKind)> = run
+            .changes
+            .iter()
+            .map(|c| (c.path.display().to_string(), c.kind))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("edit.txt".to_string(), ChangeKind::Modified),
+                ("new/blob.bin".to_string(), ChangeKind::Added),
+                ("old/gone.txt".to_string(), ChangeKind::Removed),
+            ]
+        );
+
+        let diff = run.diff();
+        assert!(diff.contains("--- a/edit.txt\n+++ b/edit.txt\n@@ -1,2 +1,2 @@\n one\n-two\n+2\n"), "{diff}");
+        assert!(diff.contains("Binary file new/blob.bin added"));
+        assert!(diff.contains("--- a/old/gone.txt\n+++ /dev/null\n"));
+
+        // Nothing touched the real directory until now
+        assert_eq!(fs::read_to_string(work.join("edit.txt")).unwrap(), "one\ntwo\n");
+        assert_eq!(run.apply().unwrap(), 3);
+        assert_eq!(fs::read_to_string(work.join("edit.txt")).unwrap(), "one\n2\n");
+        assert_eq!(fs::read(work.join("new/blob.bin")).unwrap(), [0xff, 0x00, 0xfe]);
+        assert!(!work.join("old").exists());
+        assert!(compare(&work, &scratch).unwrap().is_empty());
+
+        drop(run);
+        assert!(!scratch.exists());
+        fs::remove_dir_all(&work).unwrap();
+    }
+}
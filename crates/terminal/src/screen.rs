@@ -0,0 +1,992 @@
+//! Terminal state model: a VT100/xterm parser feeding a grid of cells.
+//!
+//! `Screen::process` takes raw PTY output. Printable text lands in the grid
+//! with the current attributes, control sequences move the cursor, erase,
+//! scroll or switch to the alternate screen. Lines scrolled off the top of
+//! the primary screen go to the scrollback. The UI renders from `rows`,
+//! `scrollback` and `cursor`.
+use unicode_width::UnicodeWidthChar;
+use vte::{Params, Parser, Perform};
+
+use crate::scrollback::{LineId, Scrollback};
+
+/// Lines kept above the visible screen.
+pub const DEFAULT_SCROLLBACK: usize = 10_000;
+
+const TAB_WIDTH: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Color {
+    #[default]
+    Default,
+    /// 256-colour palette index; 0-7 normal, 8-15 bright.
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Attrs {
+    pub fg: Color,
+    pub bg: Color,
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub inverse: bool,
+    pub strikethrough: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub c: char,
+    pub attrs: Attrs,
+    /// 1 for normal characters, 2 for wide (CJK, emoji), 0 for the cell
+    /// covered by the right half of a wide character.
+    pub width: u8,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self {
+            c: ' ',
+            attrs: Attrs::default(),
+            width: 1,
+        }
+    }
+}
+
+impl Cell {
+    fn blank(attrs: Attrs) -> Self {
+        // Erased cells keep the background colour only
+        Self {
+            c: ' ',
+            attrs: Attrs {
+                bg: attrs.bg,
+                ..Attrs::default()
+            },
+            width: 1,
+        }
+    }
+}
+
+pub type Row = Vec<Cell>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cursor {
+    pub row: usize,
+    pub col: usize,
+    pub visible: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SavedCursor {
+    row: usize,
+    col: usize,
+    attrs: Attrs,
+    line_drawing: bool,
+}
+
+struct State {
+    rows: usize,
+    cols: usize,
+    grid: Vec<Row>,
+    /// Primary screen while the alternate screen is active.
+    saved_primary: Option<Vec<Row>>,
+    scrollback: Scrollback,
+    cursor: Cursor,
+    saved_cursor: Option<SavedCursor>,
+    attrs: Attrs,
+    /// Scroll region, inclusive.
+    top: usize,
+    bottom: usize,
+    /// Cursor sits past the last column; the next printable wraps first.
+    pending_wrap: bool,
+    autowrap: bool,
+    insert_mode: bool,
+    app_cursor_keys: bool,
+    bracketed_paste: bool,
+    /// G0 designated as DEC special graphics (box drawing).
+    line_drawing: bool,
+    title: String,
+    responses: Vec<u8>,
+}
+
+/// A terminal emulator screen. Feed it PTY output with `process`.
+pub struct Screen {
+    parser: Parser,
+    state: State,
+}
+
+impl Screen {
+    pub fn new(rows: u16, cols: u16) -> Self {
+        Self::with_scrollback(rows, cols, DEFAULT_SCROLLBACK)
+    }
+
+    pub fn with_scrollback(rows: u16, cols: u16, scrollback_limit: usize) -> Self {
+        let rows = rows.max(1) as usize;
+        let cols = cols.max(1) as usize;
+        Self {
+            parser: Parser::new(),
+            state: State {
+                rows,
+                cols,
+                grid: vec![vec![Cell::default(); cols]; rows],
+                saved_primary: None,
+                scrollback: Scrollback::new(scrollback_limit),
+                cursor: Cursor {
+                    row: 0,
+                    col: 0,
+                    visible: true,
+                },
+                saved_cursor: None,
+                attrs: Attrs::default(),
+                top: 0,
+                bottom: rows - 1,
+                pending_wrap: false,
+                autowrap: true,
+                insert_mode: false,
+                app_cursor_keys: false,
+                bracketed_paste: false,
+                line_drawing: false,
+                title: String::new(),
+                responses: Vec::new(),
+            },
+        }
+    }
+
+    /// Feed raw output from the PTY.
+    pub fn process(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.parser.advance(&mut self.state, byte);
+        }
+    }
+
+    /// Change the screen size. Lines pushed off the top go to the scrollback.
+    pub fn resize(&mut self, rows: u16, cols: u16) {
+        self.state.resize(rows.max(1) as usize, cols.max(1) as usize);
+    }
+
+    /// (rows, cols)
+    pub fn size(&self) -> (usize, usize) {
+        (self.state.rows, self.state.cols)
+    }
+
+    pub fn cursor(&self) -> Cursor {
+        self.state.cursor
+    }
+
+    /// Visible rows, top to bottom.
+    pub fn rows(&self) -> &[Row] {
+        &self.state.grid
+    }
+
+    pub fn cell(&self, row: usize, col: usize) -> Option<&Cell> {
+        self.state.grid.get(row).and_then(|r| r.get(col))
+    }
+
+    /// Lines that scrolled off the top, oldest first.
+    pub fn scrollback(&self) -> &Scrollback {
+        &self.state.scrollback
+    }
+
+    pub fn clear_scrollback(&mut self) {
+        self.state.scrollback.clear();
+    }
+
+    /// Keep at most `limit` scrollback lines from now on.
+    pub fn set_scrollback_limit(&mut self, limit: usize) {
+        self.state.scrollback.set_limit(limit);
+    }
+
+    /// A scrollback line or visible row by id; `None` once it has left the
+    /// scrollback or below the last row.
+    pub fn line(&self, id: LineId) -> Option<&Row> {
+        let scrollback = &self.state.scrollback;
+        if id < scrollback.first_line() {
+            return None;
+        }
+        match id.checked_sub(scrollback.end_line()) {
+            Some(row) => self.state.grid.get(row as usize),
+            None => scrollback.get((id - scrollback.first_line()) as usize),
+        }
+    }
+
+    /// Text of a line by id, without trailing blanks.
+    pub fn line_text(&self, id: LineId) -> String {
+        self.line(id).map(|r| row_to_string(r)).unwrap_or_default()
+    }
+
+    pub fn is_alternate_screen(&self) -> bool {
+        self.state.saved_primary.is_some()
+    }
+
+    /// Window title set with OSC 0/2.
+    pub fn title(&self) -> &str {
+        &self.state.title
+    }
+
+    /// Cursor keys should send SS3 (`ESC O A`) rather than CSI sequences.
+    pub fn app_cursor_keys(&self) -> bool {
+        self.state.app_cursor_keys
+    }
+
+    pub fn bracketed_paste(&self) -> bool {
+        self.state.bracketed_paste
+    }
+
+    /// Replies the terminal owes the program (cursor position reports,
+    /// device attributes). Write them back to the PTY.
+    pub fn take_responses(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.state.responses)
+    }
+
+    /// Text of one visible row without trailing blanks.
+    pub fn row_text(&self, row: usize) -> String {
+        self.state.grid.get(row).map(|r| row_to_string(r)).unwrap_or_default()
+    }
+
+    /// Visible screen as text, one line per row, trailing blank lines removed.
+    pub fn contents(&self) -> String {
+        let lines: Vec<String> = self.state.grid.iter().map(|r| row_to_string(r)).collect();
+        let end = lines.iter().rposition(|l| !l.is_empty()).map_or(0, |i| i + 1);
+        lines[..end].join("\n")
+    }
+}
+
+/// Text of a row with trailing blanks trimmed.
+pub fn row_to_string(row: &[Cell]) -> String {
+    let text: String = row.iter().filter(|c| c.width > 0).map(|c| c.c).collect();
+    text.trim_end().to_string()
+}
+
+/// Split a row into runs of identical attributes, for rendering.
+pub fn row_runs(row: &[Cell]) -> Vec<(String, Attrs)> {
+    let mut runs: Vec<(String, Attrs)> = Vec::new();
+    for cell in row.iter().filter(|c| c.width > 0) {
+        match runs.last_mut() {
+            Some((text, attrs)) if *attrs == cell.attrs => text.push(cell.c),
+            _ => runs.push((cell.c.to_string(), cell.attrs)),
+        }
+    }
+    // Drop trailing default-styled blanks
+    if let Some((text, attrs)) = runs.last_mut() {
+        if attrs.bg == Color::Default && !attrs.inverse {
+            let trimmed = text.trim_end().len();
+            text.truncate(trimmed);
+        }
+    }
+    if runs.last().is_some_and(|(text, _)| text.is_empty()) {
+        runs.pop();
+    }
+    runs
+}
+
+/// DEC special graphics: the characters `ESC ( 0` maps to box drawing.
+fn dec_line_drawing(c: char) -> char {
+    match c {
+        '`' => '◆',
+        'a' => '▒',
+        'f' => '°',
+        'g' => '±',
+        'j' => '┘',
+        'k' => '┐',
+        'l' => '┌',
+        'm' => '└',
+        'n' => '┼',
+        'o' => '⎺',
+        'p' => '⎻',
+        'q' => '─',
+        'r' => '⎼',
+        's' => '⎽',
+        't' => '├',
+        'u' => '┤',
+        'v' => '┴',
+        'w' => '┬',
+        'x' => '│',
+        'y' => '≤',
+        'z' => '≥',
+        '{' => 'π',
+        '|' => '≠',
+        '}' => '£',
+        '~' => '·',
+        other => other,
+    }
+}
+
+impl State {
+    fn blank_row(&self) -> Row {
+        vec![Cell::blank(self.attrs); self.cols]
+    }
+
+    fn push_scrollback(&mut self, row: Row) {
+        self.scrollback.push(row);
+    }
+
+    /// Scroll the region up by `n`, filling blank lines at the bottom.
+    fn scroll_up(&mut self, n: usize) {
+        let n = n.min(self.bottom - self.top + 1);
+        for _ in 0..n {
+            let row = self.grid.remove(self.top);
+            // Only a full-screen scroll of the primary screen feeds the scrollback
+            if self.top == 0 && self.bottom == self.rows - 1 && self.saved_primary.is_none() {
+                self.push_scrollback(row);
+            }
+            let blank = self.blank_row();
+            self.grid.insert(self.bottom, blank);
+        }
+    }
+
+    /// Scroll the region down by `n`, filling blank lines at the top.
+    fn scroll_down(&mut self, n: usize) {
+        let n = n.min(self.bottom - self.top + 1);
+        for _ in 0..n {
+            self.grid.remove(self.bottom);
+            let blank = self.blank_row();
+            self.grid.insert(self.top, blank);
+        }
+    }
+
+    fn linefeed(&mut self) {
+        self.pending_wrap = false;
+        if self.cursor.row == self.bottom {
+            self.scroll_up(1);
+        } else if self.cursor.row + 1 < self.rows {
+            self.cursor.row += 1;
+        }
+    }
+
+    fn reverse_index(&mut self) {
+        self.pending_wrap = false;
+        if self.cursor.row == self.top {
+            self.scroll_down(1);
+        } else if self.cursor.row > 0 {
+            self.cursor.row -= 1;
+        }
+    }
+
+    fn move_to(&mut self, row: usize, col: usize) {
+        self.cursor.row = row.min(self.rows - 1);
+        self.cursor.col = col.min(self.cols - 1);
+        self.pending_wrap = false;
+    }
+
+    fn print_char(&mut self, c: char) {
+        let c = if self.line_drawing { dec_line_drawing(c) } else { c };
+        let width = match c.width() {
+            Some(0) | None => return, // combining marks and controls are not rendered
+            Some(w) => w.min(2),
+        };
+        // A one-column screen has no room for a wide character: show a blank
+        let (c, width) = if width == 2 && self.cols < 2 { (' ', 1) } else { (c, width) };
+
+        if self.pending_wrap {
+            if self.autowrap {
+                self.cursor.col = 0;
+                self.linefeed();
+            }
+            self.pending_wrap = false;
+        }
+        // A wide character does not fit in the last column
+        if width == 2 && self.cursor.col + 1 >= self.cols {
+            if !self.autowrap {
+                return;
+            }
+            self.cursor.col = 0;
+            self.linefeed();
+        }
+
+        let (row, col) = (self.cursor.row, self.cursor.col);
+        if self.insert_mode {
+            let line = &mut self.grid[row];
+            for _ in 0..width {
+                line.pop();
+                line.insert(col, Cell::blank(self.attrs));
+            }
+        }
+
+        let attrs = self.attrs;
+        let line = &mut self.grid[row];
+        // Overwriting half of a wide character blanks the other half
+        if line[col].width == 0 && col > 0 {
+            line[col - 1] = Cell::blank(attrs);
+        }
+        line[col] = Cell { c, attrs, width: width as u8 };
+        if width == 2 {
+            line[col + 1] = Cell { c: ' ', attrs, width: 0 };
+        }
+
+        if col + width >= self.cols {
+            self.cursor.col = self.cols - 1;
+            self.pending_wrap = true;
+        } else {
+            self.cursor.col = col + width;
+        }
+    }
+
+    /// Erase cells `[from, to)` of a row.
+    fn erase_cells(&mut self, row: usize, from: usize, to: usize) {
+        let blank = Cell::blank(self.attrs);
+        let to = to.min(self.cols);
+        for cell in &mut self.grid[row][from.min(to)..to] {
+            *cell = blank;
+        }
+    }
+
+    fn erase_display(&mut self, mode: u16) {
+        let (row, col) = (self.cursor.row, self.cursor.col);
+        match mode {
+            0 => {
+                self.erase_cells(row, col, self.cols);
+                for r in row + 1..self.rows {
+                    self.erase_cells(r, 0, self.cols);
+                }
+            }
+            1 => {
+                for r in 0..row {
+                    self.erase_cells(r, 0, self.cols);
+                }
+                self.erase_cells(row, 0, col + 1);
+            }
+            2 => {
+                for r in 0..self.rows {
+                    self.erase_cells(r, 0, self.cols);
+                }
+            }
+            3 => self.scrollback.clear(),
+            _ => {}
+        }
+    }
+
+    fn erase_line(&mut self, mode: u16) {
+        let (row, col) = (self.cursor.row, self.cursor.col);
+        match mode {
+            0 => self.erase_cells(row, col, self.cols),
+            1 => self.erase_cells(row, 0, col + 1),
+            2 => self.erase_cells(row, 0, self.cols),
+            _ => {}
+        }
+    }
+
+    fn insert_lines(&mut self, n: usize) {
+        let row = self.cursor.row;
+        if row < self.top || row > self.bottom {
+            return;
+        }
+        for _ in 0..n.min(self.bottom - row + 1) {
+            self.grid.remove(self.bottom);
+            let blank = self.blank_row();
+            self.grid.insert(row, blank);
+        }
+        self.cursor.col = 0;
+    }
+
+    fn delete_lines(&mut self, n: usize) {
+        let row = self.cursor.row;
+        if row < self.top || row > self.bottom {
+            return;
+        }
+        for _ in 0..n.min(self.bottom - row + 1) {
+            self.grid.remove(row);
+            let blank = self.blank_row();
+            self.grid.insert(self.bottom, blank);
+        }
+        self.cursor.col = 0;
+    }
+
+    fn insert_chars(&mut self, n: usize) {
+        let (row, col) = (self.cursor.row, self.cursor.col);
+        let blank = Cell::blank(self.attrs);
+        let line = &mut self.grid[row];
+        for _ in 0..n.min(self.cols - col) {
+            line.pop();
+            line.insert(col, blank);
+        }
+    }
+
+    fn delete_chars(&mut self, n: usize) {
+        let (row, col) = (self.cursor.row, self.cursor.col);
+        let blank = Cell::blank(self.attrs);
+        let line = &mut self.grid[row];
+        for _ in 0..n.min(self.cols - col) {
+            line.remove(col);
+            line.push(blank);
+        }
+    }
+
+    fn save_cursor(&mut self) {
+        self.saved_cursor = Some(SavedCursor {
+            row: self.cursor.row,
+            col: self.cursor.col,
+            attrs: self.attrs,
+            line_drawing: self.line_drawing,
+        });
+    }
+
+    fn restore_cursor(&mut self) {
+        if let Some(saved) = self.saved_cursor {
+            self.move_to(saved.row, saved.col);
+            self.attrs = saved.attrs;
+            self.line_drawing = saved.line_drawing;
+        } else {
+            self.move_to(0, 0);
+        }
+    }
+
+    fn enter_alternate_screen(&mut self) {
+        if self.saved_primary.is_none() {
+            let blank = vec![vec![Cell::default(); self.cols]; self.rows];
+            self.saved_primary = Some(std::mem::replace(&mut self.grid, blank));
+        }
+    }
+
+    fn leave_alternate_screen(&mut self) {
+        if let Some(primary) = self.saved_primary.take() {
+            self.grid = primary;
+        }
+    }
+
+    fn resize(&mut self, rows: usize, cols: usize) {
+        // Keep the cursor on screen by scrolling lines into the scrollback
+        if rows < self.rows && self.cursor.row >= rows {
+            let excess = self.cursor.row + 1 - rows;
+            for _ in 0..excess {
+                let row = self.grid.remove(0);
+                if self.saved_primary.is_none() {
+                    self.push_scrollback(row);
+                }
+            }
+            self.cursor.row -= excess;
+        }
+
+        for grid in std::iter::once(&mut self.grid).chain(self.saved_primary.iter_mut()) {
+            grid.truncate(rows);
+            for line in grid.iter_mut() {
+                line.resize(cols, Cell::default());
+            }
+            while grid.len() < rows {
+                grid.push(vec![Cell::default(); cols]);
+            }
+        }
+
+        self.rows = rows;
+        self.cols = cols;
+        self.top = 0;
+        self.bottom = rows - 1;
+        self.cursor.row = self.cursor.row.min(rows - 1);
+        self.cursor.col = self.cursor.col.min(cols - 1);
+        self.pending_wrap = false;
+    }
+
+    fn reset(&mut self) {
+        let (rows, cols) = (self.rows, self.cols);
+        let scrollback = std::mem::replace(&mut self.scrollback, Scrollback::new(0));
+        *self = Screen::with_scrollback(rows as u16, cols as u16, 0).state;
+        self.scrollback = scrollback;
+    }
+
+    fn set_private_mode(&mut self, mode: u16, on: bool) {
+        match mode {
+            1 => self.app_cursor_keys = on,
+            7 => self.autowrap = on,
+            25 => self.cursor.visible = on,
+            47 | 1047 => {
+                if on {
+                    self.enter_alternate_screen();
+                } else {
+                    self.leave_alternate_screen();
+                }
+            }
+            1049 => {
+                if on {
+                    self.save_cursor();
+                    self.enter_alternate_screen();
+                    self.move_to(0, 0);
+                } else {
+                    self.leave_alternate_screen();
+                    self.restore_cursor();
+                }
+            }
+            2004 => self.bracketed_paste = on,
+            _ => {}
+        }
+    }
+
+    fn sgr(&mut self, params: &Params) {
+        if params.is_empty() {
+            self.attrs = Attrs::default();
+            return;
+        }
+
+        let mut iter = params.iter();
+        while let Some(p) = iter.next() {
+            match p[0] {
+                0 => self.attrs = Attrs::default(),
+                1 => self.attrs.bold = true,
+                2 => self.attrs.dim = true,
+                3 => self.attrs.italic = true,
+                4 => self.attrs.underline = true,
+                7 => self.attrs.inverse = true,
+                9 => self.attrs.strikethrough = true,
+                22 => {
+                    self.attrs.bold = false;
+                    self.attrs.dim = false;
+                }
+                23 => self.attrs.italic = false,
+                24 => self.attrs.underline = false,
+                27 => self.attrs.inverse = false,
+                29 => self.attrs.strikethrough = false,
+                n @ 30..=37 => self.attrs.fg = Color::Indexed((n - 30) as u8),
+                39 => self.attrs.fg = Color::Default,
+                n @ 40..=47 => self.attrs.bg = Color::Indexed((n - 40) as u8),
+                49 => self.attrs.bg = Color::Default,
+                n @ 90..=97 => self.attrs.fg = Color::Indexed((n - 90 + 8) as u8),
+                n @ 100..=107 => self.attrs.bg = Color::Indexed((n - 100 + 8) as u8),
+                n @ (38 | 48) => {
+                    let color = if p.len() > 1 {
+                        // Colon form: 38:5:n, 38:2:r:g:b or 38:2:<colour space>:r:g:b
+                        match p[1] {
+                            5 => p.get(2).map(|&v| Color::Indexed(v as u8)),
+                            2 => {
+                                let rgb = if p.len() >= 6 { &p[3..6] } else { &p[2..] };
+                                (rgb.len() == 3).then(|| Color::Rgb(rgb[0] as u8, rgb[1] as u8, rgb[2] as u8))
+                            }
+                            _ => None,
+                        }
+                    } else {
+                        // Semicolon form: 38;5;n or 38;2;r;g;b
+                        match iter.next().map(|p| p[0]) {
+                            Some(5) => iter.next().map(|p| Color::Indexed(p[0] as u8)),
+                            Some(2) => match (iter.next(), iter.next(), iter.next()) {
+                                (Some(r), Some(g), Some(b)) => Some(Color::Rgb(r[0] as u8, g[0] as u8, b[0] as u8)),
+                                _ => None,
+                            },
+                            _ => None,
+                        }
+                    };
+                    if let Some(color) = color {
+                        if n == 38 {
+                            self.attrs.fg = color;
+                        } else {
+                            self.attrs.bg = color;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// First parameter, with 0 and missing treated as `default`.
+fn param(params: &Params, index: usize, default: u16) -> u16 {
+    match params.iter().nth(index).and_then(|p| p.first()).copied() {
+        None | Some(0) => default,
+        Some(v) => v,
+    }
+}
+
+impl Perform for State {
+    fn print(&mut self, c: char) {
+        self.print_char(c);
+    }
+
+    fn execute(&mut self, byte: u8) {
+        match byte {
+            b'\n' | 0x0b | 0x0c => self.linefeed(),
+            b'\r' => {
+                self.cursor.col = 0;
+                self.pending_wrap = false;
+            }
+            0x08 => {
+                self.cursor.col = self.cursor.col.saturating_sub(1);
+                self.pending_wrap = false;
+            }
+            b'\t' => {
+                let next = (self.cursor.col / TAB_WIDTH + 1) * TAB_WIDTH;
+                self.cursor.col = next.min(self.cols - 1);
+            }
+            0x0e => self.line_drawing = true,  // SO: shift to G1 (treated as graphics)
+            0x0f => self.line_drawing = false, // SI: back to G0
+            _ => {}
+        }
+    }
+
+    fn osc_dispatch(&mut self, params: &[&[u8]], _bell_terminated: bool) {
+        match params.first() {
+            Some(&b"0") | Some(&b"2") => {
+                if let Some(title) = params.get(1) {
+                    self.title = String::from_utf8_lossy(title).to_string();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], ignore: bool, action: char) {
+        if ignore {
+            return;
+        }
+        let private = intermediates.first() == Some(&b'?');
+        let n = param(params, 0, 1) as usize;
+        let (row, col) = (self.cursor.row, self.cursor.col);
+
+        match (action, private) {
+            ('h', true) | ('l', true) => {
+                for p in params.iter() {
+                    self.set_private_mode(p[0], action == 'h');
+                }
+            }
+            ('h', false) | ('l', false) if params.iter().any(|p| p[0] == 4) => {
+                self.insert_mode = action == 'h';
+            }
+            (_, true) => {}
+            ('@', _) => self.insert_chars(n),
+            ('A', _) => {
+                let stop = if row >= self.top { self.top } else { 0 };
+                self.move_to(row.saturating_sub(n).max(stop), col);
+            }
+            ('B', _) | ('e', _) => {
+                let stop = if row <= self.bottom { self.bottom } else { self.rows - 1 };
+                self.move_to((row + n).min(stop), col);
+            }
+            ('C', _) | ('a', _) => self.move_to(row, col + n),
+            ('D', _) => self.move_to(row, col.saturating_sub(n)),
+            ('E', _) => self.move_to(row + n, 0),
+            ('F', _) => self.move_to(row.saturating_sub(n), 0),
+            ('G', _) | ('`', _) => self.move_to(row, n - 1),
+            ('H', _) | ('f', _) => {
+                let r = param(params, 0, 1) as usize;
+                let c = param(params, 1, 1) as usize;
+                self.move_to(r - 1, c - 1);
+            }
+            ('J', _) => self.erase_display(params.iter().next().map_or(0, |p| p[0])),
+            ('K', _) => self.erase_line(params.iter().next().map_or(0, |p| p[0])),
+            ('L', _) => self.insert_lines(n),
+            ('M', _) => self.delete_lines(n),
+            ('P', _) => self.delete_chars(n),
+            ('S', _) => self.scroll_up(n),
+            ('T', _) => self.scroll_down(n),
+            ('X', _) => self.erase_cells(row, col, col + n),
+            ('d', _) => self.move_to(n - 1, col),
+            ('m', _) => self.sgr(params),
+            ('r', _) => {
+                let top = param(params, 0, 1) as usize - 1;
+                let bottom = (param(params, 1, self.rows as u16) as usize).min(self.rows) - 1;
+                if top < bottom {
+                    self.top = top;
+                    self.bottom = bottom;
+                    self.move_to(0, 0);
+                }
+            }
+            ('s', _) => self.save_cursor(),
+            ('u', _) => self.restore_cursor(),
+            ('n', _) => match param(params, 0, 0) {
+                5 => self.responses.extend_from_slice(b"\x1b[0n"),
+                6 => {
+                    let report = format!("\x1b[{};{}R", self.cursor.row + 1, self.cursor.col + 1);
+                    self.responses.extend_from_slice(report.as_bytes());
+                }
+                _ => {}
+            },
+            ('c', _) if intermediates.is_empty() => {
+                // Primary device attributes: VT102
+                self.responses.extend_from_slice(b"\x1b[?6c");
+            }
+            _ => {}
+        }
+    }
+
+    fn esc_dispatch(&mut self, intermediates: &[u8], _ignore: bool, byte: u8) {
+        match (intermediates.first(), byte) {
+            (None, b'7') => self.save_cursor(),
+            (None, b'8') => self.restore_cursor(),
+            (None, b'D') => self.linefeed(),
+            (None, b'E') => {
+                self.cursor.col = 0;
+                self.linefeed();
+            }
+            (None, b'M') => self.reverse_index(),
+            (None, b'c') => self.reset(),
+            (Some(b'('), b'0') => self.line_drawing = true,
+            (Some(b'('), _) => self.line_drawing = false,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn screen(rows: u16, cols: u16, input: &[u8]) -> Screen {
+        let mut s = Screen::new(rows, cols);
+        s.process(input);
+        s
+    }
+
+    #[test]
+    fn test_plain_text_and_newlines() {
+        let s = screen(5, 20, b"hello\r\nworld");
+        assert_eq!(s.contents(), "hello\nworld");
+        assert_eq!(s.cursor(), Cursor { row: 1, col: 5, visible: true });
+    }
+
+    #[test]
+    fn test_carriage_return_progress_bar() {
+        // paru/pacman redraw the same line with \r
+        let s = screen(3, 40, b"downloading [##  ] 50%\rdownloading [####] 100%\r\n");
+        assert_eq!(s.row_text(0), "downloading [####] 100%");
+        assert_eq!(s.cursor().row, 1);
+    }
+
+    #[test]
+    fn test_cursor_addressing_and_erase() {
+        let s = screen(4, 10, b"aaaaaaaaaa\r\nbbbbbbbbbb\x1b[1;4H\x1b[K\x1b[2;3HXY\x1b[1P");
+        assert_eq!(s.row_text(0), "aaa");
+        assert_eq!(s.row_text(1), "bbXYbbbbb");
+        assert_eq!(s.cursor(), Cursor { row: 1, col: 4, visible: true });
+
+        let s = screen(3, 5, b"12345\r\n67890\x1b[2J");
+        assert_eq!(s.contents(), "");
+    }
+
+    #[test]
+    fn test_autowrap_and_scrollback() {
+        let mut s = Screen::new(2, 4);
+        s.process(b"abcdefgh");
+        assert_eq!(s.contents(), "abcd\nefgh");
+        // The cursor waits at the margin until the next character
+        assert_eq!(s.cursor().col, 3);
+
+        s.process(b"\r\nnext");
+        assert_eq!(s.contents(), "efgh\nnext");
+        assert_eq!(s.scrollback().len(), 1);
+        assert_eq!(row_to_string(&s.scrollback()[0]), "abcd");
+    }
+
+    #[test]
+    fn test_sgr_attributes() {
+        let s = screen(2, 20, b"\x1b[1;31mred\x1b[0m \x1b[38;5;208mo\x1b[48;2;1;2;3mx\x1b[7;94mz");
+        let red = s.cell(0, 0).unwrap();
+        assert_eq!(red.c, 'r');
+        assert!(red.attrs.bold);
+        assert_eq!(red.attrs.fg, Color::Indexed(1));
+        assert_eq!(s.cell(0, 3).unwrap().attrs, Attrs::default());
+        assert_eq!(s.cell(0, 4).unwrap().attrs.fg, Color::Indexed(208));
+        let x = s.cell(0, 5).unwrap().attrs;
+        assert_eq!(x.bg, Color::Rgb(1, 2, 3));
+        assert_eq!(x.fg, Color::Indexed(208));
+        let z = s.cell(0, 6).unwrap().attrs;
+        assert!(z.inverse);
+        assert_eq!(z.fg, Color::Indexed(12));
+
+        let runs = row_runs(&s.rows()[0]);
+        assert_eq!(runs[0].0, "red");
+        assert_eq!(runs.len(), 5);
+    }
+
+    #[test]
+    fn test_colon_separated_truecolor() {
+        let s = screen(1, 5, b"\x1b[38:2::10:20:30mA");
+        assert_eq!(s.cell(0, 0).unwrap().attrs.fg, Color::Rgb(10, 20, 30));
+    }
+
+    #[test]
+    fn test_alternate_screen_restores_primary() {
+        // What less/htop do on start and exit
+        let mut s = screen(3, 10, b"$ less f\r\n");
+        s.process(b"\x1b[?1049h\x1b[H\x1b[2Jpage 1\x1b[3;1H:");
+        assert!(s.is_alternate_screen());
+        assert_eq!(s.contents(), "page 1\n\n:");
+
+        s.process(b"\x1b[?1049l");
+        assert!(!s.is_alternate_screen());
+        assert_eq!(s.contents(), "$ less f");
+        assert_eq!(s.cursor().row, 1);
+        assert!(s.scrollback().is_empty());
+    }
+
+    #[test]
+    fn test_scroll_region() {
+        // Status line at the bottom stays put while the region scrolls
+        let mut s = screen(4, 10, b"\x1b[4;1Hstatus\x1b[1;3r\x1b[1;1H");
+        s.process(b"one\r\ntwo\r\nthree\r\nfour");
+        assert_eq!(s.contents(), "two\nthree\nfour\nstatus");
+        // Region scrolling is not scrollback
+        assert!(s.scrollback().is_empty());
+
+        s.process(b"\x1b[1;1H\x1bM");
+        assert_eq!(s.contents(), "\ntwo\nthree\nstatus");
+    }
+
+    #[test]
+    fn test_insert_and_delete_lines() {
+        let mut s = screen(3, 5, b"a\r\nb\r\nc");
+        s.process(b"\x1b[2;1H\x1b[L");
+        assert_eq!(s.contents(), "a\n\nb");
+        s.process(b"\x1b[M");
+        assert_eq!(s.contents(), "a\nb");
+    }
+
+    #[test]
+    fn test_wide_characters() {
+        let s = screen(2, 4, "日本".as_bytes());
+        assert_eq!(s.row_text(0), "日本");
+        assert_eq!(s.cell(0, 1).unwrap().width, 0);
+
+        let s = screen(2, 3, "ab日".as_bytes());
+        assert_eq!(s.contents(), "ab\n日");
+
+        // Used to index past the end of the line
+        let s = screen(2, 1, "日a".as_bytes());
+        assert_eq!(s.cell(0, 0).unwrap().width, 1);
+        assert_eq!(s.row_text(1), "a");
+    }
+
+    #[test]
+    fn test_line_drawing_charset() {
+        let s = screen(1, 10, b"\x1b(0lqqk\x1b(B ok");
+        assert_eq!(s.row_text(0), "┌──┐ ok");
+    }
+
+    #[test]
+    fn test_device_status_report() {
+        let mut s = screen(5, 10, b"\x1b[3;4H\x1b[6n");
+        assert_eq!(s.take_responses(), b"\x1b[3;4R");
+        assert!(s.take_responses().is_empty());
+    }
+
+    #[test]
+    fn test_title_and_modes() {
+        let s = screen(2, 10, b"\x1b]0;htop\x07\x1b[?25l\x1b[?1h\x1b[?2004h");
+        assert_eq!(s.title(), "htop");
+        assert!(!s.cursor().visible);
+        assert!(s.app_cursor_keys());
+        assert!(s.bracketed_paste());
+    }
+
+    #[test]
+    fn test_resize_keeps_cursor_line() {
+        let mut s = screen(4, 10, b"1\r\n2\r\n3\r\n4");
+        s.resize(2, 5);
+        assert_eq!(s.contents(), "3\n4");
+        assert_eq!(s.scrollback().len(), 2);
+        assert_eq!(s.cursor().row, 1);
+    }
+
+    #[test]
+    fn test_recorded_htop_frame() {
+        // Trimmed from a real htop start-up: alt screen, region, colours, absolute moves
+        let frame: &[u8] = b"\x1b[?1049h\x1b[1;24r\x1b[m\x1b[4l\x1b[?1h\x1b=\x1b[?25l\x1b[H\x1b[2J\
+\x1b[1;3H\x1b[36m0\x1b[39m\x1b[1m[\x1b[32m||||\x1b[30m\x1b[1m      \x1b[37m\x1b[1m 12.5%\x1b[39m\x1b[1m]\
+\x1b[2;3H\x1b[36mMem\x1b[39m\x1b[1m[\x1b[32m|||\x1b[37m\x1b[1m 1.2G/15.5G\x1b[39m\x1b[1m]\x1b[m\
+\x1b[24;1H\x1b[30m\x1b[46mF1\x1b[39;49mHelp";
+        let s = screen(24, 40, frame);
+        assert!(s.is_alternate_screen());
+        assert_eq!(s.row_text(0), "  0[||||       12.5%]");
+        assert_eq!(s.row_text(1), "  Mem[||| 1.2G/15.5G]");
+        assert_eq!(s.row_text(23), "F1Help");
+        assert_eq!(s.cell(23, 0).unwrap().attrs.bg, Color::Indexed(6));
+        assert_eq!(s.cell(0, 2).unwrap().attrs.fg, Color::Indexed(6));
+    }
+}
@@ -0,0 +1,378 @@
+//! Bounded scrollback, regex search and text selection.
+//!
+//! Every line a `Screen` has shown gets a `LineId` that never changes: lines
+//! dropped from the front of the ring keep counting, so the id of a line is
+//! stable while newer output arrives. `Search` uses that to scan each
+//! scrollback line once, and `Selection` uses it to address ranges that
+//! span the scrollback and the visible rows.
+use std::collections::VecDeque;
+use std::ops::Index;
+
+use regex::{Regex, RegexBuilder};
+
+use crate::screen::{Cell, Row, Screen};
+
+/// Absolute line number: scrollback lines first, then the visible rows.
+pub type LineId = u64;
+
+/// Ring buffer of rows that scrolled off the top of the screen.
+#[derive(Debug, Clone)]
+pub struct Scrollback {
+    lines: VecDeque<Row>,
+    limit: usize,
+    /// Lines dropped from the front (or cleared) so far.
+    dropped: u64,
+}
+
+impl Scrollback {
+    /// A buffer keeping at most `limit` lines; 0 keeps none.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            lines: VecDeque::new(),
+            limit,
+            dropped: 0,
+        }
+    }
+
+    pub(crate) fn push(&mut self, row: Row) {
+        if self.limit == 0 {
+            self.dropped += 1;
+            return;
+        }
+        if self.lines.len() >= self.limit {
+            self.lines.pop_front();
+            self.dropped += 1;
+        }
+        self.lines.push_back(row);
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// Oldest first.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Row> + ExactSizeIterator {
+        self.lines.iter()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Row> {
+        self.lines.get(index)
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Change the line limit, dropping the oldest lines if over it.
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        let excess = self.lines.len().saturating_sub(limit);
+        self.lines.drain(..excess);
+        self.dropped += excess as u64;
+    }
+
+    pub fn clear(&mut self) {
+        self.dropped += self.lines.len() as u64;
+        self.lines.clear();
+    }
+
+    /// Id of the oldest line still kept.
+    pub fn first_line(&self) -> LineId {
+        self.dropped
+    }
+
+    /// Id one past the newest scrollback line, i.e. the top visible row.
+    pub fn end_line(&self) -> LineId {
+        self.dropped + self.lines.len() as u64
+    }
+}
+
+impl Index<usize> for Scrollback {
+    type Output = Row;
+
+    fn index(&self, index: usize) -> &Row {
+        &self.lines[index]
+    }
+}
+
+/// Text of a row plus the column of every byte of it, so regex offsets can
+/// be mapped back to cells. The extra last entry is the row width.
+fn text_with_columns(row: &[Cell]) -> (String, Vec<usize>) {
+    let mut text = String::new();
+    let mut columns = Vec::new();
+    for (col, cell) in row.iter().enumerate().filter(|(_, c)| c.width > 0) {
+        text.push(cell.c);
+        columns.resize(text.len(), col);
+    }
+    columns.push(row.len());
+    (text, columns)
+}
+
+/// One match, in cells: `start..end` on `line`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchMatch {
+    pub line: LineId,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Incremental regex search over a screen's scrollback and visible rows.
+///
+/// Call `update` whenever the screen changed. Scrollback lines are only
+/// scanned once; matches on lines that fall out of the ring are dropped, so
+/// the match list stays as bounded as the scrollback itself.
+pub struct Search {
+    regex: Regex,
+    /// Matches in the scrollback, oldest first.
+    settled: VecDeque<SearchMatch>,
+    /// First scrollback line not scanned yet.
+    scanned_to: LineId,
+    /// Matches on the visible rows, rescanned on every update.
+    visible: Vec<SearchMatch>,
+}
+
+impl Search {
+    /// Compile `pattern`. Case-insensitive unless it contains an uppercase
+    /// letter.
+    pub fn new(pattern: &str) -> Result<Self, regex::Error> {
+        let regex = RegexBuilder::new(pattern)
+            .case_insensitive(!pattern.chars().any(char::is_uppercase))
+            .build()?;
+        Ok(Self {
+            regex,
+            settled: VecDeque::new(),
+            scanned_to: 0,
+            visible: Vec::new(),
+        })
+    }
+
+    pub fn pattern(&self) -> &str {
+        self.regex.as_str()
+    }
+
+    fn scan(&self, line: LineId, row: &[Cell], out: &mut impl Extend<SearchMatch>) {
+        let (text, columns) = text_with_columns(row);
+        out.extend(
+            self.regex
+                .find_iter(&text)
+                .filter(|m| !m.is_empty())
+                .map(|m| SearchMatch {
+                    line,
+                    start: columns[m.start()],
+                    end: columns[m.end()],
+                }),
+        );
+    }
+
+    /// Catch up with new output on `screen`.
+    pub fn update(&mut self, screen: &Screen) {
+        let scrollback = screen.scrollback();
+        let first = scrollback.first_line();
+        while self.settled.front().is_some_and(|m| m.line < first) {
+            self.settled.pop_front();
+        }
+
+        let mut settled = std::mem::take(&mut self.settled);
+        for line in self.scanned_to.max(first)..scrollback.end_line() {
+            self.scan(line, &scrollback[(line - first) as usize], &mut settled);
+        }
+        self.settled = settled;
+        self.scanned_to = scrollback.end_line();
+
+        let mut visible = Vec::new();
+        for (r, row) in screen.rows().iter().enumerate() {
+            self.scan(scrollback.end_line() + r as u64, row, &mut visible);
+        }
+        self.visible = visible;
+    }
+
+    /// All matches, oldest first.
+    pub fn matches(&self) -> impl DoubleEndedIterator<Item = &SearchMatch> {
+        self.settled.iter().chain(self.visible.iter())
+    }
+
+    pub fn len(&self) -> usize {
+        self.settled.len() + self.visible.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The `n`th match counting back from the newest (0 = newest).
+    pub fn nth_from_end(&self, n: usize) -> Option<SearchMatch> {
+        self.matches().rev().nth(n).copied()
+    }
+}
+
+/// A cell position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Point {
+    pub line: LineId,
+    pub col: usize,
+}
+
+/// Cells between `anchor` (where the selection started) and `head`, end
+/// exclusive. Either end may come first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Selection {
+    pub anchor: Point,
+    pub head: Point,
+}
+
+impl Selection {
+    /// An empty selection at `anchor`; grow it with `extend_to`.
+    pub fn new(anchor: Point) -> Self {
+        Self { anchor, head: anchor }
+    }
+
+    pub fn extend_to(&mut self, head: Point) {
+        self.head = head;
+    }
+
+    /// Whole lines `first..=last`.
+    pub fn lines(first: LineId, last: LineId) -> Self {
+        Self {
+            anchor: Point { line: first, col: 0 },
+            head: Point {
+                line: last,
+                col: usize::MAX,
+            },
+        }
+    }
+
+    /// (start, end) in reading order.
+    pub fn range(&self) -> (Point, Point) {
+        if self.anchor <= self.head {
+            (self.anchor, self.head)
+        } else {
+            (self.head, self.anchor)
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.anchor == self.head
+    }
+
+    pub fn contains(&self, point: Point) -> bool {
+        let (start, end) = self.range();
+        start <= point && point < end
+    }
+
+    /// Selected text, one line per row with trailing blanks trimmed. Lines
+    /// that already left the scrollback are skipped.
+    pub fn text(&self, screen: &Screen) -> String {
+        let (start, end) = self.range();
+        let mut lines = Vec::new();
+        for line in start.line..=end.line {
+            let Some(row) = screen.line(line) else { continue };
+            let from = if line == start.line { start.col } else { 0 }.min(row.len());
+            let to = if line == end.line { end.col } else { usize::MAX }.min(row.len());
+            let text: String = row[from..to.max(from)]
+                .iter()
+                .filter(|c| c.width > 0)
+                .map(|c| c.c)
+                .collect();
+            lines.push(text.trim_end().to_string());
+        }
+        lines.join("\n")
+    }
+}
+
+impl From<SearchMatch> for Selection {
+    fn from(m: SearchMatch) -> Self {
+        Self {
+            anchor: Point {
+                line: m.line,
+                col: m.start,
+            },
+            head: Point { line: m.line, col: m.end },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ring_keeps_line_ids_stable() {
+        let mut s = Screen::with_scrollback(2, 10, 3);
+        for i in 0..6 {
+            s.process(format!("line {i}\r\n").as_bytes());
+        }
+        // line 0..=4 scrolled off; only the last 3 are kept
+        let sb = s.scrollback();
+        assert_eq!(sb.len(), 3);
+        assert_eq!(sb.first_line(), 2);
+        assert_eq!(s.line_text(2), "line 2");
+        assert_eq!(s.line_text(5), "line 5");
+        assert_eq!(s.line(1), None);
+
+        s.set_scrollback_limit(1);
+        assert_eq!(s.scrollback().first_line(), 4);
+        s.clear_scrollback();
+        assert_eq!(s.scrollback().first_line(), 5);
+        assert_eq!(s.line_text(5), "line 5");
+    }
+
+    #[test]
+    fn test_search_is_incremental_and_bounded() {
+        let mut s = Screen::with_scrollback(2, 30, 4);
+        let mut search = Search::new("error").unwrap();
+        s.process(b"ok\r\nERROR: one\r\n");
+        search.update(&s);
+        assert_eq!(search.len(), 1);
+        let m = search.nth_from_end(0).unwrap();
+        assert_eq!((m.line, m.start, m.end), (1, 0, 5));
+
+        s.process(b"warn\r\nan error here\r\n");
+        search.update(&s);
+        assert_eq!(search.len(), 2);
+        assert_eq!(search.nth_from_end(0).unwrap(), SearchMatch { line: 3, start: 3, end: 8 });
+
+        // Push both matches out of the 4-line ring
+        for _ in 0..6 {
+            s.process(b"x\r\n");
+        }
+        search.update(&s);
+        assert!(search.is_empty());
+
+        // Uppercase in the pattern makes it case-sensitive
+        let mut strict = Search::new("ERROR").unwrap();
+        s.process(b"error ERROR\r\n");
+        strict.update(&s);
+        assert_eq!(strict.len(), 1);
+        assert!(Search::new("(").is_err());
+    }
+
+    #[test]
+    fn test_match_columns_with_wide_chars() {
+        let mut s = Screen::new(2, 20);
+        s.process("日本 fail".as_bytes());
+        let mut search = Search::new("fail").unwrap();
+        search.update(&s);
+        let m = search.nth_from_end(0).unwrap();
+        assert_eq!((m.start, m.end), (5, 9));
+        assert_eq!(Selection::from(m).text(&s), "fail");
+    }
+
+    #[test]
+    fn test_selection_text_across_scrollback() {
+        let mut s = Screen::with_scrollback(2, 20, 100);
+        s.process(b"first line\r\nsecond line\r\nthird\r\nfourth");
+        // Lines 0 and 1 are in the scrollback, 2 and 3 on screen
+        let mut sel = Selection::new(Point { line: 3, col: 3 });
+        sel.extend_to(Point { line: 0, col: 6 });
+        assert_eq!(sel.text(&s), "line\nsecond line\nthird\nfou");
+        assert!(sel.contains(Point { line: 1, col: 0 }));
+        assert!(!sel.contains(Point { line: 3, col: 3 }));
+
+        assert_eq!(Selection::lines(1, 2).text(&s), "second line\nthird");
+        assert!(Selection::new(Point { line: 0, col: 0 }).is_empty());
+    }
+}
@@ -1 +1,514 @@
-// API handlers
+// JSON-RPC 2.0 methods served by the local API
+use kael_storage::StorageManager;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::llm::{self, LLMProvider, LLMRequest};
+use crate::services::chat_history::ChatHistory;
+use crate::services::{app_projects, command_rewriter};
+use crate::settings::Settings;
+use crate::state::{AppProject, AppStatus};
+
+/// Standard JSON-RPC error codes
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+/// A method ran and failed (provider down, unknown id, ...)
+pub const SERVER_ERROR: i64 = -32000;
+
+/// Every method, for `rpc.methods` and the docs
+pub const METHODS: &[&str] = &[
+    "rpc.methods",
+    "chat.complete",
+    "history.conversations",
+    "history.search",
+    "history.messages",
+    "history.export",
+    "command.rewrite",
+    "command.translate",
+    "system.context",
+    "projects.list",
+    "projects.get",
+    "projects.create",
+    "projects.update",
+    "projects.archive",
+    "projects.delete",
+];
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RpcRequest {
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+    /// Absent for notifications, which get no response
+    #[serde(default)]
+    pub id: Option<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<String> for RpcError {
+    fn from(message: String) -> Self {
+        Self::new(SERVER_ERROR, message)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RpcResponse {
+    pub jsonrpc: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+    pub id: Value,
+}
+
+impl RpcResponse {
+    pub fn error(id: Value, error: RpcError) -> Self {
+        Self {
+            jsonrpc: "2.0",
+            result: None,
+            error: Some(error),
+            id,
+        }
+    }
+}
+
+/// What the handlers work on
+#[derive(Clone)]
+pub struct ApiContext {
+    pub storage: StorageManager,
+}
+
+fn params<T: DeserializeOwned>(value: Value) -> Result<T, RpcError> {
+    // Methods without parameters accept `null`, `{}` or nothing
+    let value = if value.is_null() { json!({}) } else { value };
+    serde_json::from_value(value).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+fn to_value<T: Serialize>(value: T) -> Result<Value, RpcError> {
+    serde_json::to_value(value).map_err(|e| RpcError::from(e.to_string()))
+}
+
+/// Handle one parsed request body: a single call or a batch. Returns `None`
+/// when nothing needs to be sent back (only notifications).
+pub async fn handle_body(ctx: &ApiContext, body: &[u8]) -> Option<Value> {
+    let value: Value = match serde_json::from_slice(body) {
+        Ok(value) => value,
+        Err(e) => {
+            let response = RpcResponse::error(Value::Null, RpcError::new(PARSE_ERROR, e.to_string()));
+            return to_value(response).ok();
+        }
+    };
+    match value {
+        Value::Array(calls) if !calls.is_empty() => {
+            let mut responses = Vec::new();
+            for call in calls {
+                if let Some(response) = handle_value(ctx, call).await {
+                    responses.push(response);
+                }
+            }
+            (!responses.is_empty()).then(|| to_value(responses).unwrap_or(Value::Null))
+        }
+        value => handle_value(ctx, value)
+            .await
+            .and_then(|response| to_value(response).ok()),
+    }
+}
+
+async fn handle_value(ctx: &ApiContext, value: Value) -> Option<RpcResponse> {
+    let id = value.get("id").cloned().unwrap_or(Value::Null);
+    let request: RpcRequest = match serde_json::from_value(value) {
+        Ok(request) => request,
+        Err(e) => return Some(RpcResponse::error(id, RpcError::new(INVALID_REQUEST, e.to_string()))),
+    };
+    if request.jsonrpc != "2.0" {
+        return Some(RpcResponse::error(id, RpcError::new(INVALID_REQUEST, "jsonrpc must be \"2.0\"")));
+    }
+    let result = dispatch(ctx, &request.method, request.params).await;
+    let id = request.id?;
+    Some(match result {
+        Ok(result) => RpcResponse {
+            jsonrpc: "2.0",
+            result: Some(result),
+            error: None,
+            id,
+        },
+        Err(error) => RpcResponse::error(id, error),
+    })
+}
+
+#[derive(Deserialize)]
+struct ChatParams {
+    prompt: String,
+    /// First provider to try (e.g. "Ollama", "Mistral"); the rest follow as fallbacks
+    #[serde(default)]
+    provider: Option<LLMProvider>,
+    #[serde(default)]
+    model: Option<String>,
+    /// Replaces Kael's system prompt
+    #[serde(default)]
+    system: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ConversationsParams {
+    #[serde(default)]
+    query: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SearchParams {
+    query: String,
+    #[serde(default = "default_limit")]
+    limit: usize,
+}
+
+fn default_limit() -> usize {
+    20
+}
+
+#[derive(Deserialize)]
+struct ConversationParams {
+    conversation_id: String,
+}
+
+#[derive(Deserialize)]
+struct CommandParams {
+    command: String,
+}
+
+#[derive(Deserialize)]
+struct TranslateParams {
+    request: String,
+}
+
+#[derive(Deserialize)]
+struct ProjectsParams {
+    /// `true` for archived projects only, `false` for active ones, absent for all
+    #[serde(default)]
+    archived: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct ProjectIdParams {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct NewProjectParams {
+    name: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default = "default_status")]
+    status: AppStatus,
+}
+
+fn default_status() -> AppStatus {
+    AppStatus::Want
+}
+
+#[derive(Deserialize)]
+struct ArchiveParams {
+    id: String,
+    archived: bool,
+}
+
+/// Providers tried after the first one when no order is saved, as in the chat panel
+const FALLBACK_PROVIDERS: [LLMProvider; 4] = [
+    LLMProvider::Mistral,
+    LLMProvider::Gemini,
+    LLMProvider::Copilot,
+    LLMProvider::CopilotAgent,
+];
+
+/// Providers to try after `first`, following the saved provider order. A
+/// local request only escalates to the cloud with hybrid assist on, and a
+/// local-only profile never does.
+fn fallback_providers(settings: &Settings, first: &LLMProvider, local_profile: bool) -> Vec<(LLMProvider, Option<String>)> {
+    if local_profile || (*first == LLMProvider::Ollama && !settings.hybrid_assist) {
+        return Vec::new();
+    }
+    if settings.provider_order.is_empty() {
+        return FALLBACK_PROVIDERS.into_iter().map(|p| (p, None)).collect();
+    }
+    settings
+        .provider_order
+        .iter()
+        .filter_map(|label| llm::provider_from_label(label))
+        .filter(|p| p != first)
+        .map(|p| (p, None))
+        .collect()
+}
+
+async fn complete(prompt: String, provider: Option<LLMProvider>, model: Option<String>, system: Option<String>) -> Result<llm::LLMResponse, RpcError> {
+    let request = LLMRequest {
+        provider: provider.unwrap_or(LLMProvider::Ollama),
+        model: model.unwrap_or_default(),
+        prompt,
+        api_key: None,
+        system: Some(system.unwrap_or_else(llm::get_kael_system_prompt)),
+    };
+    let settings = crate::settings::init()
+        .await
+        .map(|store| store.get())
+        .unwrap_or_else(|_| crate::settings::current());
+    let fallback = fallback_providers(&settings, &request.provider, crate::profiles::is_local());
+    Ok(llm::send_request_with_fallback(request, None, fallback).await?)
+}
+
+async fn project(ctx: &ApiContext, id: &str) -> Result<AppProject, RpcError> {
+    ctx.storage
+        .projects()
+        .get(id)
+        .await
+        .map_err(|e| format!("Failed to get project: {}", e))?
+        .ok_or_else(|| RpcError::from(format!("Project {} not found", id)))
+}
+
+/// Run one method
+pub async fn dispatch(ctx: &ApiContext, method: &str, raw: Value) -> Result<Value, RpcError> {
+    let history = || ChatHistory::with_storage(ctx.storage.clone());
+    match method {
+        "rpc.methods" => to_value(METHODS),
+
+        "chat.complete" => {
+            let p: ChatParams = params(raw)?;
+            let response = complete(p.prompt, p.provider, p.model, p.system).await?;
+            Ok(json!({ "provider": response.provider, "content": response.content }))
+        }
+
+        "history.conversations" => {
+            let p: ConversationsParams = params(raw)?;
+            let conversations = match p.query.as_deref().map(str::trim).filter(|q| !q.is_empty()) {
+                Some(query) => history().search_conversations(query).await?,
+                None => history().get_conversations().await?,
+            };
+            to_value(conversations)
+        }
+        "history.search" => {
+            let p: SearchParams = params(raw)?;
+            let conversations = history().search_conversations(&p.query).await?;
+            let messages = ctx
+                .storage
+                .messages()
+                .search(&p.query, p.limit)
+                .await
+                .map_err(|e| format!("Failed to search messages: {}", e))?;
+            Ok(json!({ "conversations": conversations, "messages": messages }))
+        }
+        "history.messages" => {
+            let p: ConversationParams = params(raw)?;
+            to_value(history().get_messages(&p.conversation_id).await?)
+        }
+        "history.export" => {
+            let p: ConversationParams = params(raw)?;
+            let export = history().export_conversation(&p.conversation_id).await?;
+            serde_json::from_str(&export).map_err(|e| RpcError::from(e.to_string()))
+        }
+
+        "command.rewrite" => {
+            let p: CommandParams = params(raw)?;
+            let context = command_rewriter::build_user_context().await?;
+            let (command, corrections) = command_rewriter::rewrite_command(&p.command, &context);
+            Ok(json!({ "command": command, "corrections": corrections }))
+        }
+        "command.translate" => {
+            let p: TranslateParams = params(raw)?;
+            let prompt = format!(
+                "Translate this request into a single shell command for this system. Reply with the command only, no explanation or code fences.\n\nRequest: {}",
+                p.request
+            );
+            let response = complete(prompt, None, None, None).await?;
+            let command = crate::services::script_library::extract_snippet(&response.content);
+            Ok(json!({ "command": command, "provider": response.provider }))
+        }
+
+        "system.context" => {
+            let context = match crate::services::system_context::SystemContext::load_from_default_path() {
+                Ok(context) => context,
+                Err(_) => crate::services::system_context::SystemContext::detect().await?,
+            };
+            to_value(context)
+        }
+
+        "projects.list" => {
+            let p: ProjectsParams = params(raw)?;
+            let projects = match p.archived {
+                Some(true) => app_projects::get_archived_projects(&ctx.storage).await?,
+                Some(false) => app_projects::get_active_projects(&ctx.storage).await?,
+                None => app_projects::get_projects_local(&ctx.storage).await?,
+            };
+            to_value(projects)
+        }
+        "projects.get" => {
+            let p: ProjectIdParams = params(raw)?;
+            to_value(project(ctx, &p.id).await?)
+        }
+        "projects.create" => {
+            let p: NewProjectParams = params(raw)?;
+            if p.name.trim().is_empty() {
+                return Err(RpcError::new(INVALID_PARAMS, "Project name cannot be empty"));
+            }
+            let project = AppProject::new(p.name, p.description, p.status);
+            app_projects::save_project_local(&ctx.storage, &project).await?;
+            to_value(project)
+        }
+        "projects.update" => {
+            let mut updated: AppProject = params(raw)?;
+            let existing = project(ctx, &updated.id).await?;
+            updated.created_at = existing.created_at;
+            updated.updated_at = chrono::Utc::now();
+            app_projects::save_project_local(&ctx.storage, &updated).await?;
+            to_value(updated)
+        }
+        "projects.archive" => {
+            let p: ArchiveParams = params(raw)?;
+            project(ctx, &p.id).await?;
+            app_projects::toggle_archive(&ctx.storage, &p.id, p.archived).await?;
+            to_value(project(ctx, &p.id).await?)
+        }
+        "projects.delete" => {
+            let p: ProjectIdParams = params(raw)?;
+            project(ctx, &p.id).await?;
+            app_projects::delete_project_local(&ctx.storage, &p.id).await?;
+            Ok(json!({ "deleted": p.id }))
+        }
+
+        _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("Unknown method: {}", method))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ctx() -> ApiContext {
+        ApiContext {
+            storage: StorageManager::open_in_memory().unwrap(),
+        }
+    }
+
+    async fn call(ctx: &ApiContext, body: Value) -> Value {
+        handle_body(ctx, body.to_string().as_bytes()).await.unwrap()
+    }
+
+    #[test]
+    fn test_fallback_follows_settings() {
+        let mut settings = Settings::default();
+        let providers = |settings: &Settings, first, local| {
+            fallback_providers(settings, &first, local).into_iter().map(|(p, _)| p).collect::<Vec<_>>()
+        };
+        assert_eq!(providers(&settings, LLMProvider::Ollama, false), FALLBACK_PROVIDERS.to_vec());
+
+        settings.provider_order = vec!["Ollama (Local)".into(), "Google Gemini".into(), "Minstrel AI".into()];
+        assert_eq!(
+            providers(&settings, LLMProvider::Ollama, false),
+            vec![LLMProvider::Gemini, LLMProvider::Minstrel]
+        );
+        assert_eq!(providers(&settings, LLMProvider::Gemini, true), vec![]);
+
+        settings.hybrid_assist = false;
+        assert_eq!(providers(&settings, LLMProvider::Ollama, false), vec![]);
+        assert_eq!(
+            providers(&settings, LLMProvider::Gemini, false),
+            vec![LLMProvider::Ollama, LLMProvider::Minstrel]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_project_crud() {
+        let ctx = ctx();
+        let created = call(
+            &ctx,
+            json!({"jsonrpc": "2.0", "id": 1, "method": "projects.create", "params": {"name": "kael-notes", "status": "making"}}),
+        )
+        .await;
+        assert_eq!(created["id"], 1);
+        let project = created["result"].clone();
+        assert_eq!(project["status"], "making");
+        let id = project["id"].as_str().unwrap().to_string();
+
+        let mut edited = project.clone();
+        edited["description"] = json!("Markdown notes");
+        let updated = call(&ctx, json!({"jsonrpc": "2.0", "id": 2, "method": "projects.update", "params": edited})).await;
+        assert_eq!(updated["result"]["description"], "Markdown notes");
+
+        let archived = call(
+            &ctx,
+            json!({"jsonrpc": "2.0", "id": 3, "method": "projects.archive", "params": {"id": id, "archived": true}}),
+        )
+        .await;
+        assert_eq!(archived["result"]["archived"], true);
+        let active = dispatch(&ctx, "projects.list", json!({"archived": false})).await.unwrap();
+        assert_eq!(active, json!([]));
+        let all = dispatch(&ctx, "projects.list", Value::Null).await.unwrap();
+        assert_eq!(all.as_array().unwrap().len(), 1);
+
+        dispatch(&ctx, "projects.delete", json!({"id": id})).await.unwrap();
+        let missing = dispatch(&ctx, "projects.get", json!({"id": id})).await.unwrap_err();
+        assert_eq!(missing.code, SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn test_history_queries() {
+        let ctx = ctx();
+        let history = ChatHistory::with_storage(ctx.storage.clone());
+        let id = history.create_conversation("Fix pacman keys", "ollama", "llama3").await.unwrap();
+        history.add_message(&id, "user", "pacman says invalid signature").await.unwrap();
+
+        let found = dispatch(&ctx, "history.conversations", json!({"query": "pacman"})).await.unwrap();
+        assert_eq!(found[0]["id"], id.as_str());
+        let messages = dispatch(&ctx, "history.messages", json!({"conversation_id": id})).await.unwrap();
+        assert_eq!(messages.as_array().unwrap().len(), 1);
+        let search = dispatch(&ctx, "history.search", json!({"query": "signature"})).await.unwrap();
+        assert_eq!(search["conversations"], json!([]));
+        assert_eq!(search["messages"][0]["text"], "pacman says invalid signature");
+        let export = dispatch(&ctx, "history.export", json!({"conversation_id": id})).await.unwrap();
+        assert_eq!(export["conversation"]["title"], "Fix pacman keys");
+    }
+
+    #[tokio::test]
+    async fn test_protocol_errors() {
+        let ctx = ctx();
+        let response = handle_body(&ctx, b"{not json").await.unwrap();
+        assert_eq!(response["error"]["code"], PARSE_ERROR);
+
+        let response = call(&ctx, json!({"jsonrpc": "1.0", "id": 7, "method": "rpc.methods"})).await;
+        assert_eq!(response["error"]["code"], INVALID_REQUEST);
+        assert_eq!(response["id"], 7);
+
+        let response = call(&ctx, json!({"jsonrpc": "2.0", "id": "a", "method": "nope"})).await;
+        assert_eq!(response["error"]["code"], METHOD_NOT_FOUND);
+
+        let response = call(&ctx, json!({"jsonrpc": "2.0", "id": 1, "method": "projects.get", "params": {}})).await;
+        assert_eq!(response["error"]["code"], INVALID_PARAMS);
+
+        // Notifications are run but not answered; a batch answers the rest
+        let batch = json!([
+            {"jsonrpc": "2.0", "method": "projects.create", "params": {"name": "quiet"}},
+            {"jsonrpc": "2.0", "id": 9, "method": "projects.list"}
+        ]);
+        let response = call(&ctx, batch).await;
+        assert_eq!(response.as_array().unwrap().len(), 1);
+        assert_eq!(response[0]["result"][0]["name"], "quiet");
+        let notification = json!({"jsonrpc": "2.0", "method": "rpc.methods"});
+        assert!(handle_body(&ctx, notification.to_string().as_bytes()).await.is_none());
+    }
+}
@@ -1 +1,397 @@
+//! Local API so editor plugins, scripts and CI helpers can use Kael.
+//!
+//! JSON-RPC 2.0 over HTTP: `POST /rpc` with a request (or a batch) as the
+//! body; `handlers` lists the methods. The server always listens on a Unix
+//! socket in the user's runtime directory, which only the user can reach.
+//! With `api_port` set it also listens on 127.0.0.1, where every request
+//! must carry `Authorization: Bearer <token>`; the token is generated on
+//! first start and kept in `~/.config/kael-os/api-token` (mode 0600).
+//!
+//! ```bash
+//! curl --unix-socket "$XDG_RUNTIME_DIR/kael-os/api.sock" http://kael/rpc \
+//!   -d '{"jsonrpc": "2.0", "id": 1, "method": "command.rewrite", "params": {"command": "yay -S htop"}}'
+//! ```
+#![allow(dead_code)]
+
 pub mod handlers;
+
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use rand::RngCore;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, UnixListener};
+
+use handlers::ApiContext;
+
+/// Largest request body accepted
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+/// Largest request line or header
+const MAX_HEADER_LINE: usize = 8 * 1024;
+const MAX_HEADERS: usize = 64;
+/// Time a client gets to send its whole request
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Unix socket of the API: `$XDG_RUNTIME_DIR/kael-os/api.sock`, or a
+/// per-user directory under the system temp dir
+pub fn socket_path() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR").filter(|d| !d.is_empty()) {
+        Some(dir) => PathBuf::from(dir).join("kael-os").join("api.sock"),
+        None => {
+            let user = std::env::var("USER").unwrap_or_else(|_| "user".to_string());
+            std::env::temp_dir().join(format!("kael-os-{}", user)).join("api.sock")
+        }
+    }
+}
+
+/// Where the TCP token is kept (~/.config/kael-os/api-token)
+pub fn token_path() -> PathBuf {
+    let home = std::env::var("HOME").unwrap_or_else(|_| "/tmp".to_string());
+    PathBuf::from(home).join(".config").join("kael-os").join("api-token")
+}
+
+/// Read the token at `path`, creating a random one on first use
+pub fn load_or_create_token(path: &Path) -> Result<String, String> {
+    if let Ok(token) = std::fs::read_to_string(path) {
+        let token = token.trim().to_string();
+        if !token.is_empty() {
+            return Ok(token);
+        }
+    }
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let token: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    // An empty file left by an interrupted write is replaced; the new file
+    // is owner-only from the moment it exists
+    if path.exists() {
+        std::fs::remove_file(path).map_err(|e| format!("Failed to replace API token: {}", e))?;
+    }
+    use std::io::Write;
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .map_err(|e| format!("Failed to create API token: {}", e))?;
+    file.write_all(token.as_bytes())
+        .map_err(|e| format!("Failed to write API token: {}", e))?;
+    Ok(token)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct HttpRequest {
+    method: String,
+    path: String,
+    authorization: Option<String>,
+    body: Vec<u8>,
+}
+
+/// Why a request could not be read, as an HTTP status
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum HttpError {
+    BadRequest,
+    TooLarge,
+    /// Connection closed before a full request arrived
+    Closed,
+    /// The request didn't arrive in time
+    Timeout,
+}
+
+async fn read_line<R: AsyncRead + Unpin>(reader: &mut BufReader<R>) -> Result<String, HttpError> {
+    let mut line = Vec::new();
+    let n = (&mut *reader)
+        .take(MAX_HEADER_LINE as u64 + 1)
+        .read_until(b'\n', &mut line)
+        .await
+        .map_err(|_| HttpError::Closed)?;
+    if n == 0 {
+        return Err(HttpError::Closed);
+    }
+    if n > MAX_HEADER_LINE {
+        return Err(HttpError::TooLarge);
+    }
+    let line = String::from_utf8(line).map_err(|_| HttpError::BadRequest)?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+async fn read_request<R: AsyncRead + Unpin>(reader: &mut BufReader<R>) -> Result<HttpRequest, HttpError> {
+    let request_line = read_line(reader).await?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(path), Some(_version)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(HttpError::BadRequest);
+    };
+
+    let mut content_length = 0;
+    let mut authorization = None;
+    for _ in 0..=MAX_HEADERS {
+        let line = read_line(reader).await?;
+        if line.is_empty() {
+            if content_length > MAX_BODY_BYTES {
+                return Err(HttpError::TooLarge);
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).await.map_err(|_| HttpError::Closed)?;
+            return Ok(HttpRequest {
+                method: method.to_string(),
+                path: path.to_string(),
+                authorization,
+                body,
+            });
+        }
+        let (name, value) = line.split_once(':').ok_or(HttpError::BadRequest)?;
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = value.parse().map_err(|_| HttpError::BadRequest)?;
+        } else if name.eq_ignore_ascii_case("authorization") {
+            authorization = value.strip_prefix("Bearer ").map(|t| t.trim().to_string());
+        }
+    }
+    Err(HttpError::TooLarge)
+}
+
+/// `read_request`, giving up after `timeout` so idle clients don't hold a
+/// connection open
+async fn read_request_within<R: AsyncRead + Unpin>(
+    reader: &mut BufReader<R>,
+    timeout: Duration,
+) -> Result<HttpRequest, HttpError> {
+    tokio::time::timeout(timeout, read_request(reader))
+        .await
+        .unwrap_or(Err(HttpError::Timeout))
+}
+
+/// Compare without leaking how much of the token matched
+fn token_matches(given: Option<&str>, expected: &str) -> bool {
+    let Some(given) = given else { return false };
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+async fn write_response<W: AsyncWrite + Unpin>(writer: &mut W, status: &str, body: &str) {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    let _ = writer.write_all(response.as_bytes()).await;
+    let _ = writer.shutdown().await;
+}
+
+fn error_body(message: &str) -> String {
+    serde_json::json!({ "error": message }).to_string()
+}
+
+/// Serve one request on `stream`. `token` is required when set (TCP).
+async fn handle_connection<S>(stream: S, ctx: ApiContext, token: Option<String>)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut reader = BufReader::new(stream);
+    let request = match read_request_within(&mut reader, READ_TIMEOUT).await {
+        Ok(request) => request,
+        Err(HttpError::Closed) => return,
+        Err(HttpError::Timeout) => {
+            return write_response(reader.get_mut(), "408 Request Timeout", &error_body("Request not received in time")).await
+        }
+        Err(HttpError::TooLarge) => {
+            return write_response(reader.get_mut(), "413 Payload Too Large", &error_body("Request too large")).await
+        }
+        Err(HttpError::BadRequest) => {
+            return write_response(reader.get_mut(), "400 Bad Request", &error_body("Malformed HTTP request")).await
+        }
+    };
+    let stream = reader.get_mut();
+
+    if let Some(token) = &token {
+        if !token_matches(request.authorization.as_deref(), token) {
+            return write_response(stream, "401 Unauthorized", &error_body("Missing or wrong bearer token")).await;
+        }
+    }
+    if request.path != "/rpc" {
+        return write_response(stream, "404 Not Found", &error_body("Only /rpc is served")).await;
+    }
+    if request.method != "POST" {
+        return write_response(stream, "405 Method Not Allowed", &error_body("Use POST")).await;
+    }
+
+    match handlers::handle_body(&ctx, &request.body).await {
+        Some(response) => write_response(stream, "200 OK", &response.to_string()).await,
+        None => write_response(stream, "204 No Content", "").await,
+    }
+}
+
+/// Accept connections on a Unix socket at `path`, replacing a stale one
+pub async fn serve_unix(path: &Path, ctx: ApiContext) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))
+            .map_err(|e| format!("Failed to protect {}: {}", dir.display(), e))?;
+    }
+    let _ = std::fs::remove_file(path);
+    let listener = UnixListener::bind(path).map_err(|e| format!("Failed to bind {}: {}", path.display(), e))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+        .map_err(|e| format!("Failed to protect {}: {}", path.display(), e))?;
+    log::info!("Kael API listening on {}", path.display());
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(handle_connection(stream, ctx.clone(), None));
+            }
+            Err(e) => log::error!("Kael API accept error: {}", e),
+        }
+    }
+}
+
+/// Accept connections on 127.0.0.1:`port`; requests need `token`
+pub async fn serve_tcp(port: u16, token: String, ctx: ApiContext) -> Result<(), String> {
+    let listener = TcpListener::bind(("127.0.0.1", port))
+        .await
+        .map_err(|e| format!("Failed to bind 127.0.0.1:{}: {}", port, e))?;
+    log::info!("Kael API listening on 127.0.0.1:{}", port);
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(handle_connection(stream, ctx.clone(), Some(token.clone())));
+            }
+            Err(e) => log::error!("Kael API accept error: {}", e),
+        }
+    }
+}
+
+async fn run_server() -> Result<(), String> {
+    let settings = crate::settings::init().await?.get();
+    if !settings.api_server {
+        return Ok(());
+    }
+    let ctx = ApiContext {
+        storage: crate::db::shared()?,
+    };
+
+    if settings.api_port != 0 {
+        let token = load_or_create_token(&token_path())?;
+        let (port, ctx) = (settings.api_port, ctx.clone());
+        tokio::spawn(async move {
+            if let Err(e) = serve_tcp(port, token, ctx).await {
+                log::error!("Kael API: {}", e);
+            }
+        });
+    }
+    serve_unix(&socket_path(), ctx).await
+}
+
+/// Start the API in a background thread with its own Tokio runtime, if
+/// enabled in settings
+pub fn start_api_server() {
+    std::thread::spawn(|| {
+        let rt = tokio::runtime::Runtime::new().expect("Failed to create Tokio runtime");
+        rt.block_on(async {
+            if let Err(e) = run_server().await {
+                log::error!("Kael API: {}", e);
+            }
+        });
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kael_storage::StorageManager;
+
+    #[test]
+    fn test_token_file_is_owner_only() {
+        let dir = std::env::temp_dir().join(format!("kael-api-token-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("api-token");
+        let token = load_or_create_token(&path).unwrap();
+        assert_eq!(token.len(), 64);
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(load_or_create_token(&path).unwrap(), token);
+
+        std::fs::write(&path, "").unwrap();
+        assert_ne!(load_or_create_token(&path).unwrap(), token);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_read_request() {
+        let raw = b"POST /rpc HTTP/1.1\r\nHost: kael\r\nauthorization: Bearer abc\r\nContent-Length: 4\r\n\r\nbody";
+        let request = read_request(&mut BufReader::new(&raw[..])).await.unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/rpc");
+        assert_eq!(request.authorization.as_deref(), Some("abc"));
+        assert_eq!(request.body, b"body");
+
+        let huge = format!("POST /rpc HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_BODY_BYTES + 1);
+        let err = read_request(&mut BufReader::new(huge.as_bytes())).await.unwrap_err();
+        assert_eq!(err, HttpError::TooLarge);
+        let err = read_request(&mut BufReader::new(&b"nonsense\r\n\r\n"[..])).await.unwrap_err();
+        assert_eq!(err, HttpError::BadRequest);
+
+        // A client that connects and sends nothing is dropped
+        let (_client, idle) = tokio::io::duplex(64);
+        let err = read_request_within(&mut BufReader::new(idle), Duration::from_millis(50))
+            .await
+            .unwrap_err();
+        assert_eq!(err, HttpError::Timeout);
+
+        assert!(token_matches(Some("abc"), "abc"));
+        assert!(!token_matches(Some("abd"), "abc"));
+        assert!(!token_matches(None, "abc"));
+    }
+
+    #[tokio::test]
+    async fn test_serve_over_unix_socket_and_tcp() {
+        let dir = std::env::temp_dir().join(format!("kael-api-{}", std::process::id()));
+        let socket = dir.join("api.sock");
+        let ctx = ApiContext {
+            storage: StorageManager::open_in_memory().unwrap(),
+        };
+        let (path, unix_ctx) = (socket.clone(), ctx.clone());
+        tokio::spawn(async move { serve_unix(&path, unix_ctx).await });
+        for _ in 0..50 {
+            if socket.exists() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(std::fs::metadata(&socket).unwrap().permissions().mode() & 0o777, 0o600);
+
+        let body = r#"{"jsonrpc":"2.0","id":1,"method":"projects.list"}"#;
+        let mut stream = tokio::net::UnixStream::connect(&socket).await.unwrap();
+        let request = format!("POST /rpc HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}", body.len(), body);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        let body: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(body, serde_json::json!({"jsonrpc": "2.0", "id": 1, "result": []}));
+
+        // TCP without the token is refused
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            handle_connection(stream, ctx, Some("secret".to_string())).await;
+        });
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 401 Unauthorized"), "{}", response);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
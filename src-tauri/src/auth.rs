@@ -2,8 +2,12 @@
 
 use base64::{engine::general_purpose, Engine};
 use serde::{Deserialize, Serialize};
+use once_cell::sync::Lazy;
 use std::sync::{Arc, Mutex};
 
+use crate::crypto::envelope::{self, DataKey, WrappedKey};
+use crate::oauth_server::{AuthorizationCode, OAuthFlow};
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct User {
     pub uid: String,
@@ -15,34 +19,146 @@ pub struct User {
     pub expires_in: Option<i64>,
 }
 
-// Simple XOR + base64 helper using the user's id_token as key material.
-fn xor_encrypt(key: &str, data: &[u8]) -> String {
-    let k = key.as_bytes();
-    let enc: Vec<u8> = data
-        .iter()
-        .enumerate()
-        .map(|(i, b)| b ^ k[i % k.len()])
-        .collect();
-    general_purpose::STANDARD.encode(enc)
-}
-
+// Secrets used to be XORed with the id_token and base64-encoded. Those blobs
+// can only be read back while the same id_token is current; they are
+// re-encrypted into envelopes by `migrate_legacy_*` once the user unlocks.
 fn xor_decrypt(key: &str, data_b64: &str) -> Option<String> {
     let k = key.as_bytes();
+    if k.is_empty() {
+        return None;
+    }
     let raw = general_purpose::STANDARD.decode(data_b64).ok()?;
     let dec: Vec<u8> = raw
         .iter()
         .enumerate()
         .map(|(i, b)| b ^ k[i % k.len()])
         .collect();
-    String::from_utf8(dec).ok()
+    let text = String::from_utf8(dec).ok()?;
+    // XOR never fails, so a rotated token shows up as control characters
+    text.chars()
+        .all(|c| !c.is_control() || c.is_whitespace())
+        .then_some(text)
+}
+
+/// The unlocked data key for the signed-in user, by uid
+static DATA_KEY: Lazy<Mutex<Option<(String, DataKey)>>> = Lazy::new(|| Mutex::new(None));
+
+fn wrapped_key_path(uid: &str) -> std::path::PathBuf {
+    AuthService::get_data_dir().join(format!("data-key-{}.json", uid))
+}
+
+fn load_wrapped_key(uid: &str) -> Option<WrappedKey> {
+    let json = std::fs::read_to_string(wrapped_key_path(uid)).ok()?;
+    serde_json::from_str(&json).ok()
+}
+
+fn save_wrapped_key(uid: &str, wrapped: &WrappedKey) -> Result<(), String> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let json = serde_json::to_string_pretty(wrapped).map_err(|e| e.to_string())?;
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(wrapped_key_path(uid))
+        .map_err(|e| format!("Failed to save wrapped data key: {}", e))?;
+    file.write_all(json.as_bytes())
+        .map_err(|e| format!("Failed to save wrapped data key: {}", e))
 }
 
-pub fn encrypt_secret(user: &User, plaintext: &str) -> String {
-    xor_encrypt(&user.id_token, plaintext.as_bytes())
+/// Unlock the user's secrets with their passphrase. The wrapped data key is
+/// looked up locally, then in Firestore; if there is none yet a new data
+/// key is created under this passphrase and stored in both places.
+/// Returns `true` when a new key was created.
+pub async fn unlock_secrets(user: &User, passphrase: &str) -> Result<bool, String> {
+    if passphrase.is_empty() {
+        return Err("Enter your secrets passphrase".to_string());
+    }
+    let (wrapped, created) = match load_wrapped_key(&user.uid) {
+        Some(wrapped) => (wrapped, false),
+        None => match crate::firebase::get_wrapped_data_key(user).await? {
+            Some(wrapped) => {
+                save_wrapped_key(&user.uid, &wrapped)?;
+                (wrapped, false)
+            }
+            None => {
+                let wrapped = DataKey::generate().wrap(passphrase);
+                crate::firebase::save_wrapped_data_key(user, &wrapped).await?;
+                save_wrapped_key(&user.uid, &wrapped)?;
+                log::info!("Created a new data key for {}", user.uid);
+                (wrapped, true)
+            }
+        },
+    };
+    let key = wrapped.unwrap(passphrase)?;
+    if let Ok(mut slot) = DATA_KEY.lock() {
+        *slot = Some((user.uid.clone(), key));
+    }
+    Ok(created)
 }
 
-pub fn decrypt_secret(user: &User, ciphertext_b64: &str) -> Option<String> {
-    xor_decrypt(&user.id_token, ciphertext_b64)
+/// Forget the unlocked data key
+pub fn lock_secrets() {
+    if let Ok(mut slot) = DATA_KEY.lock() {
+        *slot = None;
+    }
+}
+
+pub fn secrets_unlocked(user: &User) -> bool {
+    DATA_KEY
+        .lock()
+        .map(|slot| key_for(&slot, user).is_some())
+        .unwrap_or(false)
+}
+
+/// The unlocked key in `slot`, if it belongs to `user`
+fn key_for<'a>(slot: &'a Option<(String, DataKey)>, user: &User) -> Option<&'a DataKey> {
+    match slot {
+        Some((uid, key)) if *uid == user.uid => Some(key),
+        _ => None,
+    }
+}
+
+fn with_data_key<T>(user: &User, f: impl FnOnce(&DataKey) -> T) -> Option<T> {
+    let slot = DATA_KEY.lock().ok()?;
+    key_for(&slot, user).map(f)
+}
+
+fn seal_with(key: &DataKey, plaintext: &str) -> String {
+    key.seal(plaintext.as_bytes())
+}
+
+fn open_with(key: &DataKey, ciphertext: &str) -> Option<String> {
+    String::from_utf8(key.open(ciphertext).ok()?).ok()
+}
+
+/// Seal a secret under the user's data key; the secrets must be unlocked
+pub fn encrypt_secret(user: &User, plaintext: &str) -> Result<String, String> {
+    with_data_key(user, |key| seal_with(key, plaintext))
+        .ok_or_else(|| "Secrets are locked - unlock them with your passphrase in Settings → Security".to_string())
+}
+
+/// Open a sealed secret, or read a legacy XOR blob while its id_token is
+/// still current
+pub fn decrypt_secret(user: &User, ciphertext: &str) -> Option<String> {
+    if is_legacy_secret(ciphertext) {
+        return xor_decrypt(&user.id_token, ciphertext);
+    }
+    with_data_key(user, |key| open_with(key, ciphertext))?
+}
+
+pub fn is_legacy_secret(ciphertext: &str) -> bool {
+    !envelope::is_envelope(ciphertext)
+}
+
+/// Outcome of re-encrypting legacy secrets
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SecretMigration {
+    pub migrated: usize,
+    /// Secrets whose id_token has rotated away; they must be entered again
+    pub unreadable: Vec<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -52,27 +168,120 @@ pub struct EncryptedKey {
     pub created_at: String,
 }
 
+/// Why a token refresh failed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RefreshError {
+    /// Network trouble or a server error; worth retrying
+    Transient(String),
+    /// The refresh token was refused; the user has to sign in again
+    Rejected(String),
+}
+
+impl std::fmt::Display for RefreshError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RefreshError::Transient(e) | RefreshError::Rejected(e) => write!(f, "Token refresh failed: {}", e),
+        }
+    }
+}
+
+/// Where token refreshes are sent: Firebase's token service, or the Auth
+/// emulator's copy of it when `emulator_host` is set
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RefreshConfig {
+    pub api_key: String,
+    pub emulator_host: Option<String>,
+}
+
+impl RefreshConfig {
+    /// `VITE_FIREBASE_API_KEY` and, for the emulator, `FIREBASE_AUTH_EMULATOR_HOST`
+    pub fn from_env() -> Result<Self, RefreshError> {
+        let api_key = std::env::var("VITE_FIREBASE_API_KEY")
+            .map_err(|_| RefreshError::Transient("Missing VITE_FIREBASE_API_KEY".to_string()))?;
+        Ok(Self {
+            api_key,
+            emulator_host: std::env::var("FIREBASE_AUTH_EMULATOR_HOST").ok(),
+        })
+    }
+
+    fn token_url(&self) -> String {
+        // The Auth emulator serves the token endpoint under its own host
+        match &self.emulator_host {
+            Some(host) => format!(
+                "http://{}/securetoken.googleapis.com/v1/token?key={}",
+                host, self.api_key
+            ),
+            None => format!(
+                "https://securetoken.googleapis.com/v1/token?key={}",
+                self.api_key
+            ),
+        }
+    }
+}
+
+/// Attempts at one refresh while the token service answers 408 or 429
+const REFRESH_ATTEMPTS: u32 = 3;
+const REFRESH_RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// How a failed refresh response is treated. Only a refusal of the refresh
+/// token itself (400, 401, 403) signs the user out; throttling, timeouts and
+/// server errors may pass.
+fn refresh_error(status: reqwest::StatusCode, text: String) -> RefreshError {
+    match status.as_u16() {
+        400 | 401 | 403 => RefreshError::Rejected(text),
+        _ => RefreshError::Transient(text),
+    }
+}
+
+// The signed-in user, shared by every `AuthService` in the process so that
+// a refresh in the background is seen by the UI and by API calls alike
+static CURRENT_USER: Lazy<Arc<Mutex<Option<User>>>> =
+    Lazy::new(|| Arc::new(Mutex::new(AuthService::load_from_storage())));
+
 pub struct AuthService {
     current_user: Arc<Mutex<Option<User>>>,
     provider_keys: Arc<Mutex<Vec<EncryptedKey>>>,
+    persist: bool,
+    /// Token service to refresh against; read from the environment when unset
+    refresh_config: Option<RefreshConfig>,
 }
 
 impl AuthService {
     pub fn new() -> Self {
-        // Try to load user from localStorage
-        let stored_user = Self::load_from_storage();
         Self {
-            current_user: Arc::new(Mutex::new(stored_user)),
+            current_user: Arc::clone(&CURRENT_USER),
             provider_keys: Arc::new(Mutex::new(Vec::new())),
+            persist: true,
+            refresh_config: None,
+        }
+    }
+
+    /// A session of its own that never touches `user.json`
+    #[cfg(test)]
+    pub(crate) fn ephemeral(user: Option<User>) -> Self {
+        Self {
+            current_user: Arc::new(Mutex::new(user)),
+            provider_keys: Arc::new(Mutex::new(Vec::new())),
+            persist: false,
+            refresh_config: None,
+        }
+    }
+
+    /// Refresh tokens against `config` instead of the environment's service
+    pub fn with_refresh_config(mut self, config: RefreshConfig) -> Self {
+        self.refresh_config = Some(config);
+        self
+    }
+
+    pub(crate) fn refresh_config(&self) -> Result<RefreshConfig, RefreshError> {
+        match &self.refresh_config {
+            Some(config) => Ok(config.clone()),
+            None => RefreshConfig::from_env(),
         }
     }
 
     fn get_data_dir() -> std::path::PathBuf {
-        let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
-        let data_dir = std::path::Path::new(&home).join(".local/share/kael-os");
-        // Ensure directory exists
-        let _ = std::fs::create_dir_all(&data_dir);
-        data_dir
+        crate::profiles::data_dir()
     }
 
     fn get_user_file_path() -> std::path::PathBuf {
@@ -83,13 +292,10 @@ impl AuthService {
         let user_path = Self::get_user_file_path();
         if let Ok(user_json) = std::fs::read_to_string(&user_path) {
             if let Ok(user) = serde_json::from_str::<User>(&user_json) {
-                // Check if token expired (just log, don't refresh here to avoid nested runtime)
+                // An expired token is refreshed by the session manager
                 if let Some(expires_in) = user.expires_in {
-                    let now = chrono::Utc::now().timestamp();
-                    if now >= expires_in - 300 {  // Check if expiring within 5 min
-                        log::warn!("⚠️  Token expired or expiring soon - user should re-login");
-                        // Don't refresh here - causes nested runtime panic
-                        // The app will handle token refresh via async use_effect
+                    if chrono::Utc::now().timestamp() >= expires_in {
+                        log::info!("Stored session token has expired; it will be refreshed");
                     }
                 }
                 return Some(user);
@@ -110,33 +316,40 @@ impl AuthService {
     }
 
     pub async fn refresh_token_public(refresh_token: &str) -> Result<User, String> {
-        Self::refresh_id_token(refresh_token).await
+        let config = RefreshConfig::from_env().map_err(|e| e.to_string())?;
+        Self::refresh_id_token(&config, refresh_token).await.map_err(|e| e.to_string())
     }
 
-    async fn refresh_id_token(refresh_token: &str) -> Result<User, String> {
-        let api_key = std::env::var("VITE_FIREBASE_API_KEY")
-            .map_err(|_| "Missing VITE_FIREBASE_API_KEY".to_string())?;
-
-        let url = format!(
-            "https://securetoken.googleapis.com/v1/token?key={}",
-            api_key
-        );
-
+    /// Trade a refresh token for a new ID token. The result carries no
+    /// profile; see `apply_refresh`. A throttled (429) or timed out (408)
+    /// request is retried with backoff before giving up.
+    pub(crate) async fn refresh_id_token(config: &RefreshConfig, refresh_token: &str) -> Result<User, RefreshError> {
+        let url = config.token_url();
         let body = serde_json::json!({
             "grant_type": "refresh_token",
             "refresh_token": refresh_token
         });
 
         let client = reqwest::Client::new();
-        let resp = client.post(&url).json(&body).send().await
-            .map_err(|e| format!("Token refresh failed: {}", e))?;
-
-        if !resp.status().is_success() {
+        let mut attempt = 0;
+        let resp = loop {
+            attempt += 1;
+            let resp = client.post(&url).json(&body).send().await
+                .map_err(|e| RefreshError::Transient(e.to_string()))?;
             let status = resp.status();
+            if status.is_success() {
+                break resp;
+            }
+            let retry = matches!(status.as_u16(), 408 | 429) && attempt < REFRESH_ATTEMPTS;
             let text = resp.text().await.unwrap_or_default();
-            log::error!("Token refresh error {}: {}", status, text);
-            return Err(format!("Token refresh failed: {}", text));
-        }
+            if !retry {
+                log::error!("Token refresh error {}: {}", status, text);
+                return Err(refresh_error(status, text));
+            }
+            let delay = REFRESH_RETRY_DELAY * 2u32.pow(attempt - 1);
+            log::warn!("Token refresh got {}, retrying in {:?}", status, delay);
+            tokio::time::sleep(delay).await;
+        };
 
         #[derive(Deserialize)]
         struct RefreshResponse {
@@ -147,7 +360,7 @@ impl AuthService {
         }
 
         let data: RefreshResponse = resp.json().await
-            .map_err(|e| format!("Failed to parse refresh response: {}", e))?;
+            .map_err(|e| RefreshError::Transient(format!("Failed to parse refresh response: {}", e)))?;
 
         let expires_in = data.expires_in.parse::<i64>().ok()
             .map(|sec| chrono::Utc::now().timestamp() + sec);
@@ -169,9 +382,30 @@ impl AuthService {
 
     pub fn set_user(&self, user: User) {
         if let Ok(mut u) = self.current_user.lock() {
+            if self.persist {
+                Self::save_to_storage(&user);
+            }
+            *u = Some(user.clone());
+        }
+        crate::session::notify(crate::session::SessionEvent::SignedIn(user));
+    }
+
+    /// Swap in refreshed tokens, keeping the profile of the signed-in user.
+    /// `None` if that user signed out in the meantime.
+    pub(crate) fn apply_refresh(&self, refreshed: User) -> Option<User> {
+        let mut u = self.current_user.lock().ok()?;
+        let current = u.as_ref().filter(|user| user.uid == refreshed.uid)?;
+        let user = User {
+            id_token: refreshed.id_token,
+            refresh_token: refreshed.refresh_token.or_else(|| current.refresh_token.clone()),
+            expires_in: refreshed.expires_in,
+            ..current.clone()
+        };
+        if self.persist {
             Self::save_to_storage(&user);
-            *u = Some(user);
         }
+        *u = Some(user.clone());
+        Some(user)
     }
 
     pub fn get_user(&self) -> Option<User> {
@@ -184,14 +418,21 @@ impl AuthService {
 
     pub fn logout(&self) {
         if let Ok(mut u) = self.current_user.lock() {
-            let user_path = Self::get_user_file_path();
-            if let Err(e) = std::fs::remove_file(&user_path) {
-                log::warn!("Failed to remove user file during logout: {}", e);
-            } else {
-                log::info!("User logged out and session cleared");
+            if self.persist {
+                let user_path = Self::get_user_file_path();
+                if let Err(e) = std::fs::remove_file(&user_path) {
+                    log::warn!("Failed to remove user file during logout: {}", e);
+                } else {
+                    log::info!("User logged out and session cleared");
+                }
             }
             *u = None;
         }
+        // The data key belongs to the process-wide session
+        if self.persist {
+            lock_secrets();
+        }
+        crate::session::notify(crate::session::SessionEvent::SignedOut);
     }
 
     pub fn is_authenticated(&self) -> bool {
@@ -229,6 +470,8 @@ impl Clone for AuthService {
         Self {
             current_user: Arc::clone(&self.current_user),
             provider_keys: Arc::clone(&self.provider_keys),
+            persist: self.persist,
+            refresh_config: self.refresh_config.clone(),
         }
     }
 }
@@ -384,7 +627,7 @@ pub async fn firebase_sign_in_email_password(email: &str, password: &str) -> Res
 }
 
 // Exchange Google authorization code for ID token
-pub async fn exchange_google_code_for_token(code: &str) -> Result<User, String> {
+pub async fn exchange_google_code_for_token(grant: &AuthorizationCode) -> Result<User, String> {
     let api_key = std::env::var("VITE_FIREBASE_API_KEY")
         .map_err(|_| "Missing VITE_FIREBASE_API_KEY".to_string())?;
 
@@ -392,15 +635,15 @@ pub async fn exchange_google_code_for_token(code: &str) -> Result<User, String>
         "384654392238-k02b3cvemoee9uq87pa3a3bk0gf1hbnk.apps.googleusercontent.com".to_string()
     });
     let client_secret = std::env::var("GOOGLE_OAUTH_CLIENT_SECRET").ok();
-    let redirect_uri = std::env::var("GOOGLE_OAUTH_REDIRECT_URI")
-        .unwrap_or_else(|_| "http://localhost:5173/auth/google/callback".to_string());
+    let redirect_uri = grant.redirect_uri.clone();
 
     let client = reqwest::Client::new();
     let mut params = vec![
-        ("code", code.to_string()),
+        ("code", grant.code.clone()),
         ("client_id", client_id.clone()),
         ("redirect_uri", redirect_uri.clone()),
         ("grant_type", "authorization_code".to_string()),
+        ("code_verifier", grant.code_verifier.clone()),
     ];
 
     if let Some(secret) = client_secret.clone() {
@@ -494,7 +737,7 @@ async fn fetch_google_profile(access_token: &str) -> Result<serde_json::Value, S
 }
 
 // Exchange GitHub authorization code for access token
-pub async fn exchange_github_code_for_token(code: &str) -> Result<User, String> {
+pub async fn exchange_github_code_for_token(grant: &AuthorizationCode) -> Result<User, String> {
     let api_key = std::env::var("VITE_FIREBASE_API_KEY")
         .map_err(|_| "Missing VITE_FIREBASE_API_KEY".to_string())?;
 
@@ -502,16 +745,16 @@ pub async fn exchange_github_code_for_token(code: &str) -> Result<User, String>
         .unwrap_or_else(|_| "Ov23liqnLH8iIZOZ8sMT".to_string());
     let client_secret = std::env::var("GITHUB_OAUTH_CLIENT_SECRET")
         .map_err(|_| "Missing GITHUB_OAUTH_CLIENT_SECRET".to_string())?;
-    let redirect_uri = std::env::var("GITHUB_OAUTH_REDIRECT_URI")
-        .unwrap_or_else(|_| "http://localhost:5173/auth/github/callback".to_string());
+    let redirect_uri = grant.redirect_uri.clone();
 
     let client = reqwest::Client::new();
 
     let params = [
         ("client_id", client_id.clone()),
         ("client_secret", client_secret.clone()),
-        ("code", code.to_string()),
+        ("code", grant.code.clone()),
         ("redirect_uri", redirect_uri.clone()),
+        ("code_verifier", grant.code_verifier.clone()),
     ];
 
     let response = client
@@ -559,32 +802,34 @@ fn base64_decode(s: &str) -> Result<String, Box<dyn std::error::Error>> {
     use base64::{engine::general_purpose, Engine as _};
     let bytes = general_purpose::URL_SAFE_NO_PAD.decode(s)?;
     Ok(String::from_utf8(bytes)?)
-} // Build Google OAuth URL for Firebase
-pub fn get_google_oauth_url() -> Result<String, String> {
+}
+
+// Build Google OAuth URL for a loopback sign-in flow
+pub fn get_google_oauth_url(flow: &OAuthFlow) -> Result<String, String> {
     let client_id = std::env::var("GOOGLE_OAUTH_CLIENT_ID").unwrap_or_else(|_| {
         "384654392238-k02b3cvemoee9uq87pa3a3bk0gf1hbnk.apps.googleusercontent.com".to_string()
     });
-    let redirect_url = std::env::var("GOOGLE_OAUTH_REDIRECT_URI")
-        .unwrap_or_else(|_| "http://localhost:5173/auth/google/callback".to_string());
 
     Ok(format!(
-        "https://accounts.google.com/o/oauth2/v2/auth?client_id={}&redirect_uri={}&response_type=code&scope=openid%20email%20profile&access_type=offline&prompt=consent",
+        "https://accounts.google.com/o/oauth2/v2/auth?client_id={}&redirect_uri={}&response_type=code&scope=openid%20email%20profile&access_type=offline&prompt=consent&state={}&code_challenge={}&code_challenge_method=S256",
         client_id,
-        urlencoding::encode(&redirect_url)
+        urlencoding::encode(&flow.redirect_uri),
+        flow.state,
+        flow.code_challenge
     ))
 }
 
-// Build GitHub OAuth URL for Firebase
-pub fn get_github_oauth_url() -> Result<String, String> {
+// Build GitHub OAuth URL for a loopback sign-in flow
+pub fn get_github_oauth_url(flow: &OAuthFlow) -> Result<String, String> {
     let client_id = std::env::var("GITHUB_OAUTH_CLIENT_ID")
         .unwrap_or_else(|_| "Ov23liqnLH8iIZOZ8sMT".to_string());
-    let redirect_url = std::env::var("GITHUB_OAUTH_REDIRECT_URI")
-        .unwrap_or_else(|_| "http://localhost:5173/auth/github/callback".to_string());
 
     Ok(format!(
-        "https://github.com/login/oauth/authorize?client_id={}&redirect_uri={}&scope=user:email",
+        "https://github.com/login/oauth/authorize?client_id={}&redirect_uri={}&response_type=code&scope=user:email&state={}&code_challenge={}&code_challenge_method=S256",
         client_id,
-        urlencoding::encode(&redirect_url)
+        urlencoding::encode(&flow.redirect_uri),
+        flow.state,
+        flow.code_challenge
     ))
 }
 pub async fn firebase_sign_up_email_password(email: &str, password: &str) -> Result<User, String> {
@@ -649,3 +894,63 @@ pub async fn firebase_sign_up_email_password(email: &str, password: &str) -> Res
         expires_in,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(uid: &str, id_token: &str) -> User {
+        User {
+            uid: uid.to_string(),
+            email: format!("{}@example.com", uid),
+            name: uid.to_string(),
+            photo_url: None,
+            id_token: id_token.to_string(),
+            refresh_token: None,
+            expires_in: None,
+        }
+    }
+
+    // How secrets were stored before envelopes
+    fn xor_encrypt(key: &str, data: &str) -> String {
+        let k = key.as_bytes();
+        let enc: Vec<u8> = data.bytes().enumerate().map(|(i, b)| b ^ k[i % k.len()]).collect();
+        general_purpose::STANDARD.encode(enc)
+    }
+
+    #[test]
+    fn test_secrets() {
+        let alice = user("alice", "eyJhbGciOiJSUzI1NiJ9.first-token");
+        let legacy = xor_encrypt(&alice.id_token, "sk-proj-4f9a8b7c6d5e");
+        assert!(is_legacy_secret(&legacy));
+        assert_eq!(decrypt_secret(&alice, &legacy).as_deref(), Some("sk-proj-4f9a8b7c6d5e"));
+        // After the id_token rotates the blob reads as garbage, which is rejected
+        let rotated = user("alice", "Zm9vYmFyYmF6cXV4.second-token-7Q2");
+        assert_eq!(decrypt_secret(&rotated, &legacy), None);
+
+        // The unlocked key is per user, not per id_token
+        let slot = Some(("alice".to_string(), DataKey::generate()));
+        let key = key_for(&slot, &rotated).unwrap();
+        assert!(key_for(&slot, &user("bob", "t")).is_none());
+        assert!(key_for(&None, &alice).is_none());
+
+        let sealed = seal_with(key, "sk-new");
+        assert!(!is_legacy_secret(&sealed));
+        assert_eq!(open_with(key, &sealed).as_deref(), Some("sk-new"));
+        assert_eq!(open_with(&DataKey::generate(), &sealed), None);
+    }
+    #[test]
+    fn test_refresh_error_classification() {
+        use reqwest::StatusCode;
+        for status in [StatusCode::BAD_REQUEST, StatusCode::UNAUTHORIZED, StatusCode::FORBIDDEN] {
+            assert!(matches!(refresh_error(status, String::new()), RefreshError::Rejected(_)));
+        }
+        for status in [
+            StatusCode::REQUEST_TIMEOUT,
+            StatusCode::TOO_MANY_REQUESTS,
+            StatusCode::SERVICE_UNAVAILABLE,
+        ] {
+            assert!(matches!(refresh_error(status, String::new()), RefreshError::Transient(_)));
+        }
+    }
+}
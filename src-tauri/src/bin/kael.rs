@@ -0,0 +1,689 @@
+//! `kael`: Kael from the command line, for SSH sessions and scripts.
+//!
+//! Uses the same routing, storage and services as the desktop app, without
+//! the GUI. `--json` prints machine-readable output instead of text.
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use kael_os::crypto::stream::{self, Progress};
+use kael_os::crypto::{Params, Secret};
+use kael_os::llm::{self, LLMProvider, LLMRequest};
+use kael_os::profiles::{self, ProfileKind};
+use kael_os::publish::{self, PublishOptions, UploadStatus};
+use kael_os::services::{app_projects, command_rewriter, first_launch, ollama_manager};
+use kael_os::state::{AppProject, AppStatus};
+use serde_json::json;
+
+const USAGE: &str = "Usage: kael [--json] [--profile NAME] <command>
+
+Commands:
+  ask [--provider NAME] [--model MODEL] <question>   Ask Kael (streams from the local model)
+  rewrite <command>                                  Adapt a command to this system
+  history search <text> [--limit N]                  Search past conversations
+  projects list [--archived | --all]                 List tracked projects
+  projects add <name> [--description TEXT] [--status want|making|testing|done]
+  models list                                        List local Ollama models
+  models pull <model>                                Download an Ollama model
+  context show                                       Show the detected system context
+  context refresh                                    Detect the system again and save it
+  encrypt <file> [--output PATH]                     Encrypt a file to <file>.kaelenc
+  decrypt <file.kaelenc> [--output PATH]             Decrypt a .kaelenc file
+  mcp                                                Serve Kael's tools to an MCP client on stdin/stdout
+  profiles list                                      List profiles; * marks the one in use
+  profiles add <name> [--local]                      Add a profile (--local: never uses the cloud)
+  profiles use <name>                                Start the desktop app and kael in this profile
+  publish [--manifest PATH] [--dry-run] [--no-build] [--force]
+                                                     Build, sign and upload the release in release.json
+
+--profile (or KAEL_PROFILE) runs one command in another profile.
+encrypt and decrypt take the passphrase from KAEL_PASSPHRASE, or ask for it.
+publish resumes an interrupted release; --force uploads everything again.";
+
+/// Providers tried after the first one, as in the chat panel
+const FALLBACK_PROVIDERS: [LLMProvider; 4] = [
+    LLMProvider::Mistral,
+    LLMProvider::Gemini,
+    LLMProvider::Copilot,
+    LLMProvider::CopilotAgent,
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Command {
+    Ask {
+        question: String,
+        provider: Option<LLMProvider>,
+        model: Option<String>,
+    },
+    Rewrite(String),
+    HistorySearch {
+        query: String,
+        limit: usize,
+    },
+    ProjectsList {
+        archived: Option<bool>,
+    },
+    ProjectsAdd {
+        name: String,
+        description: String,
+        status: AppStatus,
+    },
+    ModelsList,
+    ModelsPull(String),
+    ContextShow,
+    ContextRefresh,
+    Encrypt {
+        input: PathBuf,
+        output: Option<PathBuf>,
+    },
+    Decrypt {
+        input: PathBuf,
+        output: Option<PathBuf>,
+    },
+    Mcp,
+    ProfilesList,
+    ProfilesAdd {
+        name: String,
+        kind: ProfileKind,
+    },
+    ProfilesUse(String),
+    Publish {
+        manifest: PathBuf,
+        options: PublishOptions,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Cli {
+    json: bool,
+    profile: Option<String>,
+    command: Command,
+}
+
+fn parse_provider(name: &str) -> Result<LLMProvider, String> {
+    let provider = match name.to_ascii_lowercase().as_str() {
+        "ollama" | "local" => LLMProvider::Ollama,
+        "mistral" => LLMProvider::Mistral,
+        "gemini" => LLMProvider::Gemini,
+        "copilot" => LLMProvider::Copilot,
+        "copilot-cli" | "copilotagent" => LLMProvider::CopilotAgent,
+        _ => return Err(format!("Unknown provider: {}", name)),
+    };
+    Ok(provider)
+}
+
+fn parse_status(name: &str) -> Result<AppStatus, String> {
+    serde_json::from_value(json!(name.to_ascii_lowercase()))
+        .map_err(|_| format!("Unknown status: {} (want, making, testing or done)", name))
+}
+
+fn parse_args(args: &[String]) -> Result<Cli, String> {
+    let mut json = false;
+    let mut words = Vec::new();
+    let mut options: Vec<(String, Option<String>)> = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--archived" | "--all" | "--local" | "--dry-run" | "--no-build" | "--force" | "--help" | "-h" => {
+                options.push((arg.clone(), None))
+            }
+            "--provider" | "--model" | "--limit" | "--description" | "--status" | "--output" | "--profile"
+            | "--manifest" => {
+                let value = iter.next().ok_or_else(|| format!("{} needs a value", arg))?;
+                options.push((arg.clone(), Some(value.clone())));
+            }
+            flag if flag.starts_with("--") => return Err(format!("Unknown option: {}", flag)),
+            _ => words.push(arg.as_str()),
+        }
+    }
+    if options.iter().any(|(name, _)| name == "--help" || name == "-h") {
+        return Err(USAGE.to_string());
+    }
+    let option = |name: &str| {
+        options
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.clone().unwrap_or_default())
+    };
+    let rest = |from: usize| words.get(from..).map(|w| w.join(" ")).filter(|w| !w.trim().is_empty());
+
+    let command = match words.as_slice() {
+        ["ask", ..] => Command::Ask {
+            question: rest(1).ok_or("ask needs a question")?,
+            provider: option("--provider").map(|p| parse_provider(&p)).transpose()?,
+            model: option("--model"),
+        },
+        ["rewrite", ..] => Command::Rewrite(rest(1).ok_or("rewrite needs a command")?),
+        ["history", "search", ..] => Command::HistorySearch {
+            query: rest(2).ok_or("history search needs some text")?,
+            limit: option("--limit")
+                .map(|l| l.parse().map_err(|_| format!("Invalid --limit: {}", l)))
+                .transpose()?
+                .unwrap_or(20),
+        },
+        ["projects", "list"] => Command::ProjectsList {
+            archived: match (option("--archived"), option("--all")) {
+                (Some(_), _) => Some(true),
+                (None, Some(_)) => None,
+                (None, None) => Some(false),
+            },
+        },
+        ["projects", "add", ..] => Command::ProjectsAdd {
+            name: rest(2).ok_or("projects add needs a name")?,
+            description: option("--description").unwrap_or_default(),
+            status: option("--status")
+                .map(|s| parse_status(&s))
+                .transpose()?
+                .unwrap_or(AppStatus::Want),
+        },
+        ["models", "list"] => Command::ModelsList,
+        ["models", "pull", model] => Command::ModelsPull(model.to_string()),
+        ["context", "show"] => Command::ContextShow,
+        ["context", "refresh"] => Command::ContextRefresh,
+        ["encrypt", file] => Command::Encrypt {
+            input: PathBuf::from(file),
+            output: option("--output").map(PathBuf::from),
+        },
+        ["decrypt", file] => Command::Decrypt {
+            input: PathBuf::from(file),
+            output: option("--output").map(PathBuf::from),
+        },
+        ["mcp"] => Command::Mcp,
+        ["profiles", "list"] => Command::ProfilesList,
+        ["profiles", "add", ..] => Command::ProfilesAdd {
+            name: rest(2).ok_or("profiles add needs a name")?,
+            kind: if option("--local").is_some() {
+                ProfileKind::Local
+            } else {
+                ProfileKind::Cloud
+            },
+        },
+        ["profiles", "use", ..] => Command::ProfilesUse(rest(2).ok_or("profiles use needs a name")?),
+        ["publish"] => Command::Publish {
+            manifest: PathBuf::from(option("--manifest").unwrap_or_else(|| "release.json".to_string())),
+            options: PublishOptions {
+                dry_run: option("--dry-run").is_some(),
+                skip_build: option("--no-build").is_some(),
+                force: option("--force").is_some(),
+            },
+        },
+        _ => return Err(USAGE.to_string()),
+    };
+    Ok(Cli {
+        json,
+        profile: option("--profile"),
+        command,
+    })
+}
+
+fn print_json(value: &serde_json::Value) {
+    println!("{}", serde_json::to_string_pretty(value).unwrap_or_default());
+}
+
+/// Ask, streaming from Ollama when it is the first provider and the output
+/// is text; otherwise (or when Ollama fails) go through the fallback chain
+async fn ask(question: String, provider: Option<LLMProvider>, model: Option<String>, json: bool) -> Result<(), String> {
+    let request = LLMRequest {
+        provider: provider.unwrap_or(LLMProvider::Ollama),
+        model: model.unwrap_or_default(),
+        prompt: question.clone(),
+        api_key: None,
+        system: Some(llm::get_kael_system_prompt()),
+    };
+
+    let mut streamed = None;
+    if !json && request.provider == LLMProvider::Ollama {
+        let mut stdout = std::io::stdout();
+        match llm::stream_local(&request, |chunk| {
+            let _ = stdout.write_all(chunk.as_bytes());
+            let _ = stdout.flush();
+        })
+        .await
+        {
+            Ok(reply) => {
+                println!();
+                streamed = Some((LLMProvider::Ollama, reply));
+            }
+            Err(e) => log::warn!("Local model unavailable, trying cloud providers: {}", e),
+        }
+    }
+    let (provider, content) = match streamed {
+        Some(reply) => reply,
+        None => {
+            // A local-only profile has nothing to fall back to
+            let fallback = if profiles::is_local() {
+                Vec::new()
+            } else {
+                FALLBACK_PROVIDERS.into_iter().map(|p| (p, None)).collect()
+            };
+            let response = llm::send_request_with_fallback(request, None, fallback).await?;
+            if json {
+                print_json(&json!({ "provider": response.provider, "content": response.content }));
+            } else {
+                println!("{}", response.content);
+            }
+            (response.provider, response.content)
+        }
+    };
+
+    // Keep the exchange in the local history, like the desktop chat
+    if let Ok(storage) = kael_os::db::shared() {
+        let _ = kael_os::db::add_message(&storage, "user", &question).await;
+        let _ = kael_os::db::add_message(&storage, "model", &content).await;
+    }
+    log::debug!("Answered by {:?}", provider);
+    Ok(())
+}
+
+/// The passphrase from `KAEL_PASSPHRASE`, or asked for on the terminal
+fn read_passphrase(confirm: bool) -> Result<String, String> {
+    if let Ok(passphrase) = std::env::var("KAEL_PASSPHRASE") {
+        if !passphrase.is_empty() {
+            return Ok(passphrase);
+        }
+    }
+    let ask = |prompt: &str| -> Result<String, String> {
+        eprint!("{}", prompt);
+        let _ = std::io::stderr().flush();
+        let hidden = std::process::Command::new("stty")
+            .arg("-echo")
+            .status()
+            .map(|s| s.success())
+            .unwrap_or(false);
+        let mut line = String::new();
+        let read = std::io::stdin().read_line(&mut line);
+        if hidden {
+            let _ = std::process::Command::new("stty").arg("echo").status();
+            eprintln!();
+        }
+        read.map_err(|e| format!("Failed to read passphrase: {}", e))?;
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    };
+    let passphrase = ask("Passphrase: ")?;
+    if passphrase.is_empty() {
+        return Err("Empty passphrase".to_string());
+    }
+    if confirm && ask("Passphrase (again): ")? != passphrase {
+        return Err("Passphrases do not match".to_string());
+    }
+    Ok(passphrase)
+}
+
+/// Encrypt or decrypt `input` into `output`, with progress on stderr
+fn transform_file(encrypt: bool, input: &Path, output: &Path, json: bool) -> Result<(), String> {
+    if output.exists() {
+        return Err(format!("{} already exists", output.display()));
+    }
+    let passphrase = read_passphrase(encrypt)?;
+    let secret = Secret::Passphrase(&passphrase);
+    let label = if encrypt { "Encrypting" } else { "Decrypting" };
+    let show = |progress: Progress| {
+        if json {
+            return;
+        }
+        match progress.percent() {
+            Some(percent) => eprint!("\r{} {}: {:>3}%", label, input.display(), percent),
+            None => eprint!("\r{} {}: {} MiB", label, input.display(), progress.done >> 20),
+        }
+    };
+    let bytes = if encrypt {
+        stream::encrypt_file(input, output, secret, &Params::default(), show)
+    } else {
+        stream::decrypt_file(input, output, secret, show)
+    };
+    if !json {
+        eprintln!();
+    }
+    let bytes = bytes?;
+    if json {
+        print_json(&json!({ "input": input, "output": output, "bytes": bytes }));
+    } else {
+        println!("{}", output.display());
+    }
+    Ok(())
+}
+
+async fn run(cli: Cli) -> Result<(), String> {
+    let json = cli.json;
+    let refresh = cli.command == Command::ContextRefresh;
+    match cli.command {
+        Command::Ask {
+            question,
+            provider,
+            model,
+        } => ask(question, provider, model, json).await,
+
+        Command::Rewrite(input) => {
+            let context = command_rewriter::build_user_context().await?;
+            let (command, corrections) = command_rewriter::rewrite_command(&input, &context);
+            if json {
+                print_json(&json!({ "command": command, "corrections": corrections }));
+            } else {
+                println!("{}", command);
+                for correction in corrections {
+                    eprintln!("# {}", correction);
+                }
+            }
+            Ok(())
+        }
+
+        Command::HistorySearch { query, limit } => {
+            let storage = kael_os::db::shared()?;
+            let conversations = storage
+                .sessions()
+                .search(&query)
+                .await
+                .map_err(|e| format!("Failed to search conversations: {}", e))?;
+            let messages = storage
+                .messages()
+                .search(&query, limit)
+                .await
+                .map_err(|e| format!("Failed to search messages: {}", e))?;
+            if json {
+                print_json(&json!({ "conversations": conversations, "messages": messages }));
+                return Ok(());
+            }
+            for conversation in &conversations {
+                println!("[{}] {}", conversation.id, conversation.title);
+            }
+            for message in &messages {
+                let line = message
+                    .text
+                    .lines()
+                    .find(|l| l.contains(&query))
+                    .or_else(|| message.text.lines().next())
+                    .unwrap_or_default();
+                println!(
+                    "{} {:>9}: {}",
+                    message.timestamp.format("%Y-%m-%d %H:%M"),
+                    message.role,
+                    line.trim()
+                );
+            }
+            if conversations.is_empty() && messages.is_empty() {
+                eprintln!("No matches for \"{}\"", query);
+            }
+            Ok(())
+        }
+
+        Command::ProjectsList { archived } => {
+            let storage = kael_os::db::shared()?;
+            let projects = match archived {
+                Some(true) => app_projects::get_archived_projects(&storage).await?,
+                Some(false) => app_projects::get_active_projects(&storage).await?,
+                None => app_projects::get_projects_local(&storage).await?,
+            };
+            if json {
+                print_json(&json!(projects));
+                return Ok(());
+            }
+            for project in projects {
+                println!("{:<14} {:<24} {}", project.status.label(), project.name, project.description);
+            }
+            Ok(())
+        }
+        Command::ProjectsAdd {
+            name,
+            description,
+            status,
+        } => {
+            let storage = kael_os::db::shared()?;
+            let project = AppProject::new(name, description, status);
+            app_projects::save_project_local(&storage, &project).await?;
+            if json {
+                print_json(&json!(project));
+            } else {
+                println!("Added {} ({})", project.name, project.id);
+            }
+            Ok(())
+        }
+
+        Command::ModelsList => {
+            let models = ollama_manager::get_available_models().await?;
+            if json {
+                print_json(&json!(models));
+            } else {
+                for model in models {
+                    println!("{}", model);
+                }
+            }
+            Ok(())
+        }
+        Command::ModelsPull(model) => {
+            // ollama draws its own progress bar; keep stdout clean for --json
+            let mut pull = std::process::Command::new("ollama");
+            pull.arg("pull").arg(&model);
+            if json {
+                pull.stdout(std::process::Stdio::null());
+            }
+            let status = pull.status().map_err(|e| format!("Failed to run ollama: {}", e))?;
+            if !status.success() {
+                return Err(format!("ollama pull {} failed", model));
+            }
+            if json {
+                print_json(&json!({ "model": model, "pulled": true }));
+            }
+            Ok(())
+        }
+
+        Command::ContextShow | Command::ContextRefresh => {
+            let context = if refresh {
+                first_launch::refresh_context_standalone().await?
+            } else {
+                first_launch::get_or_init_context_standalone().await?
+            };
+            if json {
+                print_json(&json!(context));
+            } else {
+                println!("{}", context.system_prompt.trim_end());
+            }
+            Ok(())
+        }
+
+        Command::Encrypt { input, output } => {
+            let output = output.unwrap_or_else(|| stream::encrypted_path(&input));
+            transform_file(true, &input, &output, json)
+        }
+        Command::Decrypt { input, output } => {
+            let output = output
+                .or_else(|| stream::decrypted_path(&input))
+                .ok_or("decrypt needs --output for files without a .kaelenc extension")?;
+            transform_file(false, &input, &output, json)
+        }
+
+        Command::Mcp => {
+            let ctx = kael_os::api::handlers::ApiContext {
+                storage: kael_os::db::shared()?,
+            };
+            kael_os::mcp::serve_stdio(ctx).await
+        }
+
+        Command::ProfilesList => {
+            let registry = profiles::registry();
+            let current = profiles::active();
+            if json {
+                let list: Vec<_> = registry
+                    .list()
+                    .iter()
+                    .map(|p| {
+                        json!({
+                            "id": p.id,
+                            "name": p.name,
+                            "kind": p.kind,
+                            "active": p.id == current.id,
+                            "data_dir": registry.data_dir(p),
+                        })
+                    })
+                    .collect();
+                print_json(&json!(list));
+                return Ok(());
+            }
+            for profile in registry.list() {
+                let mark = if profile.id == current.id { "*" } else { " " };
+                let dir = registry.data_dir(profile);
+                println!("{} {:<20} {:<6} {}", mark, profile.name, profile.kind.as_str(), dir.display());
+            }
+            Ok(())
+        }
+        Command::ProfilesAdd { name, kind } => {
+            let profile = profiles::registry().create(&name, kind)?;
+            if json {
+                print_json(&json!(profile));
+            } else {
+                println!("Added {} profile {} ({})", profile.kind.as_str(), profile.name, profile.id);
+            }
+            Ok(())
+        }
+        Command::ProfilesUse(name) => {
+            let profile = profiles::registry().set_active(&name)?;
+            if json {
+                print_json(&json!(profile));
+            } else {
+                println!("Now using {}", profile.name);
+            }
+            Ok(())
+        }
+
+        Command::Publish { manifest, options } => {
+            let report = publish::publish(&manifest, &options, |step| {
+                if !json {
+                    eprintln!("{}", step);
+                }
+            })
+            .await?;
+            if json {
+                print_json(&json!(report));
+                return Ok(());
+            }
+            for upload in &report.uploads {
+                let status = match upload.status {
+                    UploadStatus::Uploaded => "uploaded",
+                    UploadStatus::Unchanged => "unchanged",
+                    UploadStatus::Planned => "planned",
+                };
+                println!("{:<10} {:<40} {}", status, upload.name, upload.target);
+            }
+            if report.dry_run {
+                println!("Dry run of {}: nothing was built, signed or uploaded", report.version);
+            } else {
+                println!("Published {}", report.version);
+            }
+            Ok(())
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let cli = match parse_args(&args) {
+        Ok(cli) => cli,
+        Err(message) => {
+            eprintln!("{}", message);
+            return ExitCode::from(2);
+        }
+    };
+    if let Some(name) = &cli.profile {
+        if let Err(e) = profiles::select(name) {
+            eprintln!("kael: {}", e);
+            return ExitCode::FAILURE;
+        }
+    }
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("kael: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, String> {
+        parse_args(&args.iter().map(|a| a.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let cli = parse(&["--json", "ask", "why", "is", "pacman", "locked?", "--provider", "gemini"]).unwrap();
+        assert!(cli.json);
+        assert_eq!(
+            cli.command,
+            Command::Ask {
+                question: "why is pacman locked?".to_string(),
+                provider: Some(LLMProvider::Gemini),
+                model: None,
+            }
+        );
+
+        assert_eq!(
+            parse(&["rewrite", "yay -S htop"]).unwrap().command,
+            Command::Rewrite("yay -S htop".to_string())
+        );
+        assert_eq!(
+            parse(&["history", "search", "mirrorlist", "--limit", "5"]).unwrap().command,
+            Command::HistorySearch {
+                query: "mirrorlist".to_string(),
+                limit: 5
+            }
+        );
+        assert_eq!(
+            parse(&["projects", "list", "--all"]).unwrap().command,
+            Command::ProjectsList { archived: None }
+        );
+        assert_eq!(
+            parse(&["projects", "add", "Kael", "notes", "--status", "making"]).unwrap().command,
+            Command::ProjectsAdd {
+                name: "Kael notes".to_string(),
+                description: String::new(),
+                status: AppStatus::Making,
+            }
+        );
+        assert_eq!(parse(&["models", "pull", "llama3"]).unwrap().command, Command::ModelsPull("llama3".to_string()));
+        assert_eq!(parse(&["mcp"]).unwrap().command, Command::Mcp);
+        let cli = parse(&["--profile", "lab", "profiles", "add", "Air", "gapped", "--local"]).unwrap();
+        assert_eq!(cli.profile.as_deref(), Some("lab"));
+        assert_eq!(
+            cli.command,
+            Command::ProfilesAdd {
+                name: "Air gapped".to_string(),
+                kind: ProfileKind::Local,
+            }
+        );
+        assert_eq!(parse(&["profiles", "use", "work"]).unwrap().command, Command::ProfilesUse("work".to_string()));
+        assert_eq!(
+            parse(&["publish", "--dry-run", "--manifest", "ci/release.json"]).unwrap().command,
+            Command::Publish {
+                manifest: PathBuf::from("ci/release.json"),
+                options: PublishOptions {
+                    dry_run: true,
+                    ..Default::default()
+                },
+            }
+        );
+        assert_eq!(
+            parse(&["encrypt", "chat.db", "--output", "/backup/chat.kaelenc"]).unwrap().command,
+            Command::Encrypt {
+                input: PathBuf::from("chat.db"),
+                output: Some(PathBuf::from("/backup/chat.kaelenc")),
+            }
+        );
+        assert_eq!(
+            parse(&["decrypt", "chat.db.kaelenc"]).unwrap().command,
+            Command::Decrypt {
+                input: PathBuf::from("chat.db.kaelenc"),
+                output: None,
+            }
+        );
+
+        assert!(parse(&["ask"]).is_err());
+        assert!(parse(&["ask", "hi", "--provider", "nope"]).is_err());
+        assert!(parse(&["projects", "add", "x", "--status", "later"]).is_err());
+        assert_eq!(parse(&["frobnicate"]).unwrap_err(), USAGE);
+    }
+}
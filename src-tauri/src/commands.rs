@@ -10,34 +10,84 @@ use crate::app_scaffold::AppTemplate;
 use crate::firebase::uploader::FirebaseUploader;
 use crate::github::uploader::GitHubUploader;
 use std::path::Path;
-use rusqlite::Connection;
+use kael_storage::StorageManager;
 use serde::{Deserialize, Serialize};
 use std::sync::Mutex;
 use tauri::{command, State, Window};
 
 #[allow(dead_code)]
 #[tauri::command]
-pub fn send_message(message: String, db: State<Mutex<Connection>>) -> Result<ChatMessage, String> {
-    let conn = db.lock().map_err(|e| e.to_string())?;
+pub async fn send_message(message: String, db: State<'_, StorageManager>) -> Result<ChatMessage, String> {
     let msg = ChatMessage::new("user".to_string(), message);
-
-    crate::db::add_message(&conn, &msg.role, &msg.text).map_err(|e| e.to_string())?;
+    db.messages()
+        .insert(&msg)
+        .await
+        .map_err(|e| format!("Failed to add message: {}", e))?;
 
     Ok(msg)
 }
 
 #[allow(dead_code)]
 #[tauri::command]
-pub fn get_chat_history(db: State<Mutex<Connection>>) -> Result<Vec<ChatMessage>, String> {
-    let conn = db.lock().map_err(|e| e.to_string())?;
-    crate::db::get_chat_history(&conn).map_err(|e| e.to_string())
+pub async fn get_chat_history(db: State<'_, StorageManager>) -> Result<Vec<ChatMessage>, String> {
+    crate::db::get_chat_history(&db).await
 }
 
 #[allow(dead_code)]
 #[tauri::command]
-pub fn execute_script(script: String) -> Result<String, String> {
-    log::info!("Executing script: {}", script);
-    Ok(format!("Script executed: {}", script))
+pub async fn execute_script(
+    script_id: String,
+    args: std::collections::BTreeMap<String, String>,
+    db: State<'_, StorageManager>,
+) -> Result<ScriptRunResult, String> {
+    use crate::services::script_library;
+
+    let script = db
+        .scripts()
+        .get(&script_id)
+        .await
+        .map_err(|e| format!("Failed to load script: {}", e))?
+        .ok_or_else(|| format!("Script {} not found", script_id))?;
+
+    let body = script_library::render(&script.content, &args)?;
+    let run = db
+        .scripts()
+        .start_run(&script.id, &body, &args)
+        .await
+        .map_err(|e| format!("Failed to record run: {}", e))?;
+
+    log::info!("Executing script '{}' (run {})", script.name, run.id);
+    let output = tokio::process::Command::new("bash")
+        .arg("-c")
+        .arg(&body)
+        .output()
+        .await
+        .map_err(|e| format!("Failed to execute script: {}", e))?;
+
+    // Killed by a signal: report it the way shells do
+    let exit_code = output.status.code().unwrap_or_else(|| {
+        use std::os::unix::process::ExitStatusExt;
+        128 + output.status.signal().unwrap_or(0)
+    });
+    db.scripts()
+        .finish_run(&run.id, exit_code)
+        .await
+        .map_err(|e| format!("Failed to record exit code: {}", e))?;
+
+    Ok(ScriptRunResult {
+        run_id: run.id,
+        exit_code,
+        stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+        stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+    })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScriptRunResult {
+    pub run_id: String,
+    pub exit_code: i32,
+    pub stdout: String,
+    pub stderr: String,
 }
 
 #[allow(dead_code)]
@@ -49,14 +99,15 @@ pub fn execute_terminal_command(command: String) -> Result<String, String> {
 
 #[allow(dead_code)]
 #[tauri::command]
-pub fn get_kael_config() -> Result<KaelConfig, String> {
-    Ok(KaelConfig::default())
+pub async fn get_kael_config() -> Result<KaelConfig, String> {
+    Ok(crate::settings::init().await?.get().kael)
 }
 
 #[allow(dead_code)]
 #[tauri::command]
-pub fn save_kael_config(config: KaelConfig) -> Result<(), String> {
+pub async fn save_kael_config(config: KaelConfig) -> Result<(), String> {
     log::info!("Saving Kael config: {:?}", config);
+    crate::settings::update(|s| s.kael = config).await?;
     Ok(())
 }
 
@@ -132,39 +183,31 @@ pub async fn initiate_oauth(
     Ok(oauth_url)
 }
 
-/// Get OAuth URL for in-app WebView login
+/// Get OAuth URL for in-app WebView login. The loopback server for the
+/// flow keeps the resulting code until `exchange_oauth_token` collects it.
 #[allow(dead_code)]
 #[tauri::command]
-pub fn get_oauth_url(provider: String) -> Result<String, String> {
+pub async fn get_oauth_url(provider: String) -> Result<String, String> {
+    let flow = crate::oauth_server::OAuthFlow::start(&provider).await?;
     let oauth_url = match provider.as_str() {
-        "google" => {
-            let client_id = std::env::var("GOOGLE_OAUTH_CLIENT_ID").unwrap_or_else(|_| {
-                "384654392238-k02b3cvemoee9uq87pa3a3bk0gf1hbnk.apps.googleusercontent.com"
-                    .to_string()
-            });
-
-            format!(
-                "https://accounts.google.com/o/oauth2/v2/auth?client_id={}&redirect_uri=http://localhost:5173/auth/google/callback&response_type=code&scope=email%20profile%20openid",
-                client_id
-            )
-        }
-        "github" => {
-            let client_id = std::env::var("GITHUB_OAUTH_CLIENT_ID")
-                .unwrap_or_else(|_| "Ov23liqnLH8iIZOZ8sMT".to_string());
-
-            format!(
-                "https://github.com/login/oauth/authorize?client_id={}&redirect_uri=http://localhost:5173/auth/github/callback&scope=user:email",
-                client_id
-            )
-        }
+        "google" => crate::auth::get_google_oauth_url(&flow)?,
+        "github" => crate::auth::get_github_oauth_url(&flow)?,
         _ => return Err("Invalid provider".to_string()),
     };
 
+    tokio::spawn(async move {
+        match flow.wait(crate::oauth_server::FLOW_TIMEOUT).await {
+            Ok(grant) => crate::webview_oauth::store_oauth_result(grant).await,
+            Err(e) => log::warn!("OAuth sign-in did not complete: {}", e),
+        }
+    });
+
     log::info!("OAuth URL for {}: {}", provider, oauth_url);
     Ok(oauth_url)
 }
 
-/// Store OAuth result when callback is detected
+/// Store OAuth result when the WebView sees the callback itself. The
+/// `state` must belong to a flow started by `get_oauth_url`.
 #[allow(dead_code)]
 #[tauri::command]
 pub async fn store_oauth_code(
@@ -172,56 +215,35 @@ pub async fn store_oauth_code(
     code: String,
     state: Option<String>,
 ) -> Result<(), String> {
-    let oauth_result = crate::webview_oauth::OAuthResult {
-        provider,
-        code,
-        state: state.unwrap_or_default(),
-    };
-
-    crate::webview_oauth::store_oauth_result(oauth_result).await;
+    let grant = crate::oauth_server::redeem(&provider, &state.unwrap_or_default(), code)?;
+    crate::webview_oauth::store_oauth_result(grant).await;
     log::info!("Stored OAuth result");
     Ok(())
 }
 
-/// Get stored OAuth code (called after modal closes)
-#[allow(dead_code)]
-#[tauri::command]
-pub async fn get_stored_oauth_code() -> Result<Option<crate::webview_oauth::OAuthResult>, String> {
-    let result = crate::webview_oauth::get_and_clear_oauth_result().await;
-    Ok(result)
-}
-
-/// Poll for OAuth callback result from the server
+/// Poll whether the OAuth sign-in for `provider` has completed
 #[allow(dead_code)]
 #[tauri::command]
-pub async fn poll_oauth_callback(
-    provider: String,
-) -> Result<Option<crate::webview_oauth::OAuthResult>, String> {
-    // First check if result is already stored locally
-    if let Some(result) = crate::webview_oauth::get_and_clear_oauth_result().await {
-        return Ok(Some(result));
-    }
-
-    // Then check the OAuth server
-    let result = crate::webview_oauth::get_oauth_result_from_server(&provider).await;
-    Ok(result)
+pub async fn poll_oauth_callback(provider: String) -> Result<bool, String> {
+    Ok(crate::webview_oauth::has_oauth_result(&provider).await)
 }
 
-/// Exchange OAuth code for token (called with the code from WebView)
+/// Exchange the completed sign-in's code for a token
 #[allow(dead_code)]
 #[tauri::command]
-pub async fn exchange_oauth_token(
-    provider: String,
-    code: String,
-) -> Result<crate::auth::User, String> {
+pub async fn exchange_oauth_token(provider: String) -> Result<crate::auth::User, String> {
+    let grant = crate::webview_oauth::take_oauth_result(&provider)
+        .await
+        .ok_or_else(|| format!("No completed {} sign-in", provider))?;
     match provider.as_str() {
-        "google" => crate::auth::exchange_google_code_for_token(&code).await,
-        "github" => crate::auth::exchange_github_code_for_token(&code).await,
+        "google" => crate::auth::exchange_google_code_for_token(&grant).await,
+        "github" => crate::auth::exchange_github_code_for_token(&grant).await,
         _ => Err("Invalid provider".to_string()),
     }
 }
 
 /// Upload a local file to WebDAV using basic auth PUT
+/// With a `passphrase`, the file is encrypted to `.kaelenc` first
 #[tauri::command]
 pub async fn webdav_upload_file(
     base_url: String,
@@ -229,6 +251,7 @@ pub async fn webdav_upload_file(
     password: String,
     local_path: String,
     remote_path: String,
+    passphrase: Option<String>,
 ) -> Result<String, String> {
     let client = WebDavClient::new(WebDavConfig {
         url: base_url,
@@ -236,11 +259,22 @@ pub async fn webdav_upload_file(
         password,
     });
     let path = std::path::Path::new(&local_path);
-    client
-        .upload_file(path, &remote_path)
-        .await
-        .map_err(|e| e.to_string())?;
-    Ok("WebDAV upload complete".to_string())
+    match passphrase.filter(|p| !p.is_empty()) {
+        Some(passphrase) => {
+            let remote = client
+                .upload_encrypted_file(path, &remote_path, &passphrase)
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok(format!("WebDAV upload complete (encrypted as {})", remote))
+        }
+        None => {
+            client
+                .upload_file(path, &remote_path)
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok("WebDAV upload complete".to_string())
+        }
+    }
 }
 
 /// Get current app version from version.json
@@ -385,84 +419,55 @@ use crate::services::app_projects;
 pub async fn sync_projects(
     id_token: String,
     user_id: String,
-    db: State<'_, Mutex<Connection>>,
+    db: State<'_, StorageManager>,
 ) -> Result<usize, String> {
-    let conn = db.lock().map_err(|e| format!("Failed to lock database: {}", e))?;
-    
-    app_projects::sync_projects_with_firebase(&conn, &id_token, &user_id).await
-}
-
-/// Initialize app projects table
-#[tauri::command]
-pub fn init_app_projects(db: State<'_, Mutex<Connection>>) -> Result<(), String> {
-    let conn = db.lock().map_err(|e| format!("Failed to lock database: {}", e))?;
-    
-    app_projects::init_projects_table(&conn)
-        .map_err(|e| format!("Failed to init projects table: {}", e))
+    app_projects::sync_projects_with_firebase(&db, &id_token, &user_id).await
 }
 
 /// Save a project locally
 #[tauri::command]
-pub fn save_project(
+pub async fn save_project(
     project: crate::state::AppProject,
-    db: State<'_, Mutex<Connection>>,
+    db: State<'_, StorageManager>,
 ) -> Result<(), String> {
-    let conn = db.lock().map_err(|e| format!("Failed to lock database: {}", e))?;
-    
-    app_projects::save_project_local(&conn, &project)
-        .map_err(|e| format!("Failed to save project: {}", e))
+    app_projects::save_project_local(&db, &project).await
 }
 
 /// Get all projects
 #[tauri::command]
-pub fn get_all_projects(db: State<'_, Mutex<Connection>>) -> Result<Vec<crate::state::AppProject>, String> {
-    let conn = db.lock().map_err(|e| format!("Failed to lock database: {}", e))?;
-    
-    app_projects::get_projects_local(&conn)
-        .map_err(|e| format!("Failed to get projects: {}", e))
+pub async fn get_all_projects(db: State<'_, StorageManager>) -> Result<Vec<crate::state::AppProject>, String> {
+    app_projects::get_projects_local(&db).await
 }
 
 /// Get active projects (not archived)
 #[tauri::command]
-pub fn get_active_projects(db: State<'_, Mutex<Connection>>) -> Result<Vec<crate::state::AppProject>, String> {
-    let conn = db.lock().map_err(|e| format!("Failed to lock database: {}", e))?;
-    
-    app_projects::get_active_projects(&conn)
-        .map_err(|e| format!("Failed to get active projects: {}", e))
+pub async fn get_active_projects(db: State<'_, StorageManager>) -> Result<Vec<crate::state::AppProject>, String> {
+    app_projects::get_active_projects(&db).await
 }
 
 /// Get archived projects
 #[tauri::command]
-pub fn get_archived_projects(db: State<'_, Mutex<Connection>>) -> Result<Vec<crate::state::AppProject>, String> {
-    let conn = db.lock().map_err(|e| format!("Failed to lock database: {}", e))?;
-    
-    app_projects::get_archived_projects(&conn)
-        .map_err(|e| format!("Failed to get archived projects: {}", e))
+pub async fn get_archived_projects(db: State<'_, StorageManager>) -> Result<Vec<crate::state::AppProject>, String> {
+    app_projects::get_archived_projects(&db).await
 }
 
 /// Archive or unarchive a project
 #[tauri::command]
-pub fn archive_project(
+pub async fn archive_project(
     project_id: String,
     archived: bool,
-    db: State<'_, Mutex<Connection>>,
+    db: State<'_, StorageManager>,
 ) -> Result<(), String> {
-    let conn = db.lock().map_err(|e| format!("Failed to lock database: {}", e))?;
-    
-    app_projects::toggle_archive(&conn, &project_id, archived)
-        .map_err(|e| format!("Failed to archive project: {}", e))
+    app_projects::toggle_archive(&db, &project_id, archived).await
 }
 
 /// Delete a project from local database
 #[tauri::command]
-pub fn delete_project(
+pub async fn delete_project(
     project_id: String,
-    db: State<'_, Mutex<Connection>>,
+    db: State<'_, StorageManager>,
 ) -> Result<(), String> {
-    let conn = db.lock().map_err(|e| format!("Failed to lock database: {}", e))?;
-    
-    app_projects::delete_project_local(&conn, &project_id)
-        .map_err(|e| format!("Failed to delete project: {}", e))
+    app_projects::delete_project_local(&db, &project_id).await
 }
 
 /// Delete a project from Firebase
@@ -471,8 +476,9 @@ pub async fn delete_project_from_cloud(
     id_token: String,
     user_id: String,
     project_id: String,
+    db: State<'_, StorageManager>,
 ) -> Result<(), String> {
-    app_projects::delete_project_from_firebase(&id_token, &user_id, &project_id).await
+    app_projects::delete_project_from_firebase(&db, &id_token, &user_id, &project_id).await
 }
 
 // ==================== SYSTEM CONTEXT ====================
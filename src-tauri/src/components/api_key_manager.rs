@@ -1,6 +1,6 @@
 use crate::auth::AuthService;
-use crate::firebase::{self, ApiKey};
 use crate::llm::{self, LLMProvider, LLMRequest};
+use crate::vault::{self, VaultEntry};
 use dioxus::prelude::*;
 
 #[derive(Props, Clone, PartialEq)]
@@ -8,6 +8,13 @@ pub struct ApiKeyManagerProps {
     pub auth_service: Signal<AuthService>,
 }
 
+fn load_entries(mut api_keys: Signal<Vec<VaultEntry>>, mut error_message: Signal<String>) {
+    match vault::list() {
+        Ok(keys) => api_keys.set(keys),
+        Err(e) => error_message.set(format!("Failed to load keys: {}", e)),
+    }
+}
+
 #[allow(non_snake_case)]
 pub fn ApiKeyManager(props: ApiKeyManagerProps) -> Element {
     let mut key_name = use_signal(String::new);
@@ -15,38 +22,13 @@ pub fn ApiKeyManager(props: ApiKeyManagerProps) -> Element {
     let mut error_message = use_signal(String::new);
     let mut success_message = use_signal(String::new);
     let mut test_status = use_signal(String::new);
-    let api_keys = use_signal(Vec::<ApiKey>::new);
+    let api_keys = use_signal(Vec::<VaultEntry>::new);
 
     let auth = props.auth_service.read();
     let user_signal = use_signal(|| auth.get_user());
 
     use_effect(move || {
-        if let Some(user) = user_signal().clone() {
-            let mut api_keys = api_keys;
-            let mut error_msg = error_message.clone();
-            spawn(async move {
-                match firebase::get_api_keys(&user).await {
-                    Ok(keys) => {
-                        // Cache keys locally as fallback
-                        if let Ok(json) = serde_json::to_string(&keys) {
-                            let _ = std::fs::write("/tmp/kael_cached_api_keys.json", json);
-                        }
-                        api_keys.set(keys);
-                    }
-                    Err(e) => {
-                        // Try to load from local cache if Firebase fails
-                        if let Ok(cached) = std::fs::read_to_string("/tmp/kael_cached_api_keys.json") {
-                            if let Ok(keys) = serde_json::from_str::<Vec<firebase::ApiKey>>(&cached) {
-                                log::warn!("Firebase unavailable, using cached keys");
-                                api_keys.set(keys);
-                                return;
-                            }
-                        }
-                        error_msg.set(format!("Failed to load keys: {}", e));
-                    }
-                }
-            });
-        }
+        load_entries(api_keys, error_message);
     });
 
     rsx! {
@@ -56,107 +38,84 @@ pub fn ApiKeyManager(props: ApiKeyManagerProps) -> Element {
 
             h2 { class: "text-lg font-bold text-gray-200 mb-2", "API Key Management" }
 
+            if !vault::is_unlocked() {
+                p { class: "text-yellow-400 text-sm mb-2", "🔒 The vault is locked - unlock it in Settings → Security to add or change keys." }
+            }
+
             if !error_message().is_empty() {
                 p { class: "text-red-400 text-sm mb-2", "{error_message}" }
             }
-            
+
             if !success_message().is_empty() {
                 p { class: "text-green-400 text-sm mb-2", "{success_message}" }
             }
-            
+
             if !test_status().is_empty() {
                 p { class: "text-yellow-400 text-sm mb-2", "{test_status}" }
             }
 
-            // Form to add a new key
+            // Form to add or replace a key
             form {
                 class: "flex flex-col gap-2",
                 onsubmit: move |_ev| {
-                    if let Some(user) = user_signal().clone() {
-                        let name = key_name();
-                        let value = key_value();
-                        let mut api_keys_writer = api_keys;
-                        let mut test_signal = test_status.clone();
-                        let mut error_signal = error_message.clone();
-                        let mut success_signal = success_message.clone();
-                        
-                        test_signal.set(format!("🔐 Saving key '{}'...", name));
-                        error_signal.set(String::new());
-                        success_signal.set(String::new());
-                        
-                        spawn(async move {
-                            // Save the key first
-                            match firebase::save_api_key(&user, &name, &value).await {
-                                Ok(_) => {
-                                    test_signal.set(format!("✅ Saved! Testing '{}'...", name));
-                                    
-                                    // Test the key based on provider name
-                                    let test_result = match name.as_str() {
-                                        "Mistral AI" => {
-                                            let req = LLMRequest {
-                                                provider: LLMProvider::Mistral,
-                                                model: String::new(),
-                                                prompt: "ping".to_string(),
-                                                api_key: Some(value.clone()),
-                                                system: Some("Reply with 'ok'".to_string()),
-                                            };
-                                            llm::send_request_with_fallback(req, Some(&user), vec![]).await
-                                        },
-                                        "Google Gemini" => {
-                                            let req = LLMRequest {
-                                                provider: LLMProvider::Gemini,
-                                                model: String::new(),
-                                                prompt: "ping".to_string(),
-                                                api_key: Some(value.clone()),
-                                                system: Some("Reply with 'ok'".to_string()),
-                                            };
-                                            llm::send_request_with_fallback(req, Some(&user), vec![]).await
-                                        },
-                                        _ => {
-                                            // For other providers, just mark as saved
-                                            test_signal.set(format!("✅ '{}' saved (validation not implemented for this provider)", name));
-                                            match firebase::get_api_keys(&user).await {
-                                                Ok(keys) => api_keys_writer.set(keys),
-                                                Err(e) => error_signal.set(e),
-                                            }
-                                            key_name.set(String::new());
-                                            key_value.set(String::new());
-                                            return;
-                                        }
-                                    };
-                                    
-                                    // Show test results
-                                    match test_result {
-                                        Ok(_) => {
-                                            success_signal.set(format!("✅ '{}' is working correctly!", name));
-                                            test_signal.set(String::new());
-                                        },
-                                        Err(e) => {
-                                            error_signal.set(format!("⚠️ Key saved but test failed: {}", e));
-                                            test_signal.set(String::new());
-                                        }
-                                    }
-                                    
-                                    // Refresh the list of keys
-                                    match firebase::get_api_keys(&user).await {
-                                        Ok(keys) => api_keys_writer.set(keys),
-                                        Err(e) => error_signal.set(e),
-                                    }
-                                    key_name.set(String::new());
-                                    key_value.set(String::new());
-                                },
-                                Err(e) => {
-                                    error_signal.set(format!("❌ Failed to save: {}", e));
-                                    test_signal.set(String::new());
-                                }
-                            }
-                        });
+                    let user = user_signal().clone();
+                    let name = key_name().trim().to_string();
+                    let value = key_value();
+                    let mut test_signal = test_status.clone();
+                    let mut error_signal = error_message.clone();
+                    let mut success_signal = success_message.clone();
+
+                    test_signal.set(format!("🔐 Saving key '{}'...", name));
+                    error_signal.set(String::new());
+                    success_signal.set(String::new());
+
+                    // Replacing a stored key is a rotation
+                    let exists = api_keys().iter().any(|k| k.provider == name);
+                    let saved = if exists { vault::rotate(&name, &value) } else { vault::set(&name, &value) };
+                    if let Err(e) = saved {
+                        error_signal.set(format!("❌ Failed to save: {}", e));
+                        test_signal.set(String::new());
+                        return;
                     }
+                    load_entries(api_keys, error_message);
+                    key_name.set(String::new());
+                    key_value.set(String::new());
+
+                    spawn(async move {
+                        if let Some(user) = &user {
+                            if let Err(e) = vault::push_to_firestore(user, &name, &value).await {
+                                error_signal.set(format!("⚠️ Saved locally, but Firebase sync failed: {}", e));
+                            }
+                        }
+                        test_signal.set(format!("✅ Saved! Testing '{}'...", name));
+
+                        // Test the key based on provider name
+                        let provider = match name.as_str() {
+                            "Mistral AI" => LLMProvider::Mistral,
+                            "Google Gemini" => LLMProvider::Gemini,
+                            _ => {
+                                test_signal.set(format!("✅ '{}' saved (validation not implemented for this provider)", name));
+                                return;
+                            }
+                        };
+                        let req = LLMRequest {
+                            provider,
+                            model: String::new(),
+                            prompt: "ping".to_string(),
+                            api_key: Some(value),
+                            system: Some("Reply with 'ok'".to_string()),
+                        };
+                        match llm::send_request_with_fallback(req, user.as_ref(), vec![]).await {
+                            Ok(_) => success_signal.set(format!("✅ '{}' is working correctly!", name)),
+                            Err(e) => error_signal.set(format!("⚠️ Key saved but test failed: {}", e)),
+                        }
+                        test_signal.set(String::new());
+                    });
                 },
                 input {
                     class: "w-full p-2 rounded-md border",
                     style: "background-color: #0f0b1a; border-color: #3a2a50; color: #f7f2ff; font-size: 12px;",
-                    placeholder: "API Key Name (e.g., OpenAI)",
+                    placeholder: "Provider (e.g., Mistral AI)",
                     value: "{key_name}",
                     oninput: move |e| key_name.set(e.value()),
                 }
@@ -187,26 +146,35 @@ pub fn ApiKeyManager(props: ApiKeyManagerProps) -> Element {
                             li {
                                 class: "flex items-center justify-between p-2 rounded-md",
                                 style: "background-color: #0f0b1a; border: 1px solid #3a2a50;",
-                                span { class: "text-gray-300", "{key.name}" }
+                                div {
+                                    span { class: "text-gray-300", "{key.provider}" }
+                                    span { class: "text-xs text-gray-500", style: "margin-left: 8px;",
+                                        {
+                                            let when = key.rotated_at.unwrap_or(key.created_at);
+                                            let verb = if key.rotated_at.is_some() { "rotated" } else { "added" };
+                                            format!("{} {}", verb, when.format("%Y-%m-%d"))
+                                        }
+                                    }
+                                }
                                 button {
                                     class: "px-2 py-1 text-xs rounded-md font-bold",
                                     style: "background: #e53e3e; color: white;",
                                     onclick: move |_| {
+                                        let provider = key.provider.clone();
+                                        if let Err(e) = vault::delete(&provider) {
+                                            error_message.set(e);
+                                            return;
+                                        }
+                                        load_entries(api_keys, error_message);
                                         if let Some(user) = user_signal().clone() {
-                                            let key_id = key.id.clone();
-                                            let mut api_keys_writer = api_keys;
-                                            spawn(async move {
-                                                match firebase::delete_api_key(&user, &key_id).await {
-                                                    Ok(_) => {
-                                                        // Refresh the list of keys
-                                                        match firebase::get_api_keys(&user).await {
-                                                            Ok(keys) => api_keys_writer.set(keys),
-                                                            Err(e) => error_message.set(e),
-                                                        }
-                                                    },
-                                                    Err(e) => error_message.set(e),
-                                                }
-                                            });
+                                            if crate::settings::current().sync_api_keys {
+                                                spawn(async move {
+                                                    let doc_id = provider.to_lowercase().replace(' ', "_");
+                                                    if let Err(e) = crate::firebase::delete_api_key(&user, &doc_id).await {
+                                                        error_message.set(format!("⚠️ Deleted locally, but not from Firebase: {}", e));
+                                                    }
+                                                });
+                                            }
                                         }
                                     },
                                     "Delete"
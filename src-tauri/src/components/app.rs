@@ -8,11 +8,16 @@ use crate::components::chat::ChatPanel;
 use crate::components::header::Header;
 use crate::components::icons::{KaelSigilIcon, PanelIcon, SparkIcon};
 use crate::components::project_archive_settings::ProjectArchiveSettings;
+use crate::components::script_library::ScriptLibraryPanel;
 use crate::components::settings::SettingsPanel;
 use crate::components::terminal::TerminalPanel;
+use crate::settings::{self, SettingKey};
 use crate::state::{AppProject, AppStatus};
 use crate::llm;
 
+/// Finished commands kept for the terminal's block list
+const MAX_COMMAND_BLOCKS: usize = 50;
+
 // Strip ANSI escape sequences from text (robustly skips ESC sequences)
 fn strip_ansi(text: &str) -> String {
     let mut out = String::with_capacity(text.len());
@@ -63,9 +68,14 @@ fn strip_ansi(text: &str) -> String {
     out
 }
 
+/// Sidebar projects, in the active profile's data dir
+fn projects_path() -> std::path::PathBuf {
+    crate::profiles::data_dir().join("projects.json")
+}
+
 // Load app projects from persistent storage
 fn load_projects() -> Vec<AppProject> {
-    if let Ok(json) = std::fs::read_to_string("/tmp/kael_projects.json") {
+    if let Ok(json) = std::fs::read_to_string(projects_path()) {
         serde_json::from_str(&json).unwrap_or_default()
     } else {
         // Default projects
@@ -92,7 +102,7 @@ fn load_projects() -> Vec<AppProject> {
 // Save app projects to persistent storage
 fn save_projects(projects: &[AppProject]) {
     if let Ok(json) = serde_json::to_string(projects) {
-        let _ = std::fs::write("/tmp/kael_projects.json", json);
+        let _ = std::fs::write(projects_path(), json);
     }
 }
 
@@ -122,7 +132,12 @@ pub fn App() -> Element {
         ("Local DB", "Online", "#e040fb"),
     ];
 
-    let mut terminal_output = use_signal(String::new);
+    let mut kael_screen = use_signal(|| kael_terminal::Screen::new(24, 120));
+    let mut command_blocks = use_signal(Vec::<kael_terminal::CommandBlock>::new);
+    let explain_block = use_signal(|| None::<kael_terminal::CommandBlock>);
+    let mut sudo_prompt = use_signal(|| None::<kael_terminal::PromptKind>);
+    let attach_recording = use_signal(|| None::<std::path::PathBuf>);
+    let terminal_context = use_signal(|| None::<String>);
     let mut pty_ready = use_signal(|| false);
     let current_command = use_signal(String::new);
     let auth_service = use_signal(|| AuthService::new());
@@ -130,111 +145,80 @@ pub fn App() -> Element {
     let mut projects = use_signal(|| load_projects());
     let mut clear_chat_trigger = use_signal(|| false);
     let chat_messages_out = use_signal(Vec::<crate::components::chat::Message>::new);
+    let mut export_passphrase = use_signal(String::new);
     let hybrid_assist = use_signal(|| false);
     let show_brainstorm = use_signal(|| false);
+    let mut scripts_version = use_signal(|| 0u32);
     let pty_instance = use_signal(|| {
         use crate::terminal::PtyTerminal;
         PtyTerminal::new()
     });
 
-    // Prefetch provider API keys on authentication to avoid first-request delay
-    {
-        let auth_signal = auth_service.clone();
-        use_effect(move || {
-            if let Some(user) = auth_signal.read().get_user() {
-                spawn(async move {
-                    match crate::firebase::get_api_keys(&user).await {
-                        Ok(keys) => {
-                            let simple: Vec<serde_json::Value> = keys
-                                .into_iter()
-                                .map(|k| serde_json::json!({"name": k.name, "value": k.value}))
-                                .collect();
-                            let json = serde_json::to_string(&simple).unwrap_or_else(|_| "[]".to_string());
-                            let _ = std::fs::write("/tmp/kael_cached_keys.json", json);
-                            log::info!("Prefetched provider keys and cached to /tmp/kael_cached_keys.json");
-                        }
-                        Err(e) => {
-                            log::warn!("Failed to prefetch provider keys: {}", e);
-                        }
-                    }
-                });
-            }
-        });
-    }
-
-    // Auto-refresh token before it expires (keeps user logged in indefinitely)
+    // Follow session changes made in the background (token refresh, expiry)
     {
         let mut auth_signal = auth_service.clone();
         use_effect(move || {
             spawn(async move {
+                let mut events = crate::session::subscribe();
                 loop {
-                    // Check every 30 minutes
-                    tokio::time::sleep(std::time::Duration::from_secs(30 * 60)).await;
-                    
-                    let should_refresh = {
-                        let auth = auth_signal.read();
-                        if let Some(user) = auth.get_user() {
-                            if let Some(expires_in) = user.expires_in {
-                                let now = chrono::Utc::now().timestamp();
-                                // Refresh if token expires in less than 10 minutes
-                                expires_in - now < 10 * 60
-                            } else {
-                                false
-                            }
-                        } else {
-                            false
+                    match events.recv().await {
+                        Ok(crate::session::SessionEvent::RefreshFailed { error, retry_in }) => {
+                            log::warn!("Session refresh failed, retrying in {:?}: {}", retry_in, error);
                         }
-                    };
-
-                    if should_refresh {
-                        let current_user = auth_signal.read().get_user();
-                        if let Some(user) = current_user {
-                            if let Some(refresh_token) = &user.refresh_token {
-                                log::info!("🔄 Auto-refreshing token...");
-                                match crate::auth::AuthService::refresh_token_public(refresh_token).await {
-                                    Ok(mut refreshed_user) => {
-                                        // Preserve existing user info (email, name, photo)
-                                        refreshed_user.email = user.email;
-                                        refreshed_user.name = user.name;
-                                        refreshed_user.photo_url = user.photo_url;
-                                        
-                                        auth_signal.write().set_user(refreshed_user);
-                                        log::info!("✅ Token auto-refreshed successfully");
-                                    }
-                                    Err(e) => {
-                                        log::error!("❌ Auto-refresh failed: {}", e);
-                                    }
-                                }
-                            }
+                        Ok(crate::session::SessionEvent::Expired) => {
+                            log::warn!("Session expired - please sign in again");
+                        }
+                        Ok(_) | Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {
+                            // The session is shared; re-render with its new state
+                            auth_signal.with_mut(|_| {});
                         }
+                        Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
                     }
                 }
             });
         });
     }
 
-    // Load hybrid assist flag from cache - default to hybrid, fallback to local-only if no API keys
+    // Load settings (migrating old /tmp files) - hybrid by default, local-only if no API keys
     {
         let mut ha = hybrid_assist.clone();
         use_effect(move || {
-            // Check if we have any API keys cached
-            let has_api_keys = std::fs::read_to_string("/tmp/kael_cached_keys.json")
-                .ok()
-                .and_then(|s| serde_json::from_str::<Vec<serde_json::Value>>(&s).ok())
-                .map(|keys| !keys.is_empty())
-                .unwrap_or(false);
-            
-            // If we have API keys, enable hybrid mode by default
-            if has_api_keys {
-                ha.set(true);
-                let _ = std::fs::write("/tmp/kael_hybrid_assist.json", "true");
-                log::info!("🔄 Hybrid mode enabled - API keys detected");
-            } else {
-                // No API keys - use local-only (Ollama)
-                ha.set(false);
-                let _ = std::fs::write("/tmp/kael_hybrid_assist.json", "false");
-                log::info!("🏠 Local-only mode - no API keys found, using Ollama");
-            }
+            spawn(async move {
+                let store = match settings::init().await {
+                    Ok(store) => store,
+                    Err(e) => {
+                        log::error!("Failed to load settings: {}", e);
+                        return;
+                    }
+                };
+                let current = store.get();
+                kael_screen.write().set_scrollback_limit(current.terminal_scrollback);
+
+                // Keys not yet moved into the vault still count
+                let has_keys = crate::vault::list().map(|keys| !keys.is_empty()).unwrap_or(false)
+                    || !current.cached_keys.is_empty();
+                if !has_keys {
+                    // No API keys - use local-only (Ollama)
+                    ha.set(false);
+                    log::info!("🏠 Local-only mode - no API keys found, using Ollama");
+                } else {
+                    ha.set(current.hybrid_assist);
+                    if current.hybrid_assist {
+                        log::info!("🔄 Hybrid mode enabled - API keys detected");
+                    }
+                }
+
+                // Follow changes made from the settings panel
+                let mut changes = store.subscribe();
+                while let Ok(key) = changes.recv().await {
+                    if key == SettingKey::HybridAssist {
+                        ha.set(settings::current().hybrid_assist);
+                    }
+                    if key == SettingKey::TerminalScrollback {
+                        kael_screen.write().set_scrollback_limit(settings::current().terminal_scrollback);
+                    }
+                }
+            });
         });
     }
 
@@ -323,10 +307,41 @@ pub fn App() -> Element {
             // Stream output
             if let Ok(rx) = pty.get_output_receiver().await {
                 spawn(async move {
+                    let mut run_markers = crate::services::script_library::RunMarkerScanner::new();
+                    let mut tracker = kael_terminal::CommandTracker::new();
+                    let mut password_prompt = kael_terminal::PasswordPrompt::new();
                     while let Ok(chunk) = rx.recv().await {
+                        // sudo/polkit asking for a password; ChatPanel answers it
+                        if let Some(prompt) = password_prompt.feed(&chunk) {
+                            sudo_prompt.set(Some(prompt));
+                        }
+                        // Shell integration markers delimit each command's block
+                        for event in tracker.feed(&chunk) {
+                            if let kael_terminal::BlockEvent::Finished(block) = event {
+                                let mut blocks = command_blocks.write();
+                                blocks.push(block);
+                                let excess = blocks.len().saturating_sub(MAX_COMMAND_BLOCKS);
+                                blocks.drain(..excess);
+                            }
+                        }
+                        let responses = {
+                            let mut screen = kael_screen.write();
+                            screen.process(&chunk);
+                            screen.take_responses()
+                        };
+                        if !responses.is_empty() {
+                            let _ = pty.write_bytes(&responses).await;
+                        }
                         let text = String::from_utf8_lossy(&chunk).to_string();
                         let clean_text = strip_ansi(&text);
-                        terminal_output.write().push_str(&clean_text);
+                        // Script runs report their exit code through a marker line
+                        let (_, finished) = run_markers.feed(&clean_text);
+                        if !finished.is_empty() {
+                            if let Ok(storage) = crate::db::shared() {
+                                crate::services::script_library::record_run_results(&storage, finished).await;
+                            }
+                            *scripts_version.write() += 1;
+                        }
                     }
                 });
             }
@@ -448,6 +463,14 @@ pub fn App() -> Element {
                             span { class: "section-label", "Chat Controls" }
                             SparkIcon { class: "w-4 h-4 text-[#ffcc00]" }
                         }
+                        input {
+                            class: "w-full mb-2",
+                            style: "padding: 8px 10px; border-radius: 8px; border: 1px solid #3a2d56; background: #0f0b1a; color: #f7f2ff; font-size: 12px;",
+                            "type": "password",
+                            placeholder: "Export passphrase (optional, saves .kaelenc)",
+                            value: "{export_passphrase}",
+                            oninput: move |e| export_passphrase.set(e.value()),
+                        }
                         button {
                             class: "w-full mb-2",
                             style: "padding: 10px 12px; border-radius: 8px; border: 1px solid #3a2d56; background: linear-gradient(135deg, #7aebbe 0%, #5af0c8 100%); color: #120e1a; font-weight: 600; font-size: 13px; box-shadow: 0 4px 12px rgba(122, 235, 190, 0.3);",
@@ -460,7 +483,7 @@ pub fn App() -> Element {
                                 text_content.push_str(&"=".repeat(60));
                                 text_content.push_str("\n\n");
 
-                                match std::fs::read_to_string("/tmp/kael_chat_history.json") {
+                                match std::fs::read_to_string(crate::components::chat::chat_history_path()) {
                                     Ok(json_content) => {
                                         match serde_json::from_str::<Vec<serde_json::Value>>(&json_content) {
                                             Ok(messages) => {
@@ -495,9 +518,33 @@ pub fn App() -> Element {
                                 }
 
                                 let save_path = format!("/tmp/{}", filename);
-                                match std::fs::write(&save_path, text_content) {
-                                    Ok(_) => log::info!("Chat saved to: {}", save_path),
-                                    Err(e) => log::error!("Failed to save chat: {}", e),
+                                let passphrase = export_passphrase();
+                                if passphrase.is_empty() {
+                                    match std::fs::write(&save_path, text_content) {
+                                        Ok(_) => log::info!("Chat saved to: {}", save_path),
+                                        Err(e) => log::error!("Failed to save chat: {}", e),
+                                    }
+                                } else {
+                                    // Sealed from memory, so the chat never touches the disk in the clear
+                                    let save_path = crate::crypto::stream::encrypted_path(std::path::Path::new(&save_path));
+                                    spawn(async move {
+                                        let path = save_path.clone();
+                                        let saved = tokio::task::spawn_blocking(move || {
+                                            crate::crypto::stream::write_encrypted_file(
+                                                &path,
+                                                text_content.as_bytes(),
+                                                crate::crypto::Secret::Passphrase(&passphrase),
+                                                &crate::crypto::Params::default(),
+                                            )
+                                        })
+                                        .await
+                                        .map_err(|e| e.to_string())
+                                        .and_then(|saved| saved);
+                                        match saved {
+                                            Ok(_) => log::info!("Encrypted chat saved to: {}", save_path.display()),
+                                            Err(e) => log::error!("Failed to save encrypted chat: {}", e),
+                                        }
+                                    });
                                 }
                             },
                             "💾 Save Chat"
@@ -506,7 +553,7 @@ pub fn App() -> Element {
                             class: "w-full",
                             style: "padding: 10px 12px; border-radius: 8px; border: 1px solid #3a2d56; background: linear-gradient(135deg, #ff6b6b 0%, #ff8787 100%); color: white; font-weight: 600; font-size: 13px; box-shadow: 0 4px 12px rgba(255, 107, 107, 0.3);",
                             onclick: move |_| {
-                                match std::fs::remove_file("/tmp/kael_chat_history.json") {
+                                match std::fs::remove_file(crate::components::chat::chat_history_path()) {
                                     Ok(_) => {
                                         log::info!("Chat history cleared");
                                         clear_chat_trigger.set(true);
@@ -535,6 +582,18 @@ pub fn App() -> Element {
                         }
                     }
 
+                    // Script Library
+                    div { class: "left-card p-3 mb-4",
+                        div { class: "flex items-center justify-between mb-3",
+                            span { class: "section-label", "Script Library" }
+                            PanelIcon { class: "w-4 h-4 text-[#e040fb]" }
+                        }
+                        ScriptLibraryPanel {
+                            pty: pty_instance.clone(),
+                            scripts_version: scripts_version.clone(),
+                        }
+                    }
+
                     // Terminal Status
                     div { class: "left-card p-3",
                         div { class: "flex items-center justify-between mb-3",
@@ -566,7 +625,6 @@ pub fn App() -> Element {
                             let user_name = auth_service.read().get_user().map(|u| u.name).unwrap_or_else(|| "Architect".to_string());
                             rsx! {
                                 ChatPanel {
-                                    term_out: terminal_output.clone(),
                                     pty: pty_instance.clone(),
                                     current_cmd: current_command.clone(),
                                     user_photo_url: user_photo_url,
@@ -576,11 +634,20 @@ pub fn App() -> Element {
                                     messages_out: chat_messages_out.clone(),
                                     last_provider: use_signal(|| String::new()),
                                     hybrid_assist: hybrid_assist.clone(),
+                                    scripts_version: scripts_version.clone(),
+                                    explain_block: explain_block,
+                                    sudo_prompt: sudo_prompt,
+                                    attach_recording: attach_recording,
+                                    terminal_context: terminal_context,
                                 }
                             }
                         }
                         TerminalPanel { 
-                            term_out: terminal_output.clone(),
+                            screen: kael_screen,
+                            blocks: command_blocks,
+                            explain_block: explain_block,
+                            attach_recording: attach_recording,
+                            terminal_context: terminal_context,
                             pty: pty_instance.clone()
                         }
                     }
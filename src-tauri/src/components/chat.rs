@@ -3,6 +3,7 @@ use crate::components::icons::{PanelIcon, SendIcon, SparkIcon};
 use crate::llm::{self, LLMProvider, LLMRequest};
 use crate::services::command_rewriter::{self, AIDecision, KaelOSPersonality, UserContext};
 use crate::terminal::PtyTerminal;
+use kael_terminal::{ChangeKind, Elevation, PromptKind, SandboxRun, Secret};
 use dioxus::events::Key;
 use dioxus::prelude::*;
 use serde::{Deserialize, Serialize};
@@ -49,10 +50,20 @@ fn provider_to_icon(provider: &str) -> String {
     }
 }
 
+/// Chat panel history, in the active profile's data dir
+pub(crate) fn chat_history_path() -> std::path::PathBuf {
+    crate::profiles::data_dir().join("chat_history.json")
+}
+
+/// Per-provider message counts, in the active profile's data dir
+pub(crate) fn provider_usage_path() -> std::path::PathBuf {
+    crate::profiles::data_dir().join("provider_usage.json")
+}
+
 /// Track provider usage statistics
 fn increment_usage(provider_label: String) {
-    let path = "/tmp/kael_provider_usage.json";
-    let mut map: std::collections::BTreeMap<String, u64> = if let Ok(s) = std::fs::read_to_string(path) {
+    let path = provider_usage_path();
+    let mut map: std::collections::BTreeMap<String, u64> = if let Ok(s) = std::fs::read_to_string(&path) {
         serde_json::from_str(&s).unwrap_or_default()
     } else { 
         std::collections::BTreeMap::new() 
@@ -131,6 +142,7 @@ fn is_system_query(s: &str) -> bool {
         "chmod",
         "chown",
         "sudo",
+        "pkexec",
         "permissions",
         "grep",
         "sed",
@@ -285,12 +297,9 @@ fn provider_label_to_enum(label: &str) -> Option<llm::LLMProvider> {
 }
 
 fn build_provider_order() -> Vec<String> {
-    if let Ok(json) = std::fs::read_to_string("/tmp/kael_provider_order.json") {
-        if let Ok(list) = serde_json::from_str::<Vec<String>>(&json) {
-            if !list.is_empty() {
-                return list;
-            }
-        }
+    let saved = crate::settings::current().provider_order;
+    if !saved.is_empty() {
+        return saved;
     }
     // Default: Ollama first, then cloud providers if keys exist
     vec![
@@ -324,6 +333,120 @@ fn next_provider_after(
 }
 
 // Simple classifier: treat as command if it looks like a shell command
+/// Send a prompt built from terminal context through the usual provider chain
+async fn ask_with_context(prompt: String, user: Option<&crate::auth::User>) -> Message {
+    let req = llm::LLMRequest {
+        provider: llm::LLMProvider::Ollama,
+        model: String::new(),
+        prompt: prompt.clone(),
+        api_key: None,
+        system: Some(llm::get_kael_system_prompt()),
+    };
+    let fallback_providers = vec![
+        (llm::LLMProvider::Mistral, None),
+        (llm::LLMProvider::Gemini, None),
+        (llm::LLMProvider::Copilot, None),
+        (llm::LLMProvider::CopilotAgent, None),
+    ];
+    match llm::send_request_with_fallback(req, user, fallback_providers).await {
+        Ok(res) => Message {
+            author: "Kael".to_string(),
+            text: res.content,
+            is_streaming: false,
+            provider: Some(provider_enum_to_label(&res.provider).to_string()),
+            prompt: Some(prompt),
+        },
+        Err(e) => Message {
+            author: "Kael".to_string(),
+            text: format!("❌ All providers failed: {}", e),
+            is_streaming: false,
+            prompt: Some(prompt),
+            ..Default::default()
+        },
+    }
+}
+
+/// Put terminal text the user attached in front of their question
+fn with_terminal_context(prompt: &str, context: Option<&str>) -> String {
+    match context {
+        Some(context) => format!("Context from my terminal:\n```\n{}\n```\n\n{}", context, prompt),
+        None => prompt.to_string(),
+    }
+}
+
+/// "📎 12 terminal lines attached" under the user's message
+fn context_note(context: &str) -> String {
+    let lines = context.lines().count();
+    format!("📎 {} terminal line{} attached", lines, if lines == 1 { "" } else { "s" })
+}
+
+/// Prompt asking the AI why a terminal command failed, with the tail of its output
+fn explain_failure_prompt(block: &kael_terminal::CommandBlock) -> String {
+    const MAX_OUTPUT_CHARS: usize = 4000;
+    let output = block.output.trim_end();
+    let start = output
+        .char_indices()
+        .rev()
+        .nth(MAX_OUTPUT_CHARS)
+        .map_or(0, |(i, _)| i);
+    let exit = block
+        .exit_code
+        .map_or_else(|| "unknown".to_string(), |c| c.to_string());
+    let cwd = block
+        .cwd
+        .as_ref()
+        .map_or_else(|| "unknown".to_string(), |p| p.display().to_string());
+    format!(
+        "This command failed in my terminal. Explain why and how to fix it.\n\nCommand: {}\nExit code: {}\nWorking directory: {}\n\nOutput{}:\n```\n{}\n```",
+        block.command,
+        exit,
+        cwd,
+        if start > 0 { " (last part)" } else { "" },
+        &output[start..]
+    )
+}
+
+/// Chat message for a sandboxed try: how it ended, its output and the
+/// file changes it would make
+fn sandbox_report(cmd: &str, run: &SandboxRun) -> String {
+    let status = if run.timed_out {
+        "timed out".to_string()
+    } else if run.exit.success {
+        "succeeded".to_string()
+    } else {
+        format!("failed (exit {})", run.exit.code)
+    };
+    let mut report = format!("🧪 Sandboxed run {} in {}\n$ {}\n", status, run.workdir().display(), cmd);
+    let output = run.output.trim_end();
+    if !output.is_empty() {
+        report.push_str(&format!("\n{}\n", output));
+    }
+    if run.changes.is_empty() {
+        report.push_str("\nNo file changes.");
+        return report;
+    }
+    report.push_str(&format!("\n{} file change(s), not applied yet:\n", run.changes.len()));
+    for change in &run.changes {
+        let mark = match change.kind {
+            ChangeKind::Added => "+",
+            ChangeKind::Modified => "~",
+            ChangeKind::Removed => "-",
+        };
+        report.push_str(&format!("  {} {}\n", mark, change.path.display()));
+    }
+    report.push('\n');
+    report.push_str(&run.diff());
+    report
+}
+
+/// sudo as asked, unless it is missing and pkexec is there instead
+fn elevation_for(requested: Elevation) -> Elevation {
+    match (requested, Elevation::preferred()) {
+        (Elevation::Sudo, Some(Elevation::Pkexec)) => Elevation::Pkexec,
+        _ => requested,
+    }
+}
+
 fn is_command(s: &str) -> bool {
     let s = s.trim();
     if s.is_empty() {
@@ -422,7 +545,6 @@ pub struct Message {
 
 #[derive(Props, Clone, PartialEq)]
 pub struct ChatProps {
-    pub term_out: Signal<String>,
     pub pty: Signal<PtyTerminal>,
     pub current_cmd: Signal<String>,
     pub user_photo_url: Option<String>,
@@ -434,16 +556,32 @@ pub struct ChatProps {
     pub last_provider: Signal<String>,
     #[props(default = use_signal(|| false))]
     pub hybrid_assist: Signal<bool>,
+    /// Bumped after "Save as script" so the script library reloads
+    #[props(default = use_signal(|| 0))]
+    pub scripts_version: Signal<u32>,
+    /// Failed terminal command the user asked Kael to explain
+    #[props(default = use_signal(|| None))]
+    pub explain_block: Signal<Option<kael_terminal::CommandBlock>>,
+    /// Password prompt currently waiting in Kael's terminal
+    #[props(default = use_signal(|| None))]
+    pub sudo_prompt: Signal<Option<PromptKind>>,
+    /// Terminal recording the user attached for Kael to analyse
+    #[props(default = use_signal(|| None))]
+    pub attach_recording: Signal<Option<std::path::PathBuf>>,
+    /// Terminal text attached to the next chat message
+    #[props(default = use_signal(|| None))]
+    pub terminal_context: Signal<Option<String>>,
 }
 
 #[allow(non_snake_case)]
 pub fn ChatPanel(mut props: ChatProps) -> Element {
     // Persistent clipboard instance to avoid quick drop issues on Linux
+    let mut terminal_context = props.terminal_context;
     let mut clipboard = use_signal(|| {
         arboard::Clipboard::new().ok()
     });
     let load_messages = || -> Vec<Message> {
-        match std::fs::read_to_string("/tmp/kael_chat_history.json") {
+        match std::fs::read_to_string(chat_history_path()) {
             Ok(json) => match serde_json::from_str::<Vec<Message>>(&json) {
                 Ok(mut msgs) => {
                     // Apply message limit to prevent memory issues
@@ -458,8 +596,9 @@ pub fn ChatPanel(mut props: ChatProps) -> Element {
                 Err(e) => {
                     log::error!("⚠️  Chat history corrupted: {} - Starting fresh (old file backed up)", e);
                     // Backup corrupted file
-                    let backup = format!("/tmp/kael_chat_history.corrupted.{}", chrono::Local::now().timestamp());
-                    let _ = std::fs::rename("/tmp/kael_chat_history.json", &backup);
+                    let path = chat_history_path();
+                    let backup = path.with_extension(format!("corrupted.{}", chrono::Local::now().timestamp()));
+                    let _ = std::fs::rename(&path, &backup);
                     vec![Message {
                         author: "Kael".to_string(),
                         text: "Greetings, Architect! I am Kael, your partner in creation.".to_string(),
@@ -486,10 +625,11 @@ pub fn ChatPanel(mut props: ChatProps) -> Element {
         match serde_json::to_string(messages) {
             Ok(json) => {
                 // Atomic write: write to temp file first, then rename
-                let temp_path = "/tmp/kael_chat_history.tmp";
-                match std::fs::write(temp_path, &json) {
+                let path = chat_history_path();
+                let temp_path = path.with_extension("tmp");
+                match std::fs::write(&temp_path, &json) {
                     Ok(_) => {
-                        if let Err(e) = std::fs::rename(temp_path, "/tmp/kael_chat_history.json") {
+                        if let Err(e) = std::fs::rename(&temp_path, &path) {
                             log::error!("Failed to save chat history: {}", e);
                         }
                     }
@@ -503,7 +643,8 @@ pub fn ChatPanel(mut props: ChatProps) -> Element {
     let mut messages = use_signal(load_messages);
     let mut user_input = use_signal(String::new);
     let mut echo_commands = use_signal(|| false);
-    let mut sudo_pending = use_signal(|| Option::<String>::None);
+    // Changes from a sandboxed try, waiting for Apply or Discard
+    let mut sandbox_run = use_signal(|| None::<std::rc::Rc<SandboxRun>>);
     let mut is_loading = use_signal(|| false);  // Loading indicator
     let mut loading_message = use_signal(|| String::from("Thinking..."));
     
@@ -521,7 +662,6 @@ pub fn ChatPanel(mut props: ChatProps) -> Element {
             });
         }
     });
-    let mut sudo_pw = use_signal(String::new);
     let pty = props.pty;
 
     // Listen for clear trigger and reset messages + persist
@@ -541,6 +681,90 @@ pub fn ChatPanel(mut props: ChatProps) -> Element {
         });
     }
 
+    // "Explain failure" on a terminal command block
+    {
+        let mut explain = props.explain_block;
+        let auth_service = props.auth_service;
+        let mut last_provider = props.last_provider;
+        use_effect(move || {
+            let Some(block) = explain() else { return };
+            explain.set(None);
+            let mut msgs = messages.clone();
+            msgs.write().push(Message {
+                author: "Architect".to_string(),
+                text: format!("Explain why `{}` failed", block.command),
+                is_streaming: false,
+                ..Default::default()
+            });
+            save_messages(&msgs.read());
+            is_loading.set(true);
+            loading_message.set(String::from("🔍 Reading the failure..."));
+
+            spawn(async move {
+                let user_opt = auth_service.read().get_user();
+                let reply = ask_with_context(explain_failure_prompt(&block), user_opt.as_ref()).await;
+                if let Some(label) = &reply.provider {
+                    last_provider.set(label.clone());
+                }
+                msgs.write().push(reply);
+                save_messages(&msgs.read());
+                is_loading.set(false);
+            });
+        });
+    }
+
+    // Terminal recording attached from the terminal panel
+    {
+        let mut attach = props.attach_recording;
+        let auth_service = props.auth_service;
+        let mut last_provider = props.last_provider;
+        use_effect(move || {
+            let Some(path) = attach() else { return };
+            attach.set(None);
+            let mut msgs = messages.clone();
+            let context = match crate::services::recordings::chat_context(&path) {
+                Ok(context) => context,
+                Err(e) => {
+                    msgs.write().push(Message {
+                        author: "Kael".to_string(),
+                        text: format!("❌ {}", e),
+                        is_streaming: false,
+                        ..Default::default()
+                    });
+                    return;
+                }
+            };
+            let name = path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+            msgs.write().push(Message {
+                author: "Architect".to_string(),
+                text: format!("📎 Attached terminal recording `{}`. What happened here?", name),
+                is_streaming: false,
+                ..Default::default()
+            });
+            save_messages(&msgs.read());
+            is_loading.set(true);
+            loading_message.set(String::from("🎞️ Watching the recording..."));
+
+            spawn(async move {
+                let prompt = format!(
+                    "Here is a recording of my terminal session. Summarise what happened, point out any errors and how to fix them.\n\n{}",
+                    context
+                );
+                let user_opt = auth_service.read().get_user();
+                let reply = ask_with_context(prompt, user_opt.as_ref()).await;
+                if let Some(label) = &reply.provider {
+                    last_provider.set(label.clone());
+                }
+                msgs.write().push(reply);
+                save_messages(&msgs.read());
+                is_loading.set(false);
+            });
+        });
+    }
+
     // Warm up local AI and warn if unavailable at startup
     use_effect(move || {
         let mut msgs = messages.clone();
@@ -657,19 +881,11 @@ pub fn ChatPanel(mut props: ChatProps) -> Element {
                     AIDecision::HandleLocally(_) => LLMProvider::Ollama,
                     AIDecision::EscalateToCloud(_) => {
                         // Try to use best cloud provider from user's preference
-                        if let Ok(json) = std::fs::read_to_string("/tmp/kael_last_cloud_provider.json") {
-                            if let Ok(provider_name) = serde_json::from_str::<String>(&json) {
-                                match provider_name.as_str() {
-                                    "Mistral AI" => LLMProvider::Mistral,
-                                    "Google Gemini" => LLMProvider::Gemini,
-                                    "GitHub Copilot" => LLMProvider::Copilot,
-                                    _ => LLMProvider::Ollama,
-                                }
-                            } else {
-                                LLMProvider::Ollama
-                            }
-                        } else {
-                            LLMProvider::Ollama
+                        match crate::settings::current().last_cloud_provider.as_deref() {
+                            Some("Mistral AI") => LLMProvider::Mistral,
+                            Some("Google Gemini") => LLMProvider::Gemini,
+                            Some("GitHub Copilot") => LLMProvider::Copilot,
+                            _ => LLMProvider::Ollama,
                         }
                     }
                     AIDecision::AskForClarification(_) => LLMProvider::Ollama,
@@ -697,37 +913,15 @@ pub fn ChatPanel(mut props: ChatProps) -> Element {
                             _ => None,
                         }
                     }
-                    if let Ok(json) = std::fs::read_to_string("/tmp/kael_provider_order.json") {
-                        if let Ok(list) = serde_json::from_str::<Vec<String>>(&json) {
-                            let mut out = Vec::new();
-                            for name in list {
-                                if let Some(p) = map_name(&name) {
-                                    if p != LLMProvider::Ollama { // exclude primary local
-                                        out.push((p, None));
-                                    }
-                                }
+                    let mut out = Vec::new();
+                    for name in crate::settings::current().provider_order {
+                        if let Some(p) = map_name(&name) {
+                            if p != LLMProvider::Ollama { // exclude primary local
+                                out.push((p, None));
                             }
-                            if !out.is_empty() { out } else {
-                                vec![
-                                    (LLMProvider::Mistral, None),
-                                    (LLMProvider::Gemini, None),
-                                    (LLMProvider::Copilot, None),
-                                    (LLMProvider::CopilotAgent, None),
-                                    (LLMProvider::Office365AI, None),
-                                    (LLMProvider::GoogleOneAI, None),
-                                ]
-                            }
-                        } else {
-                            vec![
-                                (LLMProvider::Mistral, None),
-                                (LLMProvider::Gemini, None),
-                                (LLMProvider::Copilot, None),
-                                (LLMProvider::CopilotAgent, None),
-                                (LLMProvider::Office365AI, None),
-                                (LLMProvider::GoogleOneAI, None),
-                            ]
                         }
-                    } else {
+                    }
+                    if !out.is_empty() { out } else {
                         vec![
                             (LLMProvider::Mistral, None),
                             (LLMProvider::Gemini, None),
@@ -947,6 +1141,63 @@ pub fn ChatPanel(mut props: ChatProps) -> Element {
                                                             },
                                                             "Copy"
                                                         }
+                                                        {
+                                                            let script_text = message.text.clone();
+                                                            let mut scripts_version = props.scripts_version;
+                                                            rsx! {
+                                                                button {
+                                                                    style: "position: absolute; top: 8px; right: 64px; background: #1f1631; color: #7aebbe; border: 1px solid #3a2d56; border-radius: 8px; padding: 4px 8px; font-size: 12px;",
+                                                                    onclick: move |_| {
+                                                                        let text = script_text.clone();
+                                                                        spawn(async move {
+                                                                            let saved = match crate::db::shared() {
+                                                                                Ok(storage) => crate::services::script_library::save_from_chat(&storage, &text, None, Vec::new()).await,
+                                                                                Err(e) => Err(e),
+                                                                            };
+                                                                            match saved {
+                                                                                Ok(_) => *scripts_version.write() += 1,
+                                                                                Err(e) => log::error!("{}", e),
+                                                                            }
+                                                                        });
+                                                                    },
+                                                                    "Save as script"
+                                                                }
+                                                            }
+                                                        }
+                                                        {
+                                                            let try_text = message.text.clone();
+                                                            let mut msgs = messages.clone();
+                                                            rsx! {
+                                                                button {
+                                                                    style: "position: absolute; top: 8px; right: 176px; background: #1f1631; color: #ffcc00; border: 1px solid #3a2d56; border-radius: 8px; padding: 4px 8px; font-size: 12px;",
+                                                                    title: "Run with a read-only system, a throwaway home and no network; file changes are shown before they are applied",
+                                                                    onclick: move |_| {
+                                                                        let cmd = crate::services::script_library::extract_snippet(&try_text);
+                                                                        spawn(async move {
+                                                                            let workdir = std::env::current_dir()
+                                                                                .unwrap_or_else(|_| std::path::PathBuf::from(std::env::var("HOME").unwrap_or_else(|_| "/".to_string())));
+                                                                            let text = match crate::terminal::TerminalManager::new().run_sandboxed(&cmd, &workdir).await {
+                                                                                Ok(run) => {
+                                                                                    let report = sandbox_report(&cmd, &run);
+                                                                                    if !run.changes.is_empty() {
+                                                                                        sandbox_run.set(Some(std::rc::Rc::new(run)));
+                                                                                    }
+                                                                                    report
+                                                                                }
+                                                                                Err(e) => format!("⚠️  Could not try the command in a sandbox: {}", e),
+                                                                            };
+                                                                            msgs.write().push(Message {
+                                                                                author: "Kael".to_string(),
+                                                                                text,
+                                                                                ..Default::default()
+                                                                            });
+                                                                            save_messages(&msgs.read());
+                                                                        });
+                                                                    },
+                                                                    "Try in sandbox"
+                                                                }
+                                                            }
+                                                        }
                                                     }
                                             } else {
                                                 p { style: "margin: 0; word-wrap: break-word; word-break: break-word; overflow-wrap: break-word;", 
@@ -1065,6 +1316,57 @@ pub fn ChatPanel(mut props: ChatProps) -> Element {
                     }
                 }
             }
+            // Terminal text waiting to go out with the next message
+            if terminal_context.read().is_some() {
+                {
+                    let note = terminal_context.read().as_deref().map(context_note).unwrap_or_default();
+                    rsx! {
+                        div { style: "display: flex; align-items: center; gap: 8px; align-self: flex-start; padding: 4px 10px; border: 1px solid #e040fb; border-radius: 999px; background: #e040fb22; color: #f7f2ff; font-size: 12px;",
+                            span { "{note}" }
+                            span {
+                                style: "color: #ff6b9d; cursor: pointer;",
+                                title: "Remove",
+                                onclick: move |_| terminal_context.set(None),
+                                "×"
+                            }
+                        }
+                    }
+                }
+            }
+            // File changes from a sandboxed try
+            if sandbox_run.read().is_some() {
+                {
+                    let count = sandbox_run.read().as_ref().map(|run| run.changes.len()).unwrap_or(0);
+                    let mut msgs = messages.clone();
+                    rsx! {
+                        div { style: "display: flex; align-items: center; gap: 8px; align-self: flex-start; padding: 4px 10px; border: 1px solid #ffcc00; border-radius: 999px; background: #ffcc0022; color: #f7f2ff; font-size: 12px;",
+                            span { "🧪 {count} sandboxed file change(s)" }
+                            span {
+                                style: "color: #7aebbe; cursor: pointer;",
+                                onclick: move |_| {
+                                    let Some(run) = sandbox_run.write().take() else { return };
+                                    let text = match run.apply() {
+                                        Ok(n) => format!("✅ Applied {} file change(s) to {}", n, run.workdir().display()),
+                                        Err(e) => format!("❌ Applying the sandboxed changes failed: {}", e),
+                                    };
+                                    msgs.write().push(Message {
+                                        author: "Kael".to_string(),
+                                        text,
+                                        ..Default::default()
+                                    });
+                                    save_messages(&msgs.read());
+                                },
+                                "Apply"
+                            }
+                            span {
+                                style: "color: #ff6b9d; cursor: pointer;",
+                                onclick: move |_| sandbox_run.set(None),
+                                "Discard"
+                            }
+                        }
+                    }
+                }
+            }
             // Input area at bottom
             div {
                 class: "flex items-center gap-3 p-3 rounded-xl border",
@@ -1108,10 +1410,15 @@ pub fn ChatPanel(mut props: ChatProps) -> Element {
                                     });
                                 }
 
-                                // Handle sudo separately
-                                if cmd.starts_with("sudo ") || cmd == "sudo" {
-                                    sudo_pending.set(Some(cmd.clone()));
-                                    props.current_cmd.set(cmd);
+                                // Privileged commands run in the PTY; the password prompt is answered below
+                                if let Some(requested) = Elevation::requested_by(&cmd) {
+                                    let p = pty();
+                                    props.current_cmd.set(cmd.clone());
+                                    spawn(async move {
+                                        if let Err(e) = p.run_elevated(&cmd, elevation_for(requested)).await {
+                                            log::error!("PTY elevated command error: {}", e);
+                                        }
+                                    });
                                 } else {
                                     // Regular command - send to PTY
                                     let p = pty();
@@ -1134,9 +1441,13 @@ pub fn ChatPanel(mut props: ChatProps) -> Element {
                                 }
                             } else {
                                 // Not a command: treat as chat to LLM with fallback providers
+                                let context = terminal_context.write().take();
                                 messages.write().push(Message {
                                     author: "Architect".to_string(),
-                                    text: input_text.clone(),
+                                    text: match &context {
+                                        Some(context) => format!("{}\n\n{}", input_text, context_note(context)),
+                                        None => input_text.clone(),
+                                    },
                                     is_streaming: false,
                                     ..Default::default()
                                 });
@@ -1220,7 +1531,7 @@ pub fn ChatPanel(mut props: ChatProps) -> Element {
                                     let req = llm::LLMRequest {
                                         provider: primary_provider,
                                         model: String::new(),
-                                        prompt: clean_prompt.clone(),
+                                        prompt: with_terminal_context(&clean_prompt, context.as_deref()),
                                         api_key: None,
                                         system: Some(llm::get_kael_system_prompt()),
                                     };
@@ -1262,40 +1573,57 @@ pub fn ChatPanel(mut props: ChatProps) -> Element {
                         }
                     }
                 }
-                // Sudo prompt (appears only when needed)
-                if sudo_pending().is_some() {
-                    div { class: "flex items-center gap-2 px-2 py-2 rounded-md border", style: "border-color: #3a2d56; background: linear-gradient(135deg, #1f1631 0%, #181024 80%, #120b1f 100%);",
-                        span { style: "color: #a99ec3; font-size: 12px;", "sudo password:" }
-                        input {
-                            class: "p-2 rounded-md border",
-                            style: "background-color: #0f0b1a; border-color: #3a2a50; color: #f7f2ff;",
-                            value: "{sudo_pw}",
-                            oninput: move |e| sudo_pw.set(e.value()),
-                            r#type: "password",
-                            placeholder: "••••••••",
-                        }
-                        button { class: "px-2 py-1 rounded-md font-bold", style: "background: linear-gradient(135deg, #e040fb 0%, #ffcc00 60%, #7aebbe 100%); color: #120e1a; border: 1px solid #ffcc00;",
-                            onclick: move |_| {
-                                if let Some(cmd) = sudo_pending() {
+                // Password prompt from sudo/polkit in the terminal (appears only when needed).
+                // The field is not bound to a signal; the value goes straight into a Secret.
+                if props.sudo_prompt().is_some() {
+                    {
+                        let label = match props.sudo_prompt() {
+                            Some(PromptKind::Sudo { user }) => format!("sudo password for {}:", user),
+                            Some(PromptKind::Other(line)) => line,
+                            None => String::new(),
+                        };
+                        let mut sudo_prompt = props.sudo_prompt;
+                        rsx! {
+                            form { class: "flex items-center gap-2 px-2 py-2 rounded-md border", style: "border-color: #3a2d56; background: linear-gradient(135deg, #1f1631 0%, #181024 80%, #120b1f 100%);",
+                                onsubmit: move |evt| {
+                                    let password = evt.values().get("password").map(|v| v.as_value()).unwrap_or_default();
+                                    let secret = Secret::new(password);
                                     let p = pty();
-                                    let pw = sudo_pw();
+                                    sudo_prompt.set(None);
                                     spawn(async move {
-                                        // Write password then command
-                                        let sudo_line = format!("sudo {}", cmd.strip_prefix("sudo ").unwrap_or(&cmd));
-                                        if let Err(e) = p.write_line(&sudo_line).await {
-                                            eprintln!("PTY sudo error: {e}");
-                                        }
-                                        // PTY will prompt for password, send it
-                                        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-                                        if let Err(e) = p.write_line(&pw).await {
-                                            eprintln!("PTY password error: {e}");
+                                        if let Err(e) = p.answer_prompt(secret).await {
+                                            log::error!("PTY password error: {}", e);
                                         }
                                     });
-                                    sudo_pw.set(String::new());
-                                    sudo_pending.set(None);
+                                    eval("document.getElementById('kael-sudo-form')?.reset();");
+                                },
+                                id: "kael-sudo-form",
+                                span { style: "color: #a99ec3; font-size: 12px;", "{label}" }
+                                input {
+                                    class: "p-2 rounded-md border",
+                                    style: "background-color: #0f0b1a; border-color: #3a2a50; color: #f7f2ff;",
+                                    name: "password",
+                                    r#type: "password",
+                                    autocomplete: "off",
+                                    placeholder: "••••••••",
+                                }
+                                button { class: "px-2 py-1 rounded-md font-bold", style: "background: linear-gradient(135deg, #e040fb 0%, #ffcc00 60%, #7aebbe 100%); color: #120e1a; border: 1px solid #ffcc00;",
+                                    r#type: "submit",
+                                    "Submit"
                                 }
-                            },
-                            "Submit"
+                                button { class: "px-2 py-1 rounded-md", style: "background: transparent; color: #a99ec3; border: 1px solid #3a2d56;",
+                                    r#type: "button",
+                                    onclick: move |_| {
+                                        // Ctrl-C makes sudo give up
+                                        let p = pty();
+                                        sudo_prompt.set(None);
+                                        spawn(async move {
+                                            let _ = p.write_bytes(b"\x03").await;
+                                        });
+                                    },
+                                    "Cancel"
+                                }
+                            }
                         }
                     }
                 }
@@ -1325,9 +1653,15 @@ pub fn ChatPanel(mut props: ChatProps) -> Element {
                                     });
                                 }
 
-                                if cmd.starts_with("sudo ") || cmd == "sudo" {
-                                    sudo_pending.set(Some(cmd.clone()));
-                                    props.current_cmd.set(cmd);
+                                // Privileged commands run in the PTY; the password prompt is answered below
+                                if let Some(requested) = Elevation::requested_by(&cmd) {
+                                    let p = pty();
+                                    props.current_cmd.set(cmd.clone());
+                                    spawn(async move {
+                                        if let Err(e) = p.run_elevated(&cmd, elevation_for(requested)).await {
+                                            log::error!("PTY elevated command error: {}", e);
+                                        }
+                                    });
                                 } else {
                                     let p = pty();
                                     let cmd_display = cmd.clone();
@@ -1348,9 +1682,13 @@ pub fn ChatPanel(mut props: ChatProps) -> Element {
                                 }
                             } else {
                                 // Send to LLM as chat
+                                let context = terminal_context.write().take();
                                 messages.write().push(Message {
                                     author: "Architect".to_string(),
-                                    text: input_text.clone(),
+                                    text: match &context {
+                                        Some(context) => format!("{}\n\n{}", input_text, context_note(context)),
+                                        None => input_text.clone(),
+                                    },
                                     is_streaming: false,
                                     ..Default::default()
                                 });
@@ -1383,7 +1721,7 @@ pub fn ChatPanel(mut props: ChatProps) -> Element {
                                     let req = llm::LLMRequest {
                                         provider: primary_provider,
                                         model: String::new(),
-                                        prompt: prompt.clone(),
+                                        prompt: with_terminal_context(&prompt, context.as_deref()),
                                         api_key: None,
                                         system: Some(llm::get_kael_system_prompt()),
                                     };
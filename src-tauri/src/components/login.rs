@@ -3,157 +3,90 @@ use crate::auth::{
     firebase_sign_up_email_password, get_google_oauth_url, AuthService, User,
 };
 use crate::components::icons::SparkIcon;
-use crate::oauth_server::OAUTH_SERVER;
+use crate::oauth_server::{OAuthFlow, FLOW_TIMEOUT};
 use dioxus::prelude::*;
-use dioxus_desktop::{use_window, Config, DesktopService};
-use std::rc::Weak;
-use tao::dpi::LogicalSize;
-use tao::window::WindowBuilder;
-use tokio::time::{sleep, Duration};
 
 #[derive(Props, Clone, PartialEq)]
 pub struct LoginProps {
     pub auth_service: Signal<AuthService>,
 }
 
-#[derive(Props, Clone, PartialEq)]
-struct OAuthWebviewProps {
-    provider: String,
-    url: String,
-}
-
-#[allow(non_snake_case)]
-fn OAuthWebview(props: OAuthWebviewProps) -> Element {
-    let window = use_window();
-    let url = props.url.clone();
-
-    // Push the OAuth URL into the dedicated webview window as soon as it mounts.
-    use_effect(move || {
-        let _ = window.webview.load_url(&url);
-    });
-
-    rsx! {
-        div { class: "h-full w-full flex items-center justify-center bg-[#0f0b1a] text-[#cbd5ff]",
-            div { class: "text-center space-y-2",
-                div { class: "text-lg font-bold", "Opening {props.provider} sign-in" }
-                div { class: "text-sm text-[#a99ec3]", "You can close this window after completing the login." }
-            }
-        }
-    }
-}
-
-fn open_oauth_webview(
-    desktop: &dioxus_desktop::DesktopContext,
-    provider: &str,
-    url: &str,
-) -> Weak<DesktopService> {
-    let cfg = Config::new().with_window(
-        WindowBuilder::new()
-            .with_title(format!("{provider} Sign In"))
-            .with_inner_size(LogicalSize::new(480.0, 720.0))
-            .with_resizable(true),
-    );
-
-    let dom = VirtualDom::new_with_props(
-        OAuthWebview,
-        OAuthWebviewProps {
-            provider: provider.to_string(),
-            url: url.to_string(),
-        },
-    );
-
-    desktop.new_window(dom, cfg)
-}
-
-fn spawn_oauth_poll(
+/// Run one sign-in in the system browser. Starting a new one cancels a flow
+/// still waiting, so a stale flow cannot time out over the current one.
+fn spawn_oauth_flow(
     provider: String,
     mut auth_service: Signal<AuthService>,
     mut show_oauth_message: Signal<Option<String>>,
-    mut oauth_window_handle: Signal<Option<Weak<DesktopService>>>,
+    mut oauth_task: Signal<Option<Task>>,
 ) {
-    spawn(async move {
-        let mut attempts = 0;
-        let max_attempts = 240; // 120 seconds with 500ms polling
-
-        loop {
-            attempts += 1;
-            log::debug!("OAuth poll attempt {} for {}", attempts, provider);
-
-            if let Some(callback) = OAUTH_SERVER.get_callback(&provider).await {
-                OAUTH_SERVER.clear_callback(&provider).await;
-
-                log::info!(
-                    "OAuth callback received for {}: code={:?}, error={:?}",
-                    provider,
-                    callback.code.is_some(),
-                    callback.error
-                );
-
-                if let Some(err) = callback.error {
-                    log::warn!("OAuth error from provider: {}", err);
-                    show_oauth_message.set(Some(format!("error_oauth:{}", err)));
-                    break;
-                } else if let Some(code) = callback.code.clone() {
-                    if code.is_empty() {
-                        log::warn!("OAuth code is empty");
-                        show_oauth_message.set(Some("error_empty_code".to_string()));
-                        break;
+    if let Some(previous) = oauth_task.write().take() {
+        previous.cancel();
+    }
+    let task = spawn(async move {
+        // Each sign-in gets its own loopback port, state and PKCE verifier
+        let flow = match OAuthFlow::start(&provider).await {
+            Ok(flow) => flow,
+            Err(e) => {
+                log::error!("{}", e);
+                show_oauth_message.set(Some(format!("error_config: {}", e)));
+                return;
+            }
+        };
+        let url = match get_google_oauth_url(&flow) {
+            Ok(url) => url,
+            Err(e) => {
+                log::error!("Failed to build Google OAuth URL: {}", e);
+                show_oauth_message.set(Some(format!("error_config: {}", e)));
+                return;
+            }
+        };
+
+        log::info!("Opening Google OAuth in the system browser");
+        if let Err(e) = open::that(&url) {
+            log::error!("Failed to open the browser: {}", e);
+            show_oauth_message.set(Some("error_browser".to_string()));
+            oauth_task.set(None);
+            return;
+        }
+        show_oauth_message.set(Some("opening_browser_google".to_string()));
+
+        match flow.wait(FLOW_TIMEOUT).await {
+            Ok(grant) => {
+                log::info!("OAuth callback received for {}", provider);
+                match exchange_google_code_for_token(&grant).await {
+                    Ok(user) => {
+                        log::info!("OAuth succeeded for {}, user: {}", provider, user.email);
+                        auth_service.write().set_user(user);
+                        show_oauth_message.set(None);
                     }
-
-                    log::info!("Exchanging OAuth code for {}: {}", provider, code);
-
-                    let result = if provider == "google" {
-                        log::debug!("Calling exchange_google_code_for_token");
-                        exchange_google_code_for_token(&code).await
-                    } else {
-                        Err("Invalid provider".to_string())
-                    };
-
-                    match result {
-                        Ok(user) => {
-                            log::info!("OAuth succeeded for {}, user: {}", provider, user.email);
-                            auth_service.write().set_user(user);
-                            show_oauth_message.set(None);
-                        }
-                        Err(e) => {
-                            log::error!("OAuth exchange failed: {}", e);
-                            show_oauth_message.set(Some(format!("error_exchange:{}", e)));
-                        }
+                    Err(e) => {
+                        log::error!("OAuth exchange failed: {}", e);
+                        show_oauth_message.set(Some(format!("error_exchange:{}", e)));
                     }
                 }
-
-                break;
             }
-
-            if attempts >= max_attempts {
-                log::warn!("OAuth poll timeout for {}", provider);
+            Err(e) if e.ends_with("timed out") => {
+                log::warn!("OAuth sign-in for {} timed out", provider);
                 show_oauth_message.set(Some("error_timeout".to_string()));
-                break;
             }
-
-            sleep(Duration::from_millis(500)).await;
-        }
-
-        // Close the OAuth window
-        if let Some(handle) = oauth_window_handle() {
-            if let Some(win) = handle.upgrade() {
-                log::debug!("Closing OAuth window");
-                win.close();
+            Err(e) => {
+                log::warn!("OAuth sign-in for {} did not complete: {}", provider, e);
+                show_oauth_message.set(Some(format!("error_oauth:{}", e)));
             }
         }
-        oauth_window_handle.set(None);
+        oauth_task.set(None);
     });
+    oauth_task.set(Some(task));
 }
 
 #[allow(non_snake_case)]
 pub fn LoginPanel(mut props: LoginProps) -> Element {
-    let desktop = use_window();
     let mut email_input = use_signal(String::new);
     let mut token_input = use_signal(String::new);
     let mut password_input = use_signal(String::new);
     let login_error = use_signal(|| None as Option<String>);
     let mut show_oauth_message = use_signal(|| None as Option<String>);
-    let mut oauth_window_handle = use_signal(|| None as Option<Weak<DesktopService>>);
+    let mut oauth_task = use_signal(|| None as Option<Task>);
 
     let auth = props.auth_service.read();
 
@@ -254,27 +187,8 @@ pub fn LoginPanel(mut props: LoginProps) -> Element {
                         button {
                             class: "w-full px-4 py-3 rounded-lg font-bold flex items-center justify-center gap-2",
                             style: "background: linear-gradient(135deg, #4285f4 0%, #357ae8 100%); color: white; border: 1px solid #357ae8; cursor: pointer;",
-                            onclick: {
-                                let desktop = desktop.clone();
-                                move |_| {
-                                    match get_google_oauth_url() {
-                                        Ok(url) => {
-                                            log::info!("Opening Google OAuth in embedded webview");
-                                            if let Some(handle) = oauth_window_handle() {
-                                                if let Some(win) = handle.upgrade() { win.close(); }
-                                            }
-
-                                            let weak = open_oauth_webview(&desktop, "Google", &url);
-                                            oauth_window_handle.set(Some(weak));
-                                            show_oauth_message.set(Some("opening_webview_google".to_string()));
-                                            spawn_oauth_poll("google".to_string(), props.auth_service.clone(), show_oauth_message.clone(), oauth_window_handle.clone());
-                                        }
-                                        Err(e) => {
-                                            log::error!("Failed to build Google OAuth URL: {}", e);
-                                            show_oauth_message.set(Some(format!("error_config: {}", e)));
-                                        }
-                                    }
-                                }
+                            onclick: move |_| {
+                                spawn_oauth_flow("google".to_string(), props.auth_service.clone(), show_oauth_message.clone(), oauth_task.clone());
                             },
                             span { "🔵 Sign in with Google" }
                         }
@@ -381,7 +295,7 @@ pub fn LoginPanel(mut props: LoginProps) -> Element {
 
                     // OAuth status modal
                     if let Some(message) = show_oauth_message() {
-                        if message == "opening_webview_google" || message == "opening_webview_github" {
+                        if message == "opening_browser_google" || message == "opening_browser_github" {
                             div {
                                 class: "fixed inset-0 bg-black/60 flex items-center justify-center",
                                 style: "z-index: 9999;",
@@ -389,25 +303,22 @@ pub fn LoginPanel(mut props: LoginProps) -> Element {
                                     class: "bg-[#0f0b1a] border border-[#3a2d56] rounded-xl shadow-xl w-[450px] p-6",
                                     div { class: "flex items-center justify-between mb-4",
                                         div { style: "color: #ffcc00; font-weight: bold; font-size: 16px;",
-                                            if message == "opening_webview_google" { "Google Authentication" } else { "GitHub Authentication" }
+                                            if message == "opening_browser_google" { "Google Authentication" } else { "GitHub Authentication" }
                                         }
                                         button {
                                             class: "px-2 py-1 text-sm rounded-md",
                                             style: "background: #1a1426; color: #cbd5ff; border: 1px solid #3a2d56; cursor: pointer;",
                                             onclick: move |_| {
-                                                if let Some(handle) = oauth_window_handle() {
-                                                    if let Some(win) = handle.upgrade() { win.close(); }
-                                                }
-                                                oauth_window_handle.set(None);
+                                                if let Some(task) = oauth_task.write().take() { task.cancel(); }
                                                 show_oauth_message.set(None);
                                             },
                                             "✕"
                                         }
                                     }
                                     div { style: "color: #a99ec3; line-height: 1.8; font-size: 14px;",
-                                        p { "A secure in-app browser window opened for authentication." }
+                                        p { "A sign-in page opened in your browser." }
                                         p { style: "margin-top: 12px; font-size: 13px; color: #7aebbe;",
-                                            "✓ After you sign in, we'll detect the callback and close the window automatically."
+                                            "✓ After you sign in, we'll detect the callback and finish automatically."
                                         }
                                     }
                                     button {
@@ -429,12 +340,15 @@ pub fn LoginPanel(mut props: LoginProps) -> Element {
                                         button {
                                             class: "px-2 py-1 text-sm rounded-md",
                                             style: "background: #1a1426; color: #cbd5ff; border: 1px solid #3a2d56; cursor: pointer;",
-                                            onclick: move |_| show_oauth_message.set(None),
+                                            onclick: move |_| {
+                                                if let Some(task) = oauth_task.write().take() { task.cancel(); }
+                                                show_oauth_message.set(None);
+                                            },
                                             "✕"
                                         }
                                     }
                                     div { style: "color: #a99ec3; line-height: 1.6; font-size: 13px;",
-                                        p { "Complete the login in the in-app browser window. This dialog will close once the callback is received." }
+                                        p { "Complete the login in your browser. This dialog will close once the callback is received." }
                                     }
                                 }
                             }
@@ -9,7 +9,9 @@ pub mod gpu_status;
 pub mod header;
 pub mod icons;
 pub mod login;
+pub mod profiles;
 pub mod project_archive_settings;
+pub mod script_library;
 pub mod settings;
 pub mod system_info;
 pub mod terminal;
@@ -0,0 +1,108 @@
+use crate::profiles::{self, ProfileKind};
+use dioxus::prelude::*;
+
+/// Start Kael again in the now-active profile; data dirs are fixed per process
+fn relaunch() -> Result<(), String> {
+    let exe = std::env::current_exe().map_err(|e| format!("Cannot find Kael's executable: {}", e))?;
+    std::process::Command::new(exe)
+        .args(std::env::args_os().skip(1))
+        .env_remove("KAEL_PROFILE")
+        .spawn()
+        .map_err(|e| format!("Failed to restart Kael: {}", e))?;
+    std::process::exit(0);
+}
+
+#[allow(non_snake_case)]
+pub fn ProfilesPanel() -> Element {
+    let mut registry_version = use_signal(|| 0u32);
+    let mut new_name = use_signal(String::new);
+    let mut new_local = use_signal(|| false);
+    let mut status = use_signal(String::new);
+
+    // Re-read after every change
+    let _ = registry_version();
+    let registry = profiles::registry();
+    let current = profiles::active().clone();
+
+    rsx! {
+        div {
+            h2 { style: "color: #e040fb; margin-bottom: 4px;", "Profiles" }
+            p { style: "color: #a99ec3; font-size: 12px; margin: 0 0 12px 0;",
+                "Each profile has its own settings, keys, history and sign-in. Local-only profiles never use the cloud."
+            }
+
+            div { style: "display: flex; flex-direction: column; gap: 8px; margin-bottom: 12px;",
+                for profile in registry.list().iter().cloned() {
+                    div {
+                        key: "{profile.id}",
+                        style: "display: flex; align-items: center; justify-content: space-between; padding: 8px 12px; border-radius: 8px; border: 1px solid #3a2d56; background: #1a1426;",
+                        div {
+                            span { style: "color: #f7f2ff; font-weight: 600;", "{profile.name}" }
+                            span {
+                                style: if profile.is_local() { "margin-left: 8px; font-size: 11px; color: #7aebbe;" } else { "margin-left: 8px; font-size: 11px; color: #cbd5ff;" },
+                                if profile.is_local() { "local only" } else { "cloud" }
+                            }
+                        }
+                        if profile.id == current.id {
+                            span { style: "color: #ffcc00; font-size: 12px; font-weight: bold;", "In use" }
+                        } else {
+                            button {
+                                class: "px-3 py-1 rounded-md text-sm font-bold",
+                                style: "background: #1a1426; color: #ffcc00; border: 1px solid #ffcc00; cursor: pointer;",
+                                onclick: move |_| {
+                                    match profiles::registry().set_active(&profile.id) {
+                                        Ok(p) => {
+                                            log::info!("Switching to profile {}", p.name);
+                                            if let Err(e) = relaunch() {
+                                                status.set(format!("❌ {}", e));
+                                            }
+                                        }
+                                        Err(e) => status.set(format!("❌ {}", e)),
+                                    }
+                                },
+                                "Switch"
+                            }
+                        }
+                    }
+                }
+            }
+
+            div { style: "display: flex; gap: 8px; align-items: center;",
+                input {
+                    class: "flex-1 p-2 rounded-md border",
+                    style: "background-color: #0f0b1a; border-color: #3a2a50; color: #f7f2ff; font-size: 12px;",
+                    placeholder: "New profile name",
+                    value: "{new_name}",
+                    oninput: move |e| new_name.set(e.value()),
+                }
+                label { style: "color: #a99ec3; font-size: 12px; display: flex; align-items: center; gap: 4px;",
+                    input {
+                        r#type: "checkbox",
+                        checked: new_local(),
+                        onchange: move |e| new_local.set(e.checked()),
+                    }
+                    "Local only"
+                }
+                button {
+                    class: "px-3 py-2 rounded-md text-sm font-bold",
+                    style: "background: linear-gradient(135deg, #ffcc00 0%, #ffa500 100%); color: #120e1a; border: 1px solid #ffcc00; cursor: pointer;",
+                    onclick: move |_| {
+                        let kind = if new_local() { ProfileKind::Local } else { ProfileKind::Cloud };
+                        match profiles::registry().create(&new_name(), kind) {
+                            Ok(p) => {
+                                status.set(format!("✅ Added {}", p.name));
+                                new_name.set(String::new());
+                                registry_version += 1;
+                            }
+                            Err(e) => status.set(format!("❌ {}", e)),
+                        }
+                    },
+                    "Add"
+                }
+            }
+            if !status().is_empty() {
+                div { style: "color: #cbd5ff; font-size: 12px; margin-top: 8px;", "{status}" }
+            }
+        }
+    }
+}
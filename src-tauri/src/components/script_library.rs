@@ -0,0 +1,302 @@
+// src-tauri/src/components/script_library.rs
+use dioxus::prelude::*;
+use std::collections::BTreeMap;
+
+use crate::services::script_library::{self, Placeholder};
+use crate::state::Script;
+use crate::terminal::PtyTerminal;
+use kael_storage::ScriptRun;
+
+#[derive(Props, Clone, PartialEq)]
+pub struct ScriptLibraryProps {
+    pub pty: Signal<PtyTerminal>,
+    /// Bumped whenever scripts or run history change (save from chat, run finished)
+    pub scripts_version: Signal<u32>,
+}
+
+async fn load_scripts(tag: String) -> Result<(Vec<Script>, Vec<String>), String> {
+    let storage = crate::db::shared()?;
+    let repo = storage.scripts();
+    let scripts = if tag.is_empty() { repo.list().await } else { repo.list_by_tag(&tag).await }
+        .map_err(|e| format!("Failed to load scripts: {}", e))?;
+    let tags = repo.tags().await.map_err(|e| format!("Failed to load tags: {}", e))?;
+    Ok((scripts, tags))
+}
+
+async fn load_runs(script_id: String) -> Vec<ScriptRun> {
+    match crate::db::shared() {
+        Ok(storage) => storage.scripts().runs(&script_id, 5).await.unwrap_or_default(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn tag_chip_style(active: bool) -> &'static str {
+    if active {
+        "padding: 2px 6px; border-radius: 10px; font-size: 10px; border: 1px solid #7aebbe; background: rgba(122, 235, 190, 0.2); color: #7aebbe;"
+    } else {
+        "padding: 2px 6px; border-radius: 10px; font-size: 10px; border: 1px solid #3a2d56; background: transparent; color: #a99ec3;"
+    }
+}
+
+fn script_row_style(selected: bool) -> &'static str {
+    if selected {
+        "padding: 6px 8px; border-radius: 6px; border: 1px solid #7aebbe; background: rgba(122, 235, 190, 0.08); cursor: pointer;"
+    } else {
+        "padding: 6px 8px; border-radius: 6px; border: 1px solid #3a2d56; background: #181024; cursor: pointer;"
+    }
+}
+
+#[allow(non_snake_case)]
+pub fn ScriptLibraryPanel(props: ScriptLibraryProps) -> Element {
+    let mut scripts_version = props.scripts_version;
+    let tag_filter = use_signal(String::new);
+    let mut selected = use_signal(|| None::<Script>);
+    let mut param_values = use_signal(BTreeMap::<String, String>::new);
+    let mut extra_args = use_signal(String::new);
+    let mut import_path = use_signal(String::new);
+    let mut status = use_signal(String::new);
+
+    let library = use_resource(move || async move {
+        let _ = scripts_version();
+        load_scripts(tag_filter()).await
+    });
+
+    let runs = use_resource(move || async move {
+        let _ = scripts_version();
+        match selected() {
+            Some(script) => load_runs(script.id).await,
+            None => Vec::new(),
+        }
+    });
+
+    let (scripts, tags) = match &*library.read() {
+        Some(Ok((scripts, tags))) => (scripts.clone(), tags.clone()),
+        Some(Err(e)) => {
+            log::error!("{}", e);
+            (Vec::new(), Vec::new())
+        }
+        None => (Vec::new(), Vec::new()),
+    };
+    let params: Vec<Placeholder> = selected()
+        .map(|s| script_library::placeholders(&s.content))
+        .unwrap_or_default();
+    let recent_runs = runs.read().clone().unwrap_or_default();
+
+    rsx! {
+        div {
+            // Tag filter
+            if !tags.is_empty() {
+                div { style: "display: flex; flex-wrap: wrap; gap: 4px; margin-bottom: 8px;",
+                    {
+                        let mut tag_filter = tag_filter;
+                        let active = tag_filter().is_empty();
+                        rsx! {
+                            button {
+                                style: tag_chip_style(active),
+                                onclick: move |_| tag_filter.set(String::new()),
+                                "all"
+                            }
+                        }
+                    }
+                    for tag in tags.into_iter() {
+                        {
+                            let mut tag_filter = tag_filter;
+                            let active = tag_filter().eq_ignore_ascii_case(&tag);
+                            let t = tag.clone();
+                            rsx! {
+                                button {
+                                    style: tag_chip_style(active),
+                                    onclick: move |_| tag_filter.set(t.clone()),
+                                    "#{tag}"
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+
+            // Script list
+            if scripts.is_empty() {
+                span { style: "color: #a99ec3; font-size: 12px;", "No saved scripts. Use \"Save as script\" on a Kael reply." }
+            } else {
+                div { style: "max-height: 180px; overflow-y: auto; display: flex; flex-direction: column; gap: 4px;",
+                    for script in scripts.into_iter() {
+                        {
+                            let is_selected = selected().map(|s| s.id == script.id).unwrap_or(false);
+                            let pick = script.clone();
+                            let tag_list = script.tags.join(", ");
+                            rsx! {
+                                div {
+                                    style: script_row_style(is_selected),
+                                    onclick: move |_| {
+                                        let defaults = script_library::placeholders(&pick.content)
+                                            .into_iter()
+                                            .filter_map(|p| p.default.map(|d| (p.name, d)))
+                                            .collect();
+                                        param_values.set(defaults);
+                                        extra_args.set(String::new());
+                                        selected.set(Some(pick.clone()));
+                                    },
+                                    div { style: "color: #f7f2ff; font-size: 12px; font-weight: 600;", "{script.name}" }
+                                    if !tag_list.is_empty() {
+                                        div { style: "color: #a99ec3; font-size: 10px;", "{tag_list}" }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+
+            // Selected script: parameters, run, history
+            if let Some(script) = selected() {
+                div { style: "margin-top: 8px; padding: 8px; border: 1px solid #3a2d56; border-radius: 8px; background: #120b1f;",
+                    pre { style: "margin: 0 0 6px 0; font-size: 11px; color: #7aebbe; white-space: pre-wrap; word-break: break-all; max-height: 100px; overflow-y: auto;", "{script.content}" }
+                    for p in params.into_iter() {
+                        {
+                            let name = p.name.clone();
+                            let value = param_values().get(&p.name).cloned().unwrap_or_default();
+                            rsx! {
+                                input {
+                                    style: "width: 100%; margin-bottom: 4px; padding: 4px 6px; font-size: 11px; background: #181024; border: 1px solid #3a2d56; border-radius: 4px; color: #f7f2ff;",
+                                    placeholder: "{p.name}",
+                                    value: "{value}",
+                                    oninput: move |evt| {
+                                        param_values.write().insert(name.clone(), evt.value());
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    input {
+                        style: "width: 100%; margin-bottom: 6px; padding: 4px 6px; font-size: 11px; background: #181024; border: 1px solid #3a2d56; border-radius: 4px; color: #f7f2ff;",
+                        placeholder: "extra arguments ($@)",
+                        value: "{extra_args}",
+                        oninput: move |evt| extra_args.set(evt.value()),
+                    }
+                    div { style: "display: flex; gap: 4px;",
+                        {
+                            let run_script = script.clone();
+                            rsx! {
+                                button {
+                                    style: "flex: 1; padding: 4px; border-radius: 6px; border: 1px solid #7aebbe; background: rgba(122, 235, 190, 0.2); color: #7aebbe; font-size: 11px; font-weight: 600;",
+                                    onclick: move |_| {
+                                        let script = run_script.clone();
+                                        let args = param_values();
+                                        let extra: Vec<String> = extra_args().split_whitespace().map(str::to_string).collect();
+                                        let pty = props.pty.read().clone();
+                                        spawn(async move {
+                                            let result = match crate::db::shared() {
+                                                Ok(storage) => script_library::run_in_pty(&storage, &pty, &script, &args, &extra).await,
+                                                Err(e) => Err(e),
+                                            };
+                                            match result {
+                                                Ok(_) => {
+                                                    status.set(format!("▶ Running {}", script.name));
+                                                    *scripts_version.write() += 1;
+                                                }
+                                                Err(e) => status.set(format!("❌ {}", e)),
+                                            }
+                                        });
+                                    },
+                                    "▶ Run"
+                                }
+                            }
+                        }
+                        {
+                            let export_script = script.clone();
+                            rsx! {
+                                button {
+                                    style: "padding: 4px 8px; border-radius: 6px; border: 1px solid #3a2d56; background: #1f1631; color: #f7f2ff; font-size: 11px;",
+                                    onclick: move |_| {
+                                        match script_library::export_to_file(&export_script, &script_library::export_dir()) {
+                                            Ok(path) => status.set(format!("💾 Exported to {}", path.display())),
+                                            Err(e) => status.set(format!("❌ {}", e)),
+                                        }
+                                    },
+                                    "Export .sh"
+                                }
+                            }
+                        }
+                        {
+                            let delete_id = script.id.clone();
+                            rsx! {
+                                button {
+                                    style: "padding: 4px 8px; border-radius: 6px; border: 1px solid #ff6b6b; background: rgba(255, 107, 107, 0.15); color: #ff6b6b; font-size: 11px;",
+                                    onclick: move |_| {
+                                        let id = delete_id.clone();
+                                        spawn(async move {
+                                            if let Ok(storage) = crate::db::shared() {
+                                                if let Err(e) = storage.scripts().delete(&id).await {
+                                                    log::error!("Failed to delete script: {}", e);
+                                                }
+                                            }
+                                            selected.set(None);
+                                            *scripts_version.write() += 1;
+                                        });
+                                    },
+                                    "🗑️"
+                                }
+                            }
+                        }
+                    }
+                    if !recent_runs.is_empty() {
+                        div { style: "margin-top: 6px;",
+                            for run in recent_runs.into_iter() {
+                                {
+                                    let (label, color) = match run.exit_code {
+                                        Some(0) => ("exit 0".to_string(), "#7aebbe"),
+                                        Some(code) => (format!("exit {}", code), "#ff6b6b"),
+                                        None => ("running".to_string(), "#ffcc00"),
+                                    };
+                                    let when = run.started_at.with_timezone(&chrono::Local).format("%m-%d %H:%M").to_string();
+                                    rsx! {
+                                        div { style: "display: flex; justify-content: space-between; font-size: 10px; color: #a99ec3;",
+                                            span { "{when}" }
+                                            span { style: "color: {color};", "{label}" }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+
+            // Import
+            div { style: "display: flex; gap: 4px; margin-top: 8px;",
+                input {
+                    style: "flex: 1; min-width: 0; padding: 4px 6px; font-size: 11px; background: #181024; border: 1px solid #3a2d56; border-radius: 4px; color: #f7f2ff;",
+                    placeholder: "path/to/script.sh",
+                    value: "{import_path}",
+                    oninput: move |evt| import_path.set(evt.value()),
+                }
+                button {
+                    style: "padding: 4px 8px; border-radius: 6px; border: 1px solid #3a2d56; background: #1f1631; color: #f7f2ff; font-size: 11px;",
+                    onclick: move |_| {
+                        let path = std::path::PathBuf::from(import_path().trim());
+                        spawn(async move {
+                            let result = match crate::db::shared() {
+                                Ok(storage) => script_library::import_from_file(&storage, &path).await,
+                                Err(e) => Err(e),
+                            };
+                            match result {
+                                Ok(script) => {
+                                    status.set(format!("📥 Imported {}", script.name));
+                                    import_path.set(String::new());
+                                    *scripts_version.write() += 1;
+                                }
+                                Err(e) => status.set(format!("❌ {}", e)),
+                            }
+                        });
+                    },
+                    "Import"
+                }
+            }
+
+            if !status().is_empty() {
+                div { style: "margin-top: 6px; color: #a99ec3; font-size: 11px; word-break: break-all;", "{status}" }
+            }
+        }
+    }
+}
@@ -2,7 +2,9 @@
 use crate::auth::AuthService;
 use crate::components::api_key_manager::ApiKeyManager;
 use crate::components::login::LoginPanel;
+use crate::components::profiles::ProfilesPanel;
 use crate::llm::{self, LLMProvider, LLMRequest};
+use crate::settings::SyncConfig;
 use dioxus::prelude::*;
 
 fn render_themes_tab() -> Element {
@@ -165,6 +167,36 @@ struct ProviderUIState {
     api_key: String,
 }
 
+/// One row per provider known to settings, so a saved order always validates
+fn default_provider_states() -> Vec<ProviderUIState> {
+    crate::settings::PROVIDERS
+        .iter()
+        .map(|(name, enabled)| ProviderUIState {
+            name: name.to_string(),
+            enabled: *enabled,
+            api_key: String::new(),
+        })
+        .collect()
+}
+
+type SyncToggle = (&'static str, fn(&SyncConfig) -> bool, fn(&mut SyncConfig, bool));
+
+/// Per-collection sync switches shown under "Sync with Firebase"
+const SYNC_TOGGLES: &[SyncToggle] = &[
+    ("Settings", |c| c.settings, |c, v| c.settings = v),
+    ("Projects", |c| c.projects, |c, v| c.projects = v),
+    ("Scripts", |c| c.scripts, |c, v| c.scripts = v),
+    ("Chat sessions", |c| c.chat_sessions, |c, v| {
+        c.chat_sessions = v;
+        // Messages can't sync without their sessions
+        c.chat_messages &= v;
+    }),
+    ("Chat messages", |c| c.chat_messages, |c, v| {
+        c.chat_messages = v;
+        c.chat_sessions |= v;
+    }),
+];
+
 #[derive(Clone, PartialEq, Debug)]
 struct LocalModel {
     name: String,
@@ -184,6 +216,18 @@ fn provider_by_name(name: &str) -> Option<LLMProvider> {
     }
 }
 
+/// Fill in the keys the (unlocked) vault has. Returns how many were found.
+fn load_vault_keys(mut providers: Signal<Vec<ProviderUIState>>) -> usize {
+    let mut loaded = 0;
+    for p in providers.write().iter_mut() {
+        if let Some(key) = crate::vault::get(&p.name) {
+            p.api_key = key.to_string();
+            loaded += 1;
+        }
+    }
+    loaded
+}
+
 fn provider_requires_key(name: &str) -> bool {
     matches!(
         name,
@@ -262,60 +306,17 @@ pub struct SettingsPanelProps {
 #[allow(unknown_lints)]
 pub fn SettingsPanel(mut props: SettingsPanelProps) -> Element {
     let mut active_tab = use_signal(|| SettingsTab::Authentication);
-    let mut providers = use_signal(|| {
-        vec![
-            ProviderUIState {
-                name: "Ollama (Local)".to_string(),
-                enabled: true,
-                api_key: String::new(),
-            },
-            ProviderUIState {
-                name: "Mistral AI".to_string(),
-                enabled: true,
-                api_key: String::new(),
-            },
-            ProviderUIState {
-                name: "Google Gemini".to_string(),
-                enabled: false,
-                api_key: String::new(),
-            },
-            ProviderUIState {
-                name: "GitHub Copilot".to_string(),
-                enabled: false,
-                api_key: String::new(),
-            },
-            ProviderUIState {
-                name: "GitHub Copilot CLI (New)".to_string(),
-                enabled: true,
-                api_key: String::new(),
-            },
-            ProviderUIState {
-                name: "GitHub Copilot CLI".to_string(),
-                enabled: true,
-                api_key: String::new(),
-            },
-            ProviderUIState {
-                name: "Office 365 AI".to_string(),
-                enabled: false,
-                api_key: String::new(),
-            },
-            ProviderUIState {
-                name: "Google One AI".to_string(),
-                enabled: false,
-                api_key: String::new(),
-            },
-            ProviderUIState {
-                name: "Minstrel AI".to_string(),
-                enabled: false,
-                api_key: String::new(),
-            },
-        ]
-    });
+    let mut providers = use_signal(default_provider_states);
 
     let mut save_status = use_signal(String::new);
     let mut test_logs = use_signal(Vec::<String>::new);
     let local_models = use_signal(Vec::<LocalModel>::new);
     let mut hybrid_assist = use_signal(|| false);
+    let mut persistent_terminal = use_signal(|| false);
+    let mut api_server = use_signal(|| false);
+    let mut api_port = use_signal(String::new);
+    let mut sync_api_keys = use_signal(|| false);
+    let mut sync_config = use_signal(SyncConfig::default);
     let usage_counts = use_signal(|| std::collections::BTreeMap::<String, u64>::new());
     let available_models = vec![
         "llama3.1:8b".to_string(),
@@ -326,38 +327,21 @@ pub fn SettingsPanel(mut props: SettingsPanelProps) -> Element {
 
     let auth_signal = props.auth_service.clone();
     let mut gpg_status = use_signal(|| String::new());
+    let mut secrets_passphrase = use_signal(|| String::new());
+    let mut secrets_status = use_signal(|| String::new());
 
-    // Load provider keys from Firestore on mount (decrypts per user id_token)
+    // Load provider keys from the vault on mount, pulling from Firestore first when sync is on
     use_effect(move || {
-        let auth = auth_signal();
-        if let Some(user) = auth.get_user() {
-            log::info!("🔐 User authenticated, loading API keys from Firebase...");
-            let mut set_providers = providers.clone();
-            spawn(async move {
-                match crate::firebase::get_api_keys(&user).await {
-                    Ok(keys) => {
-                        log::info!("✅ Loaded {} API keys from Firebase", keys.len());
-                        let mut current = set_providers.write();
-                        for p in current.iter_mut() {
-                            if let Some(k) = keys.iter().find(|k| k.name == p.name) {
-                                log::info!("  ✓ Setting key for: {} ({} chars)", p.name, k.value.len());
-                                p.api_key = k.value.clone();
-                                // Cache in llm module for runtime use
-                                if !k.value.is_empty() {
-                                    crate::llm::cache_api_key(&p.name, &k.value);
-                                }
-                            }
-                        }
-                        log::info!("🎯 Provider keys loaded and UI should update");
-                    }
-                    Err(e) => {
-                        log::error!("❌ Failed to load API keys from Firebase: {}", e);
-                    }
+        let user = auth_signal().get_user();
+        spawn(async move {
+            if let Some(user) = user {
+                if let Err(e) = crate::vault::pull_from_firestore(&user).await {
+                    log::warn!("Failed to pull API keys from Firebase: {}", e);
                 }
-            });
-        } else {
-            log::info!("⚠️  No user authenticated, skipping API key load");
-        }
+            }
+            let loaded = load_vault_keys(providers);
+            log::info!("🔐 Loaded {} provider keys from the vault", loaded);
+        });
     });
 
     let auth_signal_clone = auth_signal.clone();
@@ -373,14 +357,26 @@ pub fn SettingsPanel(mut props: SettingsPanelProps) -> Element {
         });
     });
 
-    // Load hybrid assist toggle from cache
+    // Load the hybrid assist, persistent terminal and API toggles from settings
     use_effect(move || {
         let mut h = hybrid_assist.clone();
+        let mut pt = persistent_terminal.clone();
+        let mut api = api_server.clone();
+        let mut port = api_port.clone();
+        let mut sync = sync_api_keys.clone();
+        let mut sc = sync_config.clone();
         spawn(async move {
-            if let Ok(v) = std::fs::read_to_string("/tmp/kael_hybrid_assist.json") {
-                if v.trim() == "true" {
-                    h.set(true);
+            match crate::settings::init().await {
+                Ok(store) => {
+                    let settings = store.get();
+                    h.set(settings.hybrid_assist);
+                    pt.set(settings.persistent_terminal);
+                    api.set(settings.api_server);
+                    port.set(if settings.api_port == 0 { String::new() } else { settings.api_port.to_string() });
+                    sync.set(settings.sync_api_keys);
+                    sc.set(settings.sync);
                 }
+                Err(e) => log::warn!("Failed to load settings: {}", e),
             }
         });
     });
@@ -389,7 +385,7 @@ pub fn SettingsPanel(mut props: SettingsPanelProps) -> Element {
     {
         let mut uc = usage_counts.clone();
         use_effect(move || {
-            if let Ok(s) = std::fs::read_to_string("/tmp/kael_provider_usage.json") {
+            if let Ok(s) = std::fs::read_to_string(crate::components::chat::provider_usage_path()) {
                 if let Ok(map) = serde_json::from_str::<std::collections::BTreeMap<String, u64>>(&s) {
                     uc.set(map);
                 }
@@ -494,12 +490,23 @@ pub fn SettingsPanel(mut props: SettingsPanelProps) -> Element {
                 if active_tab() == SettingsTab::Authentication {
                     div {
                         h1 { style: "color: #ffcc00; letter-spacing: 0.02em; margin-bottom: 16px;", "Authentication" }
+                        div {
+                            style: "border: 1px solid #3a2a50; border-radius: 12px; padding: 16px; margin-bottom: 20px; background: linear-gradient(160deg, #1c162b 0%, #120e1a 60%, #0f0b1f 100%); box-shadow: 0 12px 28px #00000055;",
+                            ProfilesPanel {}
+                        }
                         div {
                             style: "border: 1px solid #3a2a50; border-radius: 12px; padding: 16px; background: linear-gradient(160deg, #1c162b 0%, #120e1a 60%, #0f0b1f 100%); box-shadow: 0 12px 28px #00000055;",
-                            LoginPanel { auth_service: props.auth_service.clone() }
+                            if crate::profiles::is_local() {
+                                div { style: "color: #7aebbe; font-weight: bold; margin-bottom: 6px;", "🏠 Local-only profile" }
+                                p { style: "color: #cbd5ff; font-size: 13px; margin: 0;",
+                                    "No sign-in is needed. Settings, keys and history stay on this machine and only local models are used."
+                                }
+                            } else {
+                                LoginPanel { auth_service: props.auth_service.clone() }
+                            }
                         }
 
-                        if props.auth_service.read().is_authenticated() {
+                        if props.auth_service.read().is_authenticated() && !crate::profiles::is_local() {
                             div {
                                 style: "margin-top: 20px; border: 1px solid #3a2a50; border-radius: 12px; padding: 16px; background: linear-gradient(160deg, #1c162b 0%, #120e1a 60%, #0f0b1f 100%); box-shadow: 0 12px 28px #00000055;",
                                 h2 { style: "color: #e040fb; margin-bottom: 12px;", "API Key Management" }
@@ -519,42 +526,22 @@ pub fn SettingsPanel(mut props: SettingsPanelProps) -> Element {
                             div { style: "display: flex; align-items: center; justify-content: space-between; margin-bottom: 16px;",
                                 h2 { style: "color: #e040fb; margin: 0;", "Available Providers" }
                                 
-                                // Refresh keys from Firebase button
-                                if props.auth_service.read().is_authenticated() {
-                                    button {
-                                        style: "padding: 6px 12px; border-radius: 6px; background: linear-gradient(135deg, #e040fb 0%, #7aebbe 100%); color: #0f0b1a; font-weight: 600; font-size: 12px; border: none; cursor: pointer;",
-                                        onclick: move |_| {
-                                            let auth = props.auth_service.read();
-                                            if let Some(user) = auth.get_user() {
-                                                let mut set_providers = providers.clone();
-                                                spawn(async move {
-                                                    log::info!("🔄 Manually refreshing API keys from Firebase...");
-                                                    match crate::firebase::get_api_keys(&user).await {
-                                                        Ok(keys) => {
-                                                            log::info!("✅ Loaded {} API keys from Firebase", keys.len());
-                                                            let mut current = set_providers.write();
-                                                            for p in current.iter_mut() {
-                                                                if let Some(k) = keys.iter().find(|k| k.name == p.name) {
-                                                                    log::info!("  ✓ Found key for: {} ({} chars)", p.name, k.value.len());
-                                                                    p.api_key = k.value.clone();
-                                                                    // Cache in llm module for runtime use
-                                                                    if !k.value.is_empty() {
-                                                                        crate::llm::cache_api_key(&p.name, &k.value);
-                                                                    }
-                                                                } else {
-                                                                    log::info!("  ✗ No key found for: {}", p.name);
-                                                                }
-                                                            }
-                                                        }
-                                                        Err(e) => {
-                                                            log::error!("❌ Failed to load keys from Firebase: {}", e);
-                                                        }
-                                                    }
-                                                });
+                                // Reload keys from the vault (and Firebase when sync is on)
+                                button {
+                                    style: "padding: 6px 12px; border-radius: 6px; background: linear-gradient(135deg, #e040fb 0%, #7aebbe 100%); color: #0f0b1a; font-weight: 600; font-size: 12px; border: none; cursor: pointer;",
+                                    onclick: move |_| {
+                                        let user = props.auth_service.read().get_user();
+                                        spawn(async move {
+                                            if let Some(user) = user {
+                                                if let Err(e) = crate::vault::pull_from_firestore(&user).await {
+                                                    log::error!("❌ Failed to pull keys from Firebase: {}", e);
+                                                }
                                             }
-                                        },
-                                        "🔄 Refresh Keys"
-                                    }
+                                            let loaded = load_vault_keys(providers);
+                                            log::info!("✅ Loaded {} API keys from the vault", loaded);
+                                        });
+                                    },
+                                    "🔄 Refresh Keys"
                                 }
                             }
 
@@ -566,7 +553,11 @@ pub fn SettingsPanel(mut props: SettingsPanelProps) -> Element {
                                     onchange: move |ev| {
                                         let val = ev.checked();
                                         hybrid_assist.set(val);
-                                        let _ = std::fs::write("/tmp/kael_hybrid_assist.json", if val { "true" } else { "false" });
+                                        spawn(async move {
+                                            if let Err(e) = crate::settings::update(|s| s.hybrid_assist = val).await {
+                                                log::error!("Failed to save hybrid assist: {}", e);
+                                            }
+                                        });
                                     }
                                 }
                                 span { style: "color: #f7f2ff; font-weight: 600;", "Hybrid Assist (local can delegate to cloud in your order)" }
@@ -576,42 +567,32 @@ pub fn SettingsPanel(mut props: SettingsPanelProps) -> Element {
                             div { style: "display: flex; gap: 8px; margin-bottom: 12px;",
                                 button { style: "padding: 8px 12px; border-radius: 8px; border: 1px solid #3a2d56; background: linear-gradient(135deg, #1f1631 0%, #181024 100%); color: #a99ec3; font-size: 12px;",
                                     onclick: move |_| {
-                                        let auth = auth_signal_clone();
-                                        if let Some(user) = auth.get_user() {
-                                            let mut set_providers = providers.clone();
-                                            spawn(async move {
-                                                match crate::firebase::get_api_keys(&user).await {
-                                                    Ok(keys) => {
-                                                        let mut current = set_providers.write();
-                                                        for p in current.iter_mut() {
-                                                            if let Some(k) = keys.iter().find(|k| k.name == p.name) {
-                                                                p.api_key = k.value.clone();
-                                                            }
-                                                        }
-                                                        // update cache file
-                                                        let simple: Vec<serde_json::Value> = keys.into_iter().map(|k| serde_json::json!({"name": k.name, "value": k.value})).collect();
-                                                        let json = serde_json::to_string(&simple).unwrap_or_else(|_| "[]".to_string());
-                                                        let _ = std::fs::write("/tmp/kael_cached_keys.json", json);
-                                                        test_logs.write().push("🔄 Keys refreshed from Firebase".to_string());
-                                                    }
-                                                    Err(e) => {
-                                                        test_logs.write().push(format!("❌ Refresh failed: {}", e));
-                                                    }
+                                        let user = auth_signal_clone().get_user();
+                                        spawn(async move {
+                                            if let Some(user) = user {
+                                                if let Err(e) = crate::vault::pull_from_firestore(&user).await {
+                                                    test_logs.write().push(format!("❌ Firebase pull failed: {}", e));
                                                 }
-                                            });
-                                        } else {
-                                            test_logs.write().push("⚠️ Not authenticated".to_string());
-                                        }
+                                            }
+                                            if !crate::vault::is_unlocked() {
+                                                test_logs.write().push("🔒 Vault is locked - unlock it in Security".to_string());
+                                            }
+                                            let loaded = load_vault_keys(providers);
+                                            test_logs.write().push(format!("🔄 {} keys loaded from the vault", loaded));
+                                        });
                                     },
                                     "Refresh Keys"
                                 }
                                 button { style: "padding: 8px 12px; border-radius: 8px; border: 1px solid #3a2d56; background: linear-gradient(135deg, #1f1631 0%, #181024 100%); color: #a99ec3; font-size: 12px;",
                                     onclick: move |_| {
                                         // Persist enabled provider order
-                                        let order: Vec<serde_json::Value> = providers().iter().filter(|p| p.enabled).map(|p| serde_json::json!(p.name.clone())).collect();
-                                        let json = serde_json::to_string(&order).unwrap_or_else(|_| "[]".to_string());
-                                        let _ = std::fs::write("/tmp/kael_provider_order.json", json);
-                                        test_logs.write().push("💾 Provider order saved".to_string());
+                                        let order: Vec<String> = providers().iter().filter(|p| p.enabled).map(|p| p.name.clone()).collect();
+                                        spawn(async move {
+                                            match crate::settings::update(|s| s.provider_order = order).await {
+                                                Ok(_) => test_logs.write().push("💾 Provider order saved".to_string()),
+                                                Err(e) => test_logs.write().push(format!("❌ Provider order not saved: {}", e)),
+                                            }
+                                        });
                                     },
                                     "Save Order"
                                 }
@@ -774,72 +755,66 @@ pub fn SettingsPanel(mut props: SettingsPanelProps) -> Element {
                                         save_status.set("Saving & testing providers...".to_string());
                                         test_logs.set(Vec::new());
 
-                                        let auth = auth_signal_clone();
-                                        if let Some(user) = auth.get_user() {
-                                            let snapshot = providers.read().clone();
-                                            let mut status_signal = save_status.clone();
-                                            let mut logs_signal = test_logs.clone();
-                                            spawn(async move {
-                                                let mut logs: Vec<String> = Vec::new();
-
-                                                for p in snapshot.iter() {
-                                                    // Persist only if key is non-empty
-                                                    if !p.api_key.is_empty() {
-                                                        log::info!("💾 Saving API key for: {}", p.name);
-                                                        match crate::firebase::save_api_key(&user, &p.name, &p.api_key).await {
-                                                            Ok(_) => {
-                                                                // Cache the key in memory for runtime use
-                                                                crate::llm::cache_api_key(&p.name, &p.api_key);
-                                                                log::info!("✅ Saved and cached key for: {}", p.name);
-                                                            }
-                                                            Err(e) => {
-                                                                log::error!("❌ Failed to save key for {}: {}", p.name, e);
-                                                            }
+                                        let user = auth_signal_clone().get_user();
+                                        let snapshot = providers.read().clone();
+                                        let mut status_signal = save_status.clone();
+                                        let mut logs_signal = test_logs.clone();
+                                        spawn(async move {
+                                            let mut logs: Vec<String> = Vec::new();
+
+                                            for p in snapshot.iter() {
+                                                // Persist only if key is non-empty
+                                                if !p.api_key.is_empty() {
+                                                    log::info!("💾 Saving API key for: {}", p.name);
+                                                    if let Err(e) = crate::vault::set(&p.name, &p.api_key) {
+                                                        logs.push(format!("❌ {} key not saved: {}", p.name, e));
+                                                        continue;
+                                                    }
+                                                    if let Some(user) = &user {
+                                                        if let Err(e) = crate::vault::push_to_firestore(user, &p.name, &p.api_key).await {
+                                                            logs.push(format!("⚠️ {} key not synced to Firebase: {}", p.name, e));
                                                         }
                                                     }
                                                 }
+                                            }
 
-                                                for p in snapshot.iter() {
-                                                    let Some(provider) = provider_by_name(&p.name) else {
-                                                        logs.push(format!("Skipped unknown provider: {}", p.name));
-                                                        continue;
-                                                    };
+                                            for p in snapshot.iter() {
+                                                let Some(provider) = provider_by_name(&p.name) else {
+                                                    logs.push(format!("Skipped unknown provider: {}", p.name));
+                                                    continue;
+                                                };
 
-                                                    if !p.enabled {
-                                                        logs.push(format!("⏸️ {} disabled; skipped test", p.name));
-                                                        continue;
-                                                    }
+                                                if !p.enabled {
+                                                    logs.push(format!("⏸️ {} disabled; skipped test", p.name));
+                                                    continue;
+                                                }
 
-                                                    if provider_requires_key(&p.name) && p.api_key.is_empty() {
-                                                        logs.push(format!("⚠️ {} missing API key; skipped test", p.name));
-                                                        continue;
-                                                    }
+                                                if provider_requires_key(&p.name) && p.api_key.is_empty() {
+                                                    logs.push(format!("⚠️ {} missing API key; skipped test", p.name));
+                                                    continue;
+                                                }
 
-                                                    let req = LLMRequest {
-                                                        provider: provider.clone(),
-                                                        model: String::new(),
-                                                        prompt: "ping".to_string(),
-                                                        api_key: if provider_requires_key(&p.name) {
-                                                            Some(p.api_key.clone())
-                                                        } else {
-                                                            None
-                                                        },
-                                                        system: Some("You are a quick connectivity probe. Reply with 'ok'.".to_string()),
-                                                    };
+                                                let req = LLMRequest {
+                                                    provider: provider.clone(),
+                                                    model: String::new(),
+                                                    prompt: "ping".to_string(),
+                                                    api_key: if provider_requires_key(&p.name) {
+                                                        Some(p.api_key.clone())
+                                                    } else {
+                                                        None
+                                                    },
+                                                    system: Some("You are a quick connectivity probe. Reply with 'ok'.".to_string()),
+                                                };
 
-                                                    match llm::send_request_with_fallback(req, Some(&user), vec![]).await {
-                                                        Ok(res) => logs.push(format!("✅ {} responding via {:?}", p.name, res.provider)),
-                                                        Err(e) => logs.push(format!("❌ {} failed: {}", p.name, e)),
-                                                    }
+                                                match llm::send_request_with_fallback(req, user.as_ref(), vec![]).await {
+                                                    Ok(res) => logs.push(format!("✅ {} responding via {:?}", p.name, res.provider)),
+                                                    Err(e) => logs.push(format!("❌ {} failed: {}", p.name, e)),
                                                 }
+                                            }
 
-                                                logs_signal.set(logs);
-                                                status_signal.set("Saved & tested providers".to_string());
-                                            });
-                                        } else {
-                                            save_status.set("Sign in to save and test providers".to_string());
-                                            log::warn!("Cannot save provider keys: not authenticated");
-                                        }
+                                            logs_signal.set(logs);
+                                            status_signal.set("Saved & tested providers".to_string());
+                                        });
                                     },
                                     "Save Settings"
                                 }
@@ -847,28 +822,7 @@ pub fn SettingsPanel(mut props: SettingsPanelProps) -> Element {
                                 button {
                                     style: "background: #1f1631; color: #f7f2ff; border: 1px solid #3a2d56; cursor: pointer; padding: 10px 18px; border-radius: 10px;",
                                     onclick: move |_| {
-                                        providers.set(vec![
-                                            ProviderUIState {
-                                                name: "Ollama (Local)".to_string(),
-                                                enabled: true,
-                                                api_key: String::new(),
-                                            },
-                                            ProviderUIState {
-                                                name: "Mistral AI".to_string(),
-                                                enabled: true,
-                                                api_key: String::new(),
-                                            },
-                                            ProviderUIState {
-                                                name: "Google Gemini".to_string(),
-                                                enabled: false,
-                                                api_key: String::new(),
-                                            },
-                                            ProviderUIState {
-                                                name: "GitHub Copilot".to_string(),
-                                                enabled: false,
-                                                api_key: String::new(),
-                                            },
-                                        ]);
+                                        providers.set(default_provider_states());
                                         log::info!("Provider settings reset to defaults");
                                     },
                                     "Reset to Defaults"
@@ -937,11 +891,12 @@ pub fn SettingsPanel(mut props: SettingsPanelProps) -> Element {
                                                         Ok(list) => {
                                                             // Update UI
                                                             lm.set(list.clone());
-                                                            // Write cache for runtime selection
+                                                            // Remember for runtime selection
                                                             let names: Vec<String> = list.into_iter().map(|m| m.name).collect();
-                                                            let json = serde_json::to_string(&names).unwrap_or_else(|_| "[]".to_string());
-                                                            let _ = std::fs::write("/tmp/kael_local_models.json", json);
-                                                            log::info!("Cached local models to /tmp/kael_local_models.json");
+                                                            match crate::settings::update(|s| s.local_models = names).await {
+                                                                Ok(_) => log::info!("Saved local model list to settings"),
+                                                                Err(e) => log::warn!("Failed to save local models: {}", e),
+                                                            }
                                                         },
                                                         Err(e) => log::warn!("Failed to list local models: {}", e),
                                                     }
@@ -1015,6 +970,67 @@ pub fn SettingsPanel(mut props: SettingsPanelProps) -> Element {
                                 p { style: "color: #cbd5ff; margin: 0; font-size: 14px;", "Terminal Emulator" }
                                 p { style: "color: #f7f2ff; margin: 4px 0 0 0; font-weight: bold;", "Kitty + tmux" }
                             }
+
+                            // Persistent terminal toggle
+                            div { style: "display: flex; align-items: center; gap: 10px; margin-top: 12px;",
+                                input {
+                                    r#type: "checkbox",
+                                    checked: persistent_terminal(),
+                                    onchange: move |ev| {
+                                        let val = ev.checked();
+                                        persistent_terminal.set(val);
+                                        spawn(async move {
+                                            if let Err(e) = crate::settings::update(|s| s.persistent_terminal = val).await {
+                                                log::error!("Failed to save persistent terminal: {}", e);
+                                            }
+                                        });
+                                    }
+                                }
+                                span { style: "color: #f7f2ff; font-weight: 600;", "Keep Kael's terminal running when the app closes (applies on next start)" }
+                            }
+
+                            // Local API toggle
+                            div { style: "display: flex; align-items: center; gap: 10px; margin-top: 12px;",
+                                input {
+                                    r#type: "checkbox",
+                                    checked: api_server(),
+                                    onchange: move |ev| {
+                                        let val = ev.checked();
+                                        api_server.set(val);
+                                        spawn(async move {
+                                            if let Err(e) = crate::settings::update(|s| s.api_server = val).await {
+                                                log::error!("Failed to save API setting: {}", e);
+                                            }
+                                        });
+                                    }
+                                }
+                                span { style: "color: #f7f2ff; font-weight: 600;", "Serve the local API for editors and scripts (applies on next start)" }
+                            }
+                            if api_server() {
+                                div { style: "display: flex; align-items: center; gap: 10px; margin: 8px 0 0 28px;",
+                                    span { style: "color: #cbd5ff; font-size: 13px;", "Also on 127.0.0.1, port" }
+                                    input {
+                                        r#type: "number",
+                                        placeholder: "off",
+                                        value: "{api_port}",
+                                        style: "width: 90px; padding: 4px 8px; background: #120e1a; color: #f7f2ff; border: 1px solid #3a2d56; border-radius: 6px;",
+                                        onchange: move |ev| {
+                                            let raw = ev.value();
+                                            api_port.set(raw.clone());
+                                            let Ok(port) = (if raw.trim().is_empty() { Ok(0) } else { raw.trim().parse::<u16>() }) else {
+                                                log::error!("Invalid API port: {}", raw);
+                                                return;
+                                            };
+                                            spawn(async move {
+                                                if let Err(e) = crate::settings::update(|s| s.api_port = port).await {
+                                                    log::error!("Failed to save API port: {}", e);
+                                                }
+                                            });
+                                        }
+                                    }
+                                    span { style: "color: #a99ec3; font-size: 12px;", "needs the token in ~/.config/kael-os/api-token" }
+                                }
+                            }
                         }
                     }
                 }
@@ -1024,6 +1040,160 @@ pub fn SettingsPanel(mut props: SettingsPanelProps) -> Element {
                     div {
                         h1 { style: "color: #ffcc00; letter-spacing: 0.02em; margin-bottom: 16px;", "Security & Signing" }
 
+                        // Secrets vault
+                        div {
+                            style: "border: 1px solid #3a2a50; border-radius: 12px; padding: 16px; background: linear-gradient(160deg, #1c162b 0%, #120e1a 60%, #0f0b1f 100%); box-shadow: 0 12px 28px #00000055; margin-bottom: 16px;",
+
+                            h2 { style: "color: #e040fb; margin-bottom: 12px;", "🗝️ Secrets Vault" }
+
+                            p { style: "color: #a99ec3; font-size: 14px; margin-bottom: 12px;",
+                                "Provider API keys live in an encrypted vault on this machine; API key and GPG backups in Firebase are sealed the same way. One passphrase unlocks both for this session. The first unlock sets it; use the same one on every machine."
+                            }
+
+                            div { style: "display: flex; gap: 8px; align-items: center;",
+                                input {
+                                    r#type: "password",
+                                    placeholder: "Passphrase",
+                                    value: "{secrets_passphrase}",
+                                    style: "flex: 1; padding: 8px 10px; border-radius: 6px; border: 1px solid #3a2a50; background: #120e1a; color: #f7f2ff;",
+                                    oninput: move |evt| secrets_passphrase.set(evt.value()),
+                                }
+                                button {
+                                    style: "background: linear-gradient(135deg, #1f1631 0%, #181024 100%); color: #ffcc00; border: 1px solid #ffcc00; cursor: pointer; padding: 8px 16px; border-radius: 6px; font-size: 12px;",
+                                    onclick: move |_| {
+                                        let user = auth_signal_clone().get_user();
+                                        let passphrase = secrets_passphrase();
+                                        secrets_status.set("🔓 Unlocking...".to_string());
+                                        spawn(async move {
+                                            let mut msg = match crate::vault::unlock(&passphrase) {
+                                                Ok(true) => "✅ Vault created - passphrase set".to_string(),
+                                                Ok(false) => "✅ Vault unlocked".to_string(),
+                                                Err(e) => {
+                                                    secrets_status.set(format!("❌ {}", e));
+                                                    return;
+                                                }
+                                            };
+                                            match crate::vault::import_legacy().await {
+                                                Ok(0) => {}
+                                                Ok(n) => msg.push_str(&format!("\n   Moved {} plaintext API keys into the vault", n)),
+                                                Err(e) => msg.push_str(&format!("\n   ⚠️ Importing old API keys failed: {}", e)),
+                                            }
+
+                                            if let Some(user) = user {
+                                                match crate::auth::unlock_secrets(&user, &passphrase).await {
+                                                    Ok(_) => msg.push_str("\n   ✅ Cloud secrets unlocked"),
+                                                    Err(e) => msg.push_str(&format!("\n   ⚠️ Cloud secrets: {}", e)),
+                                                }
+                                                // Re-encrypt anything still in the old XOR format
+                                                let api_keys = crate::firebase::migrate_legacy_api_keys(&user).await;
+                                                let backups = crate::services::gpg_backup::migrate_legacy_backups(&user).await;
+                                                for (what, result) in [("API keys", api_keys), ("GPG backups", backups)] {
+                                                    match result {
+                                                        Ok(report) => {
+                                                            if report.migrated > 0 {
+                                                                msg.push_str(&format!("\n   Re-encrypted {} {}", report.migrated, what));
+                                                            }
+                                                            if !report.unreadable.is_empty() {
+                                                                msg.push_str(&format!(
+                                                                    "\n   ⚠️ Could not read old {} (login changed since): {} - enter them again",
+                                                                    what,
+                                                                    report.unreadable.join(", ")
+                                                                ));
+                                                            }
+                                                        }
+                                                        Err(e) => msg.push_str(&format!("\n   ⚠️ {} migration failed: {}", what, e)),
+                                                    }
+                                                }
+                                                match crate::vault::pull_from_firestore(&user).await {
+                                                    Ok(0) => {}
+                                                    Ok(n) => msg.push_str(&format!("\n   Pulled {} API keys from Firebase", n)),
+                                                    Err(e) => msg.push_str(&format!("\n   ⚠️ Firebase pull failed: {}", e)),
+                                                }
+                                            }
+                                            secrets_passphrase.set(String::new());
+                                            load_vault_keys(providers);
+                                            secrets_status.set(msg);
+                                        });
+                                    },
+                                    "🔓 Unlock"
+                                }
+                                button {
+                                    style: "background: linear-gradient(135deg, #1f1631 0%, #181024 100%); color: #a99ec3; border: 1px solid #3a2d56; cursor: pointer; padding: 8px 16px; border-radius: 6px; font-size: 12px;",
+                                    onclick: move |_| {
+                                        crate::vault::lock();
+                                        crate::auth::lock_secrets();
+                                        secrets_status.set("🔒 Locked".to_string());
+                                    },
+                                    "🔒 Lock"
+                                }
+                            }
+
+                            div { style: "display: flex; align-items: center; gap: 10px; margin-top: 12px;",
+                                input {
+                                    r#type: "checkbox",
+                                    checked: sync_api_keys(),
+                                    onchange: move |ev| {
+                                        let val = ev.checked();
+                                        sync_api_keys.set(val);
+                                        spawn(async move {
+                                            if let Err(e) = crate::settings::update(|s| s.sync_api_keys = val).await {
+                                                log::error!("Failed to save key sync setting: {}", e);
+                                            }
+                                        });
+                                    }
+                                }
+                                span { style: "color: #f7f2ff; font-weight: 600;", "Sync API keys with Firebase (encrypted)" }
+                            }
+
+                            div { style: "display: flex; align-items: center; gap: 10px; margin-top: 12px;",
+                                input {
+                                    r#type: "checkbox",
+                                    checked: sync_config().enabled,
+                                    onchange: move |ev| {
+                                        let val = ev.checked();
+                                        sync_config.with_mut(|c| c.enabled = val);
+                                        spawn(async move {
+                                            match crate::settings::update(|s| s.sync.enabled = val).await {
+                                                Ok(_) if val => crate::sync::start_sync_service(),
+                                                Ok(_) => {}
+                                                Err(e) => log::error!("Failed to save sync setting: {}", e),
+                                            }
+                                        });
+                                    }
+                                }
+                                span { style: "color: #f7f2ff; font-weight: 600;", "Sync with Firebase" }
+                            }
+
+                            if sync_config().enabled {
+                                div { style: "display: flex; flex-wrap: wrap; gap: 16px; margin: 8px 0 0 28px; color: #a99ec3; font-size: 13px;",
+                                    for &(name, get, set) in SYNC_TOGGLES {
+                                        label { style: "display: flex; align-items: center; gap: 6px;",
+                                            input {
+                                                r#type: "checkbox",
+                                                checked: get(&sync_config()),
+                                                onchange: move |ev| {
+                                                    let val = ev.checked();
+                                                    sync_config.with_mut(|c| set(c, val));
+                                                    spawn(async move {
+                                                        if let Err(e) = crate::settings::update(|s| set(&mut s.sync, val)).await {
+                                                            log::error!("Failed to save sync setting: {}", e);
+                                                        }
+                                                    });
+                                                }
+                                            }
+                                            "{name}"
+                                        }
+                                    }
+                                }
+                            }
+
+                            if !secrets_status().is_empty() {
+                                div { style: "margin-top: 12px; padding: 12px; background: rgba(58, 42, 80, 0.3); border-radius: 8px; border-left: 3px solid #ffcc00; color: #f7f2ff; font-size: 13px; white-space: pre-wrap; font-family: ui-monospace, monospace;",
+                                    "{secrets_status()}"
+                                }
+                            }
+                        }
+
                         // GPG Key Management
                         div {
                             style: "border: 1px solid #3a2a50; border-radius: 12px; padding: 16px; background: linear-gradient(160deg, #1c162b 0%, #120e1a 60%, #0f0b1f 100%); box-shadow: 0 12px 28px #00000055; margin-bottom: 16px;",
@@ -1105,7 +1275,7 @@ pub fn SettingsPanel(mut props: SettingsPanelProps) -> Element {
                                                         log::info!("📤 Backing up GPG key {} to Firebase", key_id);
                                                         match crate::services::gpg_backup::backup_gpg_key(&user, key_id).await {
                                                             Ok(_) => {
-                                                                status.set(format!("✅ GPG key backed up successfully!\n   Key ID: {}\n   Encrypted with your Firebase UID", key_id));
+                                                                status.set(format!("✅ GPG key backed up successfully!\n   Key ID: {}\n   Sealed with your secrets passphrase", key_id));
                                                             }
                                                             Err(e) => {
                                                                 log::error!("❌ GPG backup error: {}", e);
@@ -1163,7 +1333,7 @@ pub fn SettingsPanel(mut props: SettingsPanelProps) -> Element {
                             }
 
                             p { style: "color: #cbd5ff; font-size: 12px; background: rgba(58, 42, 80, 0.3); padding: 10px; border-radius: 6px; border-left: 2px solid #e040fb;",
-                                "🔐 Keys are encrypted with AES-256-GCM before saving to Firebase. Only accessible with your credentials."
+                                "🔐 Keys are sealed with AES-256-GCM under your secrets passphrase before saving to Firebase."
                             }
 
                             p { style: "color: #ffcc00; font-size: 11px; margin-top: 8px;",
@@ -2,120 +2,620 @@
 use dioxus::events::Code;
 use dioxus::prelude::*;
 
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::rc::Rc;
+
 use crate::components::icons::{PanelIcon, SparkIcon};
-use crate::terminal::PtyTerminal;
+use crate::services::recordings::{self, RecordingFile};
+use crate::settings;
+use crate::terminal::{sessions, PtyTerminal};
+use kael_terminal::screen::{row_runs, row_to_string, Attrs, Cell, Color, Row};
+use kael_terminal::{
+    Cast, CastEvent, CommandBlock, ExitInfo, LineId, Point, Screen, Search, SearchMatch, Selection, SessionEvent,
+    SessionId, ShellConfig,
+};
 
 #[derive(Props, Clone, PartialEq)]
 pub struct TerminalProps {
-    pub term_out: Signal<String>,
+    /// Emulated screen of Kael's own terminal
+    pub screen: Signal<Screen>,
     pub pty: Signal<PtyTerminal>,
+    /// Finished commands in Kael's terminal, oldest first
+    #[props(default = use_signal(Vec::new))]
+    pub blocks: Signal<Vec<CommandBlock>>,
+    /// Set to ask the chat to explain a failed command
+    #[props(default = use_signal(|| None))]
+    pub explain_block: Signal<Option<CommandBlock>>,
+    /// Set to attach a recording to the chat
+    #[props(default = use_signal(|| None))]
+    pub attach_recording: Signal<Option<PathBuf>>,
+    /// Set to attach terminal text to the next chat message
+    #[props(default = use_signal(|| None))]
+    pub terminal_context: Signal<Option<String>>,
 }
 
-/// Strip ANSI escape sequences from text
-fn strip_ansi(text: &str) -> String {
-    let mut out = String::with_capacity(text.len());
-    let mut in_escape = false;
-    for ch in text.chars() {
-        if !in_escape {
-            if ch == '\x1b' {
-                in_escape = true;
-            } else {
-                out.push(ch);
+/// Scrollback lines rendered above the live screen
+const RENDER_SCROLLBACK: usize = 500;
+
+/// Longest pause kept when replaying a recording, in seconds
+const REPLAY_IDLE_LIMIT: f64 = 2.0;
+
+/// Lines around a search match shown above it, and sent with it when
+/// nothing is selected
+const SEARCH_CONTEXT_LINES: u64 = 3;
+
+/// Reports the text selected in the output as `[line, offset, line, offset]`
+/// (anchor, then focus), or null. Offsets count UTF-16 units from the start
+/// of the line's `div`.
+const SELECTION_JS: &str = r#"
+const output = document.getElementById('kael-term-output');
+const point = (node, offset) => {
+    const el = node && (node.nodeType === 1 ? node : node.parentElement);
+    const line = el && el.closest('[data-line]');
+    if (!line || !output || !output.contains(line)) return null;
+    const range = document.createRange();
+    range.setStart(line, 0);
+    range.setEnd(node, offset);
+    return [Number(line.dataset.line), range.toString().length];
+};
+const sel = window.getSelection();
+let result = null;
+if (sel && !sel.isCollapsed) {
+    const a = point(sel.anchorNode, sel.anchorOffset);
+    const b = point(sel.focusNode, sel.focusOffset);
+    if (a && b) result = a.concat(b);
+}
+dioxus.send(result);
+"#;
+
+/// Which screen a search ran on: (replay generation, tab)
+type SearchKey = (Option<u64>, Option<SessionId>);
+
+/// A recording being replayed in place of the live terminal
+struct Replay {
+    name: String,
+    screen: Screen,
+    /// Bumped for every replay so a stale player stops feeding
+    generation: u64,
+}
+
+/// Load a recording and play it into `replay` in real time
+fn start_replay(path: PathBuf, mut replay: Signal<Option<Replay>>) {
+    let cast = match Cast::load(&path) {
+        Ok(cast) => cast,
+        Err(e) => {
+            log::error!("Failed to load recording {}: {}", path.display(), e);
+            return;
+        }
+    };
+    let name = path
+        .file_stem()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let generation = replay.read().as_ref().map_or(0, |r| r.generation) + 1;
+    replay.set(Some(Replay {
+        name,
+        screen: Screen::new(cast.header.height, cast.header.width),
+        generation,
+    }));
+
+    let rx = cast.play(1.0, Some(REPLAY_IDLE_LIMIT));
+    spawn(async move {
+        while let Ok(event) = rx.recv().await {
+            let mut replay = replay.write();
+            // Closed, or replaced by another replay
+            let Some(current) = replay.as_mut().filter(|r| r.generation == generation) else { break };
+            match event {
+                CastEvent::Output(data) => current.screen.process(data.as_bytes()),
+                CastEvent::Resize { cols, rows } => current.screen.resize(rows, cols),
+                CastEvent::Input(_) => {}
             }
-        } else {
-            // Inside an escape: skip until we hit a final byte in the range '@'..='~'
-            if ('@'..='~').contains(&ch) {
-                in_escape = false;
+        }
+    });
+}
+
+/// Run `f` on whichever screen is showing: a replay, a tab or Kael's terminal
+fn with_shown_screen<R>(
+    replay: Signal<Option<Replay>>,
+    screens: Signal<BTreeMap<SessionId, Screen>>,
+    active: Option<SessionId>,
+    kael: Signal<Screen>,
+    f: impl FnOnce(&Screen, SearchKey) -> R,
+) -> Option<R> {
+    if let Some(replay) = replay.read().as_ref() {
+        return Some(f(&replay.screen, (Some(replay.generation), None)));
+    }
+    match active {
+        Some(id) => screens.read().get(&id).map(|screen| f(screen, (None, Some(id)))),
+        None => Some(f(&kael.read(), (None, None))),
+    }
+}
+
+/// Cell column of the `offset`th UTF-16 unit of a rendered line
+fn column_at(row: Option<&Row>, offset: u64) -> usize {
+    let Some(row) = row else { return 0 };
+    let mut units = 0;
+    for (col, cell) in row.iter().enumerate().filter(|(_, c)| c.width > 0) {
+        if units >= offset {
+            return col;
+        }
+        units += cell.c.len_utf16() as u64;
+    }
+    usize::MAX
+}
+
+/// An extra shell opened from the tab bar (the Kael terminal is not a tab entry)
+#[derive(Clone, PartialEq)]
+struct TerminalTab {
+    id: SessionId,
+    name: String,
+    exit: Option<ExitInfo>,
+}
+
+/// Open a new tab running the user's shell in their home directory
+async fn open_tab(
+    mut tabs: Signal<Vec<TerminalTab>>,
+    mut screens: Signal<BTreeMap<SessionId, Screen>>,
+    mut active: Signal<Option<SessionId>>,
+) {
+    let mut config = ShellConfig::default();
+    if let Ok(home) = std::env::var("HOME") {
+        config = config.with_cwd(home);
+    }
+    let name = format!("{} {}", config.display_name(), tabs.read().len() + 1);
+    let config = config.with_name(name.clone()).with_integration().with_sudo_prompt();
+    let (rows, cols) = (config.size.rows, config.size.cols);
+
+    let id = match sessions().spawn(config).await {
+        Ok(id) => id,
+        Err(e) => {
+            log::error!("Failed to open terminal tab: {}", e);
+            return;
+        }
+    };
+    let scrollback = settings::current().terminal_scrollback;
+    screens.write().insert(id, Screen::with_scrollback(rows, cols, scrollback));
+    tabs.write().push(TerminalTab { id, name, exit: None });
+    active.set(Some(id));
+
+    if let Ok(rx) = sessions().output(id).await {
+        while let Ok(chunk) = rx.recv().await {
+            let responses = {
+                let mut screens = screens.write();
+                let Some(screen) = screens.get_mut(&id) else { break }; // tab was closed
+                screen.process(&chunk);
+                screen.take_responses()
+            };
+            if !responses.is_empty() {
+                let _ = sessions().write(id, &responses).await;
             }
         }
     }
-    out
 }
 
-fn format_terminal_line(line: &str) -> String {
-    // Strip ANSI codes first
-    let line = strip_ansi(line);
-    let line = line.trim();
-    if line.is_empty() {
-        return String::new();
+fn tab_style(selected: bool) -> &'static str {
+    if selected {
+        "display: flex; align-items: center; gap: 6px; padding: 4px 10px; border-radius: 8px 8px 0 0; border: 1px solid #7aebbe; border-bottom: none; background: #181024; color: #7aebbe; font-size: 12px; cursor: pointer;"
+    } else {
+        "display: flex; align-items: center; gap: 6px; padding: 4px 10px; border-radius: 8px 8px 0 0; border: 1px solid #3a2d56; border-bottom: none; background: #120b1f; color: #a99ec3; font-size: 12px; cursor: pointer;"
     }
+}
 
-    // HTML escape the line content
-    let escaped = line
-        .replace('&', "&amp;")
+/// xterm palette entry as CSS
+fn palette(index: u8) -> String {
+    const BASE: [&str; 16] = [
+        "#120e1a", "#ff6b9d", "#7aebbe", "#ffcc00", "#6b8cff", "#e040fb", "#5af0c8", "#cbd5ff",
+        "#3a2d56", "#ff8fb4", "#9af5d2", "#ffe066", "#8fa8ff", "#ea80fc", "#8af7dc", "#f7f2ff",
+    ];
+    match index {
+        0..=15 => BASE[index as usize].to_string(),
+        16..=231 => {
+            let i = index - 16;
+            let level = |v: u8| if v == 0 { 0 } else { 55 + v * 40 };
+            format!("rgb({},{},{})", level(i / 36), level((i / 6) % 6), level(i % 6))
+        }
+        232..=255 => {
+            let v = 8 + (index - 232) * 10;
+            format!("rgb({},{},{})", v, v, v)
+        }
+    }
+}
+
+fn css_color(color: Color) -> Option<String> {
+    match color {
+        Color::Default => None,
+        Color::Indexed(i) => Some(palette(i)),
+        Color::Rgb(r, g, b) => Some(format!("rgb({},{},{})", r, g, b)),
+    }
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
         .replace('<', "&lt;")
         .replace('>', "&gt;")
-        .replace('"', "&quot;");
+        .replace('"', "&quot;")
+}
 
-    // Detect message type and add color
-    if line.starts_with("✅")
-        || line.contains("success")
-        || line.contains("Success")
-        || line.contains("SUCCESS")
-    {
-        format!("<span style='color: #7aebbe;'>{}</span>", escaped)
-    } else if line.starts_with("❌")
-        || line.contains("error")
-        || line.contains("Error")
-        || line.contains("ERROR")
-        || line.contains("failed")
-        || line.contains("Failed")
-    {
-        format!("<span style='color: #ff6b9d;'>{}</span>", escaped)
-    } else if line.starts_with("⚠️")
-        || line.starts_with("💡")
-        || line.contains("warning")
-        || line.contains("Warning")
-        || line.contains("WARN")
-    {
-        format!("<span style='color: #ffcc00;'>{}</span>", escaped)
-    } else if line.starts_with("📋")
-        || line.starts_with("☁️")
-        || line.starts_with("💾")
-        || line.starts_with("🔍")
-    {
-        format!("<span style='color: #e040fb;'>{}</span>", escaped)
-    } else if line.starts_with("$") || line.starts_with(">") {
-        format!("<span style='color: #5af0c8;'>{}</span>", escaped)
-    } else if line.starts_with("   ") || line.starts_with("  ") {
-        // Indented continuation lines
-        format!(
-            "<span style='color: #a99ec3; padding-left: 1em;'>{}</span>",
-            escaped
-        )
+fn render_run(out: &mut String, text: &str, attrs: &Attrs, cursor: bool) {
+    let (mut fg, mut bg) = (css_color(attrs.fg), css_color(attrs.bg));
+    if attrs.inverse ^ cursor {
+        let swapped_fg = bg.take().unwrap_or_else(|| "#0b0816".to_string());
+        let swapped_bg = fg.take().unwrap_or_else(|| "#f7f2ff".to_string());
+        fg = Some(swapped_fg);
+        bg = Some(swapped_bg);
+    }
+
+    let mut style = String::new();
+    if let Some(fg) = fg {
+        style.push_str(&format!("color:{};", fg));
+    }
+    if let Some(bg) = bg {
+        style.push_str(&format!("background:{};", bg));
+    }
+    if attrs.bold {
+        style.push_str("font-weight:700;");
+    }
+    if attrs.dim {
+        style.push_str("opacity:0.6;");
+    }
+    if attrs.italic {
+        style.push_str("font-style:italic;");
+    }
+    match (attrs.underline, attrs.strikethrough) {
+        (true, true) => style.push_str("text-decoration:underline line-through;"),
+        (true, false) => style.push_str("text-decoration:underline;"),
+        (false, true) => style.push_str("text-decoration:line-through;"),
+        (false, false) => {}
+    }
+
+    if style.is_empty() {
+        out.push_str(&html_escape(text));
+    } else {
+        out.push_str(&format!("<span style=\"{}\">{}</span>", style, html_escape(text)));
+    }
+}
+
+/// Row with search matches painted in: yellow, or pink for the current one
+fn highlight<'a>(row: &'a [Cell], line: LineId, matches: &[SearchMatch], current: Option<SearchMatch>) -> Cow<'a, [Cell]> {
+    let first = matches.partition_point(|m| m.line < line);
+    let on_line = matches[first..].iter().take_while(|m| m.line == line);
+    let mut cells = Cow::Borrowed(row);
+    for m in on_line {
+        let bg = if Some(*m) == current { Color::Indexed(5) } else { Color::Indexed(3) };
+        let end = m.end.min(row.len());
+        for cell in &mut cells.to_mut()[m.start.min(end)..end] {
+            cell.attrs.fg = Color::Indexed(0);
+            cell.attrs.bg = bg;
+            cell.attrs.inverse = false;
+        }
+    }
+    cells
+}
+
+fn line_open(out: &mut String, line: LineId, current: Option<SearchMatch>) {
+    if current.is_some_and(|m| m.line == line) {
+        out.push_str(&format!("<div data-line=\"{}\" id=\"kael-search-current\">", line));
+    } else {
+        out.push_str(&format!("<div data-line=\"{}\">", line));
+    }
+}
+
+/// Render scrollback and screen grid as HTML, one `div` per row. `matches`
+/// are highlighted; when `current` is further back than the usual window,
+/// the window moves to it.
+fn render_screen(screen: &Screen, matches: &[SearchMatch], current: Option<SearchMatch>) -> String {
+    let mut out = String::new();
+    let cursor = screen.cursor();
+    let scrollback = screen.scrollback();
+    let end = scrollback.end_line();
+
+    if !screen.is_alternate_screen() {
+        let mut from = end.saturating_sub(RENDER_SCROLLBACK as u64).max(scrollback.first_line());
+        let mut to = end;
+        if let Some(m) = current.filter(|m| m.line < from) {
+            from = m.line.saturating_sub(SEARCH_CONTEXT_LINES).max(scrollback.first_line());
+            to = (from + RENDER_SCROLLBACK as u64).min(end);
+        }
+        for line in from..to {
+            let Some(row) = screen.line(line) else { continue };
+            line_open(&mut out, line, current);
+            for (text, attrs) in row_runs(&highlight(row, line, matches, current)) {
+                render_run(&mut out, &text, &attrs, false);
+            }
+            out.push_str("&nbsp;</div>");
+        }
+        if to < end {
+            out.push_str(&format!(
+                "<div style=\"color:#a99ec3;font-style:italic;\">… {} more lines …</div>",
+                end - to
+            ));
+        }
+    }
+
+    // Skip trailing empty rows below the cursor so short sessions stay compact
+    let rows = screen.rows();
+    let last = rows
+        .iter()
+        .rposition(|r| !row_to_string(r).is_empty())
+        .unwrap_or(0)
+        .max(cursor.row);
+
+    for (r, row) in rows.iter().enumerate().take(last + 1) {
+        let line = end + r as u64;
+        let row = highlight(row, line, matches, current);
+        line_open(&mut out, line, current);
+        if cursor.visible && r == cursor.row {
+            // Split the row around the cursor cell
+            let (before, rest) = row.split_at(cursor.col.min(row.len()));
+            for (text, attrs) in row_runs(before) {
+                render_run(&mut out, &text, &attrs, false);
+            }
+            if let Some((cell, after)) = rest.split_first() {
+                render_run(&mut out, &cell.c.to_string(), &cell.attrs, true);
+                for (text, attrs) in row_runs(after) {
+                    render_run(&mut out, &text, &attrs, false);
+                }
+            }
+        } else {
+            for (text, attrs) in row_runs(&row) {
+                render_run(&mut out, &text, &attrs, false);
+            }
+        }
+        out.push_str("&nbsp;</div>");
+    }
+    out
+}
+
+fn search_input_style(invalid: bool) -> &'static str {
+    if invalid {
+        "flex: 1; padding: 6px 10px; background: #120b1f; border: 1px solid #ff6b9d; border-radius: 8px; color: #f7f2ff; font-family: ui-monospace, monospace; font-size: 12px; outline: none;"
+    } else {
+        "flex: 1; padding: 6px 10px; background: #120b1f; border: 1px solid #3a2d56; border-radius: 8px; color: #f7f2ff; font-family: ui-monospace, monospace; font-size: 12px; outline: none;"
+    }
+}
+
+fn rec_button_style(recording: bool) -> &'static str {
+    if recording {
+        "margin-left: auto; padding: 4px 10px; border-radius: 8px; border: 1px solid #ff6b9d; background: #ff6b9d22; color: #ff6b9d; font-size: 12px;"
+    } else {
+        "margin-left: auto; padding: 4px 10px; border-radius: 8px; border: 1px solid #3a2d56; background: transparent; color: #a99ec3; font-size: 12px;"
+    }
+}
+
+/// "exit 2 · 1.3s · /home/me" for the block list
+fn block_summary(block: &CommandBlock) -> String {
+    let mut parts = vec![match block.exit_code {
+        Some(code) => format!("exit {}", code),
+        None => "exit ?".to_string(),
+    }];
+    if let Some(duration) = block.duration {
+        parts.push(format!("{:.1}s", duration.as_secs_f32()));
+    }
+    if let Some(cwd) = &block.cwd {
+        parts.push(cwd.display().to_string());
+    }
+    parts.join(" · ")
+}
+
+fn block_badge_style(block: &CommandBlock) -> &'static str {
+    if block.failed() {
+        "color: #ff6b9d; font-size: 11px; white-space: nowrap;"
     } else {
-        format!("<span style='color: #f7f2ff;'>{}</span>", escaped)
+        "color: #7aebbe; font-size: 11px; white-space: nowrap;"
     }
 }
 
+/// Last few non-empty lines, to detect password prompts
+fn tail_text(screen: &Screen, lines: usize) -> Vec<String> {
+    screen
+        .rows()
+        .iter()
+        .map(|r| row_to_string(r))
+        .filter(|l| !l.is_empty())
+        .rev()
+        .take(lines)
+        .collect()
+}
+
 #[allow(non_snake_case)]
 pub fn TerminalPanel(props: TerminalProps) -> Element {
     let mut user_input = use_signal(String::new);
     let mut is_password_prompt = use_signal(|| false);
+    let mut tabs = use_signal(Vec::<TerminalTab>::new);
+    let mut screens = use_signal(BTreeMap::<SessionId, Screen>::new);
+    // None = Kael's terminal, Some(id) = an extra tab
+    let mut active = use_signal(|| None::<SessionId>);
+    // Running recordings, keyed like `active`
+    let mut recording = use_signal(BTreeMap::<Option<SessionId>, PathBuf>::new);
+    let mut recording_files = use_signal(|| recordings::list_recordings(&recordings::recordings_dir()));
+    let mut replay = use_signal(|| None::<Replay>);
+    let mut search_query = use_signal(String::new);
+    // Current match, counted back from the newest
+    let mut search_offset = use_signal(|| 0usize);
+    // Kept across renders so new output is scanned incrementally
+    let search_state = use_hook(|| Rc::new(RefCell::new(None::<(SearchKey, Search)>)));
 
-    // Format output with colors
-    let formatted_output = props.term_out.read();
+    // Bring the current match into view
+    use_effect(move || {
+        let _ = (search_query(), search_offset());
+        eval("document.getElementById('kael-search-current')?.scrollIntoView({ block: 'nearest' });");
+    });
+
+    // Track tab lifecycle (exit status, closed elsewhere)
+    use_effect(move || {
+        spawn(async move {
+            let mut events = sessions().subscribe();
+            while let Ok(event) = events.recv().await {
+                match event {
+                    SessionEvent::Exited { id, exit } => {
+                        if let Some(tab) = tabs.write().iter_mut().find(|t| t.id == id) {
+                            tab.exit = Some(exit);
+                        }
+                    }
+                    SessionEvent::Closed { id } => {
+                        tabs.write().retain(|t| t.id != id);
+                        screens.write().remove(&id);
+                        // The recorder was dropped with the session
+                        if recording.write().remove(&Some(id)).is_some() {
+                            recording_files.set(recordings::list_recordings(&recordings::recordings_dir()));
+                        }
+                        if active() == Some(id) {
+                            active.set(None);
+                        }
+                    }
+                    SessionEvent::Spawned { .. } => {}
+                }
+            }
+        });
+    });
+
+    // Send a line to whichever terminal is showing
+    let send_line = move |line: String| {
+        let pty = props.pty.read().clone();
+        let target = active();
+        spawn(async move {
+            let result = match target {
+                Some(id) => sessions()
+                    .write(id, format!("{}\n", line).as_bytes())
+                    .await
+                    .map_err(|e| e.to_string()),
+                None => pty.write_line(&line).await,
+            };
+            if let Err(e) = result {
+                log::error!("Failed to send input to terminal: {}", e);
+            }
+        });
+    };
+
+    // Start or stop recording whichever terminal is showing. Kael's terminal
+    // goes through the PtyTerminal, which may live in the session daemon.
+    let toggle_recording = move |_| {
+        let pty = props.pty.read().clone();
+        let target = active();
+        spawn(async move {
+            let running = recording.read().contains_key(&target);
+            if running {
+                let stopped = match target {
+                    Some(id) => sessions().stop_recording(id).await.map_err(|e| e.to_string()),
+                    None => pty.stop_recording().await,
+                };
+                if let Err(e) = stopped {
+                    log::warn!("Failed to stop recording: {}", e);
+                }
+                recording.write().remove(&target);
+            } else {
+                let name = match target {
+                    Some(id) => sessions().info(id).await.map(|i| i.name).unwrap_or_default(),
+                    None => pty.name(),
+                };
+                let path = recordings::new_recording_path(&recordings::recordings_dir(), &name);
+                let started = match target {
+                    Some(id) => sessions().start_recording(id, &path).await.map_err(|e| e.to_string()),
+                    None => pty.start_recording(&path).await,
+                };
+                if let Err(e) = started {
+                    log::error!("Failed to start recording: {}", e);
+                    return;
+                }
+                recording.write().insert(target, path);
+            }
+            recording_files.set(recordings::list_recordings(&recordings::recordings_dir()));
+        });
+    };
+    let is_recording = recording.read().contains_key(&active());
+
+    // Render whichever screen is showing, searching it as it grows
+    let query = search_query();
+    let (formatted_html, last_lines, is_empty, match_count) = {
+        let search_state = search_state.clone();
+        let render = |screen: &Screen, key: SearchKey| {
+            let mut state = search_state.borrow_mut();
+            // A new pattern or another screen starts over; otherwise only new lines are scanned
+            if !state.as_ref().is_some_and(|(k, search)| *k == key && search.pattern() == query) {
+                *state = if query.is_empty() { None } else { Search::new(&query).ok().map(|search| (key, search)) };
+            }
+            let (matches, current) = match state.as_mut() {
+                Some((_, search)) => {
+                    search.update(screen);
+                    let offset = search_offset().min(search.len().saturating_sub(1));
+                    (search.matches().copied().collect::<Vec<_>>(), search.nth_from_end(offset))
+                }
+                None => (Vec::new(), None),
+            };
+            let empty = screen.scrollback().is_empty() && screen.contents().trim().is_empty();
+            (render_screen(screen, &matches, current), tail_text(screen, 3), empty, matches.len())
+        };
+        with_shown_screen(replay, screens, active(), props.screen, render)
+            .unwrap_or_else(|| (String::new(), Vec::new(), true, 0))
+    };
+    let search_invalid = !query.is_empty() && search_state.borrow().is_none();
+    let search_status = if search_invalid {
+        "invalid pattern".to_string()
+    } else if match_count == 0 {
+        "no matches".to_string()
+    } else {
+        format!("{}/{}", match_count - search_offset().min(match_count - 1), match_count)
+    };
+
+    // Attach the selected output (or the current match with a few lines
+    // around it) to the next chat message
+    let send_selection = {
+        let search_state = search_state.clone();
+        move |_| {
+            let current = search_state
+                .borrow()
+                .as_ref()
+                .and_then(|(_, search)| search.nth_from_end(search_offset()));
+            let mut terminal_context = props.terminal_context;
+            let kael = props.screen;
+            let target = active();
+            spawn(async move {
+                let mut js = eval(SELECTION_JS);
+                let points = match js.recv().await {
+                    Ok(value) => serde_json::from_value::<Option<[u64; 4]>>(value).ok().flatten(),
+                    Err(e) => {
+                        log::warn!("Failed to read terminal selection: {:?}", e);
+                        None
+                    }
+                };
+                let text = with_shown_screen(replay, screens, target, kael, |screen, _| {
+                    let selection = match (points, current) {
+                        (Some([anchor_line, anchor_offset, head_line, head_offset]), _) => Selection {
+                            anchor: Point { line: anchor_line, col: column_at(screen.line(anchor_line), anchor_offset) },
+                            head: Point { line: head_line, col: column_at(screen.line(head_line), head_offset) },
+                        },
+                        (None, Some(m)) => Selection::lines(
+                            m.line.saturating_sub(SEARCH_CONTEXT_LINES),
+                            m.line + SEARCH_CONTEXT_LINES,
+                        ),
+                        (None, None) => return String::new(),
+                    };
+                    selection.text(screen)
+                })
+                .unwrap_or_default();
+                if !text.trim().is_empty() {
+                    terminal_context.set(Some(text));
+                }
+            });
+        }
+    };
+    let replay_name = replay.read().as_ref().map(|r| r.name.clone());
+    let replay_label = replay_name.clone().unwrap_or_default();
 
     // Detect password prompts in the last few lines
     {
-        let last_lines: Vec<&str> = formatted_output.lines().rev().take(3).collect();
-        let mut is_pwd = false;
-        for line in last_lines {
+        let is_pwd = last_lines.iter().any(|line| {
             let lower = line.to_lowercase();
-            if lower.contains("password") || lower.contains("sudo") {
-                is_pwd = true;
-                break;
-            }
+            lower.contains("password") || lower.contains("sudo")
+        });
+        if is_password_prompt() != is_pwd {
+            is_password_prompt.set(is_pwd);
         }
-        is_password_prompt.set(is_pwd);
     }
 
-    let lines: Vec<String> = formatted_output
-        .lines()
-        .map(|line| format_terminal_line(line))
-        .collect();
-    let formatted_html = lines.join("<br/>");
-
     rsx! {
         div {
             class: "flex flex-col gap-3",
@@ -124,6 +624,73 @@ pub fn TerminalPanel(props: TerminalProps) -> Element {
                 PanelIcon { class: "w-3 h-3" }
                 span { style: "color: #7aebbe; font-size: 12px; text-transform: uppercase; letter-spacing: 0.06em;", "Terminal Output" }
             }
+            // Tabs: Kael's terminal plus any extra shells
+            div { style: "display: flex; gap: 4px; align-items: flex-end; flex-wrap: wrap;",
+                div {
+                    style: tab_style(replay_name.is_none() && active().is_none()),
+                    onclick: move |_| {
+                        replay.set(None);
+                        active.set(None);
+                    },
+                    "Kael"
+                }
+                for tab in tabs() {
+                    {
+                        let id = tab.id;
+                        let status = match tab.exit {
+                            None => String::new(),
+                            Some(exit) => format!(" (exit {})", exit.code),
+                        };
+                        rsx! {
+                            div {
+                                key: "{id}",
+                                style: tab_style(replay_name.is_none() && active() == Some(id)),
+                                onclick: move |_| {
+                                    replay.set(None);
+                                    active.set(Some(id));
+                                },
+                                span { "{tab.name}{status}" }
+                                span {
+                                    style: "color: #ff6b9d; padding-left: 4px;",
+                                    onclick: move |evt| {
+                                        evt.stop_propagation();
+                                        spawn(async move {
+                                            if let Err(e) = sessions().close(id).await {
+                                                log::warn!("Failed to close terminal tab: {}", e);
+                                            }
+                                        });
+                                    },
+                                    "×"
+                                }
+                            }
+                        }
+                    }
+                }
+                button {
+                    style: "padding: 4px 10px; border-radius: 8px 8px 0 0; border: 1px dashed #3a2d56; border-bottom: none; background: transparent; color: #7aebbe; font-size: 12px;",
+                    onclick: move |_| {
+                        spawn(open_tab(tabs, screens, active));
+                    },
+                    "+"
+                }
+                if replay_name.is_some() {
+                    div {
+                        style: tab_style(true),
+                        span { "▶ {replay_label}" }
+                        span {
+                            style: "color: #ff6b9d; padding-left: 4px;",
+                            onclick: move |_| replay.set(None),
+                            "×"
+                        }
+                    }
+                }
+                button {
+                    style: rec_button_style(is_recording),
+                    title: "Record this terminal to an asciicast file",
+                    onclick: toggle_recording,
+                    if is_recording { "■ Stop recording" } else { "● Rec" }
+                }
+            }
             // Container card
             div {
                 class: "pane-scroll",
@@ -133,13 +700,119 @@ pub fn TerminalPanel(props: TerminalProps) -> Element {
                     SparkIcon { class: "w-3 h-3" }
                     span { "Shell Status" }
                 }
+                // Scrollback search
+                div { style: "margin: 12px 0 0; display: flex; gap: 8px; align-items: center;",
+                    input {
+                        value: "{search_query}",
+                        placeholder: "Search scrollback (regex)...",
+                        style: search_input_style(search_invalid),
+                        oninput: move |evt| {
+                            search_query.set(evt.value());
+                            search_offset.set(0);
+                        },
+                        onkeydown: move |evt| {
+                            if evt.code() == Code::Enter && search_offset() + 1 < match_count {
+                                // Enter steps back to older matches
+                                search_offset.set(search_offset() + 1);
+                            } else if evt.code() == Code::Escape {
+                                search_query.set(String::new());
+                                search_offset.set(0);
+                            }
+                        }
+                    }
+                    if !query.is_empty() {
+                        span { style: "color: #a99ec3; font-size: 11px; white-space: nowrap;", "{search_status}" }
+                        button {
+                            style: "padding: 2px 8px; border-radius: 6px; border: 1px solid #3a2d56; background: transparent; color: #a99ec3; font-size: 12px; cursor: pointer;",
+                            title: "Older match",
+                            onclick: move |_| {
+                                if search_offset() + 1 < match_count {
+                                    search_offset.set(search_offset() + 1);
+                                }
+                            },
+                            "↑"
+                        }
+                        button {
+                            style: "padding: 2px 8px; border-radius: 6px; border: 1px solid #3a2d56; background: transparent; color: #a99ec3; font-size: 12px; cursor: pointer;",
+                            title: "Newer match",
+                            onclick: move |_| search_offset.set(search_offset().saturating_sub(1)),
+                            "↓"
+                        }
+                    }
+                    button {
+                        style: "padding: 4px 10px; border-radius: 8px; border: 1px solid #e040fb; background: transparent; color: #e040fb; font-size: 12px; cursor: pointer; white-space: nowrap;",
+                        title: "Attach the selected output, or the current match, to your next chat message",
+                        onclick: send_selection,
+                        "Send selection to Kael"
+                    }
+                }
                 // Output area with better formatting
                 div {
-                    style: "margin: 12px 0 0; font-family: ui-monospace, SFMono-Regular, Menlo, Consolas, 'Liberation Mono', monospace; background: linear-gradient(180deg, #181024 0%, #120b1f 100%); padding: 12px; max-height: 260px; overflow-y: auto; border: 1px solid #3a2d56; border-radius: 10px; font-size: 13px; line-height: 1.6;",
+                    id: "kael-term-output",
+                    style: "margin: 12px 0 0; font-family: ui-monospace, SFMono-Regular, Menlo, Consolas, 'Liberation Mono', monospace; background: linear-gradient(180deg, #181024 0%, #120b1f 100%); padding: 12px; max-height: 260px; overflow-y: auto; border: 1px solid #3a2d56; border-radius: 10px; font-size: 13px; line-height: 1.6; white-space: pre; color: #f7f2ff;",
                     dangerous_inner_html: "{formatted_html}"
                 }
+                // Recent commands from shell integration, newest first
+                if active().is_none() && !props.blocks.read().is_empty() {
+                    div { style: "margin: 12px 0 0; display: flex; flex-direction: column; gap: 4px;",
+                        for block in props.blocks.read().iter().rev().take(5).cloned() {
+                            {
+                                let summary = block_summary(&block);
+                                let badge_style = block_badge_style(&block);
+                                let failed = block.failed();
+                                let (id, command) = (block.id, block.command.clone());
+                                let mut explain_block = props.explain_block;
+                                rsx! {
+                                    div {
+                                        key: "{id}",
+                                        style: "display: flex; align-items: center; gap: 8px; padding: 4px 8px; border: 1px solid #3a2d56; border-radius: 8px; background: #120b1f; font-size: 12px;",
+                                        span { style: "flex: 1; color: #f7f2ff; font-family: ui-monospace, monospace; overflow: hidden; text-overflow: ellipsis; white-space: nowrap;", "{command}" }
+                                        span { style: badge_style, "{summary}" }
+                                        if failed {
+                                            button {
+                                                style: "padding: 2px 8px; border-radius: 6px; border: 1px solid #ff6b9d; background: transparent; color: #ff6b9d; font-size: 11px; cursor: pointer;",
+                                                onclick: move |_| explain_block.set(Some(block.clone())),
+                                                "Explain failure"
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+                // Saved recordings, newest first
+                if !recording_files.read().is_empty() {
+                    div { style: "margin: 12px 0 0; display: flex; flex-direction: column; gap: 4px;",
+                        span { style: "color: #a99ec3; font-size: 11px; text-transform: uppercase; letter-spacing: 0.06em;", "Recordings" }
+                        for file in recording_files.read().iter().take(5).cloned() {
+                            {
+                                let RecordingFile { path, name, .. } = file;
+                                let replay_path = path.clone();
+                                let mut attach_recording = props.attach_recording;
+                                rsx! {
+                                    div {
+                                        key: "{name}",
+                                        style: "display: flex; align-items: center; gap: 8px; padding: 4px 8px; border: 1px solid #3a2d56; border-radius: 8px; background: #120b1f; font-size: 12px;",
+                                        span { style: "flex: 1; color: #f7f2ff; overflow: hidden; text-overflow: ellipsis; white-space: nowrap;", "{name}" }
+                                        button {
+                                            style: "padding: 2px 8px; border-radius: 6px; border: 1px solid #7aebbe; background: transparent; color: #7aebbe; font-size: 11px; cursor: pointer;",
+                                            onclick: move |_| start_replay(replay_path.clone(), replay),
+                                            "Replay"
+                                        }
+                                        button {
+                                            style: "padding: 2px 8px; border-radius: 6px; border: 1px solid #e040fb; background: transparent; color: #e040fb; font-size: 11px; cursor: pointer;",
+                                            onclick: move |_| attach_recording.set(Some(path.clone())),
+                                            "Attach to chat"
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
                 // Info message if no output
-                if formatted_output.is_empty() {
+                if is_empty {
                     div { style: "margin: 12px 0 0; padding: 12px; color: #a99ec3; font-size: 13px; text-align: center; font-style: italic;",
                         "Terminal output will appear here..."
                     }
@@ -157,25 +830,9 @@ pub fn TerminalPanel(props: TerminalProps) -> Element {
                         },
                         onkeydown: move |evt| {
                             if evt.code() == Code::Enter {
-                                let input_text = user_input();
-                                if !input_text.is_empty() {
-                                    let pty = props.pty.read().clone();
-                                    let text_to_send = input_text.clone();
-                                    spawn(async move {
-                                        if let Err(e) = pty.write_line(&text_to_send).await {
-                                            log::error!("Failed to send input to terminal: {}", e);
-                                        }
-                                    });
-                                    user_input.set(String::new());
-                                } else {
-                                    // Just send newline if empty (for prompts)
-                                    let pty = props.pty.read().clone();
-                                    spawn(async move {
-                                        if let Err(e) = pty.write_line("").await {
-                                            log::error!("Failed to send newline to terminal: {}", e);
-                                        }
-                                    });
-                                }
+                                // Empty input just sends a newline (for prompts)
+                                send_line(user_input());
+                                user_input.set(String::new());
                             }
                         }
                     }
@@ -184,13 +841,7 @@ pub fn TerminalPanel(props: TerminalProps) -> Element {
                         onclick: move |_| {
                             let input_text = user_input();
                             if !input_text.is_empty() {
-                                let pty = props.pty.read().clone();
-                                let text_to_send = input_text.clone();
-                                spawn(async move {
-                                    if let Err(e) = pty.write_line(&text_to_send).await {
-                                        log::error!("Failed to send input to terminal: {}", e);
-                                    }
-                                });
+                                send_line(input_text);
                                 user_input.set(String::new());
                             }
                         },
@@ -1,154 +1,45 @@
 #![allow(dead_code)]
 
-use aes_gcm::aead::consts::U12;
-use aes_gcm::Nonce;
-use aes_gcm::{
-    aead::{Aead, KeyInit},
-    Aes256Gcm, Key,
-};
-use base64::{engine::general_purpose, Engine};
-use pbkdf2::pbkdf2_hmac;
-use rand::Rng;
-use sha2::Sha256;
-use std::error::Error;
+pub mod envelope;
+pub mod stream;
 
-const B64_ENGINE: general_purpose::GeneralPurpose = general_purpose::STANDARD;
+use std::error::Error;
 
-/// Derive a 256-bit key from a passphrase using PBKDF2
-pub fn derive_key_from_passphrase(passphrase: &str, salt: &[u8; 16]) -> Key<Aes256Gcm> {
-    let mut key = [0u8; 32];
-    pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, 100_000, &mut key);
-    Key::<Aes256Gcm>::from(key)
-}
+pub use envelope::{inspect, needs_rotation, open, rotate, seal, Kdf, Params, Secret, Version};
 
-/// Encrypt data with AES-256-GCM using a passphrase
-/// Returns: (salt + nonce + ciphertext) base64 encoded
+/// Encrypt data with AES-256-GCM under a passphrase (Argon2id)
+/// Returns: a `kael:v2:` envelope
 pub fn encrypt_with_passphrase(
     plaintext: &str,
     passphrase: &str,
 ) -> Result<String, Box<dyn Error>> {
-    let mut rng = rand::thread_rng();
-
-    // Generate random 16-byte salt
-    let salt: [u8; 16] = rng.gen();
-
-    // Generate random 12-byte nonce
-    let mut nonce_bytes = [0u8; 12];
-    rng.fill(&mut nonce_bytes);
-    let nonce = Nonce::<U12>::from_slice(&nonce_bytes);
-
-    // Derive key from passphrase
-    let key = derive_key_from_passphrase(passphrase, &salt);
-    let cipher = Aes256Gcm::new(&key);
-
-    // Encrypt
-    let ciphertext = cipher
-        .encrypt(nonce, plaintext.as_bytes())
-        .map_err(|e| format!("Encryption failed: {}", e))?;
-
-    // Combine salt + nonce + ciphertext and encode
-    let mut combined = Vec::new();
-    combined.extend_from_slice(&salt);
-    combined.extend_from_slice(&nonce_bytes);
-    combined.extend_from_slice(&ciphertext);
-
-    Ok(B64_ENGINE.encode(&combined))
+    Ok(seal(
+        plaintext.as_bytes(),
+        Secret::Passphrase(passphrase),
+        &Params::default(),
+        b"",
+    )?)
 }
 
-// Decrypt data with AES-256-GCM using a passphrase
+/// Decrypt data with AES-256-GCM using a passphrase
+/// Accepts v2 envelopes and the older unversioned format
 pub fn decrypt_with_passphrase(
     encrypted_data: &str,
     passphrase: &str,
 ) -> Result<String, Box<dyn Error>> {
-    // Decode base64
-    let combined = B64_ENGINE
-        .decode(encrypted_data)
-        .map_err(|e| format!("Base64 decode failed: {}", e))?;
-
-    if combined.len() < 28 {
-        return Err("Invalid encrypted data: too short".into());
-    }
-
-    // Extract salt (first 16 bytes)
-    let salt: [u8; 16] = combined[0..16].try_into()?;
-
-    // Extract nonce (next 12 bytes)
-    let nonce = Nonce::<U12>::from_slice(&combined[16..28]);
-
-    // Extract ciphertext (rest)
-    let ciphertext = &combined[28..];
-
-    // Derive key from passphrase using same salt
-    let key = derive_key_from_passphrase(passphrase, &salt);
-    let cipher = Aes256Gcm::new(&key);
-
-    // Decrypt
-    let plaintext_bytes = cipher
-        .decrypt(nonce, ciphertext)
-        .map_err(|e| format!("Decryption failed: {}", e))?;
-
+    let plaintext_bytes = open(encrypted_data, Secret::Passphrase(passphrase), b"")?;
     Ok(String::from_utf8(plaintext_bytes)?)
 }
 
-/// Encrypt with a derived key directly (e.g., from id_token)
+/// Encrypt under a token rather than a passphrase (e.g., id_token)
+/// Same envelope as `encrypt_with_passphrase`, with a fresh salt each time
 pub fn encrypt_with_key(plaintext: &str, key: &str) -> Result<String, Box<dyn Error>> {
-    let mut rng = rand::thread_rng();
-
-    // Hash the key to get consistent 256-bit value
-    let mut key_bytes = [0u8; 32];
-    pbkdf2_hmac::<Sha256>(key.as_bytes(), b"kael-os-key", 100_000, &mut key_bytes);
-    let key = Key::<Aes256Gcm>::from(key_bytes);
-
-    // Generate random 12-byte nonce
-    let mut nonce_bytes = [0u8; 12];
-    rng.fill(&mut nonce_bytes);
-    let nonce = Nonce::<U12>::from_slice(&nonce_bytes);
-
-    let cipher = Aes256Gcm::new(&key);
-
-    // Encrypt
-    let ciphertext = cipher
-        .encrypt(nonce, plaintext.as_bytes())
-        .map_err(|e| format!("Encryption failed: {}", e))?;
-
-    // Combine nonce + ciphertext and encode
-    let mut combined = Vec::new();
-    combined.extend_from_slice(&nonce_bytes);
-    combined.extend_from_slice(&ciphertext);
-
-    Ok(B64_ENGINE.encode(&combined))
+    encrypt_with_passphrase(plaintext, key)
 }
 
-/// Decrypt with a derived key
+/// Decrypt with a token, including the older fixed-salt format
 pub fn decrypt_with_key(encrypted_data: &str, key: &str) -> Result<String, Box<dyn Error>> {
-    // Decode base64
-    let combined = B64_ENGINE
-        .decode(encrypted_data)
-        .map_err(|e| format!("Base64 decode failed: {}", e))?;
-
-    if combined.len() < 12 {
-        return Err("Invalid encrypted data: too short".into());
-    }
-
-    // Extract nonce (first 12 bytes)
-    let nonce = Nonce::<U12>::from_slice(&combined[0..12]);
-
-    // Extract ciphertext (rest)
-    let ciphertext = &combined[12..];
-
-    // Hash the key
-    let mut key_bytes = [0u8; 32];
-    pbkdf2_hmac::<Sha256>(key.as_bytes(), b"kael-os-key", 100_000, &mut key_bytes);
-    let key = Key::<Aes256Gcm>::from(key_bytes);
-
-    let cipher = Aes256Gcm::new(&key);
-
-    // Decrypt
-    let plaintext_bytes = cipher
-        .decrypt(nonce, ciphertext)
-        .map_err(|e| format!("Decryption failed: {}", e))?;
-
-    Ok(String::from_utf8(plaintext_bytes)?)
+    decrypt_with_passphrase(encrypted_data, key)
 }
 
 #[cfg(test)]
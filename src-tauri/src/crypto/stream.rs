@@ -0,0 +1,594 @@
+//! Streaming file encryption (`.kaelenc`) for things too big to seal in
+//! memory: chat databases, project archives, exports.
+//!
+//! Uses the STREAM construction (Hoang, Reyhanitabar, Rogaway and Vizár,
+//! 2015) over AES-256-GCM. The plaintext is cut into fixed-size chunks and
+//! chunk `i` is sealed with the nonce `prefix ‖ i ‖ last`, so chunks cannot
+//! be reordered, dropped, or cut off at a chunk boundary without decryption
+//! failing.
+//!
+//! ```text
+//! "KAELENC" 0x01 | header length (u32 BE) | header JSON | chunk 0 | chunk 1 | ...
+//! ```
+//!
+//! The header names the KDF, salt and key id like a v2 envelope, plus the
+//! chunk size and nonce prefix. Everything up to the first chunk is the
+//! associated data of every chunk.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use aes_gcm::aead::consts::U12;
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use super::envelope::{self, Header, Params, Secret, B64URL, SALT_LEN, TAG_LEN};
+
+/// File extension of encrypted files
+pub const EXTENSION: &str = "kaelenc";
+/// Plaintext bytes per chunk in new files
+pub const CHUNK_SIZE: u32 = 64 * 1024;
+const MAGIC: &[u8; 7] = b"KAELENC";
+const VERSION: u8 = 1;
+const PREFIX_LEN: usize = 7;
+/// Ceilings for values read from a file header
+const MAX_CHUNK_SIZE: u32 = 16 * 1024 * 1024;
+const MAX_HEADER_LEN: u32 = 64 * 1024;
+
+#[derive(Serialize, Deserialize)]
+struct FileHeader {
+    #[serde(flatten)]
+    key: Header,
+    /// Plaintext bytes per chunk
+    chunk: u32,
+    /// Base64url nonce prefix
+    nonce: String,
+}
+
+/// How far an encryption or decryption has got
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Progress {
+    /// Plaintext bytes processed so far
+    pub done: u64,
+    /// Plaintext size, when known
+    pub total: Option<u64>,
+}
+
+impl Progress {
+    pub fn percent(&self) -> Option<u8> {
+        self.total.map(|total| {
+            (self.done * 100)
+                .checked_div(total)
+                .map_or(100, |percent| percent.min(100) as u8)
+        })
+    }
+}
+
+/// Seals or opens consecutive chunks of one stream
+struct Chunker {
+    cipher: Aes256Gcm,
+    prefix: [u8; PREFIX_LEN],
+    counter: u32,
+    finished: bool,
+    aad: Vec<u8>,
+}
+
+impl Chunker {
+    fn new(key: &[u8; 32], prefix: [u8; PREFIX_LEN], aad: Vec<u8>) -> Self {
+        Self {
+            cipher: envelope::cipher(key),
+            prefix,
+            counter: 0,
+            finished: false,
+            aad,
+        }
+    }
+
+    fn next_nonce(&mut self, last: bool) -> Result<[u8; 12], String> {
+        if self.finished {
+            return Err("Stream already finished".to_string());
+        }
+        let mut nonce = [0u8; 12];
+        nonce[..PREFIX_LEN].copy_from_slice(&self.prefix);
+        nonce[PREFIX_LEN..11].copy_from_slice(&self.counter.to_be_bytes());
+        nonce[11] = last as u8;
+        self.counter = self.counter.checked_add(1).ok_or("Stream too long")?;
+        self.finished = last;
+        Ok(nonce)
+    }
+
+    fn seal(&mut self, chunk: &[u8], last: bool) -> Result<Vec<u8>, String> {
+        let nonce = self.next_nonce(last)?;
+        let payload = Payload {
+            msg: chunk,
+            aad: &self.aad,
+        };
+        self.cipher
+            .encrypt(Nonce::<U12>::from_slice(&nonce), payload)
+            .map_err(|_| "Encryption failed".to_string())
+    }
+
+    fn open(&mut self, chunk: &[u8], last: bool) -> Result<Zeroizing<Vec<u8>>, String> {
+        let index = self.counter;
+        let nonce = self.next_nonce(last)?;
+        let payload = Payload {
+            msg: chunk,
+            aad: &self.aad,
+        };
+        self.cipher
+            .decrypt(Nonce::<U12>::from_slice(&nonce), payload)
+            .map(Zeroizing::new)
+            .map_err(|_| match index {
+                0 => "Decryption failed: wrong key or tampered data".to_string(),
+                _ => format!(
+                    "Decryption failed at chunk {}: file truncated, reordered or tampered with",
+                    index
+                ),
+            })
+    }
+}
+
+/// Read until `buf` is full or the input ends
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> Result<usize, String> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(format!("Read failed: {}", e)),
+        }
+    }
+    Ok(filled)
+}
+
+/// Call `f` on each block of `size` bytes, telling it which is the last.
+/// Reads one block ahead, so the last block is known before it is handled.
+fn for_each_block(
+    reader: &mut impl Read,
+    size: usize,
+    mut f: impl FnMut(&[u8], bool) -> Result<(), String>,
+) -> Result<(), String> {
+    let mut current = Zeroizing::new(vec![0u8; size]);
+    let mut next = Zeroizing::new(vec![0u8; size]);
+    let mut len = read_full(reader, &mut current)?;
+    loop {
+        let next_len = if len == size {
+            read_full(reader, &mut next)?
+        } else {
+            0
+        };
+        let last = next_len == 0;
+        f(&current[..len], last)?;
+        if last {
+            return Ok(());
+        }
+        std::mem::swap(&mut current, &mut next);
+        len = next_len;
+    }
+}
+
+/// Encrypt everything `reader` yields into `writer`. `progress` gets the
+/// number of plaintext bytes done after each chunk. Returns that number.
+pub fn encrypt_stream(
+    mut reader: impl Read,
+    mut writer: impl Write,
+    secret: Secret,
+    params: &Params,
+    mut progress: impl FnMut(u64),
+) -> Result<u64, String> {
+    let salt: [u8; SALT_LEN] = envelope::random();
+    let prefix: [u8; PREFIX_LEN] = envelope::random();
+    let header = FileHeader {
+        key: envelope::new_header(secret, params, &salt),
+        chunk: CHUNK_SIZE,
+        nonce: B64URL.encode(prefix),
+    };
+    let key = envelope::derive(secret, &header.key.kdf, &salt)?;
+
+    let json = serde_json::to_vec(&header).map_err(|e| e.to_string())?;
+    let mut preamble = MAGIC.to_vec();
+    preamble.push(VERSION);
+    preamble.extend_from_slice(&(json.len() as u32).to_be_bytes());
+    preamble.extend_from_slice(&json);
+    writer
+        .write_all(&preamble)
+        .map_err(|e| format!("Write failed: {}", e))?;
+
+    let mut chunker = Chunker::new(&key, prefix, preamble);
+    let mut done = 0u64;
+    for_each_block(&mut reader, CHUNK_SIZE as usize, |chunk, last| {
+        let sealed = chunker.seal(chunk, last)?;
+        writer
+            .write_all(&sealed)
+            .map_err(|e| format!("Write failed: {}", e))?;
+        done += chunk.len() as u64;
+        progress(done);
+        Ok(())
+    })?;
+    writer.flush().map_err(|e| format!("Write failed: {}", e))?;
+    Ok(done)
+}
+
+/// Read and check the header, returning it with the bytes it was read from
+fn read_header(reader: &mut impl Read) -> Result<(FileHeader, Vec<u8>), String> {
+    let mut preamble = vec![0u8; MAGIC.len() + 1 + 4];
+    if read_full(reader, &mut preamble)? < preamble.len() || &preamble[..MAGIC.len()] != MAGIC {
+        return Err("Not a .kaelenc file".to_string());
+    }
+    if preamble[MAGIC.len()] != VERSION {
+        return Err(format!(
+            "Unsupported .kaelenc version {}",
+            preamble[MAGIC.len()]
+        ));
+    }
+    let len = u32::from_be_bytes(preamble[MAGIC.len() + 1..].try_into().expect("4 bytes"));
+    if len > MAX_HEADER_LEN {
+        return Err("Corrupt .kaelenc header".to_string());
+    }
+    let mut json = vec![0u8; len as usize];
+    if read_full(reader, &mut json)? < json.len() {
+        return Err("Corrupt .kaelenc header: truncated".to_string());
+    }
+    let header: FileHeader =
+        serde_json::from_slice(&json).map_err(|e| format!("Corrupt .kaelenc header: {}", e))?;
+    if !(1..=MAX_CHUNK_SIZE).contains(&header.chunk) {
+        return Err(format!("Unsupported .kaelenc chunk size {}", header.chunk));
+    }
+    preamble.extend_from_slice(&json);
+    Ok((header, preamble))
+}
+
+/// Decrypt a `.kaelenc` stream into `writer`. Each chunk is checked before
+/// it is written, but truncation only shows at the end: on error, discard
+/// whatever was written. `decrypt_file` does that for you.
+pub fn decrypt_stream(
+    mut reader: impl Read,
+    mut writer: impl Write,
+    secret: Secret,
+    mut progress: impl FnMut(u64),
+) -> Result<u64, String> {
+    let (header, preamble) = read_header(&mut reader)?;
+    let salt = B64URL
+        .decode(&header.key.salt)
+        .map_err(|e| format!("Corrupt .kaelenc salt: {}", e))?;
+    let prefix: [u8; PREFIX_LEN] = B64URL
+        .decode(&header.nonce)
+        .ok()
+        .and_then(|n| n.try_into().ok())
+        .ok_or("Corrupt .kaelenc nonce")?;
+    let key = envelope::derive(secret, &header.key.kdf, &salt)?;
+
+    let mut chunker = Chunker::new(&key, prefix, preamble);
+    let mut done = 0u64;
+    for_each_block(
+        &mut reader,
+        header.chunk as usize + TAG_LEN,
+        |chunk, last| {
+            let plaintext = chunker.open(chunk, last)?;
+            writer
+                .write_all(&plaintext)
+                .map_err(|e| format!("Write failed: {}", e))?;
+            done += plaintext.len() as u64;
+            progress(done);
+            Ok(())
+        },
+    )?;
+    writer.flush().map_err(|e| format!("Write failed: {}", e))?;
+    Ok(done)
+}
+
+/// Whether `path` starts like a `.kaelenc` file
+pub fn is_encrypted_file(path: &Path) -> bool {
+    let mut magic = [0u8; 7];
+    File::open(path)
+        .and_then(|mut f| f.read_exact(&mut magic))
+        .map(|_| &magic == MAGIC)
+        .unwrap_or(false)
+}
+
+/// `path` with `.kaelenc` appended
+pub fn encrypted_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(EXTENSION);
+    PathBuf::from(name)
+}
+
+/// `path` without its `.kaelenc` extension, if it has one
+pub fn decrypted_path(path: &Path) -> Option<PathBuf> {
+    (path.extension()? == EXTENSION).then(|| path.with_extension(""))
+}
+
+/// Write `dst` (mode 0600) via a temporary file, so a failure never leaves
+/// a partial file, or partial plaintext, behind
+fn write_atomically(
+    dst: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<u64, String>,
+) -> Result<u64, String> {
+    let mut tmp = dst.as_os_str().to_owned();
+    tmp.push(".part");
+    let tmp = PathBuf::from(tmp);
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp)
+        .map_err(|e| format!("Failed to create {}: {}", tmp.display(), e))?;
+    let mut out = BufWriter::new(file);
+    let result = write(&mut out).and_then(|n| {
+        let file = out
+            .into_inner()
+            .map_err(|e| format!("Write failed: {}", e))?;
+        file.sync_all()
+            .map_err(|e| format!("Write failed: {}", e))?;
+        Ok(n)
+    });
+    match result {
+        Ok(n) => std::fs::rename(&tmp, dst)
+            .map(|_| n)
+            .map_err(|e| format!("Failed to write {}: {}", dst.display(), e)),
+        Err(e) => {
+            let _ = std::fs::remove_file(&tmp);
+            Err(e)
+        }
+    }
+}
+
+fn open_input(path: &Path) -> Result<(BufReader<File>, u64), String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let len = file.metadata().map(|m| m.len()).unwrap_or(0);
+    Ok((BufReader::new(file), len))
+}
+
+/// Encrypt the file `src` into `dst`
+pub fn encrypt_file(
+    src: &Path,
+    dst: &Path,
+    secret: Secret,
+    params: &Params,
+    mut progress: impl FnMut(Progress),
+) -> Result<u64, String> {
+    let (reader, total) = open_input(src)?;
+    write_atomically(dst, |out| {
+        encrypt_stream(reader, out, secret, params, |done| {
+            progress(Progress {
+                done,
+                total: Some(total),
+            })
+        })
+    })
+}
+
+/// Encrypt what `reader` yields straight into the file `dst`, e.g. an
+/// export that should never touch the disk in the clear
+pub fn write_encrypted_file(
+    dst: &Path,
+    reader: impl Read,
+    secret: Secret,
+    params: &Params,
+) -> Result<u64, String> {
+    write_atomically(dst, |out| {
+        encrypt_stream(reader, out, secret, params, |_| {})
+    })
+}
+
+/// Encrypt `src` under `passphrase` into a new file in the temp directory,
+/// ready for uploading. The caller removes it afterwards.
+pub async fn encrypt_to_temp(src: &Path, passphrase: &str) -> Result<PathBuf, String> {
+    let name = src
+        .file_name()
+        .ok_or_else(|| format!("Not a file: {}", src.display()))?;
+    let tag: [u8; 4] = envelope::random();
+    let tag: String = tag.iter().map(|b| format!("{:02x}", b)).collect();
+    let dst = encrypted_path(&std::env::temp_dir().join(format!(
+        "kael-{}-{}",
+        tag,
+        name.to_string_lossy()
+    )));
+    let (src, passphrase, out) = (src.to_path_buf(), passphrase.to_string(), dst.clone());
+    tokio::task::spawn_blocking(move || {
+        encrypt_file(
+            &src,
+            &out,
+            Secret::Passphrase(&passphrase),
+            &Params::default(),
+            |_| {},
+        )
+    })
+    .await
+    .map_err(|e| format!("Encryption task failed: {}", e))??;
+    Ok(dst)
+}
+
+/// Decrypt the `.kaelenc` file `src` into `dst`. `dst` only appears once
+/// the whole file has been authenticated.
+pub fn decrypt_file(
+    src: &Path,
+    dst: &Path,
+    secret: Secret,
+    mut progress: impl FnMut(Progress),
+) -> Result<u64, String> {
+    let (reader, _) = open_input(src)?;
+    write_atomically(dst, |out| {
+        decrypt_stream(reader, out, secret, |done| {
+            progress(Progress { done, total: None })
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::envelope::{DataKey, Kdf};
+
+    const PASSPHRASE: &str = "correct horse";
+
+    fn params() -> Params {
+        Params {
+            kdf: Kdf::Pbkdf2Sha256 { rounds: 1_000 },
+            kid: None,
+        }
+    }
+
+    fn encrypt(plaintext: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        encrypt_stream(
+            plaintext,
+            &mut out,
+            Secret::Passphrase(PASSPHRASE),
+            &params(),
+            |_| {},
+        )
+        .unwrap();
+        out
+    }
+
+    fn decrypt(sealed: &[u8]) -> Result<Vec<u8>, String> {
+        let mut out = Vec::new();
+        decrypt_stream(sealed, &mut out, Secret::Passphrase(PASSPHRASE), |_| {})?;
+        Ok(out)
+    }
+
+    /// Offset of the first chunk
+    fn body_start(sealed: &[u8]) -> usize {
+        let len = u32::from_be_bytes(sealed[8..12].try_into().unwrap());
+        12 + len as usize
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let chunk = CHUNK_SIZE as usize;
+        for len in [0, 1, chunk - 1, chunk, chunk + 1, 3 * chunk] {
+            let plaintext: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+            let sealed = encrypt(&plaintext);
+            let chunks = len.div_ceil(chunk).max(1);
+            assert_eq!(sealed.len(), body_start(&sealed) + len + chunks * TAG_LEN);
+            assert_eq!(decrypt(&sealed).unwrap(), plaintext, "length {}", len);
+        }
+
+        let key = DataKey::generate();
+        let mut sealed = Vec::new();
+        let mut reports = Vec::new();
+        let data = vec![7u8; 2 * chunk + 10];
+        encrypt_stream(
+            &data[..],
+            &mut sealed,
+            Secret::Key(&key),
+            &Params::default(),
+            |done| reports.push(done),
+        )
+        .unwrap();
+        assert_eq!(
+            reports,
+            vec![chunk as u64, 2 * chunk as u64, data.len() as u64]
+        );
+        let mut out = Vec::new();
+        decrypt_stream(&sealed[..], &mut out, Secret::Key(&key), |_| {}).unwrap();
+        assert_eq!(out, data);
+        assert!(decrypt_stream(
+            &sealed[..],
+            &mut Vec::new(),
+            Secret::Key(&DataKey::generate()),
+            |_| {}
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_tampering_detected() {
+        let chunk = CHUNK_SIZE as usize;
+        let block = chunk + TAG_LEN;
+        let plaintext = vec![42u8; 3 * chunk + 100];
+        let sealed = encrypt(&plaintext);
+        let start = body_start(&sealed);
+        let blocks: Vec<&[u8]> = sealed[start..].chunks(block).collect();
+        let rebuild = |order: &[usize]| {
+            let mut out = sealed[..start].to_vec();
+            for &i in order {
+                out.extend_from_slice(blocks[i]);
+            }
+            out
+        };
+
+        // Truncated at a chunk boundary, or mid-chunk
+        assert!(decrypt(&rebuild(&[0, 1, 2]))
+            .unwrap_err()
+            .contains("truncated"));
+        assert!(decrypt(&sealed[..sealed.len() - 1]).is_err());
+        assert!(decrypt(&sealed[..start]).is_err());
+        // Reordered, dropped or duplicated chunks
+        assert!(decrypt(&rebuild(&[1, 0, 2, 3])).is_err());
+        assert!(decrypt(&rebuild(&[0, 2, 3])).is_err());
+        assert!(decrypt(&rebuild(&[0, 1, 2, 3, 3])).is_err());
+        // Extra data after the last chunk
+        let mut extended = sealed.clone();
+        extended.extend_from_slice(&[0u8; 20]);
+        assert!(decrypt(&extended).is_err());
+        // A flipped bit anywhere, header included
+        for i in [3, 20, start + 5, sealed.len() - 1] {
+            let mut flipped = sealed.clone();
+            flipped[i] ^= 1;
+            assert!(decrypt(&flipped).is_err(), "bit flip at {}", i);
+        }
+        assert_eq!(decrypt(&rebuild(&[0, 1, 2, 3])).unwrap(), plaintext);
+        assert_eq!(decrypt(b"plain text").unwrap_err(), "Not a .kaelenc file");
+    }
+
+    #[test]
+    fn test_files() {
+        let dir = std::env::temp_dir().join(format!("kael-stream-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("chat.db");
+        std::fs::write(&src, vec![1u8; 100_000]).unwrap();
+
+        let sealed = encrypted_path(&src);
+        assert_eq!(sealed, dir.join("chat.db.kaelenc"));
+        assert_eq!(decrypted_path(&sealed), Some(src.clone()));
+        assert_eq!(decrypted_path(&src), None);
+
+        let mut last = None;
+        encrypt_file(
+            &src,
+            &sealed,
+            Secret::Passphrase(PASSPHRASE),
+            &params(),
+            |p| last = Some(p),
+        )
+        .unwrap();
+        assert_eq!(last.unwrap().percent(), Some(100));
+        assert!(is_encrypted_file(&sealed));
+        assert!(!is_encrypted_file(&src));
+
+        let out = dir.join("restored.db");
+        assert_eq!(
+            decrypt_file(&sealed, &out, Secret::Passphrase(PASSPHRASE), |_| {}).unwrap(),
+            100_000
+        );
+        assert_eq!(std::fs::read(&out).unwrap(), std::fs::read(&src).unwrap());
+
+        let export = dir.join("chat.txt.kaelenc");
+        write_encrypted_file(
+            &export,
+            &b"[user]\nhi"[..],
+            Secret::Passphrase(PASSPHRASE),
+            &params(),
+        )
+        .unwrap();
+        decrypt_file(&export, &out, Secret::Passphrase(PASSPHRASE), |_| {}).unwrap();
+        assert_eq!(std::fs::read(&out).unwrap(), b"[user]\nhi");
+
+        // A failed decryption leaves nothing behind
+        let bad = dir.join("bad.db");
+        assert!(decrypt_file(&sealed, &bad, Secret::Passphrase("wrong"), |_| {}).is_err());
+        assert!(!bad.exists());
+        assert!(!dir.join("bad.db.part").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
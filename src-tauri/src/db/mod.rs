@@ -1,13 +1,12 @@
 #![allow(dead_code)]
 
-pub mod migrations;
-
-use crate::state::ChatMessage;
-use chrono::Utc;
-use rusqlite::{Connection, Result as SqlResult};
+use kael_storage::{ChatMessage, StorageManager};
 use std::path::PathBuf;
+use std::sync::OnceLock;
 use tauri::Manager;
 
+static SHARED_DB: OnceLock<StorageManager> = OnceLock::new();
+
 pub fn get_db_path(app: &tauri::AppHandle) -> PathBuf {
     let app_data_dir = app
         .path()
@@ -18,53 +17,73 @@ pub fn get_db_path(app: &tauri::AppHandle) -> PathBuf {
     app_data_dir.join("kael.db")
 }
 
-pub fn init_db(app: &tauri::AppHandle) -> SqlResult<Connection> {
-    let db_path = get_db_path(app);
-    let conn = Connection::open(&db_path)?;
-    conn.execute_batch("PRAGMA journal_mode = WAL;")?;
+pub fn init_db(app: &tauri::AppHandle) -> Result<StorageManager, String> {
+    open_db(get_db_path(app))
+}
 
-    migrations::run_migrations(&conn)?;
+// ==================== STANDALONE FUNCTIONS (for Dioxus Desktop) ====================
 
-    log::info!("Database initialized at: {:?}", db_path);
-    Ok(conn)
+/// Get database path without an AppHandle (`kael.db` in the active profile's data dir)
+pub fn get_db_path_standalone() -> PathBuf {
+    crate::profiles::data_dir().join("kael.db")
 }
 
-pub fn add_message(conn: &Connection, role: &str, text: &str) -> SqlResult<String> {
-    let id = uuid::Uuid::new_v4().to_string();
-    let now = Utc::now().to_rfc3339();
+pub fn init_db_standalone() -> Result<StorageManager, String> {
+    let storage = open_db(get_db_path_standalone())?;
+    import_legacy_chat_history(&storage);
+    Ok(storage)
+}
 
-    conn.execute(
-        "INSERT INTO chat_messages (id, role, text, timestamp, synced) 
-         VALUES (?1, ?2, ?3, ?4, ?5)",
-        rusqlite::params![&id, role, text, &now, 0],
-    )?;
+/// Move conversations from the old `chat_history.db` into `kael.db`, then
+/// rename the old file so the import runs once
+fn import_legacy_chat_history(storage: &StorageManager) {
+    let legacy_path = crate::profiles::data_dir().join("chat_history.db");
+    if !legacy_path.exists() {
+        return;
+    }
+    match storage.import_legacy_chat_history(&legacy_path) {
+        Ok(count) => {
+            log::info!("Imported {} legacy chat messages from {:?}", count, legacy_path);
+            if let Err(e) = std::fs::rename(&legacy_path, legacy_path.with_extension("db.imported")) {
+                log::warn!("Could not rename {:?}: {}", legacy_path, e);
+            }
+        }
+        Err(e) => log::warn!("Failed to import legacy chat history: {}", e),
+    }
+}
 
-    log::debug!("Message added: {}", id);
-    Ok(id)
+/// Process-wide handle to the standalone database, opened on first use
+pub fn shared() -> Result<StorageManager, String> {
+    if let Some(storage) = SHARED_DB.get() {
+        return Ok(storage.clone());
+    }
+    let storage = init_db_standalone()?;
+    Ok(SHARED_DB.get_or_init(|| storage).clone())
 }
 
-pub fn get_chat_history(conn: &Connection) -> SqlResult<Vec<ChatMessage>> {
-    let mut stmt = conn.prepare(
-        "SELECT id, role, text, timestamp, synced FROM chat_messages ORDER BY timestamp ASC",
-    )?;
+fn open_db(db_path: PathBuf) -> Result<StorageManager, String> {
+    let storage = StorageManager::open(&db_path)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
 
-    let messages = stmt.query_map([], |row| {
-        Ok(ChatMessage {
-            id: row.get(0)?,
-            role: row.get(1)?,
-            text: row.get(2)?,
-            timestamp: row
-                .get::<_, String>(3)?
-                .parse()
-                .unwrap_or_else(|_| Utc::now()),
-            synced: row.get::<_, i32>(4)? != 0,
-        })
-    })?;
+    log::info!("Database initialized at: {:?}", db_path);
+    Ok(storage)
+}
 
-    let mut result = Vec::new();
-    for msg in messages {
-        result.push(msg?);
-    }
+pub async fn add_message(storage: &StorageManager, role: &str, text: &str) -> Result<String, String> {
+    let message = storage
+        .messages()
+        .add(None, role, text)
+        .await
+        .map_err(|e| format!("Failed to add message: {}", e))?;
+
+    log::debug!("Message added: {}", message.id);
+    Ok(message.id)
+}
 
-    Ok(result)
+pub async fn get_chat_history(storage: &StorageManager) -> Result<Vec<ChatMessage>, String> {
+    storage
+        .messages()
+        .list_all()
+        .await
+        .map_err(|e| format!("Failed to load chat history: {}", e))
 }
@@ -0,0 +1,796 @@
+//! Typed client for the Firestore REST API.
+//!
+//! Rust structs map to and from Firestore documents through serde: a struct
+//! is serialized to JSON first, then each JSON value becomes the matching
+//! Firestore `Value` (`stringValue`, `integerValue`, `mapValue`, ...). Reading
+//! goes the other way, with timestamps, bytes and references read as strings.
+//!
+//! Requests go to `https://firestore.googleapis.com/v1`, or to the emulator
+//! when `FIRESTORE_EMULATOR_HOST` is set (as in the Firebase tools), and
+//! carry the signed-in user's token through `session::authorized`.
+
+use std::collections::BTreeMap;
+
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::session;
+
+const PRODUCTION_ENDPOINT: &str = "https://firestore.googleapis.com/v1";
+/// Documents asked for per list request
+const PAGE_SIZE: usize = 300;
+/// Firestore's limit on writes in one commit
+const MAX_BATCH: usize = 500;
+
+/// A document's fields
+pub type Fields = BTreeMap<String, Value>;
+
+/// One Firestore value, in the REST API's JSON form
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Value {
+    #[serde(rename = "nullValue")]
+    Null(()),
+    #[serde(rename = "booleanValue")]
+    Boolean(bool),
+    #[serde(rename = "integerValue", with = "int64")]
+    Integer(i64),
+    #[serde(rename = "doubleValue")]
+    Double(f64),
+    /// RFC 3339
+    #[serde(rename = "timestampValue")]
+    Timestamp(String),
+    #[serde(rename = "stringValue")]
+    String(String),
+    /// Base64
+    #[serde(rename = "bytesValue")]
+    Bytes(String),
+    #[serde(rename = "referenceValue")]
+    Reference(String),
+    #[serde(rename = "geoPointValue")]
+    GeoPoint { latitude: f64, longitude: f64 },
+    #[serde(rename = "arrayValue")]
+    Array(ArrayValue),
+    #[serde(rename = "mapValue")]
+    Map(MapValue),
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ArrayValue {
+    #[serde(default)]
+    pub values: Vec<Value>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct MapValue {
+    #[serde(default)]
+    pub fields: Fields,
+}
+
+/// Firestore sends 64-bit integers as strings
+mod int64 {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &i64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Int {
+            Text(String),
+            Number(i64),
+        }
+        match Int::deserialize(deserializer)? {
+            Int::Text(text) => text.parse().map_err(de::Error::custom),
+            Int::Number(n) => Ok(n),
+        }
+    }
+}
+
+impl From<serde_json::Value> for Value {
+    fn from(json: serde_json::Value) -> Self {
+        use serde_json::Value as Json;
+        match json {
+            Json::Null => Value::Null(()),
+            Json::Bool(b) => Value::Boolean(b),
+            Json::Number(n) => match n.as_i64() {
+                Some(i) => Value::Integer(i),
+                None => Value::Double(n.as_f64().unwrap_or_default()),
+            },
+            Json::String(s) => Value::String(s),
+            Json::Array(items) => Value::Array(ArrayValue {
+                values: items.into_iter().map(Value::from).collect(),
+            }),
+            Json::Object(map) => Value::Map(MapValue {
+                fields: map.into_iter().map(|(k, v)| (k, Value::from(v))).collect(),
+            }),
+        }
+    }
+}
+
+impl From<Value> for serde_json::Value {
+    fn from(value: Value) -> Self {
+        use serde_json::Value as Json;
+        match value {
+            Value::Null(()) => Json::Null,
+            Value::Boolean(b) => Json::Bool(b),
+            Value::Integer(i) => Json::from(i),
+            Value::Double(d) => serde_json::Number::from_f64(d).map_or(Json::Null, Json::Number),
+            Value::Timestamp(s) | Value::String(s) | Value::Bytes(s) | Value::Reference(s) => {
+                Json::String(s)
+            }
+            Value::GeoPoint {
+                latitude,
+                longitude,
+            } => serde_json::json!({ "latitude": latitude, "longitude": longitude }),
+            Value::Array(array) => Json::Array(array.values.into_iter().map(Json::from).collect()),
+            Value::Map(map) => Json::Object(
+                map.fields
+                    .into_iter()
+                    .map(|(k, v)| (k, Json::from(v)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// The fields of a document holding `data`, which must serialize to a map
+pub fn to_fields<T: Serialize>(data: &T) -> Result<Fields, String> {
+    let json = serde_json::to_value(data).map_err(|e| format!("Cannot encode document: {}", e))?;
+    match Value::from(json) {
+        Value::Map(map) => Ok(map.fields),
+        _ => Err("A document must be a map of fields".to_string()),
+    }
+}
+
+/// Read a document's fields into `T`
+pub fn from_fields<T: DeserializeOwned>(fields: Fields) -> Result<T, String> {
+    let json = serde_json::Value::from(Value::Map(MapValue { fields }));
+    serde_json::from_value(json).map_err(|e| format!("Cannot decode document: {}", e))
+}
+
+/// A document read from Firestore
+#[derive(Clone, Debug, PartialEq)]
+pub struct Document<T> {
+    /// Last segment of the document path
+    pub id: String,
+    pub data: T,
+    /// When the server last changed the document (RFC 3339)
+    pub update_time: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawDocument {
+    name: String,
+    #[serde(default)]
+    fields: Fields,
+    update_time: Option<String>,
+}
+
+impl RawDocument {
+    fn decode<T: DeserializeOwned>(self) -> Result<Document<T>, String> {
+        let id = self.name.rsplit('/').next().unwrap_or_default().to_string();
+        let data = from_fields(self.fields).map_err(|e| format!("{}: {}", self.name, e))?;
+        Ok(Document {
+            id,
+            data,
+            update_time: self.update_time,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ListResponse {
+    #[serde(default)]
+    documents: Vec<RawDocument>,
+    next_page_token: Option<String>,
+}
+
+/// One change in a `batch_write`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Write {
+    /// Create or replace the document at `path`; with a mask, only the
+    /// masked fields change
+    Update {
+        path: String,
+        fields: Fields,
+        mask: Option<Vec<String>>,
+    },
+    Delete {
+        path: String,
+    },
+}
+
+impl Write {
+    pub fn update<T: Serialize>(path: impl Into<String>, data: &T) -> Result<Self, String> {
+        Ok(Write::Update {
+            path: path.into(),
+            fields: to_fields(data)?,
+            mask: None,
+        })
+    }
+
+    pub fn delete(path: impl Into<String>) -> Self {
+        Write::Delete { path: path.into() }
+    }
+}
+
+/// Where Firestore requests go: the emulator if `FIRESTORE_EMULATOR_HOST`
+/// is set, the production API otherwise
+pub fn endpoint() -> String {
+    match std::env::var("FIRESTORE_EMULATOR_HOST") {
+        Ok(host) if !host.is_empty() => format!("http://{}/v1", host),
+        _ => PRODUCTION_ENDPOINT.to_string(),
+    }
+}
+
+/// A Firestore database, used as one user or unauthenticated
+#[derive(Clone)]
+pub struct Firestore {
+    client: Client,
+    endpoint: String,
+    project: String,
+    /// uid and ID token
+    user: Option<(String, String)>,
+}
+
+impl Firestore {
+    /// Unauthenticated access to `project` at `endpoint` (`.../v1`), e.g. the
+    /// emulator or a test server
+    pub fn new(endpoint: impl Into<String>, project: impl Into<String>) -> Self {
+        Self {
+            client: Client::new(),
+            endpoint: endpoint.into().trim_end_matches('/').to_string(),
+            project: project.into(),
+            user: None,
+        }
+    }
+
+    /// Kael's Firebase project as `user`. Fails in a local-only profile.
+    pub fn for_user(user: &crate::auth::User) -> Result<Self, String> {
+        let firestore = Self::new(endpoint(), super::project_id()?);
+        Ok(firestore.with_user(&user.uid, &user.id_token))
+    }
+
+    /// Send requests with this user's token, refreshed on 401
+    pub fn with_user(mut self, uid: &str, id_token: &str) -> Self {
+        self.user = Some((uid.to_string(), id_token.to_string()));
+        self
+    }
+
+    /// Full resource name of the document or collection at `path`, as
+    /// request bodies carry it
+    pub fn name(&self, path: &str) -> String {
+        format!(
+            "projects/{}/databases/(default)/documents/{}",
+            self.project,
+            path.trim_matches('/')
+        )
+    }
+
+    /// REST URL of `path`. Each segment is percent-encoded, so ids with
+    /// spaces, `#`, `?` or `%` address the right document.
+    fn url(&self, path: &str) -> String {
+        let segments: Vec<_> = path
+            .trim_matches('/')
+            .split('/')
+            .map(urlencoding::encode)
+            .collect();
+        format!(
+            "{}/projects/{}/databases/(default)/documents/{}",
+            self.endpoint,
+            self.project,
+            segments.join("/")
+        )
+    }
+
+    async fn send<F>(&self, build: F) -> Result<Response, String>
+    where
+        F: Fn() -> RequestBuilder,
+    {
+        let response = match &self.user {
+            Some((uid, id_token)) => {
+                session::authorized(uid, id_token, |token| build().bearer_auth(token)).await
+            }
+            None => build().send().await,
+        };
+        response.map_err(|e| format!("Firestore network error: {}", e))
+    }
+
+    async fn check(response: Response, action: &str) -> Result<Response, String> {
+        if response.status().is_success() {
+            return Ok(response);
+        }
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        Err(format!("Firestore {} error {}: {}", action, status, text))
+    }
+
+    /// The document at `path`, or `None` if there is none
+    pub async fn get<T: DeserializeOwned>(
+        &self,
+        path: &str,
+    ) -> Result<Option<Document<T>>, String> {
+        let url = self.url(path);
+        let response = self.send(|| self.client.get(&url)).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let raw: RawDocument = Self::check(response, "read")
+            .await?
+            .json()
+            .await
+            .map_err(|e| format!("Parse error: {}", e))?;
+        raw.decode().map(Some)
+    }
+
+    /// Every document in the collection at `path`, following pages.
+    /// Documents that don't decode as `T` are skipped with a warning.
+    pub async fn list<T: DeserializeOwned>(&self, path: &str) -> Result<Vec<Document<T>>, String> {
+        let url = self.url(path);
+        let page_size = PAGE_SIZE.to_string();
+        let mut documents = Vec::new();
+        let mut page_token: Option<String> = None;
+        loop {
+            let response = self
+                .send(|| {
+                    let request = self.client.get(&url).query(&[("pageSize", &page_size)]);
+                    match &page_token {
+                        Some(token) => request.query(&[("pageToken", token)]),
+                        None => request,
+                    }
+                })
+                .await?;
+            // A collection that was never written to doesn't exist
+            if response.status() == StatusCode::NOT_FOUND {
+                break;
+            }
+            let page: ListResponse = Self::check(response, "list")
+                .await?
+                .json()
+                .await
+                .map_err(|e| format!("Parse error: {}", e))?;
+            for raw in page.documents {
+                match raw.decode() {
+                    Ok(document) => documents.push(document),
+                    Err(e) => log::warn!("Skipping document {}", e),
+                }
+            }
+            match page.next_page_token.filter(|t| !t.is_empty()) {
+                Some(token) => page_token = Some(token),
+                None => break,
+            }
+        }
+        Ok(documents)
+    }
+
+    /// Create or replace the document at `path`
+    pub async fn patch<T: Serialize>(&self, path: &str, data: &T) -> Result<(), String> {
+        self.patch_fields(path, to_fields(data)?, None).await
+    }
+
+    /// Write `fields` to the document at `path`. With a `mask`, only the
+    /// listed fields change and the rest of the document is kept.
+    pub async fn patch_fields(
+        &self,
+        path: &str,
+        fields: Fields,
+        mask: Option<&[&str]>,
+    ) -> Result<(), String> {
+        let url = self.url(path);
+        let body = serde_json::json!({ "fields": fields });
+        let mask: Vec<(&str, &str)> = mask
+            .unwrap_or_default()
+            .iter()
+            .map(|field| ("updateMask.fieldPaths", *field))
+            .collect();
+        let response = self
+            .send(|| self.client.patch(&url).query(&mask).json(&body))
+            .await?;
+        Self::check(response, "save").await?;
+        Ok(())
+    }
+
+    /// Delete the document at `path`; deleting a missing document succeeds
+    pub async fn delete(&self, path: &str) -> Result<(), String> {
+        let url = self.url(path);
+        let response = self.send(|| self.client.delete(&url)).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(());
+        }
+        Self::check(response, "delete").await?;
+        Ok(())
+    }
+
+    /// Apply `writes` in commits of up to 500; each commit is atomic
+    pub async fn batch_write(&self, writes: Vec<Write>) -> Result<(), String> {
+        let url = format!(
+            "{}/projects/{}/databases/(default)/documents:commit",
+            self.endpoint, self.project
+        );
+        for chunk in writes.chunks(MAX_BATCH) {
+            let writes: Vec<serde_json::Value> = chunk
+                .iter()
+                .map(|write| match write {
+                    Write::Update { path, fields, mask } => {
+                        let mut json = serde_json::json!({
+                            "update": { "name": self.name(path), "fields": fields }
+                        });
+                        if let Some(mask) = mask {
+                            json["updateMask"] = serde_json::json!({ "fieldPaths": mask });
+                        }
+                        json
+                    }
+                    Write::Delete { path } => serde_json::json!({ "delete": self.name(path) }),
+                })
+                .collect();
+            let body = serde_json::json!({ "writes": writes });
+            let response = self.send(|| self.client.post(&url).json(&body)).await?;
+            Self::check(response, "commit").await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::{Mutex, OnceLock};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Documents in the mock, by full name
+    type Store = Mutex<BTreeMap<String, Fields>>;
+
+    fn store() -> &'static Store {
+        static STORE: OnceLock<Store> = OnceLock::new();
+        STORE.get_or_init(Default::default)
+    }
+
+    fn document_json(name: &str, fields: &Fields) -> serde_json::Value {
+        serde_json::json!({
+            "name": name,
+            "fields": fields,
+            "updateTime": "2025-01-01T00:00:00Z",
+        })
+    }
+
+    fn apply_update(
+        docs: &mut BTreeMap<String, Fields>,
+        name: String,
+        fields: Fields,
+        mask: Vec<String>,
+    ) {
+        if mask.is_empty() {
+            docs.insert(name, fields);
+        } else {
+            let doc = docs.entry(name).or_default();
+            for field in mask {
+                match fields.get(&field) {
+                    Some(value) => doc.insert(field, value.clone()),
+                    None => doc.remove(&field),
+                };
+            }
+        }
+    }
+
+    /// Answer one request the way the Firestore REST API does, for the
+    /// calls the client makes
+    fn handle(method: &str, target: &str, body: &str) -> (u16, String) {
+        let url = url::Url::parse(&format!("http://mock{}", target)).unwrap();
+        let path = urlencoding::decode(url.path().trim_start_matches("/v1/"))
+            .unwrap()
+            .into_owned();
+        let query = |key: &str| -> Vec<String> {
+            url.query_pairs()
+                .filter(|(k, _)| k == key)
+                .map(|(_, v)| v.to_string())
+                .collect()
+        };
+        let mut docs = store().lock().unwrap();
+        match method {
+            "GET" => {
+                if let Some(fields) = docs.get(&path) {
+                    return (200, document_json(&path, fields).to_string());
+                }
+                // A collection: its direct children, paged by offset
+                let prefix = format!("{}/", path);
+                let children: Vec<_> = docs
+                    .iter()
+                    .filter(|(name, _)| {
+                        name.strip_prefix(&prefix)
+                            .is_some_and(|id| !id.contains('/'))
+                    })
+                    .collect();
+                if children.is_empty() {
+                    return (404, r#"{"error":{"code":404}}"#.to_string());
+                }
+                let size: usize = query("pageSize")
+                    .first()
+                    .map_or(usize::MAX, |s| s.parse().unwrap());
+                let start: usize = query("pageToken").first().map_or(0, |s| s.parse().unwrap());
+                let page: Vec<_> = children
+                    .iter()
+                    .skip(start)
+                    .take(size)
+                    .map(|(name, fields)| document_json(name, fields))
+                    .collect();
+                let mut reply = serde_json::json!({ "documents": page });
+                if start + size < children.len() {
+                    reply["nextPageToken"] = serde_json::json!((start + size).to_string());
+                }
+                (200, reply.to_string())
+            }
+            "PATCH" => {
+                let body: serde_json::Value = serde_json::from_str(body).unwrap();
+                let fields: Fields = serde_json::from_value(body["fields"].clone()).unwrap();
+                apply_update(
+                    &mut docs,
+                    path.clone(),
+                    fields,
+                    query("updateMask.fieldPaths"),
+                );
+                (200, document_json(&path, &docs[&path]).to_string())
+            }
+            "DELETE" => {
+                docs.remove(&path);
+                (200, "{}".to_string())
+            }
+            "POST" if path.ends_with("documents:commit") => {
+                let body: serde_json::Value = serde_json::from_str(body).unwrap();
+                for write in body["writes"].as_array().unwrap() {
+                    if let Some(name) = write["delete"].as_str() {
+                        docs.remove(name);
+                    } else {
+                        let name = write["update"]["name"].as_str().unwrap().to_string();
+                        let fields =
+                            serde_json::from_value(write["update"]["fields"].clone()).unwrap();
+                        let mask = write["updateMask"]["fieldPaths"]
+                            .as_array()
+                            .map(|paths| {
+                                paths
+                                    .iter()
+                                    .map(|p| p.as_str().unwrap().to_string())
+                                    .collect()
+                            })
+                            .unwrap_or_default();
+                        apply_update(&mut docs, name, fields, mask);
+                    }
+                }
+                (200, r#"{"writeResults":[]}"#.to_string())
+            }
+            _ => (400, String::new()),
+        }
+    }
+
+    async fn serve(mut socket: tokio::net::TcpStream) {
+        let mut raw = Vec::new();
+        let mut buf = [0u8; 4096];
+        let (head, body) = loop {
+            let n = socket.read(&mut buf).await.unwrap();
+            raw.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&raw).to_string();
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length = head
+                    .lines()
+                    .find_map(|l| {
+                        l.to_lowercase()
+                            .strip_prefix("content-length: ")
+                            .map(str::to_string)
+                    })
+                    .map_or(0, |l| l.parse().unwrap());
+                if body.len() >= length || n == 0 {
+                    break (head.to_string(), body.to_string());
+                }
+            }
+        };
+        let mut request_line = head.lines().next().unwrap_or_default().split(' ');
+        let (method, target) = (request_line.next().unwrap(), request_line.next().unwrap());
+        let (status, reply) = handle(method, target, &body);
+        let response = format!(
+            "HTTP/1.1 {} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            reply.len(),
+            reply
+        );
+        let _ = socket.write_all(response.as_bytes()).await;
+    }
+
+    /// An in-memory stand-in for Firestore's REST API, started once.
+    /// Returns its `.../v1` endpoint.
+    pub(crate) fn mock_firestore() -> String {
+        static ENDPOINT: OnceLock<String> = OnceLock::new();
+        ENDPOINT
+            .get_or_init(|| {
+                let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+                let addr = listener.local_addr().unwrap();
+                std::thread::spawn(move || {
+                    let rt = tokio::runtime::Runtime::new().unwrap();
+                    rt.block_on(async move {
+                        listener.set_nonblocking(true).unwrap();
+                        let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+                        loop {
+                            let (socket, _) = listener.accept().await.unwrap();
+                            tokio::spawn(serve(socket));
+                        }
+                    });
+                });
+                format!("http://{}/v1", addr)
+            })
+            .clone()
+    }
+
+    /// The emulator when `FIRESTORE_EMULATOR_HOST` is set, the mock
+    /// otherwise; each caller gets its own collection prefix
+    pub(crate) fn test_firestore() -> (Firestore, String) {
+        let db = match std::env::var("FIRESTORE_EMULATOR_HOST") {
+            // The emulator lets the `owner` token past the security rules
+            Ok(_) => Firestore::new(endpoint(), "kael-test").with_user("owner", "owner"),
+            Err(_) => Firestore::new(mock_firestore(), "kael-test"),
+        };
+        (db, format!("tests/{}", uuid::Uuid::new_v4()))
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Note {
+        title: String,
+        words: i64,
+        score: f64,
+        pinned: bool,
+        tags: Vec<String>,
+        meta: BTreeMap<String, String>,
+        #[serde(default)]
+        archived_at: Option<String>,
+    }
+
+    fn note(title: &str, words: i64) -> Note {
+        Note {
+            title: title.to_string(),
+            words,
+            score: 0.5,
+            pinned: words % 2 == 0,
+            tags: vec!["rust".to_string(), "kael".to_string()],
+            meta: BTreeMap::from([("lang".to_string(), "en".to_string())]),
+            archived_at: None,
+        }
+    }
+
+    #[test]
+    fn test_value_mapping() {
+        let fields = to_fields(&note("Mirrors", 9_007_199_254_740_993)).unwrap();
+        let wire = serde_json::to_value(&fields).unwrap();
+        assert_eq!(
+            wire["title"],
+            serde_json::json!({ "stringValue": "Mirrors" })
+        );
+        // Integers travel as strings so they survive JavaScript clients
+        assert_eq!(
+            wire["words"],
+            serde_json::json!({ "integerValue": "9007199254740993" })
+        );
+        assert_eq!(wire["score"], serde_json::json!({ "doubleValue": 0.5 }));
+        assert_eq!(
+            wire["archived_at"],
+            serde_json::json!({ "nullValue": null })
+        );
+        assert_eq!(
+            wire["tags"]["arrayValue"]["values"][1],
+            serde_json::json!({ "stringValue": "kael" })
+        );
+        assert_eq!(
+            wire["meta"]["mapValue"]["fields"]["lang"],
+            serde_json::json!({ "stringValue": "en" })
+        );
+        let back: Note = from_fields(serde_json::from_value(wire).unwrap()).unwrap();
+        assert_eq!(back, note("Mirrors", 9_007_199_254_740_993));
+
+        // Values only other clients write still read as plain JSON
+        let fields: Fields = serde_json::from_value(serde_json::json!({
+            "title": { "stringValue": "From the console" },
+            "words": { "integerValue": 3 },
+            "score": { "doubleValue": 1.0 },
+            "pinned": { "booleanValue": true },
+            "tags": { "arrayValue": {} },
+            "meta": { "mapValue": {} },
+            "archived_at": { "timestampValue": "2025-03-01T10:00:00Z" },
+        }))
+        .unwrap();
+        let read: Note = from_fields(fields).unwrap();
+        assert_eq!(read.words, 3);
+        assert!(read.tags.is_empty());
+        assert_eq!(read.archived_at.as_deref(), Some("2025-03-01T10:00:00Z"));
+
+        assert!(to_fields(&"not a map").is_err());
+        assert!(from_fields::<Note>(Fields::new()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_client() {
+        let (db, root) = test_firestore();
+        let notes = format!("{}/notes", root);
+
+        assert_eq!(
+            db.get::<Note>(&format!("{}/missing", notes)).await.unwrap(),
+            None
+        );
+        assert!(db.list::<Note>(&notes).await.unwrap().is_empty());
+
+        db.patch(&format!("{}/a", notes), &note("First", 1))
+            .await
+            .unwrap();
+        let doc = db
+            .get::<Note>(&format!("{}/a", notes))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(doc.id, "a");
+        assert_eq!(doc.data, note("First", 1));
+        assert!(doc.update_time.is_some());
+
+        // A masked patch leaves the other fields alone
+        let mut fields = Fields::new();
+        fields.insert("title".to_string(), Value::String("Renamed".to_string()));
+        fields.insert("words".to_string(), Value::Integer(99));
+        db.patch_fields(&format!("{}/a", notes), fields, Some(&["title"]))
+            .await
+            .unwrap();
+        let doc = db
+            .get::<Note>(&format!("{}/a", notes))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(doc.data.title, "Renamed");
+        assert_eq!(doc.data.words, 1);
+
+        // More documents than one page holds
+        let writes: Vec<Write> = (0..PAGE_SIZE + 20)
+            .map(|i| {
+                Write::update(format!("{}/n{:04}", notes, i), &note("Bulk", i as i64)).unwrap()
+            })
+            .chain([Write::delete(format!("{}/a", notes))])
+            .collect();
+        db.batch_write(writes).await.unwrap();
+        let listed = db.list::<Note>(&notes).await.unwrap();
+        assert_eq!(listed.len(), PAGE_SIZE + 20);
+        assert!(listed
+            .iter()
+            .all(|d| d.id.starts_with('n') && d.data.title == "Bulk"));
+
+        // Documents of another shape are skipped, not fatal
+        db.patch(
+            &format!("{}/odd", notes),
+            &serde_json::json!({ "title": 5 }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(db.list::<Note>(&notes).await.unwrap().len(), PAGE_SIZE + 20);
+
+        db.delete(&format!("{}/odd", notes)).await.unwrap();
+        db.delete(&format!("{}/odd", notes)).await.unwrap();
+        assert_eq!(
+            db.get::<Note>(&format!("{}/odd", notes)).await.unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ids_are_escaped_in_urls() {
+        let (db, root) = test_firestore();
+        let notes = format!("{}/notes", root);
+        let path = format!("{}/a b#c?d%e", notes);
+
+        db.patch(&path, &note("Odd id", 1)).await.unwrap();
+        let doc = db.get::<Note>(&path).await.unwrap().unwrap();
+        assert_eq!(doc.id, "a b#c?d%e");
+        assert_eq!(doc.data, note("Odd id", 1));
+        // Nothing landed under a truncated id
+        assert_eq!(db.get::<Note>(&format!("{}/a b", notes)).await.unwrap(), None);
+        assert_eq!(db.list::<Note>(&notes).await.unwrap().len(), 1);
+
+        db.delete(&path).await.unwrap();
+        assert_eq!(db.get::<Note>(&path).await.unwrap(), None);
+    }
+}
@@ -1,15 +1,23 @@
 #![allow(dead_code)]
 
+pub mod firestore;
 pub mod uploader;
 
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
 
-use crate::auth::{decrypt_secret, encrypt_secret};
-
-fn project_id() -> Result<String, String> {
-    std::env::var("VITE_FIREBASE_PROJECT_ID")
-        .map_err(|_| "Missing VITE_FIREBASE_PROJECT_ID".to_string())
+use crate::auth::{decrypt_secret, encrypt_secret, SecretMigration};
+use crate::crypto::envelope::WrappedKey;
+use firestore::{Firestore, Write};
+
+/// Kael's Firebase project, from `VITE_FIREBASE_PROJECT_ID` (or
+/// `FIREBASE_PROJECT_ID`). Every cloud call starts here, so this is also
+/// where local-only profiles are refused.
+pub fn project_id() -> Result<String, String> {
+    crate::profiles::require_cloud()?;
+    ["VITE_FIREBASE_PROJECT_ID", "FIREBASE_PROJECT_ID"]
+        .iter()
+        .find_map(|var| std::env::var(var).ok().filter(|id| !id.is_empty()))
+        .ok_or_else(|| "Missing VITE_FIREBASE_PROJECT_ID".to_string())
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -25,78 +33,42 @@ pub struct User {
     pub email: String,
 }
 
-#[derive(Deserialize)]
-struct FirestoreList {
-    documents: Option<Vec<FirestoreDoc>>,
-}
-
-#[derive(Deserialize)]
-struct FirestoreDoc {
+/// An API key as stored in `users/<uid>/api_keys/<id>`
+#[derive(Serialize, Deserialize)]
+struct StoredKey {
+    #[serde(default)]
     name: String,
-    fields: Option<FirestoreFields>,
-}
-
-#[derive(Deserialize)]
-struct FirestoreFields {
-    name: Option<FirestoreString>,
-    value: Option<FirestoreString>,
-}
-
-#[derive(Deserialize)]
-#[allow(non_snake_case)]
-struct FirestoreString {
-    stringValue: String,
+    /// Encrypted with the user's data key
+    #[serde(default)]
+    value: String,
 }
 
-pub async fn get_api_keys(user: &crate::auth::User) -> Result<Vec<ApiKey>, String> {
-    let project = project_id()?;
-    let url = format!("https://firestore.googleapis.com/v1/projects/{}/databases/(default)/documents/users/{}/api_keys",
-        project, user.uid);
-    let client = Client::new();
-    let resp = client
-        .get(url)
-        .bearer_auth(&user.id_token)
-        .send()
-        .await
-        .map_err(|e| format!("Firestore network error: {}", e))?;
-    if !resp.status().is_success() {
-        // Empty collection returns 404; treat as empty
-        return Ok(vec![]);
-    }
-    let data: FirestoreList = resp
-        .json()
-        .await
-        .map_err(|e| format!("Parse error: {}", e))?;
-    let mut out = vec![];
-    if let Some(docs) = data.documents {
-        for d in docs {
-            if let Some(f) = d.fields {
-                let id = d.name.split('/').last().unwrap_or("").to_string();
-                let name = f.name.map(|x| x.stringValue).unwrap_or_default();
-                let value_enc = f.value.map(|x| x.stringValue).unwrap_or_default();
-                let value = decrypt_secret(user, &value_enc).unwrap_or_default();
-                out.push(ApiKey { id, name, value });
-            }
-        }
-    }
-    Ok(out)
+/// The wrapped data key in `users/<uid>/keys/data_key`
+#[derive(Serialize, Deserialize)]
+struct DataKeyDoc {
+    /// `WrappedKey` as JSON
+    wrapped: String,
 }
 
-#[derive(Serialize)]
-struct FirestoreWrite<'a> {
-    fields: FirestoreWriteFields<'a>,
+fn api_keys_path(user: &crate::auth::User) -> String {
+    format!("users/{}/api_keys", user.uid)
 }
 
-#[derive(Serialize)]
-struct FirestoreWriteFields<'a> {
-    name: FirestoreWriteString<'a>,
-    value: FirestoreWriteString<'a>,
+fn data_key_path(user: &crate::auth::User) -> String {
+    format!("users/{}/keys/data_key", user.uid)
 }
 
-#[derive(Serialize)]
-#[allow(non_snake_case)]
-struct FirestoreWriteString<'a> {
-    stringValue: &'a str,
+pub async fn get_api_keys(user: &crate::auth::User) -> Result<Vec<ApiKey>, String> {
+    let db = Firestore::for_user(user)?;
+    let keys = db.list::<StoredKey>(&api_keys_path(user)).await?;
+    Ok(keys
+        .into_iter()
+        .map(|doc| ApiKey {
+            value: decrypt_secret(user, &doc.data.value).unwrap_or_default(),
+            name: doc.data.name,
+            id: doc.id,
+        })
+        .collect())
 }
 
 pub async fn save_api_key(
@@ -104,50 +76,68 @@ pub async fn save_api_key(
     name: &str,
     plaintext_value: &str,
 ) -> Result<(), String> {
-    let project = project_id()?;
+    let db = Firestore::for_user(user)?;
     let doc_id = name.to_lowercase().replace(' ', "_");
-    let url = format!("https://firestore.googleapis.com/v1/projects/{}/databases/(default)/documents/users/{}/api_keys?documentId={}",
-        project, user.uid, doc_id);
-    let client = Client::new();
-    let encrypted = encrypt_secret(user, plaintext_value);
-    let body = FirestoreWrite {
-        fields: FirestoreWriteFields {
-            name: FirestoreWriteString { stringValue: name },
-            value: FirestoreWriteString {
-                stringValue: &encrypted,
-            },
-        },
+    let key = StoredKey {
+        name: name.to_string(),
+        value: encrypt_secret(user, plaintext_value)?,
     };
-    let resp = client
-        .post(url)
-        .bearer_auth(&user.id_token)
-        .json(&body)
-        .send()
+    // PATCH creates the document or replaces an existing one
+    db.patch(&format!("{}/{}", api_keys_path(user), doc_id), &key)
         .await
-        .map_err(|e| format!("Firestore network error: {}", e))?;
-    if !resp.status().is_success() {
-        let status = resp.status();
-        let text = resp.text().await.unwrap_or_default();
-        return Err(format!("Firestore save error {}: {}", status, text));
-    }
-    Ok(())
 }
 
 pub async fn delete_api_key(user: &crate::auth::User, id: &str) -> Result<(), String> {
-    let project = project_id()?;
-    let url = format!("https://firestore.googleapis.com/v1/projects/{}/databases/(default)/documents/users/{}/api_keys/{}",
-        project, user.uid, id);
-    let client = Client::new();
-    let resp = client
-        .delete(url)
-        .bearer_auth(&user.id_token)
-        .send()
-        .await
-        .map_err(|e| format!("Firestore network error: {}", e))?;
-    if !resp.status().is_success() {
-        let status = resp.status();
-        let text = resp.text().await.unwrap_or_default();
-        return Err(format!("Firestore delete error {}: {}", status, text));
+    let db = Firestore::for_user(user)?;
+    db.delete(&format!("{}/{}", api_keys_path(user), id)).await
+}
+
+/// The user's wrapped data key, if one was stored
+pub async fn get_wrapped_data_key(user: &crate::auth::User) -> Result<Option<WrappedKey>, String> {
+    let db = Firestore::for_user(user)?;
+    let Some(doc) = db.get::<DataKeyDoc>(&data_key_path(user)).await? else {
+        return Ok(None);
+    };
+    serde_json::from_str(&doc.data.wrapped)
+        .map(Some)
+        .map_err(|e| format!("Malformed data key: {}", e))
+}
+
+pub async fn save_wrapped_data_key(user: &crate::auth::User, wrapped: &WrappedKey) -> Result<(), String> {
+    let db = Firestore::for_user(user)?;
+    let wrapped = serde_json::to_string(wrapped).map_err(|e| e.to_string())?;
+    db.patch(&data_key_path(user), &DataKeyDoc { wrapped }).await
+}
+
+/// Re-encrypt API keys still stored as legacy XOR blobs. The secrets must
+/// be unlocked; keys whose id_token has rotated are reported, not touched.
+pub async fn migrate_legacy_api_keys(user: &crate::auth::User) -> Result<SecretMigration, String> {
+    if !crate::auth::secrets_unlocked(user) {
+        return Err("Secrets are locked".to_string());
+    }
+    let db = Firestore::for_user(user)?;
+    let mut report = SecretMigration::default();
+    let mut writes = Vec::new();
+    for doc in db.list::<StoredKey>(&api_keys_path(user)).await? {
+        let name = if doc.data.name.is_empty() {
+            doc.id.clone()
+        } else {
+            doc.data.name
+        };
+        if !crate::auth::is_legacy_secret(&doc.data.value) {
+            continue;
+        }
+        let Some(plaintext) = decrypt_secret(user, &doc.data.value) else {
+            report.unreadable.push(name);
+            continue;
+        };
+        let key = StoredKey {
+            name,
+            value: encrypt_secret(user, &plaintext)?,
+        };
+        writes.push(Write::update(format!("{}/{}", api_keys_path(user), doc.id), &key)?);
     }
-    Ok(())
+    report.migrated = writes.len();
+    db.batch_write(writes).await?;
+    Ok(report)
 }
@@ -1,10 +1,39 @@
 //! Firebase Storage uploader using Google Cloud Storage REST API
 //! Uses service account JSON for authentication via OAuth2 JWT
+//!
+//! The JWT is signed RS256 (RSA PKCS#1 v1.5 with SHA-256) with the service
+//! account's key and exchanged for an access token, which is reused until
+//! shortly before it expires. Files up to `RESUMABLE_THRESHOLD` go up in one
+//! multipart request; larger ones, like release artifacts, use a resumable
+//! upload sent in chunks, so a dropped connection only repeats the chunk in
+//! flight.
 
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::pkcs1v15::SigningKey;
+use rsa::pkcs8::DecodePrivateKey;
+use rsa::signature::{SignatureEncoding, Signer};
+use rsa::RsaPrivateKey;
 use serde::{Deserialize, Serialize};
+use sha2::Sha256;
 use std::fs;
 use std::path::Path;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::crypto::stream::Progress;
+
+const STORAGE_ENDPOINT: &str = "https://storage.googleapis.com";
+const STORAGE_SCOPE: &str = "https://www.googleapis.com/auth/devstorage.full_control";
+/// Renew the access token this many seconds before it expires
+const TOKEN_MARGIN: u64 = 60;
+/// Files larger than this use a resumable upload
+pub const RESUMABLE_THRESHOLD: u64 = 8 * 1024 * 1024;
+/// Bytes per resumable request; Cloud Storage wants multiples of 256 KiB
+const CHUNK_SIZE: usize = 8 * 1024 * 1024;
+/// Failed chunk requests in a row before giving up
+const MAX_RETRIES: u32 = 5;
+const RETRY_DELAY: Duration = Duration::from_millis(500);
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ServiceAccount {
@@ -29,21 +58,25 @@ impl ServiceAccount {
 
     /// Create a JWT token for OAuth2 authentication
     pub fn create_jwt_token(&self) -> Result<String, Box<dyn std::error::Error>> {
-        // Header
-        let header = r#"{"alg":"RS256","typ":"JWT"}"#;
-        let header_b64 = base64_url_encode(header.as_bytes());
+        self.create_jwt_token_at(now())
+    }
 
-        // Claims (scope for Cloud Storage)
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)?
-            .as_secs();
-        let expires = now + 3600; // 1 hour
+    /// The JWT as issued at `now` (Unix seconds), valid for an hour
+    fn create_jwt_token_at(&self, now: u64) -> Result<String, Box<dyn std::error::Error>> {
+        // Header; the key id lets Google pick the right public key
+        let header = serde_json::json!({
+            "alg": "RS256",
+            "typ": "JWT",
+            "kid": self.private_key_id,
+        });
+        let header_b64 = base64_url_encode(serde_json::to_string(&header)?.as_bytes());
 
+        // Claims (scope for Cloud Storage)
         let claims = serde_json::json!({
             "iss": self.client_email,
-            "scope": "https://www.googleapis.com/auth/devstorage.full_control",
+            "scope": STORAGE_SCOPE,
             "aud": self.token_uri,
-            "exp": expires,
+            "exp": now + 3600,
             "iat": now,
         });
 
@@ -59,20 +92,57 @@ impl ServiceAccount {
     }
 }
 
+struct AccessToken {
+    token: String,
+    /// Unix seconds
+    expires_at: u64,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    #[serde(default = "default_expires_in")]
+    expires_in: u64,
+}
+
+fn default_expires_in() -> u64 {
+    3600
+}
+
 /// Firebase Storage uploader
 pub struct FirebaseUploader {
     bucket: String,
     service_account: ServiceAccount,
+    client: reqwest::Client,
+    endpoint: String,
+    token: Mutex<Option<AccessToken>>,
+    resumable_threshold: u64,
+    chunk_size: usize,
 }
 
 impl FirebaseUploader {
     /// Create a new Firebase uploader
     pub fn new(bucket: String, sa_path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
         let service_account = ServiceAccount::from_file(sa_path)?;
-        Ok(Self {
+        Ok(Self::with_service_account(bucket, service_account))
+    }
+
+    pub fn with_service_account(bucket: String, service_account: ServiceAccount) -> Self {
+        Self {
             bucket,
             service_account,
-        })
+            client: reqwest::Client::new(),
+            endpoint: STORAGE_ENDPOINT.to_string(),
+            token: Mutex::new(None),
+            resumable_threshold: RESUMABLE_THRESHOLD,
+            chunk_size: CHUNK_SIZE,
+        }
+    }
+
+    /// Send uploads to another Cloud Storage endpoint, e.g. an emulator
+    pub fn with_endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoint = endpoint.into().trim_end_matches('/').to_string();
+        self
     }
 
     /// Upload a file to Firebase Storage
@@ -80,94 +150,353 @@ impl FirebaseUploader {
         &self,
         local_path: &Path,
         remote_path: &str,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        self.upload_file_with_progress(local_path, remote_path, |_| {})
+            .await
+    }
+
+    /// Upload a file to Firebase Storage, reporting the bytes sent so far
+    pub async fn upload_file_with_progress(
+        &self,
+        local_path: &Path,
+        remote_path: &str,
+        mut progress: impl FnMut(Progress),
     ) -> Result<String, Box<dyn std::error::Error>> {
         // Get access token
         let access_token = self.get_access_token().await?;
+        let size = tokio::fs::metadata(local_path).await?.len();
+        progress(Progress {
+            done: 0,
+            total: Some(size),
+        });
+
+        if size > self.resumable_threshold {
+            self.upload_resumable(&access_token, local_path, remote_path, size, &mut progress)
+                .await?;
+        } else {
+            self.upload_multipart(&access_token, local_path, remote_path)
+                .await?;
+        }
+        progress(Progress {
+            done: size,
+            total: Some(size),
+        });
+
+        Ok(format!(
+            "https://storage.googleapis.com/{}/{}",
+            self.bucket, remote_path
+        ))
+    }
 
-        // Read file
+    fn upload_url(&self) -> String {
+        format!("{}/upload/storage/v1/b/{}/o", self.endpoint, self.bucket)
+    }
+
+    fn metadata(remote_path: &str) -> serde_json::Value {
+        serde_json::json!({
+            "name": remote_path,
+            "contentType": "application/octet-stream",
+        })
+    }
+
+    /// Metadata and content in one `multipart/related` request
+    async fn upload_multipart(
+        &self,
+        access_token: &str,
+        local_path: &Path,
+        remote_path: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
         let file_data = tokio::fs::read(local_path).await?;
+        let boundary = format!("kael-{}", uuid::Uuid::new_v4().simple());
 
-        // Upload using multipart
-        let url = format!(
-            "https://storage.googleapis.com/upload/storage/v1/b/{}/o",
-            self.bucket
-        );
-
-        let client = reqwest::Client::new();
-        let response = client
-            .post(&url)
-            .header("Authorization", format!("Bearer {}", access_token))
-            .query(&[("uploadType", "media"), ("name", remote_path)])
-            .body(file_data)
+        let mut body = format!(
+            "--{b}\r\nContent-Type: application/json; charset=UTF-8\r\n\r\n{}\r\n--{b}\r\nContent-Type: application/octet-stream\r\n\r\n",
+            Self::metadata(remote_path),
+            b = boundary
+        )
+        .into_bytes();
+        body.extend_from_slice(&file_data);
+        body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+
+        let response = self
+            .client
+            .post(self.upload_url())
+            .bearer_auth(access_token)
+            .query(&[("uploadType", "multipart")])
+            .header(
+                "Content-Type",
+                format!("multipart/related; boundary={}", boundary),
+            )
+            .body(body)
             .send()
             .await?;
 
         if response.status().is_success() {
-            let _json: serde_json::Value = response.json().await?;
-            let public_url = format!(
-                "https://storage.googleapis.com/{}/{}",
-                self.bucket, remote_path
-            );
-            Ok(public_url)
+            Ok(())
         } else {
             let err_text = response.text().await.unwrap_or_default();
             Err(format!("Firebase upload failed: {}", err_text).into())
         }
     }
 
-    /// Get access token from Google OAuth2
+    /// Start a resumable session, then send the file in chunks. A failed
+    /// chunk is retried from wherever the server says it got to.
+    async fn upload_resumable(
+        &self,
+        access_token: &str,
+        local_path: &Path,
+        remote_path: &str,
+        size: u64,
+        progress: &mut impl FnMut(Progress),
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let response = self
+            .client
+            .post(self.upload_url())
+            .bearer_auth(access_token)
+            .query(&[("uploadType", "resumable")])
+            .header("X-Upload-Content-Type", "application/octet-stream")
+            .header("X-Upload-Content-Length", size)
+            .json(&Self::metadata(remote_path))
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            let err_text = response.text().await.unwrap_or_default();
+            return Err(format!("Failed to start resumable upload: {}", err_text).into());
+        }
+        let session = response
+            .headers()
+            .get("Location")
+            .and_then(|v| v.to_str().ok())
+            .ok_or("No upload session in response")?
+            .to_string();
+
+        let mut file = tokio::fs::File::open(local_path).await?;
+        let mut offset = 0u64;
+        let mut failures = 0u32;
+        // 308s that didn't move the offset forward
+        let mut stalls = 0u32;
+        loop {
+            if offset >= size {
+                // Every byte is committed but the server hasn't finished the
+                // object; a status query finalizes it
+                return match self.session_offset(&session, size).await? {
+                    None => Ok(()),
+                    Some(committed) => Err(format!(
+                        "Upload not finalized with {} of {} bytes committed",
+                        committed, size
+                    )
+                    .into()),
+                };
+            }
+            let len = (size - offset).min(self.chunk_size as u64);
+            let mut chunk = vec![0u8; len as usize];
+            file.seek(std::io::SeekFrom::Start(offset)).await?;
+            file.read_exact(&mut chunk).await?;
+
+            // The session URI is the credential; no bearer token needed
+            let range = format!("bytes {}-{}/{}", offset, offset + len - 1, size);
+            let sent = self
+                .client
+                .put(&session)
+                .header("Content-Range", range)
+                .body(chunk)
+                .send()
+                .await;
+
+            let status = match sent {
+                Ok(response) => match response.status().as_u16() {
+                    200 | 201 => return Ok(()),
+                    308 => {
+                        let next = committed(&response);
+                        if next > offset {
+                            failures = 0;
+                            stalls = 0;
+                        } else {
+                            stalls += 1;
+                            if stalls > MAX_RETRIES {
+                                return Err(format!(
+                                    "Firebase upload stuck at {} of {} bytes",
+                                    next, size
+                                )
+                                .into());
+                            }
+                        }
+                        offset = next;
+                        progress(Progress {
+                            done: offset,
+                            total: Some(size),
+                        });
+                        continue;
+                    }
+                    code if code == 429 || code >= 500 => format!("status {}", code),
+                    _ => {
+                        let err_text = response.text().await.unwrap_or_default();
+                        return Err(format!("Firebase upload failed: {}", err_text).into());
+                    }
+                },
+                Err(e) => e.to_string(),
+            };
+
+            failures += 1;
+            if failures > MAX_RETRIES {
+                return Err(format!("Firebase upload failed after {} retries: {}", MAX_RETRIES, status).into());
+            }
+            log::warn!("Upload chunk failed ({}), resuming", status);
+            tokio::time::sleep(RETRY_DELAY * 2u32.pow(failures - 1)).await;
+            match self.session_offset(&session, size).await? {
+                Some(committed) => offset = committed,
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// How much of the upload the server has, or `None` if it is complete
+    async fn session_offset(
+        &self,
+        session: &str,
+        size: u64,
+    ) -> Result<Option<u64>, Box<dyn std::error::Error>> {
+        let response = self
+            .client
+            .put(session)
+            .header("Content-Range", format!("bytes */{}", size))
+            .send()
+            .await?;
+        match response.status().as_u16() {
+            200 | 201 => Ok(None),
+            308 => Ok(Some(committed(&response))),
+            code => Err(format!("Upload session lost (status {})", code).into()),
+        }
+    }
+
+    /// Encrypt a file under `passphrase` and upload it as
+    /// `<remote_path>.kaelenc`
+    pub async fn upload_encrypted_file(
+        &self,
+        local_path: &Path,
+        remote_path: &str,
+        passphrase: &str,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let sealed = crate::crypto::stream::encrypt_to_temp(local_path, passphrase).await?;
+        let remote_path = format!("{}.{}", remote_path, crate::crypto::stream::EXTENSION);
+        let result = self.upload_file(&sealed, &remote_path).await;
+        let _ = tokio::fs::remove_file(&sealed).await;
+        result
+    }
+
+    /// Get access token from Google OAuth2, reusing the last one until it
+    /// is about to expire
     async fn get_access_token(&self) -> Result<String, Box<dyn std::error::Error>> {
-        let jwt = self.service_account.create_jwt_token()?;
+        if let Some(cached) = self.token.lock().unwrap().as_ref() {
+            if now() + TOKEN_MARGIN < cached.expires_at {
+                return Ok(cached.token.clone());
+            }
+        }
 
-        let client = reqwest::Client::new();
+        let jwt = self.service_account.create_jwt_token()?;
         let params = [
             ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
             ("assertion", &jwt),
         ];
 
-        let response = client
+        let response = self
+            .client
             .post(&self.service_account.token_uri)
             .form(&params)
             .send()
             .await?;
 
         if response.status().is_success() {
-            let data: serde_json::Value = response.json().await?;
-            let token = data["access_token"]
-                .as_str()
-                .ok_or("No access token in response")?
-                .to_string();
-            Ok(token)
+            let data: TokenResponse = response.json().await?;
+            *self.token.lock().unwrap() = Some(AccessToken {
+                token: data.access_token.clone(),
+                expires_at: now() + data.expires_in,
+            });
+            Ok(data.access_token)
         } else {
-            Err("Failed to get access token".into())
+            let err_text = response.text().await.unwrap_or_default();
+            Err(format!("Failed to get access token: {}", err_text).into())
         }
     }
 }
 
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Bytes the server has stored, from a 308's `Range: bytes=0-<last>`
+fn committed(response: &reqwest::Response) -> u64 {
+    response
+        .headers()
+        .get("Range")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|range| range.rsplit('-').next())
+        .and_then(|last| last.parse::<u64>().ok())
+        .map_or(0, |last| last + 1)
+}
+
 // Helper functions for JWT encoding
 fn base64_url_encode(data: &[u8]) -> String {
     use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
     URL_SAFE_NO_PAD.encode(data)
 }
 
+/// RSASSA-PKCS1-v1_5 with SHA-256 over `message`. Service account keys are
+/// PKCS#8 (`BEGIN PRIVATE KEY`); PKCS#1 (`BEGIN RSA PRIVATE KEY`) works too.
 fn sign_rs256(message: &str, private_key_pem: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-    // For now, use a simple HMAC placeholder. In production, use `jsonwebtoken` crate with RSA.
-    // This is a limitation: proper RS256 requires RSA key parsing, which needs additional deps.
-    // Alternative: use the `jsonwebtoken` crate which handles this properly.
-    use hmac::{Hmac, Mac};
-    use sha2::Sha256;
-
-    // WARNING: This is a HMAC-SHA256 placeholder, not RS256!
-    // For production, use the `jsonwebtoken` crate: https://docs.rs/jsonwebtoken/
-    type HmacSha256 = Hmac<Sha256>;
-    let mut mac = HmacSha256::new_from_slice(private_key_pem.as_bytes())?;
-    mac.update(message.as_bytes());
-    Ok(mac.finalize().into_bytes().to_vec())
+    let key = RsaPrivateKey::from_pkcs8_pem(private_key_pem)
+        .or_else(|_| RsaPrivateKey::from_pkcs1_pem(private_key_pem))
+        .map_err(|e| format!("Invalid service account private key: {}", e))?;
+    let signer = SigningKey::<Sha256>::new(key);
+    Ok(signer.sign(message.as_bytes()).to_vec())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use rsa::pkcs1v15::{Signature, VerifyingKey};
+    use rsa::pkcs8::{EncodePrivateKey, LineEnding};
+    use rsa::signature::Verifier;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{Arc, OnceLock};
+    use tokio::io::AsyncWriteExt;
+
+    /// Generating RSA keys is slow; share one between tests
+    fn test_key() -> &'static RsaPrivateKey {
+        static KEY: OnceLock<RsaPrivateKey> = OnceLock::new();
+        KEY.get_or_init(|| RsaPrivateKey::new(&mut rand::thread_rng(), 2048).unwrap())
+    }
+
+    fn account(token_uri: &str) -> ServiceAccount {
+        ServiceAccount {
+            account_type: "service_account".into(),
+            project_id: "test-project".into(),
+            private_key_id: "key123".into(),
+            private_key: test_key().to_pkcs8_pem(LineEnding::LF).unwrap().to_string(),
+            client_email: "release@test-project.iam.gserviceaccount.com".into(),
+            client_id: "123".into(),
+            auth_uri: "https://accounts.google.com/o/oauth2/auth".into(),
+            token_uri: token_uri.into(),
+        }
+    }
+
+    /// Check a JWT against the test key; returns its claims
+    fn verify(jwt: &str) -> serde_json::Value {
+        let (message, signature) = jwt.rsplit_once('.').unwrap();
+        let signature = Signature::try_from(URL_SAFE_NO_PAD.decode(signature).unwrap().as_slice()).unwrap();
+        VerifyingKey::<Sha256>::new(test_key().to_public_key())
+            .verify(message.as_bytes(), &signature)
+            .expect("valid RS256 signature");
+        let (header, claims) = message.split_once('.').unwrap();
+        let header: serde_json::Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(header).unwrap()).unwrap();
+        assert_eq!(header["alg"], "RS256");
+        serde_json::from_slice(&URL_SAFE_NO_PAD.decode(claims).unwrap()).unwrap()
+    }
 
     #[test]
     fn test_service_account_load() {
@@ -183,5 +512,284 @@ mod tests {
         }"#;
         let sa: ServiceAccount = serde_json::from_str(sa_json).unwrap();
         assert_eq!(sa.project_id, "test-project");
+        assert!(sa.create_jwt_token().is_err());
+    }
+
+    #[test]
+    fn test_jwt_is_rs256_signed() {
+        let sa = account("https://oauth2.googleapis.com/token");
+        let jwt = sa.create_jwt_token_at(1_700_000_000).unwrap();
+        let claims = verify(&jwt);
+        assert_eq!(claims["iss"], sa.client_email);
+        assert_eq!(claims["aud"], "https://oauth2.googleapis.com/token");
+        assert_eq!(claims["scope"], STORAGE_SCOPE);
+        assert_eq!(claims["exp"], 1_700_003_600);
+        // PKCS#1 v1.5 is deterministic
+        assert_eq!(jwt, sa.create_jwt_token_at(1_700_000_000).unwrap());
+
+        // Keys exported as PKCS#1 sign the same
+        use rsa::pkcs1::EncodeRsaPrivateKey;
+        let pkcs1 = test_key().to_pkcs1_pem(LineEnding::LF).unwrap();
+        let pkcs8 = test_key().to_pkcs8_pem(LineEnding::LF).unwrap();
+        assert_eq!(sign_rs256("m", &pkcs1).unwrap(), sign_rs256("m", &pkcs8).unwrap());
+    }
+
+    /// Fake Google: a token endpoint that checks the JWT, and Cloud Storage
+    /// uploads. The second chunk of a resumable upload fails once.
+    #[derive(Default)]
+    struct Google {
+        addr: OnceLock<String>,
+        tokens: AtomicUsize,
+        objects: Mutex<HashMap<String, Vec<u8>>>,
+        /// Resumable sessions: object name and bytes received
+        sessions: Mutex<HashMap<String, (String, Vec<u8>)>>,
+        chunks: AtomicUsize,
+        empty_chunks: AtomicUsize,
+        /// Answer every chunk with a 308 that commits nothing
+        stall: AtomicBool,
+        /// Answer the last chunk with a 308 and only finish the object on a
+        /// status query
+        finalize_on_query: AtomicBool,
+    }
+
+    struct Request {
+        target: String,
+        headers: HashMap<String, String>,
+        body: Vec<u8>,
+    }
+
+    async fn read_request(socket: &mut tokio::net::TcpStream) -> Request {
+        let mut raw = Vec::new();
+        let mut buf = [0u8; 64 * 1024];
+        loop {
+            let n = socket.read(&mut buf).await.unwrap();
+            raw.extend_from_slice(&buf[..n]);
+            let Some(end) = raw.windows(4).position(|w| w == b"\r\n\r\n") else { continue };
+            let head = String::from_utf8_lossy(&raw[..end]).to_string();
+            let mut lines = head.lines();
+            let mut request_line = lines.next().unwrap().split(' ');
+            let target = request_line.nth(1).unwrap();
+            let headers: HashMap<String, String> = lines
+                .filter_map(|l| l.split_once(": "))
+                .map(|(k, v)| (k.to_lowercase(), v.to_string()))
+                .collect();
+            let length = headers.get("content-length").map_or(0, |l| l.parse().unwrap());
+            if raw.len() >= end + 4 + length || n == 0 {
+                return Request {
+                    target: target.to_string(),
+                    headers,
+                    body: raw[end + 4..].to_vec(),
+                };
+            }
+        }
+    }
+
+    impl Google {
+        fn handle(&self, req: Request) -> (u16, Vec<(String, String)>, String) {
+            let addr = self.addr.get().unwrap();
+            let query = |key: &str| {
+                url::Url::parse(&format!("http://x{}", req.target))
+                    .unwrap()
+                    .query_pairs()
+                    .find(|(k, _)| k == key)
+                    .map(|(_, v)| v.to_string())
+            };
+
+            if req.target == "/token" {
+                let form: HashMap<String, String> = url::form_urlencoded::parse(&req.body).into_owned().collect();
+                let claims = verify(&form["assertion"]);
+                assert_eq!(claims["aud"], format!("http://{}/token", addr));
+                let n = self.tokens.fetch_add(1, Ordering::SeqCst);
+                let reply = serde_json::json!({"access_token": format!("tok-{}", n), "expires_in": 3600});
+                return (200, vec![], reply.to_string());
+            }
+            if let Some(id) = req.target.strip_prefix("/session/") {
+                let mut sessions = self.sessions.lock().unwrap();
+                let (name, data) = sessions.get_mut(id).unwrap();
+                let range = &req.headers["content-range"];
+                let total: usize = range.rsplit('/').next().unwrap().parse().unwrap();
+                let status_query = range.starts_with("bytes */");
+                if self.stall.load(Ordering::SeqCst) && !status_query {
+                    self.chunks.fetch_add(1, Ordering::SeqCst);
+                    return (308, vec![], String::new());
+                }
+                if let Some(span) = range.strip_prefix("bytes ").filter(|_| !status_query) {
+                    let start: usize = span.split('-').next().unwrap().parse().unwrap();
+                    if req.body.is_empty() {
+                        self.empty_chunks.fetch_add(1, Ordering::SeqCst);
+                    }
+                    if self.chunks.fetch_add(1, Ordering::SeqCst) == 1 {
+                        return (503, vec![], String::new());
+                    }
+                    assert_eq!(start, data.len(), "chunks resume where the last one ended");
+                    data.extend_from_slice(&req.body);
+                }
+                if data.len() == total && (status_query || !self.finalize_on_query.load(Ordering::SeqCst)) {
+                    self.objects.lock().unwrap().insert(name.clone(), data.clone());
+                    return (200, vec![], "{}".to_string());
+                }
+                let committed = vec![("Range".to_string(), format!("bytes=0-{}", data.len() - 1))];
+                return (308, committed, String::new());
+            }
+
+            assert!(req.target.starts_with("/upload/storage/v1/b/releases/o"));
+            let bearer = req.headers["authorization"].clone();
+            assert!(bearer.starts_with("Bearer tok-"));
+            match query("uploadType").as_deref() {
+                Some("multipart") => {
+                    let boundary = req.headers["content-type"].split("boundary=").nth(1).unwrap().to_string();
+                    let parts: Vec<&[u8]> = split(&req.body, format!("--{}", boundary).as_bytes());
+                    let meta_part = parts[1];
+                    let media_part = parts[2];
+                    let body = |part: &[u8]| {
+                        let start = part.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+                        part[start..part.len() - 2].to_vec()
+                    };
+                    let meta: serde_json::Value = serde_json::from_slice(&body(meta_part)).unwrap();
+                    let name = meta["name"].as_str().unwrap().to_string();
+                    self.objects.lock().unwrap().insert(name, body(media_part));
+                    (200, vec![], "{}".to_string())
+                }
+                Some("resumable") => {
+                    let meta: serde_json::Value = serde_json::from_slice(&req.body).unwrap();
+                    let id = uuid::Uuid::new_v4().to_string();
+                    let name = meta["name"].as_str().unwrap().to_string();
+                    self.sessions.lock().unwrap().insert(id.clone(), (name, Vec::new()));
+                    let location = vec![("Location".to_string(), format!("http://{}/session/{}", addr, id))];
+                    (200, location, String::new())
+                }
+                _ => (400, vec![], String::new()),
+            }
+        }
+
+        async fn start() -> Arc<Google> {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let google = Arc::new(Google::default());
+            google.addr.set(listener.local_addr().unwrap().to_string()).unwrap();
+            let server = Arc::clone(&google);
+            tokio::spawn(async move {
+                loop {
+                    let (mut socket, _) = listener.accept().await.unwrap();
+                    let server = Arc::clone(&server);
+                    tokio::spawn(async move {
+                        let req = read_request(&mut socket).await;
+                        let (status, headers, body) = server.handle(req);
+                        let mut response = format!("HTTP/1.1 {} X\r\nContent-Length: {}\r\nConnection: close\r\n", status, body.len());
+                        for (k, v) in headers {
+                            response.push_str(&format!("{}: {}\r\n", k, v));
+                        }
+                        response.push_str("\r\n");
+                        response.push_str(&body);
+                        let _ = socket.write_all(response.as_bytes()).await;
+                    });
+                }
+            });
+            google
+        }
+
+        fn uploader(&self) -> FirebaseUploader {
+            let addr = self.addr.get().unwrap();
+            FirebaseUploader::with_service_account("releases".into(), account(&format!("http://{}/token", addr)))
+                .with_endpoint(format!("http://{}", addr))
+        }
+    }
+
+    fn split<'a>(data: &'a [u8], sep: &[u8]) -> Vec<&'a [u8]> {
+        let mut parts = Vec::new();
+        let mut rest = data;
+        while let Some(i) = rest.windows(sep.len()).position(|w| w == sep) {
+            parts.push(&rest[..i]);
+            rest = &rest[i + sep.len()..];
+        }
+        parts.push(rest);
+        parts
+    }
+
+    #[tokio::test]
+    async fn test_uploads() {
+        let google = Google::start().await;
+        let mut uploader = google.uploader();
+        uploader.resumable_threshold = 512 * 1024;
+        uploader.chunk_size = 256 * 1024;
+
+        let dir = std::env::temp_dir().join(format!("kael-upload-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let small = dir.join("version.json");
+        std::fs::write(&small, br#"{"version":"0.5.0"}"#).unwrap();
+        let large = dir.join("kael-os.AppImage");
+        let artifact: Vec<u8> = (0..700 * 1024).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&large, &artifact).unwrap();
+
+        let url = uploader.upload_file(&small, "releases/version.json").await.unwrap();
+        assert_eq!(url, "https://storage.googleapis.com/releases/releases/version.json");
+
+        let mut seen = Vec::new();
+        uploader
+            .upload_file_with_progress(&large, "releases/kael-os.AppImage", |p| seen.push(p.done))
+            .await
+            .unwrap();
+
+        let objects = google.objects.lock().unwrap();
+        assert_eq!(objects["releases/version.json"], br#"{"version":"0.5.0"}"#);
+        assert_eq!(objects["releases/kael-os.AppImage"], artifact);
+        // 3 chunks plus the one that failed and was resent
+        assert_eq!(google.chunks.load(Ordering::SeqCst), 4);
+        assert_eq!(seen, vec![0, 256 * 1024, 512 * 1024, 700 * 1024]);
+        // Both uploads used the same access token
+        assert_eq!(google.tokens.load(Ordering::SeqCst), 1);
+        drop(objects);
+
+        // An expiring token is replaced
+        uploader.token.lock().unwrap().as_mut().unwrap().expires_at = now() + TOKEN_MARGIN;
+        uploader.upload_file(&small, "releases/version.json").await.unwrap();
+        assert_eq!(google.tokens.load(Ordering::SeqCst), 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_resumable_upload_finalized_by_status_query() {
+        let google = Google::start().await;
+        google.finalize_on_query.store(true, Ordering::SeqCst);
+        let mut uploader = google.uploader();
+        uploader.resumable_threshold = 256 * 1024;
+        uploader.chunk_size = 256 * 1024;
+
+        let path = std::env::temp_dir().join(format!("kael-upload-{}.bin", uuid::Uuid::new_v4()));
+        let artifact: Vec<u8> = (0..512 * 1024).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&path, &artifact).unwrap();
+
+        // Sending empty chunks instead never finishes
+        let upload = uploader.upload_file(&path, "releases/kael-os.tar.gz");
+        tokio::time::timeout(Duration::from_secs(10), upload)
+            .await
+            .expect("upload finishes")
+            .unwrap();
+        assert_eq!(google.objects.lock().unwrap()["releases/kael-os.tar.gz"], artifact);
+        assert_eq!(google.empty_chunks.load(Ordering::SeqCst), 0);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_resumable_upload_gives_up_without_progress() {
+        let google = Google::start().await;
+        google.stall.store(true, Ordering::SeqCst);
+        let mut uploader = google.uploader();
+        uploader.resumable_threshold = 256 * 1024;
+        uploader.chunk_size = 256 * 1024;
+
+        let path = std::env::temp_dir().join(format!("kael-upload-{}.bin", uuid::Uuid::new_v4()));
+        std::fs::write(&path, vec![7u8; 512 * 1024]).unwrap();
+
+        let upload = uploader.upload_file(&path, "releases/kael-os.tar.gz");
+        let error = tokio::time::timeout(Duration::from_secs(10), upload)
+            .await
+            .expect("upload gives up")
+            .unwrap_err();
+        assert!(error.to_string().contains("stuck at 0 of 524288 bytes"), "{}", error);
+        assert_eq!(google.chunks.load(Ordering::SeqCst), MAX_RETRIES as usize + 1);
+
+        std::fs::remove_file(&path).unwrap();
     }
 }
@@ -19,6 +19,15 @@ pub struct GitHubRelease {
     pub body: String,
     pub draft: bool,
     pub prerelease: bool,
+    #[serde(default)]
+    pub assets: Vec<GitHubAsset>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GitHubAsset {
+    pub id: u64,
+    pub name: String,
+    pub size: u64,
 }
 
 #[derive(Debug, Serialize)]
@@ -144,6 +153,30 @@ impl GitHubUploader {
         }
     }
 
+    /// Delete an asset, e.g. before uploading a new file under its name
+    pub async fn delete_asset(&self, asset_id: u64) -> Result<(), Box<dyn std::error::Error>> {
+        let url = format!(
+            "https://api.github.com/repos/{}/{}/releases/assets/{}",
+            self.owner, self.repo, asset_id
+        );
+
+        let client = reqwest::Client::new();
+        let response = client
+            .delete(&url)
+            .header("Authorization", format!("token {}", self.token))
+            .header("Accept", "application/vnd.github.v3+json")
+            .header("User-Agent", "kael-os")
+            .send()
+            .await?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            let err_text = response.text().await.unwrap_or_default();
+            Err(format!("Failed to delete asset: {}", err_text).into())
+        }
+    }
+
     /// Upload multiple files to a release
     pub async fn upload_assets(
         &self,
@@ -1,6 +1,7 @@
 #![allow(dead_code)]
 
 use serde::{Deserialize, Serialize};
+use std::path::Path;
 use std::process::Command;
 use std::str;
 
@@ -170,6 +171,25 @@ pub async fn get_key_fingerprint(key_id: &str) -> Result<String, String> {
     Err("Fingerprint not found".to_string())
 }
 
+/// Write a detached binary signature of `path` to `signature`, made with
+/// the secret key `key_id`
+pub async fn detach_sign(path: &Path, signature: &Path, key_id: &str) -> Result<(), String> {
+    let output = Command::new("gpg")
+        .args(["--batch", "--yes", "--detach-sign", "--local-user", key_id])
+        .arg("--output")
+        .arg(signature)
+        .arg(path)
+        .output()
+        .map_err(|e| format!("Failed to run gpg: {}", e))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("GPG signing error: {}", stderr));
+    }
+
+    Ok(())
+}
+
 /// Parse GPG output in colon-separated format
 fn parse_gpg_keys(output: &str) -> Vec<GpgKey> {
     let mut keys = Vec::new();
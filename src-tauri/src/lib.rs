@@ -0,0 +1,28 @@
+//! Kael without the desktop UI: LLM routing, auth, storage, settings, the
+//! services, sync, the local API and the release tooling. The desktop app
+//! (`main.rs`) and the `kael` command-line tool (`bin/kael.rs`) are both
+//! built on it.
+#![allow(dependency_on_unit_never_type_fallback)]
+
+pub mod api;
+pub mod auth;
+pub mod crypto;
+pub mod db;
+pub mod firebase;
+pub mod github;
+pub mod gpg;
+pub mod llm;
+pub mod mcp;
+pub mod oauth_server;
+pub mod profiles;
+pub mod publish;
+pub mod services;
+pub mod session;
+pub mod settings;
+pub mod state;
+pub mod sync;
+pub mod terminal;
+pub mod updater;
+pub mod vault;
+pub mod version;
+pub mod webdav;
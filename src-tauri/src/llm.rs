@@ -5,26 +5,6 @@ use crate::services::{ollama_manager, system_context};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
-use std::sync::{Mutex, OnceLock};
-use std::collections::HashMap;
-
-// In-memory cache for API keys
-static API_KEY_CACHE: OnceLock<Mutex<HashMap<String, String>>> = OnceLock::new();
-
-/// Cache an API key for a provider in memory
-pub fn cache_api_key(provider_name: &str, api_key: &str) {
-    let cache = API_KEY_CACHE.get_or_init(|| Mutex::new(HashMap::new()));
-    let mut map = cache.lock().unwrap();
-    map.insert(provider_name.to_string(), api_key.to_string());
-    log::info!("💾 Cached API key for: {}", provider_name);
-}
-
-/// Get cached API key for a provider
-fn get_cached_api_key(provider_name: &str) -> Option<String> {
-    let cache = API_KEY_CACHE.get()?;
-    let map = cache.lock().unwrap();
-    map.get(provider_name).cloned()
-}
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum LLMProvider {
@@ -141,6 +121,72 @@ pub async fn warm_local_model(model: &str) -> bool {
     send_request_single(req, None).await.is_ok()
 }
 
+/// Ask the local Ollama model, handing each piece of the reply to `on_chunk`
+/// as it is generated. Returns the whole reply.
+pub async fn stream_local(request: &LLMRequest, mut on_chunk: impl FnMut(&str)) -> Result<String, String> {
+    #[derive(Deserialize)]
+    struct OllamaStreamLine {
+        #[serde(default)]
+        response: String,
+        #[serde(default)]
+        done: bool,
+        #[serde(default)]
+        error: Option<String>,
+    }
+
+    ollama_manager::ensure_ollama_running().await;
+    let endpoint =
+        std::env::var("OLLAMA_ENDPOINT").unwrap_or_else(|_| "http://127.0.0.1:11434".to_string());
+    let url = format!("{}/api/generate", endpoint.trim_end_matches('/'));
+    let model = if request.model.trim().is_empty() {
+        default_model_for(&LLMProvider::Ollama)
+    } else {
+        request.model.clone()
+    };
+    let body = serde_json::json!({
+        "model": model,
+        "prompt": request.prompt,
+        "system": request.system,
+        "stream": true,
+    });
+
+    let mut resp = Client::new()
+        .post(&url)
+        .json(&body)
+        .send()
+        .await
+        .map_err(|e| format!("Ollama connection failed: {}", e))?;
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let text = resp.text().await.unwrap_or_default();
+        return Err(format!("Ollama unavailable ({}): {}", status, text));
+    }
+
+    // One JSON object per line; a line may be split across chunks
+    let mut pending: Vec<u8> = Vec::new();
+    let mut reply = String::new();
+    while let Some(chunk) = resp.chunk().await.map_err(|e| format!("Ollama stream failed: {}", e))? {
+        pending.extend_from_slice(&chunk);
+        while let Some(end) = pending.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = pending.drain(..=end).collect();
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+            let parsed: OllamaStreamLine =
+                serde_json::from_slice(&line).map_err(|e| format!("Ollama parsing error: {}", e))?;
+            if let Some(error) = parsed.error {
+                return Err(format!("Ollama error: {}", error));
+            }
+            on_chunk(&parsed.response);
+            reply.push_str(&parsed.response);
+            if parsed.done {
+                return Ok(reply);
+            }
+        }
+    }
+    Ok(reply)
+}
+
 // Try multiple providers with fallback
 pub async fn send_request_with_fallback(
     initial_request: LLMRequest,
@@ -184,66 +230,58 @@ pub async fn send_request_with_fallback(
     Err(format!("All providers failed. Last error: {}", last_error))
 }
 
+/// Provider for a name in the settings panel and the saved provider order
+pub fn provider_from_label(label: &str) -> Option<LLMProvider> {
+    match label {
+        "Ollama (Local)" => Some(LLMProvider::Ollama),
+        "Mistral AI" => Some(LLMProvider::Mistral),
+        "Google Gemini" => Some(LLMProvider::Gemini),
+        "GitHub Copilot" => Some(LLMProvider::Copilot),
+        "GitHub Copilot CLI (New)" => Some(LLMProvider::CopilotAgent),
+        "Office 365 AI" => Some(LLMProvider::Office365AI),
+        "Google One AI" => Some(LLMProvider::GoogleOneAI),
+        "Minstrel AI" => Some(LLMProvider::Minstrel),
+        _ => None,
+    }
+}
+
+/// Name a provider's key is stored under in the vault
+pub fn vault_name(provider: &LLMProvider) -> Option<&'static str> {
+    match provider {
+        LLMProvider::Mistral => Some("Mistral AI"),
+        LLMProvider::Gemini => Some("Google Gemini"),
+        LLMProvider::Copilot => Some("GitHub Copilot"),
+        LLMProvider::CopilotAgent => Some("GitHub Copilot CLI"),
+        LLMProvider::Office365AI => Some("Office 365 AI"),
+        LLMProvider::GoogleOneAI => Some("Google One AI"),
+        LLMProvider::Minstrel => Some("Minstrel AI"),
+        LLMProvider::Ollama => None,
+    }
+}
+
 async fn send_request_single(
     mut request: LLMRequest,
     user: Option<&User>,
 ) -> Result<LLMResponse, String> {
-    // Try local cached keys first to avoid initial network delay
-    if request.api_key.is_none() {
-        let provider_name = match request.provider {
-            LLMProvider::Mistral => "Mistral AI",
-            LLMProvider::Gemini => "Google Gemini",
-            LLMProvider::Copilot => "GitHub Copilot",
-            LLMProvider::CopilotAgent => "GitHub Copilot CLI",
-            LLMProvider::Office365AI => "Office 365 AI",
-            LLMProvider::GoogleOneAI => "Google One AI",
-            LLMProvider::Minstrel => "Minstrel AI",
-            _ => "",
-        };
-        if !provider_name.is_empty() {
-            if let Ok(json) = std::fs::read_to_string("/tmp/kael_cached_api_keys.json") {
-                if let Ok(list) = serde_json::from_str::<Vec<serde_json::Value>>(&json) {
-                    if let Some(val) = list.iter().find_map(|v| {
-                        let name = v.get("name").and_then(|x| x.as_str());
-                        let value = v.get("value").and_then(|x| x.as_str());
-                        match (name, value) {
-                            (Some(n), Some(v)) if n == provider_name => Some(v.to_string()),
-                            _ => None,
-                        }
-                    }) {
-                        request.api_key = Some(val);
-                    }
-                }
-            }
-        }
+    // A local-only profile answers from Ollama or not at all
+    if !matches!(request.provider, LLMProvider::Ollama) {
+        crate::profiles::require_cloud()?;
     }
 
-    if let Some(user) = user {
-        if request.api_key.is_none() {
-            let provider_name = match request.provider {
-                LLMProvider::Mistral => "Mistral AI",
-                LLMProvider::Gemini => "Google Gemini",
-                LLMProvider::Copilot => "GitHub Copilot",
-                LLMProvider::CopilotAgent => "GitHub Copilot CLI",
-                LLMProvider::Office365AI => "Office 365 AI",
-                LLMProvider::GoogleOneAI => "Google One AI",
-                LLMProvider::Minstrel => "Minstrel AI",
-                _ => "",
-            };
-            if !provider_name.is_empty() {
-                // Try cache first
-                if let Some(cached_key) = get_cached_api_key(provider_name) {
-                    log::info!("🔑 Using cached API key for {}", provider_name);
-                    request.api_key = Some(cached_key);
-                } else {
-                    // Fall back to Firebase
+    // Keys come from the local vault; Firestore only when sync is on
+    if request.api_key.is_none() {
+        if let Some(provider_name) = vault_name(&request.provider) {
+            request.api_key = crate::vault::get(provider_name).map(|key| key.to_string());
+            if request.api_key.is_none() && crate::settings::current().sync_api_keys {
+                if let Some(user) = user {
                     log::info!("🔍 Loading API key for {} from Firebase...", provider_name);
                     if let Ok(keys) = crate::firebase::get_api_keys(user).await {
-                        if let Some(key) = keys.iter().find(|k| k.name == provider_name) {
-                            log::info!("✅ Loaded key for {} from Firebase", provider_name);
-                            request.api_key = Some(key.value.clone());
-                            // Cache it for next time
-                            cache_api_key(provider_name, &key.value);
+                        if let Some(key) = keys.into_iter().find(|k| k.name == provider_name && !k.value.is_empty()) {
+                            // Keep it for next time if the vault is unlocked
+                            if let Err(e) = crate::vault::set(provider_name, &key.value) {
+                                log::debug!("Not storing {} key locally: {}", provider_name, e);
+                            }
+                            request.api_key = Some(key.value);
                         }
                     }
                 }
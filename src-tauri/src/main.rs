@@ -1,35 +1,33 @@
 #![allow(dependency_on_unit_never_type_fallback)]
 
 mod app_scaffold;
-mod auth;
 mod commands;
 mod components;
-mod crypto;
-mod db;
-mod firebase;
-mod github;
-mod gpg;
-mod llm;
-mod oauth_server;
-mod services;
 mod ssl;
-mod state;
-mod terminal;
-mod updater;
-mod version;
-mod webdav;
 mod webview_oauth;
 
+// GUI-free modules live in the library, shared with the `kael` CLI
+use kael_os::{
+    api, auth, crypto, db, firebase, github, llm, oauth_server, profiles, services, session,
+    settings, state, sync, terminal, vault, version, webdav,
+};
+
 use crate::components::app::App;
 use dioxus::prelude::*;
 
 fn main() {
     dotenv::from_filename(".env.local").ok();
     env_logger::init();
+    log::info!("Using profile {}", profiles::active().name);
+
+    // Keep the signed-in session's token fresh in the background
+    session::start_session_manager();
+
+    // Sync the collections picked in settings while signed in, if sync is on
+    sync::start_sync_service();
 
-    // Initialize OAuth callback server in background
-    // This spawns the server in a separate thread with its own Tokio runtime
-    oauth_server::start_oauth_server();
+    // Local JSON-RPC API for editors and scripts, when enabled in settings
+    api::start_api_server();
 
     // Launch using Dioxus Desktop launcher
     dioxus_desktop::launch::launch(app, Default::default(), Default::default());
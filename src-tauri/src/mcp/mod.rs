@@ -0,0 +1,272 @@
+//! Model Context Protocol server, so MCP-aware assistants and editors can
+//! use Kael's system tools.
+//!
+//! Speaks JSON-RPC 2.0 with one message per line, which is the MCP stdio
+//! transport; `kael mcp` serves it on stdin/stdout. Besides `initialize`,
+//! `ping` and the `notifications/initialized` notification it answers
+//! `tools/list` and `tools/call`; `tools` lists what is offered.
+//!
+//! ```json
+//! {"mcpServers": {"kael": {"command": "kael", "args": ["mcp"]}}}
+//! ```
+
+pub mod tools;
+
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::api::handlers::{
+    ApiContext, RpcError, RpcRequest, RpcResponse, INVALID_PARAMS, INVALID_REQUEST, METHOD_NOT_FOUND, PARSE_ERROR,
+};
+
+/// Protocol revisions this server speaks, newest first
+pub const PROTOCOL_VERSIONS: &[&str] = &["2025-03-26", "2024-11-05"];
+
+/// Reply to `initialize`. The client's revision is accepted when supported;
+/// otherwise the newest one is offered and the client decides.
+fn initialize(params: &Value) -> Value {
+    let requested = params.get("protocolVersion").and_then(Value::as_str);
+    let version = requested
+        .filter(|v| PROTOCOL_VERSIONS.contains(v))
+        .unwrap_or(PROTOCOL_VERSIONS[0]);
+    json!({
+        "protocolVersion": version,
+        "capabilities": { "tools": { "listChanged": false } },
+        "serverInfo": { "name": "kael-os", "version": env!("CARGO_PKG_VERSION") },
+        "instructions": "Tools for the Arch Linux machine Kael runs on. Commands are only ever run in a sandbox.",
+    })
+}
+
+async fn dispatch(ctx: &ApiContext, method: &str, params: Value) -> Result<Value, RpcError> {
+    match method {
+        "initialize" => Ok(initialize(&params)),
+        "ping" => Ok(json!({})),
+        "tools/list" => Ok(json!({
+            "tools": tools::TOOLS.iter().map(tools::Tool::describe).collect::<Vec<_>>(),
+        })),
+        "tools/call" => {
+            let name = params
+                .get("name")
+                .and_then(Value::as_str)
+                .ok_or_else(|| RpcError::new(INVALID_PARAMS, "tools/call needs a tool name"))?;
+            let arguments = params.get("arguments").cloned().unwrap_or(Value::Null);
+            tools::call(ctx, name, arguments).await
+        }
+        _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("Unknown method: {}", method))),
+    }
+}
+
+/// Handle one line from the client. Returns `None` for notifications and
+/// for responses the client sends back, neither of which gets a reply.
+pub async fn handle_message(ctx: &ApiContext, line: &str) -> Option<Value> {
+    let value: Value = match serde_json::from_str(line) {
+        Ok(value) => value,
+        Err(e) => {
+            return reply(RpcResponse::error(
+                Value::Null,
+                RpcError::new(PARSE_ERROR, e.to_string()),
+            ))
+        }
+    };
+    if value.get("method").is_none() && (value.get("result").is_some() || value.get("error").is_some()) {
+        return None;
+    }
+    let id = value.get("id").cloned().unwrap_or(Value::Null);
+    let request: RpcRequest = match serde_json::from_value(value) {
+        Ok(request) => request,
+        Err(e) => return reply(RpcResponse::error(id, RpcError::new(INVALID_REQUEST, e.to_string()))),
+    };
+    if request.jsonrpc != "2.0" {
+        return reply(RpcResponse::error(
+            id,
+            RpcError::new(INVALID_REQUEST, "jsonrpc must be \"2.0\""),
+        ));
+    }
+    // Notifications (`notifications/initialized`, `notifications/cancelled`)
+    // need nothing from us
+    let id = request.id?;
+    reply(match dispatch(ctx, &request.method, request.params).await {
+        Ok(result) => RpcResponse {
+            jsonrpc: "2.0",
+            result: Some(result),
+            error: None,
+            id,
+        },
+        Err(error) => RpcResponse::error(id, error),
+    })
+}
+
+fn reply(response: RpcResponse) -> Option<Value> {
+    serde_json::to_value(response).ok()
+}
+
+/// Serve one client until it closes `reader`
+pub async fn serve<R, W>(reader: R, mut writer: W, ctx: ApiContext) -> Result<(), String>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut lines = reader.lines();
+    while let Some(line) = lines.next_line().await.map_err(|e| e.to_string())? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = handle_message(&ctx, &line).await {
+            let mut out = response.to_string();
+            out.push('\n');
+            writer.write_all(out.as_bytes()).await.map_err(|e| e.to_string())?;
+            writer.flush().await.map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+/// Serve on stdin/stdout. Anything else must go to stderr.
+pub async fn serve_stdio(ctx: ApiContext) -> Result<(), String> {
+    let stdin = tokio::io::BufReader::new(tokio::io::stdin());
+    serve(stdin, tokio::io::stdout(), ctx).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kael_storage::StorageManager;
+    use tokio::io::{BufReader, DuplexStream, Lines, ReadHalf, WriteHalf};
+
+    /// Scripted client talking to a server over an in-memory pipe
+    struct Client {
+        lines: Lines<BufReader<ReadHalf<DuplexStream>>>,
+        writer: WriteHalf<DuplexStream>,
+    }
+
+    impl Client {
+        fn start() -> Self {
+            let (client, server) = tokio::io::duplex(64 * 1024);
+            let ctx = ApiContext {
+                storage: StorageManager::open_in_memory().unwrap(),
+            };
+            tokio::spawn(async move {
+                let (read, write) = tokio::io::split(server);
+                serve(BufReader::new(read), write, ctx).await.unwrap();
+            });
+            let (read, writer) = tokio::io::split(client);
+            Self {
+                lines: BufReader::new(read).lines(),
+                writer,
+            }
+        }
+
+        async fn send(&mut self, line: &str) {
+            self.writer.write_all(format!("{}\n", line).as_bytes()).await.unwrap();
+        }
+
+        async fn request(&mut self, id: u64, method: &str, params: Value) -> Value {
+            self.send(&json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params}).to_string())
+                .await;
+            let response = self.receive().await;
+            assert_eq!(response["id"], id);
+            response
+        }
+
+        async fn receive(&mut self) -> Value {
+            let line = self.lines.next_line().await.unwrap().unwrap();
+            serde_json::from_str(&line).unwrap()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handshake_and_tools() {
+        let mut client = Client::start();
+        let init = client
+            .request(1, "initialize", json!({"protocolVersion": "2024-11-05", "capabilities": {}, "clientInfo": {"name": "test", "version": "0"}}))
+            .await;
+        assert_eq!(init["result"]["protocolVersion"], "2024-11-05");
+        assert!(init["result"]["capabilities"]["tools"].is_object());
+        assert_eq!(init["result"]["serverInfo"]["name"], "kael-os");
+
+        // The notification gets no reply, so the next line answers the ping
+        client
+            .send(r#"{"jsonrpc": "2.0", "method": "notifications/initialized"}"#)
+            .await;
+        assert_eq!(client.request(2, "ping", Value::Null).await["result"], json!({}));
+
+        let listed = client.request(3, "tools/list", json!({})).await;
+        let listed = listed["result"]["tools"].as_array().unwrap().clone();
+        assert_eq!(listed.len(), tools::TOOLS.len());
+        for tool in &listed {
+            assert!(tool["name"].is_string());
+            assert!(tool["description"].is_string());
+            assert_eq!(tool["inputSchema"]["type"], "object");
+        }
+
+        let added = client
+            .request(
+                4,
+                "tools/call",
+                json!({"name": "add_project", "arguments": {"name": "kael-notes"}}),
+            )
+            .await;
+        assert_eq!(added["result"]["isError"], false);
+        assert_eq!(added["result"]["content"][0]["type"], "text");
+        assert_eq!(added["result"]["structuredContent"]["name"], "kael-notes");
+
+        let projects = client.request(5, "tools/call", json!({"name": "list_projects"})).await;
+        let text = projects["result"]["content"][0]["text"].as_str().unwrap();
+        let projects: Value = serde_json::from_str(text).unwrap();
+        assert_eq!(projects[0]["name"], "kael-notes");
+
+        let found = client
+            .request(
+                6,
+                "tools/call",
+                json!({"name": "search_history", "arguments": {"query": "nothing"}}),
+            )
+            .await;
+        assert_eq!(found["result"]["structuredContent"]["messages"], json!([]));
+    }
+
+    #[tokio::test]
+    async fn test_errors() {
+        let mut client = Client::start();
+        let unknown_version = client
+            .request(1, "initialize", json!({"protocolVersion": "1999-01-01"}))
+            .await;
+        assert_eq!(unknown_version["result"]["protocolVersion"], PROTOCOL_VERSIONS[0]);
+
+        client.send("{not json").await;
+        let parse = client.receive().await;
+        assert_eq!(parse["error"]["code"], PARSE_ERROR);
+        assert_eq!(parse["id"], Value::Null);
+
+        let method = client.request(2, "resources/list", json!({})).await;
+        assert_eq!(method["error"]["code"], METHOD_NOT_FOUND);
+
+        let tool = client
+            .request(3, "tools/call", json!({"name": "rm_rf", "arguments": {}}))
+            .await;
+        assert_eq!(tool["error"]["code"], INVALID_PARAMS);
+
+        let arguments = client
+            .request(
+                4,
+                "tools/call",
+                json!({"name": "rewrite_command", "arguments": {"cmd": "ls"}}),
+            )
+            .await;
+        assert_eq!(arguments["error"]["code"], INVALID_PARAMS);
+
+        // A tool that ran and failed is a result, not a protocol error
+        let failed = client
+            .request(
+                5,
+                "tools/call",
+                json!({"name": "try_command", "arguments": {"command": "true", "workdir": "/nonexistent/kael"}}),
+            )
+            .await;
+        assert_eq!(failed["result"]["isError"], true);
+
+        // Responses from the client are ignored
+        client.send(r#"{"jsonrpc": "2.0", "id": 9, "result": {}}"#).await;
+        assert_eq!(client.request(6, "ping", Value::Null).await["result"], json!({}));
+    }
+}
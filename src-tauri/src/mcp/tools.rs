@@ -0,0 +1,183 @@
+// Tools Kael offers to MCP clients. Most are thin wrappers around the local
+// API's methods; `try_command` runs in the command sandbox.
+use std::path::PathBuf;
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::api::handlers::{self, ApiContext, RpcError, INVALID_PARAMS, METHOD_NOT_FOUND};
+
+pub struct Tool {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// API method the arguments are passed to; `None` for tools handled here
+    method: Option<&'static str>,
+    schema: fn() -> Value,
+}
+
+impl Tool {
+    /// Entry for `tools/list`
+    pub fn describe(&self) -> Value {
+        json!({
+            "name": self.name,
+            "description": self.description,
+            "inputSchema": (self.schema)(),
+        })
+    }
+}
+
+fn no_arguments() -> Value {
+    json!({ "type": "object", "properties": {} })
+}
+
+pub const TOOLS: &[Tool] = &[
+    Tool {
+        name: "system_context",
+        description: "Hardware, OS, package manager and installed local models of this Arch Linux machine",
+        method: Some("system.context"),
+        schema: no_arguments,
+    },
+    Tool {
+        name: "rewrite_command",
+        description: "Adapt a shell command to this system (AUR helper, shell, network interface, GPU driver) and list the corrections",
+        method: Some("command.rewrite"),
+        schema: || {
+            json!({
+                "type": "object",
+                "properties": { "command": { "type": "string" } },
+                "required": ["command"],
+            })
+        },
+    },
+    Tool {
+        name: "translate_command",
+        description: "Turn a plain-language request into a single shell command for this system",
+        method: Some("command.translate"),
+        schema: || {
+            json!({
+                "type": "object",
+                "properties": { "request": { "type": "string", "description": "What the user wants done" } },
+                "required": ["request"],
+            })
+        },
+    },
+    Tool {
+        name: "try_command",
+        description: "Run a shell command in a sandbox (read-only system, throwaway home, no network) and report its output and the file changes it would make. Nothing is applied.",
+        method: None,
+        schema: || {
+            json!({
+                "type": "object",
+                "properties": {
+                    "command": { "type": "string" },
+                    "workdir": { "type": "string", "description": "Directory the command may change; defaults to the server's working directory" },
+                },
+                "required": ["command"],
+            })
+        },
+    },
+    Tool {
+        name: "search_history",
+        description: "Search Kael's past conversations and messages",
+        method: Some("history.search"),
+        schema: || {
+            json!({
+                "type": "object",
+                "properties": {
+                    "query": { "type": "string" },
+                    "limit": { "type": "integer", "minimum": 1, "default": 20 },
+                },
+                "required": ["query"],
+            })
+        },
+    },
+    Tool {
+        name: "list_projects",
+        description: "Projects in Kael's tracker",
+        method: Some("projects.list"),
+        schema: || {
+            json!({
+                "type": "object",
+                "properties": {
+                    "archived": { "type": "boolean", "description": "true for archived only, false for active only; omit for all" },
+                },
+            })
+        },
+    },
+    Tool {
+        name: "add_project",
+        description: "Add a project to Kael's tracker",
+        method: Some("projects.create"),
+        schema: || {
+            json!({
+                "type": "object",
+                "properties": {
+                    "name": { "type": "string" },
+                    "description": { "type": "string" },
+                    "status": { "type": "string", "enum": ["want", "making", "testing", "done"] },
+                },
+                "required": ["name"],
+            })
+        },
+    },
+];
+
+#[derive(Deserialize)]
+struct TryParams {
+    command: String,
+    #[serde(default)]
+    workdir: Option<PathBuf>,
+}
+
+async fn try_command(arguments: Value) -> Result<Value, RpcError> {
+    let p: TryParams = serde_json::from_value(arguments).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))?;
+    let workdir = match p.workdir {
+        Some(dir) => dir,
+        None => std::env::current_dir().map_err(|e| RpcError::from(e.to_string()))?,
+    };
+    let run = crate::terminal::TerminalManager::new()
+        .run_sandboxed(&p.command, &workdir)
+        .await?;
+    let changes: Vec<Value> = run
+        .changes
+        .iter()
+        .map(|c| json!({ "path": c.path, "kind": format!("{:?}", c.kind).to_lowercase() }))
+        .collect();
+    Ok(json!({
+        "exit_code": run.exit.code,
+        "success": run.exit.success,
+        "timed_out": run.timed_out,
+        "output": run.output,
+        "changes": changes,
+        "diff": run.diff(),
+    }))
+}
+
+/// Run tool `name`. Protocol problems (unknown tool, bad arguments) are
+/// errors; a tool that ran and failed is an `Ok` result with `isError`.
+pub async fn call(ctx: &ApiContext, name: &str, arguments: Value) -> Result<Value, RpcError> {
+    let tool = TOOLS
+        .iter()
+        .find(|t| t.name == name)
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("Unknown tool: {}", name)))?;
+    let arguments = if arguments.is_null() { json!({}) } else { arguments };
+    let result = match tool.method {
+        Some(method) => handlers::dispatch(ctx, method, arguments).await,
+        None => try_command(arguments).await,
+    };
+    match result {
+        Ok(value) => {
+            let text = serde_json::to_string_pretty(&value).unwrap_or_default();
+            Ok(json!({
+                "content": [{ "type": "text", "text": text }],
+                "structuredContent": value,
+                "isError": false,
+            }))
+        }
+        Err(e) if e.code == INVALID_PARAMS || e.code == METHOD_NOT_FOUND => Err(e),
+        Err(e) => Ok(json!({
+            "content": [{ "type": "text", "text": e.message }],
+            "isError": true,
+        })),
+    }
+}
@@ -1,183 +1,517 @@
-#![allow(dead_code)]
+//! Loopback redirect server for OAuth sign-in (RFC 8252).
+//!
+//! Each sign-in is an `OAuthFlow`: a listener on `127.0.0.1` with a port
+//! picked by the OS, a random `state` and a PKCE verifier (RFC 7636, S256).
+//! The provider sends the browser to
+//! `http://127.0.0.1:<port>/auth/<provider>/callback?code=..&state=..` and
+//! `wait` answers that request with a result page, then hands back the code
+//! together with the verifier needed to redeem it. Requests carrying another
+//! `state` are refused without ending the flow, and connections are served
+//! side by side so an idle one can't hold up the redirect. The listener
+//! closes and the state is forgotten as soon as the flow has an outcome,
+//! times out or is dropped.
 
-use once_cell::sync::Lazy;
 use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use once_cell::sync::Lazy;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
 
-#[derive(Clone, Debug)]
-pub struct OAuthCallback {
-    pub code: Option<String>,
-    pub error: Option<String>,
+/// How long a sign-in may take before the flow gives up
+pub const FLOW_TIMEOUT: Duration = Duration::from_secs(300);
+/// Redirects are a bare GET; anything longer is not one
+const MAX_REQUEST: usize = 8 * 1024;
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A code returned by the provider, with what is needed to redeem it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuthorizationCode {
     pub provider: String,
+    pub code: String,
+    pub redirect_uri: String,
+    pub code_verifier: String,
 }
 
-pub struct OAuthServer {
-    callback_data: Arc<Mutex<HashMap<String, OAuthCallback>>>,
+struct Pending {
+    provider: String,
+    redirect_uri: String,
+    code_verifier: String,
 }
 
-pub static OAUTH_SERVER: Lazy<OAuthServer> = Lazy::new(OAuthServer::new);
+/// Flows started and not yet finished, by `state`
+static PENDING: Lazy<Mutex<HashMap<String, Pending>>> = Lazy::new(|| Mutex::new(HashMap::new()));
 
-impl OAuthServer {
-    pub fn new() -> Self {
-        Self {
-            callback_data: Arc::new(Mutex::new(HashMap::new())),
-        }
+fn random_token(bytes: usize) -> String {
+    let mut buf = vec![0u8; bytes];
+    rand::thread_rng().fill_bytes(&mut buf);
+    URL_SAFE_NO_PAD.encode(buf)
+}
+
+/// S256 code challenge for a PKCE verifier
+pub fn code_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+/// Trade a callback's `state` for the flow it belongs to. Each state is
+/// accepted once, and only for the provider that started the flow.
+pub fn redeem(provider: &str, state: &str, code: String) -> Result<AuthorizationCode, String> {
+    let mut pending = PENDING
+        .lock()
+        .map_err(|_| "OAuth state lock poisoned".to_string())?;
+    if pending.get(state).map(|p| p.provider.as_str()) != Some(provider) {
+        return Err("Unknown or expired sign-in (state mismatch)".to_string());
     }
+    let flow = pending.remove(state).expect("checked above");
+    Ok(AuthorizationCode {
+        provider: flow.provider,
+        code,
+        redirect_uri: flow.redirect_uri,
+        code_verifier: flow.code_verifier,
+    })
+}
 
-    async fn run_server(&self) {
-        let callback_data = Arc::clone(&self.callback_data);
+fn forget(state: &str) {
+    if let Ok(mut pending) = PENDING.lock() {
+        pending.remove(state);
+    }
+}
 
-        let listener = match tokio::net::TcpListener::bind("127.0.0.1:5173").await {
-            Ok(l) => l,
-            Err(e) => {
-                log::error!("Failed to bind OAuth server: {}", e);
-                return;
-            }
-        };
+/// One sign-in waiting for its redirect
+pub struct OAuthFlow {
+    pub provider: String,
+    pub state: String,
+    pub redirect_uri: String,
+    pub code_challenge: String,
+    listener: TcpListener,
+}
 
-        log::info!("OAuth callback server listening on 127.0.0.1:5173");
+impl OAuthFlow {
+    /// Listen for `provider`'s redirect on a free loopback port
+    pub async fn start(provider: &str) -> Result<Self, String> {
+        let listener = TcpListener::bind(("127.0.0.1", 0))
+            .await
+            .map_err(|e| format!("Failed to start OAuth callback server: {}", e))?;
+        let port = listener.local_addr().map_err(|e| e.to_string())?.port();
+        let redirect_uri = format!("http://127.0.0.1:{}/auth/{}/callback", port, provider);
+        let state = random_token(16);
+        let code_verifier = random_token(32);
+        let code_challenge = code_challenge(&code_verifier);
+        PENDING
+            .lock()
+            .map_err(|_| "OAuth state lock poisoned".to_string())?
+            .insert(
+                state.clone(),
+                Pending {
+                    provider: provider.to_string(),
+                    redirect_uri: redirect_uri.clone(),
+                    code_verifier,
+                },
+            );
+        log::info!(
+            "OAuth callback server for {} listening on {}",
+            provider,
+            redirect_uri
+        );
+        Ok(Self {
+            provider: provider.to_string(),
+            state,
+            redirect_uri,
+            code_challenge,
+            listener,
+        })
+    }
 
+    /// Serve the redirect and return its authorization code, or give up
+    /// after `timeout`. The flow is over either way.
+    pub async fn wait(self, timeout: Duration) -> Result<AuthorizationCode, String> {
+        tokio::time::timeout(timeout, self.serve())
+            .await
+            .unwrap_or_else(|_| Err(format!("{} sign-in timed out", self.provider)))
+    }
+
+    async fn serve(&self) -> Result<AuthorizationCode, String> {
+        // Dropping the set when the flow ends closes the other connections
+        let mut connections = tokio::task::JoinSet::new();
         loop {
-            match listener.accept().await {
-                Ok((socket, _)) => {
-                    let data = Arc::clone(&callback_data);
-                    tokio::spawn(handle_connection(socket, data));
+            tokio::select! {
+                accepted = self.listener.accept() => {
+                    let (socket, _) =
+                        accepted.map_err(|e| format!("OAuth callback server failed: {}", e))?;
+                    let (provider, state) = (self.provider.clone(), self.state.clone());
+                    connections.spawn(async move { handle(&provider, &state, socket).await });
                 }
-                Err(e) => {
-                    log::error!("OAuth server accept error: {}", e);
+                Some(handled) = connections.join_next() => {
+                    if let Ok(Some(outcome)) = handled {
+                        return outcome;
+                    }
                 }
             }
         }
     }
+}
 
-    pub async fn get_callback(&self, provider: &str) -> Option<OAuthCallback> {
-        let data = self.callback_data.lock().await;
-        data.get(provider).cloned()
+/// A cancelled sign-in can no longer be completed
+impl Drop for OAuthFlow {
+    fn drop(&mut self) {
+        forget(&self.state);
     }
+}
 
-    pub async fn clear_callback(&self, provider: &str) {
-        let mut data = self.callback_data.lock().await;
-        data.remove(provider);
+/// Answer one connection to `provider`'s flow; `Some` once the flow has an
+/// outcome
+async fn handle(
+    provider: &str,
+    state: &str,
+    mut socket: TcpStream,
+) -> Option<Result<AuthorizationCode, String>> {
+    let socket = &mut socket;
+    let request = match tokio::time::timeout(READ_TIMEOUT, read_request(socket)).await {
+        Ok(Ok(request)) => request,
+        Ok(Err(status)) => {
+            respond(
+                socket,
+                status,
+                &page(false, "Bad request", "Kael could not read this request."),
+            )
+            .await;
+            return None;
+        }
+        Err(_) => return None,
+    };
+    if request.path != format!("/auth/{}/callback", provider) {
+        respond(
+            socket,
+            404,
+            &page(false, "Not found", "Nothing to see here."),
+        )
+        .await;
+        return None;
+    }
+    if request.method != "GET" {
+        respond(
+            socket,
+            405,
+            &page(false, "Method not allowed", "Sign-in redirects use GET."),
+        )
+        .await;
+        return None;
+    }
+    // Anything without this flow's state was not started by this sign-in
+    if request.query.get("state").map(String::as_str) != Some(state) {
+        log::warn!(
+            "OAuth callback for {} with a mismatched state ignored",
+            provider
+        );
+        let body = page(
+            false,
+            "Sign-in not recognised",
+            "This sign-in response does not belong to the sign-in Kael is waiting for. Start again from Kael.",
+        );
+        respond(socket, 400, &body).await;
+        return None;
     }
-}
 
-/// Start the OAuth server in a background thread with its own Tokio runtime
-pub fn start_oauth_server() {
-    std::thread::spawn(|| {
-        let rt = tokio::runtime::Runtime::new().expect("Failed to create Tokio runtime");
-        rt.block_on(async {
-            let server = &*OAUTH_SERVER;
-            server.run_server().await;
-        });
-    });
+    let outcome = if let Some(error) = request.query.get("error") {
+        let detail = request.query.get("error_description").unwrap_or(error);
+        Err(format!("{} sign-in failed: {}", provider, detail))
+    } else {
+        match request.query.get("code").filter(|c| !c.is_empty()) {
+            Some(code) => redeem(provider, state, code.clone()),
+            None => Err(format!(
+                "{} sign-in returned no authorization code",
+                provider
+            )),
+        }
+    };
+    let body = match &outcome {
+        Ok(_) => page(
+            true,
+            "Signed in",
+            "You can close this window and return to Kael.",
+        ),
+        Err(e) => page(false, "Sign-in failed", e),
+    };
+    respond(socket, 200, &body).await;
+    Some(outcome)
 }
 
-async fn handle_connection(
-    mut socket: tokio::net::TcpStream,
-    callback_data: Arc<Mutex<HashMap<String, OAuthCallback>>>,
-) {
-    use tokio::io::{AsyncReadExt, AsyncWriteExt};
-
-    let mut buf = vec![0; 4096];
-    match socket.read(&mut buf).await {
-        Ok(n) if n > 0 => {
-            let request = String::from_utf8_lossy(&buf[..n]);
-            log::info!(
-                "OAuth server received request ({}bytes): {}",
-                n,
-                request.lines().next().unwrap_or("")
-            );
-
-            // Parse the request line
-            if let Some(request_line) = request.lines().next() {
-                if request_line.contains("/auth/google/callback") {
-                    log::info!("Handling Google OAuth callback");
-                    handle_oauth_callback(&request, "google", &callback_data).await;
-                } else if request_line.contains("/auth/github/callback") {
-                    log::info!("Handling GitHub OAuth callback");
-                    handle_oauth_callback(&request, "github", &callback_data).await;
-                } else {
-                    log::debug!("Received non-callback request: {}", request_line);
-                }
-            }
+struct Request {
+    method: String,
+    path: String,
+    query: HashMap<String, String>,
+}
 
-            // Send response
-            let response = b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: 120\r\n\r\n<html><body><p>Authentication successful!</p><p>You can close this window and return to the app.</p></body></html>";
-            let _ = socket.write_all(response).await;
+/// Read the request head; a redirect has no body
+async fn read_request(socket: &mut TcpStream) -> Result<Request, u16> {
+    let mut head = Vec::new();
+    let mut chunk = [0u8; 1024];
+    loop {
+        let n = socket.read(&mut chunk).await.map_err(|_| 400u16)?;
+        if n == 0 {
+            return Err(400);
         }
-        _ => {
-            log::debug!("OAuth server: no data received");
+        head.extend_from_slice(&chunk[..n]);
+        if let Some(end) = head.windows(4).position(|w| w == b"\r\n\r\n") {
+            head.truncate(end);
+            return parse_request(&head);
+        }
+        if head.len() > MAX_REQUEST {
+            return Err(431);
         }
     }
 }
 
-async fn handle_oauth_callback(
-    request: &str,
-    provider: &str,
-    callback_data: &Arc<Mutex<HashMap<String, OAuthCallback>>>,
-) {
-    // Extract code or error from query string
-    let code = extract_param(request, "code");
-    let error = extract_param(request, "error");
-    let error_description = extract_param(request, "error_description");
-
-    if code.is_some() {
-        log::info!("OAuth callback received for {}: code present", provider);
-    } else if error.is_some() {
-        log::warn!(
-            "OAuth callback error for {}: {:?} - {:?}",
-            provider,
-            error,
-            error_description
-        );
-    } else {
-        log::warn!(
-            "OAuth callback received but no code or error for {}",
-            provider
-        );
+fn parse_request(head: &[u8]) -> Result<Request, u16> {
+    let head = std::str::from_utf8(head).map_err(|_| 400u16)?;
+    let line = head.lines().next().unwrap_or_default();
+    let parts: Vec<&str> = line.split(' ').collect();
+    let [method, target, version] = parts.as_slice() else {
+        return Err(400);
+    };
+    if !version.starts_with("HTTP/1.") || !target.starts_with('/') {
+        return Err(400);
+    }
+    let url = url::Url::parse(&format!("http://127.0.0.1{}", target)).map_err(|_| 400u16)?;
+    let mut query = HashMap::new();
+    for (key, value) in url.query_pairs() {
+        // A repeated parameter is ambiguous; the first one counts
+        query
+            .entry(key.into_owned())
+            .or_insert_with(|| value.into_owned());
     }
+    Ok(Request {
+        method: method.to_string(),
+        path: url.path().to_string(),
+        query,
+    })
+}
 
-    let callback = OAuthCallback {
-        code,
-        error,
-        provider: provider.to_string(),
+async fn respond(socket: &mut TcpStream, status: u16, body: &str) {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        431 => "Request Header Fields Too Large",
+        _ => "Error",
     };
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nCache-Control: no-store\r\nReferrer-Policy: no-referrer\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        body.len(),
+        body
+    );
+    let _ = socket.write_all(response.as_bytes()).await;
+    let _ = socket.shutdown().await;
+    // Lingering close: closing with unread input would reset the connection
+    // and could discard the response before the client reads it
+    let mut sink = [0u8; 1024];
+    let drain = async {
+        let mut total = 0;
+        while let Ok(n) = socket.read(&mut sink).await {
+            total += n;
+            if n == 0 || total > MAX_REQUEST {
+                break;
+            }
+        }
+    };
+    let _ = tokio::time::timeout(Duration::from_millis(250), drain).await;
+}
 
-    let mut data = callback_data.lock().await;
-    data.insert(provider.to_string(), callback);
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
 
-    log::info!("OAuth callback stored for provider: {}", provider);
+/// Result page in Kael's colours
+fn page(ok: bool, title: &str, message: &str) -> String {
+    let (icon, accent) = if ok {
+        ("✓", "#7aebbe")
+    } else {
+        ("✕", "#ff6b6b")
+    };
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>Kael – {title}</title>
+<style>
+  body {{ margin: 0; min-height: 100vh; display: flex; align-items: center; justify-content: center;
+         background: #0f0b1a; color: #f7f2ff; font-family: system-ui, sans-serif; }}
+  main {{ max-width: 28rem; padding: 2rem; border-radius: 16px; border: 1px solid #3a2d56; text-align: center;
+         background: linear-gradient(150deg, #1c162b 0%, #120e1a 55%, #0f0c1a 100%); }}
+  .icon {{ font-size: 2.5rem; color: {accent}; }}
+  h1 {{ font-size: 1.25rem; color: #ffcc00; }}
+  p {{ color: #cbd5ff; line-height: 1.5; }}
+</style>
+</head>
+<body>
+<main>
+  <div class="icon">{icon}</div>
+  <h1>{title}</h1>
+  <p>{message}</p>
+</main>
+</body>
+</html>
+"#,
+        title = escape_html(title),
+        message = escape_html(message),
+        icon = icon,
+        accent = accent,
+    )
 }
 
-fn extract_param(request: &str, param: &str) -> Option<String> {
-    // Look for param=value in the request
-    let search = format!("{}=", param);
-    if let Some(start) = request.find(&search) {
-        let value_start = start + search.len();
-        let remaining = &request[value_start..];
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        // Find the end of the parameter (either & or space)
-        let value_end = remaining
-            .find(|c: char| c == '&' || c == ' ')
-            .unwrap_or(remaining.len());
+    /// Send `request` to the flow's port and return the status and body
+    async fn send(redirect_uri: &str, request: &str) -> (u16, String) {
+        let url = url::Url::parse(redirect_uri).unwrap();
+        let mut socket = TcpStream::connect(("127.0.0.1", url.port().unwrap()))
+            .await
+            .unwrap();
+        socket.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        socket.read_to_string(&mut response).await.unwrap();
+        let status = response[9..12].parse().unwrap();
+        let body = response.split_once("\r\n\r\n").unwrap().1.to_string();
+        (status, body)
+    }
+
+    async fn get(redirect_uri: &str, path_and_query: &str) -> (u16, String) {
+        send(
+            redirect_uri,
+            &format!("GET {} HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n", path_and_query),
+        )
+        .await
+    }
 
-        let raw_value = &remaining[..value_end];
+    /// Plays the provider: checks the authorize URL, then redirects back
+    /// with `query` appended to the redirect URI
+    async fn fake_provider(
+        authorize_url: &str,
+        query: impl Fn(&str) -> String,
+    ) -> (u16, String, String) {
+        let url = url::Url::parse(authorize_url).unwrap();
+        let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+        assert_eq!(params["response_type"], "code");
+        assert_eq!(params["code_challenge_method"], "S256");
+        let redirect = url::Url::parse(&params["redirect_uri"]).unwrap();
+        assert_eq!(redirect.host_str(), Some("127.0.0.1"));
+        let target = format!("{}?{}", redirect.path(), query(&params["state"]));
+        let (status, body) = get(&params["redirect_uri"], &target).await;
+        (status, body, params["code_challenge"].clone())
+    }
 
-        // URL decode the value
-        let decoded = urlencoding::decode(raw_value)
-            .ok()
-            .map(|cow| cow.into_owned())
-            .unwrap_or_else(|| raw_value.to_string());
+    #[tokio::test]
+    async fn test_pkce_flow() {
+        let flow = OAuthFlow::start("google").await.unwrap();
+        let authorize_url = crate::auth::get_google_oauth_url(&flow).unwrap();
+        let redirect_uri = flow.redirect_uri.clone();
+        let state = flow.state.clone();
+        let waiting = tokio::spawn(flow.wait(Duration::from_secs(5)));
 
-        log::debug!("Extracted param {}: {} -> {}", param, raw_value, decoded);
+        // An idle connection, like a browser preconnect, doesn't hold up the rest
+        let port = url::Url::parse(&redirect_uri).unwrap().port().unwrap();
+        let _idle = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
 
-        if decoded.is_empty() {
-            None
-        } else {
-            Some(decoded)
-        }
-    } else {
-        None
+        // Stray requests are answered without ending the flow
+        assert_eq!(get(&redirect_uri, "/favicon.ico").await.0, 404);
+        let (status, body) = get(
+            &redirect_uri,
+            "/auth/google/callback?code=evil&state=forged",
+        )
+        .await;
+        assert_eq!(status, 400);
+        assert!(body.contains("does not belong"));
+        assert_eq!(
+            get(&redirect_uri, "/auth/google/callback?code=evil")
+                .await
+                .0,
+            400
+        );
+        let post = format!(
+            "POST /auth/google/callback?code=evil&state={} HTTP/1.1\r\n\r\n",
+            state
+        );
+        assert_eq!(send(&redirect_uri, &post).await.0, 405);
+        assert_eq!(send(&redirect_uri, "garbage\r\n\r\n").await.0, 400);
+        let huge = format!("GET /{} HTTP/1.1\r\n", "a".repeat(MAX_REQUEST + 1));
+        assert_eq!(send(&redirect_uri, &huge).await.0, 431);
+
+        let (status, body, challenge) = fake_provider(&authorize_url, |state| {
+            format!("code=4%2F0AX-code&state={}&scope=email", state)
+        })
+        .await;
+        assert_eq!(status, 200);
+        assert!(body.contains("Signed in"));
+
+        let grant = waiting.await.unwrap().unwrap();
+        assert_eq!(grant.provider, "google");
+        assert_eq!(grant.code, "4/0AX-code");
+        assert_eq!(grant.redirect_uri, redirect_uri);
+        assert_eq!(code_challenge(&grant.code_verifier), challenge);
+        assert!(grant.code_verifier.len() >= 43);
+
+        // One shot: the state is spent and the listener is gone
+        assert!(redeem("google", &state, "again".to_string()).is_err());
+        assert!(TcpStream::connect(("127.0.0.1", port)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_provider_error_and_timeout() {
+        let flow = OAuthFlow::start("github").await.unwrap();
+        let authorize_url = crate::auth::get_github_oauth_url(&flow).unwrap();
+        let waiting = tokio::spawn(flow.wait(Duration::from_secs(10)));
+        let (status, body, _) = fake_provider(&authorize_url, |state| {
+            format!(
+                "error=access_denied&error_description=<b>User+said+no</b>&state={}",
+                state
+            )
+        })
+        .await;
+        assert_eq!(status, 200);
+        assert!(body.contains("&lt;b&gt;User said no&lt;/b&gt;"));
+        assert_eq!(
+            waiting.await.unwrap().unwrap_err(),
+            "github sign-in failed: <b>User said no</b>"
+        );
+
+        let flow = OAuthFlow::start("google").await.unwrap();
+        let state = flow.state.clone();
+        assert!(flow
+            .wait(Duration::from_millis(50))
+            .await
+            .unwrap_err()
+            .contains("timed out"));
+        assert!(redeem("google", &state, "late".to_string()).is_err());
+
+        // Cancelling a sign-in forgets its state
+        let flow = OAuthFlow::start("google").await.unwrap();
+        let state = flow.state.clone();
+        let waiting = tokio::spawn(flow.wait(Duration::from_secs(10)));
+        waiting.abort();
+        assert!(waiting.await.unwrap_err().is_cancelled());
+        assert!(redeem("google", &state, "late".to_string()).is_err());
+
+        // A state only works for the provider that started the flow
+        let flow = OAuthFlow::start("google").await.unwrap();
+        assert!(redeem("github", &flow.state, "x".to_string()).is_err());
+        assert_eq!(
+            redeem("google", &flow.state, "x".to_string()).unwrap().code,
+            "x"
+        );
     }
 }
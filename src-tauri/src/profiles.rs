@@ -0,0 +1,390 @@
+//! Profiles: separate Kael identities on one machine.
+//!
+//! Each profile has its own data directory, and with it its own database
+//! (settings, chat history, scripts), key vault, signed-in user and
+//! recordings. The `default` profile lives directly in
+//! `~/.local/share/kael-os`, where everything was kept before profiles
+//! existed; the others live in `~/.local/share/kael-os/profiles/<id>`. The
+//! list and the active profile are kept in `profiles.json`.
+//!
+//! A `Local` profile never talks to the cloud: no sign-in, no Firestore and
+//! no cloud LLM providers, so Kael stays fully usable on air-gapped machines.
+//!
+//! The profile is chosen once per process, from `KAEL_PROFILE` or else the
+//! active one in `profiles.json`. Switching profiles means restarting.
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_ID: &str = "default";
+const REGISTRY_FILE: &str = "profiles.json";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProfileKind {
+    /// Can sign in and sync through Firebase
+    Cloud,
+    /// Never leaves this machine
+    Local,
+}
+
+impl ProfileKind {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "cloud" => Some(Self::Cloud),
+            "local" => Some(Self::Local),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Cloud => "cloud",
+            Self::Local => "local",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Profile {
+    pub id: String,
+    pub name: String,
+    pub kind: ProfileKind,
+}
+
+impl Profile {
+    pub fn is_local(&self) -> bool {
+        self.kind == ProfileKind::Local
+    }
+
+    fn default_profile() -> Self {
+        Self {
+            id: DEFAULT_ID.to_string(),
+            name: "Default".to_string(),
+            kind: ProfileKind::Cloud,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct RegistryFile {
+    active: String,
+    profiles: Vec<Profile>,
+}
+
+/// The profiles under one root directory
+pub struct Registry {
+    root: PathBuf,
+    active: String,
+    profiles: Vec<Profile>,
+}
+
+impl Registry {
+    /// Read `profiles.json` under `root`; a missing or unreadable file means
+    /// only the default profile. Entries whose id is not a slug as `create`
+    /// makes them are dropped, so an id can never leave `root/profiles`.
+    pub fn load(root: &Path) -> Self {
+        let file = std::fs::read_to_string(root.join(REGISTRY_FILE))
+            .ok()
+            .and_then(|json| match serde_json::from_str::<RegistryFile>(&json) {
+                Ok(file) => Some(file),
+                Err(e) => {
+                    log::warn!("Ignoring unreadable {}: {}", REGISTRY_FILE, e);
+                    None
+                }
+            });
+        let (active, mut profiles) = match file {
+            Some(file) => (file.active, file.profiles),
+            None => (DEFAULT_ID.to_string(), Vec::new()),
+        };
+        profiles.retain(|p| {
+            let valid = is_slug(&p.id);
+            if !valid {
+                log::warn!("Ignoring profile with invalid id {:?}", p.id);
+            }
+            valid
+        });
+        if !profiles.iter().any(|p| p.id == DEFAULT_ID) {
+            profiles.insert(0, Profile::default_profile());
+        }
+        let active = if profiles.iter().any(|p| p.id == active) {
+            active
+        } else {
+            DEFAULT_ID.to_string()
+        };
+        Self {
+            root: root.to_path_buf(),
+            active,
+            profiles,
+        }
+    }
+
+    fn save(&self) -> Result<(), String> {
+        std::fs::create_dir_all(&self.root)
+            .map_err(|e| format!("Failed to create {:?}: {}", self.root, e))?;
+        let file = RegistryFile {
+            active: self.active.clone(),
+            profiles: self.profiles.clone(),
+        };
+        let json = serde_json::to_string_pretty(&file).map_err(|e| e.to_string())?;
+        std::fs::write(self.root.join(REGISTRY_FILE), json)
+            .map_err(|e| format!("Failed to save profiles: {}", e))
+    }
+
+    pub fn list(&self) -> &[Profile] {
+        &self.profiles
+    }
+
+    /// A profile by id or (case-insensitive) name
+    pub fn get(&self, id_or_name: &str) -> Option<&Profile> {
+        self.profiles
+            .iter()
+            .find(|p| p.id == id_or_name)
+            .or_else(|| {
+                self.profiles
+                    .iter()
+                    .find(|p| p.name.eq_ignore_ascii_case(id_or_name))
+            })
+    }
+
+    /// The profile started when none is named
+    pub fn active(&self) -> &Profile {
+        self.get(&self.active)
+            .expect("active profile is registered")
+    }
+
+    pub fn data_dir(&self, profile: &Profile) -> PathBuf {
+        if profile.id == DEFAULT_ID {
+            self.root.clone()
+        } else {
+            self.root.join("profiles").join(&profile.id)
+        }
+    }
+
+    /// Add a profile with its own, empty data directory
+    pub fn create(&mut self, name: &str, kind: ProfileKind) -> Result<Profile, String> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err("Profile name is empty".to_string());
+        }
+        if self.get(name).is_some() {
+            return Err(format!("A profile named '{}' already exists", name));
+        }
+        let slug: String = name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_lowercase()
+                } else {
+                    '-'
+                }
+            })
+            .collect::<String>()
+            .split('-')
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join("-");
+        let base = if slug.is_empty() {
+            "profile".to_string()
+        } else {
+            slug
+        };
+        let mut id = base.clone();
+        let mut n = 2;
+        while self.profiles.iter().any(|p| p.id == id) {
+            id = format!("{}-{}", base, n);
+            n += 1;
+        }
+
+        let profile = Profile {
+            id,
+            name: name.to_string(),
+            kind,
+        };
+        let dir = self.data_dir(&profile);
+        std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {:?}: {}", dir, e))?;
+        self.profiles.push(profile.clone());
+        self.save()?;
+        Ok(profile)
+    }
+
+    /// Make `id_or_name` the profile started from now on
+    pub fn set_active(&mut self, id_or_name: &str) -> Result<Profile, String> {
+        let profile = self
+            .get(id_or_name)
+            .cloned()
+            .ok_or_else(|| format!("No profile named '{}'", id_or_name))?;
+        self.active = profile.id.clone();
+        self.save()?;
+        Ok(profile)
+    }
+}
+
+/// Ids are lowercase ASCII letters, digits and inner hyphens
+fn is_slug(id: &str) -> bool {
+    !id.is_empty()
+        && !id.starts_with('-')
+        && !id.ends_with('-')
+        && id
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+/// Where Kael keeps its data: `~/.local/share/kael-os`
+pub fn root_dir() -> PathBuf {
+    let home = std::env::var("HOME").unwrap_or_else(|_| "/tmp".to_string());
+    PathBuf::from(home)
+        .join(".local")
+        .join("share")
+        .join("kael-os")
+}
+
+/// The profiles on this machine
+pub fn registry() -> Registry {
+    Registry::load(&root_dir())
+}
+
+struct Active {
+    profile: Profile,
+    dir: PathBuf,
+}
+
+static ACTIVE: OnceLock<Active> = OnceLock::new();
+
+fn choose(id_or_name: Option<&str>) -> Result<Active, String> {
+    let registry = registry();
+    let profile = match id_or_name {
+        Some(name) => registry
+            .get(name)
+            .ok_or_else(|| format!("No profile named '{}'", name))?,
+        None => registry.active(),
+    };
+    Ok(Active {
+        profile: profile.clone(),
+        dir: registry.data_dir(profile),
+    })
+}
+
+/// Run this process as `id_or_name`. Only possible before the profile is
+/// first used.
+pub fn select(id_or_name: &str) -> Result<&'static Profile, String> {
+    let chosen = choose(Some(id_or_name))?;
+    let id = chosen.profile.id.clone();
+    let active = ACTIVE.get_or_init(|| chosen);
+    if active.profile.id != id {
+        return Err(format!(
+            "Already running as profile '{}'",
+            active.profile.name
+        ));
+    }
+    Ok(&active.profile)
+}
+
+fn active_entry() -> &'static Active {
+    ACTIVE.get_or_init(|| {
+        let from_env = std::env::var("KAEL_PROFILE").ok().filter(|p| !p.is_empty());
+        choose(from_env.as_deref()).unwrap_or_else(|e| {
+            log::warn!("{}; using the default profile", e);
+            choose(None).expect("the active profile is always registered")
+        })
+    })
+}
+
+/// The profile this process runs as
+pub fn active() -> &'static Profile {
+    &active_entry().profile
+}
+
+/// The active profile's data directory, created if needed
+pub fn data_dir() -> PathBuf {
+    let dir = active_entry().dir.clone();
+    let _ = std::fs::create_dir_all(&dir);
+    dir
+}
+
+pub fn is_local() -> bool {
+    active().is_local()
+}
+
+/// Refuse cloud access in a local-only profile
+pub fn require_cloud() -> Result<(), String> {
+    if is_local() {
+        Err(format!(
+            "'{}' is a local-only profile; cloud features are off",
+            active().name
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registry() {
+        let root = std::env::temp_dir().join(format!("kael-profiles-{}", uuid::Uuid::new_v4()));
+
+        // Before profiles.json exists there is just the default, in the root
+        let mut registry = Registry::load(&root);
+        assert_eq!(registry.list().len(), 1);
+        assert_eq!(registry.active().id, DEFAULT_ID);
+        assert_eq!(registry.data_dir(registry.active()), root);
+
+        let lab = registry
+            .create("Air-gapped Lab", ProfileKind::Local)
+            .unwrap();
+        assert_eq!(lab.id, "air-gapped-lab");
+        assert!(lab.is_local());
+        assert_eq!(
+            registry.data_dir(&lab),
+            root.join("profiles/air-gapped-lab")
+        );
+        assert!(registry.data_dir(&lab).is_dir());
+        assert!(registry
+            .create("air-gapped lab", ProfileKind::Cloud)
+            .is_err());
+        assert!(registry.create("  ", ProfileKind::Cloud).is_err());
+        let work = registry
+            .create("Air gapped: lab!", ProfileKind::Cloud)
+            .unwrap();
+        assert_eq!(work.id, "air-gapped-lab-2");
+
+        registry.set_active("air-gapped lab").unwrap();
+        assert!(registry.set_active("nope").is_err());
+
+        let reloaded = Registry::load(&root);
+        assert_eq!(reloaded.active(), &lab);
+        assert_eq!(reloaded.list().len(), 3);
+        assert_eq!(reloaded.get("AIR-GAPPED LAB"), Some(&lab));
+
+        // Ids that would escape root/profiles are dropped
+        let tampered = r#"{"active": "../x", "profiles": [
+            {"id": "../x", "name": "Escape", "kind": "cloud"},
+            {"id": "/etc", "name": "Root", "kind": "cloud"},
+            {"id": "air-gapped-lab", "name": "Air-gapped Lab", "kind": "local"}
+        ]}"#;
+        std::fs::write(root.join(REGISTRY_FILE), tampered).unwrap();
+        let reloaded = Registry::load(&root);
+        assert_eq!(reloaded.active().id, DEFAULT_ID);
+        let ids: Vec<&str> = reloaded.list().iter().map(|p| p.id.as_str()).collect();
+        assert_eq!(ids, vec![DEFAULT_ID, "air-gapped-lab"]);
+
+        // A broken file falls back to the default profile
+        std::fs::write(root.join(REGISTRY_FILE), "{").unwrap();
+        assert_eq!(Registry::load(&root).active().id, DEFAULT_ID);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_kind_names() {
+        for kind in [ProfileKind::Cloud, ProfileKind::Local] {
+            assert_eq!(ProfileKind::parse(kind.as_str()), Some(kind));
+        }
+        assert_eq!(ProfileKind::parse("offline"), None);
+    }
+}
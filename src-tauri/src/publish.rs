@@ -0,0 +1,1045 @@
+//! Releases from one manifest: build, package, checksum, sign and upload.
+//!
+//! `release.json` names the build command, the files to ship and where to
+//! ship them. A release reads its version from `version.json`, packages and
+//! checksums every artifact, signs it with GPG, writes the updater manifest
+//! (`updater::VersionInfo`) and uploads everything to each target. The
+//! updater manifest goes up last, once every target has the files it
+//! points to.
+//!
+//! Progress is kept in `<out_dir>/.publish-state.json`, so running again
+//! after a failure skips what was already signed and uploaded with the same
+//! checksum. Archives are packed with a fixed order, owner and time, so a
+//! rebuild of the same binary gives the same checksum. A dry run packages and
+//! checksums, but builds, signs and uploads nothing.
+
+use std::collections::{BTreeMap, HashSet};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::firebase::uploader::FirebaseUploader;
+use crate::github::uploader::{GitHubRelease, GitHubUploader};
+use crate::updater::{PlatformInfo, PlatformReleases, VersionInfo};
+use crate::version::Version;
+use crate::webdav::{WebDavClient, WebDavConfig};
+
+/// File in the output directory recording what was signed and uploaded
+const STATE_FILE: &str = ".publish-state.json";
+
+/// What to release and where. Relative paths are resolved against the
+/// manifest's directory; `{version}` (e.g. `0.5.0-beta.2`) and `{semver}`
+/// (`0.5.0`) are replaced in artifact paths and names and in the GitHub tag.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReleaseManifest {
+    #[serde(default = "default_version_file")]
+    pub version_file: PathBuf,
+    /// Where archives, signatures and the updater manifest are written
+    #[serde(default = "default_out_dir")]
+    pub out_dir: PathBuf,
+    /// Command that builds the artifacts, run from the manifest's directory
+    #[serde(default)]
+    pub build: Vec<String>,
+    pub artifacts: Vec<ArtifactSpec>,
+    #[serde(default)]
+    pub sign: Option<SignConfig>,
+    /// Release notes; the version's description when unset
+    #[serde(default)]
+    pub changelog: Option<String>,
+    /// Name the updater manifest is written and uploaded under
+    #[serde(default = "default_update_manifest")]
+    pub update_manifest: String,
+    /// The first target with a download URL hosts the files the updater
+    /// fetches; the others are listed as its mirrors
+    pub targets: Vec<Target>,
+}
+
+fn default_version_file() -> PathBuf {
+    PathBuf::from("version.json")
+}
+
+fn default_out_dir() -> PathBuf {
+    PathBuf::from("dist")
+}
+
+fn default_update_manifest() -> String {
+    "latest.json".to_string()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArtifactSpec {
+    /// `windows`, `linux`, `macos` or `android`. Artifacts without one are
+    /// uploaded but left out of the updater manifest.
+    #[serde(default)]
+    pub platform: Option<String>,
+    pub path: String,
+    /// Name to upload as; the file name of `path` when unset
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Pack `path` into a `.tar.gz` first
+    #[serde(default)]
+    pub archive: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SignConfig {
+    /// GPG secret key to sign with
+    pub key_id: String,
+}
+
+/// Where releases are uploaded. Secrets come from the environment:
+/// `GITHUB_TOKEN` and `WEBDAV_PASSWORD`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Target {
+    Github {
+        owner: String,
+        repo: String,
+        #[serde(default = "default_tag")]
+        tag: String,
+    },
+    Firebase {
+        bucket: String,
+        #[serde(default = "default_service_account")]
+        service_account: PathBuf,
+        /// Folder in the bucket
+        #[serde(default)]
+        path: String,
+    },
+    Webdav {
+        url: String,
+        username: String,
+        /// Folder on the server
+        #[serde(default)]
+        path: String,
+        /// Where the folder is served over HTTPS, if anywhere
+        #[serde(default)]
+        public_url: Option<String>,
+    },
+}
+
+fn default_tag() -> String {
+    "v{version}".to_string()
+}
+
+fn default_service_account() -> PathBuf {
+    PathBuf::from("firebase-service-account.json")
+}
+
+/// Replace `{version}` and `{semver}` in `template`
+fn expand(template: &str, version: &Version) -> String {
+    template
+        .replace("{version}", &version.to_string())
+        .replace("{semver}", &version.semver())
+}
+
+/// `name` inside the remote folder `dir`
+fn join(dir: &str, name: &str) -> String {
+    let dir = dir.trim_end_matches('/');
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+impl Target {
+    /// Name the target's uploads are recorded under
+    pub fn label(&self) -> String {
+        match self {
+            Target::Github { owner, repo, .. } => format!("github:{}/{}", owner, repo),
+            Target::Firebase { bucket, path, .. } => format!("firebase:{}", join(bucket, path)),
+            Target::Webdav { url, path, .. } => {
+                format!("webdav:{}", join(url, path.trim_start_matches('/')))
+            }
+        }
+    }
+
+    /// Public URL of the folder the files can be downloaded from
+    pub fn download_base(&self, version: &Version) -> Option<String> {
+        match self {
+            Target::Github { owner, repo, tag } => Some(format!(
+                "https://github.com/{}/{}/releases/download/{}",
+                owner,
+                repo,
+                expand(tag, version)
+            )),
+            Target::Firebase { bucket, path, .. } => Some(join(
+                &format!("https://storage.googleapis.com/{}", bucket),
+                path.trim_matches('/'),
+            )),
+            Target::Webdav { public_url, .. } => public_url
+                .as_ref()
+                .map(|url| url.trim_end_matches('/').to_string()),
+        }
+    }
+
+    /// An uploader for this target, with its credentials
+    pub fn connect(&self, base: &Path, version: &Version) -> Result<Box<dyn Publisher>, String> {
+        let publisher: Box<dyn Publisher> = match self {
+            Target::Github { owner, repo, tag } => {
+                let token = secret("GITHUB_TOKEN", self)?;
+                Box::new(GitHubPublisher {
+                    uploader: GitHubUploader::new(owner.clone(), repo.clone(), token),
+                    tag: expand(tag, version),
+                    release: None,
+                })
+            }
+            Target::Firebase {
+                bucket,
+                service_account,
+                path,
+            } => {
+                let uploader = FirebaseUploader::new(bucket.clone(), &base.join(service_account))
+                    .map_err(|e| {
+                    format!(
+                        "{}: failed to load the service account: {}",
+                        self.label(),
+                        e
+                    )
+                })?;
+                Box::new(FirebasePublisher {
+                    uploader,
+                    path: path.trim_matches('/').to_string(),
+                })
+            }
+            Target::Webdav {
+                url,
+                username,
+                path,
+                ..
+            } => {
+                let password = secret("WEBDAV_PASSWORD", self)?;
+                Box::new(WebDavPublisher {
+                    client: WebDavClient::new(WebDavConfig {
+                        url: url.clone(),
+                        username: username.clone(),
+                        password,
+                    }),
+                    path: path.clone(),
+                })
+            }
+        };
+        Ok(publisher)
+    }
+}
+
+fn secret(name: &str, target: &Target) -> Result<String, String> {
+    std::env::var(name)
+        .ok()
+        .filter(|value| !value.is_empty())
+        .ok_or_else(|| format!("{}: {} is not set", target.label(), name))
+}
+
+/// Uploads a release's files to one target
+#[async_trait]
+pub trait Publisher: Send {
+    /// Get ready for the release's uploads, e.g. create the GitHub release
+    async fn prepare(&mut self, release: &VersionInfo) -> Result<(), String>;
+    /// Upload `path` as `name`, replacing an earlier file of that name
+    async fn upload(&mut self, path: &Path, name: &str) -> Result<(), String>;
+}
+
+struct GitHubPublisher {
+    uploader: GitHubUploader,
+    tag: String,
+    release: Option<GitHubRelease>,
+}
+
+#[async_trait]
+impl Publisher for GitHubPublisher {
+    async fn prepare(&mut self, release: &VersionInfo) -> Result<(), String> {
+        let name = format!("Kael-OS {}", release.version);
+        let created = self
+            .uploader
+            .create_or_get_release(&self.tag, &name, &release.changelog)
+            .await
+            .map_err(|e| e.to_string())?;
+        self.release = Some(created);
+        Ok(())
+    }
+
+    async fn upload(&mut self, path: &Path, name: &str) -> Result<(), String> {
+        let release = self.release.as_mut().ok_or("GitHub release not created")?;
+        // Assets can't be overwritten, only deleted and uploaded again
+        if let Some(index) = release.assets.iter().position(|asset| asset.name == name) {
+            let asset = release.assets.remove(index);
+            self.uploader
+                .delete_asset(asset.id)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        let release_id = release.id;
+        self.uploader
+            .upload_asset(release_id, path, name)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
+
+struct FirebasePublisher {
+    uploader: FirebaseUploader,
+    path: String,
+}
+
+#[async_trait]
+impl Publisher for FirebasePublisher {
+    async fn prepare(&mut self, _release: &VersionInfo) -> Result<(), String> {
+        Ok(())
+    }
+
+    async fn upload(&mut self, path: &Path, name: &str) -> Result<(), String> {
+        self.uploader
+            .upload_file(path, &join(&self.path, name))
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
+
+struct WebDavPublisher {
+    client: WebDavClient,
+    path: String,
+}
+
+#[async_trait]
+impl Publisher for WebDavPublisher {
+    async fn prepare(&mut self, _release: &VersionInfo) -> Result<(), String> {
+        if self.path.trim_matches('/').is_empty() {
+            return Ok(());
+        }
+        self.client
+            .create_directory(&self.path)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn upload(&mut self, path: &Path, name: &str) -> Result<(), String> {
+        self.client
+            .upload_file(path, &join(&self.path, name))
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PublishOptions {
+    /// Package and checksum only: no build, signing or uploads
+    pub dry_run: bool,
+    /// Use the artifacts already built
+    pub skip_build: bool,
+    /// Sign and upload everything again, ignoring earlier progress
+    pub force: bool,
+}
+
+/// A file of the release, ready to upload
+#[derive(Debug, Clone, Serialize)]
+pub struct Artifact {
+    pub name: String,
+    pub path: PathBuf,
+    pub platform: Option<String>,
+    pub sha256: String,
+    pub size: u64,
+    /// Detached signature, uploaded as `<name>.sig`
+    pub signature: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UploadStatus {
+    Uploaded,
+    /// Already uploaded with the same checksum
+    Unchanged,
+    /// Would be uploaded, in a dry run
+    Planned,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Upload {
+    pub target: String,
+    pub name: String,
+    pub status: UploadStatus,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PublishReport {
+    pub version: String,
+    pub dry_run: bool,
+    pub artifacts: Vec<Artifact>,
+    pub release: VersionInfo,
+    /// Where the updater manifest was written
+    pub update_manifest: PathBuf,
+    pub uploads: Vec<Upload>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PublishState {
+    version: String,
+    /// Checksum each artifact was signed at
+    #[serde(default)]
+    signed: BTreeMap<String, String>,
+    /// Checksum of every file uploaded, by target
+    #[serde(default)]
+    uploaded: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl PublishState {
+    /// The progress of `version`'s release, or a fresh start
+    fn load(path: &Path, version: &str, fresh: bool) -> Self {
+        let saved = std::fs::read_to_string(path)
+            .ok()
+            .and_then(|json| serde_json::from_str::<PublishState>(&json).ok())
+            .filter(|state| !fresh && state.version == version);
+        saved.unwrap_or_else(|| PublishState {
+            version: version.to_string(),
+            ..Default::default()
+        })
+    }
+
+    fn save(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, json).map_err(|e| format!("Failed to save {}: {}", path.display(), e))
+    }
+
+    fn is_uploaded(&self, target: &str, name: &str, sha256: &str) -> bool {
+        self.uploaded
+            .get(target)
+            .and_then(|files| files.get(name))
+            .is_some_and(|done| done == sha256)
+    }
+}
+
+impl ReleaseManifest {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        serde_json::from_str(&json)
+            .map_err(|e| format!("Invalid release manifest {}: {}", path.display(), e))
+    }
+}
+
+/// Lowercase hex SHA-256 and size of a file, as `updater::verify_checksum`
+/// expects them
+pub fn checksum(path: &Path) -> Result<(String, u64), String> {
+    let mut file = std::fs::File::open(path)
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut size = 0u64;
+    loop {
+        let n = file
+            .read(&mut buf)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        size += n as u64;
+    }
+    Ok((format!("{:x}", hasher.finalize()), size))
+}
+
+/// Pack `source` into a gzipped tarball that is the same, byte for byte,
+/// every time it is made from the same file
+fn package(source: &Path, archive: &Path, mtime: DateTime<Utc>) -> Result<(), String> {
+    let dir = source
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let file = source
+        .file_name()
+        .ok_or_else(|| format!("Nothing to package at {}", source.display()))?;
+    let output = Command::new("tar")
+        .args(["--sort=name", "--owner=0", "--group=0", "--numeric-owner"])
+        .arg(format!("--mtime=@{}", mtime.timestamp()))
+        .arg("--use-compress-program=gzip -n")
+        .arg("-cf")
+        .arg(archive)
+        .arg("-C")
+        .arg(dir)
+        .arg(file)
+        .output()
+        .map_err(|e| format!("Failed to run tar: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "Failed to package {}: {}",
+            source.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+fn run_build(command: &[String], dir: &Path) -> Result<(), String> {
+    let (program, args) = command.split_first().ok_or("Empty build command")?;
+    let status = Command::new(program)
+        .args(args)
+        .current_dir(dir)
+        .status()
+        .map_err(|e| format!("Failed to run {}: {}", program, e))?;
+    if !status.success() {
+        return Err(format!("Build failed: {}", command.join(" ")));
+    }
+    Ok(())
+}
+
+/// The release time: the version's timestamp, so rebuilding a version gives
+/// the same archives and manifest
+fn release_time(version: &Version) -> Result<DateTime<Utc>, String> {
+    DateTime::parse_from_rfc3339(&version.timestamp)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|e| format!("Invalid version timestamp {:?}: {}", version.timestamp, e))
+}
+
+fn collect(
+    spec: &ArtifactSpec,
+    base: &Path,
+    out_dir: &Path,
+    version: &Version,
+    released: DateTime<Utc>,
+) -> Result<Artifact, String> {
+    let source = base.join(expand(&spec.path, version));
+    if !source.exists() {
+        return Err(format!("Missing artifact {}", source.display()));
+    }
+    let name = match &spec.name {
+        Some(name) => expand(name, version),
+        None => {
+            let file = source.file_name().unwrap_or_default().to_string_lossy();
+            if spec.archive {
+                format!("{}.tar.gz", file)
+            } else {
+                file.into_owned()
+            }
+        }
+    };
+    let path = if spec.archive {
+        let archive = out_dir.join(&name);
+        package(&source, &archive, released)?;
+        archive
+    } else {
+        source
+    };
+    let (sha256, size) = checksum(&path)?;
+    Ok(Artifact {
+        name,
+        path,
+        platform: spec.platform.clone(),
+        sha256,
+        size,
+        signature: None,
+    })
+}
+
+/// Where a dry run writes the updater manifest: `latest.json` becomes
+/// `latest.dry-run.json`
+fn dry_run_name(name: &str) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, ext)) => format!("{}.dry-run.{}", stem, ext),
+        None => format!("{}.dry-run", name),
+    }
+}
+
+/// The updater manifest for `artifacts`
+fn version_info(
+    manifest: &ReleaseManifest,
+    version: &Version,
+    released: DateTime<Utc>,
+    artifacts: &[Artifact],
+) -> Result<VersionInfo, String> {
+    let bases: Vec<String> = manifest
+        .targets
+        .iter()
+        .filter_map(|target| target.download_base(version))
+        .collect();
+    let (primary, mirrors) = bases
+        .split_first()
+        .ok_or("None of the targets has a public download URL")?;
+
+    let mut platforms = PlatformReleases {
+        windows: None,
+        linux: None,
+        macos: None,
+        android: None,
+    };
+    for artifact in artifacts {
+        let Some(platform) = &artifact.platform else {
+            continue;
+        };
+        let slot = match platform.as_str() {
+            "windows" => &mut platforms.windows,
+            "linux" => &mut platforms.linux,
+            "macos" => &mut platforms.macos,
+            "android" => &mut platforms.android,
+            _ => {
+                return Err(format!(
+                    "Unknown platform {} for {}",
+                    platform, artifact.name
+                ))
+            }
+        };
+        if slot.is_some() {
+            return Err(format!("More than one artifact for {}", platform));
+        }
+        *slot = Some(PlatformInfo {
+            url: format!("{}/{}", primary, artifact.name),
+            sha256: artifact.sha256.clone(),
+            size: artifact.size,
+            mirrors: mirrors.to_vec(),
+            signature_url: artifact
+                .signature
+                .as_ref()
+                .map(|_| format!("{}/{}.sig", primary, artifact.name)),
+        });
+    }
+
+    Ok(VersionInfo {
+        version: version.to_string(),
+        released,
+        changelog: manifest
+            .changelog
+            .clone()
+            .or_else(|| version.description.clone())
+            .unwrap_or_default(),
+        platforms,
+    })
+}
+
+/// Release what the manifest at `path` describes, telling `progress` about
+/// each step
+pub async fn publish(
+    path: &Path,
+    options: &PublishOptions,
+    progress: impl FnMut(&str),
+) -> Result<PublishReport, String> {
+    publish_with(path, options, Target::connect, progress).await
+}
+
+async fn publish_with(
+    path: &Path,
+    options: &PublishOptions,
+    connect: impl Fn(&Target, &Path, &Version) -> Result<Box<dyn Publisher>, String>,
+    mut progress: impl FnMut(&str),
+) -> Result<PublishReport, String> {
+    let manifest = ReleaseManifest::load(path)?;
+    let base = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let version = Version::load(&base.join(&manifest.version_file))
+        .map_err(|e| format!("Failed to load version: {}", e))?;
+    let out_dir = base.join(&manifest.out_dir);
+
+    // Missing credentials fail here, before anything is built or signed
+    let mut publishers = Vec::new();
+    if !options.dry_run {
+        for target in &manifest.targets {
+            publishers.push(connect(target, base, &version)?);
+        }
+    }
+
+    if !manifest.build.is_empty() {
+        if options.dry_run || options.skip_build {
+            progress(&format!("Skipping build: {}", manifest.build.join(" ")));
+        } else {
+            progress(&format!("Building: {}", manifest.build.join(" ")));
+            run_build(&manifest.build, base)?;
+        }
+    }
+
+    std::fs::create_dir_all(&out_dir)
+        .map_err(|e| format!("Failed to create {}: {}", out_dir.display(), e))?;
+    let state_path = out_dir.join(STATE_FILE);
+    let mut state = PublishState::load(&state_path, &version.to_string(), options.force);
+    let released = release_time(&version)?;
+
+    let mut artifacts = Vec::new();
+    let mut names = HashSet::new();
+    for spec in &manifest.artifacts {
+        let mut artifact = collect(spec, base, &out_dir, &version, released)?;
+        if !names.insert(artifact.name.clone()) {
+            return Err(format!("More than one artifact named {}", artifact.name));
+        }
+        if manifest.sign.is_some() {
+            artifact.signature = Some(out_dir.join(format!("{}.sig", artifact.name)));
+        }
+        progress(&format!("{}  {}", artifact.sha256, artifact.name));
+        artifacts.push(artifact);
+    }
+    let release = version_info(&manifest, &version, released, &artifacts)?;
+
+    if let Some(sign) = &manifest.sign {
+        for artifact in &artifacts {
+            let Some(signature) = &artifact.signature else {
+                continue;
+            };
+            let signed =
+                state.signed.get(&artifact.name) == Some(&artifact.sha256) && signature.exists();
+            if options.dry_run {
+                progress(&format!(
+                    "Would sign {} with {}",
+                    artifact.name, sign.key_id
+                ));
+            } else if !signed {
+                progress(&format!("Signing {}", artifact.name));
+                crate::gpg::detach_sign(&artifact.path, signature, &sign.key_id).await?;
+                state
+                    .signed
+                    .insert(artifact.name.clone(), artifact.sha256.clone());
+                state.save(&state_path)?;
+            }
+        }
+    }
+
+    // A dry run's manifest points at signatures that don't exist; keep it
+    // away from the one a real run published
+    let update_manifest = if options.dry_run {
+        out_dir.join(dry_run_name(&manifest.update_manifest))
+    } else {
+        out_dir.join(&manifest.update_manifest)
+    };
+    let json = serde_json::to_string_pretty(&release).map_err(|e| e.to_string())?;
+    std::fs::write(&update_manifest, json)
+        .map_err(|e| format!("Failed to write {}: {}", update_manifest.display(), e))?;
+
+    // Every file with its checksum, or none for signatures a dry run didn't make
+    let with_checksum =
+        |name: String, path: &Path| -> Result<(String, PathBuf, Option<String>), String> {
+            let sha256 = if path.exists() {
+                Some(checksum(path)?.0)
+            } else {
+                None
+            };
+            Ok((name, path.to_path_buf(), sha256))
+        };
+    let mut files = Vec::new();
+    for artifact in &artifacts {
+        files.push(with_checksum(artifact.name.clone(), &artifact.path)?);
+        if let Some(signature) = &artifact.signature {
+            files.push(with_checksum(format!("{}.sig", artifact.name), signature)?);
+        }
+    }
+    let manifest_file = vec![with_checksum(
+        manifest.update_manifest.clone(),
+        &update_manifest,
+    )?];
+
+    let mut uploads = Vec::new();
+    let mut prepared = vec![false; manifest.targets.len()];
+    for batch in [files, manifest_file] {
+        for (index, target) in manifest.targets.iter().enumerate() {
+            let label = target.label();
+            for (name, file, sha256) in &batch {
+                let status = match sha256 {
+                    Some(sha256) if state.is_uploaded(&label, name, sha256) => {
+                        UploadStatus::Unchanged
+                    }
+                    _ if options.dry_run => {
+                        progress(&format!("Would upload {} to {}", name, label));
+                        UploadStatus::Planned
+                    }
+                    Some(sha256) => {
+                        let publisher = &mut publishers[index];
+                        if !prepared[index] {
+                            publisher
+                                .prepare(&release)
+                                .await
+                                .map_err(|e| format!("{}: {}", label, e))?;
+                            prepared[index] = true;
+                        }
+                        progress(&format!("Uploading {} to {}", name, label));
+                        publisher.upload(file, name).await.map_err(|e| {
+                            format!("Failed to upload {} to {}: {}", name, label, e)
+                        })?;
+                        state
+                            .uploaded
+                            .entry(label.clone())
+                            .or_default()
+                            .insert(name.clone(), sha256.clone());
+                        state.save(&state_path)?;
+                        UploadStatus::Uploaded
+                    }
+                    None => return Err(format!("Missing {}", file.display())),
+                };
+                uploads.push(Upload {
+                    target: label.clone(),
+                    name: name.clone(),
+                    status,
+                });
+            }
+        }
+    }
+
+    Ok(PublishReport {
+        version: version.to_string(),
+        dry_run: options.dry_run,
+        artifacts,
+        release,
+        update_manifest,
+        uploads,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::updater::verify_checksum;
+
+    /// Logs `<target> <name>` for each upload, and fails the one named `fail_on`
+    struct FakePublisher {
+        label: String,
+        log: Arc<Mutex<Vec<String>>>,
+        fail_on: Option<String>,
+    }
+
+    #[async_trait]
+    impl Publisher for FakePublisher {
+        async fn prepare(&mut self, release: &VersionInfo) -> Result<(), String> {
+            let entry = format!("{} prepare {}", self.label, release.version);
+            self.log.lock().unwrap().push(entry);
+            Ok(())
+        }
+
+        async fn upload(&mut self, path: &Path, name: &str) -> Result<(), String> {
+            let entry = format!("{} {}", self.label, name);
+            if self.fail_on.as_ref() == Some(&entry) {
+                return Err("connection reset".to_string());
+            }
+            assert!(path.exists());
+            self.log.lock().unwrap().push(entry);
+            Ok(())
+        }
+    }
+
+    fn release_dir(sign: bool) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("kael-publish-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("target/release")).unwrap();
+        let version = json!({
+            "major": 0, "minor": 5, "patch": 0, "stage": "beta", "build": 2,
+            "timestamp": "2026-10-01T12:00:00Z", "description": "Offline sync",
+        });
+        std::fs::write(dir.join("version.json"), version.to_string()).unwrap();
+        std::fs::write(dir.join("target/release/kael-os"), b"\x7fELF kael").unwrap();
+        std::fs::write(dir.join("PKGBUILD"), "pkgname=kael-os").unwrap();
+        let mut manifest = json!({
+            "build": ["false"],
+            "artifacts": [
+                {
+                    "platform": "linux",
+                    "path": "target/release/kael-os",
+                    "name": "kael-os-{semver}-x86_64.tar.gz",
+                    "archive": true,
+                },
+                { "path": "PKGBUILD" },
+            ],
+            "targets": [
+                {
+                    "type": "webdav",
+                    "url": "https://leroyonline.co.za:2078",
+                    "username": "leetheorc",
+                    "path": "/public_html/kael/downloads/desktop",
+                    "public_url": "https://leroyonline.co.za/kael/downloads/desktop",
+                },
+                { "type": "github", "owner": "LeeTheOrc", "repo": "kael-os" },
+            ],
+        });
+        if sign {
+            manifest["sign"] = json!({ "key_id": "D0513E222E8EE8D7" });
+        }
+        std::fs::write(dir.join("release.json"), manifest.to_string()).unwrap();
+        dir
+    }
+
+    async fn run(
+        dir: &Path,
+        options: &PublishOptions,
+        log: &Arc<Mutex<Vec<String>>>,
+        fail_on: Option<&str>,
+    ) -> Result<PublishReport, String> {
+        let connect =
+            |target: &Target, _: &Path, _: &Version| -> Result<Box<dyn Publisher>, String> {
+                Ok(Box::new(FakePublisher {
+                    label: target.label(),
+                    log: log.clone(),
+                    fail_on: fail_on.map(str::to_string),
+                }))
+            };
+        publish_with(&dir.join("release.json"), options, connect, |_| {}).await
+    }
+
+    fn count(report: &PublishReport, status: UploadStatus) -> usize {
+        report.uploads.iter().filter(|u| u.status == status).count()
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_plans_the_release() {
+        let dir = release_dir(true);
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let dry_run = PublishOptions {
+            dry_run: true,
+            ..Default::default()
+        };
+        // The build command would fail if it ran
+        let report = run(&dir, &dry_run, &log, None).await.unwrap();
+        assert!(log.lock().unwrap().is_empty());
+        assert_eq!(report.version, "0.5.0-beta.2");
+        // Two artifacts and their signatures, then the manifest, on both targets
+        assert_eq!(count(&report, UploadStatus::Planned), 10);
+        assert!(!dir.join("dist/kael-os-0.5.0-x86_64.tar.gz.sig").exists());
+
+        let archive = &report.artifacts[0];
+        assert_eq!(archive.path, dir.join("dist/kael-os-0.5.0-x86_64.tar.gz"));
+        let data = std::fs::read(&archive.path).unwrap();
+        assert!(verify_checksum(&data, &archive.sha256).unwrap());
+        assert_eq!(report.artifacts[1].path, dir.join("PKGBUILD"));
+
+        assert_eq!(report.update_manifest, dir.join("dist/latest.dry-run.json"));
+        assert!(!dir.join("dist/latest.json").exists());
+        let written: VersionInfo = serde_json::from_str(
+            &std::fs::read_to_string(dir.join("dist/latest.dry-run.json")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(written.version, "0.5.0-beta.2");
+        assert_eq!(written.changelog, "Offline sync");
+        assert_eq!(written.released.to_rfc3339(), "2026-10-01T12:00:00+00:00");
+        let linux = written.platforms.linux.unwrap();
+        assert_eq!(
+            linux.url,
+            "https://leroyonline.co.za/kael/downloads/desktop/kael-os-0.5.0-x86_64.tar.gz"
+        );
+        assert_eq!(linux.sha256, archive.sha256);
+        assert_eq!(linux.size, data.len() as u64);
+        assert_eq!(
+            linux.mirrors,
+            vec![
+                "https://github.com/LeeTheOrc/kael-os/releases/download/v0.5.0-beta.2".to_string()
+            ]
+        );
+        assert_eq!(linux.signature_url, Some(format!("{}.sig", linux.url)));
+        assert!(written.platforms.windows.is_none());
+
+        // The archive doesn't depend on when the binary was written
+        let binary = std::fs::File::options()
+            .write(true)
+            .open(dir.join("target/release/kael-os"))
+            .unwrap();
+        binary
+            .set_modified(
+                std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(86_400),
+            )
+            .unwrap();
+        let again = run(&dir, &dry_run, &log, None).await.unwrap();
+        assert_eq!(again.artifacts[0].sha256, archive.sha256);
+
+        // A version without a usable timestamp can't be released reproducibly
+        let mut version: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(dir.join("version.json")).unwrap())
+                .unwrap();
+        version["timestamp"] = json!("yesterday");
+        std::fs::write(dir.join("version.json"), version.to_string()).unwrap();
+        let error = run(&dir, &dry_run, &log, None).await.unwrap_err();
+        assert!(error.starts_with("Invalid version timestamp"), "{}", error);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_publish_resumes_after_a_failed_upload() {
+        let dir = release_dir(false);
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let options = PublishOptions {
+            skip_build: true,
+            ..Default::default()
+        };
+        let webdav = "webdav:https://leroyonline.co.za:2078/public_html/kael/downloads/desktop";
+        let github = "github:LeeTheOrc/kael-os";
+        let tarball = "kael-os-0.5.0-x86_64.tar.gz";
+
+        let error = run(&dir, &options, &log, Some(&format!("{} PKGBUILD", github)))
+            .await
+            .unwrap_err();
+        assert!(error.starts_with("Failed to upload PKGBUILD"), "{}", error);
+        assert_eq!(
+            log.lock().unwrap().drain(..).collect::<Vec<_>>(),
+            vec![
+                format!("{} prepare 0.5.0-beta.2", webdav),
+                format!("{} {}", webdav, tarball),
+                format!("{} PKGBUILD", webdav),
+                format!("{} prepare 0.5.0-beta.2", github),
+                format!("{} {}", github, tarball),
+            ]
+        );
+
+        // Only what's missing goes up, and the manifests only once every file is there
+        let report = run(&dir, &options, &log, None).await.unwrap();
+        assert_eq!(
+            log.lock().unwrap().drain(..).collect::<Vec<_>>(),
+            vec![
+                format!("{} prepare 0.5.0-beta.2", github),
+                format!("{} PKGBUILD", github),
+                format!("{} prepare 0.5.0-beta.2", webdav),
+                format!("{} latest.json", webdav),
+                format!("{} latest.json", github),
+            ]
+        );
+        assert_eq!(count(&report, UploadStatus::Uploaded), 3);
+        assert_eq!(count(&report, UploadStatus::Unchanged), 3);
+
+        let report = run(&dir, &options, &log, None).await.unwrap();
+        assert!(log.lock().unwrap().is_empty());
+        assert_eq!(count(&report, UploadStatus::Unchanged), 6);
+
+        // A changed file goes up again; --force sends everything
+        std::fs::write(dir.join("PKGBUILD"), "pkgname=kael-os\npkgrel=2").unwrap();
+        run(&dir, &options, &log, None).await.unwrap();
+        assert_eq!(log.lock().unwrap().len(), 4);
+        let forced = PublishOptions {
+            force: true,
+            ..options.clone()
+        };
+        let report = run(&dir, &forced, &log, None).await.unwrap();
+        assert_eq!(count(&report, UploadStatus::Uploaded), 6);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_manifests() {
+        let dir = release_dir(false);
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let dry_run = PublishOptions {
+            dry_run: true,
+            ..Default::default()
+        };
+        let path = dir.join("release.json");
+        let mut manifest = ReleaseManifest::load(&path).unwrap();
+
+        manifest.artifacts[1].platform = Some("beos".to_string());
+        std::fs::write(&path, serde_json::to_string(&manifest).unwrap()).unwrap();
+        let error = run(&dir, &dry_run, &log, None).await.unwrap_err();
+        assert_eq!(error, "Unknown platform beos for PKGBUILD");
+
+        manifest.artifacts[1].platform = None;
+        manifest.targets.truncate(1);
+        if let Target::Webdav { public_url, .. } = &mut manifest.targets[0] {
+            *public_url = None;
+        }
+        std::fs::write(&path, serde_json::to_string(&manifest).unwrap()).unwrap();
+        let error = run(&dir, &dry_run, &log, None).await.unwrap_err();
+        assert_eq!(error, "None of the targets has a public download URL");
+
+        manifest.artifacts[1].path = "missing".to_string();
+        std::fs::write(&path, serde_json::to_string(&manifest).unwrap()).unwrap();
+        let error = run(&dir, &dry_run, &log, None).await.unwrap_err();
+        assert!(error.starts_with("Missing artifact"), "{}", error);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,5 +1,8 @@
 use serde::{Deserialize, Serialize};
 
+use crate::firebase::firestore::Firestore;
+use crate::session;
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct BrainstormIdea {
     pub id: String,
@@ -24,74 +27,48 @@ struct ToggleStarRequest {
     starred: bool,
 }
 
+/// A cached idea in `brainstorm_cache/<id>`, written by the Cloud Functions
+#[derive(Deserialize)]
+struct CachedIdea {
+    #[serde(default = "unknown_category")]
+    category: String,
+    #[serde(default)]
+    prompt: String,
+    #[serde(default)]
+    ideas: String,
+    #[serde(default)]
+    generated_at: Option<String>,
+    #[serde(default)]
+    starred: bool,
+    #[serde(default)]
+    on_demand: bool,
+}
+
+fn unknown_category() -> String {
+    "unknown".to_string()
+}
+
 /// Fetch all brainstorm ideas from Firestore cache
 pub async fn fetch_brainstorm_ideas(user: &crate::auth::User) -> Result<Vec<BrainstormIdea>, String> {
     log::info!("📥 Fetching brainstorm ideas from Firestore...");
-    
-    let url = format!(
-        "https://firestore.googleapis.com/v1/projects/{}/databases/(default)/documents/brainstorm_cache",
-        std::env::var("FIREBASE_PROJECT_ID").unwrap_or_else(|_| "your-project-id".to_string())
-    );
-    
-    let client = reqwest::Client::new();
-    let response = client
-        .get(&url)
-        .header("Authorization", format!("Bearer {}", user.id_token))
-        .send()
+
+    let db = Firestore::for_user(user)?;
+    let ideas: Vec<BrainstormIdea> = db
+        .list::<CachedIdea>("brainstorm_cache")
         .await
-        .map_err(|e| format!("Failed to fetch ideas: {}", e))?;
-    
-    if !response.status().is_success() {
-        let error = response.text().await.unwrap_or_default();
-        return Err(format!("Firestore error: {}", error));
-    }
-    
-    let data: serde_json::Value = response.json().await
-        .map_err(|e| format!("Failed to parse response: {}", e))?;
-    
-    let mut ideas = Vec::new();
-    
-    if let Some(documents) = data.get("documents").and_then(|d| d.as_array()) {
-        for doc in documents {
-            if let Some(fields) = doc.get("fields") {
-                let idea = BrainstormIdea {
-                    id: doc.get("name")
-                        .and_then(|n| n.as_str())
-                        .map(|s| s.split('/').last().unwrap_or("").to_string())
-                        .unwrap_or_default(),
-                    category: fields.get("category")
-                        .and_then(|f| f.get("stringValue"))
-                        .and_then(|v| v.as_str())
-                        .unwrap_or("unknown")
-                        .to_string(),
-                    prompt: fields.get("prompt")
-                        .and_then(|f| f.get("stringValue"))
-                        .and_then(|v| v.as_str())
-                        .unwrap_or("")
-                        .to_string(),
-                    ideas: fields.get("ideas")
-                        .and_then(|f| f.get("stringValue"))
-                        .and_then(|v| v.as_str())
-                        .unwrap_or("")
-                        .to_string(),
-                    generated_at: fields.get("generated_at")
-                        .and_then(|f| f.get("timestampValue"))
-                        .and_then(|v| v.as_str())
-                        .map(|s| s.to_string()),
-                    starred: fields.get("starred")
-                        .and_then(|f| f.get("booleanValue"))
-                        .and_then(|v| v.as_bool())
-                        .unwrap_or(false),
-                    on_demand: fields.get("on_demand")
-                        .and_then(|f| f.get("booleanValue"))
-                        .and_then(|v| v.as_bool())
-                        .unwrap_or(false),
-                };
-                ideas.push(idea);
-            }
-        }
-    }
-    
+        .map_err(|e| format!("Failed to fetch ideas: {}", e))?
+        .into_iter()
+        .map(|doc| BrainstormIdea {
+            id: doc.id,
+            category: doc.data.category,
+            prompt: doc.data.prompt,
+            ideas: doc.data.ideas,
+            generated_at: doc.data.generated_at,
+            starred: doc.data.starred,
+            on_demand: doc.data.on_demand,
+        })
+        .collect();
+
     log::info!("✅ Fetched {} brainstorm ideas", ideas.len());
     Ok(ideas)
 }
@@ -104,8 +81,7 @@ pub async fn request_new_ideas(
 ) -> Result<BrainstormIdea, String> {
     log::info!("🎯 Requesting new {} ideas...", category);
     
-    let project_id = std::env::var("FIREBASE_PROJECT_ID")
-        .unwrap_or_else(|_| "your-project-id".to_string());
+    let project_id = crate::firebase::project_id()?;
     let region = "us-central1"; // Change if deployed to different region
     
     let url = format!(
@@ -119,13 +95,11 @@ pub async fn request_new_ideas(
     };
     
     let client = reqwest::Client::new();
-    let response = client
-        .post(&url)
-        .header("Authorization", format!("Bearer {}", user.id_token))
-        .json(&request)
-        .send()
-        .await
-        .map_err(|e| format!("Failed to call function: {}", e))?;
+    let response = session::authorized(&user.uid, &user.id_token, |token| {
+        client.post(&url).bearer_auth(token).json(&request)
+    })
+    .await
+    .map_err(|e| format!("Failed to call function: {}", e))?;
     
     if !response.status().is_success() {
         let error = response.text().await.unwrap_or_default();
@@ -166,8 +140,7 @@ pub async fn toggle_star_idea(
 ) -> Result<(), String> {
     log::info!("⭐ {} idea {}...", if starred { "Starring" } else { "Unstarring" }, idea_id);
     
-    let project_id = std::env::var("FIREBASE_PROJECT_ID")
-        .unwrap_or_else(|_| "your-project-id".to_string());
+    let project_id = crate::firebase::project_id()?;
     let region = "us-central1";
     
     let url = format!(
@@ -181,13 +154,11 @@ pub async fn toggle_star_idea(
     };
     
     let client = reqwest::Client::new();
-    let response = client
-        .post(&url)
-        .header("Authorization", format!("Bearer {}", user.id_token))
-        .json(&request)
-        .send()
-        .await
-        .map_err(|e| format!("Failed to call function: {}", e))?;
+    let response = session::authorized(&user.uid, &user.id_token, |token| {
+        client.post(&url).bearer_auth(token).json(&request)
+    })
+    .await
+    .map_err(|e| format!("Failed to call function: {}", e))?;
     
     if !response.status().is_success() {
         let error = response.text().await.unwrap_or_default();
@@ -198,13 +169,18 @@ pub async fn toggle_star_idea(
     Ok(())
 }
 
+/// Offline copy of the ideas, in the active profile's data dir
+fn cache_path() -> std::path::PathBuf {
+    crate::profiles::data_dir().join("brainstorm_cache.json")
+}
+
 /// Cache ideas locally for offline access
 pub fn cache_ideas_locally(ideas: &[BrainstormIdea]) -> Result<(), String> {
-    let cache_path = "/tmp/kael_brainstorm_cache.json";
+    let cache_path = cache_path();
     let json = serde_json::to_string_pretty(ideas)
         .map_err(|e| format!("Failed to serialize ideas: {}", e))?;
     
-    std::fs::write(cache_path, json)
+    std::fs::write(&cache_path, json)
         .map_err(|e| format!("Failed to write cache: {}", e))?;
     
     log::info!("💾 Cached {} ideas locally", ideas.len());
@@ -213,13 +189,13 @@ pub fn cache_ideas_locally(ideas: &[BrainstormIdea]) -> Result<(), String> {
 
 /// Load cached ideas from local storage
 pub fn load_cached_ideas() -> Result<Vec<BrainstormIdea>, String> {
-    let cache_path = "/tmp/kael_brainstorm_cache.json";
+    let cache_path = cache_path();
     
-    if !std::path::Path::new(cache_path).exists() {
+    if !cache_path.exists() {
         return Ok(Vec::new());
     }
     
-    let json = std::fs::read_to_string(cache_path)
+    let json = std::fs::read_to_string(&cache_path)
         .map_err(|e| format!("Failed to read cache: {}", e))?;
     
     let ideas: Vec<BrainstormIdea> = serde_json::from_str(&json)
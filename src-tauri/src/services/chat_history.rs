@@ -1,288 +1,119 @@
-// Chat History Module - SQLite-based persistence
-use rusqlite::{params, Connection, Result};
-use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+// Chat History Module - conversations persisted through kael-storage
+use kael_storage::{ChatMessage, Session, StorageManager};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Conversation {
-    pub id: i64,
-    pub title: String,
-    pub created_at: String,
-    pub updated_at: String,
-    pub provider: String,
-    pub model: String,
-}
+/// A conversation is a storage session with provider/model labels.
+pub type Conversation = Session;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Message {
-    pub id: i64,
-    pub conversation_id: i64,
-    pub role: String, // "user" or "assistant"
-    pub content: String,
-    pub timestamp: String,
-}
+/// Messages are stored with role "user" or "assistant".
+pub type Message = ChatMessage;
 
 pub struct ChatHistory {
-    db_path: PathBuf,
+    storage: StorageManager,
 }
 
 impl ChatHistory {
-    /// Initialize chat history database
+    /// Use the shared app database in the active profile's data directory
     pub fn new() -> Result<Self, String> {
-        let db_path = Self::get_db_path()?;
-
-        // Create parent directory if needed
-        if let Some(parent) = db_path.parent() {
-            std::fs::create_dir_all(parent)
-                .map_err(|e| format!("Failed to create chat history directory: {}", e))?;
-        }
-
-        let history = ChatHistory { db_path };
-        history.init_database()?;
-        Ok(history)
-    }
-
-    /// Get database file path
-    fn get_db_path() -> Result<PathBuf, String> {
-        let home =
-            std::env::var("HOME").map_err(|_| "HOME environment variable not set".to_string())?;
-        Ok(PathBuf::from(home)
-            .join(".local")
-            .join("share")
-            .join("kael-os")
-            .join("chat_history.db"))
-    }
-
-    /// Get database connection
-    fn get_connection(&self) -> Result<Connection, String> {
-        Connection::open(&self.db_path).map_err(|e| format!("Failed to open database: {}", e))
+        Ok(ChatHistory {
+            storage: crate::db::shared()?,
+        })
     }
 
-    /// Initialize database schema
-    fn init_database(&self) -> Result<(), String> {
-        let conn = self.get_connection()?;
-
-        // Create conversations table
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS conversations (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                title TEXT NOT NULL,
-                created_at TEXT NOT NULL DEFAULT (datetime('now')),
-                updated_at TEXT NOT NULL DEFAULT (datetime('now')),
-                provider TEXT NOT NULL,
-                model TEXT NOT NULL
-            )",
-            [],
-        )
-        .map_err(|e| format!("Failed to create conversations table: {}", e))?;
-
-        // Create messages table
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS messages (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                conversation_id INTEGER NOT NULL,
-                role TEXT NOT NULL CHECK(role IN ('user', 'assistant')),
-                content TEXT NOT NULL,
-                timestamp TEXT NOT NULL DEFAULT (datetime('now')),
-                FOREIGN KEY (conversation_id) REFERENCES conversations(id) ON DELETE CASCADE
-            )",
-            [],
-        )
-        .map_err(|e| format!("Failed to create messages table: {}", e))?;
-
-        // Create indexes for performance
-        conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_messages_conversation 
-             ON messages(conversation_id)",
-            [],
-        )
-        .map_err(|e| format!("Failed to create index: {}", e))?;
-
-        conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_conversations_updated 
-             ON conversations(updated_at DESC)",
-            [],
-        )
-        .map_err(|e| format!("Failed to create index: {}", e))?;
-
-        Ok(())
+    /// Wrap an already-open storage handle (shared app DB or in-memory for tests)
+    pub fn with_storage(storage: StorageManager) -> Self {
+        ChatHistory { storage }
     }
 
     /// Create a new conversation
-    pub fn create_conversation(
+    pub async fn create_conversation(
         &self,
         title: &str,
         provider: &str,
         model: &str,
-    ) -> Result<i64, String> {
-        let conn = self.get_connection()?;
-        conn.execute(
-            "INSERT INTO conversations (title, provider, model) VALUES (?1, ?2, ?3)",
-            params![title, provider, model],
-        )
-        .map_err(|e| format!("Failed to create conversation: {}", e))?;
-
-        Ok(conn.last_insert_rowid())
+    ) -> Result<String, String> {
+        let session = self
+            .storage
+            .sessions()
+            .create(title, Some(provider), Some(model))
+            .await
+            .map_err(|e| format!("Failed to create conversation: {}", e))?;
+
+        Ok(session.id)
     }
 
     /// Add a message to a conversation
-    pub fn add_message(
+    pub async fn add_message(
         &self,
-        conversation_id: i64,
+        conversation_id: &str,
         role: &str,
         content: &str,
-    ) -> Result<i64, String> {
-        let conn = self.get_connection()?;
-
-        // Add message
-        conn.execute(
-            "INSERT INTO messages (conversation_id, role, content) VALUES (?1, ?2, ?3)",
-            params![conversation_id, role, content],
-        )
-        .map_err(|e| format!("Failed to add message: {}", e))?;
-
-        // Update conversation updated_at
-        conn.execute(
-            "UPDATE conversations SET updated_at = datetime('now') WHERE id = ?1",
-            params![conversation_id],
-        )
-        .map_err(|e| format!("Failed to update conversation timestamp: {}", e))?;
-
-        Ok(conn.last_insert_rowid())
+    ) -> Result<String, String> {
+        let message = self
+            .storage
+            .messages()
+            .add(Some(conversation_id), role, content)
+            .await
+            .map_err(|e| format!("Failed to add message: {}", e))?;
+
+        Ok(message.id)
     }
 
     /// Get all conversations (most recent first)
-    pub fn get_conversations(&self) -> Result<Vec<Conversation>, String> {
-        let conn = self.get_connection()?;
-        let mut stmt = conn
-            .prepare(
-                "SELECT id, title, created_at, updated_at, provider, model 
-                      FROM conversations ORDER BY updated_at DESC",
-            )
-            .map_err(|e| format!("Failed to prepare statement: {}", e))?;
-
-        let conversations = stmt
-            .query_map([], |row| {
-                Ok(Conversation {
-                    id: row.get(0)?,
-                    title: row.get(1)?,
-                    created_at: row.get(2)?,
-                    updated_at: row.get(3)?,
-                    provider: row.get(4)?,
-                    model: row.get(5)?,
-                })
-            })
-            .map_err(|e| format!("Failed to query conversations: {}", e))?
-            .collect::<Result<Vec<_>, _>>()
-            .map_err(|e| format!("Failed to collect conversations: {}", e))?;
-
-        Ok(conversations)
+    pub async fn get_conversations(&self) -> Result<Vec<Conversation>, String> {
+        self.storage
+            .sessions()
+            .list()
+            .await
+            .map_err(|e| format!("Failed to query conversations: {}", e))
     }
 
     /// Get messages for a conversation
-    pub fn get_messages(&self, conversation_id: i64) -> Result<Vec<Message>, String> {
-        let conn = self.get_connection()?;
-        let mut stmt = conn
-            .prepare(
-                "SELECT id, conversation_id, role, content, timestamp 
-                 FROM messages WHERE conversation_id = ?1 ORDER BY timestamp ASC",
-            )
-            .map_err(|e| format!("Failed to prepare statement: {}", e))?;
-
-        let messages = stmt
-            .query_map(params![conversation_id], |row| {
-                Ok(Message {
-                    id: row.get(0)?,
-                    conversation_id: row.get(1)?,
-                    role: row.get(2)?,
-                    content: row.get(3)?,
-                    timestamp: row.get(4)?,
-                })
-            })
-            .map_err(|e| format!("Failed to query messages: {}", e))?
-            .collect::<Result<Vec<_>, _>>()
-            .map_err(|e| format!("Failed to collect messages: {}", e))?;
-
-        Ok(messages)
+    pub async fn get_messages(&self, conversation_id: &str) -> Result<Vec<Message>, String> {
+        self.storage
+            .messages()
+            .list_for_session(conversation_id)
+            .await
+            .map_err(|e| format!("Failed to query messages: {}", e))
     }
 
     /// Delete a conversation and its messages
-    pub fn delete_conversation(&self, conversation_id: i64) -> Result<(), String> {
-        let conn = self.get_connection()?;
-        conn.execute(
-            "DELETE FROM conversations WHERE id = ?1",
-            params![conversation_id],
-        )
-        .map_err(|e| format!("Failed to delete conversation: {}", e))?;
-
-        Ok(())
+    pub async fn delete_conversation(&self, conversation_id: &str) -> Result<(), String> {
+        self.storage
+            .sessions()
+            .delete(conversation_id)
+            .await
+            .map_err(|e| format!("Failed to delete conversation: {}", e))
     }
 
     /// Update conversation title
-    pub fn update_title(&self, conversation_id: i64, new_title: &str) -> Result<(), String> {
-        let conn = self.get_connection()?;
-        conn.execute(
-            "UPDATE conversations SET title = ?1, updated_at = datetime('now') WHERE id = ?2",
-            params![new_title, conversation_id],
-        )
-        .map_err(|e| format!("Failed to update title: {}", e))?;
-
-        Ok(())
+    pub async fn update_title(&self, conversation_id: &str, new_title: &str) -> Result<(), String> {
+        self.storage
+            .sessions()
+            .rename(conversation_id, new_title)
+            .await
+            .map_err(|e| format!("Failed to update title: {}", e))
     }
 
     /// Search conversations by title
-    pub fn search_conversations(&self, query: &str) -> Result<Vec<Conversation>, String> {
-        let conn = self.get_connection()?;
-        let mut stmt = conn
-            .prepare(
-                "SELECT id, title, created_at, updated_at, provider, model 
-                 FROM conversations WHERE title LIKE ?1 ORDER BY updated_at DESC",
-            )
-            .map_err(|e| format!("Failed to prepare statement: {}", e))?;
-
-        let search_query = format!("%{}%", query);
-        let conversations = stmt
-            .query_map(params![search_query], |row| {
-                Ok(Conversation {
-                    id: row.get(0)?,
-                    title: row.get(1)?,
-                    created_at: row.get(2)?,
-                    updated_at: row.get(3)?,
-                    provider: row.get(4)?,
-                    model: row.get(5)?,
-                })
-            })
-            .map_err(|e| format!("Failed to query conversations: {}", e))?
-            .collect::<Result<Vec<_>, _>>()
-            .map_err(|e| format!("Failed to collect conversations: {}", e))?;
-
-        Ok(conversations)
+    pub async fn search_conversations(&self, query: &str) -> Result<Vec<Conversation>, String> {
+        self.storage
+            .sessions()
+            .search(query)
+            .await
+            .map_err(|e| format!("Failed to query conversations: {}", e))
     }
 
     /// Export conversation to JSON
-    pub fn export_conversation(&self, conversation_id: i64) -> Result<String, String> {
-        let conn = self.get_connection()?;
-
-        // Get conversation
-        let mut conv_stmt = conn
-            .prepare("SELECT id, title, created_at, updated_at, provider, model FROM conversations WHERE id = ?1")
-            .map_err(|e| format!("Failed to prepare statement: {}", e))?;
+    pub async fn export_conversation(&self, conversation_id: &str) -> Result<String, String> {
+        let conversation = self
+            .storage
+            .sessions()
+            .get(conversation_id)
+            .await
+            .map_err(|e| format!("Failed to get conversation: {}", e))?
+            .ok_or_else(|| format!("Conversation {} not found", conversation_id))?;
 
-        let conversation: Conversation = conv_stmt
-            .query_row(params![conversation_id], |row| {
-                Ok(Conversation {
-                    id: row.get(0)?,
-                    title: row.get(1)?,
-                    created_at: row.get(2)?,
-                    updated_at: row.get(3)?,
-                    provider: row.get(4)?,
-                    model: row.get(5)?,
-                })
-            })
-            .map_err(|e| format!("Failed to get conversation: {}", e))?;
-
-        // Get messages
-        let messages = self.get_messages(conversation_id)?;
+        let messages = self.get_messages(conversation_id).await?;
 
         let export = serde_json::json!({
             "conversation": conversation,
@@ -294,18 +125,12 @@ impl ChatHistory {
     }
 
     /// Get database statistics
-    pub fn get_stats(&self) -> Result<(usize, usize), String> {
-        let conn = self.get_connection()?;
-
-        let conv_count: usize = conn
-            .query_row("SELECT COUNT(*) FROM conversations", [], |row| row.get(0))
-            .map_err(|e| format!("Failed to count conversations: {}", e))?;
-
-        let msg_count: usize = conn
-            .query_row("SELECT COUNT(*) FROM messages", [], |row| row.get(0))
-            .map_err(|e| format!("Failed to count messages: {}", e))?;
-
-        Ok((conv_count, msg_count))
+    pub async fn get_stats(&self) -> Result<(usize, usize), String> {
+        self.storage
+            .messages()
+            .stats()
+            .await
+            .map_err(|e| format!("Failed to count messages: {}", e))
     }
 }
 
@@ -313,31 +138,33 @@ impl ChatHistory {
 mod tests {
     use super::*;
 
-    #[test]
-    fn test_chat_history() {
-        let history = ChatHistory::new().unwrap();
+    #[tokio::test]
+    async fn test_chat_history() {
+        let history = ChatHistory::with_storage(StorageManager::open_in_memory().unwrap());
 
         // Create conversation
         let conv_id = history
             .create_conversation("Test Chat", "ollama", "llama2")
+            .await
             .unwrap();
 
         // Add messages
-        history.add_message(conv_id, "user", "Hello!").unwrap();
+        history.add_message(&conv_id, "user", "Hello!").await.unwrap();
         history
-            .add_message(conv_id, "assistant", "Hi there!")
+            .add_message(&conv_id, "assistant", "Hi there!")
+            .await
             .unwrap();
 
         // Get messages
-        let messages = history.get_messages(conv_id).unwrap();
+        let messages = history.get_messages(&conv_id).await.unwrap();
         assert_eq!(messages.len(), 2);
 
         // Search
-        let results = history.search_conversations("Test").unwrap();
+        let results = history.search_conversations("Test").await.unwrap();
         assert_eq!(results.len(), 1);
 
         // Export
-        let export = history.export_conversation(conv_id).unwrap();
+        let export = history.export_conversation(&conv_id).await.unwrap();
         assert!(export.contains("Test Chat"));
     }
 }
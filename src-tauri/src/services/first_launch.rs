@@ -66,7 +66,7 @@ pub async fn get_or_init_context(app: &AppHandle) -> Result<SystemContext, Strin
 
 /// Refresh system context (e.g., after installing Ollama)
 pub async fn refresh_context(app: &AppHandle) -> Result<SystemContext, String> {
-    println!("🔄 Refreshing system context...");
+    log::info!("🔄 Refreshing system context...");
     
     let mut context = SystemContext::detect().await?;
     
@@ -79,22 +79,16 @@ pub async fn refresh_context(app: &AppHandle) -> Result<SystemContext, String> {
     
     context.save(&path)?;
     
-    println!("✅ System context refreshed");
+    log::info!("✅ System context refreshed");
     Ok(context)
 }
 
 // ==================== STANDALONE FUNCTIONS (for Dioxus Desktop) ====================
 
-/// Get path to system context file (standalone version without AppHandle)
+/// Get path to system context file (standalone version without AppHandle),
+/// in the active profile's data dir
 pub fn get_context_path_standalone() -> PathBuf {
-    let home = std::env::var("HOME")
-        .unwrap_or_else(|_| "/tmp".to_string());
-    
-    PathBuf::from(home)
-        .join(".local")
-        .join("share")
-        .join("kael-os")
-        .join("system_context.json")
+    crate::profiles::data_dir().join("system_context.json")
 }
 
 /// Check if this is the first launch (standalone)
@@ -150,7 +144,7 @@ pub async fn get_or_init_context_standalone() -> Result<SystemContext, String> {
 
 /// Refresh system context (standalone)
 pub async fn refresh_context_standalone() -> Result<SystemContext, String> {
-    println!("🔄 Refreshing system context...");
+    log::info!("🔄 Refreshing system context...");
     
     let mut context = SystemContext::detect().await?;
     
@@ -163,6 +157,6 @@ pub async fn refresh_context_standalone() -> Result<SystemContext, String> {
     
     context.save(&path)?;
     
-    println!("✅ System context refreshed");
+    log::info!("✅ System context refreshed");
     Ok(context)
 }
@@ -1,10 +1,9 @@
 // GPG Key Backup/Restore Module
 // Saves GPG private key to Firebase for OS reinstall recovery
 
-use crate::auth::User;
-use crate::firebase;
-use reqwest::Client;
-use serde_json::json;
+use crate::auth::{SecretMigration, User};
+use crate::firebase::firestore::{self, Firestore, Value};
+use serde::{Deserialize, Serialize};
 use std::process::Command;
 
 /// Export GPG private key
@@ -94,97 +93,67 @@ pub async fn backup_gpg_key(user: &User, key_id: &str) -> Result<(), String> {
     let key_data = export_gpg_key(key_id)?;
 
     // Encrypt the key data before uploading
-    let encrypted_key = crate::auth::encrypt_secret(user, &key_data);
-
-    // Get project ID
-    let project_id = std::env::var("VITE_FIREBASE_PROJECT_ID")
-        .map_err(|_| "Missing VITE_FIREBASE_PROJECT_ID".to_string())?;
-        
-    let url = format!(
-        "https://firestore.googleapis.com/v1/projects/{}/databases/(default)/documents/users/{}/gpg_keys/{}",
-        project_id, user.uid, key_id
-    );
-
-    let payload = json!({
-        "fields": {
-            "key_id": {"stringValue": key_id},
-            "key_data": {"stringValue": encrypted_key},
-            "backed_up_at": {"timestampValue": chrono::Utc::now().to_rfc3339()},
-        }
-    });
-
-    let client = Client::new();
-    let response = client
-        .patch(&url)
-        .bearer_auth(&user.id_token)
-        .query(&[("updateMask.fieldPaths", "key_id")])
-        .query(&[("updateMask.fieldPaths", "key_data")])
-        .query(&[("updateMask.fieldPaths", "backed_up_at")])
-        .json(&payload)
-        .send()
-        .await
-        .map_err(|e| format!("Failed to backup GPG key: {}", e))?;
-
-    if !response.status().is_success() {
-        let status = response.status();
-        let text = response.text().await.unwrap_or_default();
-        return Err(format!("Firestore error ({}): {}", status, text));
-    }
+    let encrypted_key = crate::auth::encrypt_secret(user, &key_data)?;
+    upload_backup(user, key_id, &encrypted_key).await?;
 
     log::info!("✅ GPG key backed up to Firebase");
     Ok(())
 }
 
-/// Restore GPG key from Firebase
-pub async fn restore_gpg_key(user: &User, key_id: &str) -> Result<String, String> {
-    log::info!("🔐 Restoring GPG key: {}", key_id);
+/// A backup in `users/<uid>/gpg_keys/<key id>`
+#[derive(Serialize, Deserialize)]
+struct KeyBackup {
+    key_id: String,
+    /// Encrypted with the user's data key
+    key_data: String,
+}
+
+fn backups_path(user: &User) -> String {
+    format!("users/{}/gpg_keys", user.uid)
+}
 
-    // Get project ID
-    let project_id = std::env::var("VITE_FIREBASE_PROJECT_ID")
-        .map_err(|_| "Missing VITE_FIREBASE_PROJECT_ID".to_string())?;
-        
-    let url = format!(
-        "https://firestore.googleapis.com/v1/projects/{}/databases/(default)/documents/users/{}/gpg_keys/{}",
-        project_id, user.uid, key_id
+/// Write an encrypted key to the user's `gpg_keys` collection
+async fn upload_backup(user: &User, key_id: &str, encrypted_key: &str) -> Result<(), String> {
+    let backup = KeyBackup {
+        key_id: key_id.to_string(),
+        key_data: encrypted_key.to_string(),
+    };
+    let mut fields = firestore::to_fields(&backup)?;
+    fields.insert(
+        "backed_up_at".to_string(),
+        Value::Timestamp(chrono::Utc::now().to_rfc3339()),
     );
 
-    let client = Client::new();
-    let response = client
-        .get(&url)        .bearer_auth(&user.id_token)        .send()
+    Firestore::for_user(user)?
+        .patch_fields(
+            &format!("{}/{}", backups_path(user), key_id),
+            fields,
+            Some(&["key_id", "key_data", "backed_up_at"]),
+        )
         .await
-        .map_err(|e| format!("Failed to restore GPG key: {}", e))?;
-
-    if !response.status().is_success() {
-        let status = response.status();
-        return Err(format!("GPG key not found in backup ({})", status));
-    }
-
-    #[derive(serde::Deserialize)]
-    struct GpgKeyDoc {
-        fields: GpgKeyFields,
-    }
-
-    #[derive(serde::Deserialize)]
-    struct GpgKeyFields {
-        key_data: StringValue,
-    }
+        .map_err(|e| format!("Failed to backup GPG key: {}", e))
+}
 
-    #[derive(serde::Deserialize)]
-    #[allow(non_snake_case)]
-    struct StringValue {
-        stringValue: String,
-    }
+/// Restore GPG key from Firebase
+pub async fn restore_gpg_key(user: &User, key_id: &str) -> Result<String, String> {
+    log::info!("🔐 Restoring GPG key: {}", key_id);
 
-    let doc: GpgKeyDoc = response
-        .json()
+    let doc = Firestore::for_user(user)?
+        .get::<KeyBackup>(&format!("{}/{}", backups_path(user), key_id))
         .await
-        .map_err(|e| format!("Failed to parse GPG key: {}", e))?;
+        .map_err(|e| format!("Failed to restore GPG key: {}", e))?
+        .ok_or_else(|| "GPG key not found in backup".to_string())?;
 
-    let encrypted_key = doc.fields.key_data.stringValue;
+    let encrypted_key = doc.data.key_data;
 
     // Decrypt the key data
-    let key_data = crate::auth::decrypt_secret(user, &encrypted_key)
-        .ok_or_else(|| "Failed to decrypt GPG key".to_string())?;
+    let key_data = crate::auth::decrypt_secret(user, &encrypted_key).ok_or_else(|| {
+        if crate::auth::is_legacy_secret(&encrypted_key) {
+            "Failed to decrypt GPG key: this backup predates your passphrase and your login has changed since; back it up again".to_string()
+        } else {
+            "Failed to decrypt GPG key: unlock your secrets with your passphrase first".to_string()
+        }
+    })?;
 
     // Import the key
     let result = import_gpg_key(&key_data)?;
@@ -195,58 +164,37 @@ pub async fn restore_gpg_key(user: &User, key_id: &str) -> Result<String, String
 
 /// List all backed up GPG keys
 pub async fn list_backed_up_keys(user: &User) -> Result<Vec<String>, String> {
-    let project_id = std::env::var("VITE_FIREBASE_PROJECT_ID")
-        .map_err(|_| "Missing VITE_FIREBASE_PROJECT_ID".to_string())?;
-        
-    let url = format!(
-        "https://firestore.googleapis.com/v1/projects/{}/databases/(default)/documents/users/{}/gpg_keys",
-        project_id, user.uid
-    );
-
-    let client = Client::new();
-    let response = client
-        .get(&url)
-        .bearer_auth(&user.id_token)
-        .send()
+    let backups = Firestore::for_user(user)?
+        .list::<KeyBackup>(&backups_path(user))
         .await
         .map_err(|e| format!("Failed to list GPG keys: {}", e))?;
+    Ok(backups.into_iter().map(|doc| doc.data.key_id).collect())
+}
 
-    if !response.status().is_success() {
-        return Ok(Vec::new()); // No keys backed up yet
-    }
-
-    #[derive(serde::Deserialize)]
-    struct ListResponse {
-        documents: Option<Vec<KeyDoc>>,
-    }
-
-    #[derive(serde::Deserialize)]
-    struct KeyDoc {
-        fields: KeyFields,
-    }
-
-    #[derive(serde::Deserialize)]
-    struct KeyFields {
-        key_id: StringValue,
-    }
-
-    #[derive(serde::Deserialize)]
-    #[allow(non_snake_case)]
-    struct StringValue {
-        stringValue: String,
+/// Re-encrypt backups still stored as legacy XOR blobs. The secrets must be
+/// unlocked; backups whose id_token has rotated are reported, not touched.
+pub async fn migrate_legacy_backups(user: &User) -> Result<SecretMigration, String> {
+    if !crate::auth::secrets_unlocked(user) {
+        return Err("Secrets are locked".to_string());
     }
-
-    let list: ListResponse = response
-        .json()
+    let backups = Firestore::for_user(user)?
+        .list::<KeyBackup>(&backups_path(user))
         .await
-        .map_err(|e| format!("Failed to parse key list: {}", e))?;
-
-    let keys = list
-        .documents
-        .unwrap_or_default()
-        .into_iter()
-        .map(|doc| doc.fields.key_id.stringValue)
-        .collect();
+        .map_err(|e| format!("Failed to list GPG keys: {}", e))?;
 
-    Ok(keys)
+    let mut report = SecretMigration::default();
+    for KeyBackup { key_id, key_data } in backups.into_iter().map(|doc| doc.data) {
+        if !crate::auth::is_legacy_secret(&key_data) {
+            continue;
+        }
+        match crate::auth::decrypt_secret(user, &key_data) {
+            Some(plaintext) => {
+                let encrypted = crate::auth::encrypt_secret(user, &plaintext)?;
+                upload_backup(user, &key_id, &encrypted).await?;
+                report.migrated += 1;
+            }
+            None => report.unreadable.push(key_id),
+        }
+    }
+    Ok(report)
 }
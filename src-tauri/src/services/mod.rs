@@ -8,4 +8,6 @@ pub mod first_launch;
 pub mod gpg_backup;
 pub mod local_ai_startup;
 pub mod ollama_manager;
+pub mod recordings;
+pub mod script_library;
 pub mod system_context;
@@ -0,0 +1,119 @@
+// Terminal recordings: asciicast files under the active profile's data dir
+use std::path::{Path, PathBuf};
+
+use kael_terminal::{Cast, CastEvent};
+
+/// Transcript characters sent to the AI with an attached recording
+const MAX_CONTEXT_CHARS: usize = 6000;
+
+/// A recording on disk, for the terminal panel's list
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordingFile {
+    pub path: PathBuf,
+    pub name: String,
+    pub modified: std::time::SystemTime,
+}
+
+/// Default recordings directory (`recordings` in the active profile's data dir)
+pub fn recordings_dir() -> PathBuf {
+    crate::profiles::data_dir().join("recordings")
+}
+
+/// `<dir>/<session>-<timestamp>.cast`, with the session name made file-safe
+pub fn new_recording_path(dir: &Path, session_name: &str) -> PathBuf {
+    let stem: String = session_name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '-' })
+        .collect();
+    let stem = stem.trim_matches('-');
+    let stem = if stem.is_empty() { "session" } else { stem };
+    let stamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+    dir.join(format!("{}-{}.cast", stem, stamp))
+}
+
+/// `.cast` files in `dir`, newest first
+pub fn list_recordings(dir: &Path) -> Vec<RecordingFile> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<RecordingFile> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "cast"))
+        .filter_map(|path| {
+            let modified = path.metadata().and_then(|m| m.modified()).ok()?;
+            let name = path.file_stem()?.to_string_lossy().to_string();
+            Some(RecordingFile { path, name, modified })
+        })
+        .collect();
+    files.sort_by_key(|f| std::cmp::Reverse(f.modified));
+    files
+}
+
+/// What a recording showed, as context for the AI: the commands typed and
+/// the end of the rendered output.
+pub fn chat_context(path: &Path) -> Result<String, String> {
+    let cast = Cast::load(path).map_err(|e| format!("Failed to load recording: {}", e))?;
+    let typed: String = cast
+        .events
+        .iter()
+        .filter_map(|(_, e)| match e {
+            CastEvent::Input(text) => Some(text.as_str()),
+            _ => None,
+        })
+        .collect();
+    let transcript = cast.transcript();
+    let start = transcript
+        .char_indices()
+        .rev()
+        .nth(MAX_CONTEXT_CHARS)
+        .map_or(0, |(i, _)| i);
+
+    let mut context = format!(
+        "Terminal recording {} ({:.0}s, {}x{})\n",
+        path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default(),
+        cast.duration(),
+        cast.header.width,
+        cast.header.height
+    );
+    let typed = typed.replace('\r', "\n");
+    if !typed.trim().is_empty() {
+        context.push_str(&format!("\nInput typed:\n```\n{}\n```\n", typed.trim_end()));
+    }
+    context.push_str(&format!(
+        "\nScreen output{}:\n```\n{}\n```",
+        if start > 0 { " (last part)" } else { "" },
+        &transcript[start..]
+    ));
+    Ok(context)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paths_and_listing() {
+        let dir = std::env::temp_dir().join(format!("kael-recordings-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = new_recording_path(&dir, "Kael / paru");
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        assert!(name.starts_with("Kael---paru-") && name.ends_with(".cast"), "{}", name);
+
+        std::fs::write(
+            &path,
+            "{\"version\": 2, \"width\": 40, \"height\": 4}\n[0.5, \"i\", \"paru -Syu\\r\"]\n[2.0, \"o\", \"error: failed to commit transaction\\r\\n\"]\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("notes.txt"), "x").unwrap();
+
+        let files = list_recordings(&dir);
+        assert_eq!(files.len(), 1);
+        let context = chat_context(&files[0].path).unwrap();
+        assert!(context.contains("(2s, 40x4)"));
+        assert!(context.contains("Input typed:\n```\nparu -Syu\n```"));
+        assert!(context.contains("error: failed to commit transaction"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,607 @@
+//! Script Library Service
+//! Saves commands and snippets from chat as reusable scripts with `{{name}}` /
+//! `{{name:default}}` placeholders, tags, run history and `.sh` export/import.
+
+use crate::state::Script;
+use crate::terminal::PtyTerminal;
+use kael_storage::{ScriptRun, StorageManager};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+static PLACEHOLDER_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"\{\{\s*([A-Za-z_][A-Za-z0-9_]*)\s*(?::([^}]*))?\}\}").unwrap()
+});
+
+static RUN_MARKER_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\r?\n?\[kael-run ([0-9a-f-]{36}) exit=(\d+)\]\r?\n?").unwrap());
+
+const RUN_MARKER_PREFIX: &str = "[kael-run ";
+
+/// A `{{name}}` or `{{name:default}}` slot in a script body
+#[derive(Debug, Clone, PartialEq)]
+pub struct Placeholder {
+    pub name: String,
+    pub default: Option<String>,
+}
+
+/// List the distinct placeholders of a script body, in order of first use
+pub fn placeholders(content: &str) -> Vec<Placeholder> {
+    let mut found: Vec<Placeholder> = Vec::new();
+    for cap in PLACEHOLDER_RE.captures_iter(content) {
+        let name = cap[1].to_string();
+        if found.iter().any(|p| p.name == name) {
+            continue;
+        }
+        found.push(Placeholder {
+            name,
+            default: cap.get(2).map(|m| m.as_str().trim().to_string()),
+        });
+    }
+    found
+}
+
+/// Substitute placeholders with argument values, escaped for the quotes
+/// around them: a bare placeholder becomes exactly one shell word, and one
+/// inside `"..."` or `'...'` stays inside those quotes. Either way values
+/// cannot inject extra commands.
+pub fn render(content: &str, args: &BTreeMap<String, String>) -> Result<String, String> {
+    let mut missing = Vec::new();
+    let mut rendered = String::with_capacity(content.len());
+    let mut quoting = Quoting::None;
+    let mut last = 0;
+    for cap in PLACEHOLDER_RE.captures_iter(content) {
+        let whole = cap.get(0).expect("group 0 always matches");
+        let before = &content[last..whole.start()];
+        quoting = quoting.after(before);
+        rendered.push_str(before);
+        last = whole.end();
+
+        let name = &cap[1];
+        match args.get(name).map(String::as_str).or(cap.get(2).map(|m| m.as_str().trim())) {
+            Some(value) => rendered.push_str(&quoting.escape(value)),
+            None => {
+                if !missing.contains(&name.to_string()) {
+                    missing.push(name.to_string());
+                }
+            }
+        }
+    }
+    rendered.push_str(&content[last..]);
+
+    if missing.is_empty() {
+        Ok(rendered)
+    } else {
+        Err(format!("Missing value for: {}", missing.join(", ")))
+    }
+}
+
+/// The shell quotes a placeholder sits in
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Quoting {
+    None,
+    Single,
+    Double,
+}
+
+impl Quoting {
+    /// The quoting in effect once the shell has read `text`
+    fn after(self, text: &str) -> Quoting {
+        let mut state = self;
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            state = match (state, c) {
+                (Quoting::Single, '\'') => Quoting::None,
+                (Quoting::Single, _) => Quoting::Single,
+                (_, '\\') => {
+                    chars.next();
+                    state
+                }
+                (Quoting::None, '\'') => Quoting::Single,
+                (Quoting::None, '"') => Quoting::Double,
+                (Quoting::Double, '"') => Quoting::None,
+                _ => state,
+            };
+        }
+        state
+    }
+
+    /// `value` as literal text at this point of a script
+    fn escape(self, value: &str) -> String {
+        match self {
+            Quoting::None => shell_quote(value),
+            Quoting::Single => value.replace('\'', r"'\''"),
+            Quoting::Double => {
+                let mut escaped = String::with_capacity(value.len());
+                for c in value.chars() {
+                    if matches!(c, '\\' | '"' | '$' | '`') {
+                        escaped.push('\\');
+                    }
+                    escaped.push(c);
+                }
+                escaped
+            }
+        }
+    }
+}
+
+/// Quote a value for POSIX shells, leaving plain words untouched
+pub fn shell_quote(value: &str) -> String {
+    let plain = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=@,+%".contains(c));
+    if plain {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', r"'\''"))
+    }
+}
+
+/// Pull a runnable snippet out of a chat message: the first fenced code block
+/// if there is one, otherwise the text with leading `$ ` prompts removed.
+pub fn extract_snippet(text: &str) -> String {
+    if let Some(start) = text.find("```") {
+        let after = &text[start + 3..];
+        // Skip the language tag line (```bash)
+        let body_start = after.find('\n').map(|i| i + 1).unwrap_or(0);
+        let body = &after[body_start..];
+        let body = body.find("```").map(|end| &body[..end]).unwrap_or(body);
+        return body.trim_end().to_string();
+    }
+
+    text.lines()
+        .map(|line| line.trim_start().strip_prefix("$ ").unwrap_or(line))
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
+/// Default script name: the first non-empty line, shortened
+fn default_name(content: &str) -> String {
+    let first = content.lines().find(|l| !l.trim().is_empty()).unwrap_or("script").trim();
+    if first.chars().count() > 40 {
+        format!("{}…", first.chars().take(40).collect::<String>())
+    } else {
+        first.to_string()
+    }
+}
+
+/// Parse a comma/space separated tag list
+pub fn parse_tags(input: &str) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for tag in input.split(|c: char| c == ',' || c.is_whitespace()) {
+        let tag = tag.trim().trim_start_matches('#');
+        if !tag.is_empty() && !tags.iter().any(|t| t.eq_ignore_ascii_case(tag)) {
+            tags.push(tag.to_string());
+        }
+    }
+    tags
+}
+
+/// "Save as script" from a chat message
+pub async fn save_from_chat(
+    storage: &StorageManager,
+    message_text: &str,
+    name: Option<&str>,
+    tags: Vec<String>,
+) -> Result<Script, String> {
+    let content = extract_snippet(message_text);
+    if content.is_empty() {
+        return Err("Nothing to save: message has no command".to_string());
+    }
+
+    let mut script = Script::new(
+        name.map(str::to_string).unwrap_or_else(|| default_name(&content)),
+        content,
+    );
+    script.tags = tags;
+
+    storage
+        .scripts()
+        .save(&script)
+        .await
+        .map_err(|e| format!("Failed to save script: {}", e))?;
+
+    log::info!("Saved script '{}' ({})", script.name, script.id);
+    Ok(script)
+}
+
+// ============================================================================
+// Running scripts in the PTY
+// ============================================================================
+
+/// Directory for per-run script files (tmpfs under XDG_RUNTIME_DIR when available)
+fn run_dir() -> PathBuf {
+    match std::env::var("XDG_RUNTIME_DIR") {
+        Ok(dir) => PathBuf::from(dir).join("kael-os").join("scripts"),
+        Err(_) => crate::profiles::data_dir().join("runs"),
+    }
+}
+
+fn run_file(run_id: &str) -> PathBuf {
+    run_dir().join(format!("{}.sh", run_id))
+}
+
+/// Write the rendered script to a private executable file
+fn write_run_file(path: &Path, body: &str) -> Result<(), String> {
+    use std::os::unix::fs::PermissionsExt;
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("Failed to create run dir: {}", e))?;
+        let _ = std::fs::set_permissions(parent, std::fs::Permissions::from_mode(0o700));
+    }
+
+    let body = if body.starts_with("#!") {
+        body.to_string()
+    } else {
+        format!("#!/usr/bin/env bash\n{}\n", body)
+    };
+    std::fs::write(path, body).map_err(|e| format!("Failed to write script: {}", e))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o700))
+        .map_err(|e| format!("Failed to chmod script: {}", e))
+}
+
+/// Shell line that runs a script file and reports its exit status as a run marker
+fn pty_command_line(path: &Path, run_id: &str, extra_args: &[String]) -> String {
+    let mut line = shell_quote(&path.to_string_lossy());
+    for arg in extra_args {
+        line.push(' ');
+        line.push_str(&shell_quote(arg));
+    }
+    format!("{}; printf '\\n[kael-run %s exit=%d]\\n' {} $?", line, run_id)
+}
+
+/// Run a saved script in the PTY with named placeholder values and optional
+/// positional arguments (available to the script as "$@").
+/// The run is recorded immediately; its exit code arrives later via `RunMarkerScanner`.
+pub async fn run_in_pty(
+    storage: &StorageManager,
+    pty: &PtyTerminal,
+    script: &Script,
+    args: &BTreeMap<String, String>,
+    extra_args: &[String],
+) -> Result<ScriptRun, String> {
+    let body = render(&script.content, args)?;
+
+    let run = storage
+        .scripts()
+        .start_run(&script.id, &body, args)
+        .await
+        .map_err(|e| format!("Failed to record run: {}", e))?;
+
+    let path = run_file(&run.id);
+    write_run_file(&path, &body)?;
+    pty.write_line(&pty_command_line(&path, &run.id, extra_args)).await?;
+
+    log::info!("Running script '{}' as run {}", script.name, run.id);
+    Ok(run)
+}
+
+/// Store exit codes reported by the PTY and clean up the per-run files
+pub async fn record_run_results(storage: &StorageManager, results: Vec<(String, i32)>) {
+    for (run_id, exit_code) in results {
+        match storage.scripts().finish_run(&run_id, exit_code).await {
+            Ok(true) => log::info!("Script run {} exited with {}", run_id, exit_code),
+            Ok(false) => log::warn!("Exit code for unknown script run {}", run_id),
+            Err(e) => log::error!("Failed to record script run {}: {}", run_id, e),
+        }
+        let _ = std::fs::remove_file(run_file(&run_id));
+    }
+}
+
+/// Strips `[kael-run <id> exit=<code>]` markers out of PTY output and reports
+/// them. Markers split across chunks are held back until complete.
+#[derive(Default)]
+pub struct RunMarkerScanner {
+    pending: String,
+}
+
+impl RunMarkerScanner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed a chunk of terminal text; returns the text to display and any finished runs
+    pub fn feed(&mut self, chunk: &str) -> (String, Vec<(String, i32)>) {
+        self.pending.push_str(chunk);
+
+        let mut results = Vec::new();
+        for cap in RUN_MARKER_RE.captures_iter(&self.pending) {
+            if let Ok(code) = cap[2].parse::<i32>() {
+                results.push((cap[1].to_string(), code));
+            }
+        }
+        let cleaned = RUN_MARKER_RE.replace_all(&self.pending, "\n").into_owned();
+
+        // Hold back a possibly incomplete marker at the end
+        let hold_from = match cleaned.rfind(RUN_MARKER_PREFIX) {
+            Some(idx) if !cleaned[idx..].contains(']') => Some(idx),
+            _ => (1..RUN_MARKER_PREFIX.len())
+                .rev()
+                .find(|&n| cleaned.ends_with(&RUN_MARKER_PREFIX[..n]))
+                .map(|n| cleaned.len() - n),
+        };
+
+        let display = match hold_from {
+            Some(idx) => {
+                self.pending = cleaned[idx..].to_string();
+                cleaned[..idx].to_string()
+            }
+            None => {
+                self.pending.clear();
+                cleaned
+            }
+        };
+
+        (display, results)
+    }
+}
+
+// ============================================================================
+// .sh export / import
+// ============================================================================
+
+/// Render a script as a standalone `.sh` file with a Kael metadata header
+pub fn export_sh(script: &Script) -> String {
+    // A script imported with its own interpreter line keeps it on export
+    let (shebang, content) = match script.content.split_once('\n') {
+        Some((first, rest)) if first.starts_with("#!") => (first, rest),
+        _ if script.content.starts_with("#!") => (script.content.as_str(), ""),
+        _ => ("#!/usr/bin/env bash", script.content.as_str()),
+    };
+    let mut out = format!("{}\n", shebang);
+    out.push_str(&format!("# kael-script: {}\n", script.name));
+    if !script.description.is_empty() {
+        out.push_str(&format!("# description: {}\n", script.description));
+    }
+    if !script.tags.is_empty() {
+        out.push_str(&format!("# tags: {}\n", script.tags.join(", ")));
+    }
+    for p in placeholders(content) {
+        match p.default {
+            Some(default) => out.push_str(&format!("# param: {}={}\n", p.name, default)),
+            None => out.push_str(&format!("# param: {}\n", p.name)),
+        }
+    }
+    out.push('\n');
+    out.push_str(content.trim_end());
+    out.push('\n');
+    out
+}
+
+/// Interpreter named by a `#!` line, e.g. `bash` for `#!/usr/bin/env bash`
+fn shebang_interpreter(line: &str) -> Option<&str> {
+    let mut words = line.strip_prefix("#!")?.split_whitespace();
+    let mut program = words.next()?;
+    if program.ends_with("/env") {
+        program = words.find(|w| !w.starts_with('-'))?;
+    }
+    program.rsplit('/').next()
+}
+
+/// Parse a `.sh` file. Kael headers are read back into name/description/tags;
+/// any other file is imported whole under `fallback_name`.
+/// Scripts run with bash by default, so a bash shebang is dropped and an sh
+/// one is kept; files for any other interpreter are refused.
+pub fn import_sh(text: &str, fallback_name: &str) -> Result<Script, String> {
+    let mut shebang = None;
+    let mut name = None;
+    let mut description = String::new();
+    let mut tags = Vec::new();
+    let mut body_start = 0;
+    let mut in_header = true;
+
+    for (idx, line) in text.lines().enumerate() {
+        if !in_header {
+            break;
+        }
+        let trimmed = line.trim();
+        if idx == 0 && trimmed.starts_with("#!") {
+            match shebang_interpreter(trimmed) {
+                Some("bash") => {}
+                Some("sh") => shebang = Some(trimmed),
+                other => {
+                    return Err(format!(
+                        "Only bash and sh scripts can be imported, not {}",
+                        other.unwrap_or(trimmed)
+                    ))
+                }
+            }
+            body_start = idx + 1;
+            continue;
+        }
+        if let Some(v) = trimmed.strip_prefix("# kael-script:") {
+            name = Some(v.trim().to_string());
+        } else if let Some(v) = trimmed.strip_prefix("# description:") {
+            description = v.trim().to_string();
+        } else if let Some(v) = trimmed.strip_prefix("# tags:") {
+            tags = parse_tags(v);
+        } else if trimmed.starts_with("# param:") {
+            // Derived from placeholders in the body
+        } else if trimmed.is_empty() && name.is_some() {
+            // Blank line closes the Kael header
+            in_header = false;
+        } else {
+            in_header = false;
+            continue;
+        }
+        body_start = idx + 1;
+    }
+
+    let body = text.lines().skip(body_start).collect::<Vec<_>>().join("\n");
+    let content = match shebang {
+        Some(line) => format!("{}\n{}", line, body.trim()),
+        None => body.trim().to_string(),
+    };
+
+    let mut script = Script::new(name.unwrap_or_else(|| fallback_name.to_string()), content);
+    script.description = description;
+    script.tags = tags;
+    Ok(script)
+}
+
+/// File-system friendly version of a script name
+fn file_stem(name: &str) -> String {
+    let stem: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '-' })
+        .collect();
+    let stem = stem.trim_matches('-').to_string();
+    if stem.is_empty() {
+        "script".to_string()
+    } else {
+        stem
+    }
+}
+
+/// Default export directory (`scripts` in the active profile's data dir)
+pub fn export_dir() -> PathBuf {
+    crate::profiles::data_dir().join("scripts")
+}
+
+/// Write a script to `<dir>/<name>.sh` with the executable bit set
+pub fn export_to_file(script: &Script, dir: &Path) -> Result<PathBuf, String> {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create export dir: {}", e))?;
+    let path = dir.join(format!("{}.sh", file_stem(&script.name)));
+    std::fs::write(&path, export_sh(script)).map_err(|e| format!("Failed to export script: {}", e))?;
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))
+        .map_err(|e| format!("Failed to chmod export: {}", e))?;
+    Ok(path)
+}
+
+/// Import a `.sh` file into the library
+pub async fn import_from_file(storage: &StorageManager, path: &Path) -> Result<Script, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let fallback = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "imported".to_string());
+
+    let script = import_sh(&text, &fallback)?;
+    storage
+        .scripts()
+        .save(&script)
+        .await
+        .map_err(|e| format!("Failed to save imported script: {}", e))?;
+    Ok(script)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_placeholders_and_render() {
+        let body = "paru -S {{pkg}} && echo {{ msg : done }} {{pkg}}";
+        let found = placeholders(body);
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0], Placeholder { name: "pkg".into(), default: None });
+        assert_eq!(found[1].default.as_deref(), Some("done"));
+
+        let args = BTreeMap::from([("pkg".to_string(), "neovim".to_string())]);
+        assert_eq!(render(body, &args).unwrap(), "paru -S neovim && echo done neovim");
+
+        let err = render(body, &BTreeMap::new()).unwrap_err();
+        assert!(err.contains("pkg"));
+    }
+
+    #[test]
+    fn test_render_quotes_hostile_values() {
+        let args = BTreeMap::from([("file".to_string(), "a b'; rm -rf ~".to_string())]);
+        assert_eq!(render("cat {{file}}", &args).unwrap(), r"cat 'a b'\''; rm -rf ~'");
+    }
+
+    #[test]
+    fn test_render_inside_quotes() {
+        let args = BTreeMap::from([
+            ("msg".to_string(), "fix bug".to_string()),
+            ("evil".to_string(), r#"a "$(reboot)" `id` \ 'b'"#.to_string()),
+        ]);
+        assert_eq!(
+            render(r#"git commit -m "{{msg}}""#, &args).unwrap(),
+            r#"git commit -m "fix bug""#
+        );
+        assert_eq!(
+            render(r#"echo "say: {{evil}}" '{{evil}}' {{msg}}"#, &args).unwrap(),
+            r#"echo "say: a \"\$(reboot)\" \`id\` \\ 'b'" 'a "$(reboot)" `id` \ '\''b'\''' 'fix bug'"#
+        );
+        // Escaped quotes don't open a quoted string
+        assert_eq!(render(r#"echo \"{{msg}}"#, &args).unwrap(), r#"echo \"'fix bug'"#);
+
+        let mut shell = std::process::Command::new("sh");
+        shell.arg("-c").arg(render(r#"printf '%s|' "{{evil}}" '{{evil}}' {{evil}}"#, &args).unwrap());
+        let out = shell.output().unwrap();
+        let expected = format!("{0}|{0}|{0}|", args["evil"]);
+        assert_eq!(String::from_utf8_lossy(&out.stdout), expected);
+    }
+
+    #[test]
+    fn test_extract_snippet() {
+        let msg = "Try this:\n```bash\nsudo pacman -Syu\nreboot\n```\nGood luck";
+        assert_eq!(extract_snippet(msg), "sudo pacman -Syu\nreboot");
+        assert_eq!(extract_snippet("$ ls -la"), "ls -la");
+    }
+
+    #[test]
+    fn test_export_import_round_trip() {
+        let mut script = Script::new("Install package", "paru -S {{pkg:neovim}}");
+        script.description = "Install from the AUR".into();
+        script.tags = vec!["aur".into(), "pacman".into()];
+
+        let exported = export_sh(&script);
+        assert!(exported.contains("# param: pkg=neovim"));
+
+        let imported = import_sh(&exported, "fallback").unwrap();
+        assert_eq!(imported.name, "Install package");
+        assert_eq!(imported.description, "Install from the AUR");
+        assert_eq!(imported.tags, script.tags);
+        assert_eq!(imported.content, script.content);
+    }
+
+    #[test]
+    fn test_import_plain_shell_file() {
+        let imported = import_sh("#!/bin/bash\n# cleanup\nrm -rf ~/.cache/paru\n", "cleanup").unwrap();
+        assert_eq!(imported.name, "cleanup");
+        assert_eq!(imported.content, "# cleanup\nrm -rf ~/.cache/paru");
+
+        // An sh script keeps its interpreter when run and exported
+        let imported = import_sh("#!/bin/sh\necho {{msg}}\n", "echo").unwrap();
+        assert_eq!(imported.content, "#!/bin/sh\necho {{msg}}");
+        let exported = export_sh(&imported);
+        assert!(exported.starts_with("#!/bin/sh\n# kael-script: echo\n# param: msg\n"));
+        assert_eq!(import_sh(&exported, "x").unwrap().content, imported.content);
+
+        let err = import_sh("#!/usr/bin/env python3\nprint('hi')\n", "hi").unwrap_err();
+        assert!(err.contains("python3"));
+    }
+
+    #[test]
+    fn test_run_marker_scanner() {
+        let id = "0b0e3a52-8a8d-4e43-9d6a-0f5e8c1d2b3a";
+        let mut scanner = RunMarkerScanner::new();
+
+        let (shown, done) = scanner.feed("building...\n[kael-r");
+        assert_eq!(shown, "building...\n");
+        assert!(done.is_empty());
+
+        let (shown, done) = scanner.feed(&format!("un {} exit=3]\n$ ", id));
+        assert_eq!(done, vec![(id.to_string(), 3)]);
+        assert!(!shown.contains("kael-run"));
+        assert!(shown.ends_with("$ "));
+    }
+
+    #[tokio::test]
+    async fn test_save_from_chat() {
+        let storage = StorageManager::open_in_memory().unwrap();
+        let script = save_from_chat(&storage, "```\njournalctl -fu {{unit}}\n```", None, vec!["logs".into()])
+            .await
+            .unwrap();
+        assert_eq!(script.name, "journalctl -fu {{unit}}");
+        assert_eq!(storage.scripts().list_by_tag("logs").await.unwrap().len(), 1);
+    }
+}
@@ -192,18 +192,9 @@ impl SystemContext {
             .map_err(|e| format!("Failed to parse: {}", e))
     }
     
-    /// Load from default user data directory (for use in llm.rs without AppHandle)
+    /// Load from the active profile's data directory (for use in llm.rs without AppHandle)
     pub fn load_from_default_path() -> Result<Self, String> {
-        let home = std::env::var("HOME")
-            .map_err(|_| "HOME environment variable not set".to_string())?;
-        
-        let path = PathBuf::from(home)
-            .join(".local")
-            .join("share")
-            .join("kael-os")
-            .join("system_context.json");
-        
-        Self::load(&path)
+        Self::load(&crate::profiles::data_dir().join("system_context.json"))
     }
 }
 
@@ -0,0 +1,467 @@
+//! Session lifecycle for the signed-in user.
+//!
+//! Firebase ID tokens last an hour. The session manager refreshes the token
+//! `REFRESH_MARGIN` seconds before it expires, measured against wall time so
+//! a suspended laptop wakes up to a refresh rather than a dead token. While
+//! the network is down it backs off and retries; once the refresh token is
+//! refused the user is signed out. Every change is broadcast as a
+//! `SessionEvent`. Requests sent through `authorized` use the freshest token
+//! and, on a 401, refresh once and retry.
+
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use tokio::sync::broadcast;
+
+use crate::auth::{AuthService, RefreshError, User};
+
+/// Refresh this many seconds before the token expires
+pub const REFRESH_MARGIN: i64 = 5 * 60;
+/// Longest sleep between looks at the clock
+const CHECK_INTERVAL: i64 = 60;
+const RETRY_MIN: Duration = Duration::from_secs(5);
+const RETRY_MAX: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Clone, Debug)]
+pub enum SessionEvent {
+    SignedIn(User),
+    Refreshed(User),
+    /// Refresh failed for a reason that may pass; retried after `retry_in`
+    RefreshFailed {
+        error: String,
+        retry_in: Duration,
+    },
+    /// The refresh token was refused; a `SignedOut` follows
+    Expired,
+    SignedOut,
+}
+
+static EVENTS: Lazy<broadcast::Sender<SessionEvent>> = Lazy::new(|| broadcast::channel(32).0);
+// One refresh at a time, so a burst of 401s spends the refresh token once
+static REFRESH_LOCK: Lazy<tokio::sync::Mutex<()>> = Lazy::new(|| tokio::sync::Mutex::new(()));
+
+/// Receive every session change after this call
+pub fn subscribe() -> broadcast::Receiver<SessionEvent> {
+    EVENTS.subscribe()
+}
+
+pub(crate) fn notify(event: SessionEvent) {
+    // No subscribers is fine
+    let _ = EVENTS.send(event);
+}
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+/// Seconds from `now` until `user`'s token should be refreshed; `None` when
+/// it can't be (a pasted token without expiry or refresh token)
+pub fn refresh_due_in(user: &User, now: i64) -> Option<i64> {
+    user.refresh_token.as_ref()?;
+    user.expires_in
+        .map(|expires| (expires - REFRESH_MARGIN - now).max(0))
+}
+
+/// Delay before retry number `failures + 1`
+fn backoff(failures: u32) -> Duration {
+    RETRY_MIN
+        .saturating_mul(2u32.saturating_pow(failures))
+        .min(RETRY_MAX)
+}
+
+/// Refresh the session's token, unless it already moved on from `stale`.
+/// A refused refresh token ends the session.
+pub async fn refresh(auth: &AuthService, stale: &str) -> Result<User, RefreshError> {
+    let _guard = REFRESH_LOCK.lock().await;
+    let user = auth
+        .get_user()
+        .ok_or_else(|| RefreshError::Rejected("Not signed in".to_string()))?;
+    if user.id_token != stale {
+        return Ok(user);
+    }
+    let refresh_token = user
+        .refresh_token
+        .clone()
+        .ok_or_else(|| RefreshError::Rejected("No refresh token".to_string()))?;
+
+    let config = auth.refresh_config()?;
+    match AuthService::refresh_id_token(&config, &refresh_token).await {
+        Ok(refreshed) => {
+            let user = auth
+                .apply_refresh(refreshed)
+                .ok_or_else(|| RefreshError::Rejected("Signed out during refresh".to_string()))?;
+            log::info!("Session token refreshed for {}", user.email);
+            notify(SessionEvent::Refreshed(user.clone()));
+            Ok(user)
+        }
+        Err(RefreshError::Rejected(e)) => {
+            log::warn!("Session expired, signing out: {}", e);
+            notify(SessionEvent::Expired);
+            auth.logout();
+            Err(RefreshError::Rejected(e))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// The freshest token for `uid`: the session's when it is the same user,
+/// refreshed first if due
+async fn token_for(auth: &AuthService, uid: &str, id_token: &str) -> String {
+    let Some(user) = auth.get_user().filter(|u| u.uid == uid) else {
+        return id_token.to_string();
+    };
+    if refresh_due_in(&user, now()) == Some(0) {
+        if let Ok(user) = refresh(auth, &user.id_token).await {
+            return user.id_token;
+        }
+    }
+    user.id_token
+}
+
+/// Send the request `build` makes for a bearer token, using the freshest
+/// token for `uid`. On a 401 the token is refreshed once and the request
+/// sent again; if that fails the 401 is returned as is.
+pub async fn authorized<F>(
+    uid: &str,
+    id_token: &str,
+    build: F,
+) -> Result<reqwest::Response, reqwest::Error>
+where
+    F: Fn(&str) -> reqwest::RequestBuilder,
+{
+    authorized_with(&AuthService::new(), uid, id_token, build).await
+}
+
+async fn authorized_with<F>(
+    auth: &AuthService,
+    uid: &str,
+    id_token: &str,
+    build: F,
+) -> Result<reqwest::Response, reqwest::Error>
+where
+    F: Fn(&str) -> reqwest::RequestBuilder,
+{
+    let token = token_for(auth, uid, id_token).await;
+    let resp = build(&token).send().await?;
+    let ours = auth.get_user().is_some_and(|u| u.uid == uid);
+    if resp.status() != reqwest::StatusCode::UNAUTHORIZED || !ours {
+        return Ok(resp);
+    }
+    match refresh(auth, &token).await {
+        Ok(user) => build(&user.id_token).send().await,
+        Err(e) => {
+            log::warn!("Request unauthorized and {}", e);
+            Ok(resp)
+        }
+    }
+}
+
+/// Keep `auth`'s session fresh until the process exits
+pub async fn run(auth: AuthService) {
+    let mut events = subscribe();
+    let mut failures = 0u32;
+    let mut retry_at: Option<i64> = None;
+
+    loop {
+        let now = now();
+        let due = auth
+            .get_user()
+            .and_then(|user| refresh_due_in(&user, now).map(|secs| (user, secs)));
+        let wait = match &due {
+            Some((_, secs)) => retry_at.unwrap_or(now + secs) - now,
+            // Nothing to refresh until someone signs in
+            None => i64::MAX,
+        };
+
+        if wait > 0 {
+            let nap = Duration::from_secs(wait.min(CHECK_INTERVAL) as u64);
+            tokio::select! {
+                _ = tokio::time::sleep(nap), if due.is_some() => {}
+                event = events.recv() => match event {
+                    Ok(SessionEvent::RefreshFailed { .. }) | Ok(SessionEvent::Expired) => {}
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {
+                        failures = 0;
+                        retry_at = None;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                },
+            }
+            continue;
+        }
+
+        let Some((user, _)) = due else { continue };
+        match refresh(&auth, &user.id_token).await {
+            Ok(_) | Err(RefreshError::Rejected(_)) => {
+                failures = 0;
+                retry_at = None;
+            }
+            Err(RefreshError::Transient(error)) => {
+                let retry_in = backoff(failures);
+                failures = failures.saturating_add(1);
+                retry_at = Some(self::now() + retry_in.as_secs() as i64);
+                log::warn!(
+                    "Session refresh failed, retrying in {:?}: {}",
+                    retry_in,
+                    error
+                );
+                notify(SessionEvent::RefreshFailed { error, retry_in });
+            }
+        }
+    }
+}
+
+/// Run the session manager for the process-wide session in the background
+pub fn start_session_manager() {
+    std::thread::spawn(|| {
+        let rt = tokio::runtime::Runtime::new().expect("Failed to create Tokio runtime");
+        rt.block_on(run(AuthService::new()));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::RefreshConfig;
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Mutex, OnceLock};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    static REFRESHES: AtomicUsize = AtomicUsize::new(0);
+    static THROTTLED: Lazy<Mutex<HashSet<String>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+    /// Answer one request to the fake Firebase: the token endpoint refreshes
+    /// `ok-<uid>` tokens, refuses `revoked-*`, is down for `down-*` and
+    /// throttles the first refresh of each `busy-*`; `/data` only accepts
+    /// refreshed tokens.
+    async fn serve(mut socket: tokio::net::TcpStream) {
+        let mut raw = Vec::new();
+        let mut buf = [0u8; 4096];
+        let (head, body) = loop {
+            let n = socket.read(&mut buf).await.unwrap();
+            raw.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&raw).to_string();
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length = head
+                    .lines()
+                    .find_map(|l| {
+                        l.to_lowercase()
+                            .strip_prefix("content-length: ")
+                            .map(str::to_string)
+                    })
+                    .map_or(0, |l| l.parse().unwrap());
+                if body.len() >= length || n == 0 {
+                    break (head.to_string(), body.to_string());
+                }
+            }
+        };
+
+        let (status, reply) =
+            if head.starts_with("POST /securetoken.googleapis.com/v1/token?key=test-key") {
+                let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+                let token = body["refresh_token"].as_str().unwrap();
+                let busy = token.strip_prefix("busy-");
+                let first_try = busy.is_some_and(|uid| THROTTLED.lock().unwrap().insert(uid.to_string()));
+                if first_try {
+                    (429, r#"{"error":{"message":"TOO_MANY_ATTEMPTS_TRY_LATER"}}"#.to_string())
+                } else if let Some(uid) = token.strip_prefix("ok-").or(busy) {
+                    REFRESHES.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    let reply = serde_json::json!({
+                        "id_token": format!("new-token-{}", uid),
+                        "refresh_token": format!("ok-{}", uid),
+                        "expires_in": "3600",
+                        "user_id": uid,
+                    });
+                    (200, reply.to_string())
+                } else if token.starts_with("revoked-") {
+                    (400, r#"{"error":{"message":"TOKEN_EXPIRED"}}"#.to_string())
+                } else {
+                    (503, "unavailable".to_string())
+                }
+            } else if head.starts_with("GET /data") {
+                if head.contains("Bearer new-token-") {
+                    (200, "ok".to_string())
+                } else {
+                    (401, "unauthorized".to_string())
+                }
+            } else {
+                (404, String::new())
+            };
+        let response = format!(
+            "HTTP/1.1 {} X\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            reply.len(),
+            reply
+        );
+        let _ = socket.write_all(response.as_bytes()).await;
+    }
+
+    /// Start the fake Firebase once; returns its address
+    fn fake_firebase() -> String {
+        static ADDR: OnceLock<String> = OnceLock::new();
+        ADDR.get_or_init(|| {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap().to_string();
+            std::thread::spawn(move || {
+                let rt = tokio::runtime::Runtime::new().unwrap();
+                rt.block_on(async move {
+                    listener.set_nonblocking(true).unwrap();
+                    let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+                    loop {
+                        let (socket, _) = listener.accept().await.unwrap();
+                        tokio::spawn(serve(socket));
+                    }
+                });
+            });
+            addr
+        })
+        .clone()
+    }
+
+    /// A session whose token refreshes go to the fake Firebase
+    fn session(user: User) -> AuthService {
+        AuthService::ephemeral(Some(user)).with_refresh_config(RefreshConfig {
+            api_key: "test-key".to_string(),
+            emulator_host: Some(fake_firebase()),
+        })
+    }
+
+    fn user(uid: &str, refresh_token: &str, expires_in: i64) -> User {
+        User {
+            uid: uid.to_string(),
+            email: format!("{}@example.com", uid),
+            name: uid.to_string(),
+            photo_url: Some("https://example.com/me.png".to_string()),
+            id_token: format!("old-token-{}", uid),
+            refresh_token: Some(refresh_token.to_string()),
+            expires_in: Some(now() + expires_in),
+        }
+    }
+
+    async fn next_event(
+        events: &mut broadcast::Receiver<SessionEvent>,
+        matches: impl Fn(&SessionEvent) -> bool,
+    ) -> SessionEvent {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let event = events.recv().await.unwrap();
+                if matches(&event) {
+                    return event;
+                }
+            }
+        })
+        .await
+        .expect("no matching session event")
+    }
+
+    #[test]
+    fn test_refresh_schedule() {
+        let alice = user("alice", "ok-alice", 3600);
+        let now = now();
+        assert_eq!(refresh_due_in(&alice, now), Some(3600 - REFRESH_MARGIN));
+        assert_eq!(refresh_due_in(&alice, now + 3600), Some(0));
+        let pasted = User {
+            refresh_token: None,
+            ..alice.clone()
+        };
+        assert_eq!(refresh_due_in(&pasted, now), None);
+
+        assert_eq!(backoff(0), RETRY_MIN);
+        assert_eq!(backoff(1), Duration::from_secs(10));
+        assert_eq!(backoff(40), RETRY_MAX);
+    }
+
+    #[tokio::test]
+    async fn test_unauthorized_request_refreshes_once() {
+        let addr = fake_firebase();
+        let url = format!("http://{}/data", addr);
+        let client = reqwest::Client::new();
+        let auth = session(user("alice", "ok-alice", 3600));
+        let mut events = subscribe();
+
+        // Both requests hit 401 with the old token; only one refresh happens
+        let before = REFRESHES.load(Ordering::SeqCst);
+        let request = |token: &str| client.get(&url).bearer_auth(token);
+        let (a, b) = tokio::join!(
+            authorized_with(&auth, "alice", "old-token-alice", request),
+            authorized_with(&auth, "alice", "old-token-alice", request),
+        );
+        assert_eq!(a.unwrap().status(), 200);
+        assert_eq!(b.unwrap().status(), 200);
+        assert_eq!(REFRESHES.load(Ordering::SeqCst) - before, 1);
+
+        let refreshed = auth.get_user().unwrap();
+        assert_eq!(refreshed.id_token, "new-token-alice");
+        assert_eq!(refreshed.email, "alice@example.com");
+        assert!(refreshed.photo_url.is_some());
+        next_event(
+            &mut events,
+            |e| matches!(e, SessionEvent::Refreshed(u) if u.uid == "alice"),
+        )
+        .await;
+
+        // A token for someone else is sent as given, without a retry
+        let other = authorized_with(&auth, "mallory", "old-token-mallory", request).await;
+        assert_eq!(other.unwrap().status(), 401);
+    }
+
+    #[tokio::test]
+    async fn test_refused_refresh_ends_session() {
+        let addr = fake_firebase();
+        let url = format!("http://{}/data", addr);
+        let client = reqwest::Client::new();
+
+        // A server outage keeps the session
+        let carol = session(user("carol", "down-carol", 3600));
+        let err = refresh(&carol, "old-token-carol").await.unwrap_err();
+        assert!(matches!(err, RefreshError::Transient(_)));
+        assert!(carol.get_user().is_some());
+
+        // Throttling is retried rather than treated as a refusal
+        let frank = session(user("frank", "busy-frank", 3600));
+        let refreshed = refresh(&frank, "old-token-frank").await.unwrap();
+        assert_eq!(refreshed.id_token, "new-token-frank");
+
+        let bob = session(user("bob", "revoked-bob", 3600));
+        let mut events = subscribe();
+        let resp = authorized_with(&bob, "bob", "old-token-bob", |t| {
+            client.get(&url).bearer_auth(t)
+        })
+        .await
+        .unwrap();
+        assert_eq!(resp.status(), 401);
+        assert!(bob.get_user().is_none());
+        next_event(&mut events, |e| matches!(e, SessionEvent::Expired)).await;
+        next_event(&mut events, |e| matches!(e, SessionEvent::SignedOut)).await;
+    }
+
+    #[tokio::test]
+    async fn test_manager_refreshes_before_expiry() {
+        let mut events = subscribe();
+
+        // Expiring within the margin: refreshed right away
+        let dave = session(user("dave", "ok-dave", 60));
+        let manager = tokio::spawn(run(dave.clone()));
+        next_event(
+            &mut events,
+            |e| matches!(e, SessionEvent::Refreshed(u) if u.uid == "dave"),
+        )
+        .await;
+        assert_eq!(dave.get_user().unwrap().id_token, "new-token-dave");
+        manager.abort();
+
+        // Unreachable token service: retried with backoff
+        let erin = session(user("erin", "down-erin", 0));
+        let manager = tokio::spawn(run(erin.clone()));
+        let event = next_event(&mut events, |e| {
+            matches!(e, SessionEvent::RefreshFailed { .. })
+        })
+        .await;
+        assert!(
+            matches!(event, SessionEvent::RefreshFailed { retry_in, .. } if retry_in == RETRY_MIN)
+        );
+        assert!(erin.get_user().is_some());
+        manager.abort();
+    }
+}
@@ -0,0 +1,651 @@
+//! Typed application settings persisted in the `kael_config` table.
+//!
+//! Each field of `Settings` is stored as one JSON value under its `SettingKey`.
+//! Missing or invalid values fall back to the schema defaults, updates are
+//! validated before they are written, and subscribers are told which keys
+//! changed. Older builds kept these values in world-readable `/tmp/kael_*.json`
+//! files; `migrate_legacy_files` imports and removes them.
+#![allow(dead_code)]
+
+use crate::state::KaelConfig;
+use kael_storage::{StorageManager, Syncable};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::{OnceLock, RwLock};
+use tokio::sync::broadcast;
+
+/// Providers listed in the settings panel, in display order, with whether each
+/// is enabled on a fresh install. Provider orders may only name these.
+pub const PROVIDERS: &[(&str, bool)] = &[
+    ("Ollama (Local)", true),
+    ("Mistral AI", true),
+    ("Google Gemini", false),
+    ("GitHub Copilot", false),
+    ("GitHub Copilot CLI (New)", true),
+    ("GitHub Copilot CLI", true),
+    ("Office 365 AI", false),
+    ("Google One AI", false),
+    ("Minstrel AI", false),
+];
+
+pub fn is_known_provider(name: &str) -> bool {
+    PROVIDERS.iter().any(|(known, _)| *known == name)
+}
+
+/// Order saved when the settings panel's defaults are kept
+pub fn default_provider_order() -> Vec<String> {
+    PROVIDERS
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| name.to_string())
+        .collect()
+}
+
+/// Cloud providers that can be remembered as the last escalation target
+pub const CLOUD_PROVIDERS: &[&str] = &["Mistral AI", "Google Gemini", "GitHub Copilot"];
+
+/// Allowed terminal scrollback sizes, in lines
+pub const TERMINAL_SCROLLBACK_RANGE: std::ops::RangeInclusive<usize> = 100..=200_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SettingKey {
+    HybridAssist,
+    ProviderOrder,
+    LocalModels,
+    CachedKeys,
+    LastCloudProvider,
+    Kael,
+    TerminalScrollback,
+    PersistentTerminal,
+    ApiServer,
+    ApiPort,
+    SyncApiKeys,
+    Sync,
+}
+
+impl SettingKey {
+    pub const ALL: [SettingKey; 12] = [
+        SettingKey::HybridAssist,
+        SettingKey::ProviderOrder,
+        SettingKey::LocalModels,
+        SettingKey::CachedKeys,
+        SettingKey::LastCloudProvider,
+        SettingKey::Kael,
+        SettingKey::TerminalScrollback,
+        SettingKey::PersistentTerminal,
+        SettingKey::ApiServer,
+        SettingKey::ApiPort,
+        SettingKey::SyncApiKeys,
+        SettingKey::Sync,
+    ];
+
+    /// Row key in `kael_config`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SettingKey::HybridAssist => "settings.hybrid_assist",
+            SettingKey::ProviderOrder => "settings.provider_order",
+            SettingKey::LocalModels => "settings.local_models",
+            SettingKey::CachedKeys => "settings.cached_keys",
+            SettingKey::LastCloudProvider => "settings.last_cloud_provider",
+            SettingKey::Kael => "settings.kael",
+            SettingKey::TerminalScrollback => "settings.terminal_scrollback",
+            SettingKey::PersistentTerminal => "settings.persistent_terminal",
+            SettingKey::ApiServer => "settings.api_server",
+            SettingKey::ApiPort => "settings.api_port",
+            SettingKey::SyncApiKeys => "settings.sync_api_keys",
+            SettingKey::Sync => "settings.sync",
+        }
+    }
+
+    /// File name used by builds that kept this setting in /tmp
+    fn legacy_file(&self) -> Option<&'static str> {
+        match self {
+            SettingKey::HybridAssist => Some("kael_hybrid_assist.json"),
+            SettingKey::ProviderOrder => Some("kael_provider_order.json"),
+            SettingKey::LocalModels => Some("kael_local_models.json"),
+            SettingKey::CachedKeys => Some("kael_cached_keys.json"),
+            SettingKey::LastCloudProvider => Some("kael_last_cloud_provider.json"),
+            SettingKey::Kael
+            | SettingKey::TerminalScrollback
+            | SettingKey::PersistentTerminal
+            | SettingKey::ApiServer
+            | SettingKey::ApiPort
+            | SettingKey::SyncApiKeys
+            | SettingKey::Sync => None,
+        }
+    }
+}
+
+/// Plaintext provider API key cached by older builds; `vault::import_legacy`
+/// moves these into the vault and clears them
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CachedKey {
+    pub name: String,
+    pub value: String,
+}
+
+/// Which records the sync service sends to Firestore. Nothing syncs until
+/// `enabled` is on; chat history stays on the device unless opted in.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SyncConfig {
+    pub enabled: bool,
+    pub settings: bool,
+    pub projects: bool,
+    pub scripts: bool,
+    pub chat_sessions: bool,
+    /// Needs `chat_sessions`, since messages belong to a session
+    pub chat_messages: bool,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            settings: true,
+            projects: true,
+            scripts: true,
+            chat_sessions: false,
+            chat_messages: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    /// Let the local model delegate to cloud providers
+    pub hybrid_assist: bool,
+    /// User's provider order; empty means "use the built-in order"
+    pub provider_order: Vec<String>,
+    /// Installed Ollama model names, refreshed from the settings panel
+    pub local_models: Vec<String>,
+    pub cached_keys: Vec<CachedKey>,
+    pub last_cloud_provider: Option<String>,
+    pub kael: KaelConfig,
+    /// Lines of terminal scrollback kept per session
+    pub terminal_scrollback: usize,
+    /// Run Kael's terminal in the detached session daemon so it survives
+    /// app restarts and crashes
+    pub persistent_terminal: bool,
+    /// Serve the local JSON-RPC API on a Unix socket
+    pub api_server: bool,
+    /// Also serve the API on 127.0.0.1 at this port, with a token; 0 = off
+    pub api_port: u16,
+    /// Mirror provider API keys to Firestore; the local vault is always used
+    pub sync_api_keys: bool,
+    /// Sync service switch and the collections it covers
+    pub sync: SyncConfig,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            hybrid_assist: true,
+            provider_order: Vec::new(),
+            local_models: Vec::new(),
+            cached_keys: Vec::new(),
+            last_cloud_provider: None,
+            kael: KaelConfig::default(),
+            terminal_scrollback: kael_terminal::screen::DEFAULT_SCROLLBACK,
+            persistent_terminal: false,
+            api_server: false,
+            api_port: 0,
+            sync_api_keys: false,
+            sync: SyncConfig::default(),
+        }
+    }
+}
+
+impl Settings {
+    /// Check every field against the schema
+    pub fn validate(&self) -> Result<(), String> {
+        for key in SettingKey::ALL {
+            self.validate_key(key)?;
+        }
+        Ok(())
+    }
+
+    fn validate_key(&self, key: SettingKey) -> Result<(), String> {
+        match key {
+            SettingKey::HybridAssist
+            | SettingKey::PersistentTerminal
+            | SettingKey::ApiServer
+            | SettingKey::SyncApiKeys => Ok(()),
+            SettingKey::ProviderOrder => {
+                for (i, name) in self.provider_order.iter().enumerate() {
+                    if !is_known_provider(name) {
+                        return Err(format!("Unknown provider in order: {}", name));
+                    }
+                    if self.provider_order[..i].contains(name) {
+                        return Err(format!("Provider listed twice: {}", name));
+                    }
+                }
+                Ok(())
+            }
+            SettingKey::LocalModels => {
+                if self.local_models.iter().any(|m| m.trim().is_empty()) {
+                    return Err("Local model names cannot be empty".to_string());
+                }
+                Ok(())
+            }
+            SettingKey::CachedKeys => {
+                if self.cached_keys.iter().any(|k| k.name.trim().is_empty()) {
+                    return Err("Cached API keys need a provider name".to_string());
+                }
+                Ok(())
+            }
+            SettingKey::LastCloudProvider => match &self.last_cloud_provider {
+                Some(name) if !CLOUD_PROVIDERS.contains(&name.as_str()) => {
+                    Err(format!("Not a cloud provider: {}", name))
+                }
+                _ => Ok(()),
+            },
+            SettingKey::Kael => {
+                if self.kael.personality_level > 10 {
+                    return Err("Personality level must be between 0 and 10".to_string());
+                }
+                Ok(())
+            }
+            SettingKey::TerminalScrollback => {
+                if !TERMINAL_SCROLLBACK_RANGE.contains(&self.terminal_scrollback) {
+                    return Err(format!(
+                        "Terminal scrollback must be between {} and {} lines",
+                        TERMINAL_SCROLLBACK_RANGE.start(),
+                        TERMINAL_SCROLLBACK_RANGE.end()
+                    ));
+                }
+                Ok(())
+            }
+            SettingKey::ApiPort => {
+                if self.api_port != 0 && self.api_port < 1024 {
+                    return Err("API port must be 0 (off) or between 1024 and 65535".to_string());
+                }
+                Ok(())
+            }
+            SettingKey::Sync => {
+                if self.sync.chat_messages && !self.sync.chat_sessions {
+                    return Err("Chat messages can only sync together with chat sessions".to_string());
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn to_json(&self, key: SettingKey) -> Result<String, String> {
+        let value = match key {
+            SettingKey::HybridAssist => serde_json::to_string(&self.hybrid_assist),
+            SettingKey::ProviderOrder => serde_json::to_string(&self.provider_order),
+            SettingKey::LocalModels => serde_json::to_string(&self.local_models),
+            SettingKey::CachedKeys => serde_json::to_string(&self.cached_keys),
+            SettingKey::LastCloudProvider => serde_json::to_string(&self.last_cloud_provider),
+            SettingKey::Kael => serde_json::to_string(&self.kael),
+            SettingKey::TerminalScrollback => serde_json::to_string(&self.terminal_scrollback),
+            SettingKey::PersistentTerminal => serde_json::to_string(&self.persistent_terminal),
+            SettingKey::ApiServer => serde_json::to_string(&self.api_server),
+            SettingKey::ApiPort => serde_json::to_string(&self.api_port),
+            SettingKey::SyncApiKeys => serde_json::to_string(&self.sync_api_keys),
+            SettingKey::Sync => serde_json::to_string(&self.sync),
+        };
+        value.map_err(|e| format!("Failed to serialize {}: {}", key.as_str(), e))
+    }
+
+    fn apply_json(&mut self, key: SettingKey, raw: &str) -> Result<(), String> {
+        let err = |e: serde_json::Error| format!("Invalid value for {}: {}", key.as_str(), e);
+        match key {
+            SettingKey::HybridAssist => self.hybrid_assist = serde_json::from_str(raw).map_err(err)?,
+            SettingKey::ProviderOrder => self.provider_order = serde_json::from_str(raw).map_err(err)?,
+            SettingKey::LocalModels => self.local_models = serde_json::from_str(raw).map_err(err)?,
+            SettingKey::CachedKeys => self.cached_keys = serde_json::from_str(raw).map_err(err)?,
+            SettingKey::LastCloudProvider => {
+                self.last_cloud_provider = serde_json::from_str(raw).map_err(err)?
+            }
+            SettingKey::Kael => self.kael = serde_json::from_str(raw).map_err(err)?,
+            SettingKey::TerminalScrollback => {
+                self.terminal_scrollback = serde_json::from_str(raw).map_err(err)?
+            }
+            SettingKey::PersistentTerminal => {
+                self.persistent_terminal = serde_json::from_str(raw).map_err(err)?
+            }
+            SettingKey::ApiServer => self.api_server = serde_json::from_str(raw).map_err(err)?,
+            SettingKey::ApiPort => self.api_port = serde_json::from_str(raw).map_err(err)?,
+            SettingKey::SyncApiKeys => self.sync_api_keys = serde_json::from_str(raw).map_err(err)?,
+            SettingKey::Sync => self.sync = serde_json::from_str(raw).map_err(err)?,
+        }
+        Ok(())
+    }
+
+    /// Keys whose values differ between two snapshots
+    fn changed_keys(&self, other: &Settings) -> Vec<SettingKey> {
+        SettingKey::ALL
+            .into_iter()
+            .filter(|&key| self.to_json(key).ok() != other.to_json(key).ok())
+            .collect()
+    }
+}
+
+pub struct SettingsStore {
+    storage: StorageManager,
+    current: RwLock<Settings>,
+    changes: broadcast::Sender<SettingKey>,
+    // Serializes read-modify-write cycles in `update`
+    write_lock: tokio::sync::Mutex<()>,
+}
+
+impl SettingsStore {
+    /// Load settings, replacing missing or invalid values with defaults
+    pub async fn load(storage: StorageManager) -> Result<Self, String> {
+        let config = storage.config();
+        let mut settings = Settings::default();
+
+        for key in SettingKey::ALL {
+            let raw = config
+                .get_raw(key.as_str())
+                .await
+                .map_err(|e| format!("Failed to read {}: {}", key.as_str(), e))?;
+            let Some(raw) = raw else { continue };
+
+            let mut candidate = settings.clone();
+            match candidate.apply_json(key, &raw).and_then(|_| candidate.validate_key(key)) {
+                Ok(()) => settings = candidate,
+                Err(e) => log::warn!("Ignoring stored setting: {}", e),
+            }
+        }
+
+        let (changes, _) = broadcast::channel(32);
+        Ok(Self {
+            storage,
+            current: RwLock::new(settings),
+            changes,
+            write_lock: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    /// Snapshot of the current settings
+    pub fn get(&self) -> Settings {
+        self.current.read().map(|s| s.clone()).unwrap_or_default()
+    }
+
+    /// Receive the key of every setting that changes after this call
+    pub fn subscribe(&self) -> broadcast::Receiver<SettingKey> {
+        self.changes.subscribe()
+    }
+
+    /// Apply `f` to a copy of the settings; if the result validates, persist
+    /// the changed keys and notify subscribers.
+    pub async fn update<F>(&self, f: F) -> Result<Settings, String>
+    where
+        F: FnOnce(&mut Settings),
+    {
+        let _guard = self.write_lock.lock().await;
+        let before = self.get();
+        let mut after = before.clone();
+        f(&mut after);
+        after.validate()?;
+
+        let changed = after.changed_keys(&before);
+        let config = self.storage.config();
+        for &key in &changed {
+            config
+                .set_raw(key.as_str(), &after.to_json(key)?)
+                .await
+                .map_err(|e| format!("Failed to save {}: {}", key.as_str(), e))?;
+        }
+
+        if let Ok(mut current) = self.current.write() {
+            *current = after.clone();
+        }
+        for key in changed {
+            // No subscribers is fine
+            let _ = self.changes.send(key);
+        }
+        Ok(after)
+    }
+
+    /// Import settings from the old `/tmp/kael_*.json` files in `dir` and delete
+    /// them. Values already stored in the database win over legacy files.
+    pub async fn migrate_legacy_files(&self, dir: &Path) -> Result<usize, String> {
+        let config = self.storage.config();
+        let mut imported = Vec::new();
+
+        for key in SettingKey::ALL {
+            let Some(file) = key.legacy_file() else { continue };
+            let path = dir.join(file);
+            let Ok(raw) = std::fs::read_to_string(&path) else { continue };
+
+            let already_set = config
+                .get_raw(key.as_str())
+                .await
+                .map_err(|e| format!("Failed to read {}: {}", key.as_str(), e))?
+                .is_some();
+            if !already_set {
+                // The hybrid flag was written as a bare true/false, which is also valid JSON
+                let raw = raw.trim().to_string();
+                imported.push((key, raw));
+            }
+
+            if let Err(e) = std::fs::remove_file(&path) {
+                log::warn!("Failed to remove legacy settings file {}: {}", path.display(), e);
+            }
+        }
+
+        let count = imported.len();
+        if count > 0 {
+            self.update(|settings| {
+                for (key, raw) in imported {
+                    let mut candidate = settings.clone();
+                    match candidate.apply_json(key, &raw).and_then(|_| candidate.validate_key(key)) {
+                        Ok(()) => *settings = candidate,
+                        Err(e) => log::warn!("Skipping legacy setting: {}", e),
+                    }
+                }
+            })
+            .await?;
+            log::info!("Migrated {} settings from {}", count, dir.display());
+        }
+        Ok(count)
+    }
+}
+
+// ==================== GLOBAL STORE (for Dioxus Desktop) ====================
+
+static STORE: OnceLock<SettingsStore> = OnceLock::new();
+
+/// Load the settings store from the shared database and migrate /tmp files.
+/// Safe to call more than once; later calls return the existing store.
+pub async fn init() -> Result<&'static SettingsStore, String> {
+    if let Some(store) = STORE.get() {
+        return Ok(store);
+    }
+
+    let store = SettingsStore::load(crate::db::shared()?).await?;
+    let store = STORE.get_or_init(|| store);
+    if let Err(e) = store.migrate_legacy_files(Path::new("/tmp")).await {
+        log::warn!("Legacy settings migration failed: {}", e);
+    }
+    Ok(store)
+}
+
+/// Current settings, or the defaults before `init` has finished
+pub fn current() -> Settings {
+    STORE.get().map(|s| s.get()).unwrap_or_default()
+}
+
+/// Validate, persist and broadcast a settings change
+pub async fn update<F>(f: F) -> Result<Settings, String>
+where
+    F: FnOnce(&mut Settings),
+{
+    init().await?.update(f).await
+}
+
+// ==================== SYNC ====================
+
+#[async_trait::async_trait]
+impl Syncable for Settings {
+    const COLLECTION: &'static str = "settings";
+    /// Machine-specific: installed models, old plaintext keys, local servers,
+    /// and what this device opted in to sync
+    const LOCAL_FIELDS: &'static [&'static str] = &[
+        "local_models",
+        "cached_keys",
+        "persistent_terminal",
+        "api_server",
+        "api_port",
+        "sync",
+    ];
+
+    fn sync_id(&self) -> String {
+        "settings".to_string()
+    }
+
+    async fn load_all(storage: &StorageManager) -> anyhow::Result<Vec<Self>> {
+        Ok(Self::load(storage, "settings").await?.into_iter().collect())
+    }
+
+    async fn load(storage: &StorageManager, _id: &str) -> anyhow::Result<Option<Self>> {
+        let settings = match STORE.get().filter(|store| store.storage.same_as(storage)) {
+            Some(store) => store.get(),
+            None => SettingsStore::load(storage.clone())
+                .await
+                .map_err(anyhow::Error::msg)?
+                .get(),
+        };
+        Ok(Some(settings))
+    }
+
+    /// Goes through the global store when it owns `storage`, so the UI sees
+    /// the change
+    async fn store(storage: &StorageManager, record: &Self) -> anyhow::Result<()> {
+        let apply = |settings: &mut Settings| *settings = record.clone();
+        match STORE.get().filter(|store| store.storage.same_as(storage)) {
+            Some(store) => store.update(apply).await,
+            None => {
+                let store = SettingsStore::load(storage.clone())
+                    .await
+                    .map_err(anyhow::Error::msg)?;
+                store.update(apply).await
+            }
+        }
+        .map(drop)
+        .map_err(anyhow::Error::msg)
+    }
+
+    /// Settings always exist
+    async fn remove(_storage: &StorageManager, _id: &str) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn store() -> SettingsStore {
+        SettingsStore::load(StorageManager::open_in_memory().unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_defaults_and_persistence() {
+        let storage = StorageManager::open_in_memory().unwrap();
+        let store = SettingsStore::load(storage.clone()).await.unwrap();
+        assert_eq!(store.get(), Settings::default());
+
+        store
+            .update(|s| {
+                s.hybrid_assist = false;
+                s.provider_order = vec!["Mistral AI".into(), "Ollama (Local)".into()];
+            })
+            .await
+            .unwrap();
+
+        let reloaded = SettingsStore::load(storage).await.unwrap().get();
+        assert!(!reloaded.hybrid_assist);
+        assert_eq!(reloaded.provider_order[0], "Mistral AI");
+    }
+
+    #[tokio::test]
+    async fn test_validation_rejects_bad_values() {
+        let store = store().await;
+        assert!(store.update(|s| s.provider_order = vec!["Nope".into()]).await.is_err());
+        assert!(store
+            .update(|s| s.provider_order = vec!["Mistral AI".into(), "Mistral AI".into()])
+            .await
+            .is_err());
+        assert!(store.update(|s| s.kael.personality_level = 11).await.is_err());
+        assert!(store.update(|s| s.terminal_scrollback = 10).await.is_err());
+        assert!(store.update(|s| s.api_port = 80).await.is_err());
+        assert!(store.update(|s| s.sync.chat_messages = true).await.is_err());
+        assert_eq!(store.get(), Settings::default());
+    }
+
+    #[tokio::test]
+    async fn test_default_provider_order_saves() {
+        let store = store().await;
+        let every: Vec<String> = PROVIDERS.iter().map(|(name, _)| name.to_string()).collect();
+        store.update(|s| s.provider_order = every).await.unwrap();
+
+        let settings = store
+            .update(|s| s.provider_order = default_provider_order())
+            .await
+            .unwrap();
+        assert!(settings.provider_order.contains(&"GitHub Copilot CLI".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_stored_value_falls_back_to_default() {
+        let storage = StorageManager::open_in_memory().unwrap();
+        storage
+            .config()
+            .set_raw(SettingKey::LastCloudProvider.as_str(), "\"Ollama (Local)\"")
+            .await
+            .unwrap();
+        storage
+            .config()
+            .set_raw(SettingKey::HybridAssist.as_str(), "not json")
+            .await
+            .unwrap();
+
+        let settings = SettingsStore::load(storage).await.unwrap().get();
+        assert_eq!(settings.last_cloud_provider, None);
+        assert!(settings.hybrid_assist);
+    }
+
+    #[tokio::test]
+    async fn test_change_notifications() {
+        let store = store().await;
+        let mut rx = store.subscribe();
+
+        store.update(|s| s.local_models = vec!["llama3:latest".into()]).await.unwrap();
+        assert_eq!(rx.try_recv().unwrap(), SettingKey::LocalModels);
+        assert!(rx.try_recv().is_err());
+
+        // Writing the same value again is not a change
+        store.update(|s| s.local_models = vec!["llama3:latest".into()]).await.unwrap();
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_migrate_legacy_files() {
+        let dir = std::env::temp_dir().join(format!("kael-settings-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("kael_hybrid_assist.json"), "false").unwrap();
+        std::fs::write(dir.join("kael_provider_order.json"), r#"["Google Gemini"]"#).unwrap();
+        std::fs::write(dir.join("kael_cached_keys.json"), r#"[{"name":"Mistral AI","value":"k"}]"#).unwrap();
+        std::fs::write(dir.join("kael_last_cloud_provider.json"), r#""Bogus""#).unwrap();
+
+        let store = store().await;
+        store.migrate_legacy_files(&dir).await.unwrap();
+
+        let settings = store.get();
+        assert!(!settings.hybrid_assist);
+        assert_eq!(settings.provider_order, vec!["Google Gemini"]);
+        assert_eq!(settings.cached_keys[0].name, "Mistral AI");
+        assert_eq!(settings.last_cloud_provider, None);
+        assert!(!dir.join("kael_hybrid_assist.json").exists());
+        assert!(!dir.join("kael_last_cloud_provider.json").exists());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,381 @@
+//! Sync of settings, projects, scripts and chat history with Firestore.
+//!
+//! The merge rules, outbox and retries live in `kael_storage::sync`; this
+//! module stores its records under `users/<uid>/<collection>/<id>`. A
+//! document holds the record's fields plus a `_sync` map with the merge
+//! metadata. Documents written before sync existed have no `_sync` and lose
+//! to any tracked edit.
+//!
+//! Sync is off until the user turns it on, and each collection has its own
+//! switch in `Settings::sync`; chat history is opt-in. While sync is on, the
+//! service runs a pass on sign-in and every `SYNC_INTERVAL`, and backs off
+//! while Firestore can't be reached. Local-only profiles never sync.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use kael_storage::sync::{Fields, Stamp, VersionVector, Versioned};
+use kael_storage::{Remote, StorageManager, SyncEngine, SyncReport, Syncable};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::auth::{AuthService, User};
+use crate::firebase::firestore::Firestore;
+use crate::session::{self, SessionEvent};
+use crate::settings::{self as app_settings, SettingKey, Settings, SyncConfig};
+use crate::state::{AppProject, ChatMessage, Script, Session};
+
+/// Time between sync passes while online
+const SYNC_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const RETRY_MIN: Duration = Duration::from_secs(30);
+/// Document field holding the merge metadata
+const META_FIELD: &str = "_sync";
+
+// One pass at a time, so the service and a manual sync don't push twice
+static SYNC_LOCK: Lazy<tokio::sync::Mutex<()>> = Lazy::new(|| tokio::sync::Mutex::new(()));
+
+#[derive(Serialize, Deserialize)]
+struct SyncMeta {
+    stamps: BTreeMap<String, Stamp>,
+    version: VersionVector,
+    #[serde(default)]
+    deleted: Option<Stamp>,
+}
+
+/// One user's synced records in Firestore
+pub struct FirestoreRemote {
+    db: Firestore,
+    /// Path the collections live under
+    root: String,
+}
+
+impl FirestoreRemote {
+    pub fn new(db: Firestore, root: impl Into<String>) -> Self {
+        Self {
+            db,
+            root: root.into(),
+        }
+    }
+
+    /// `user`'s records in Kael's Firebase project
+    pub fn for_user(user: &User) -> Result<Self, String> {
+        Ok(Self::new(
+            Firestore::for_user(user)?,
+            format!("users/{}", user.uid),
+        ))
+    }
+
+    fn encode(record: &Versioned) -> Result<Fields, String> {
+        let meta = SyncMeta {
+            stamps: record.stamps.clone(),
+            version: record.version.clone(),
+            deleted: record.deleted.clone(),
+        };
+        let mut fields = record.fields.clone();
+        fields.insert(
+            META_FIELD.to_string(),
+            serde_json::to_value(meta).map_err(|e| e.to_string())?,
+        );
+        Ok(fields)
+    }
+
+    fn decode(id: &str, mut fields: Fields) -> Versioned {
+        let meta = fields
+            .remove(META_FIELD)
+            .and_then(|meta| serde_json::from_value::<SyncMeta>(meta).ok());
+        match meta {
+            Some(meta) => Versioned {
+                id: id.to_string(),
+                fields,
+                stamps: meta.stamps,
+                version: meta.version,
+                deleted: meta.deleted,
+            },
+            None => Versioned::untracked(id, fields),
+        }
+    }
+}
+
+#[async_trait]
+impl Remote for FirestoreRemote {
+    async fn fetch_all(&self, collection: &str) -> anyhow::Result<Vec<Versioned>> {
+        let docs = self
+            .db
+            .list::<Fields>(&format!("{}/{}", self.root, collection))
+            .await
+            .map_err(anyhow::Error::msg)?;
+        Ok(docs
+            .into_iter()
+            .map(|doc| Self::decode(&doc.id, doc.data))
+            .collect())
+    }
+
+    async fn store(&self, collection: &str, record: &Versioned) -> anyhow::Result<()> {
+        let fields = Self::encode(record).map_err(anyhow::Error::msg)?;
+        let path = format!("{}/{}/{}", self.root, collection, record.id);
+        self.db.patch(&path, &fields).await.map_err(anyhow::Error::msg)
+    }
+}
+
+/// Sync one record type
+pub async fn sync_one<T: Syncable, R: Remote>(
+    storage: &StorageManager,
+    remote: R,
+) -> Result<SyncReport, String> {
+    let _guard = SYNC_LOCK.lock().await;
+    let engine = SyncEngine::new(storage.clone(), remote)
+        .await
+        .map_err(|e| e.to_string())?;
+    engine
+        .sync::<T>()
+        .await
+        .map_err(|e| format!("Failed to sync {}: {}", T::COLLECTION, e))
+}
+
+/// Sync the record types turned on in `config`. Sessions go before their
+/// messages.
+pub async fn sync_all<R: Remote>(
+    storage: &StorageManager,
+    remote: R,
+    config: &SyncConfig,
+) -> Result<SyncReport, String> {
+    let _guard = SYNC_LOCK.lock().await;
+    let engine = SyncEngine::new(storage.clone(), remote)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut report = SyncReport::default();
+    if config.settings {
+        report.add(pass(engine.sync::<Settings>().await)?);
+    }
+    if config.projects {
+        report.add(pass(engine.sync::<AppProject>().await)?);
+    }
+    if config.scripts {
+        report.add(pass(engine.sync::<Script>().await)?);
+    }
+    if config.chat_sessions {
+        report.add(pass(engine.sync::<Session>().await)?);
+        if config.chat_messages {
+            report.add(pass(engine.sync::<ChatMessage>().await)?);
+        }
+    }
+    Ok(report)
+}
+
+fn pass(result: anyhow::Result<SyncReport>) -> Result<SyncReport, String> {
+    result.map_err(|e| format!("Sync failed: {}", e))
+}
+
+/// Sync the shared database as the signed-in user
+pub async fn sync_now() -> Result<SyncReport, String> {
+    crate::profiles::require_cloud()?;
+    let config = app_settings::init().await?.get().sync;
+    if !config.enabled {
+        return Err("Sync is turned off".to_string());
+    }
+    let user = AuthService::new()
+        .get_user()
+        .ok_or_else(|| "Not signed in".to_string())?;
+    sync_all(&crate::db::shared()?, FirestoreRemote::for_user(&user)?, &config).await
+}
+
+static REPORTS: Lazy<broadcast::Sender<SyncReport>> = Lazy::new(|| broadcast::channel(16).0);
+
+/// Receive the report of every background sync pass after this call
+pub fn subscribe() -> broadcast::Receiver<SyncReport> {
+    REPORTS.subscribe()
+}
+
+/// Delay before retry number `failures + 1`
+fn backoff(failures: u32) -> Duration {
+    RETRY_MIN
+        .saturating_mul(2u32.saturating_pow(failures))
+        .min(SYNC_INTERVAL)
+}
+
+/// Sync on sign-in and every `SYNC_INTERVAL` until sync is turned off
+pub async fn run() {
+    if crate::profiles::is_local() {
+        return;
+    }
+    let store = match app_settings::init().await {
+        Ok(store) => store,
+        Err(e) => {
+            log::warn!("Sync not started: {}", e);
+            return;
+        }
+    };
+    let mut changes = store.subscribe();
+    let mut events = session::subscribe();
+    let mut failures = 0u32;
+
+    loop {
+        if !store.get().sync.enabled {
+            log::info!("Sync is turned off");
+            return;
+        }
+        let wait = if AuthService::new().get_user().is_none() {
+            // Nothing to sync until someone signs in
+            None
+        } else {
+            match sync_now().await {
+                Ok(report) => {
+                    let offline = report.offline.clone();
+                    if report.uploaded + report.downloaded > 0 {
+                        log::info!(
+                            "Synced: {} up, {} down, {} conflicts merged",
+                            report.uploaded,
+                            report.downloaded,
+                            report.conflicts
+                        );
+                    }
+                    let _ = REPORTS.send(report);
+                    match offline {
+                        None => {
+                            failures = 0;
+                            Some(SYNC_INTERVAL)
+                        }
+                        Some(error) => {
+                            let retry_in = backoff(failures);
+                            failures = failures.saturating_add(1);
+                            log::warn!("Sync offline, retrying in {:?}: {}", retry_in, error);
+                            Some(retry_in)
+                        }
+                    }
+                }
+                Err(e) => {
+                    log::warn!("{}", e);
+                    Some(SYNC_INTERVAL)
+                }
+            }
+        };
+
+        let deadline = tokio::time::Instant::now() + wait.unwrap_or(SYNC_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep_until(deadline), if wait.is_some() => break,
+                event = events.recv() => match event {
+                    Ok(SessionEvent::SignedIn(_)) | Err(broadcast::error::RecvError::Lagged(_)) => {
+                        failures = 0;
+                        break;
+                    }
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Closed) => return,
+                },
+                // Sync right away when collections are turned on, stop when sync is off
+                change = changes.recv() => match change {
+                    Ok(SettingKey::Sync) | Err(broadcast::error::RecvError::Lagged(_)) => break,
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Closed) => return,
+                },
+            }
+        }
+    }
+}
+
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Run the sync service in the background if sync is turned on. Call again
+/// after turning sync on; does nothing while the service is running.
+pub fn start_sync_service() {
+    if RUNNING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    std::thread::spawn(|| {
+        let rt = tokio::runtime::Runtime::new().expect("Failed to create Tokio runtime");
+        rt.block_on(run());
+        RUNNING.store(false, Ordering::SeqCst);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::firebase::firestore::tests::test_firestore;
+    use crate::state::AppStatus;
+
+    #[tokio::test]
+    async fn test_firestore_remote() {
+        let (db, root) = test_firestore();
+        let laptop = StorageManager::open_in_memory().unwrap();
+        let desktop = StorageManager::open_in_memory().unwrap();
+
+        let project = AppProject::new("Kael".into(), "AI terminal".into(), AppStatus::Making);
+        laptop.projects().save(&project).await.unwrap();
+        // A project written by the old sync, without merge metadata
+        let legacy = AppProject::new("Old".into(), String::new(), AppStatus::Done);
+        db.patch(&format!("{}/projects/{}", root, legacy.id), &legacy)
+            .await
+            .unwrap();
+
+        let report = sync_one::<AppProject, _>(&laptop, FirestoreRemote::new(db.clone(), &root))
+            .await
+            .unwrap();
+        assert_eq!((report.uploaded, report.downloaded), (1, 1));
+        let report = sync_one::<AppProject, _>(&desktop, FirestoreRemote::new(db.clone(), &root))
+            .await
+            .unwrap();
+        assert_eq!(report.downloaded, 2);
+        assert_eq!(desktop.projects().get(&project.id).await.unwrap(), Some(project.clone()));
+
+        // Deletes travel as tombstones
+        desktop.projects().delete(&project.id).await.unwrap();
+        sync_one::<AppProject, _>(&desktop, FirestoreRemote::new(db.clone(), &root))
+            .await
+            .unwrap();
+        sync_one::<AppProject, _>(&laptop, FirestoreRemote::new(db.clone(), &root))
+            .await
+            .unwrap();
+        assert_eq!(laptop.projects().get(&project.id).await.unwrap(), None);
+        assert!(laptop.projects().get(&legacy.id).await.unwrap().is_some());
+
+        // Unreachable Firestore leaves edits queued
+        let offline = FirestoreRemote::new(Firestore::new("http://127.0.0.1:9/v1", "kael-test"), &root);
+        laptop
+            .projects()
+            .set_archived(&legacy.id, true)
+            .await
+            .unwrap();
+        let report = sync_one::<AppProject, _>(&laptop, offline).await.unwrap();
+        assert!(report.offline.is_some());
+        assert_eq!(report.pending, 1);
+    }
+
+    #[tokio::test]
+    async fn test_chat_history_is_opt_in() {
+        let (db, root) = test_firestore();
+        let laptop = StorageManager::open_in_memory().unwrap();
+        let desktop = StorageManager::open_in_memory().unwrap();
+
+        let project = AppProject::new("Kael".into(), String::new(), AppStatus::Making);
+        laptop.projects().save(&project).await.unwrap();
+        let session = laptop.sessions().create("Private", None, None).await.unwrap();
+
+        let defaults = SyncConfig::default();
+        sync_all(&laptop, FirestoreRemote::new(db.clone(), &root), &defaults)
+            .await
+            .unwrap();
+        sync_all(&desktop, FirestoreRemote::new(db.clone(), &root), &defaults)
+            .await
+            .unwrap();
+        assert!(desktop.projects().get(&project.id).await.unwrap().is_some());
+        assert_eq!(desktop.sessions().get(&session.id).await.unwrap(), None);
+
+        let chat = SyncConfig {
+            chat_sessions: true,
+            chat_messages: true,
+            ..SyncConfig::default()
+        };
+        sync_all(&laptop, FirestoreRemote::new(db.clone(), &root), &chat)
+            .await
+            .unwrap();
+        sync_all(&desktop, FirestoreRemote::new(db.clone(), &root), &chat)
+            .await
+            .unwrap();
+        assert!(desktop.sessions().get(&session.id).await.unwrap().is_some());
+    }
+}
@@ -1,61 +1,261 @@
 // Full PTY terminal with async streaming
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
 use tokio::sync::Mutex;
-use kael_terminal::{PtySession, TerminalManager as PtyBackend};
+use kael_terminal::{
+    daemon, Attachment, DaemonClient, Elevation, Secret, SessionId, SessionManager, ShellConfig, SKIP_USER_RC_ENV,
+};
 
+static SESSIONS: OnceLock<SessionManager> = OnceLock::new();
+
+/// Process-wide session manager shared by every terminal tab
+pub fn sessions() -> &'static SessionManager {
+    SESSIONS.get_or_init(SessionManager::new)
+}
+
+/// The `kael-terminal` daemon binary: next to our own executable, else on PATH
+fn daemon_binary() -> PathBuf {
+    std::env::current_exe()
+        .ok()
+        .map(|exe| exe.with_file_name("kael-terminal"))
+        .filter(|path| path.exists())
+        .unwrap_or_else(|| PathBuf::from("kael-terminal"))
+}
+
+/// Where a terminal's shell lives
+enum Backend {
+    /// In the shared in-process manager; dies with the app
+    Local(SessionId),
+    /// In the detached daemon; survives app restarts
+    Daemon {
+        client: DaemonClient,
+        id: SessionId,
+        attachment: Attachment,
+    },
+}
+
+impl Backend {
+    fn id(&self) -> SessionId {
+        match self {
+            Backend::Local(id) | Backend::Daemon { id, .. } => *id,
+        }
+    }
+}
+
+/// One terminal backed by a session in the shared manager, or in the
+/// session daemon when it is persistent and the setting is on.
+/// The session is spawned lazily and respawned if it was killed.
 pub struct PtyTerminal {
-    session: Arc<Mutex<Option<PtySession>>>,
+    config: ShellConfig,
+    /// May run in the daemon (Kael's own terminal)
+    persistent: bool,
+    session: Arc<Mutex<Option<Backend>>>,
 }
 
 impl Clone for PtyTerminal {
     fn clone(&self) -> Self {
         Self {
+            config: self.config.clone(),
+            persistent: self.persistent,
             session: Arc::clone(&self.session),
         }
     }
 }
 
 impl PtyTerminal {
+    /// Kael's own terminal. Prefers bash with shell integration, without the
+    /// user's rc files so generated commands behave predictably; falls back to
+    /// POSIX sh (no command blocks) when bash is missing.
     pub fn new() -> Self {
+        let config = if std::path::Path::new("/bin/bash").exists() {
+            ShellConfig::new("/bin/bash")
+                .with_integration()
+                .with_env(SKIP_USER_RC_ENV, "1")
+        } else {
+            ShellConfig::sh()
+        };
+        Self {
+            persistent: true,
+            ..Self::with_config(config.with_name("Kael").with_sudo_prompt())
+        }
+    }
+
+    pub fn with_config(config: ShellConfig) -> Self {
         Self {
+            config,
+            persistent: false,
             session: Arc::new(Mutex::new(None)),
         }
     }
 
-    pub async fn ensure_session(&self) -> Result<(), String> {
+    /// Wrap a session that was already spawned in the shared manager
+    pub fn attach(id: SessionId) -> Self {
+        Self {
+            config: ShellConfig::default(),
+            persistent: false,
+            session: Arc::new(Mutex::new(Some(Backend::Local(id)))),
+        }
+    }
+
+    /// Reattach to the daemon session left running under our name by an
+    /// earlier run of the app, or start a new one there
+    async fn daemon_session(&self) -> Result<Backend, String> {
+        let client = DaemonClient::connect_or_start(daemon::default_socket_path(), &daemon_binary())
+            .await
+            .map_err(|e| e.to_string())?;
+        let name = self.config.display_name();
+        let existing = client
+            .list()
+            .await
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .find(|s| s.name == name && s.exit.is_none());
+        let id = match existing {
+            Some(session) => {
+                log::info!("Reattaching to terminal session {} in the daemon", session.id);
+                session.id
+            }
+            None => client.spawn(&self.config).await.map_err(|e| e.to_string())?,
+        };
+        let attachment = client.attach(id).await.map_err(|e| e.to_string())?;
+        Ok(Backend::Daemon { client, id, attachment })
+    }
+
+    pub async fn ensure_session(&self) -> Result<SessionId, String> {
         let mut lock = self.session.lock().await;
-        if lock.is_none() {
-            let pty = PtyBackend::spawn_shell(None).map_err(|e| e.to_string())?;
-            *lock = Some(pty);
+        match lock.as_ref() {
+            Some(Backend::Local(id)) => match sessions().info(*id).await {
+                Some(info) if info.exit.is_none() => return Ok(*id),
+                // The shell exited: drop the dead session and start a new one
+                Some(_) => {
+                    let _ = sessions().close(*id).await;
+                }
+                None => {}
+            },
+            Some(Backend::Daemon { id, attachment, .. }) if !attachment.is_closed() => return Ok(*id),
+            _ => {}
         }
-        Ok(())
+
+        let persistent = self.persistent
+            && crate::settings::init()
+                .await
+                .map(|store| store.get().persistent_terminal)
+                .unwrap_or(false);
+        if persistent {
+            match self.daemon_session().await {
+                Ok(backend) => {
+                    let id = backend.id();
+                    *lock = Some(backend);
+                    return Ok(id);
+                }
+                Err(e) => log::warn!("Terminal daemon unavailable, using an in-app shell: {}", e),
+            }
+        }
+
+        let id = sessions()
+            .spawn(self.config.clone())
+            .await
+            .map_err(|e| e.to_string())?;
+        *lock = Some(Backend::Local(id));
+        Ok(id)
+    }
+
+    /// Session id, in the daemon when the terminal runs there
+    pub async fn id(&self) -> Option<SessionId> {
+        self.session.lock().await.as_ref().map(Backend::id)
     }
 
     pub async fn write_line(&self, line: &str) -> Result<(), String> {
+        let input = format!("{}\n", line);
+        self.write_bytes(input.as_bytes()).await
+    }
+
+    /// Raw input, e.g. terminal query responses
+    pub async fn write_bytes(&self, data: &[u8]) -> Result<(), String> {
         self.ensure_session().await?;
-        let mut lock = self.session.lock().await;
-        if let Some(ref mut pty) = *lock {
-            let input = format!("{}\n", line);
-            PtyBackend::write_input(pty, input.as_bytes()).await.map_err(|e| e.to_string())?;
+        match self.session.lock().await.as_ref() {
+            Some(Backend::Local(id)) => sessions().write(*id, data).await.map_err(|e| e.to_string()),
+            Some(Backend::Daemon { attachment, .. }) => attachment.write(data).await.map_err(|e| e.to_string()),
+            None => Err("No terminal session".to_string()),
+        }
+    }
+
+    /// Type a privileged command into the shell. Its password prompt shows
+    /// up in the output and is answered with `answer_prompt`.
+    pub async fn run_elevated(&self, cmdline: &str, method: Elevation) -> Result<(), String> {
+        self.write_line(&method.command_line(cmdline)).await
+    }
+
+    /// Answer a password prompt. The secret is consumed and zeroized, and a
+    /// running recording only gets `***`.
+    pub async fn answer_prompt(&self, secret: Secret) -> Result<(), String> {
+        let answer = secret
+            .into_answer()
+            .ok_or_else(|| "Password expired before it was used".to_string())?;
+        self.ensure_session().await?;
+        match self.session.lock().await.as_ref() {
+            Some(Backend::Local(id)) => sessions().write_secret(*id, &answer).await.map_err(|e| e.to_string()),
+            Some(Backend::Daemon { client, id, .. }) => {
+                client.write_secret(*id, &answer).await.map_err(|e| e.to_string())
+            }
+            None => Err("No terminal session".to_string()),
+        }
+    }
+
+    pub async fn resize(&self, rows: u16, cols: u16) -> Result<(), String> {
+        self.ensure_session().await?;
+        match self.session.lock().await.as_ref() {
+            Some(Backend::Local(id)) => sessions().resize(*id, rows, cols).await.map_err(|e| e.to_string()),
+            Some(Backend::Daemon { client, id, .. }) => {
+                client.resize(*id, rows, cols).await.map_err(|e| e.to_string())
+            }
+            None => Err("No terminal session".to_string()),
         }
-        Ok(())
     }
 
+    /// Output stream. From the daemon it starts with the output the session
+    /// produced before this attach, so the screen can be rebuilt.
     pub async fn get_output_receiver(&self) -> Result<async_channel::Receiver<Vec<u8>>, String> {
         self.ensure_session().await?;
-        let lock = self.session.lock().await;
-        if let Some(ref pty) = *lock {
-            Ok(PtyBackend::output_stream(pty))
-        } else {
-            Err("No PTY session available".to_string())
+        match self.session.lock().await.as_ref() {
+            Some(Backend::Local(id)) => sessions().output(*id).await.map_err(|e| e.to_string()),
+            Some(Backend::Daemon { attachment, .. }) => Ok(attachment.output()),
+            None => Err("No terminal session".to_string()),
+        }
+    }
+
+    /// Display name of the session, for recording file names
+    pub fn name(&self) -> String {
+        self.config.display_name()
+    }
+
+    /// Record the session to an asciicast file at `path`
+    pub async fn start_recording(&self, path: &Path) -> Result<(), String> {
+        self.ensure_session().await?;
+        match self.session.lock().await.as_ref() {
+            Some(Backend::Local(id)) => sessions().start_recording(*id, path).await.map_err(|e| e.to_string()),
+            Some(Backend::Daemon { client, id, .. }) => {
+                client.start_recording(*id, path).await.map_err(|e| e.to_string())
+            }
+            None => Err("No terminal session".to_string()),
+        }
+    }
+
+    pub async fn stop_recording(&self) -> Result<Option<PathBuf>, String> {
+        match self.session.lock().await.as_ref() {
+            Some(Backend::Local(id)) => sessions().stop_recording(*id).await.map_err(|e| e.to_string()),
+            Some(Backend::Daemon { client, id, .. }) => client.stop_recording(*id).await.map_err(|e| e.to_string()),
+            None => Ok(None),
         }
     }
 
+    /// Kill the shell, wherever it runs
     pub async fn kill(&self) -> Result<(), String> {
         let mut lock = self.session.lock().await;
-        if let Some(ref mut pty) = *lock {
-            pty.kill().map_err(|e| e.to_string())?;
-            *lock = None;
+        match lock.take() {
+            Some(Backend::Local(id)) => sessions().close(id).await.map_err(|e| e.to_string())?,
+            Some(Backend::Daemon { client, id, .. }) => client.close(id).await.map_err(|e| e.to_string())?,
+            None => {}
         }
         Ok(())
     }
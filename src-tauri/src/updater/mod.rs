@@ -167,10 +167,14 @@ pub fn verify_checksum(data: &[u8], expected_sha256: &str) -> Result<bool, Box<d
     Ok(computed.to_lowercase() == expected_sha256.to_lowercase())
 }
 
-/// Compare versions (returns true if new_version > current_version)
+/// Compare versions (returns true if new_version > current_version).
+/// Only major.minor.patch count; a "-beta.2" suffix is ignored.
 pub fn should_update(current: &str, new: &str) -> bool {
     let parse_version = |v: &str| -> Vec<u32> {
-        v.split('.')
+        v.split('-')
+            .next()
+            .unwrap_or(v)
+            .split('.')
             .take(3)
             .map(|part| part.parse::<u32>().unwrap_or(0))
             .collect()
@@ -203,6 +207,8 @@ mod tests {
         assert!(should_update("0.1.0", "0.1.1"));
         assert!(!should_update("0.2.0", "0.1.0"));
         assert!(!should_update("0.1.0", "0.1.0"));
+        assert!(should_update("0.1.0", "0.1.1-beta.2"));
+        assert!(!should_update("0.1.1-alpha.1", "0.1.1-beta.2"));
     }
 
     #[test]
@@ -0,0 +1,429 @@
+//! Local vault for provider API keys.
+//!
+//! `vault.json` in the profile's data dir (mode 0600) holds a data key wrapped
+//! under the user's passphrase and one `kael:v1:` envelope per provider
+//! (see `crypto::envelope`). Provider names and dates stay in the clear so
+//! keys can be listed while the vault is locked. The vault is unlocked once
+//! per session; after that only the data key is kept in memory, zeroed on
+//! `lock`, and values are opened on demand as `Zeroizing<String>`.
+//!
+//! Older builds cached keys in plaintext (`/tmp/kael_cached_api_keys.json`,
+//! `settings.cached_keys`); `import_legacy` moves them in and removes those
+//! copies. Firestore is only involved when `sync_api_keys` is on.
+#![allow(dead_code)]
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::auth::User;
+use crate::crypto::envelope::{DataKey, WrappedKey, PBKDF2_ROUNDS};
+
+/// Where the plaintext key cache of older builds lived
+const LEGACY_CACHE: &str = "/tmp/kael_cached_api_keys.json";
+
+#[derive(Serialize, Deserialize)]
+struct VaultFile {
+    version: u32,
+    key: WrappedKey,
+    entries: BTreeMap<String, Entry>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Entry {
+    /// The key as a `kael:v1:` envelope
+    value: String,
+    created_at: DateTime<Utc>,
+    #[serde(default)]
+    rotated_at: Option<DateTime<Utc>>,
+}
+
+/// What `list` shows about a stored key; never the key itself
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VaultEntry {
+    pub provider: String,
+    pub created_at: DateTime<Utc>,
+    pub rotated_at: Option<DateTime<Utc>>,
+}
+
+pub struct Vault {
+    path: PathBuf,
+    key: Option<DataKey>,
+    rounds: u32,
+}
+
+impl Vault {
+    /// A locked vault backed by `path`, which need not exist yet
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            key: None,
+            rounds: PBKDF2_ROUNDS,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    pub fn is_unlocked(&self) -> bool {
+        self.key.is_some()
+    }
+
+    /// Unlock with `passphrase`, creating the vault under it if there is none
+    /// yet. Returns `true` when the vault was created.
+    pub fn unlock(&mut self, passphrase: &str) -> Result<bool, String> {
+        if passphrase.is_empty() {
+            return Err("Enter your vault passphrase".to_string());
+        }
+        if let Some(file) = self.read()? {
+            self.key = Some(file.key.unwrap(passphrase)?);
+            return Ok(false);
+        }
+        let key = DataKey::generate();
+        self.write(&VaultFile {
+            version: 1,
+            key: key.wrap_with_rounds(passphrase, self.rounds),
+            entries: BTreeMap::new(),
+        })?;
+        self.key = Some(key);
+        log::info!("Created API key vault at {}", self.path.display());
+        Ok(true)
+    }
+
+    /// Forget the data key
+    pub fn lock(&mut self) {
+        self.key = None;
+    }
+
+    /// Stored providers, in name order. Works while locked.
+    pub fn list(&self) -> Result<Vec<VaultEntry>, String> {
+        let Some(file) = self.read()? else {
+            return Ok(Vec::new());
+        };
+        Ok(file
+            .entries
+            .into_iter()
+            .map(|(provider, entry)| VaultEntry {
+                provider,
+                created_at: entry.created_at,
+                rotated_at: entry.rotated_at,
+            })
+            .collect())
+    }
+
+    pub fn get(&self, provider: &str) -> Result<Option<Zeroizing<String>>, String> {
+        let provider = provider.trim();
+        let key = self.key()?;
+        let Some(file) = self.read()? else {
+            return Ok(None);
+        };
+        let Some(entry) = file.entries.get(provider) else {
+            return Ok(None);
+        };
+        let plaintext = Zeroizing::new(key.open(&entry.value)?);
+        let value = std::str::from_utf8(&plaintext).map_err(|_| format!("Corrupt vault entry for {}", provider))?;
+        Ok(Some(Zeroizing::new(value.to_string())))
+    }
+
+    /// Store `value` for `provider`, replacing any existing key
+    pub fn set(&self, provider: &str, value: &str) -> Result<(), String> {
+        self.store(provider, value, false)
+    }
+
+    /// Replace an existing key with a new one and record when it happened
+    pub fn rotate(&self, provider: &str, value: &str) -> Result<(), String> {
+        self.store(provider, value, true)
+    }
+
+    fn store(&self, provider: &str, value: &str, rotate: bool) -> Result<(), String> {
+        let provider = provider.trim();
+        if provider.is_empty() {
+            return Err("API keys need a provider name".to_string());
+        }
+        if value.is_empty() {
+            return Err(format!("Empty API key for {}", provider));
+        }
+        let sealed = self.key()?.seal(value.as_bytes());
+        let mut file = self.read()?.ok_or("Vault file is missing")?;
+        let now = Utc::now();
+        match file.entries.get_mut(provider) {
+            Some(entry) => {
+                entry.value = sealed;
+                if rotate {
+                    entry.rotated_at = Some(now);
+                }
+            }
+            None if rotate => return Err(format!("No API key stored for {}", provider)),
+            None => {
+                file.entries.insert(
+                    provider.to_string(),
+                    Entry {
+                        value: sealed,
+                        created_at: now,
+                        rotated_at: None,
+                    },
+                );
+            }
+        }
+        self.write(&file)
+    }
+
+    /// Remove a provider's key. Returns whether there was one.
+    pub fn delete(&self, provider: &str) -> Result<bool, String> {
+        let provider = provider.trim();
+        self.key()?;
+        let Some(mut file) = self.read()? else {
+            return Ok(false);
+        };
+        let removed = file.entries.remove(provider).is_some();
+        if removed {
+            self.write(&file)?;
+        }
+        Ok(removed)
+    }
+
+    /// Add `(provider, value)` pairs the vault doesn't have yet; existing
+    /// keys win. Returns how many were added.
+    pub fn import(&self, keys: impl IntoIterator<Item = (String, String)>) -> Result<usize, String> {
+        let mut existing: Vec<String> = self.list()?.into_iter().map(|e| e.provider).collect();
+        let mut added = 0;
+        for (provider, value) in keys {
+            let value = Zeroizing::new(value);
+            let provider = provider.trim();
+            if provider.is_empty() || value.is_empty() || existing.iter().any(|p| p == provider) {
+                continue;
+            }
+            self.set(provider, &value)?;
+            existing.push(provider.to_string());
+            added += 1;
+        }
+        Ok(added)
+    }
+
+    fn key(&self) -> Result<&DataKey, String> {
+        self.key
+            .as_ref()
+            .ok_or_else(|| "Vault is locked - unlock it with your passphrase in Settings → Security".to_string())
+    }
+
+    fn read(&self) -> Result<Option<VaultFile>, String> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(json) => serde_json::from_str(&json)
+                .map(Some)
+                .map_err(|e| format!("Corrupt vault {}: {}", self.path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("Failed to read vault: {}", e)),
+        }
+    }
+
+    /// Write via a temporary file so a crash never leaves half a vault
+    fn write(&self, file: &VaultFile) -> Result<(), String> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        }
+        let json = serde_json::to_string_pretty(file).map_err(|e| e.to_string())?;
+        let tmp = self.path.with_extension("json.tmp");
+        let mut out = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&tmp)
+            .map_err(|e| format!("Failed to write vault: {}", e))?;
+        out.write_all(json.as_bytes())
+            .and_then(|_| out.sync_all())
+            .map_err(|e| format!("Failed to write vault: {}", e))?;
+        std::fs::rename(&tmp, &self.path).map_err(|e| format!("Failed to write vault: {}", e))
+    }
+}
+
+// ==================== SESSION VAULT ====================
+
+static VAULT: Lazy<Mutex<Vault>> = Lazy::new(|| Mutex::new(Vault::new(default_path())));
+
+pub fn default_path() -> PathBuf {
+    crate::profiles::data_dir().join("vault.json")
+}
+
+fn with_vault<T>(f: impl FnOnce(&mut Vault) -> Result<T, String>) -> Result<T, String> {
+    let mut vault = VAULT.lock().map_err(|_| "Vault lock poisoned".to_string())?;
+    f(&mut vault)
+}
+
+/// Unlock the session vault; see `Vault::unlock`
+pub fn unlock(passphrase: &str) -> Result<bool, String> {
+    with_vault(|v| v.unlock(passphrase))
+}
+
+pub fn lock() {
+    let _ = with_vault(|v| {
+        v.lock();
+        Ok(())
+    });
+}
+
+pub fn is_unlocked() -> bool {
+    with_vault(|v| Ok(v.is_unlocked())).unwrap_or(false)
+}
+
+pub fn list() -> Result<Vec<VaultEntry>, String> {
+    with_vault(|v| v.list())
+}
+
+/// A provider's key, or `None` when it is missing or the vault is locked
+pub fn get(provider: &str) -> Option<Zeroizing<String>> {
+    match with_vault(|v| v.get(provider)) {
+        Ok(value) => value,
+        Err(e) => {
+            log::debug!("No vault key for {}: {}", provider, e);
+            None
+        }
+    }
+}
+
+pub fn set(provider: &str, value: &str) -> Result<(), String> {
+    with_vault(|v| v.set(provider, value))
+}
+
+pub fn rotate(provider: &str, value: &str) -> Result<(), String> {
+    with_vault(|v| v.rotate(provider, value))
+}
+
+pub fn delete(provider: &str) -> Result<bool, String> {
+    with_vault(|v| v.delete(provider))
+}
+
+/// Move keys from the plaintext caches of older builds into the unlocked
+/// vault, then remove those caches
+pub async fn import_legacy() -> Result<usize, String> {
+    let mut keys: Vec<(String, String)> = crate::settings::current()
+        .cached_keys
+        .into_iter()
+        .map(|k| (k.name, k.value))
+        .collect();
+    if let Ok(json) = std::fs::read_to_string(LEGACY_CACHE) {
+        if let Ok(list) = serde_json::from_str::<Vec<serde_json::Value>>(&json) {
+            keys.extend(list.iter().filter_map(|v| {
+                let name = v.get("name")?.as_str()?;
+                let value = v.get("value")?.as_str()?;
+                Some((name.to_string(), value.to_string()))
+            }));
+        }
+    }
+    let added = with_vault(|v| v.import(keys))?;
+
+    if std::path::Path::new(LEGACY_CACHE).exists() {
+        std::fs::remove_file(LEGACY_CACHE).map_err(|e| format!("Failed to remove {}: {}", LEGACY_CACHE, e))?;
+    }
+    if !crate::settings::current().cached_keys.is_empty() {
+        crate::settings::update(|s| s.cached_keys.clear()).await?;
+    }
+    if added > 0 {
+        log::info!("Moved {} plaintext API keys into the vault", added);
+    }
+    Ok(added)
+}
+
+/// Copy keys stored in Firestore that the vault doesn't have yet. Only
+/// when `sync_api_keys` is on.
+pub async fn pull_from_firestore(user: &User) -> Result<usize, String> {
+    if !crate::settings::current().sync_api_keys {
+        return Ok(0);
+    }
+    let keys = crate::firebase::get_api_keys(user).await?;
+    with_vault(|v| v.import(keys.into_iter().map(|k| (k.name, k.value))))
+}
+
+/// Mirror a key to Firestore when `sync_api_keys` is on
+pub async fn push_to_firestore(user: &User, provider: &str, value: &str) -> Result<(), String> {
+    if !crate::settings::current().sync_api_keys {
+        return Ok(());
+    }
+    crate::firebase::save_api_key(user, provider, value).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vault() -> (Vault, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("kael-vault-{}", uuid::Uuid::new_v4()));
+        let mut vault = Vault::new(dir.join("vault.json"));
+        vault.rounds = 1_000;
+        (vault, dir)
+    }
+
+    #[test]
+    fn test_vault_lifecycle() {
+        let (mut vault, dir) = vault();
+        assert!(vault.get("Mistral AI").is_err());
+        assert!(vault.unlock("hunter2").unwrap());
+        vault.set("Mistral AI", "mk-123").unwrap();
+        vault.set("Google Gemini", "AIza-456").unwrap();
+        assert_eq!(vault.get("Mistral AI").unwrap().unwrap().as_str(), "mk-123");
+        assert!(vault.get("GitHub Copilot").unwrap().is_none());
+        vault.set(" OpenAI ", "sk-1").unwrap();
+        assert_eq!(vault.get("OpenAI ").unwrap().unwrap().as_str(), "sk-1");
+        assert!(vault.delete(" OpenAI").unwrap());
+
+        // Nothing readable on disk, and only the owner can read the file
+        let raw = std::fs::read_to_string(vault.path()).unwrap();
+        assert!(!raw.contains("mk-123") && !raw.contains("AIza-456"));
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(vault.path()).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        assert!(vault.rotate("GitHub Copilot", "ghp").is_err());
+        vault.rotate("Mistral AI", "mk-789").unwrap();
+        let listed = vault.list().unwrap();
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed[1].provider, "Mistral AI");
+        assert!(listed[1].rotated_at.is_some());
+
+        assert!(vault.delete("Google Gemini").unwrap());
+        assert!(!vault.delete("Google Gemini").unwrap());
+
+        // Listing works locked; reading needs the passphrase again
+        vault.lock();
+        assert_eq!(vault.list().unwrap().len(), 1);
+        assert!(vault.set("Google Gemini", "x").is_err());
+        let mut reopened = Vault::new(vault.path());
+        assert_eq!(reopened.unlock("hunter3").err().unwrap(), "Wrong passphrase");
+        assert!(!reopened.unlock("hunter2").unwrap());
+        assert_eq!(reopened.get("Mistral AI").unwrap().unwrap().as_str(), "mk-789");
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_import_keeps_existing_keys() {
+        let (mut vault, dir) = vault();
+        vault.unlock("hunter2").unwrap();
+        vault.set("Mistral AI", "current").unwrap();
+        let added = vault
+            .import(vec![
+                (" Mistral AI ".to_string(), "stale".to_string()),
+                ("Google Gemini ".to_string(), "AIza".to_string()),
+                ("Google Gemini".to_string(), "newer".to_string()),
+                ("GitHub Copilot".to_string(), String::new()),
+                ("  ".to_string(), "orphan".to_string()),
+            ])
+            .unwrap();
+        assert_eq!(added, 1);
+        assert_eq!(vault.get("Mistral AI").unwrap().unwrap().as_str(), "current");
+        assert_eq!(vault.get("Google Gemini").unwrap().unwrap().as_str(), "AIza");
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -57,6 +57,22 @@ impl WebDavClient {
         }
     }
 
+    /// Encrypt a file under `passphrase` and upload it as
+    /// `<remote_path>.kaelenc`; returns that remote path. The plaintext
+    /// never leaves this machine.
+    pub async fn upload_encrypted_file(
+        &self,
+        local_path: &Path,
+        remote_path: &str,
+        passphrase: &str,
+    ) -> Result<String, Box<dyn Error>> {
+        let sealed = crate::crypto::stream::encrypt_to_temp(local_path, passphrase).await?;
+        let remote_path = format!("{}.{}", remote_path, crate::crypto::stream::EXTENSION);
+        let result = self.upload_file(&sealed, &remote_path).await;
+        let _ = tokio::fs::remove_file(&sealed).await;
+        result.map(|_| remote_path)
+    }
+
     /// Create a directory on WebDAV server
     pub async fn create_directory(&self, remote_path: &str) -> Result<(), Box<dyn Error>> {
         let dir_url = format!(
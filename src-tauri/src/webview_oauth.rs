@@ -5,6 +5,8 @@ use std::sync::Arc;
 use tokio::sync::Mutex;
 use url::Url;
 
+use crate::oauth_server::AuthorizationCode;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OAuthResult {
     pub provider: String,
@@ -12,8 +14,8 @@ pub struct OAuthResult {
     pub state: String,
 }
 
-// Store the OAuth result in a thread-safe way
-pub static OAUTH_RESULT: once_cell::sync::Lazy<Arc<Mutex<Option<OAuthResult>>>> =
+// Completed sign-ins waiting to be exchanged, one at a time
+pub static OAUTH_RESULT: once_cell::sync::Lazy<Arc<Mutex<Option<AuthorizationCode>>>> =
     once_cell::sync::Lazy::new(|| Arc::new(Mutex::new(None)));
 
 /// Extract OAuth code from callback URL
@@ -54,38 +56,22 @@ pub fn extract_oauth_code(url: &str) -> Option<OAuthResult> {
     })
 }
 
-/// Store OAuth result for retrieval
-pub async fn store_oauth_result(result: OAuthResult) {
+/// Store a redeemed authorization code for retrieval
+pub async fn store_oauth_result(result: AuthorizationCode) {
     let mut oauth_result = OAUTH_RESULT.lock().await;
     *oauth_result = Some(result);
 }
 
-/// Retrieve and clear OAuth result
-pub async fn get_and_clear_oauth_result() -> Option<OAuthResult> {
-    let mut oauth_result = OAUTH_RESULT.lock().await;
-    oauth_result.take()
+/// Whether a sign-in for `provider` has completed
+pub async fn has_oauth_result(provider: &str) -> bool {
+    let oauth_result = OAUTH_RESULT.lock().await;
+    oauth_result.as_ref().is_some_and(|r| r.provider == provider)
 }
 
-/// Get OAuth result from the OAuth server
-pub async fn get_oauth_result_from_server(provider: &str) -> Option<OAuthResult> {
-    if let Some(callback) = crate::oauth_server::OAUTH_SERVER
-        .get_callback(provider)
-        .await
-    {
-        if let Some(code) = callback.code {
-            let result = OAuthResult {
-                provider: provider.to_string(),
-                code,
-                state: String::new(),
-            };
-            // Clear the callback from the server
-            crate::oauth_server::OAUTH_SERVER
-                .clear_callback(provider)
-                .await;
-            return Some(result);
-        }
-    }
-    None
+/// Retrieve and clear the authorization code for `provider`
+pub async fn take_oauth_result(provider: &str) -> Option<AuthorizationCode> {
+    let mut oauth_result = OAUTH_RESULT.lock().await;
+    oauth_result.take_if(|r| r.provider == provider)
 }
 
 #[cfg(test)]
@@ -94,7 +80,7 @@ mod tests {
 
     #[test]
     fn test_extract_google_code() {
-        let url = "http://localhost:5173/auth/google/callback?code=test123&scope=email";
+        let url = "http://127.0.0.1:49152/auth/google/callback?code=test123&scope=email";
         let result = extract_oauth_code(url).unwrap();
         assert_eq!(result.provider, "google");
         assert_eq!(result.code, "test123");
@@ -102,7 +88,7 @@ mod tests {
 
     #[test]
     fn test_extract_github_code() {
-        let url = "http://localhost:5173/auth/github/callback?code=github_code_123&state=xyz";
+        let url = "http://127.0.0.1:49152/auth/github/callback?code=github_code_123&state=xyz";
         let result = extract_oauth_code(url).unwrap();
         assert_eq!(result.provider, "github");
         assert_eq!(result.code, "github_code_123");